@@ -0,0 +1,201 @@
+//! Benchmarks, run with `cargo bench`
+//!
+//! The fixtures are made up on the spot, so that nothing needs network access: the tag list of a
+//! repository with a long history, a large lock file made of copies of `tests/sources.json`, and
+//! local git repositories for updating many pins end to end (which needs `git`). To compare a
+//! change against `main`, run `cargo bench -- --save-baseline main` on `main` first and then
+//! `cargo bench -- --baseline main` with the change.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use npins::{
+    engine::{self, UpdateStrategy},
+    git, NixPins, Pin,
+};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Tags like those of a project with a long history: releases, release candidates, and others
+fn tags(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            let (major, minor, patch) = (i / 400, i / 20 % 20, i % 20);
+            match i % 10 {
+                7 => format!("v{major}.{minor}.{patch}-rc{}", i % 3 + 1),
+                9 => format!("nightly-{i:06}"),
+                _ => format!("v{major}.{minor}.{patch}"),
+            }
+        })
+        .collect()
+}
+
+fn release_pin(scheme: &str) -> git::GitReleasePin {
+    let mut pin = git::GitReleasePin::new(
+        git::Repository::github("owner", "repo"),
+        false,
+        Some("20".into()),
+        None,
+        false,
+    );
+    if scheme == "semver" {
+        pin.version_scheme = npins::latest::VersionScheme {
+            comparator: npins::latest::Comparator::Semver,
+            normalize: vec!["^nightly-=0.0.0-".parse().unwrap()],
+        };
+    }
+    pin
+}
+
+fn latest_release(c: &mut Criterion) {
+    let mut group = c.benchmark_group("latest_release");
+    for count in [100, 1_000, 10_000] {
+        let tags = tags(count);
+        group.throughput(Throughput::Elements(count as u64));
+        for scheme in ["lenient", "semver"] {
+            let pin = release_pin(scheme);
+            group.bench_with_input(BenchmarkId::new(scheme, count), &tags, |b, tags| {
+                b.iter(|| pin.latest_tag(tags.iter().map(String::as_str)).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+/// The pins of `tests/sources.json`, copied until there are about `count` of them
+fn lock_file(count: usize) -> NixPins {
+    let recorded = include_str!("../tests/sources.json");
+    let recorded = NixPins::from_json_versioned(serde_json::from_str(recorded).unwrap()).unwrap();
+    let mut pins = NixPins::default();
+    for i in 0..count.div_ceil(recorded.pins.len()) {
+        for (name, pin) in &recorded.pins {
+            pins.pins.insert(format!("{name}-{i}"), pin.clone());
+        }
+    }
+    pins
+}
+
+fn lock_files(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lock_file");
+    for count in [10, 1_000] {
+        let pins = lock_file(count);
+        let json = serde_json::to_string_pretty(&pins.to_value_versioned()).unwrap();
+        group.throughput(Throughput::Elements(pins.pins.len() as u64));
+        group.bench_with_input(BenchmarkId::new("read", count), &json, |b, json| {
+            b.iter(|| NixPins::from_json_versioned(serde_json::from_str(json).unwrap()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("write", count), &pins, |b, pins| {
+            b.iter(|| serde_json::to_vec_pretty(&pins.to_value_versioned()).unwrap())
+        });
+
+        /* Every other pin moved on */
+        let mut updated = pins.clone();
+        for pin in updated.pins.values_mut().step_by(2) {
+            pin.metadata_mut().labels.insert("updated".into());
+        }
+        group.bench_with_input(
+            BenchmarkId::new("diff", count),
+            &(pins, updated),
+            |b, (old, new)| b.iter(|| npins::diff::diff_pins(old, new)),
+        );
+    }
+    group.finish();
+}
+
+fn git(directory: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .env("GIT_AUTHOR_NAME", "npins")
+        .env("GIT_AUTHOR_EMAIL", "npins@example.org")
+        .env("GIT_COMMITTER_NAME", "npins")
+        .env("GIT_COMMITTER_EMAIL", "npins@example.org")
+        .status()
+        .expect("git is needed for the end to end benchmarks");
+    assert!(status.success(), "git {} failed", args.join(" "));
+}
+
+/// A repository with a single commit on `main` and `tags` all pointing to it
+fn repository(directory: &Path, tags: &[String]) {
+    std::fs::create_dir_all(directory).unwrap();
+    git(directory, &["init", "--quiet", "--initial-branch=main"]);
+    git(
+        directory,
+        &["commit", "--quiet", "--allow-empty", "--message=init"],
+    );
+    /* Much faster than running `git tag` for every one of them */
+    let head = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .unwrap()
+        .stdout;
+    let head = String::from_utf8(head).unwrap();
+    let packed = tags
+        .iter()
+        .map(|tag| format!("{} refs/tags/{tag}\n", head.trim()))
+        .collect::<String>();
+    std::fs::write(directory.join(".git/packed-refs"), packed).unwrap();
+}
+
+/// Half of the pins track `main`, the others the latest release
+fn local_pins(repositories: &[PathBuf]) -> Vec<engine::Job> {
+    repositories
+        .iter()
+        .enumerate()
+        .map(|(i, directory)| {
+            let url = url::Url::from_directory_path(directory).unwrap();
+            let repository = git::Repository::git(url);
+            let pin: Pin = if i % 2 == 0 {
+                git::GitPin::new(repository, "main".into(), false).into()
+            } else {
+                git::GitReleasePin::new(repository, false, None, None, false).into()
+            };
+            engine::Job::new(format!("pin-{i}"), pin, UpdateStrategy::VersionOnly)
+        })
+        .collect()
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let root = std::env::temp_dir().join(format!("npins-bench-{}", std::process::id()));
+    let tags = tags(1_000);
+    let repositories = (0..8)
+        .map(|i| {
+            let directory = root.join(format!("repository-{i}"));
+            repository(&directory, &tags);
+            directory
+        })
+        .collect::<Vec<_>>();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("end_to_end");
+    group.sample_size(10);
+    for count in [8, 64] {
+        let jobs = local_pins(&repositories)
+            .into_iter()
+            .cycle()
+            .take(count)
+            .enumerate()
+            .map(|(i, job)| engine::Job::new(format!("pin-{i}"), job.pin, job.strategy))
+            .collect::<Vec<_>>();
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::new("update", count), &jobs, |b, jobs| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    let events = engine::update_all(jobs.clone(), Default::default());
+                    futures::StreamExt::for_each(events, |event| async move {
+                        if let engine::PinEvent::Failed { name, error, .. } = event {
+                            panic!("Updating {name} failed: {error:#}");
+                        }
+                    })
+                    .await
+                })
+            })
+        });
+    }
+    group.finish();
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+criterion_group!(benches, latest_release, lock_files, end_to_end);
+criterion_main!(benches);
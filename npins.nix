@@ -11,12 +11,16 @@
   # runtime dependencies
   lix, # for nix-prefetch-url
   nix-prefetch-git,
+  nix-prefetch-docker,
   git, # for git ls-remote
 }:
 let
   paths = [
     "^/src$"
     "^/src/.+$"
+    # Declared in Cargo.toml, so Cargo wants them to exist
+    "^/benches$"
+    "^/benches/.+$"
     "^/Cargo.lock$"
     "^/Cargo.toml$"
   ];
@@ -44,6 +48,7 @@ let
   runtimePath = lib.makeBinPath [
     lix
     nix-prefetch-git
+    nix-prefetch-docker
     git
   ];
   self = rustPlatform.buildRustPackage {
@@ -40,7 +40,10 @@ pkgs.mkShell {
       nixfmt-rfc-style
       lix
       nix-prefetch-git
+      nix-prefetch-docker
       git
+      # bsdtar, for the tests of the builtin hasher
+      libarchive
       npins
     ]
     ++ (lib.optionals stdenv.isDarwin [
@@ -0,0 +1,182 @@
+//! Two-person approval of changes to sensitive pins
+//!
+//! Pins marked with `requires_approval` are not changed by `npins update` directly. Instead, the
+//! change is proposed in a file next to the lock file (`sources.proposals.json`), and somebody
+//! else applies it with `npins approve <name> --by <name>`. The approval is recorded in the pin
+//! along with a digest of what was approved, so that `npins check` can tell when an approved pin
+//! was changed without going through this.
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Who approved the current contents of a pin
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Approval {
+    pub by: String,
+    /// Who proposed the change, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proposed_by: Option<String>,
+    /// The [`provenance::digest`] of the pin that was approved
+    pub digest: String,
+}
+
+impl Approval {
+    /// Approve `pin` as it is now
+    pub fn new(pin: &Pin, by: &str, proposed_by: Option<String>) -> Result<Self> {
+        Ok(Approval {
+            by: by.to_owned(),
+            proposed_by,
+            digest: provenance::digest(pin)?,
+        })
+    }
+}
+
+/// A change that waits for approval
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Proposal {
+    /// The [`provenance::digest`] of the pin the change is based on
+    pub base: String,
+    /// The pin as it would be after the change
+    pub pin: Pin,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proposed_by: Option<String>,
+    /// When the change was proposed, in UTC
+    pub timestamp: String,
+}
+
+impl Proposal {
+    pub fn new(before: &Pin, after: Pin) -> Result<Self> {
+        Ok(Proposal {
+            base: provenance::digest(before)?,
+            pin: after,
+            proposed_by: current_user(),
+            timestamp: provenance::timestamp(std::time::SystemTime::now()),
+        })
+    }
+}
+
+/// The user running npins, as far as the environment tells
+pub fn current_user() -> Option<String> {
+    ["NPINS_USER", "USER", "USERNAME"]
+        .into_iter()
+        .find_map(|variable| std::env::var(variable).ok())
+        .filter(|user| !user.is_empty())
+}
+
+/// The proposals file belonging to a lock file
+pub fn proposals_path(lock_file: &Path) -> PathBuf {
+    lock_file.with_extension("proposals.json")
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Proposals {
+    pub proposals: BTreeMap<String, Proposal>,
+}
+
+impl Proposals {
+    /// Read the proposals, which are empty if the file doesn't exist
+    pub fn read(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("{} is not a valid proposals file", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Write the proposals, removing the file once there are none left
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if self.proposals.is_empty() {
+            return match std::fs::remove_file(path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    Err(err).with_context(|| format!("Failed to remove {}", path.display()))
+                },
+                _ => Ok(()),
+            };
+        }
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Apply the proposed change to `pin` in the name of `by`
+    pub fn approve(&mut self, name: &str, pin: &mut Pin, by: &str) -> Result<()> {
+        let proposal = self
+            .proposals
+            .get(name)
+            .with_context(|| format!("There is no proposed change to '{}'", name))?;
+        anyhow::ensure!(
+            proposal.proposed_by.as_deref() != Some(by),
+            "The change to '{}' was proposed by {}, it must be approved by somebody else",
+            name,
+            by
+        );
+        anyhow::ensure!(
+            provenance::matches(&proposal.base, pin)?,
+            "'{}' was changed since the change was proposed, it needs to be proposed again",
+            name
+        );
+        let proposal = self.proposals.remove(name).unwrap();
+        *pin = proposal.pin;
+        let approval = Approval::new(pin, by, proposal.proposed_by)?;
+        let metadata = pin.metadata_mut();
+        metadata.requires_approval = true;
+        metadata.approval = Some(approval);
+        Ok(())
+    }
+}
+
+/// Check that a pin requiring approval is in the state that was approved
+pub fn check(pin: &Pin) -> Result<()> {
+    if !pin.requires_approval() {
+        return Ok(());
+    }
+    let approval = pin
+        .metadata()
+        .approval
+        .as_ref()
+        .context("The pin requires approval, but was never approved")?;
+    anyhow::ensure!(
+        provenance::matches(&approval.digest, pin)?,
+        "The pin was changed after {} approved it",
+        approval.by
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_approve() {
+        let mut current = test_pin("https://example.org/tool-1.tar.gz");
+        current.metadata_mut().requires_approval = true;
+        current.metadata_mut().approval = Some(Approval::new(&current, "alice", None).unwrap());
+        check(&current).unwrap();
+
+        let mut proposals = Proposals::default();
+        let mut proposal =
+            Proposal::new(&current, test_pin("https://example.org/tool-2.tar.gz")).unwrap();
+        proposal.proposed_by = Some("bob".into());
+        proposals.proposals.insert("tool".into(), proposal);
+
+        assert!(proposals.approve("tool", &mut current, "bob").is_err());
+        assert!(proposals.approve("other", &mut current, "alice").is_err());
+        proposals.approve("tool", &mut current, "alice").unwrap();
+        assert!(proposals.proposals.is_empty());
+        assert!(current.requires_approval());
+        check(&current).unwrap();
+        assert_eq!(
+            current.metadata().approval.as_ref().unwrap().proposed_by,
+            Some("bob".into())
+        );
+
+        /* Changes made without approval are caught */
+        let mut changed = test_pin("https://example.org/tool-3.tar.gz");
+        *changed.metadata_mut() = current.metadata().clone();
+        assert!(check(&changed).is_err());
+    }
+}
@@ -0,0 +1,406 @@
+//! Pin artifacts from a generic/raw repository on Artifactory or Nexus
+//!
+//! The artifacts are looked up in a directory of the repository, by a file name pattern with a single
+//! `*` standing for the version, e.g. `tool-*.tar.gz`. The highest version wins. Both repository
+//! managers know the SHA-256 of their artifacts, so usually nothing needs to be downloaded.
+//!
+//! Credentials (e.g. `X-JFrog-Art-Api` or `Authorization`) are taken from `NPINS_HEADERS_FILE`,
+//! see [`crate::headers`].
+
+use crate::*;
+use anyhow::{Context, Result};
+use lenient_version::Version;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// The kind of repository manager serving the artifacts
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RepositoryManager {
+    /// JFrog Artifactory, using the storage API
+    #[default]
+    Artifactory,
+    /// Sonatype Nexus Repository 3, using the search API
+    Nexus,
+}
+
+impl std::fmt::Display for RepositoryManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RepositoryManager::Artifactory => "artifactory",
+            RepositoryManager::Nexus => "nexus",
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ArtifactPin {
+    /// Base URL of the repository manager, e.g. `https://example.org/artifactory/` or
+    /// `https://nexus.example.org/`
+    pub server: Url,
+    #[serde(default)]
+    pub manager: RepositoryManager,
+    /// Name of the repository on the server
+    pub repository: String,
+    /// Directory within the repository, without leading or trailing slashes
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+    /// File name pattern, where `*` stands for the version, e.g. `tool-*.tar.gz`
+    pub pattern: String,
+    /// Whether the artifact is an archive that should be unpacked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unpack: bool,
+}
+
+impl diff::Diff for ArtifactPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("server".into(), self.server.to_string())),
+            Some(("manager".into(), self.manager.to_string())),
+            Some(("repository".into(), self.repository.clone())),
+            (!self.path.is_empty()).then(|| ("path".into(), self.path.clone())),
+            Some(("pattern".into(), self.pattern.clone())),
+            self.unpack.then(|| ("unpack".into(), "true".into())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl ArtifactPin {
+    /// Split a `directory/pattern` path into the pin's path and pattern
+    pub fn new(
+        server: Url,
+        manager: RepositoryManager,
+        repository: String,
+        path: &str,
+        unpack: bool,
+    ) -> Result<Self> {
+        let path = path.trim_matches('/');
+        let (path, pattern) = path.rsplit_once('/').unwrap_or(("", path));
+        anyhow::ensure!(
+            pattern.matches('*').count() == 1,
+            "The file name pattern '{pattern}' must contain exactly one `*` for the version"
+        );
+        Ok(Self {
+            server,
+            manager,
+            repository,
+            path: path.into(),
+            pattern: pattern.into(),
+            unpack,
+        })
+    }
+
+    /// The part of a file name that matches the `*` of the pattern
+    fn match_version<'a>(&self, file_name: &'a str) -> Option<&'a str> {
+        let (prefix, suffix) = self.pattern.split_once('*')?;
+        file_name
+            .strip_prefix(prefix)?
+            .strip_suffix(suffix)
+            .filter(|version| !version.is_empty() && !version.contains('/'))
+    }
+
+    fn file_name(&self, version: &str) -> String {
+        self.pattern.replacen('*', version, 1)
+    }
+
+    /// The path of a file relative to the repository
+    fn file_path(&self, file_name: &str) -> String {
+        if self.path.is_empty() {
+            file_name.to_owned()
+        } else {
+            format!("{}/{}", self.path, file_name)
+        }
+    }
+
+    /// Pick the highest version among the file names
+    fn latest<'a>(&self, file_names: impl IntoIterator<Item = &'a str>) -> Result<String> {
+        file_names
+            .into_iter()
+            .filter_map(|file_name| self.match_version(file_name))
+            .filter_map(|version| {
+                let parsed = lenient_semver_parser::parse::<Version>(version);
+                if parsed.is_err() {
+                    log::debug!("Ignoring artifact version '{version}', which is not a version");
+                }
+                Some((parsed.ok()?, version))
+            })
+            .max_by(|(a, a_str), (b, b_str)| latest::cmp((a, a_str), (b, b_str)))
+            .map(|(_, version)| version.to_owned())
+            .ok_or_else(|| {
+                anyhow::format_err!(
+                    "No artifacts matching '{}' found in {}",
+                    self.file_path(&self.pattern),
+                    self.repository
+                )
+            })
+    }
+
+    /// `<server>/api/storage/<repository>/<path…>`, the metadata of a folder or file on Artifactory
+    fn artifactory_storage_url(&self, file_name: Option<&str>) -> Result<Url> {
+        let mut url = self.server.clone();
+        url.path_segments_mut()
+            .map_err(|()| anyhow::format_err!("Invalid server URL {}", self.server))?
+            .pop_if_empty()
+            .extend(["api", "storage", self.repository.as_str()])
+            .extend(self.path.split('/').filter(|segment| !segment.is_empty()))
+            .extend(file_name);
+        Ok(url)
+    }
+
+    /// `<server>/service/rest/v1/search/assets?repository=<repository>&name=<path>`
+    fn nexus_search_url(&self, name: &str, continuation_token: Option<&str>) -> Result<Url> {
+        let mut url = self.server.clone();
+        url.path_segments_mut()
+            .map_err(|()| anyhow::format_err!("Invalid server URL {}", self.server))?
+            .pop_if_empty()
+            .extend(["service", "rest", "v1", "search", "assets"]);
+        url.query_pairs_mut()
+            .append_pair("repository", &self.repository)
+            .append_pair("name", name)
+            .extend_pairs(continuation_token.map(|token| ("continuationToken", token)));
+        Ok(url)
+    }
+
+    /// All assets of the repository matching `name`, which may contain wildcards
+    async fn nexus_assets(&self, name: &str) -> Result<Vec<NexusAsset>> {
+        let mut assets = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let page: NexusPage =
+                get_and_deserialize(self.nexus_search_url(name, continuation_token.as_deref())?)
+                    .await
+                    .context("Could not search the Nexus repository")?;
+            assets.extend(page.items);
+            match page.continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => return Ok(assets),
+            }
+        }
+    }
+
+    async fn latest_version(&self) -> Result<String> {
+        match self.manager {
+            RepositoryManager::Artifactory => {
+                let folder: ArtifactoryFolder =
+                    get_and_deserialize(self.artifactory_storage_url(None)?)
+                        .await
+                        .context("Could not list the Artifactory folder")?;
+                self.latest(
+                    folder
+                        .children
+                        .iter()
+                        .filter(|child| !child.folder)
+                        .map(|child| child.uri.trim_start_matches('/')),
+                )
+            },
+            RepositoryManager::Nexus => {
+                let assets = self.nexus_assets(&self.file_path(&self.pattern)).await?;
+                self.latest(assets.iter().filter_map(|asset| {
+                    /* Search results may include other directories, so check the path as well */
+                    let (path, file_name) = asset
+                        .path
+                        .trim_start_matches('/')
+                        .rsplit_once('/')
+                        .unwrap_or(("", &asset.path));
+                    (path == self.path).then_some(file_name)
+                }))
+            },
+        }
+    }
+
+    /// The download URL and SHA-256 (if known) of an artifact
+    async fn artifact(&self, file_name: &str) -> Result<(Url, Option<String>)> {
+        match self.manager {
+            RepositoryManager::Artifactory => {
+                let file: ArtifactoryFile =
+                    get_and_deserialize(self.artifactory_storage_url(Some(file_name))?)
+                        .await
+                        .with_context(|| {
+                            format!("Could not fetch the Artifactory metadata of {file_name}")
+                        })?;
+                Ok((file.download_uri, file.checksums.sha256))
+            },
+            RepositoryManager::Nexus => {
+                let path = self.file_path(file_name);
+                let asset = self
+                    .nexus_assets(&path)
+                    .await?
+                    .into_iter()
+                    .find(|asset| asset.path.trim_start_matches('/') == path)
+                    .ok_or_else(|| {
+                        anyhow::format_err!("Could not find {} in {}", path, self.repository)
+                    })?;
+                Ok((asset.download_url, asset.checksum.sha256))
+            },
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for ArtifactPin {
+    type Version = GenericVersion;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(&self, old: Option<&GenericVersion>) -> Result<GenericVersion> {
+        let version = self.latest_version().await?;
+
+        if let Some(old) = old {
+            let old_version = lenient_semver_parser::parse::<Version>(&old.version);
+            let new_version = lenient_semver_parser::parse::<Version>(&version);
+            if let (Ok(old_version), Ok(new_version)) = (old_version, new_version) {
+                anyhow::ensure!(
+                    new_version >= old_version,
+                    "Failed to ensure version monotonicity, latest found version is {} but current is {}",
+                    new_version,
+                    old_version,
+                );
+            }
+        }
+
+        Ok(GenericVersion { version })
+    }
+
+    async fn fetch(&self, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        let (url, sha256) = self.artifact(&self.file_name(&version.version)).await?;
+        let hash = match sha256 {
+            /* Unpacking needs the hash of the contents, which the repository manager doesn't know */
+            _ if self.unpack => nix::nix_prefetch_tarball(&url).await?,
+            Some(sha256) => nix::hash_to_sri(&sha256, "sha256")?,
+            /* Artifacts uploaded before SHA-256 was computed may only have SHA-1 and MD5 */
+            None => nix::nix_prefetch_url(&url).await?,
+        };
+        let archive = match self.unpack {
+            true => nix::archive_format(&url, &[]).await?,
+            false => None,
+        };
+        Ok(GenericUrlHashes { url, hash, archive })
+    }
+}
+
+/// We only deserialize what we are interested in.
+/// API reference: <https://jfrog.com/help/r/jfrog-rest-apis/folder-info>
+#[derive(Debug, Deserialize)]
+struct ArtifactoryFolder {
+    children: Vec<ArtifactoryChild>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtifactoryChild {
+    /// The file name with a leading `/`
+    uri: String,
+    folder: bool,
+}
+
+/// API reference: <https://jfrog.com/help/r/jfrog-rest-apis/file-info>
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ArtifactoryFile {
+    download_uri: Url,
+    checksums: Checksums,
+}
+
+#[derive(Debug, Deserialize)]
+struct Checksums {
+    sha256: Option<String>,
+}
+
+/// API reference: <https://help.sonatype.com/en/search-api.html>
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NexusPage {
+    items: Vec<NexusAsset>,
+    continuation_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NexusAsset {
+    download_url: Url,
+    /// Relative to the repository, with or without a leading `/` depending on the version
+    path: String,
+    checksum: Checksums,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pin(manager: RepositoryManager, path: &str) -> ArtifactPin {
+        ArtifactPin::new(
+            "https://example.org/artifactory/".parse().unwrap(),
+            manager,
+            "generic-local".into(),
+            path,
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_new() {
+        let pin = pin(RepositoryManager::Artifactory, "/vendor/tool/tool-*.tar.gz");
+        assert_eq!(pin.path, "vendor/tool");
+        assert_eq!(pin.pattern, "tool-*.tar.gz");
+
+        let pin = self::pin(RepositoryManager::Artifactory, "tool-*.zip");
+        assert_eq!(pin.path, "");
+        assert_eq!(pin.file_path("tool-1.0.zip"), "tool-1.0.zip");
+
+        let new = |path| {
+            ArtifactPin::new(
+                "https://example.org/".parse().unwrap(),
+                RepositoryManager::Nexus,
+                "raw".into(),
+                path,
+                false,
+            )
+        };
+        assert!(new("tool.tar.gz").is_err());
+        assert!(new("tool-*-*.tar.gz").is_err());
+    }
+
+    #[test]
+    fn test_latest() {
+        let pin = pin(RepositoryManager::Artifactory, "vendor/tool-*.tar.gz");
+        assert_eq!(pin.match_version("tool-1.2.3.tar.gz"), Some("1.2.3"));
+        assert_eq!(pin.match_version("tool-.tar.gz"), None);
+        assert_eq!(pin.match_version("tool-1.2.3.zip"), None);
+        assert_eq!(
+            pin.latest([
+                "tool-1.9.0.tar.gz",
+                "tool-1.10.0.tar.gz",
+                "tool-latest.tar.gz",
+                "tool-2.0.0.zip",
+                "other-3.0.0.tar.gz",
+            ])
+            .unwrap(),
+            "1.10.0"
+        );
+        assert_eq!(pin.file_name("1.10.0"), "tool-1.10.0.tar.gz");
+        assert!(pin.latest(["tool-2.0.0.zip"]).is_err());
+    }
+
+    #[test]
+    fn test_api_urls() {
+        let pin = pin(RepositoryManager::Artifactory, "vendor/tool-*.tar.gz");
+        assert_eq!(
+            pin.artifactory_storage_url(None).unwrap().as_str(),
+            "https://example.org/artifactory/api/storage/generic-local/vendor"
+        );
+        assert_eq!(
+            pin.artifactory_storage_url(Some("tool-1.0 β.tar.gz"))
+                .unwrap()
+                .as_str(),
+            "https://example.org/artifactory/api/storage/generic-local/vendor/tool-1.0%20%CE%B2.tar.gz"
+        );
+        assert_eq!(
+            pin.nexus_search_url("vendor/tool-*.tar.gz", Some("abc"))
+                .unwrap()
+                .as_str(),
+            "https://example.org/artifactory/service/rest/v1/search/assets?repository=generic-local&name=vendor%2Ftool-*.tar.gz&continuationToken=abc"
+        );
+    }
+}
@@ -0,0 +1,396 @@
+//! Pin files attached to forge releases, or uploaded to a package registry
+//!
+//! Many projects ship prebuilt binaries as release assets, next to the source tarball the forge
+//! generates. The asset is selected by a glob pattern on its file name, like
+//! `*-x86_64-linux.tar.gz`, because file names usually contain the version.
+//!
+//! Where a glob is not enough, e.g. to exclude checksum files, it can be a regular expression
+//! instead.
+//!
+//! Only GitHub, GitLab and Forgejo list release assets at the moment, see [`forge::Release::assets`].
+//! GitLab projects may also upload files to their [generic package registry] instead, which is
+//! often where the release assets link to.
+//!
+//! [generic package registry]: https://docs.gitlab.com/ee/user/packages/generic_packages/
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ReleaseAssetPin {
+    pub repository: git::Repository,
+    /// Glob pattern for the asset's file name, where `*` matches any characters and `?` a single one
+    pub asset: String,
+    /// Match `asset` as a regular expression against the whole file name instead
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub regex: bool,
+    /// Also track pre-releases
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pre_releases: bool,
+    /// Whether the asset is an archive that should be unpacked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unpack: bool,
+}
+
+impl diff::Diff for ReleaseAssetPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some((
+                "repository".into(),
+                self.repository.git_url().unwrap().to_string(),
+            )),
+            Some(("asset".into(), self.asset.clone())),
+            self.regex.then(|| ("regex".into(), "true".into())),
+            self.pre_releases
+                .then(|| ("pre_releases".into(), "true".into())),
+            self.unpack.then(|| ("unpack".into(), "true".into())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Match a file name against a glob pattern with `*` and `?`
+pub(crate) fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    /* Classic backtracking to the last `*`, which is linear enough for file names */
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Download an asset and hash it, sending the headers if there are any
+async fn prefetch(
+    url: &Url,
+    headers: &[(&'static str, String)],
+    unpack: bool,
+) -> Result<(String, Option<nix::ArchiveFormat>)> {
+    Ok(match (unpack, headers.is_empty()) {
+        (true, true) => (
+            nix::nix_prefetch_tarball(url).await?,
+            nix::archive_format(url, &[]).await?,
+        ),
+        (true, false) => (
+            nix::nix_prefetch_tarball_authenticated(url, headers).await?,
+            nix::archive_format(url, headers).await?,
+        ),
+        (false, true) => (nix::nix_prefetch_url(url).await?, None),
+        (false, false) => (
+            nix::nix_prefetch_url_authenticated(url, headers).await?,
+            None,
+        ),
+    })
+}
+
+impl ReleaseAssetPin {
+    fn forge(&self) -> Result<Box<dyn forge::Forge>> {
+        self.repository
+            .forge()
+            .context("Plain git repositories have no releases")
+    }
+
+    /// Whether a file name matches the pattern
+    fn matcher(&self) -> Result<impl Fn(&str) -> bool + '_> {
+        let regex = self
+            .regex
+            .then(|| regex::Regex::new(&format!("^(?:{})$", self.asset)))
+            .transpose()
+            .with_context(|| format!("'{}' is not a valid regular expression", self.asset))?;
+        Ok(move |name: &str| match &regex {
+            Some(regex) => regex.is_match(name),
+            None => glob_matches(&self.asset, name),
+        })
+    }
+
+    /// The one asset of the release matching the pattern
+    fn select<'a>(&self, release: &'a forge::Release) -> Result<&'a forge::Asset> {
+        let matches = self.matcher()?;
+        let matching = release
+            .assets
+            .iter()
+            .filter(|asset| matches(&asset.name))
+            .collect::<Vec<_>>();
+        match matching[..] {
+            [asset] => Ok(asset),
+            [] => anyhow::bail!(
+                "Release {} has no asset matching '{}'. It has: {}",
+                release.tag,
+                self.asset,
+                release
+                    .assets
+                    .iter()
+                    .map(|asset| asset.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            _ => anyhow::bail!(
+                "Release {} has several assets matching '{}': {}",
+                release.tag,
+                self.asset,
+                matching
+                    .iter()
+                    .map(|asset| asset.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for ReleaseAssetPin {
+    type Version = GenericVersion;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(&self, _old: Option<&GenericVersion>) -> Result<GenericVersion> {
+        let forge = self.forge()?;
+        let release = match forge.latest_release(self.pre_releases).await? {
+            Some(release) => release,
+            None => forge
+                .list_releases()
+                .await?
+                .into_iter()
+                .find(|release| !release.draft && (self.pre_releases || !release.prerelease))
+                .context("The repository has no releases")?,
+        };
+        /* Fail early, and not only when fetching. Assets may still be uploading. */
+        self.select(&release)?;
+        Ok(GenericVersion {
+            version: release.tag,
+        })
+    }
+
+    async fn fetch(&self, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        let forge = self.forge()?;
+        let release = forge
+            .list_releases()
+            .await?
+            .into_iter()
+            .find(|release| release.tag == version.version)
+            .with_context(|| {
+                format!(
+                    "The repository has no release {} among the recent ones",
+                    version.version
+                )
+            })?;
+        let url = self.select(&release)?.url.clone();
+
+        let (hash, archive) = prefetch(&url, &forge.auth_headers(), self.unpack).await?;
+        Ok(GenericUrlHashes { url, hash, archive })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct GitLabPackagePin {
+    /// Of the kind <https://gitlab.example.org/>
+    pub server: Url,
+    /// The project path, e.g. `group/project`
+    pub project: String,
+    /// Access token for private projects, `GITLAB_TOKEN` is used otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_token: Option<String>,
+    /// Name of the package
+    pub package: String,
+    /// Glob pattern for the file name within the package, see [`ReleaseAssetPin::asset`]
+    pub file: String,
+    /// Whether the file is an archive that should be unpacked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unpack: bool,
+}
+
+impl diff::Diff for GitLabPackagePin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("server".into(), self.server.to_string())),
+            Some(("project".into(), self.project.clone())),
+            Some(("package".into(), self.package.clone())),
+            Some(("file".into(), self.file.clone())),
+            self.unpack.then(|| ("unpack".into(), "true".into())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl GitLabPackagePin {
+    fn forge(&self) -> forge::gitlab::GitLab {
+        forge::gitlab::GitLab {
+            repo_path: self.project.clone(),
+            server: self.server.clone(),
+            private_token: self.private_token.clone(),
+        }
+    }
+
+    /// The one file of the package version matching the pattern
+    fn select(&self, version: &str, files: &[String]) -> Result<String> {
+        let matching = files
+            .iter()
+            .filter(|file| glob_matches(&self.file, file))
+            .collect::<Vec<_>>();
+        match matching[..] {
+            [file] => Ok(file.clone()),
+            [] => anyhow::bail!(
+                "Version {version} of {} has no file matching '{}'. It has: {}",
+                self.package,
+                self.file,
+                files.join(", ")
+            ),
+            _ => anyhow::bail!(
+                "Version {version} of {} has several files matching '{}': {}",
+                self.package,
+                self.file,
+                matching
+                    .iter()
+                    .map(|file| file.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// Find the package version and the file to download from it
+    async fn locate(&self, version: Option<&str>) -> Result<(forge::gitlab::Package, String)> {
+        let forge = self.forge();
+        let package = forge
+            .generic_packages(&self.package)
+            .await?
+            .into_iter()
+            .find(|package| version.is_none() || version == Some(package.version.as_str()))
+            .with_context(|| match version {
+                Some(version) => format!(
+                    "The package {} has no version {version} among the recent ones",
+                    self.package
+                ),
+                None => format!("The project has no package {}", self.package),
+            })?;
+        let files = forge.package_files(&package).await?;
+        let file = self.select(&package.version, &files)?;
+        Ok((package, file))
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for GitLabPackagePin {
+    type Version = GenericVersion;
+    type Hashes = GenericUrlHashes;
+
+    /// Package versions are arbitrary strings, so the newest upload wins
+    async fn update(&self, _old: Option<&GenericVersion>) -> Result<GenericVersion> {
+        let (package, _) = self.locate(None).await?;
+        Ok(GenericVersion {
+            version: package.version,
+        })
+    }
+
+    async fn fetch(&self, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        use forge::Forge;
+
+        let forge = self.forge();
+        let (package, file) = self.locate(Some(&version.version)).await?;
+        let url = forge.generic_package_url(&package.name, &package.version, &file)?;
+        let (hash, archive) = prefetch(&url, &forge.auth_headers(), self.unpack).await?;
+        Ok(GenericUrlHashes { url, hash, archive })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches(
+            "*-x86_64-linux.tar.gz",
+            "tool-1.2.3-x86_64-linux.tar.gz"
+        ));
+        assert!(!glob_matches(
+            "*-x86_64-linux.tar.gz",
+            "tool-1.2.3-x86_64-linux.tar.gz.sha256"
+        ));
+        assert!(glob_matches("tool-?.?.*", "tool-1.2.3"));
+        assert!(!glob_matches("tool-?.?.*", "tool-10.2.3"));
+        assert!(glob_matches("*a*b*", "xxaxxbxx"));
+        assert!(glob_matches("exact", "exact"));
+        assert!(!glob_matches("exact", "exactly"));
+    }
+
+    #[test]
+    fn test_select() {
+        let mut pin = ReleaseAssetPin {
+            repository: git::Repository::github("owner", "tool"),
+            asset: "*-linux.tar.gz".into(),
+            regex: false,
+            pre_releases: false,
+            unpack: false,
+        };
+        let asset = |name: &str| forge::Asset {
+            name: name.into(),
+            url: format!("https://example.org/{name}").parse().unwrap(),
+        };
+        let mut release = forge::Release {
+            tag: "v1.0".into(),
+            name: None,
+            draft: false,
+            prerelease: false,
+            historical: false,
+            assets: vec![asset("tool-linux.tar.gz"), asset("tool-darwin.tar.gz")],
+        };
+        assert_eq!(pin.select(&release).unwrap().name, "tool-linux.tar.gz");
+        release.assets.push(asset("tool-musl-linux.tar.gz"));
+        assert!(pin.select(&release).is_err());
+
+        pin.regex = true;
+        pin.asset = r"tool-(darwin|linux)\.tar\.gz".into();
+        assert!(pin.select(&release).is_err());
+        pin.asset = r"tool-linux\.tar\.gz".into();
+        assert_eq!(pin.select(&release).unwrap().name, "tool-linux.tar.gz");
+        /* The whole name must match */
+        pin.asset = "linux".into();
+        assert!(pin.select(&release).is_err());
+        pin.asset = "tool-(linux".into();
+        assert!(pin.select(&release).is_err());
+
+        release.assets.clear();
+        assert!(pin.select(&release).is_err());
+    }
+
+    #[test]
+    fn test_select_package_file() {
+        let pin = GitLabPackagePin {
+            server: "https://gitlab.com/".parse().unwrap(),
+            project: "group/tool".into(),
+            private_token: None,
+            package: "tool".into(),
+            file: "tool-*-linux-amd64".into(),
+            unpack: false,
+        };
+        let files = ["tool-1.0-linux-amd64", "tool-1.0-linux-amd64.sha256"].map(String::from);
+        assert_eq!(pin.select("1.0", &files).unwrap(), "tool-1.0-linux-amd64");
+        assert!(pin.select("1.0", &files[1..]).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! Signed in-toto attestations of how a lock file was produced
+//!
+//! The attestation is an [in-toto statement](https://github.com/in-toto/attestation/blob/main/spec/v1/statement.md)
+//! with the lock file as subject and a [SLSA provenance](https://slsa.dev/spec/v1.0/provenance) predicate,
+//! which lists all pinned artifacts as resolved dependencies. It is wrapped in a
+//! [DSSE envelope](https://github.com/secure-systems-lab/dsse/blob/master/envelope.md) and signed with
+//! an Ed25519 key in the format of `nix key generate-secret`.
+//!
+//! The lock file is identified by its SHA-256 digest, or by its BLAKE3 one with `checksum =
+//! "blake3"` under `[attestation]` in `npins.toml`, see [`crate::checksum`].
+
+use crate::{checksum, NixPins};
+use anyhow::{Context, Result};
+use data_encoding::{BASE64, HEXLOWER};
+use ed25519_dalek::{Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+pub const PREDICATE_TYPE: &str = "https://slsa.dev/provenance/v1";
+pub const PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+const BUILD_TYPE: &str = "https://github.com/andir/npins/update@v1";
+const BUILDER_ID: &str = "https://github.com/andir/npins";
+
+/// A secret key as generated by `nix key generate-secret`: `<name>:<base64 of 64 bytes>`
+pub struct SigningKey {
+    pub name: String,
+    key: ed25519_dalek::SigningKey,
+}
+
+impl std::str::FromStr for SigningKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, key) = split_key(s)?;
+        let key: [u8; 64] = key
+            .try_into()
+            .map_err(|_| anyhow::format_err!("The secret key must be 64 bytes long"))?;
+        Ok(SigningKey {
+            name,
+            key: ed25519_dalek::SigningKey::from_keypair_bytes(&key)
+                .context("Invalid secret key")?,
+        })
+    }
+}
+
+impl SigningKey {
+    /// The matching public key
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            name: self.name.clone(),
+            key: self.key.verifying_key(),
+        }
+    }
+
+    /// The Ed25519 signature of a message
+    pub(crate) fn sign_message(&self, message: &[u8]) -> [u8; 64] {
+        self.key.sign(message).to_bytes()
+    }
+}
+
+/// A public key as generated by `nix key convert-secret-to-public`: `<name>:<base64 of 32 bytes>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    pub name: String,
+    key: ed25519_dalek::VerifyingKey,
+}
+
+impl std::str::FromStr for PublicKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, key) = split_key(s)?;
+        let key: [u8; 32] = key
+            .try_into()
+            .map_err(|_| anyhow::format_err!("The public key must be 32 bytes long"))?;
+        Ok(PublicKey {
+            name,
+            key: ed25519_dalek::VerifyingKey::from_bytes(&key).context("Invalid public key")?,
+        })
+    }
+}
+
+impl PublicKey {
+    /// Whether `signature` is a valid Ed25519 signature of the message by this key
+    pub(crate) fn verifies(&self, message: &[u8], signature: &[u8]) -> bool {
+        ed25519_dalek::Signature::from_slice(signature)
+            .is_ok_and(|signature| self.key.verify(message, &signature).is_ok())
+    }
+}
+
+impl std::fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.name, BASE64.encode(self.key.as_bytes()))
+    }
+}
+
+fn split_key(s: &str) -> Result<(String, Vec<u8>)> {
+    let (name, key) = s
+        .trim()
+        .split_once(':')
+        .context("Expected a key of the form `<name>:<base64>`")?;
+    anyhow::ensure!(!name.is_empty(), "The key has no name");
+    let key = BASE64
+        .decode(key.as_bytes())
+        .context("The key is not valid base64")?;
+    Ok((name.to_owned(), key))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub payload_type: String,
+    /// Base64 of the serialized statement
+    pub payload: String,
+    pub signatures: Vec<Signature>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// The name of the key
+    pub keyid: String,
+    /// Base64 of the Ed25519 signature over the [`pae`] of the payload
+    pub sig: String,
+}
+
+impl Envelope {
+    /// Sign a statement
+    pub fn sign(statement: &serde_json::Value, key: &SigningKey) -> Result<Self> {
+        let payload = serde_json::to_vec(statement)?;
+        let signature = key.key.sign(&pae(PAYLOAD_TYPE, &payload));
+        Ok(Envelope {
+            payload_type: PAYLOAD_TYPE.into(),
+            payload: BASE64.encode(&payload),
+            signatures: vec![Signature {
+                keyid: key.name.clone(),
+                sig: BASE64.encode(&signature.to_bytes()),
+            }],
+        })
+    }
+
+    /// Check that the envelope is signed by any of the given keys, and return the statement
+    pub fn verify(&self, keys: &[PublicKey]) -> Result<serde_json::Value> {
+        anyhow::ensure!(
+            self.payload_type == PAYLOAD_TYPE,
+            "Unsupported payload type '{}'",
+            self.payload_type
+        );
+        let payload = BASE64
+            .decode(self.payload.as_bytes())
+            .context("The payload is not valid base64")?;
+        let message = pae(&self.payload_type, &payload);
+
+        let verified = self.signatures.iter().any(|signature| {
+            let Ok(sig) = BASE64.decode(signature.sig.as_bytes()) else {
+                return false;
+            };
+            let Ok(sig) = ed25519_dalek::Signature::from_slice(&sig) else {
+                return false;
+            };
+            keys.iter()
+                .filter(|key| key.name == signature.keyid)
+                .any(|key| key.key.verify(&message, &sig).is_ok())
+        });
+        anyhow::ensure!(
+            verified,
+            "The attestation is not signed by any of the trusted keys"
+        );
+
+        serde_json::from_slice(&payload).context("The payload is not valid JSON")
+    }
+}
+
+/// DSSE pre-authentication encoding, which is what actually gets signed
+fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Build the statement for a lock file
+///
+/// `lock_file` is the serialized content as written to disk, `name` its file name. Run details
+/// (invocation, CI job, timestamps) are taken from the current process, like for the
+/// [`Provenance`](crate::provenance::Provenance) of pins.
+pub fn statement(
+    name: &str,
+    lock_file: &[u8],
+    pins: &NixPins,
+    started_on: std::time::SystemTime,
+) -> Result<serde_json::Value> {
+    let dependencies = pins
+        .pins
+        .iter()
+        .map(|(name, pin)| resolved_dependency(name, &serde_json::to_value(pin)?))
+        .collect::<Result<Vec<_>>>()?;
+    let checksum = crate::config::get().attestation.checksum;
+
+    let mut metadata = serde_json::json!({
+        "startedOn": crate::provenance::timestamp(started_on),
+        "finishedOn": crate::provenance::timestamp(std::time::SystemTime::now()),
+    });
+    if let Some(url) = crate::provenance::ci_job_url() {
+        metadata["invocationId"] = url.into();
+    }
+
+    Ok(serde_json::json!({
+        "_type": STATEMENT_TYPE,
+        "subject": [{
+            "name": name,
+            "digest": { checksum.to_string(): checksum.hex(lock_file) },
+        }],
+        "predicateType": PREDICATE_TYPE,
+        "predicate": {
+            "buildDefinition": {
+                "buildType": BUILD_TYPE,
+                "externalParameters": {
+                    "invocation": crate::provenance::redact_arguments(std::env::args()),
+                },
+                "resolvedDependencies": dependencies,
+            },
+            "runDetails": {
+                "builder": {
+                    "id": BUILDER_ID,
+                    "version": { "npins": env!("CARGO_PKG_VERSION") },
+                },
+                "metadata": metadata,
+            },
+        },
+    }))
+}
+
+/// Describe a pin as in-toto resource descriptor
+///
+/// This looks at the serialized form, so that it works regardless of the pin type. The digests
+/// are the ones Nix checks when fetching, i.e. over the unpacked contents in most cases, and are
+/// thus recorded as `nix-<algo>` instead of e.g. `sha256`.
+fn resolved_dependency(name: &str, pin: &serde_json::Value) -> Result<serde_json::Value> {
+    let field = |key| pin.get(key).and_then(serde_json::Value::as_str);
+
+    let mut digest = serde_json::Map::new();
+    if let Some(hash) = field("hash") {
+        let hash = nix_compat::nixhash::from_str(hash, None)
+            .with_context(|| format!("Invalid hash '{hash}' in pin '{name}'"))?;
+        digest.insert(
+            format!("nix-{}", hash.algo()),
+            HEXLOWER.encode(hash.digest_as_bytes()).into(),
+        );
+    }
+    if let Some(revision) = field("revision") {
+        digest.insert("gitCommit".into(), revision.into());
+    }
+
+    let mut dependency = serde_json::json!({ "name": name, "digest": digest });
+    if let Some(url) = field("url") {
+        dependency["uri"] = url.into();
+    }
+    Ok(dependency)
+}
+
+/// Check that a verified statement is about the given lock file, see [`Envelope::verify`]
+pub fn check_subject(statement: &serde_json::Value, lock_file: &[u8]) -> Result<()> {
+    anyhow::ensure!(
+        statement["_type"] == STATEMENT_TYPE,
+        "Not an in-toto statement"
+    );
+    /* Whichever algorithm the attestation was made with, as long as npins knows it */
+    let matches = statement["subject"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|subject| subject["digest"].as_object())
+        .flatten()
+        .any(|(algorithm, digest)| {
+            algorithm
+                .parse::<checksum::Algorithm>()
+                .is_ok_and(|algorithm| *digest == algorithm.hex(lock_file).as_str())
+        });
+    let checksum = crate::config::get().attestation.checksum;
+    anyhow::ensure!(
+        matches,
+        "The attestation is about a different version of the lock file ({checksum} {})",
+        checksum.hex(lock_file)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /* Seed 0x00..0x1f, in the format of `nix key generate-secret --key-name test-1` */
+    const SECRET_KEY: &str = "test-1:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8DoQe/884Qvh1w3RjnS8CZZ+TWMJulDV8d3IZkElUxuA==";
+    const PUBLIC_KEY: &str = "test-1:A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=";
+
+    #[test]
+    fn test_pae() {
+        /* Example from the DSSE specification */
+        assert_eq!(
+            pae("http://example.com/HelloWorld", b"hello world"),
+            b"DSSEv1 29 http://example.com/HelloWorld 11 hello world"
+        );
+    }
+
+    #[test]
+    fn test_keys() {
+        let key: SigningKey = SECRET_KEY.parse().unwrap();
+        assert_eq!(key.public_key().to_string(), PUBLIC_KEY);
+        assert!("test-1:AAAA".parse::<SigningKey>().is_err());
+        assert!("A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg="
+            .parse::<PublicKey>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key: SigningKey = SECRET_KEY.parse().unwrap();
+        let statement = serde_json::json!({"_type": STATEMENT_TYPE, "subject": []});
+        let envelope = Envelope::sign(&statement, &key).unwrap();
+
+        assert_eq!(
+            envelope.verify(&[PUBLIC_KEY.parse().unwrap()]).unwrap(),
+            statement
+        );
+
+        let other = SigningKey {
+            name: "test-1".into(),
+            key: ed25519_dalek::SigningKey::from_bytes(&[7; 32]),
+        }
+        .public_key();
+        assert!(envelope.verify(&[other]).is_err());
+
+        let mut tampered = envelope.clone();
+        tampered.payload = BASE64.encode(br#"{"_type":"https://in-toto.io/Statement/v1"}"#);
+        assert!(tampered.verify(&[PUBLIC_KEY.parse().unwrap()]).is_err());
+    }
+
+    #[test]
+    fn test_resolved_dependency() {
+        let pin = serde_json::json!({
+            "type": "Git",
+            "revision": "1edb0a9cebe046cc915a218c57dbf7f40739aeee",
+            "url": "https://github.com/andir/npins/archive/1edb0a9cebe046cc915a218c57dbf7f40739aeee.tar.gz",
+            "hash": "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+        });
+        assert_eq!(
+            resolved_dependency("npins", &pin).unwrap(),
+            serde_json::json!({
+                "name": "npins",
+                "uri": "https://github.com/andir/npins/archive/1edb0a9cebe046cc915a218c57dbf7f40739aeee.tar.gz",
+                "digest": {
+                    "nix-sha256": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                    "gitCommit": "1edb0a9cebe046cc915a218c57dbf7f40739aeee",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_subject() {
+        let statement = serde_json::json!({
+            "_type": STATEMENT_TYPE,
+            "subject": [{
+                "name": "sources.json",
+                "digest": { "sha256": checksum::Algorithm::Sha256.hex(b"{}\n") },
+            }],
+        });
+        assert!(check_subject(&statement, b"{}\n").is_ok());
+        assert!(check_subject(&statement, b"{ }\n").is_err());
+
+        let mut blake3 = statement.clone();
+        blake3["subject"][0]["digest"] =
+            serde_json::json!({ "blake3": checksum::Algorithm::Blake3.hex(b"{}\n") });
+        assert!(check_subject(&blake3, b"{}\n").is_ok());
+        assert!(check_subject(&blake3, b"{ }\n").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Find pins that are, or soon will be, no longer maintained
+//!
+//! Release cycles and their end of life dates are looked up on [endoflife.date](https://endoflife.date),
+//! by the pin's name (or a product given explicitly) and the version it is pinned to. Channel pins
+//! map to the `nixos` product, with the release in the channel name. Additionally, forges are asked
+//! whether the repository of a pin was archived.
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// How long before the end of life to start warning about it
+const WARN_BEFORE: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+fn get_endoflife_url() -> String {
+    std::env::var("NPINS_ENDOFLIFE_URL").unwrap_or_else(|_| "https://endoflife.date".into())
+}
+
+/// A release cycle as listed on endoflife.date
+#[derive(Debug, Deserialize)]
+struct Cycle {
+    /// Usually a string, but some products use plain numbers
+    cycle: serde_json::Value,
+    /// The date of the end of life, or whether it was reached if there is no date
+    eol: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// The release cycle reached its end of life, on the given date if known
+    EndOfLife {
+        product: String,
+        cycle: String,
+        date: Option<String>,
+    },
+    /// The release cycle reaches its end of life soon
+    EndOfLifeSoon {
+        product: String,
+        cycle: String,
+        date: String,
+    },
+    /// The repository was archived by its maintainers
+    Archived,
+}
+
+impl Finding {
+    /// Whether this needs action now rather than being a heads-up
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Finding::EndOfLifeSoon { .. })
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Finding::EndOfLife {
+                product,
+                cycle,
+                date: Some(date),
+            } => write!(f, "{product} {cycle} reached its end of life on {date}"),
+            Finding::EndOfLife {
+                product,
+                cycle,
+                date: None,
+            } => write!(f, "{product} {cycle} reached its end of life"),
+            Finding::EndOfLifeSoon {
+                product,
+                cycle,
+                date,
+            } => write!(f, "{product} {cycle} reaches its end of life on {date}"),
+            Finding::Archived => write!(f, "the repository was archived"),
+        }
+    }
+}
+
+/// The first version-like part of a string, e.g. `24.05` for `nixos-24.05-small`
+fn version_in(string: &str) -> Option<&str> {
+    let start = string.find(|c: char| c.is_ascii_digit())?;
+    let rest = &string[start..];
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(rest.len());
+    Some(rest[..end].trim_end_matches('.'))
+}
+
+/// The endoflife.date product and the version of a pin, if it can tell
+fn product_and_version(name: &str, pin: &Pin) -> Option<(String, String)> {
+    if let Pin::Channel { input, .. } = pin {
+        return Some(("nixos".into(), version_in(&input.name)?.to_owned()));
+    }
+    let properties = pin.properties();
+    let version = ["version", "branch"].into_iter().find_map(|key| {
+        properties
+            .iter()
+            .find(|(property, _)| property == key)
+            .and_then(|(_, value)| version_in(value))
+    })?;
+    /* Variants like `postgres.v15` are versions of the same product */
+    let product = NixPins::split_variant(name, pin).map_or(name, |(group, _)| group);
+    Some((product.to_lowercase(), version.to_owned()))
+}
+
+/// The most specific cycle that `version` belongs to, e.g. `3.12` for `3.12.4`
+fn find_cycle<'a>(cycles: &'a [Cycle], version: &str) -> Option<(String, &'a Cycle)> {
+    cycles
+        .iter()
+        .map(|cycle| {
+            let name = match &cycle.cycle {
+                serde_json::Value::String(name) => name.clone(),
+                other => other.to_string(),
+            };
+            (name, cycle)
+        })
+        .filter(|(name, _)| {
+            version == name
+                || version
+                    .strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+        .max_by_key(|(name, _)| name.len())
+}
+
+/// Judge a cycle's end of life, with dates as `YYYY-MM-DD`
+fn judge(
+    product: &str,
+    cycle: String,
+    eol: &serde_json::Value,
+    today: &str,
+    soon: &str,
+) -> Option<Finding> {
+    match eol {
+        serde_json::Value::Bool(true) => Some(Finding::EndOfLife {
+            product: product.to_owned(),
+            cycle,
+            date: None,
+        }),
+        serde_json::Value::String(date) if date.as_str() <= today => Some(Finding::EndOfLife {
+            product: product.to_owned(),
+            cycle,
+            date: Some(date.clone()),
+        }),
+        serde_json::Value::String(date) if date.as_str() <= soon => Some(Finding::EndOfLifeSoon {
+            product: product.to_owned(),
+            cycle,
+            date: date.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// The release cycles of a product, `None` if endoflife.date doesn't know it
+async fn cycles(product: &str) -> Result<Option<Vec<Cycle>>> {
+    let base: Url = get_endoflife_url()
+        .parse()
+        .context("NPINS_ENDOFLIFE_URL is not a valid URL")?;
+    let url = forge::url_with_segments(&base, ["api", &format!("{product}.json")])?;
+    provenance::record_endpoint(&url);
+    let response = http_get(url.clone())?.send_throttled().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let cycles = response
+        .error_for_status()
+        .with_context(|| format!("Failed to query {}", url))?
+        .json()
+        .await
+        .with_context(|| format!("Unexpected response from {}", url))?;
+    Ok(Some(cycles))
+}
+
+/// Audit a pin, looking it up on endoflife.date as `product` if given
+pub async fn audit(name: &str, pin: &Pin, product: Option<&str>) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+
+    if let Some((guessed, version)) = product_and_version(name, pin) {
+        let product = product.map(ToOwned::to_owned).unwrap_or(guessed);
+        match cycles(&product).await? {
+            Some(cycles) => match find_cycle(&cycles, &version) {
+                Some((cycle, found)) => {
+                    let now = SystemTime::now();
+                    let today = &provenance::timestamp(now)[..10];
+                    let soon = &provenance::timestamp(now + WARN_BEFORE)[..10];
+                    findings.extend(judge(&product, cycle, &found.eol, today, soon));
+                },
+                None => log::debug!("[{name}] endoflife.date has no {product} {version}"),
+            },
+            None => log::debug!("[{name}] endoflife.date does not know {product}"),
+        }
+    }
+
+    if let Some(forge) = pin.repository().and_then(git::Repository::forge) {
+        if forge.is_archived().await? == Some(true) {
+            findings.push(Finding::Archived);
+        }
+    }
+
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_product_and_version() {
+        let channel: Pin = channel::Pin::new("nixos-24.05-small").into();
+        assert_eq!(
+            product_and_version("nixpkgs", &channel),
+            Some(("nixos".into(), "24.05".into()))
+        );
+        assert_eq!(
+            product_and_version("nixpkgs", &channel::Pin::new("nixos-unstable").into()),
+            None
+        );
+        assert_eq!(version_in("v3.12.4"), Some("3.12.4"));
+        assert_eq!(version_in("release-1.2."), Some("1.2"));
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        let cycles: Vec<Cycle> = serde_json::from_str(
+            r#"[
+                {"cycle": "3.13", "eol": "2029-10-31"},
+                {"cycle": "3.1", "eol": "2012-04-09"},
+                {"cycle": 3, "eol": false},
+                {"cycle": "2.7", "eol": true}
+            ]"#,
+        )
+        .unwrap();
+        let judge_version = |version: &str| {
+            let (cycle, found) = find_cycle(&cycles, version)?;
+            judge("python", cycle, &found.eol, "2026-10-16", "2027-01-14")
+        };
+        assert_eq!(judge_version("3.13.1"), None);
+        assert_eq!(
+            judge_version("3.1.5"),
+            Some(Finding::EndOfLife {
+                product: "python".into(),
+                cycle: "3.1".into(),
+                date: Some("2012-04-09".into()),
+            })
+        );
+        /* 3.12 isn't listed, so it falls back to 3 */
+        assert_eq!(judge_version("3.12"), None);
+        assert!(judge_version("2.7.18").unwrap().is_error());
+        assert_eq!(judge_version("4.0"), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! The protocol of `npins batch`, for driving npins from other tools
+//!
+//! Every line on stdin is a request in JSON, like `{"id": 1, "command": "get", "names":
+//! ["nixpkgs"]}`, and for each one a [`Response`] is printed as a single line of JSON on stdout.
+//! Requests are handled one after the other by the same process, which saves starting npins and
+//! opening new connections for each of them.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// What a request asks for, in its `command` field
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Command {
+    /// The pins with the given names, or all of them
+    Get {
+        #[serde(default)]
+        names: Vec<String>,
+    },
+    /// Add a pin, `args` are those of `npins add`, e.g. `["github", "NixOS", "nixpkgs"]`
+    Add { args: Vec<String> },
+    /// Update the given pins, or all of them. Frozen pins are skipped
+    Update {
+        #[serde(default)]
+        names: Vec<String>,
+        /// Like `npins update --full`
+        #[serde(default)]
+        full: bool,
+        /// Like `npins update --approved-by`, for pins that the policy requires approval for
+        #[serde(default)]
+        approved_by: Option<String>,
+    },
+    /// Remove the given pins
+    Remove { names: Vec<String> },
+}
+
+/// Parse a line of input into the `id` of the request and its command
+///
+/// The `id` is taken even if the command is invalid, so that the error can be matched up.
+pub fn parse(line: &str) -> (Value, Result<Command>) {
+    let mut request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => return (Value::Null, Err(err).context("Invalid JSON")),
+    };
+    let id = request
+        .as_object_mut()
+        .and_then(|request| request.remove("id"))
+        .unwrap_or(Value::Null);
+    let command = serde_json::from_value(request).context("Invalid request");
+    (id, command)
+}
+
+/// The result of a request
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Response {
+    /// The `id` of the request, as is
+    pub id: Value,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    pub fn new(id: Value, result: Result<Value>) -> Self {
+        match result {
+            Ok(result) => Response {
+                id,
+                ok: true,
+                result: Some(result),
+                error: None,
+            },
+            Err(err) => Response {
+                id,
+                ok: false,
+                result: None,
+                error: Some(format!("{err:#}")),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_protocol() {
+        let (id, command) = parse(r#"{"id": "a", "command": "update", "names": ["nixpkgs"]}"#);
+        assert_eq!(id, "a");
+        assert_eq!(
+            command.unwrap(),
+            Command::Update {
+                names: vec!["nixpkgs".into()],
+                full: false,
+                approved_by: None,
+            }
+        );
+        assert_eq!(
+            parse(r#"{"command": "get"}"#).1.unwrap(),
+            Command::Get { names: vec![] }
+        );
+
+        /* The id is kept for errors */
+        let (id, command) = parse(r#"{"id": 7, "command": "upgrade"}"#);
+        assert_eq!(id, 7);
+        let response = Response::new(id, command.map(|_| Value::Null));
+        assert!(!response.ok);
+        assert!(response.error.unwrap().starts_with("Invalid request"));
+        assert_eq!(parse("add").0, Value::Null);
+
+        assert_eq!(
+            serde_json::to_value(Response::new(Value::Null, Ok(serde_json::json!(["a"])))).unwrap(),
+            serde_json::json!({ "id": null, "ok": true, "result": ["a"] })
+        );
+    }
+}
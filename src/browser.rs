@@ -0,0 +1,291 @@
+//! Pin browser extensions
+//!
+//! Firefox add-ons are resolved via the [addons.mozilla.org API](https://mozilla.github.io/addons-server/topics/api/addons.html),
+//! Chrome extensions via the update service of the Chrome Web Store.
+
+use crate::*;
+use anyhow::{Context, Result};
+use lenient_version::Version;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Make sure versions only move forward
+fn check_monotonicity(old: &str, new: &str) -> Result<()> {
+    let old_version = lenient_semver_parser::parse::<Version>(old);
+    let new_version = lenient_semver_parser::parse::<Version>(new);
+    match (old_version, new_version) {
+        (Ok(old_version), Ok(new_version)) => {
+            anyhow::ensure!(
+                new_version >= old_version,
+                "Failed to ensure version monotonicity, latest found version is {} but current is {}",
+                new_version,
+                old_version,
+            );
+        },
+        _ => {
+            log::warn!("This extension does not appear to be following SemVer, so no guarantees on monotonicity can be made.");
+        },
+    }
+    Ok(())
+}
+
+/// An add-on on addons.mozilla.org
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct FirefoxAddonPin {
+    /// The add-on's slug (as in its AMO URL, e.g. `ublock-origin`) or GUID
+    pub addon: String,
+}
+
+impl diff::Diff for FirefoxAddonPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![("addon".into(), self.addon.clone())]
+    }
+}
+
+impl FirefoxAddonPin {
+    fn api_url(&self, version: Option<&str>) -> Url {
+        let mut url: Url = "https://addons.mozilla.org/api/v5/addons/addon/"
+            .parse()
+            .unwrap();
+        {
+            let mut segments = url.path_segments_mut().unwrap();
+            segments.pop_if_empty().push(&self.addon);
+            if let Some(version) = version {
+                segments.push("versions").push(version);
+            }
+            /* The API wants a trailing slash */
+            segments.push("");
+        }
+        url
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for FirefoxAddonPin {
+    type Version = GenericVersion;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(&self, old: Option<&GenericVersion>) -> Result<GenericVersion> {
+        let metadata: AmoAddon = get_and_deserialize(self.api_url(None))
+            .await
+            .context("Could not fetch add-on metadata from addons.mozilla.org")?;
+        let version = metadata.current_version.version;
+
+        if let Some(old) = old {
+            check_monotonicity(&old.version, &version)?;
+        }
+
+        Ok(GenericVersion { version })
+    }
+
+    async fn fetch(&self, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        let metadata: AmoVersion = get_and_deserialize(self.api_url(Some(&version.version)))
+            .await
+            .with_context(|| {
+                format!(
+                    "Could not fetch metadata for version {} from addons.mozilla.org",
+                    version.version
+                )
+            })?;
+
+        /* Hashes are of the form `sha256:<hex>` */
+        let (algo, hash) = metadata.file.hash.split_once(':').ok_or_else(|| {
+            anyhow::format_err!("Unexpected hash format '{}'", metadata.file.hash)
+        })?;
+        Ok(GenericUrlHashes {
+            url: metadata.file.url,
+            hash: nix::hash_to_sri(hash, algo)?,
+            archive: None,
+        })
+    }
+}
+
+/// We only deserialize what we are interested in.
+/// API reference: <https://mozilla.github.io/addons-server/topics/api/addons.html#detail>
+#[derive(Debug, Deserialize)]
+struct AmoAddon {
+    current_version: AmoVersion,
+}
+
+#[derive(Debug, Deserialize)]
+struct AmoVersion {
+    version: String,
+    file: AmoFile,
+}
+
+#[derive(Debug, Deserialize)]
+struct AmoFile {
+    url: Url,
+    hash: String,
+}
+
+/// An extension in the Chrome Web Store
+///
+/// The store only serves the latest version of an extension, thus the version information
+/// already contains the download URL (which is immutable).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ChromeExtensionPin {
+    /// The 32 character extension ID, e.g. `cjpalhdlnbpafiamejdnhcphjbkeiagm`
+    pub id: String,
+}
+
+impl diff::Diff for ChromeExtensionPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![("id".into(), self.id.clone())]
+    }
+}
+
+impl ChromeExtensionPin {
+    pub fn new(id: impl Into<String>) -> Result<Self> {
+        let id = id.into();
+        anyhow::ensure!(
+            id.len() == 32 && id.chars().all(|c| ('a'..='p').contains(&c)),
+            "'{id}' is not a valid Chrome extension ID (32 characters a-p)"
+        );
+        Ok(Self { id })
+    }
+}
+
+/// The Chrome version we pretend to be when asking for updates. Extensions may require
+/// some minimum browser version, so this should be bumped every once in a while.
+const CHROME_PRODVERSION: &str = "130.0";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ChromeExtensionVersion {
+    pub version: String,
+    /// Download URL of the CRX file
+    pub url: Url,
+}
+
+impl diff::Diff for ChromeExtensionVersion {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![
+            ("version".into(), self.version.clone()),
+            ("url".into(), self.url.to_string()),
+        ]
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for ChromeExtensionPin {
+    type Version = ChromeExtensionVersion;
+    type Hashes = GenericHash;
+
+    async fn update(&self, old: Option<&ChromeExtensionVersion>) -> Result<ChromeExtensionVersion> {
+        /* This is the same update protocol that the browser itself uses.
+         * https://github.com/google/omaha/blob/main/doc/ServerProtocolV2.md
+         */
+        let mut url: Url = "https://clients2.google.com/service/update2/crx"
+            .parse()
+            .unwrap();
+        url.query_pairs_mut()
+            .append_pair("response", "updatecheck")
+            .append_pair("prodversion", CHROME_PRODVERSION)
+            .append_pair("acceptformat", "crx2,crx3")
+            .append_pair("x", &format!("id={}&uc", self.id));
+
+        provenance::record_endpoint(&url);
+        let response = http_get(url)?
+            .send_throttled()
+            .await?
+            .error_for_status()?
+            .text()
+            .await
+            .context("Could not query the Chrome Web Store")?;
+        let version = parse_update_check(&response)
+            .with_context(|| format!("Extension {} not found in the Chrome Web Store", self.id))?;
+
+        if let Some(old) = old {
+            check_monotonicity(&old.version, &version.version)?;
+        }
+
+        Ok(version)
+    }
+
+    async fn fetch(&self, version: &ChromeExtensionVersion) -> Result<GenericHash> {
+        Ok(GenericHash {
+            hash: nix::nix_prefetch_url(&version.url).await?,
+            archive: None,
+        })
+    }
+}
+
+/// Extract the version and download URL from an update check response, which looks like this:
+///
+/// ```xml
+/// <gupdate xmlns="http://www.google.com/update2/response" protocol="2.0" server="prod">
+///   <app appid="…" cohort="1::" cohortname="" status="ok">
+///     <updatecheck codebase="https://…/extension_1_2_3_0.crx" fp="…" hash_sha256="…" size="…" status="ok" version="1.2.3"/>
+///   </app>
+/// </gupdate>
+/// ```
+fn parse_update_check(response: &str) -> Result<ChromeExtensionVersion> {
+    /* We don't want to pull in a whole XML parser for reading two attributes */
+    fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+        let start = element.find(&format!(" {name}=\""))? + name.len() + 3;
+        let length = element[start..].find('"')?;
+        Some(&element[start..start + length])
+    }
+
+    let start = response
+        .find("<updatecheck")
+        .context("Response contains no `updatecheck` element")?;
+    let element = &response[start..];
+    let element = &element[..element.find('>').unwrap_or(element.len())];
+
+    match attribute(element, "status") {
+        Some("ok") => {},
+        status => anyhow::bail!("Update check failed with status {:?}", status),
+    }
+    let version = attribute(element, "version").context("Response contains no version")?;
+    let url = attribute(element, "codebase")
+        .context("Response contains no download URL")?
+        .replace("&amp;", "&");
+
+    Ok(ChromeExtensionVersion {
+        version: version.into(),
+        url: url.parse().context("Invalid download URL")?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_update_check() {
+        let response = r#"<?xml version="1.0" encoding="UTF-8"?><gupdate xmlns="http://www.google.com/update2/response" protocol="2.0" server="prod"><daystart elapsed_days="6498" elapsed_seconds="50105"/><app appid="cjpalhdlnbpafiamejdnhcphjbkeiagm" cohort="1::" cohortname="" status="ok"><updatecheck codebase="https://clients2.googleusercontent.com/crx/blobs/AcLY/CJPALHDLNBPAFIAMEJDNHCPHJBKEIAGM_1_59_0_0.crx" fp="1.5c3b" hash_sha256="3e5b" size="3890471" status="ok" version="1.59.0"/></app></gupdate>"#;
+        assert_eq!(
+            parse_update_check(response).unwrap(),
+            ChromeExtensionVersion {
+                version: "1.59.0".into(),
+                url: "https://clients2.googleusercontent.com/crx/blobs/AcLY/CJPALHDLNBPAFIAMEJDNHCPHJBKEIAGM_1_59_0_0.crx".parse().unwrap(),
+            }
+        );
+
+        let response = r#"<gupdate protocol="2.0" server="prod"><app appid="aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa" status="ok"><updatecheck status="noupdate"/></app></gupdate>"#;
+        assert!(parse_update_check(response).is_err());
+    }
+
+    #[test]
+    fn test_amo_api_url() {
+        let pin = FirefoxAddonPin {
+            addon: "ublock-origin".into(),
+        };
+        assert_eq!(
+            pin.api_url(None).as_str(),
+            "https://addons.mozilla.org/api/v5/addons/addon/ublock-origin/"
+        );
+        assert_eq!(
+            pin.api_url(Some("1.59.0")).as_str(),
+            "https://addons.mozilla.org/api/v5/addons/addon/ublock-origin/versions/1.59.0/"
+        );
+        let pin = FirefoxAddonPin {
+            addon: "{446900e4-71c2-419f-a6a7-df9c091e268b}".into(),
+        };
+        assert_eq!(
+            pin.api_url(None).as_str(),
+            "https://addons.mozilla.org/api/v5/addons/addon/%7B446900e4-71c2-419f-a6a7-df9c091e268b%7D/"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Summaries of the commits a pin moved over, to make updates reviewable
+//!
+//! When the revision of a pin hosted on a forge changes, the forge is asked for the commits in
+//! between. They are shown after updating, and can be written out as a commit message for the
+//! update, e.g. for automated pull requests.
+
+use crate::*;
+use anyhow::Result;
+use std::fmt::Write;
+
+/// How many commits to list per pin, the rest are only counted
+const MAX_COMMITS: usize = 20;
+
+/// The commits between two revisions of a pin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changelog {
+    pub from: String,
+    pub to: String,
+    pub comparison: forge::Comparison,
+}
+
+fn short(revision: &str) -> &str {
+    &revision[..revision.len().min(7)]
+}
+
+fn revision(pin: &Pin) -> Option<String> {
+    pin.properties()
+        .into_iter()
+        .find(|(property, _)| property == "revision")
+        .map(|(_, revision)| revision)
+}
+
+/// The commits `after` is ahead of `before`, if the pin's revision changed and its forge can tell
+pub async fn changelog(before: &Pin, after: &Pin) -> Result<Option<Changelog>> {
+    let (Some(from), Some(to)) = (revision(before), revision(after)) else {
+        return Ok(None);
+    };
+    if from == to {
+        return Ok(None);
+    }
+    let Some(forge) = after.repository().and_then(git::Repository::forge) else {
+        return Ok(None);
+    };
+    let comparison = forge.compare(&from, &to).await?;
+    Ok(Some(Changelog {
+        from,
+        to,
+        comparison,
+    }))
+}
+
+impl Changelog {
+    /// How many commits `to` is ahead of `from`, even if the forge didn't list all of them
+    pub fn total_commits(&self) -> usize {
+        self.comparison
+            .total_commits
+            .unwrap_or(self.comparison.commits.len())
+    }
+}
+
+impl std::fmt::Display for Changelog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let commits = &self.comparison.commits;
+        let total = self.total_commits();
+        writeln!(
+            f,
+            "{}..{} ({} commit{})",
+            short(&self.from),
+            short(&self.to),
+            total,
+            if total == 1 { "" } else { "s" }
+        )?;
+        for commit in commits.iter().take(MAX_COMMITS) {
+            write!(f, "  - {} ", commit.summary)?;
+            if let Some(author) = &commit.author {
+                write!(f, "({author}, {})", short(&commit.revision))?;
+            } else {
+                write!(f, "({})", short(&commit.revision))?;
+            }
+            writeln!(f)?;
+        }
+        let shown = commits.len().min(MAX_COMMITS);
+        if total > shown {
+            writeln!(f, "  - and {} more", total - shown)?;
+        }
+        if let Some(url) = &self.comparison.web_url {
+            writeln!(f, "  {url}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A commit message for an update, listing the changelogs of the pins or else their changes
+pub fn commit_message(
+    changes: &BTreeMap<String, diff::PinDiff>,
+    changelogs: &BTreeMap<String, Changelog>,
+) -> String {
+    let names = changes.keys().map(String::as_str).collect::<Vec<_>>();
+    let mut message = if names.len() <= 3 {
+        format!("npins: update {}\n", names.join(", "))
+    } else {
+        format!("npins: update {} pins\n", names.len())
+    };
+    for (name, change) in changes {
+        match changelogs.get(name) {
+            Some(changelog) => write!(message, "\n{name}: {changelog}").unwrap(),
+            None => {
+                writeln!(message, "\n{name}:").unwrap();
+                for entry in &change.changes {
+                    write!(message, "{entry}").unwrap();
+                }
+            },
+        }
+    }
+    message
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_commit_message() {
+        let mut old = NixPins::default();
+        old.pins
+            .insert("lib".into(), test_pin("https://example.org/lib-1.tar.gz"));
+        old.pins
+            .insert("tool".into(), test_pin("https://example.org/tool-1.tar.gz"));
+        let mut new = NixPins::default();
+        new.pins
+            .insert("lib".into(), test_pin("https://example.org/lib-2.tar.gz"));
+        new.pins
+            .insert("tool".into(), test_pin("https://example.org/tool-2.tar.gz"));
+
+        let commit = |revision: &str, summary: &str, author: Option<&str>| forge::CommitSummary {
+            revision: revision.into(),
+            summary: summary.into(),
+            author: author.map(Into::into),
+        };
+        let mut changelogs = BTreeMap::new();
+        changelogs.insert(
+            "tool".to_owned(),
+            Changelog {
+                from: "0123456789".into(),
+                to: "abcdef0123".into(),
+                comparison: forge::Comparison {
+                    commits: vec![
+                        commit("1111111111", "Fix the frobnicator", Some("Alice")),
+                        commit("abcdef0123", "Release 2.0", None),
+                    ],
+                    total_commits: Some(25),
+                    web_url: Some(
+                        "https://example.org/tool/compare/0123456789...abcdef0123"
+                            .parse()
+                            .unwrap(),
+                    ),
+                },
+            },
+        );
+
+        assert_eq!(
+            commit_message(&diff::diff_pins(&old, &new), &changelogs),
+            "npins: update lib, tool
+
+lib:
+-    url: https://example.org/lib-1.tar.gz
++    url: https://example.org/lib-2.tar.gz
+
+tool: 0123456..abcdef0 (25 commits)
+  - Fix the frobnicator (Alice, 1111111)
+  - Release 2.0 (abcdef0)
+  - and 23 more
+  https://example.org/tool/compare/0123456789...abcdef0123
+"
+        );
+    }
+}
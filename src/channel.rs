@@ -52,12 +52,10 @@ impl Updatable for Pin {
         /* We want to get from something like https://channels.nixos.org/nixos-21.11
          * to https://releases.nixos.org/nixos/21.11/nixos-21.11.335807.df4f1f7cc3f/nixexprs.tar.xz
          */
-        let url = build_client()?
-            .head(&format!(
-                "https://channels.nixos.org/{}/nixexprs.tar.xz",
-                self.name
-            ))
-            .send()
+        let channel_url = format!("https://channels.nixos.org/{}/nixexprs.tar.xz", self.name);
+        provenance::record_endpoint(&channel_url);
+        let url = http_request(reqwest::Method::HEAD, &channel_url)?
+            .send_throttled()
             .await?
             .url()
             .clone();
@@ -0,0 +1,106 @@
+//! Checksums of npins' own files, as opposed to the hashes of the pins
+//!
+//! The hashes in the lock file are for Nix, and thus always SHA-256 in SRI format. Internally,
+//! npins is free to use whatever is fastest. The algorithm is set in `npins.toml` per subsystem:
+//!
+//! ```toml
+//! # Which lock file the journal belongs to, see `crate::journal`
+//! [journal]
+//! checksum = "blake3"
+//! # The digests of the pins in their provenance and approvals, see `crate::provenance`
+//! [provenance]
+//! checksum = "blake3"
+//! # The digest of the lock file in attestations, see `crate::attestation`
+//! [attestation]
+//! checksum = "blake3"
+//! ```
+//!
+//! Everything records the algorithm it was written with, so that changing the algorithm doesn't
+//! invalidate the existing files and digests. They are rewritten with the new algorithm as they
+//! are updated.
+
+use anyhow::{Context, Result};
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    /// What all files were written with before they recorded their algorithm
+    #[default]
+    Sha256,
+    /// Several times faster than SHA-256 on large files
+    Blake3,
+}
+
+impl Algorithm {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The checksum of `data`, in hexadecimal
+    pub fn hex(self, data: &[u8]) -> String {
+        match self {
+            Algorithm::Sha256 => HEXLOWER.encode(&Sha256::digest(data)),
+            Algorithm::Blake3 => blake3::hash(data).to_hex().to_string(),
+        }
+    }
+
+    /// The checksum of `data` along with the algorithm, as `<algorithm>:<hex>`
+    pub fn prefixed(self, data: &[u8]) -> String {
+        format!("{self}:{}", self.hex(data))
+    }
+}
+
+/// Whether a checksum from [`Algorithm::prefixed`] is the one of `data`
+pub fn matches_prefixed(checksum: &str, data: &[u8]) -> Result<bool> {
+    let (algorithm, hex) = checksum
+        .split_once(':')
+        .with_context(|| format!("Invalid checksum {checksum}, expected `<algorithm>:<hex>`"))?;
+    Ok(algorithm.parse::<Algorithm>()?.hex(data) == hex)
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha256" => Ok(Algorithm::Sha256),
+            "blake3" => Ok(Algorithm::Blake3),
+            _ => anyhow::bail!("Unknown checksum algorithm {s}, expected `sha256` or `blake3`"),
+        }
+    }
+}
+
+impl std::fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Blake3 => "blake3",
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(
+            Algorithm::Sha256.hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            Algorithm::Blake3.hex(b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+
+        for algorithm in [Algorithm::Sha256, Algorithm::Blake3] {
+            assert!(matches_prefixed(&algorithm.prefixed(b"abc"), b"abc").unwrap());
+            assert!(!matches_prefixed(&algorithm.prefixed(b"abc"), b"abd").unwrap());
+        }
+        assert!(matches_prefixed("md5:900150983cd24fb0d6963f7d28e17f72", b"abc").is_err());
+    }
+}
@@ -0,0 +1,542 @@
+//! Pin artifacts of CI runs
+//!
+//! Some projects only publish (nightly) builds as artifacts of their CI. These pins track the latest
+//! successful run on a branch and pin one of its artifacts. Note that CI systems usually delete
+//! artifacts after some time, after which the pin can only be used from the Nix store.
+//!
+//! Downloading artifacts mostly requires a token, even for public projects. It is passed via the
+//! environment, and the download happens in-process (see [`nix::nix_prefetch_url_authenticated`]).
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// GET some JSON with extra headers
+async fn get_json<T>(url: Url, headers: &[(&'static str, String)]) -> Result<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    provenance::record_endpoint(&url);
+    let mut request = http_get(url)?;
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    Ok(request
+        .send_throttled()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+/// Download an artifact and hash it
+async fn prefetch(url: &Url, headers: &[(&'static str, String)], unpack: bool) -> Result<String> {
+    if unpack {
+        nix::nix_prefetch_tarball_authenticated(url, headers).await
+    } else {
+        nix::nix_prefetch_url_authenticated(url, headers).await
+    }
+}
+
+/// A CI run, identified by a number that increases with every run
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct CiRun {
+    pub run_id: u64,
+    /// The commit that was built
+    pub revision: String,
+}
+
+impl diff::Diff for CiRun {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![
+            ("run_id".into(), self.run_id.to_string()),
+            ("revision".into(), self.revision.clone()),
+        ]
+    }
+}
+
+impl CiRun {
+    fn check_monotonicity(&self, old: Option<&CiRun>) -> Result<()> {
+        if let Some(old) = old {
+            anyhow::ensure!(
+                self.run_id >= old.run_id,
+                "Failed to ensure monotonicity, latest successful run is {} but current is {}",
+                self.run_id,
+                old.run_id,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// An artifact of a GitHub Actions workflow
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct GitHubArtifactPin {
+    pub owner: String,
+    pub repo: String,
+    /// The workflow's file name (e.g. `nightly.yml`) or ID
+    pub workflow: String,
+    pub branch: String,
+    /// Name of the artifact
+    pub artifact: String,
+    /// Whether to unpack the artifact's zip file
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unpack: bool,
+}
+
+impl diff::Diff for GitHubArtifactPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("owner".into(), self.owner.clone())),
+            Some(("repo".into(), self.repo.clone())),
+            Some(("workflow".into(), self.workflow.clone())),
+            Some(("branch".into(), self.branch.clone())),
+            Some(("artifact".into(), self.artifact.clone())),
+            self.unpack.then(|| ("unpack".into(), "true".into())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl GitHubArtifactPin {
+    /// `<github_api>/repos/<owner>/<repo>/actions/<path…>`
+    fn api_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let base: Url = forge::github::get_github_api_url()
+            .parse()
+            .context("The GitHub API host is not a valid URL")?;
+        let url = forge::url_with_segments(&base, ["repos", &self.owner, &self.repo, "actions"])?;
+        forge::url_with_segments(&url, path)
+    }
+
+    /// Downloading artifacts needs a token even for public repositories
+    fn auth_headers() -> Vec<(&'static str, String)> {
+        match std::env::var("GITHUB_TOKEN") {
+            Ok(token) => vec![("authorization", format!("Bearer {token}"))],
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for GitHubArtifactPin {
+    type Version = CiRun;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(&self, old: Option<&CiRun>) -> Result<CiRun> {
+        /* https://docs.github.com/en/rest/actions/workflow-runs#list-workflow-runs-for-a-workflow */
+        let mut url = self.api_url(["workflows", &self.workflow, "runs"])?;
+        url.query_pairs_mut()
+            .append_pair("branch", &self.branch)
+            .append_pair("status", "success")
+            .append_pair("per_page", "1");
+        let runs: GitHubWorkflowRuns = get_json(url, &Self::auth_headers())
+            .await
+            .context("Couldn't fetch the workflow runs")?;
+        let run = runs.workflow_runs.into_iter().next().ok_or_else(|| {
+            anyhow::format_err!(
+                "No successful runs of {} on {} found",
+                self.workflow,
+                self.branch
+            )
+        })?;
+
+        let run = CiRun {
+            run_id: run.id,
+            revision: run.head_sha,
+        };
+        run.check_monotonicity(old)?;
+        Ok(run)
+    }
+
+    async fn fetch(&self, version: &CiRun) -> Result<GenericUrlHashes> {
+        /* https://docs.github.com/en/rest/actions/artifacts#list-workflow-run-artifacts */
+        let mut url = self.api_url(["runs", &version.run_id.to_string(), "artifacts"])?;
+        url.query_pairs_mut().append_pair("name", &self.artifact);
+        let headers = Self::auth_headers();
+        let artifacts: GitHubArtifacts = get_json(url, &headers)
+            .await
+            .context("Couldn't fetch the artifacts of the run")?;
+        let artifact = artifacts
+            .artifacts
+            .into_iter()
+            .find(|artifact| artifact.name == self.artifact)
+            .ok_or_else(|| {
+                anyhow::format_err!(
+                    "Run {} has no artifact named {}",
+                    version.run_id,
+                    self.artifact
+                )
+            })?;
+        anyhow::ensure!(
+            !artifact.expired,
+            "The artifact {} of run {} has expired",
+            self.artifact,
+            version.run_id
+        );
+        anyhow::ensure!(
+            !headers.is_empty(),
+            "Downloading artifacts from GitHub requires a token in GITHUB_TOKEN"
+        );
+
+        let hash = prefetch(&artifact.archive_download_url, &headers, self.unpack).await?;
+        Ok(GenericUrlHashes {
+            url: artifact.archive_download_url,
+            hash,
+            /* Artifacts are always zipped, but the URL ends in `/zip` */
+            archive: self.unpack.then_some(nix::ArchiveFormat::Zip),
+        })
+    }
+}
+
+/// An artifact archive of a GitLab CI job
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct GitLabArtifactPin {
+    /// Of the kind <https://gitlab.example.org/>
+    pub server: Url,
+    /// The project path, e.g. `group/project`
+    pub project: String,
+    pub branch: String,
+    /// Name of the job
+    pub job: String,
+    /// Whether to unpack the artifacts' zip file
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unpack: bool,
+}
+
+impl diff::Diff for GitLabArtifactPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("server".into(), self.server.to_string())),
+            Some(("project".into(), self.project.clone())),
+            Some(("branch".into(), self.branch.clone())),
+            Some(("job".into(), self.job.clone())),
+            self.unpack.then(|| ("unpack".into(), "true".into())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl GitLabArtifactPin {
+    /// How many pipelines to look at for the job, as it might not be part of every one of them
+    const MAX_PIPELINES: usize = 20;
+
+    /// `<server>/api/v4/projects/<project>/<path…>`
+    fn api_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let url = forge::url_with_segments(&self.server, ["api", "v4", "projects", &self.project])?;
+        forge::url_with_segments(&url, path)
+    }
+
+    /// Same as for GitLab repositories, i.e. from `GITLAB_TOKEN`
+    fn auth_headers(&self) -> Vec<(&'static str, String)> {
+        use forge::Forge;
+        forge::gitlab::GitLab {
+            repo_path: self.project.clone(),
+            server: self.server.clone(),
+            private_token: None,
+        }
+        .auth_headers()
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for GitLabArtifactPin {
+    type Version = CiRun;
+    type Hashes = GenericUrlHashes;
+
+    /// The run is the job, whose IDs increase as well
+    async fn update(&self, old: Option<&CiRun>) -> Result<CiRun> {
+        let headers = self.auth_headers();
+        /* https://docs.gitlab.com/ee/api/pipelines.html#list-project-pipelines */
+        let mut url = self.api_url(["pipelines"])?;
+        url.query_pairs_mut()
+            .append_pair("ref", &self.branch)
+            .append_pair("status", "success")
+            .append_pair("per_page", &Self::MAX_PIPELINES.to_string());
+        let pipelines: Vec<GitLabPipeline> = get_json(url, &headers)
+            .await
+            .context("Couldn't fetch the pipelines")?;
+
+        for pipeline in pipelines {
+            /* https://docs.gitlab.com/ee/api/jobs.html#list-pipeline-jobs */
+            let mut url = self.api_url(["pipelines", &pipeline.id.to_string(), "jobs"])?;
+            url.query_pairs_mut()
+                .append_pair("scope[]", "success")
+                .append_pair("per_page", "100");
+            let jobs: Vec<GitLabJob> = get_json(url, &headers)
+                .await
+                .context("Couldn't fetch the jobs of the pipeline")?;
+            if let Some(job) = jobs.into_iter().find(|job| job.name == self.job) {
+                let run = CiRun {
+                    run_id: job.id,
+                    revision: pipeline.sha,
+                };
+                run.check_monotonicity(old)?;
+                return Ok(run);
+            }
+        }
+        anyhow::bail!(
+            "No successful job {} found in the last {} successful pipelines on {}",
+            self.job,
+            Self::MAX_PIPELINES,
+            self.branch
+        )
+    }
+
+    async fn fetch(&self, version: &CiRun) -> Result<GenericUrlHashes> {
+        /* https://docs.gitlab.com/ee/api/job_artifacts.html#get-job-artifacts
+         * Unlike `jobs/artifacts/<ref>/download?job=<name>`, this does not change with new pipelines.
+         */
+        let url = self.api_url(["jobs", &version.run_id.to_string(), "artifacts"])?;
+        let hash = prefetch(&url, &self.auth_headers(), self.unpack).await?;
+        Ok(GenericUrlHashes {
+            url,
+            hash,
+            archive: self.unpack.then_some(nix::ArchiveFormat::Zip),
+        })
+    }
+}
+
+/// An artifact of a Buildkite build
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct BuildkiteArtifactPin {
+    pub organization: String,
+    pub pipeline: String,
+    pub branch: String,
+    /// The path the artifact was uploaded with, e.g. `dist/tool.tar.gz`
+    pub artifact: String,
+    /// Whether the artifact is an archive that should be unpacked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unpack: bool,
+}
+
+impl diff::Diff for BuildkiteArtifactPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("organization".into(), self.organization.clone())),
+            Some(("pipeline".into(), self.pipeline.clone())),
+            Some(("branch".into(), self.branch.clone())),
+            Some(("artifact".into(), self.artifact.clone())),
+            self.unpack.then(|| ("unpack".into(), "true".into())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl BuildkiteArtifactPin {
+    /// `https://api.buildkite.com/v2/organizations/<organization>/pipelines/<pipeline>/builds/<path…>`
+    fn api_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let url = forge::url_with_segments(
+            &"https://api.buildkite.com/v2/organizations/".parse()?,
+            [&self.organization, "pipelines", &self.pipeline, "builds"],
+        )?;
+        forge::url_with_segments(&url, path)
+    }
+
+    /// The REST API always needs an access token
+    fn auth_headers() -> Result<Vec<(&'static str, String)>> {
+        let token = std::env::var("BUILDKITE_TOKEN")
+            .context("Accessing Buildkite requires an API access token in BUILDKITE_TOKEN")?;
+        Ok(vec![("authorization", format!("Bearer {token}"))])
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for BuildkiteArtifactPin {
+    type Version = CiRun;
+    type Hashes = GenericUrlHashes;
+
+    /// The run is the build number
+    async fn update(&self, old: Option<&CiRun>) -> Result<CiRun> {
+        /* https://buildkite.com/docs/apis/rest-api/builds#list-builds-for-a-pipeline */
+        let mut url = self.api_url(std::iter::empty::<&str>())?;
+        url.query_pairs_mut()
+            .append_pair("branch", &self.branch)
+            .append_pair("state", "passed")
+            .append_pair("per_page", "1");
+        let builds: Vec<BuildkiteBuild> = get_json(url, &Self::auth_headers()?)
+            .await
+            .context("Couldn't fetch the builds")?;
+        let build = builds.into_iter().next().ok_or_else(|| {
+            anyhow::format_err!(
+                "No passed builds of {} on {} found",
+                self.pipeline,
+                self.branch
+            )
+        })?;
+
+        let run = CiRun {
+            run_id: build.number,
+            revision: build.commit,
+        };
+        run.check_monotonicity(old)?;
+        Ok(run)
+    }
+
+    async fn fetch(&self, version: &CiRun) -> Result<GenericUrlHashes> {
+        /* https://buildkite.com/docs/apis/rest-api/artifacts#list-artifacts-for-a-build */
+        let mut url = self.api_url([&version.run_id.to_string(), "artifacts"])?;
+        url.query_pairs_mut().append_pair("per_page", "100");
+        let headers = Self::auth_headers()?;
+        let artifacts: Vec<BuildkiteArtifact> = get_json(url, &headers)
+            .await
+            .context("Couldn't fetch the artifacts of the build")?;
+        let artifact = artifacts
+            .into_iter()
+            .find(|artifact| artifact.path == self.artifact)
+            .ok_or_else(|| {
+                anyhow::format_err!("Build {} has no artifact {}", version.run_id, self.artifact)
+            })?;
+
+        let hash = match artifact.sha256sum {
+            Some(sha256) if !self.unpack => nix::hash_to_sri(&sha256, "sha256")?,
+            _ => prefetch(&artifact.download_url, &headers, self.unpack).await?,
+        };
+        /* The download URL has no file name, but the artifact's path does */
+        let archive = match nix::ArchiveFormat::from_path(&self.artifact) {
+            _ if !self.unpack => None,
+            Some(format) => Some(format),
+            None => nix::archive_format(&artifact.download_url, &headers).await?,
+        };
+        Ok(GenericUrlHashes {
+            url: artifact.download_url,
+            hash,
+            archive,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabPipeline {
+    id: u64,
+    sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabJob {
+    id: u64,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BuildkiteBuild {
+    number: u64,
+    commit: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BuildkiteArtifact {
+    path: String,
+    download_url: Url,
+    /// Only present for artifacts uploaded by recent agents
+    #[serde(default)]
+    sha256sum: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubWorkflowRuns {
+    workflow_runs: Vec<GitHubWorkflowRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubWorkflowRun {
+    id: u64,
+    head_sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubArtifacts {
+    artifacts: Vec<GitHubArtifact>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubArtifact {
+    name: String,
+    expired: bool,
+    archive_download_url: Url,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_actions_api_url() {
+        let pin = GitHubArtifactPin {
+            owner: "NixOS".into(),
+            repo: "nix".into(),
+            workflow: "ci.yml".into(),
+            branch: "master".into(),
+            artifact: "installer".into(),
+            unpack: false,
+        };
+        assert_eq!(
+            pin.api_url(["workflows", "ci.yml", "runs"])
+                .unwrap()
+                .as_str(),
+            "https://api.github.com/repos/NixOS/nix/actions/workflows/ci.yml/runs"
+        );
+
+        let run = CiRun {
+            run_id: 42,
+            revision: "abc".into(),
+        };
+        assert!(run
+            .check_monotonicity(Some(&CiRun {
+                run_id: 41,
+                revision: "def".into()
+            }))
+            .is_ok());
+        assert!(run
+            .check_monotonicity(Some(&CiRun {
+                run_id: 43,
+                revision: "def".into()
+            }))
+            .is_err());
+    }
+
+    #[test]
+    fn test_ci_api_urls() {
+        let pin = GitLabArtifactPin {
+            server: "https://gitlab.com/".parse().unwrap(),
+            project: "group/sub/project".into(),
+            branch: "main".into(),
+            job: "build".into(),
+            unpack: false,
+        };
+        assert_eq!(
+            pin.api_url(["jobs", "42", "artifacts"]).unwrap().as_str(),
+            "https://gitlab.com/api/v4/projects/group%2Fsub%2Fproject/jobs/42/artifacts"
+        );
+
+        let pin = BuildkiteArtifactPin {
+            organization: "acme".into(),
+            pipeline: "tool".into(),
+            branch: "main".into(),
+            artifact: "dist/tool.tar.gz".into(),
+            unpack: false,
+        };
+        assert_eq!(
+            pin.api_url(["42", "artifacts"]).unwrap().as_str(),
+            "https://api.buildkite.com/v2/organizations/acme/pipelines/tool/builds/42/artifacts"
+        );
+    }
+}
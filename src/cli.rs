@@ -1,12 +1,12 @@
 //! The main CLI application
 
-use npins::*;
+use npins::{engine::UpdateStrategy, *};
 
 use std::{
     cell::{Cell, RefCell},
     collections::{BTreeMap, BTreeSet},
-    io::{stderr, IsTerminal, Write},
-    path::PathBuf,
+    io::{stderr, BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
 };
 
 use anyhow::{Context, Result};
@@ -20,42 +20,25 @@ use crossterm::{
 use futures::{
     future,
     stream::{self, StreamExt},
-    TryStreamExt,
 };
 
 use url::{ParseError, Url};
 
 const DEFAULT_NIX: &'static str = include_str!("default.nix");
 
-/// How to handle updates
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub enum UpdateStrategy {
-    /// Fetch latest version, update hashes if necessary
-    Normal,
-    /// Update hashes of the currently pinned version
-    HashesOnly,
-    /// Fetch latest version, always update hashes
-    Full,
+/// Load an Ed25519 secret key in the format of `nix key generate-secret`
+fn read_signing_key(path: &std::path::Path) -> Result<attestation::SigningKey> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .parse()
+        .with_context(|| format!("Failed to load the signing key {}", path.display()))
 }
 
-impl UpdateStrategy {
-    /// Whether the latest version should be fetched
-    pub fn should_update(&self) -> bool {
-        match self {
-            UpdateStrategy::Normal => true,
-            UpdateStrategy::HashesOnly => false,
-            UpdateStrategy::Full => true,
-        }
-    }
-
-    /// Whether we want to force-update the hashes
-    pub fn must_fetch(&self) -> bool {
-        match self {
-            UpdateStrategy::Normal => false,
-            UpdateStrategy::HashesOnly => true,
-            UpdateStrategy::Full => true,
-        }
-    }
+/// The number of things to do at once, which must be at least one
+fn parse_jobs(jobs: &str) -> Result<usize> {
+    let jobs = jobs.parse()?;
+    anyhow::ensure!(jobs > 0, "must be at least 1");
+    Ok(jobs)
 }
 
 #[derive(Debug, Parser)]
@@ -76,13 +59,52 @@ impl ChannelAddOpts {
 }
 
 #[derive(Debug, Parser)]
+pub struct HydraAddOpts {
+    /// The job to follow, as `project/jobset/job`. For example `nixos/release-24.05/tested`
+    pub job: String,
+
+    /// The jobset input to pin
+    #[arg(long, default_value = "nixpkgs")]
+    pub input: String,
+
+    #[arg(
+        long,
+        default_value = "https://hydra.nixos.org/",
+        help = "Use a different Hydra instance",
+        value_name = "url"
+    )]
+    pub server: url::Url,
+}
+
+impl HydraAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let [project, jobset, job] = self.job.split('/').collect::<Vec<_>>()[..] else {
+            anyhow::bail!(
+                "Invalid job '{}', it must have the form `project/jobset/job`",
+                self.job
+            );
+        };
+        let pin = hydra::HydraPin {
+            server: self.server.clone(),
+            project: project.to_owned(),
+            jobset: jobset.to_owned(),
+            job: job.to_owned(),
+            input: self.input.clone(),
+        };
+        Ok((Some(jobset.to_owned()), pin.into()))
+    }
+}
+
+#[derive(Debug, Default, Parser)]
 pub struct GenericGitAddOpts {
-    /// Track a branch instead of a release
-    #[arg(short, long)]
+    /// Track a branch instead of a release. Other refs are tracked by giving them in full, like
+    /// `--ref refs/pull/42/head`
+    #[arg(short, long, visible_alias = "ref")]
     pub branch: Option<String>,
 
     /// Use a specific commit/release instead of the latest.
-    /// This may be a tag name, or a git revision when --branch is set.
+    /// This may be a tag name, or a git revision when --branch is set. Revisions must be on the
+    /// branch, which is checked and recorded.
     #[arg(long, value_name = "tag or rev")]
     pub at: Option<String>,
 
@@ -100,6 +122,15 @@ pub struct GenericGitAddOpts {
     )]
     pub version_upper_bound: Option<String>,
 
+    /// Only consider the releases matching a version requirement, like ">=1.2, <2.0" or
+    /// "~1.4" for tracking a series. Conflicts with the --branch option.
+    #[arg(
+        long,
+        value_name = "requirement",
+        conflicts_with_all = &["branch", "at"]
+    )]
+    pub version_requirement: Option<String>,
+
     /// Optional prefix required for each release name / tag. For
     /// example, setting this to "release/" will only consider those
     /// that start with that string.
@@ -109,13 +140,74 @@ pub struct GenericGitAddOpts {
     /// Also fetch submodules
     #[arg(long)]
     pub submodules: bool,
+
+    /// Always fetch via git, or always as tarball. By default, tarballs are used when the forge provides them, unless the repository uses `export-subst`.
+    #[arg(long, value_name = "git|tarball")]
+    pub prefer: Option<git::Prefer>,
+
+    /// Use the release the forge marks as "latest" instead of the highest version among the tags.
+    /// Only supported on GitHub and GitLab, other forges fall back to the tags. Conflicts with the --branch option.
+    #[arg(long, conflicts_with = "branch")]
+    pub forge_latest: bool,
+
+    /// How to compare the tags as versions: `lenient` (the default) accepts anything that looks
+    /// like a version, `semver` only strict SemVer 2.0 versions, ordering build metadata too.
+    /// `calver` compares calendar versions like `2024.05.1` or `r20240915`, `numeric` only the
+    /// numbers in the tags, and `lexicographic` the tags as strings.
+    /// Conflicts with the --branch option.
+    #[arg(
+        long,
+        value_name = "lenient|semver|calver|numeric|lexicographic",
+        conflicts_with = "branch"
+    )]
+    pub version_scheme: Option<latest::Comparator>,
+
+    /// Rewrite the tags before comparing them, as `<regex>=<replacement>`. May be repeated, the
+    /// rewrites are applied in order. E.g. `--normalize '^OpenSSL_=' --normalize '_=.'` turns
+    /// `OpenSSL_1_1_1w` into `1.1.1w`. Conflicts with the --branch option.
+    #[arg(long, value_name = "REWRITE", conflicts_with = "branch")]
+    pub normalize: Vec<latest::Rewrite>,
+
+    /// Start at this epoch instead of 0. Bump the `epoch` in the lock file when upstream restarts
+    /// its versioning, to allow updating to a lower version. Conflicts with the --branch option.
+    #[arg(long, default_value_t = 0, conflicts_with = "branch")]
+    pub epoch: u64,
+
+    /// Track a tag that upstream moves to new commits, like `latest` or `stable`, instead of
+    /// the latest release. The pin is locked to the commit the tag points to.
+    #[arg(
+        long,
+        value_name = "TAG",
+        conflicts_with_all = &["branch", "at", "pre_releases", "version_upper_bound", "version_requirement", "release_prefix", "forge_latest", "version_scheme", "normalize"]
+    )]
+    pub moving_tag: Option<String>,
+
+    /// Only consider the tags on this branch, e.g. to skip releases cut from maintenance
+    /// branches. Checked via the forge's API, or by cloning the repository without any files
+    #[arg(
+        long,
+        value_name = "BRANCH",
+        conflicts_with_all = &["branch", "forge_latest", "moving_tag"]
+    )]
+    pub reachable_from: Option<String>,
+
+    /// Only consider the releases matching this glob pattern, like `3.*`. May be repeated.
+    /// Conflicts with the --branch option.
+    #[arg(long = "only-version", value_name = "GLOB", conflicts_with_all = &["branch", "moving_tag"])]
+    pub only_versions: Vec<String>,
+
+    /// Skip the releases matching this glob pattern, like a broken `3.2.1` or all `*.0`. May be
+    /// repeated. Conflicts with the --branch option.
+    #[arg(long = "exclude-version", value_name = "GLOB", conflicts_with_all = &["branch", "moving_tag"])]
+    pub exclude_versions: Vec<String>,
 }
 
 impl GenericGitAddOpts {
     fn add(&self, repository: git::Repository) -> Result<Pin> {
         Ok(match &self.branch {
             Some(branch) => {
-                let pin = git::GitPin::new(repository, branch.clone(), self.submodules);
+                let mut pin = git::GitPin::new(repository, branch.clone(), self.submodules);
+                pin.prefer = self.prefer;
                 let version = self
                     .at
                     .as_ref()
@@ -124,15 +216,29 @@ impl GenericGitAddOpts {
                 (pin, version).into()
             },
             None => {
-                let pin = git::GitReleasePin::new(
+                let mut pin = git::GitReleasePin::new(
                     repository,
                     self.pre_releases,
                     self.version_upper_bound.clone(),
                     self.release_prefix.clone(),
                     self.submodules,
                 );
-                let version = self.at.as_ref().map(|at| GenericVersion {
+                pin.version_requirement = self.version_requirement.clone();
+                pin.prefer = self.prefer;
+                pin.forge_latest = self.forge_latest;
+                pin.version_scheme = latest::VersionScheme {
+                    comparator: self.version_scheme.unwrap_or_default(),
+                    normalize: self.normalize.clone(),
+                };
+                pin.epoch = self.epoch;
+                pin.moving_tag = self.moving_tag.clone();
+                pin.reachable_from = self.reachable_from.clone();
+                pin.only_versions = self.only_versions.clone();
+                pin.exclude_versions = self.exclude_versions.clone();
+                let version = self.at.as_ref().map(|at| git::ReleaseVersion {
                     version: at.clone(),
+                    epoch: self.epoch,
+                    tag_commit: None,
                 });
                 (pin, version).into()
             },
@@ -157,6 +263,209 @@ impl GitHubAddOpts {
     }
 }
 
+#[derive(Debug, Parser)]
+pub struct ReleaseAssetAddOpts {
+    pub owner: String,
+    pub repository: String,
+
+    /// Glob pattern for the asset's file name, e.g. `*-x86_64-linux.tar.gz`.
+    /// It must match exactly one asset of the release.
+    pub asset: String,
+
+    /// Match the asset's file name against a regular expression instead of a glob pattern
+    #[arg(long)]
+    pub regex: bool,
+
+    /// Also track pre-releases
+    #[arg(long)]
+    pub pre_releases: bool,
+
+    /// Unpack the downloaded archive
+    #[arg(long)]
+    pub unpack: bool,
+
+    /// Use the release with this tag instead of the latest
+    #[arg(long, value_name = "tag")]
+    pub at: Option<String>,
+}
+
+impl ReleaseAssetAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = asset::ReleaseAssetPin {
+            repository: git::Repository::github(&self.owner, &self.repository),
+            asset: self.asset.clone(),
+            regex: self.regex,
+            pre_releases: self.pre_releases,
+            unpack: self.unpack,
+        };
+        let version = self.at.as_ref().map(|at| GenericVersion {
+            version: at.clone(),
+        });
+        Ok((Some(self.repository.clone()), (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct GitLabReleaseAssetAddOpts {
+    /// The project path, e.g. `group/project`
+    pub project: String,
+
+    /// Glob pattern for the name of the asset link, e.g. `*-x86_64-linux.tar.gz`.
+    /// It must match exactly one asset of the release.
+    pub asset: String,
+
+    /// Match the asset's name against a regular expression instead of a glob pattern
+    #[arg(long)]
+    pub regex: bool,
+
+    #[arg(
+        long,
+        default_value = "https://gitlab.com/",
+        help = "Use a self-hosted GitLab instance instead",
+        value_name = "url"
+    )]
+    pub server: url::Url,
+
+    #[arg(
+        long,
+        help = "Use a private token to access the project.",
+        value_name = "token"
+    )]
+    pub private_token: Option<String>,
+
+    /// Unpack the downloaded archive
+    #[arg(long)]
+    pub unpack: bool,
+
+    /// Use the release with this tag instead of the latest
+    #[arg(long, value_name = "tag")]
+    pub at: Option<String>,
+}
+
+impl GitLabReleaseAssetAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let project = self.project.trim_matches('/').to_owned();
+        let name = project.rsplit('/').next().map(ToOwned::to_owned);
+        let pin = asset::ReleaseAssetPin {
+            repository: git::Repository::gitlab(
+                project,
+                Some(self.server.clone()),
+                self.private_token.clone(),
+            ),
+            asset: self.asset.clone(),
+            regex: self.regex,
+            pre_releases: false,
+            unpack: self.unpack,
+        };
+        let version = self.at.as_ref().map(|at| GenericVersion {
+            version: at.clone(),
+        });
+        Ok((name, (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ForgejoReleaseAssetAddOpts {
+    pub server: String,
+    pub owner: String,
+    pub repository: String,
+
+    /// Glob pattern for the asset's file name, e.g. `*-x86_64-linux.tar.gz`.
+    /// It must match exactly one asset of the release.
+    pub asset: String,
+
+    /// Match the asset's file name against a regular expression instead of a glob pattern
+    #[arg(long)]
+    pub regex: bool,
+
+    /// Also track pre-releases
+    #[arg(long)]
+    pub pre_releases: bool,
+
+    /// Unpack the downloaded archive
+    #[arg(long)]
+    pub unpack: bool,
+
+    /// Use the release with this tag instead of the latest
+    #[arg(long, value_name = "tag")]
+    pub at: Option<String>,
+}
+
+impl ForgejoReleaseAssetAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let server_url = Url::parse(&self.server).or_else(|err| match err {
+            ParseError::RelativeUrlWithoutBase => {
+                Url::parse(&("https://".to_string() + self.server.as_str()))
+            },
+            _ => Err(err),
+        })?;
+        let pin = asset::ReleaseAssetPin {
+            repository: git::Repository::forgejo(server_url, &self.owner, &self.repository),
+            asset: self.asset.clone(),
+            regex: self.regex,
+            pre_releases: self.pre_releases,
+            unpack: self.unpack,
+        };
+        let version = self.at.as_ref().map(|at| GenericVersion {
+            version: at.clone(),
+        });
+        Ok((Some(self.repository.clone()), (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct GitLabPackageAddOpts {
+    /// The project path, e.g. `group/project`
+    pub project: String,
+
+    /// Name of the package in the generic package registry
+    pub package: String,
+
+    /// Glob pattern for the file name within the package, e.g. `*-linux-amd64.tar.gz`.
+    /// It must match exactly one file of the package version.
+    pub file: String,
+
+    #[arg(
+        long,
+        default_value = "https://gitlab.com/",
+        help = "Use a self-hosted GitLab instance instead",
+        value_name = "url"
+    )]
+    pub server: url::Url,
+
+    #[arg(
+        long,
+        help = "Use a private token to access the project.",
+        value_name = "token"
+    )]
+    pub private_token: Option<String>,
+
+    /// Unpack the downloaded archive
+    #[arg(long)]
+    pub unpack: bool,
+
+    /// Use this version of the package instead of the latest
+    #[arg(long, value_name = "version")]
+    pub at: Option<String>,
+}
+
+impl GitLabPackageAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = asset::GitLabPackagePin {
+            server: self.server.clone(),
+            project: self.project.trim_matches('/').to_owned(),
+            private_token: self.private_token.clone(),
+            package: self.package.clone(),
+            file: self.file.clone(),
+            unpack: self.unpack,
+        };
+        let version = self.at.as_ref().map(|at| GenericVersion {
+            version: at.clone(),
+        });
+        Ok((Some(self.package.clone()), (pin, version).into()))
+    }
+}
+
 #[derive(Debug, Parser)]
 pub struct ForgejoAddOpts {
     pub server: String,
@@ -180,6 +489,103 @@ impl ForgejoAddOpts {
     }
 }
 
+#[derive(Debug, Parser)]
+pub struct SourceHutAddOpts {
+    /// The owner, with or without the leading `~`
+    pub owner: String,
+    pub repository: String,
+
+    #[arg(
+        long,
+        help = "Use a self-hosted SourceHut instance instead of git.sr.ht",
+        value_name = "url"
+    )]
+    pub server: Option<String>,
+
+    #[command(flatten)]
+    pub more: GenericGitAddOpts,
+}
+
+impl SourceHutAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let server_url = self
+            .server
+            .as_deref()
+            .map(|server| {
+                Url::parse(server).or_else(|err| match err {
+                    ParseError::RelativeUrlWithoutBase => {
+                        Url::parse(&("https://".to_string() + server))
+                    },
+                    _ => Err(err),
+                })
+            })
+            .transpose()?;
+        let repository = git::Repository::sourcehut(server_url, &self.owner, &self.repository);
+
+        Ok((Some(self.repository.clone()), self.more.add(repository)?))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct BitbucketAddOpts {
+    /// The workspace on Bitbucket Cloud, or the project key on Bitbucket Server
+    pub owner: String,
+    pub repository: String,
+
+    #[arg(
+        long,
+        help = "Use a self-hosted Bitbucket Server/Data Center instance instead of bitbucket.org",
+        value_name = "url"
+    )]
+    pub server: Option<url::Url>,
+
+    #[command(flatten)]
+    pub more: GenericGitAddOpts,
+}
+
+impl BitbucketAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let repository =
+            git::Repository::bitbucket(self.server.clone(), &self.owner, &self.repository);
+
+        Ok((Some(self.repository.clone()), self.more.add(repository)?))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct RadicleAddOpts {
+    /// The repository ID, with or without the `rad:` prefix
+    pub rid: String,
+
+    #[arg(
+        long,
+        help = "Use this seed node instead of seed.radicle.garden",
+        value_name = "url"
+    )]
+    pub seed: Option<url::Url>,
+
+    #[command(flatten)]
+    pub more: GenericGitAddOpts,
+}
+
+impl RadicleAddOpts {
+    pub async fn add(&self) -> Result<(Option<String>, Pin)> {
+        let repository = git::Repository::radicle(self.seed.clone(), &self.rid);
+        /* RIDs make for bad names, so use the project's name instead */
+        let git::Repository::Radicle { seed, rid } = &repository else {
+            unreachable!()
+        };
+        let project = forge::radicle::Radicle {
+            seed: seed.clone(),
+            rid: rid.clone(),
+        }
+        .project()
+        .await?;
+
+        Ok((Some(project.data.name), self.more.add(repository)?))
+    }
+}
+
 #[derive(Debug, Parser)]
 pub struct GitLabAddOpts {
     /// Usually just `"owner" "repository"`, but GitLab allows arbitrary folder-like structures.
@@ -275,6 +681,20 @@ pub struct PyPiAddOpts {
     /// restrict updates to 1.X versions. Conflicts with the --branch option.
     #[arg(long = "upper-bound", value_name = "version", conflicts_with = "at")]
     pub version_upper_bound: Option<String>,
+
+    /// Pin the wheel with these compatibility tags instead of the source distribution,
+    /// e.g. `cp311-manylinux_x86_64` or `py3-none-any`
+    #[arg(long, value_name = "tags")]
+    pub wheel: Option<String>,
+
+    /// Only consider the releases matching this glob pattern, like `3.*`. May be repeated
+    #[arg(long = "only-version", value_name = "GLOB", conflicts_with = "at")]
+    pub only_versions: Vec<String>,
+
+    /// Skip the releases matching this glob pattern, like a broken `3.2.1` or all `*.0`. May be
+    /// repeated
+    #[arg(long = "exclude-version", value_name = "GLOB", conflicts_with = "at")]
+    pub exclude_versions: Vec<String>,
 }
 
 impl PyPiAddOpts {
@@ -283,6 +703,9 @@ impl PyPiAddOpts {
             let pin = pypi::Pin {
                 name: self.package_name.clone(),
                 version_upper_bound: self.version_upper_bound.clone(),
+                wheel: self.wheel.clone(),
+                only_versions: self.only_versions.clone(),
+                exclude_versions: self.exclude_versions.clone(),
             };
             let version = self.at.as_ref().map(|at| GenericVersion {
                 version: at.clone(),
@@ -292,45 +715,735 @@ impl PyPiAddOpts {
     }
 }
 
+#[derive(Debug, Parser)]
+pub struct GoModuleAddOpts {
+    /// The module path, e.g. `golang.org/x/tools`
+    pub module: String,
+
+    /// Use a specific version instead of the latest.
+    #[arg(long, value_name = "version")]
+    pub at: Option<String>,
+
+    /// Bound the version resolution. For example, setting this to "v2" will
+    /// restrict updates to v1.X versions.
+    #[arg(long = "upper-bound", value_name = "version", conflicts_with = "at")]
+    pub version_upper_bound: Option<String>,
+}
+
+impl GoModuleAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = gomod::GoModPin {
+            module: self.module.clone(),
+            version_upper_bound: self.version_upper_bound.clone(),
+        };
+        let version = self.at.as_ref().map(|at| GenericVersion {
+            version: at.clone(),
+        });
+        /* The last segment may be a major version suffix like `v2` */
+        let name = self
+            .module
+            .rsplit('/')
+            .find(|segment| {
+                !(segment.starts_with('v') && segment[1..].chars().all(|c| c.is_ascii_digit()))
+            })
+            .map(ToOwned::to_owned);
+        Ok((name, (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct FlakeAddOpts {
+    /// The flake reference, e.g. `github:owner/repo/branch` or `git+https://example.org/repo.git?ref=main`
+    pub flake: String,
+}
+
+impl FlakeAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = flake::FlakeRefPin {
+            flake: self.flake.clone(),
+        };
+        Ok((pin.guess_name(), pin.into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct SvnAddOpts {
+    /// The URL to track within the repository, e.g. `https://svn.example.org/repos/tool/trunk`
+    pub url: Url,
+}
+
+impl SvnAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = svn::SvnPin {
+            url: self.url.clone(),
+        };
+        Ok((pin.guess_name(), pin.into()))
+    }
+}
+
 #[derive(Debug, Parser)]
 pub struct TarballAddOpts {
     /// Tarball URL
     pub url: Url,
+
+    /// Strip this many leading directories, like `tar --strip-components`.
+    /// The archive's root directory is always stripped, each further one must be the only entry.
+    #[arg(long, value_name = "n", value_parser = clap::value_parser!(u32).range(1..))]
+    pub strip_components: Option<u32>,
+
+    /// Only use this path within the archive, after stripping
+    #[arg(long, value_name = "path")]
+    pub subpath: Option<String>,
 }
 
 impl TarballAddOpts {
     pub fn add(&self) -> Result<(Option<String>, Pin)> {
         let url = self.url.clone();
-        Ok((None, tarball::TarballPin { url }.into()))
+        let pin = tarball::TarballPin {
+            url,
+            strip_components: self.strip_components,
+            subpath: self
+                .subpath
+                .as_ref()
+                .map(|subpath| subpath.trim_matches('/').to_owned()),
+        };
+        Ok((None, pin.into()))
     }
 }
 
-#[derive(Debug, Subcommand)]
-pub enum AddCommands {
+#[derive(Debug, Parser)]
+pub struct UrlAddOpts {
+    /// Any URL, pointing to a tarball, a single file or a git repository
+    pub url: Url,
+
+    /// Skip the detection and add the URL as the given kind of pin
+    #[arg(long = "as", value_name = "tarball|file|git")]
+    pub kind: Option<file::Content>,
+
+    /// Track a branch instead of a release, if the URL is a git repository
+    #[arg(short, long)]
+    pub branch: Option<String>,
+}
+
+impl UrlAddOpts {
+    /// Ask which kind of pin to create, if the URL could be several
+    fn ask(&self, candidates: &[file::Content]) -> Result<file::Content> {
+        let choices = candidates
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut prompt = prompt::Prompt::terminal().with_context(|| {
+            format!(
+                "Couldn't tell what {} is, it may be any of: {choices}. Use `--as` to choose one",
+                self.url
+            )
+        })?;
+        prompt.ask_parsed(
+            &format!("{} may be any of: {choices}. Add it as:", self.url),
+            None,
+        )
+    }
+
+    pub async fn add(&self) -> Result<(Option<String>, Pin)> {
+        let kind = match self.kind {
+            Some(kind) => kind,
+            None => match &file::probe(&self.url).await?[..] {
+                [kind] => *kind,
+                candidates => self.ask(candidates)?,
+            },
+        };
+        log::info!("Adding {} as {kind}", self.url);
+
+        let name = self
+            .url
+            .path_segments()
+            .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+            .map(ToOwned::to_owned);
+        Ok(match kind {
+            file::Content::Tarball => (
+                name.map(|name| {
+                    let end = [".tar", ".tgz", ".txz", ".tbz", ".tzst", ".zip"]
+                        .iter()
+                        .find_map(|suffix| name.find(suffix));
+                    name[..end.unwrap_or(name.len())].to_owned()
+                }),
+                tarball::TarballPin::new(self.url.clone()).into(),
+            ),
+            file::Content::File => (
+                name,
+                file::FilePin {
+                    url: self.url.clone(),
+                }
+                .into(),
+            ),
+            file::Content::Git => {
+                let opts = GitAddOpts {
+                    url: self.url.to_string(),
+                    more: GenericGitAddOpts {
+                        branch: self.branch.clone(),
+                        ..Default::default()
+                    },
+                };
+                opts.add()?
+            },
+        })
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct VsCodeAddOpts {
+    /// Extension identifier, of the form `publisher.name`
+    pub id: String,
+
+    /// Use the Visual Studio Marketplace instead of Open VSX
+    #[arg(long)]
+    pub marketplace: bool,
+
+    /// Use the package for a specific platform, e.g. `linux-x64`.
+    /// Required for extensions which only publish platform-specific packages.
+    #[arg(long, value_name = "platform")]
+    pub target_platform: Option<String>,
+
+    /// Use a specific release instead of the latest.
+    #[arg(long, value_name = "version")]
+    pub at: Option<String>,
+}
+
+impl VsCodeAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let registry = if self.marketplace {
+            vscode::Registry::Marketplace
+        } else {
+            vscode::Registry::OpenVsx
+        };
+        let pin = vscode::ExtensionPin::new(&self.id, registry, self.target_platform.clone())?;
+        let version = self.at.as_ref().map(|at| GenericVersion {
+            version: at.clone(),
+        });
+        Ok((Some(pin.name.clone()), (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct FirefoxAddOpts {
+    /// The add-on's slug (as in its addons.mozilla.org URL) or GUID
+    pub addon: String,
+
+    /// Use a specific release instead of the latest.
+    #[arg(long, value_name = "version")]
+    pub at: Option<String>,
+}
+
+impl FirefoxAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = browser::FirefoxAddonPin {
+            addon: self.addon.clone(),
+        };
+        let version = self.at.as_ref().map(|at| GenericVersion {
+            version: at.clone(),
+        });
+        Ok((Some(self.addon.clone()), (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ChromeAddOpts {
+    /// The extension ID, as in its Chrome Web Store URL.
+    /// Only the latest version can be pinned.
+    pub id: String,
+}
+
+impl ChromeAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        Ok((None, browser::ChromeExtensionPin::new(&self.id)?.into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ManifestAddOpts {
+    /// URL of the JSON manifest, e.g. a `latest.json` or an update manifest
+    pub manifest: Url,
+
+    /// JSON pointer to the version in the manifest, e.g. `/channels/{channel}/version`
+    #[arg(long, value_name = "pointer")]
+    pub version_pointer: String,
+
+    /// JSON pointer to the download URL in the manifest, e.g. `/channels/{channel}/url`
+    #[arg(long, value_name = "pointer")]
+    pub url_pointer: String,
+
+    /// Name of the channel to follow, substituted for `{channel}` in the pointers
+    #[arg(long)]
+    pub channel: Option<String>,
+
+    /// Unpack the downloaded archive
+    #[arg(long)]
+    pub unpack: bool,
+}
+
+impl ManifestAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        Ok((
+            None,
+            manifest::ManifestPin {
+                manifest: self.manifest.clone(),
+                version_pointer: self.version_pointer.clone(),
+                url_pointer: self.url_pointer.clone(),
+                channel: self.channel.clone(),
+                unpack: self.unpack,
+            }
+            .into(),
+        ))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ArtifactAddOpts {
+    /// Base URL of the repository manager, e.g. `https://example.org/artifactory/`
+    pub server: Url,
+
+    /// Name of the (generic or raw) repository
+    pub repository: String,
+
+    /// Path of the artifacts within the repository, with a `*` for the version in the file name,
+    /// e.g. `vendor/tool-*.tar.gz`
+    pub path: String,
+
+    /// The server is a Nexus instead of an Artifactory
+    #[arg(long)]
+    pub nexus: bool,
+
+    /// Unpack the downloaded archive
+    #[arg(long)]
+    pub unpack: bool,
+
+    /// Use a specific version instead of the latest.
+    #[arg(long, value_name = "version")]
+    pub at: Option<String>,
+}
+
+impl ArtifactAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let manager = if self.nexus {
+            artifact::RepositoryManager::Nexus
+        } else {
+            artifact::RepositoryManager::Artifactory
+        };
+        let pin = artifact::ArtifactPin::new(
+            self.server.clone(),
+            manager,
+            self.repository.clone(),
+            &self.path,
+            self.unpack,
+        )?;
+        /* `tool-*.tar.gz` → `tool` */
+        let name = pin
+            .pattern
+            .split('*')
+            .next()
+            .map(|prefix| prefix.trim_end_matches(['-', '_', '.']))
+            .filter(|prefix| !prefix.is_empty())
+            .map(Into::into);
+        let version = self.at.as_ref().map(|at| GenericVersion {
+            version: at.clone(),
+        });
+        Ok((name, (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct MavenAddOpts {
+    /// The artifact's coordinates, as `group:artifact`, e.g. `com.google.guava:guava`
+    pub coordinates: String,
+
+    /// Use an internal repository (e.g. on Nexus or Artifactory) instead of Maven Central
+    #[arg(long, value_name = "url", default_value = maven::MAVEN_CENTRAL)]
+    pub repository: Url,
+
+    /// The kind of file to download, e.g. `aar`
+    #[arg(long, default_value = "jar")]
+    pub packaging: String,
+
+    /// E.g. `sources`
+    #[arg(long)]
+    pub classifier: Option<String>,
+
+    /// Use a specific version instead of the latest release.
+    #[arg(long, value_name = "version")]
+    pub at: Option<String>,
+}
+
+impl MavenAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let (group_id, artifact_id) = self
+            .coordinates
+            .split_once(':')
+            .filter(|(group_id, artifact_id)| !group_id.is_empty() && !artifact_id.is_empty())
+            .with_context(|| {
+                format!("'{}' is not of the form `group:artifact`", self.coordinates)
+            })?;
+        let pin = maven::MavenPin {
+            repository: self.repository.clone(),
+            group_id: group_id.into(),
+            artifact_id: artifact_id.into(),
+            packaging: self.packaging.clone(),
+            classifier: self.classifier.clone(),
+        };
+        let version = self.at.as_ref().map(|at| GenericVersion {
+            version: at.clone(),
+        });
+        Ok((Some(artifact_id.into()), (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ContainerAddOpts {
+    /// The image, e.g. `nginx`, `ghcr.io/owner/image` or `nginx:1.27`
+    pub image: String,
+
+    /// The platform to pull, for multi-platform images
+    #[arg(long, default_value = "linux")]
+    pub os: String,
+
+    /// The architecture to pull, for multi-platform images
+    #[arg(long, default_value = "amd64")]
+    pub arch: String,
+
+    /// Use a specific digest instead of the one the tag currently points to.
+    #[arg(long, value_name = "digest")]
+    pub at: Option<String>,
+}
+
+impl ContainerAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        /* The tag is after the last colon, unless that is part of the registry's port */
+        let (image_name, image_tag) = match self.image.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, tag),
+            _ => (self.image.as_str(), "latest"),
+        };
+        let pin = container::ContainerPin {
+            image_name: image_name.into(),
+            image_tag: image_tag.into(),
+            os: self.os.clone(),
+            arch: self.arch.clone(),
+        };
+        let version = self.at.as_ref().map(|at| container::ContainerVersion {
+            image_digest: at.clone(),
+        });
+        let name = image_name.rsplit('/').next().map(Into::into);
+        Ok((name, (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct BucketAddOpts {
+    /// The bucket and prefix, e.g. `s3://bucket/models/` or `gs://bucket/models/`
+    pub location: Url,
+
+    /// Pattern for the rest of the key, where `*` stands for the version, e.g. `model-*.bin`.
+    /// Without it, the most recently modified object is used.
+    #[arg(long)]
+    pub pattern: Option<String>,
+
+    /// The region of the S3 bucket
+    #[arg(long, env = "AWS_REGION")]
+    pub region: Option<String>,
+
+    /// Use S3-compatible storage instead of AWS, e.g. `https://minio.example.org`
+    #[arg(long, value_name = "url")]
+    pub endpoint: Option<Url>,
+
+    /// Unpack the downloaded archive
+    #[arg(long)]
+    pub unpack: bool,
+
+    /// Use a specific key instead of the latest.
+    #[arg(long, value_name = "key")]
+    pub at: Option<String>,
+}
+
+impl BucketAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let provider = match self.location.scheme() {
+            "s3" => bucket::Provider::S3,
+            "gs" => bucket::Provider::Gcs,
+            scheme => anyhow::bail!("Unsupported scheme '{scheme}', use s3:// or gs://"),
+        };
+        if let Some(pattern) = &self.pattern {
+            anyhow::ensure!(
+                pattern.matches('*').count() == 1,
+                "The pattern '{pattern}' must contain exactly one `*` for the version"
+            );
+        }
+        let bucket = self
+            .location
+            .host_str()
+            .context("The location has no bucket")?
+            .to_owned();
+        let prefix = self.location.path().trim_start_matches('/').to_owned();
+        /* `models/model-*.bin` → `model`, `models/` → `models` */
+        let name = self
+            .pattern
+            .as_deref()
+            .and_then(|pattern| pattern.split('*').next())
+            .map(|prefix| prefix.trim_end_matches(['-', '_', '.']))
+            .filter(|name| !name.is_empty())
+            .or_else(|| prefix.split('/').rfind(|segment| !segment.is_empty()))
+            .unwrap_or(&bucket)
+            .to_owned();
+        let pin = bucket::BucketPin {
+            provider,
+            bucket,
+            region: self.region.clone(),
+            endpoint: self.endpoint.clone(),
+            prefix,
+            pattern: self.pattern.clone(),
+            unpack: self.unpack,
+        };
+        let version = self.at.as_ref().map(|at| bucket::BucketObject {
+            key: at.clone(),
+            etag: None,
+        });
+        Ok((Some(name), (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct IpfsAddOpts {
+    /// The IPNS name, optionally with a path within it, e.g. `k51q…/releases/tool.tar.gz` or `/ipns/example.org/tool.tar.gz`
+    pub name: String,
+
+    /// The HTTP gateway to resolve the name and download the content with
+    #[arg(long, value_name = "url", default_value = "https://ipfs.io")]
+    pub gateway: Url,
+
+    /// Unpack the downloaded archive
+    #[arg(long)]
+    pub unpack: bool,
+
+    /// Use a specific CID instead of the one the name currently points to.
+    #[arg(long, value_name = "cid")]
+    pub at: Option<String>,
+}
+
+impl IpfsAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = ipfs::IpfsPin::new(&self.name, self.gateway.clone(), self.unpack)?;
+        let version = self
+            .at
+            .as_ref()
+            .map(|at| ipfs::IpfsVersion { cid: at.clone() });
+        Ok((pin.guess_name(), (pin, version).into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct GitHubArtifactAddOpts {
+    pub owner: String,
+    pub repository: String,
+
+    /// The workflow's file name, e.g. `nightly.yml`
+    pub workflow: String,
+
+    /// Name of the artifact
+    pub artifact: String,
+
+    /// Track the runs on this branch
+    #[arg(short, long)]
+    pub branch: String,
+
+    /// Unpack the artifact's zip file
+    #[arg(long)]
+    pub unpack: bool,
+}
+
+impl GitHubArtifactAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = ci::GitHubArtifactPin {
+            owner: self.owner.clone(),
+            repo: self.repository.clone(),
+            workflow: self.workflow.clone(),
+            branch: self.branch.clone(),
+            artifact: self.artifact.clone(),
+            unpack: self.unpack,
+        };
+        Ok((Some(self.artifact.clone()), pin.into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct GitLabArtifactAddOpts {
+    /// The project path, e.g. `group/project`
+    pub project: String,
+
+    /// Name of the job whose artifacts to track
+    pub job: String,
+
+    /// Track the pipelines on this branch
+    #[arg(short, long)]
+    pub branch: String,
+
+    #[arg(
+        long,
+        default_value = "https://gitlab.com/",
+        help = "Use a self-hosted GitLab instance instead",
+        value_name = "url"
+    )]
+    pub server: url::Url,
+
+    /// Unpack the artifacts' zip file
+    #[arg(long)]
+    pub unpack: bool,
+}
+
+impl GitLabArtifactAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = ci::GitLabArtifactPin {
+            server: self.server.clone(),
+            project: self.project.trim_matches('/').to_owned(),
+            branch: self.branch.clone(),
+            job: self.job.clone(),
+            unpack: self.unpack,
+        };
+        Ok((Some(self.job.clone()), pin.into()))
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct BuildkiteArtifactAddOpts {
+    /// The organization's slug
+    pub organization: String,
+
+    /// The pipeline's slug
+    pub pipeline: String,
+
+    /// The path the artifact was uploaded with, e.g. `dist/tool.tar.gz`
+    pub artifact: String,
+
+    /// Track the builds on this branch
+    #[arg(short, long)]
+    pub branch: String,
+
+    /// Unpack the downloaded archive
+    #[arg(long)]
+    pub unpack: bool,
+}
+
+impl BuildkiteArtifactAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        let pin = ci::BuildkiteArtifactPin {
+            organization: self.organization.clone(),
+            pipeline: self.pipeline.clone(),
+            branch: self.branch.clone(),
+            artifact: self.artifact.clone(),
+            unpack: self.unpack,
+        };
+        Ok((Some(self.pipeline.clone()), pin.into()))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AddCommands {
     /// Track a Nix channel
     #[command(name = "channel")]
     Channel(ChannelAddOpts),
+    /// Track the input of the latest Hydra evaluation in which a job succeeded
+    #[command(name = "hydra")]
+    Hydra(HydraAddOpts),
     /// Track a GitHub repository
     #[command(name = "github")]
     GitHub(GitHubAddOpts),
+    /// Track a file attached to the latest release of a GitHub repository
+    #[command(name = "release-asset")]
+    ReleaseAsset(ReleaseAssetAddOpts),
     /// Track a Forgejo repository
     #[command(name = "forgejo")]
     Forgejo(ForgejoAddOpts),
+    /// Track a file attached to the latest release of a Forgejo or Gitea repository
+    #[command(name = "forgejo-release-asset")]
+    ForgejoReleaseAsset(ForgejoReleaseAssetAddOpts),
+    /// Track a SourceHut repository
+    #[command(name = "sourcehut")]
+    SourceHut(SourceHutAddOpts),
+    /// Track a Bitbucket repository
+    #[command(name = "bitbucket")]
+    Bitbucket(BitbucketAddOpts),
+    /// Track a Radicle repository
+    #[command(name = "radicle")]
+    Radicle(RadicleAddOpts),
     /// Track a GitLab repository
     #[command(name = "gitlab")]
     GitLab(GitLabAddOpts),
+    /// Track a file linked to the latest release of a GitLab project
+    #[command(name = "gitlab-release-asset")]
+    GitLabReleaseAsset(GitLabReleaseAssetAddOpts),
+    /// Track a file in the generic package registry of a GitLab project
+    #[command(name = "gitlab-package")]
+    GitLabPackage(GitLabPackageAddOpts),
     /// Track a git repository
     #[command(name = "git")]
     Git(GitAddOpts),
     /// Track a package on PyPi
     #[command(name = "pypi")]
     PyPi(PyPiAddOpts),
+    /// Track a Go module on proxy.golang.org, or the proxy in GOPROXY
+    #[command(name = "go-module")]
+    GoModule(GoModuleAddOpts),
+    /// Track a flake, as resolved and locked by `nix flake metadata`
+    #[command(name = "flake")]
+    Flake(FlakeAddOpts),
+    /// Track the trunk, a branch or a tag of a Subversion repository
+    ///
+    /// The pin is locked to the revision that last changed that URL. Evaluating it needs Nixpkgs.
+    #[command(name = "svn")]
+    Svn(SvnAddOpts),
     /// Track a tarball
     ///
-    /// This can be either a static URL that never changes its contents or a
-    /// URL which supports flakes "Lockable HTTP Tarball" API.
+    /// This can be either a static URL that never changes its contents, a
+    /// URL which supports flakes "Lockable HTTP Tarball" API, or a "latest"
+    /// URL which redirects to the current release.
     #[command(name = "tarball")]
     Tarball(TarballAddOpts),
+    /// Track a URL, figuring out whether it is a tarball, a single file or a git repository
+    #[command(name = "url")]
+    Url(UrlAddOpts),
+    /// Track a VS Code extension on Open VSX or the Visual Studio Marketplace
+    #[command(name = "vscode")]
+    VsCode(VsCodeAddOpts),
+    /// Track a Firefox add-on on addons.mozilla.org
+    #[command(name = "firefox")]
+    Firefox(FirefoxAddOpts),
+    /// Track an extension in the Chrome Web Store
+    #[command(name = "chrome")]
+    Chrome(ChromeAddOpts),
+    /// Track the release a JSON manifest points to, e.g. the latest one of a channel
+    #[command(name = "manifest")]
+    Manifest(ManifestAddOpts),
+    /// Track an artifact in a generic repository on Artifactory or Nexus
+    #[command(name = "artifact")]
+    Artifact(ArtifactAddOpts),
+    /// Track the latest release of an artifact in a Maven repository
+    #[command(name = "maven")]
+    Maven(MavenAddOpts),
+    /// Track a container image tag on an OCI registry
+    #[command(name = "container")]
+    Container(ContainerAddOpts),
+    /// Track an object in an S3 or Google Cloud Storage bucket
+    #[command(name = "bucket")]
+    Bucket(BucketAddOpts),
+    /// Track content published over IPFS under an IPNS name
+    #[command(name = "ipfs")]
+    Ipfs(IpfsAddOpts),
+    /// Track an artifact of the latest successful GitHub Actions run of a workflow
+    #[command(name = "github-artifact")]
+    GitHubArtifact(GitHubArtifactAddOpts),
+    /// Track the artifacts of a job in the latest successful GitLab CI pipeline
+    #[command(name = "gitlab-artifact")]
+    GitLabArtifact(GitLabArtifactAddOpts),
+    /// Track an artifact of the latest passed Buildkite build
+    #[command(name = "buildkite-artifact")]
+    BuildkiteArtifact(BuildkiteArtifactAddOpts),
 }
 
 #[derive(Debug, Parser)]
@@ -342,6 +1455,20 @@ pub struct AddOpts {
     /// Add the pin as frozen, meaning that it will be ignored by `npins update` by default.
     #[arg(long, global = true)]
     pub frozen: bool,
+    /// Label the pin, e.g. to update it together with others. May be repeated
+    #[arg(long = "label", value_name = "LABEL", global = true)]
+    pub labels: Vec<String>,
+    /// Add the pin as a variant of the pin `--name`, to lock several versions of it. In Nix, it is
+    /// available as `<name>.<variant>`, and it gets updated together with the pin
+    #[arg(long, value_name = "VARIANT", global = true)]
+    pub variant: Option<String>,
+    /// Keep this many of the previously pinned versions when updating, e.g. to fall back to an
+    /// older nightly build. In Nix, they are available as `<name>.previous`, newest first
+    #[arg(long, value_name = "N", default_value_t = 0, global = true)]
+    pub keep: usize,
+    /// Describe what the pin is for. See `npins annotate` to change it later
+    #[arg(long, value_name = "TEXT", global = true)]
+    pub describe: Option<String>,
     /// Don't actually apply the changes
     #[arg(short = 'n', long)]
     pub dry_run: bool,
@@ -350,15 +1477,39 @@ pub struct AddOpts {
 }
 
 impl AddOpts {
-    fn run(&self) -> Result<(String, Pin)> {
+    async fn run(&self) -> Result<(String, Pin)> {
         let (name, mut pin) = match &self.command {
             AddCommands::Channel(c) => c.add()?,
+            AddCommands::Hydra(h) => h.add()?,
             AddCommands::Git(g) => g.add()?,
             AddCommands::GitHub(gh) => gh.add()?,
+            AddCommands::ReleaseAsset(p) => p.add()?,
+            AddCommands::GitLabReleaseAsset(p) => p.add()?,
+            AddCommands::GitLabPackage(p) => p.add()?,
             AddCommands::Forgejo(fg) => fg.add()?,
+            AddCommands::ForgejoReleaseAsset(p) => p.add()?,
+            AddCommands::SourceHut(sh) => sh.add()?,
+            AddCommands::Bitbucket(bb) => bb.add()?,
+            AddCommands::Radicle(rad) => rad.add().await?,
             AddCommands::GitLab(gl) => gl.add()?,
             AddCommands::PyPi(p) => p.add()?,
+            AddCommands::GoModule(g) => g.add()?,
+            AddCommands::Flake(f) => f.add()?,
+            AddCommands::Svn(s) => s.add()?,
             AddCommands::Tarball(p) => p.add()?,
+            AddCommands::Url(p) => p.add().await?,
+            AddCommands::VsCode(p) => p.add()?,
+            AddCommands::Firefox(p) => p.add()?,
+            AddCommands::Chrome(p) => p.add()?,
+            AddCommands::Manifest(p) => p.add()?,
+            AddCommands::Artifact(p) => p.add()?,
+            AddCommands::Maven(p) => p.add()?,
+            AddCommands::Container(p) => p.add()?,
+            AddCommands::Bucket(p) => p.add()?,
+            AddCommands::Ipfs(p) => p.add()?,
+            AddCommands::GitHubArtifact(p) => p.add()?,
+            AddCommands::GitLabArtifact(p) => p.add()?,
+            AddCommands::BuildkiteArtifact(p) => p.add()?,
         };
 
         let name = match (&self.name, name) {
@@ -370,9 +1521,25 @@ impl AddOpts {
                 )
             },
         };
+        let name = match &self.variant {
+            Some(variant) => {
+                anyhow::ensure!(
+                    !name.contains('.') && !name.is_empty() && !variant.is_empty(),
+                    "Variants need a pin name without dots, and a variant name"
+                );
+                pin.metadata_mut().variant = true;
+                format!("{name}.{variant}")
+            },
+            None => name,
+        };
         if self.frozen {
             pin.freeze();
         }
+        pin.metadata_mut()
+            .labels
+            .extend(self.labels.iter().cloned());
+        pin.metadata_mut().keep = self.keep;
+        pin.metadata_mut().description = self.describe.clone();
 
         Ok((name, pin))
     }
@@ -380,58 +1547,603 @@ impl AddOpts {
 
 #[derive(Debug, Parser)]
 pub struct RemoveOpts {
-    pub name: String,
-}
-
-#[derive(Debug, Parser)]
-pub struct UpdateOpts {
-    /// Updates only the specified pins.
-    pub names: Vec<String>,
-    /// Don't update versions, only re-fetch hashes
-    #[arg(short, long, conflicts_with = "full")]
-    pub partial: bool,
-    /// Re-fetch hashes even if the version hasn't changed.
-    /// Useful to make sure the derivations are in the Nix store.
-    #[arg(short, long, conflicts_with = "partial")]
-    pub full: bool,
-    /// Print the diff, but don't write back the changes
-    #[arg(short = 'n', long, global = true)]
-    pub dry_run: bool,
-    /// Allow updating frozen pins, which would otherwise be ignored
-    #[arg(long = "frozen")]
-    pub update_frozen: bool,
-    /// Maximum number of simultaneous downloads
-    #[structopt(default_value = "5", long)]
-    pub max_concurrent_downloads: usize,
-}
-
-#[derive(Debug, Parser)]
-pub struct InitOpts {
-    /// Don't add an initial `nixpkgs` entry
+    #[arg(required_unless_present = "label", conflicts_with = "label")]
+    pub name: Option<String>,
+    /// Remove all pins with this label instead
     #[arg(long)]
-    pub bare: bool,
+    pub label: Option<String>,
 }
 
 #[derive(Debug, Parser)]
-pub struct ImportOpts {
-    #[arg(default_value = "nix/sources.json")]
-    pub path: PathBuf,
-    /// Only import one entry from Niv
-    #[arg(short, long)]
+pub struct RollbackOpts {
+    /// Only roll back this pin, leaving the others as they are
     pub name: Option<String>,
+    /// How many changes to go back
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    pub to: usize,
+    /// List the previous states instead of rolling back
+    #[arg(long, conflicts_with_all = ["name", "to"])]
+    pub list: bool,
 }
 
 #[derive(Debug, Parser)]
-pub struct ImportFlakeOpts {
+pub struct ExportFlakeOpts {
     #[arg(default_value = "flake.lock")]
     pub path: PathBuf,
-    /// Only import one entry from the flake
-    #[arg(short, long)]
-    pub name: Option<String>,
+    /// Print the `inputs` for the flake.nix that go along with the flake.lock instead
+    #[arg(long)]
+    pub inputs: bool,
 }
 
 #[derive(Debug, Parser)]
-pub struct FreezeOpts {
+pub struct MaintenanceOpts {
+    /// How many previous states of the lock file to keep, at most
+    #[arg(long, default_value_t = history::KEEP, value_name = "N")]
+    pub keep: usize,
+    /// Remove previous states of the lock file and ranges older than this many days
+    #[arg(long, value_name = "DAYS")]
+    pub max_age: Option<u64>,
+    /// Only report what would be removed
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ExplainNixOpts {
+    /// Name of the pin
+    pub name: String,
+    /// Show the expression for when Nixpkgs is passed to `default.nix`, which uses its fetchers
+    #[arg(long)]
+    pub pkgs: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ShowOpts {
+    /// Only show the pins with any of these labels. May be repeated
+    #[arg(long = "label", value_name = "LABEL")]
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct EditOpts {
+    /// Name of the pin
+    pub name: String,
+    /// Add a label. May be repeated
+    #[arg(long = "label", value_name = "LABEL")]
+    pub labels: Vec<String>,
+    /// Remove a label. May be repeated
+    #[arg(long = "remove-label", value_name = "LABEL")]
+    pub removed_labels: Vec<String>,
+    /// Also provide the pin under this name in Nix, with a deprecation warning. May be repeated
+    #[arg(long = "alias", value_name = "NAME")]
+    pub aliases: Vec<String>,
+    /// Stop providing the pin under this former name. May be repeated
+    #[arg(long = "remove-alias", value_name = "NAME")]
+    pub removed_aliases: Vec<String>,
+    /// Also provide the pin under this name in Nix, without any warning. May be repeated
+    #[arg(long = "other-name", value_name = "NAME")]
+    pub other_names: Vec<String>,
+    /// Stop providing the pin under this other name. May be repeated
+    #[arg(long = "remove-other-name", value_name = "NAME")]
+    pub removed_other_names: Vec<String>,
+    /// Keep this many of the previously pinned versions when updating, 0 to drop them
+    #[arg(long, value_name = "N")]
+    pub keep: Option<usize>,
+    /// Track this branch instead, for git pins
+    #[arg(short, long)]
+    pub branch: Option<String>,
+    /// Also fetch submodules, or stop doing so, for git and release pins
+    #[arg(long, value_name = "true|false")]
+    pub submodules: Option<bool>,
+    /// Also track pre-releases, or stop doing so
+    #[arg(long, value_name = "true|false")]
+    pub pre_releases: Option<bool>,
+    /// Restrict the releases to versions below this one, an empty one removes the bound
+    #[arg(long = "upper-bound", value_name = "version")]
+    pub version_upper_bound: Option<String>,
+    /// Restrict the releases to versions matching this requirement, an empty one removes it
+    #[arg(long, value_name = "requirement")]
+    pub version_requirement: Option<String>,
+    /// Only consider the releases with this prefix, an empty one removes the prefix
+    #[arg(long, value_name = "prefix")]
+    pub release_prefix: Option<String>,
+    /// Use the release the forge marks as "latest", or the highest version among the tags
+    #[arg(long, value_name = "true|false")]
+    pub forge_latest: Option<bool>,
+    /// How to compare the tags as versions
+    #[arg(long, value_name = "lenient|semver|calver|numeric|lexicographic")]
+    pub version_scheme: Option<latest::Comparator>,
+    /// Set the epoch of a release pin, to allow going back in versions once
+    #[arg(long)]
+    pub epoch: Option<u64>,
+    /// Track this tag that upstream moves to new commits instead, an empty one goes back to
+    /// tracking the latest release
+    #[arg(long, value_name = "TAG")]
+    pub moving_tag: Option<String>,
+    /// Only consider the tags on this branch, an empty one considers all of them again
+    #[arg(long, value_name = "BRANCH")]
+    pub reachable_from: Option<String>,
+    /// Only consider the releases matching this glob pattern, for release and PyPI pins. May be
+    /// repeated
+    #[arg(long = "only-version", value_name = "GLOB")]
+    pub only_versions: Vec<String>,
+    /// Stop restricting the releases to this glob pattern. May be repeated
+    #[arg(long = "remove-only-version", value_name = "GLOB")]
+    pub removed_only_versions: Vec<String>,
+    /// Skip the releases matching this glob pattern, for release and PyPI pins. May be repeated
+    #[arg(long = "exclude-version", value_name = "GLOB")]
+    pub exclude_versions: Vec<String>,
+    /// Stop skipping the releases matching this glob pattern. May be repeated
+    #[arg(long = "remove-exclude-version", value_name = "GLOB")]
+    pub removed_exclude_versions: Vec<String>,
+}
+
+/// What changing the configuration of a pin means for its version, see [`EditOpts::configure`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Revalidate {
+    /// The pinned version is still valid
+    Nothing,
+    /// The version is, but the hashes have to be fetched again
+    Refetch,
+    /// The pinned version is no longer valid, and was removed
+    Update,
+}
+
+impl EditOpts {
+    fn changes_release_options(&self) -> bool {
+        self.changes_tag_options() || self.changes_version_lists()
+    }
+
+    /// Whether options change that only release pins of git repositories have
+    fn changes_tag_options(&self) -> bool {
+        self.pre_releases.is_some()
+            || self.version_upper_bound.is_some()
+            || self.version_requirement.is_some()
+            || self.release_prefix.is_some()
+            || self.forge_latest.is_some()
+            || self.version_scheme.is_some()
+            || self.epoch.is_some()
+            || self.moving_tag.is_some()
+            || self.reachable_from.is_some()
+    }
+
+    /// Whether the `only_versions` or `exclude_versions` change, which PyPI pins have too
+    fn changes_version_lists(&self) -> bool {
+        !(self.only_versions.is_empty()
+            && self.removed_only_versions.is_empty()
+            && self.exclude_versions.is_empty()
+            && self.removed_exclude_versions.is_empty())
+    }
+
+    /// Apply the changes to the `only_versions` and `exclude_versions` of a pin
+    fn edit_version_lists(
+        &self,
+        only_versions: &mut Vec<String>,
+        exclude_versions: &mut Vec<String>,
+    ) -> bool {
+        fn edit(list: &mut Vec<String>, added: &[String], removed: &[String]) -> bool {
+            let before = list.clone();
+            list.retain(|pattern| !removed.contains(pattern));
+            for pattern in added {
+                if !list.contains(pattern) {
+                    list.push(pattern.clone());
+                }
+            }
+            *list != before
+        }
+        let only = edit(
+            only_versions,
+            &self.only_versions,
+            &self.removed_only_versions,
+        );
+        let exclude = edit(
+            exclude_versions,
+            &self.exclude_versions,
+            &self.removed_exclude_versions,
+        );
+        only || exclude
+    }
+
+    /// Change how the pin tracks upstream, keeping its version if the pin could still be at it
+    fn configure(&self, pin: &mut Pin) -> Result<Revalidate> {
+        fn set<T: PartialEq>(field: &mut T, value: Option<T>) -> bool {
+            match value {
+                Some(value) if *field != value => {
+                    *field = value;
+                    true
+                },
+                _ => false,
+            }
+        }
+        let non_empty = |value: &Option<String>| {
+            value
+                .as_ref()
+                .map(|value| Some(value.clone()).filter(|value| !value.is_empty()))
+        };
+
+        match pin {
+            Pin::Git {
+                input,
+                version,
+                hashes,
+                ..
+            } => {
+                anyhow::ensure!(
+                    !self.changes_release_options(),
+                    "'{}' tracks a branch, the release options don't apply to it",
+                    self.name
+                );
+                if set(&mut input.branch, self.branch.clone()) {
+                    *version = None;
+                    *hashes = None;
+                    Ok(Revalidate::Update)
+                } else if set(&mut input.submodules, self.submodules) {
+                    Ok(Revalidate::Refetch)
+                } else {
+                    Ok(Revalidate::Nothing)
+                }
+            },
+            Pin::GitRelease {
+                input,
+                version,
+                hashes,
+                ..
+            } => {
+                anyhow::ensure!(
+                    self.branch.is_none(),
+                    "'{}' tracks releases. Remove it and add it again with `--branch` to track a branch instead",
+                    self.name
+                );
+                let mut changed = set(&mut input.pre_releases, self.pre_releases);
+                changed |= set(
+                    &mut input.version_upper_bound,
+                    non_empty(&self.version_upper_bound),
+                );
+                changed |= set(
+                    &mut input.version_requirement,
+                    non_empty(&self.version_requirement),
+                );
+                changed |= set(&mut input.release_prefix, non_empty(&self.release_prefix));
+                changed |= set(&mut input.forge_latest, self.forge_latest);
+                changed |= set(&mut input.version_scheme.comparator, self.version_scheme);
+                changed |= set(&mut input.moving_tag, non_empty(&self.moving_tag));
+                /* Only allows going back in versions on the next update */
+                set(&mut input.epoch, self.epoch);
+                changed |=
+                    self.edit_version_lists(&mut input.only_versions, &mut input.exclude_versions);
+                /* Whether the version is on the new branch can't be told offline */
+                let moved = set(&mut input.reachable_from, non_empty(&self.reachable_from));
+
+                let admitted = match version {
+                    Some(version) => !moved && input.admits(version)?,
+                    None => false,
+                };
+                let changed = changed || moved;
+                if changed && !admitted {
+                    /* Also skips the monotonicity check, going back in versions is intended */
+                    *version = None;
+                    *hashes = None;
+                    Ok(Revalidate::Update)
+                } else if set(&mut input.submodules, self.submodules) {
+                    Ok(Revalidate::Refetch)
+                } else {
+                    Ok(Revalidate::Nothing)
+                }
+            },
+            Pin::PyPi {
+                input,
+                version,
+                hashes,
+                ..
+            } => {
+                anyhow::ensure!(
+                    self.branch.is_none()
+                        && self.submodules.is_none()
+                        && !self.changes_tag_options(),
+                    "'{}' is a PyPI pin, only the versions to consider and its labels, aliases and the versions to keep can be changed",
+                    self.name
+                );
+                let changed =
+                    self.edit_version_lists(&mut input.only_versions, &mut input.exclude_versions);
+                match version {
+                    Some(pinned) if changed && !input.admits(&pinned.version) => {
+                        *version = None;
+                        *hashes = None;
+                        Ok(Revalidate::Update)
+                    },
+                    _ => Ok(Revalidate::Nothing),
+                }
+            },
+            _ => {
+                anyhow::ensure!(
+                    self.branch.is_none()
+                        && self.submodules.is_none()
+                        && !self.changes_release_options(),
+                    "'{}' is not a git pin, only its labels, aliases and the versions to keep can be changed",
+                    self.name
+                );
+                Ok(Revalidate::Nothing)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct AnnotateOpts {
+    /// Name of the pin
+    pub name: String,
+    /// What the pin is for. An empty description removes it
+    #[arg(long = "describe", value_name = "TEXT")]
+    pub description: Option<String>,
+    /// Set a metadata entry, which `default.nix` passes through. May be repeated
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_metadata)]
+    pub set: Vec<(String, String)>,
+    /// Remove a metadata entry. May be repeated
+    #[arg(long = "unset", value_name = "KEY")]
+    pub unset: Vec<String>,
+}
+
+fn parse_metadata(entry: &str) -> Result<(String, String)> {
+    let (key, value) = entry
+        .split_once('=')
+        .context("must be of the form <key>=<value>")?;
+    anyhow::ensure!(!key.is_empty(), "the key must not be empty");
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+#[derive(Debug, Parser)]
+pub struct DiffOpts {
+    /// The lock file to compare from. Defaults to the current lock file as of `--rev`
+    pub old: Option<PathBuf>,
+    /// The lock file to compare to. Defaults to the current lock file
+    pub new: Option<PathBuf>,
+    /// The git revision to take the old lock file from
+    #[arg(long, default_value = "HEAD", conflicts_with = "old")]
+    pub rev: String,
+    /// Print the differences as JSON, same as `--output json`
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct MigrateConfigOpts {
+    /// Print the resulting npins.toml instead of writing it
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct CleanTempOpts {
+    /// Only list the directories that would be removed
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct RenameOpts {
+    /// Current name of the pin
+    pub old: String,
+    /// New name of the pin
+    pub new: String,
+    /// Keep providing the pin under its old name in Nix, with a deprecation warning, so that code
+    /// using it can be migrated gradually
+    #[arg(long)]
+    pub keep_alias: bool,
+    /// Keep providing the pin under its old name in Nix, without any warning, e.g. while both
+    /// names are in use
+    #[arg(long, conflicts_with = "keep_alias")]
+    pub keep_name: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct PinOpts {
+    /// Name of the pin
+    pub name: String,
+    /// The full hash of the commit
+    pub revision: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct UpdateOpts {
+    /// Updates only the specified pins. Glob patterns like `'nixpkgs*'` select all matching pins
+    pub names: Vec<String>,
+    /// Also update the pins whose name matches this regular expression. May be repeated
+    #[arg(long = "regex", value_name = "REGEX")]
+    pub regexes: Vec<String>,
+    /// Also update the pins with this label. May be repeated
+    #[arg(long = "label", value_name = "LABEL")]
+    pub labels: Vec<String>,
+    /// Don't update versions, only re-fetch hashes
+    #[arg(short, long, conflicts_with = "full")]
+    pub partial: bool,
+    /// Re-fetch hashes even if the version hasn't changed.
+    /// Useful to make sure the derivations are in the Nix store.
+    #[arg(short, long, conflicts_with = "partial")]
+    pub full: bool,
+    /// Print the diff, but don't write back the changes. Fails if any pin is outdated
+    #[arg(short = 'n', long, global = true)]
+    pub dry_run: bool,
+    /// Allow updating frozen pins, which would otherwise be ignored
+    #[arg(long = "frozen")]
+    pub update_frozen: bool,
+    /// Only look for new versions, and leave fetching them to `npins fetch`
+    #[arg(long, conflicts_with_all = ["partial", "full"])]
+    pub no_fetch: bool,
+    /// Change the repository of pins whose GitHub repository was renamed or transferred. The old
+    /// name is kept as alias. Without this, renames are only reported
+    #[arg(long)]
+    pub follow_renames: bool,
+    /// Maximum number of simultaneous downloads
+    #[arg(default_value = "5", short = 'j', long, visible_alias = "jobs", value_parser = parse_jobs)]
+    pub max_concurrent_downloads: usize,
+    /// Write a signed in-toto attestation of the resulting lock file to this path
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "signing_key",
+        conflicts_with = "dry_run"
+    )]
+    pub attest: Option<PathBuf>,
+    /// Sign the resulting lock file, see `npins sign`
+    #[arg(long, requires = "signing_key", conflicts_with = "dry_run")]
+    pub sign: bool,
+    /// Ed25519 secret key file to sign the attestation or lock file with, as generated by
+    /// `nix key generate-secret`
+    #[arg(long, value_name = "PATH", env = "NPINS_SIGNING_KEY_FILE")]
+    pub signing_key: Option<PathBuf>,
+    /// Keep going when pins fail to update, and write a report of which ones did to this path
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+    /// Only retry the pins that failed according to a report from `--report`, at the versions
+    /// found back then. The report is updated, unless `--report` is given as well
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["names", "regexes", "labels", "partial"])]
+    pub resume: Option<PathBuf>,
+    /// The policy that changes must comply with [default: policy.json in the npins directory]
+    #[arg(long, value_name = "PATH", env = "NPINS_POLICY")]
+    pub policy: Option<PathBuf>,
+    /// Who approved this update, for pins that the policy requires approval for
+    #[arg(long, value_name = "NAME")]
+    pub approved_by: Option<String>,
+    /// Write the policy violations as JSON to this path, e.g. for CI
+    #[arg(long, value_name = "PATH")]
+    pub violations: Option<PathBuf>,
+    /// Show the commits that pins on GitHub, GitLab, Forgejo and Bitbucket moved over, as told by
+    /// their API
+    #[arg(long)]
+    pub changelog: bool,
+    /// Write a commit message for the update to this file, including the commits of `--changelog`
+    #[arg(long, value_name = "PATH")]
+    pub commit_message_file: Option<PathBuf>,
+    /// Show the changes of each pin and ask whether to keep them. Rejected pins stay as they were
+    #[arg(long, conflicts_with = "dry_run")]
+    pub interactive: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct BatchOpts {
+    /// The policy that updates must comply with [default: policy.json in the npins directory]
+    #[arg(long, value_name = "PATH", env = "NPINS_POLICY")]
+    pub policy: Option<PathBuf>,
+    /// Write a signed in-toto attestation of the lock file to this path after each update
+    #[arg(long, value_name = "PATH", requires = "signing_key")]
+    pub attest: Option<PathBuf>,
+    /// Sign the lock file after each update, see `npins sign`
+    #[arg(long, requires = "signing_key")]
+    pub sign: bool,
+    /// Ed25519 secret key file to sign the attestation or lock file with, as generated by
+    /// `nix key generate-secret`
+    #[arg(long, value_name = "PATH", env = "NPINS_SIGNING_KEY_FILE")]
+    pub signing_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct InitOpts {
+    /// Don't add an initial `nixpkgs` entry
+    #[arg(long)]
+    pub bare: bool,
+    /// Ask what to pin, explaining the choices along the way
+    #[arg(long, conflicts_with = "bare")]
+    pub guide: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImportOpts {
+    #[arg(default_value = "nix/sources.json")]
+    pub path: PathBuf,
+    /// Only import one entry from Niv
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Update the imported entries to their latest versions, instead of keeping the revisions
+    /// and hashes pinned by Niv
+    #[arg(long)]
+    pub upgrade: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct NivShimOpts {
+    #[arg(default_value = "nix/sources.nix")]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImportFlakeOpts {
+    #[arg(default_value = "flake.lock")]
+    pub path: PathBuf,
+    /// Only import one entry from the flake
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Update the imported inputs to their latest versions, instead of keeping the revisions
+    /// and hashes locked by the flake
+    #[arg(long)]
+    pub upgrade: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImportNvfetcherOpts {
+    #[arg(default_value = "nvfetcher.toml")]
+    pub path: PathBuf,
+    /// The sources generated by nvfetcher [default: `_sources/generated.json` next to the
+    /// configuration]
+    #[arg(long, value_name = "PATH")]
+    pub generated: Option<PathBuf>,
+    /// Only import one entry from nvfetcher
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Update the imported entries to their latest versions, instead of keeping the versions
+    /// and hashes generated by nvfetcher
+    #[arg(long)]
+    pub upgrade: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImportGridlockOpts {
+    #[arg(default_value = "gridlock.json")]
+    pub path: PathBuf,
+    /// Only import one entry from gridlock
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Update the imported entries to their latest versions, instead of keeping the revisions
+    /// and hashes locked by gridlock
+    #[arg(long)]
+    pub upgrade: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct SyncOpts {
+    /// The registry: a git repository with a sources.json (at the top or in `npins/`),
+    /// an URL of a sources.json, or a local path to one
+    #[arg(long, env = "NPINS_REGISTRY")]
+    pub registry: String,
+    /// Pins to adopt from the registry, replacing local pins with the same name
+    pub names: Vec<String>,
+    /// Don't actually apply the changes
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ExportOpts {
+    /// Name of the pin
+    pub name: String,
+    /// Write the snippet to this file instead of stdout
+    #[arg(short = 'o', long, value_name = "FILE")]
+    pub to: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImportPinOpts {
+    /// The snippet written by `npins export`, as a path, an http(s) URL, or `-` for stdin
+    pub source: String,
+    /// Import the pin under a different name
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Replace an existing pin with the same name
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct FreezeOpts {
     /// Names of the pin(s)
     #[structopt(required = true)]
     pub names: Vec<String>,
@@ -444,48 +2156,338 @@ pub struct GetPathOpts {
     pub name: String,
 }
 
-#[derive(Debug, Subcommand)]
-pub enum Command {
-    /// Intializes the npins directory. Running this multiple times will restore/upgrade the
-    /// `default.nix` and never touch your sources.json.
-    Init(InitOpts),
+#[derive(Debug, Parser)]
+pub struct ApprovalOpts {
+    /// Names of the pin(s)
+    #[arg(required = true)]
+    pub names: Vec<String>,
+    /// Who approves
+    #[arg(long, value_name = "NAME")]
+    pub by: String,
+}
 
-    /// Adds a new pin entry.
-    // Boxing AddOpts as it is by far our largest structure, reduces
-    // memory requirements for smaller devices (even if maginal)
-    Add(Box<AddOpts>),
+#[derive(Debug, Parser)]
+pub struct CheckOpts {
+    /// Names of the pin(s) to check; all if omitted
+    pub names: Vec<String>,
+    /// Also evaluate all pins with `default.nix` like a project using them would, which fetches
+    /// them. Pins that need Nixpkgs are evaluated with `<nixpkgs>` from `NIX_PATH`
+    #[arg(long)]
+    pub eval: bool,
+    /// Also check that the lock file is signed by one of the `--public-key`s, see `npins sign`
+    #[arg(long, requires = "public_keys")]
+    pub verify_signature: bool,
+    /// Trusted public key(s), as generated by `nix key convert-secret-to-public`
+    #[arg(long = "public-key", value_name = "KEY")]
+    pub public_keys: Vec<attestation::PublicKey>,
+}
 
-    /// Lists the current pin entries.
-    Show,
+#[derive(Debug, Parser)]
+pub struct SignOpts {
+    /// Ed25519 secret key file to sign with, as generated by `nix key generate-secret`
+    #[arg(long, value_name = "PATH", env = "NPINS_SIGNING_KEY_FILE")]
+    pub signing_key: PathBuf,
+}
 
-    /// Updates all or the given pins to the latest version.
-    Update(UpdateOpts),
+#[derive(Debug, Parser)]
+pub struct FetchOpts {
+    /// Names of the pin(s) to fetch; all that need it if omitted
+    pub names: Vec<String>,
+    /// Maximum number of simultaneous downloads
+    #[arg(default_value = "5", short = 'j', long, visible_alias = "jobs", value_parser = parse_jobs)]
+    pub max_concurrent_downloads: usize,
+}
 
-    /// Upgrade the sources.json and default.nix to the latest format version. This may occasionally break Nix evaluation!
-    Upgrade,
+#[derive(Debug, Parser)]
+pub struct OutdatedOpts {
+    /// Names of the pin(s) to check; all if omitted
+    pub names: Vec<String>,
+    /// Don't ask the forges how many commits git pins are behind
+    #[arg(long)]
+    pub no_commits: bool,
+    /// Maximum number of simultaneous requests
+    #[arg(default_value = "5", short = 'j', long, visible_alias = "jobs", value_parser = parse_jobs)]
+    pub max_concurrent_downloads: usize,
+}
 
-    /// Removes one pin entry.
-    Remove(RemoveOpts),
+#[derive(Debug, Parser)]
+pub struct VerifyOpts {
+    /// Names of the pin(s) to verify; all if omitted
+    pub names: Vec<String>,
+    /// Additionally fetch via a second, independent path (e.g. git instead of a tarball)
+    /// and make sure that the contents are the same
+    #[arg(long)]
+    pub cross_check: bool,
+    /// Don't download anything, only hash the pins already in the local Nix store again.
+    /// Pins that aren't there are skipped
+    #[arg(long, conflicts_with = "cross_check")]
+    pub offline: bool,
+    /// Maximum number of simultaneous downloads
+    #[arg(default_value = "5", short = 'j', long, visible_alias = "jobs", value_parser = parse_jobs)]
+    pub max_concurrent_downloads: usize,
+}
 
-    /// Try to import entries from Niv
-    ImportNiv(ImportOpts),
+#[derive(Debug, Parser)]
+pub struct RangeOpts {
+    /// Name of the git pin
+    pub name: String,
+    /// The oldest revision of the range, e.g. the last known good one
+    #[arg(long)]
+    pub from: String,
+    /// The newest revision of the range, by default the pinned one
+    #[arg(long)]
+    pub to: Option<String>,
+    /// How many evenly spaced revisions to pin, including both ends of the range
+    #[arg(long, default_value = "10", value_parser = parse_jobs)]
+    pub sample: usize,
+    /// Maximum number of simultaneous downloads
+    #[arg(default_value = "5", short = 'j', long, visible_alias = "jobs", value_parser = parse_jobs)]
+    pub max_concurrent_downloads: usize,
+}
 
-    /// Try to import entries from flake.lock
-    ImportFlake(ImportFlakeOpts),
+#[derive(Debug, Parser)]
+pub struct ProvenanceOpts {
+    /// Name of the pin
+    pub name: String,
+}
 
-    /// Freeze a pin entry
-    Freeze(FreezeOpts),
+#[derive(Debug, Parser)]
+pub struct VerifyAttestationOpts {
+    /// The attestation, as written by `npins update --attest`
+    pub path: PathBuf,
+    /// Trusted public key(s), as generated by `nix key convert-secret-to-public`
+    #[arg(long = "public-key", value_name = "KEY", required = true)]
+    pub public_keys: Vec<attestation::PublicKey>,
+}
 
-    /// Thaw a pin entry
-    Unfreeze(FreezeOpts),
+#[derive(Debug, Parser)]
+pub struct UpdateScriptOpts {
+    /// Names of the pin(s); all that aren't frozen if omitted
+    pub names: Vec<String>,
+    /// Print a single shell script that updates all the pins, instead of one command per pin
+    #[arg(long)]
+    pub shell: bool,
+}
 
-    /// Evaluates the store path to a pin, fetching it if necessary. Don't forget to add a GC root
-    GetPath(GetPathOpts),
+#[derive(Debug, Parser)]
+pub struct EnvOpts {
+    /// Names of the pin(s); all if omitted
+    pub names: Vec<String>,
+    /// Print `export` lines for a shell, or a dotenv file for `docker run --env-file` and the like
+    #[arg(long, value_name = "shell|dotenv", default_value_t = env::Format::Shell)]
+    pub format: env::Format,
 }
 
 #[derive(Debug, Parser)]
-#[command(
-    version,
+pub struct DirenvHookOpts {
+    /// Also run `npins check` on entering the project, to notice broken pins right away
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MirrorsCommand {
+    /// Sends a HEAD request to every URL recorded in the pins and reports dead links, redirects and
+    /// certificate problems
+    Check(MirrorsCheckOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct MirrorsCheckOpts {
+    /// Names of the pin(s) to check; all if omitted
+    pub names: Vec<String>,
+    /// Also check the URLs on a mirror, given as `<original prefix>=<mirror prefix>`,
+    /// e.g. `https://github.com/=https://mirror.example.org/github/`. May be repeated
+    #[arg(long = "mirror", value_name = "MIRROR")]
+    pub mirrors: Vec<mirrors::Mirror>,
+    /// Maximum number of simultaneous requests
+    #[arg(default_value = "5", short = 'j', long, visible_alias = "jobs", value_parser = parse_jobs)]
+    pub max_concurrent_requests: usize,
+}
+
+#[derive(Debug, Parser)]
+pub struct AuditOpts {
+    /// Names of the pin(s) to audit; all if omitted
+    pub names: Vec<String>,
+    /// The product on endoflife.date a pin is a version of, given as `<pin>=<product>`.
+    /// Defaults to the pin's name. May be repeated
+    #[arg(long = "product", value_name = "PIN=PRODUCT", value_parser = parse_product)]
+    pub products: Vec<(String, String)>,
+    /// Maximum number of simultaneous requests
+    #[arg(default_value = "5", short = 'j', long, visible_alias = "jobs", value_parser = parse_jobs)]
+    pub max_concurrent_requests: usize,
+}
+
+fn parse_product(product: &str) -> Result<(String, String)> {
+    let (pin, product) = product
+        .split_once('=')
+        .context("must be of the form <pin>=<product>")?;
+    Ok((pin.to_owned(), product.to_owned()))
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Intializes the npins directory. Running this multiple times will restore/upgrade the
+    /// `default.nix` and never touch your sources.json.
+    Init(InitOpts),
+
+    /// Adds a new pin entry.
+    // Boxing AddOpts as it is by far our largest structure, reduces
+    // memory requirements for smaller devices (even if maginal)
+    Add(Box<AddOpts>),
+
+    /// Lists the current pin entries.
+    Show(ShowOpts),
+
+    /// Updates all or the given pins to the latest version.
+    Update(UpdateOpts),
+
+    /// Sets a git pin to a specific commit on its branch, instead of the latest one. Freeze the
+    /// pin to keep `npins update` from moving it on.
+    Pin(PinOpts),
+
+    /// Fetches the hashes of pins whose version was updated with `npins update --no-fetch`.
+    Fetch(FetchOpts),
+
+    /// Lists the pins that have a newer version, without changing anything.
+    Outdated(OutdatedOpts),
+
+    /// Upgrade the sources.json and default.nix to the latest format version. This may occasionally break Nix evaluation!
+    Upgrade,
+
+    /// Removes one pin entry along with its variants, or all with a label.
+    Remove(RemoveOpts),
+
+    /// Changes a pin: its labels and aliases, or how it tracks upstream. The pinned version is
+    /// kept if the pin could still be at it, otherwise the pin is updated.
+    Edit(EditOpts),
+
+    /// Describes what a pin is for, and attaches metadata to it. Without any changes, prints them
+    Annotate(AnnotateOpts),
+
+    /// Renames a pin.
+    Rename(RenameOpts),
+
+    /// Compares two lock files, by default the current one against the one committed to git
+    Diff(DiffOpts),
+
+    /// Reverts the pins, or only one of them, to a previous state of the lock file.
+    /// The last few states are kept locally whenever the lock file changes
+    Rollback(RollbackOpts),
+
+    /// Prunes the previous states of the lock file, stale update journals and old ranges,
+    /// and checks that the rest can still be read
+    Maintenance(MaintenanceOpts),
+
+    /// Try to import entries from Niv
+    ImportNiv(ImportOpts),
+
+    /// Writes a replacement for Niv's `sources.nix`, which provides the pins the way Niv did.
+    /// This keeps Nix code written for Niv working during a gradual migration
+    NivShim(NivShimOpts),
+
+    /// Try to import entries from flake.lock
+    ImportFlake(ImportFlakeOpts),
+
+    /// Writes the pins as inputs of a flake.lock, so that a flake can expose the same sources
+    ExportFlake(ExportFlakeOpts),
+
+    /// Try to import entries from nvfetcher.toml, keeping the versions in nvfetcher's generated
+    /// sources
+    ImportNvfetcher(ImportNvfetcherOpts),
+
+    /// Try to import entries from gridlock's lock file
+    ImportGridlock(ImportGridlockOpts),
+
+    /// Replaces the pins managed by a registry with the registry's version of them, and adopts
+    /// the given pins from it
+    Sync(SyncOpts),
+
+    /// Prints a pin along with its version and hashes as a snippet, to be shared with `npins import-pin`
+    Export(ExportOpts),
+
+    /// Adds a pin from a snippet written by `npins export`
+    ImportPin(ImportPinOpts),
+
+    /// Freeze a pin entry
+    Freeze(FreezeOpts),
+
+    /// Thaw a pin entry
+    Unfreeze(FreezeOpts),
+
+    /// Marks pins as requiring approval: `npins update` then only proposes changes to them,
+    /// which somebody else applies with `npins approve`
+    RequireApproval(ApprovalOpts),
+
+    /// Applies the proposed changes to pins that require approval
+    Approve(ApprovalOpts),
+
+    /// Evaluates the store path to a pin, fetching it if necessary. Don't forget to add a GC root
+    GetPath(GetPathOpts),
+
+    /// Prints the Nix expression that `default.nix` uses to fetch a pin
+    ExplainNix(ExplainNixOpts),
+
+    /// Checks the pins for problems, like credentials in URLs that would end up in the Nix store
+    Check(CheckOpts),
+
+    /// Re-fetches the pins without updating them, and makes sure that their hashes did not change
+    Verify(VerifyOpts),
+
+    /// Pins evenly spaced revisions between two commits of a git pin to a lock file of their own,
+    /// so that any of them can be built when bisecting
+    Range(RangeOpts),
+
+    /// Shows how a pin was last changed, and checks that it was not modified since
+    Provenance(ProvenanceOpts),
+
+    /// Checks that an attestation written by `npins update --attest` is signed by a trusted key,
+    /// and that it matches the current lock file
+    VerifyAttestation(VerifyAttestationOpts),
+
+    /// Signs the lock file as it is, e.g. after reviewing changes to it. `npins check
+    /// --verify-signature` refuses lock files that were changed since
+    Sign(SignOpts),
+
+    /// Prints commands to update the pins, for use as `passthru.updateScript` in Nixpkgs.
+    /// The lock file is referred to as given with `--directory` or `--lock-file`, so give it
+    /// relative to where the commands will run
+    UpdateScript(UpdateScriptOpts),
+
+    /// Prints the pins as environment variables like `NPINS_<NAME>_REVISION`, `_URL` and `_HASH`,
+    /// for scripts and builds that don't use Nix
+    Env(EnvOpts),
+
+    /// Prints a snippet for direnv's `.envrc`, which exports the pins like `npins env` and
+    /// reloads them when the lock file changes. The lock file is referred to as given with
+    /// `--directory` or `--lock-file`, relative to the `.envrc`
+    DirenvHook(DirenvHookOpts),
+
+    /// Checks that the recorded URLs and their mirrors are still reachable
+    #[command(subcommand)]
+    Mirrors(MirrorsCommand),
+
+    /// Reports pins whose release cycle reached (or soon reaches) its end of life according to
+    /// endoflife.date, and pins of archived repositories
+    Audit(AuditOpts),
+
+    /// Reads requests as JSON lines from stdin and prints the result of each as a JSON line, so
+    /// that other tools can get, add, update and remove pins without starting npins over and over.
+    /// See the README for the requests
+    Batch(BatchOpts),
+
+    /// Moves the settings from the deprecated environment variables `NPINS_GITHUB_HOST` and
+    /// `NPINS_GITHUB_API_HOST` into npins.toml, next to the lock file
+    MigrateConfig(MigrateConfigOpts),
+
+    /// Removes the temporary files that interrupted or crashed runs of npins left behind, in
+    /// `$NPINS_TMPDIR` or else the system's temporary directory
+    CleanTemp(CleanTempOpts),
+}
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
     about,
     arg_required_else_help = true,
     // Confirm clap defaults
@@ -503,506 +2505,2558 @@ pub struct Opts {
     )]
     folder: std::path::PathBuf,
 
-    /// Specifies the path to the sources.json and activates lockfile mode.
-    /// In lockfile mode, no default.nix will be generated and --directory will be ignored.
-    #[arg(long)]
-    lock_file: Option<std::path::PathBuf>,
+    /// Specifies the path to the sources.json and activates lockfile mode.
+    /// In lockfile mode, no default.nix will be generated and --directory will be ignored.
+    #[arg(long)]
+    lock_file: Option<std::path::PathBuf>,
+
+    /// Print debug messages.
+    #[arg(global = true, short = 'v', long = "verbose")]
+    pub verbose: bool,
+
+    /// Compute hashes on another machine, as `ssh://[user@]host[:port]`
+    #[arg(global = true, long, value_name = "URL", env = "NPINS_PREFETCH_ON")]
+    prefetch_on: Option<remote::Builder>,
+
+    /// Compute hashes with Nix, or in-process for machines without Nix, like Windows ones. By
+    /// default, Nix is used if it is installed
+    #[arg(global = true, long, value_name = "nix|builtin", env = "NPINS_HASHER")]
+    hasher: Option<nix::Hasher>,
+
+    /// Print the results of `show`, `update`, `outdated`, `diff` and `verify` as `json` or as
+    /// `table` on stdout, instead of as text for humans
+    #[arg(
+        global = true,
+        long,
+        value_name = "text|json|table",
+        default_value_t = output::Format::Text
+    )]
+    output: output::Format,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// What updated pins must pass before they are written, and what is done with the lock file after
+struct UpdateGate {
+    policy: Option<policy::Policy>,
+    context: policy::UpdateContext,
+    /// Where to write the policy violations
+    violations: Option<PathBuf>,
+    /// Where to write the attestation
+    attest: Option<PathBuf>,
+    /// Whether to sign the lock file
+    sign: bool,
+    /// Only read with `attest` or `sign`
+    signing_key: Option<attestation::SigningKey>,
+    started_on: std::time::SystemTime,
+}
+
+fn write_diff(writer: &mut impl Write, name: &str, diff: &[diff::DiffEntry]) {
+    if diff.is_empty() {
+        writeln!(writer, "[{name}] No Changes").unwrap();
+    } else {
+        writeln!(writer, "[{name}] Changes:").unwrap();
+        for entry in diff {
+            write!(writer, "{entry}").unwrap();
+        }
+    }
+}
+
+/// Ask which of the changed pins to keep, for `npins update --interactive`. The others are put
+/// back as they were. Returns whether any changes are left
+fn review_changes<R: BufRead, W: Write>(
+    prompt: &mut prompt::Prompt<R, W>,
+    original: &NixPins,
+    pins: &mut NixPins,
+    outcomes: &mut BTreeMap<String, output::Outcome>,
+) -> Result<bool> {
+    let mut accepted = false;
+    for (name, pin_diff) in diff::diff_pins(original, pins) {
+        let Some(before) = original.pins.get(&name) else {
+            continue;
+        };
+        let mut text = Vec::new();
+        write_diff(&mut text, &name, &pin_diff.changes);
+        prompt.say(String::from_utf8_lossy(&text).trim_end())?;
+        if prompt.confirm(&format!("Keep the changes of {name}?"), true)? {
+            accepted = true;
+        } else {
+            pins.pins.insert(name.clone(), before.clone());
+            outcomes.insert(
+                name,
+                output::Outcome::Skipped {
+                    reason: "rejected".into(),
+                },
+            );
+        }
+    }
+    Ok(accepted)
+}
+
+impl Opts {
+    fn lock_file_path(&self) -> PathBuf {
+        if let Some(lock_file) = self.lock_file.as_ref() {
+            lock_file.to_owned()
+        } else {
+            self.folder.join("sources.json")
+        }
+    }
+
+    fn read_pins(&self) -> Result<NixPins> {
+        let path = self.lock_file_path();
+        let fh = std::io::BufReader::new(std::fs::File::open(&path).with_context(move || {
+            format!(
+                "Failed to open {}. You must initialize npins before you can show current pins.",
+                path.display()
+            )
+        })?);
+        NixPins::from_json_versioned(serde_json::from_reader(fh)?)
+            .context("Failed to deserialize sources.json")
+    }
+
+    /// Where npins.toml is, next to the lock file
+    fn config_path(&self) -> PathBuf {
+        match &self.lock_file {
+            Some(lock_file) => lock_file.with_file_name("npins.toml"),
+            None => self.folder.join("npins.toml"),
+        }
+    }
+
+    /// The lock file as of a git revision
+    fn read_pins_at(&self, rev: &str) -> Result<NixPins> {
+        let path = self.lock_file_path();
+        let directory = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(std::path::Path::new("."));
+        let file_name = path
+            .file_name()
+            .context("The lock file path has no file name")?
+            .to_string_lossy();
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(directory)
+            .arg("show")
+            .arg(format!("{rev}:./{file_name}"))
+            .output()
+            .context("Failed to spawn git show")?;
+        anyhow::ensure!(
+            output.status.success(),
+            "Failed to read {} as of {}\n{}",
+            path.display(),
+            rev,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        NixPins::from_json_versioned(serde_json::from_slice(&output.stdout)?)
+            .with_context(|| format!("Failed to deserialize {} as of {}", path.display(), rev))
+    }
+
+    /// The policy at `path`, or else in the npins directory if there is one
+    fn read_policy(&self, path: Option<&std::path::Path>) -> Result<Option<policy::Policy>> {
+        match path {
+            Some(path) => policy::Policy::read(path)?
+                .with_context(|| format!("The policy {} does not exist", path.display()))
+                .map(Some),
+            None if self.lock_file.is_none() => {
+                policy::Policy::read(&self.folder.join("policy.json"))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Where the previous states of the lock file are kept
+    fn history(&self) -> history::History {
+        match &self.lock_file {
+            Some(lock_file) => history::History::new(lock_file.with_extension("history")),
+            None => history::History::new(self.folder.join("history")),
+        }
+    }
+
+    fn write_pins(&self, pins: &NixPins) -> Result<()> {
+        if self.lock_file.is_none() && !self.folder.exists() {
+            std::fs::create_dir(&self.folder)?;
+        }
+        let path = self.lock_file_path();
+        /* Write to a temporary file first, so that an interruption can't leave a truncated lock file behind */
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        let mut contents = serde_json::to_vec_pretty(&pins.to_value_versioned())?;
+        contents.push(b'\n');
+        match std::fs::read(&path) {
+            Ok(previous) if previous != contents => self.history().record(&previous)?,
+            Ok(_) => {},
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            },
+        }
+        let mut fh = std::fs::File::create(&temporary)
+            .with_context(|| format!("Failed to open {} for writing.", temporary.display()))?;
+        fh.write_all(&contents)?;
+        fh.sync_all()?;
+        std::fs::rename(&temporary, &path)
+            .with_context(move || format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    async fn init(&self, o: &InitOpts) -> Result<()> {
+        let mut prompt = o.guide.then(prompt::Prompt::terminal).transpose()?;
+        log::info!("Welcome to npins!");
+
+        // Skip the entire default.nix and convenience creating folders bit in lockfile mode
+        if self.lock_file.is_none() {
+            let default_nix = DEFAULT_NIX;
+            if !self.folder.exists() {
+                log::info!("Creating `{}` directory", self.folder.display());
+                std::fs::create_dir(&self.folder).context("Failed to create npins folder")?;
+            }
+            log::info!("Writing default.nix");
+            let p = self.folder.join("default.nix");
+            let mut fh = std::fs::File::create(&p).context("Failed to create npins default.nix")?;
+            fh.write_all(default_nix.as_bytes())?;
+        }
+
+        let sources_json = self.folder.join("sources.json");
+        let path = self.lock_file.as_ref().unwrap_or(&sources_json);
+        // Only create the pins if the file isn't there yet
+        if path.exists() {
+            log::info!(
+                "The file '{}' already exists; nothing to do.",
+                path.display()
+            );
+            return Ok(());
+        }
+
+        let initial_pins = if let Some(prompt) = &mut prompt {
+            let pins = self.guide(prompt).await?;
+            log::info!("Writing initial lock file with {} pins", pins.pins.len());
+            pins
+        } else if o.bare {
+            log::info!("Writing initial lock file (empty)");
+            NixPins::default()
+        } else {
+            log::info!(
+                "Writing initial lock file with nixpkgs entry (need to fetch latest commit first)"
+            );
+            let mut pin = NixPins::new_with_nixpkgs();
+            engine::update_one(
+                pin.pins.get_mut("nixpkgs").unwrap(),
+                UpdateStrategy::Full,
+                false,
+            )
+            .await
+            .context("Failed to fetch initial nixpkgs entry")?;
+            pin
+        };
+        self.write_pins(&initial_pins)?;
+        log::info!(
+            "Successfully written initial files to '{}'.",
+            self.lock_file
+                .as_ref()
+                .unwrap_or(&self.folder.join("sources.json"))
+                .display()
+        );
+        self.usage_hints(&initial_pins);
+        Ok(())
+    }
+
+    /// Ask what to pin, for `npins init --guide`
+    async fn guide<R: BufRead, W: Write>(
+        &self,
+        prompt: &mut prompt::Prompt<R, W>,
+    ) -> Result<NixPins> {
+        let mut pins = NixPins::default();
+        prompt.say(
+            "npins keeps track of the sources your project depends on. Each of them is a pin, \
+            which follows something upstream, like a branch or the releases of a repository, \
+            and is locked to one version of it along with its hash.\n",
+        )?;
+        if prompt.confirm("Pin nixpkgs?", true)? {
+            prompt.say(
+                "nixpkgs is best followed through a channel, which only moves once the commits \
+                it points to are built. Take `nixpkgs-unstable` for the latest packages, or a \
+                release like `nixos-24.05` for stable ones.",
+            )?;
+            let channel = prompt.ask("Which channel?", Some("nixpkgs-unstable"))?;
+            pins.pins
+                .insert("nixpkgs".into(), channel::Pin::new(channel).into());
+        }
+        while prompt.confirm("Pin a GitHub repository?", false)? {
+            let (owner, name) = loop {
+                match prompt
+                    .ask("Which one (owner/repository)?", None)?
+                    .split_once('/')
+                {
+                    Some((owner, name)) if !owner.is_empty() && !name.is_empty() => {
+                        break (owner.to_owned(), name.to_owned())
+                    },
+                    _ => prompt.say("Please answer like `nix-community/home-manager`")?,
+                }
+            };
+            let repository = git::Repository::github(&owner, &name);
+            prompt.say(
+                "The pin can follow the releases of the repository, i.e. its tags that look like \
+                versions, or the latest commit of a branch. Releases change less often, branches \
+                get fixes sooner.",
+            )?;
+            let pin: Pin = match prompt.choose(
+                "Follow its releases or a branch?",
+                &["releases", "branch"],
+                "releases",
+            )? {
+                "releases" => git::GitReleasePin::new(repository, false, None, None, false).into(),
+                _ => {
+                    let default = git::fetch_default_branch(&repository.git_url()?).await.ok();
+                    let branch = prompt.ask("Which branch?", default.as_deref())?;
+                    git::GitPin::new(repository, branch, false).into()
+                },
+            };
+            let name = prompt.ask("Name of the pin?", Some(&name))?;
+            if pins.pins.insert(name.clone(), pin).is_some() {
+                prompt.say(format!("Replaced the previous pin called {name}"))?;
+            }
+        }
+
+        for (name, pin) in pins.pins.iter_mut() {
+            log::info!("Fetching {name} …");
+            engine::update_one(pin, UpdateStrategy::Full, false)
+                .await
+                .with_context(|| format!("Failed to fetch {name}"))?;
+        }
+        Ok(pins)
+    }
+
+    /// How to go on after `npins init`
+    fn usage_hints(&self, pins: &NixPins) {
+        if self.lock_file.is_none() {
+            let example = pins.pins.keys().next().map_or("<name>", String::as_str);
+            log::info!(
+                "Use the pins in Nix with `sources = import ./{};`, e.g. `sources.{example}`",
+                self.folder.display()
+            );
+        }
+        log::info!("Add more pins with `npins add`, and update them with `npins update`");
+    }
+
+    fn show(&self, o: &ShowOpts) -> Result<()> {
+        let pins = self.read_pins()?;
+        let pins = pins
+            .pins
+            .iter()
+            .filter(|(_, pin)| o.labels.is_empty() || pin.has_any_label(&o.labels));
+        match self.output {
+            output::Format::Text => {
+                for (name, pin) in pins {
+                    println!("{}: ({})", name, pin.pin_type());
+                    println!("{}", pin);
+                }
+            },
+            output::Format::Json => output::print_json(&pins.collect::<BTreeMap<_, _>>())?,
+            output::Format::Table => print!("{}", output::pins_table(pins)),
+        }
+
+        Ok(())
+    }
+
+    async fn add(&self, opts: &AddOpts) -> Result<()> {
+        let (_, pin) = self.add_pin(opts).await?;
+        println!("{}", pin);
+        Ok(())
+    }
+
+    /// Add the pin, and return it along with its name
+    async fn add_pin(&self, opts: &AddOpts) -> Result<(String, Pin)> {
+        let mut pins = self.read_pins()?;
+        let (name, mut pin) = opts.run().await?;
+        if opts.frozen {
+            log::info!("Adding '{}' (frozen) …", name);
+        } else {
+            log::info!("Adding '{}' …", name);
+        }
+        if let Pin::Git {
+            input,
+            version: Some(version),
+            ..
+        } = &mut pin
+        {
+            *version = input.verify(version.clone()).await?;
+        }
+        /* Fetch the latest version unless the user specified some */
+        let strategy = if pin.has_version() {
+            UpdateStrategy::HashesOnly
+        } else {
+            UpdateStrategy::Full
+        };
+        engine::update_one(&mut pin, strategy, false)
+            .await
+            .context("Failed to fully initialize the pin")?;
+        pins.pins.insert(name.clone(), pin.clone());
+        if !opts.dry_run {
+            self.write_pins(&pins)?;
+        }
+        Ok((name, pin))
+    }
+
+    /// Show the commits the pins moved over and write the commit message, as asked for
+    ///
+    /// Returns the pins whose commits couldn't be listed.
+    async fn write_changelog(
+        &self,
+        original: &NixPins,
+        pins: &NixPins,
+        opts: &UpdateOpts,
+    ) -> Result<Vec<String>> {
+        let changes = diff::diff_pins(original, pins);
+        let results =
+            stream::iter(&changes)
+                .filter_map(|(name, _)| {
+                    future::ready(original.pins.get(name).zip(pins.pins.get(name)).map(
+                        |(before, after)| async move {
+                            (name, changelog::changelog(before, after).await)
+                        },
+                    ))
+                })
+                .buffer_unordered(opts.max_concurrent_downloads)
+                .collect::<Vec<_>>()
+                .await;
+
+        let mut changelogs = BTreeMap::new();
+        let mut failed = Vec::new();
+        for (name, result) in results {
+            match result {
+                Ok(Some(changelog)) => {
+                    changelogs.insert(name.clone(), changelog);
+                },
+                Ok(None) => {},
+                Err(err) => {
+                    log::warn!("[{name}] Failed to list the new commits: {err:#}");
+                    failed.push(name.clone());
+                },
+            }
+        }
+
+        if opts.changelog {
+            let mut stderr = stderr();
+            for (name, changelog) in &changelogs {
+                write!(stderr, "[{name}] Commits: {changelog}")?;
+            }
+        }
+        if let Some(path) = &opts.commit_message_file {
+            std::fs::write(path, changelog::commit_message(&changes, &changelogs))
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(failed)
+    }
+
+    async fn update(&self, opts: &UpdateOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        let original = pins.clone();
+        /* Fail early on a bad policy or key, and not after all the fetching */
+        let gate = self.update_gate(
+            opts.policy.as_deref(),
+            opts.approved_by.clone(),
+            opts.violations.clone(),
+            opts.attest.clone(),
+            opts.sign,
+            opts.signing_key.as_deref(),
+        )?;
+        let mut prompt = opts
+            .interactive
+            .then(prompt::Prompt::terminal)
+            .transpose()?;
+
+        /* Retry only what failed last time, at the versions that were found back then */
+        let report_path = opts.report.as_deref().or(opts.resume.as_deref());
+        let mut report = opts
+            .resume
+            .as_deref()
+            .map(report::Report::read)
+            .transpose()?
+            .unwrap_or_default();
+        report.pins.retain(|name, _| pins.pins.contains_key(name));
+        let mut has_diff = false;
+        let mut resolved = BTreeSet::new();
+        let names = if opts.resume.is_some() {
+            let mut names = Vec::new();
+            for (name, pin) in report.failed() {
+                if let Some(pin) = pin {
+                    pins.pins.insert(name.to_owned(), pin.clone());
+                    resolved.insert(name.to_owned());
+                    has_diff = true;
+                }
+                names.push(name.to_owned());
+            }
+            if names.is_empty() {
+                log::info!("Nothing to retry, all pins were updated successfully.");
+                return Ok(());
+            }
+            names
+        } else {
+            let mut names = pins.with_variants(&selection::expand(
+                pins.pins.keys(),
+                &opts.names,
+                &opts.regexes,
+            )?);
+            for (name, pin) in &pins.pins {
+                if pin.has_any_label(&opts.labels) && !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            if names.is_empty()
+                && !(opts.names.is_empty() && opts.regexes.is_empty() && opts.labels.is_empty())
+            {
+                log::info!("Nothing to update, no pins match the selection.");
+                return Ok(());
+            }
+            names
+        };
+
+        let mut outcomes = BTreeMap::new();
+        let mut selected_pins = BTreeSet::new();
+        for name in &names {
+            if !selected_pins.insert(name) {
+                log::warn!("Ignoring duplicate pin: {name}")
+            }
+        }
+        let options = engine::Options {
+            concurrency: opts.max_concurrent_downloads,
+            update_frozen: opts.update_frozen,
+            follow_renames: opts.follow_renames,
+        };
+        selected_pins.retain(|&name| match pins.pins.get(name) {
+            Some(p) => match engine::skip_reason(p, &options) {
+                Some(engine::SkipReason::Frozen) => {
+                    log::warn!("Ignoring frozen pin: {name}");
+                    outcomes.insert(
+                        name.clone(),
+                        output::Outcome::Skipped {
+                            reason: "frozen".into(),
+                        },
+                    );
+                    false
+                },
+                Some(engine::SkipReason::Managed) => {
+                    log::warn!("Ignoring pin managed by the registry, use `npins sync`: {name}");
+                    outcomes.insert(
+                        name.clone(),
+                        output::Outcome::Skipped {
+                            reason: "managed by the registry".into(),
+                        },
+                    );
+                    false
+                },
+                None => true,
+            },
+            None => {
+                log::warn!("Specified pin does not exist: {name}");
+                false
+            },
+        });
+
+        /* Pick up where an interrupted update left off */
+        let (journal, done) = if opts.dry_run {
+            (None, BTreeMap::new())
+        } else {
+            let (journal, done) = journal::Journal::open(&self.lock_file_path())?;
+            (Some(journal), done)
+        };
+        let done: BTreeMap<String, Pin> = done
+            .into_iter()
+            .filter(|(name, _)| pins.pins.contains_key(name))
+            .collect();
+        if !done.is_empty() {
+            log::info!(
+                "Resuming an interrupted update, {} pins are already done",
+                done.len()
+            );
+        }
+        for (name, pin) in &done {
+            has_diff |= pins.pins.insert(name.clone(), pin.clone()).as_ref() != Some(pin);
+        }
+
+        let strategy = match (opts.partial, opts.full, opts.no_fetch) {
+            (false, false, false) => UpdateStrategy::Normal,
+            (false, true, false) => UpdateStrategy::Full,
+            (true, false, false) => UpdateStrategy::HashesOnly,
+            (false, false, true) => UpdateStrategy::VersionOnly,
+            _ => unreachable!("clap rejects combining --partial, --full and --no-fetch"),
+        };
+
+        /* Without a selection, everything that isn't skipped gets updated */
+        let jobs: Vec<engine::Job> = pins
+            .pins
+            .iter()
+            .filter(|(name, pin)| {
+                (selected_pins.contains(name)
+                    || (names.is_empty() && engine::skip_reason(pin, &options).is_none()))
+                    && !done.contains_key(*name)
+            })
+            .map(|(name, pin)| {
+                let strategy = if resolved.contains(name) {
+                    UpdateStrategy::HashesOnly
+                } else {
+                    strategy
+                };
+                engine::Job::new(name, pin.clone(), strategy)
+            })
+            .collect();
+        let length = jobs.len();
+        let job_names: BTreeSet<String> = jobs.iter().map(|job| job.name.clone()).collect();
+
+        let animation = Animation::new(|stderr, finished| {
+            write!(stderr, "Updated {finished}/{length} pins").unwrap()
+        });
+        /* Frozen pins are held back on purpose, but that shouldn't be forgotten */
+        let frozen: Vec<String> = if names.is_empty() {
+            pins.pins
+                .iter()
+                .filter(|(_, pin)| {
+                    engine::skip_reason(pin, &options) == Some(engine::SkipReason::Frozen)
+                })
+                .map(|(name, _)| name.clone())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        for name in &frozen {
+            outcomes.insert(
+                name.clone(),
+                output::Outcome::Skipped {
+                    reason: "frozen".into(),
+                },
+            );
+        }
+        /* Stop updating on Ctrl-C, but keep the pins that are done */
+        let _graceful = interrupt::graceful();
+        let result = async {
+            let mut events = std::pin::pin!(engine::update_all(jobs, options));
+            loop {
+                let event = tokio::select! {
+                    event = events.next() => event,
+                    () = interrupt::interrupted() => return Ok(true),
+                };
+                let Some(event) = event else {
+                    return Ok(false);
+                };
+                match event {
+                    engine::PinEvent::Started { name } => {
+                        animation.on_pin_start(job_names.get(&name).expect("one of the jobs"))
+                    },
+                    engine::PinEvent::Resolved { .. } => {},
+                    engine::PinEvent::Fetched {
+                        name,
+                        pin,
+                        diff,
+                        missing,
+                    } => {
+                        if let Some(journal) = &journal {
+                            journal.record(&name, &pin)?;
+                        }
+                        animation.on_pin_finish(&name);
+                        if self.output == output::Format::Text {
+                            animation.write(|stderr| write_diff(stderr, &name, &diff));
+                        }
+                        has_diff |= !diff.is_empty();
+                        report
+                            .pins
+                            .insert(name.clone(), report::Outcome::Succeeded { missing });
+                        outcomes.insert(name.clone(), output::Outcome::Ok { changes: diff });
+                        pins.pins.insert(name, pin);
+                    },
+                    engine::PinEvent::Failed { name, pin, error } => {
+                        if report_path.is_none() {
+                            return Err(error);
+                        }
+                        let outcome = report::Outcome::failed(&error, &pins.pins[&name], &pin);
+                        animation.write(|stderr| {
+                            writeln!(stderr, "[{name}] Failed: {error:#}").unwrap()
+                        });
+                        animation.on_pin_finish(&name);
+                        outcomes.insert(name.clone(), output::Outcome::failed(&error));
+                        report.pins.insert(name, outcome);
+                    },
+                    /* Skipped pins are not among the jobs in the first place */
+                    engine::PinEvent::Skipped { .. } => {},
+                }
+            }
+        }
+        .await;
+
+        /* Flush the status line */
+        if length != 0 && stderr().is_terminal() {
+            eprintln!();
+        }
+        let interrupted = result?;
+        if interrupted {
+            let unfinished = job_names
+                .iter()
+                .filter(|name| !outcomes.contains_key(*name))
+                .cloned()
+                .collect::<Vec<_>>();
+            log::warn!(
+                "Interrupted, not updating {} pin(s): {}. Keeping the others",
+                unfinished.len(),
+                unfinished.join(", ")
+            );
+            for name in unfinished {
+                outcomes.insert(
+                    name.clone(),
+                    output::Outcome::Skipped {
+                        reason: "interrupted".into(),
+                    },
+                );
+                /* So that `--resume` picks them up */
+                report.pins.insert(
+                    name,
+                    report::Outcome::Failed {
+                        error: interrupt::Interrupted.to_string(),
+                        resolved: None,
+                    },
+                );
+            }
+        }
+        if !frozen.is_empty() {
+            log::warn!(
+                "Not updating {} frozen pin(s): {}. Run `npins unfreeze` or pass `--frozen` to update them",
+                frozen.len(),
+                frozen.join(", ")
+            );
+        }
+
+        if let (Some(prompt), true) = (&mut prompt, has_diff) {
+            has_diff = review_changes(prompt, &original, &mut pins, &mut outcomes)?;
+        }
+
+        let failures = report.failed().count();
+        if let Some(path) = report_path {
+            report.write(path)?;
+        }
+
+        if has_diff {
+            self.land_update(&gate, &original, &mut pins, opts.dry_run)?;
+        }
+        if let Some(journal) = journal {
+            journal.finish()?;
+        }
+        if has_diff && (opts.changelog || opts.commit_message_file.is_some()) {
+            let missing = self.write_changelog(&original, &pins, opts).await?;
+            if let Some(path) = report_path.filter(|_| !missing.is_empty()) {
+                for name in missing {
+                    if let Some(report::Outcome::Succeeded { missing }) = report.pins.get_mut(&name)
+                    {
+                        missing.push("changelog".into());
+                    }
+                }
+                report.write(path)?;
+            }
+        }
+        self.print_outcomes(&outcomes)?;
+        if interrupted {
+            if let Some(path) = report_path {
+                log::info!(
+                    "Run `npins update --resume {}` to update the rest",
+                    path.display()
+                );
+            }
+            return Err(interrupt::Interrupted.into());
+        }
+        if let Some(path) = report_path.filter(|_| failures > 0) {
+            anyhow::bail!(
+                "{failures} pins failed to update. Run `npins update --resume {}` to retry them",
+                path.display()
+            );
+        }
+
+        if !opts.dry_run {
+            self.seal_update(&gate, &pins)?;
+            log::info!("Update successful.");
+        } else if has_diff {
+            /* Useful for CI to check whether anything is stale */
+            anyhow::bail!("Some pins are outdated, run `npins update` to update them");
+        } else {
+            log::info!("Dry run successful, all pins are up to date.");
+        }
+
+        Ok(())
+    }
+
+    /// Print what happened to the pins, unless the output is for humans
+    fn print_outcomes(&self, outcomes: &BTreeMap<String, output::Outcome>) -> Result<()> {
+        match self.output {
+            output::Format::Text => Ok(()),
+            output::Format::Json => output::print_json(outcomes),
+            output::Format::Table => {
+                print!("{}", output::Outcome::table(outcomes));
+                Ok(())
+            },
+        }
+    }
+
+    async fn fetch(&self, o: &FetchOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+
+        for name in &o.names {
+            anyhow::ensure!(
+                pins.pins.contains_key(name),
+                "Could not find the pin '{}'",
+                name
+            );
+        }
+
+        let mut results: Vec<(&String, Result<Vec<diff::DiffEntry>>)> = stream::iter(
+            pins.pins
+                .iter_mut()
+                .filter(|(name, pin)| {
+                    if o.names.is_empty() {
+                        pin.metadata().state != PinState::Fetched
+                    } else {
+                        o.names.contains(name)
+                    }
+                })
+                .map(|(name, pin)| async move {
+                    log::info!("Fetching {name} …");
+                    let result = engine::update_one(pin, UpdateStrategy::HashesOnly, false).await;
+                    if result.is_err() {
+                        pin.metadata_mut().state = PinState::Error;
+                    }
+                    (&*name, result)
+                }),
+        )
+        .buffer_unordered(o.max_concurrent_downloads)
+        .collect()
+        .await;
+        results.sort_by_key(|(name, _)| *name);
+
+        let mut failures = 0;
+        let mut stderr = stderr();
+        for (name, result) in &results {
+            match result {
+                Ok(diff) => write_diff(&mut stderr, name, diff),
+                Err(err) => {
+                    log::error!("[{name}] {err:#}");
+                    failures += 1;
+                },
+            }
+        }
+        let fetched = results.len();
+        drop(results);
+
+        /* Failures are recorded in the lock file as well */
+        if fetched > 0 {
+            self.write_pins(&pins)?;
+        }
+        anyhow::ensure!(failures == 0, "{failures} pin(s) failed to fetch");
+        log::info!("Fetched {fetched} pin(s) successfully.");
+        Ok(())
+    }
+
+    async fn outdated(&self, o: &OutdatedOpts) -> Result<()> {
+        let pins = self.read_pins()?;
+        for name in &o.names {
+            anyhow::ensure!(
+                pins.pins.contains_key(name),
+                "Could not find the pin '{}'",
+                name
+            );
+        }
+
+        /* Only look for the latest versions, the hashes are not needed for comparing them */
+        let jobs = pins
+            .pins
+            .iter()
+            .filter(|(name, _)| o.names.is_empty() || o.names.contains(name))
+            .map(|(name, pin)| engine::Job::new(name, pin.clone(), UpdateStrategy::VersionOnly))
+            .collect();
+        let options = engine::Options {
+            concurrency: o.max_concurrent_downloads,
+            ..Default::default()
+        };
+        let mut updated = Vec::new();
+        let mut failures = 0;
+        let mut events = std::pin::pin!(engine::update_all(jobs, options));
+        while let Some(event) = events.next().await {
+            match event {
+                engine::PinEvent::Fetched {
+                    name, pin, diff, ..
+                } if !diff.is_empty() => updated.push((name, pin)),
+                engine::PinEvent::Failed { name, error, .. } => {
+                    log::error!("[{name}] {error:#}");
+                    failures += 1;
+                },
+                _ => {},
+            }
+        }
+
+        let outdated: BTreeMap<String, output::Outdated> = stream::iter(updated)
+            .map(|(name, pin)| {
+                let current = &pins.pins[&name];
+                async move {
+                    let behind = if o.no_commits {
+                        None
+                    } else {
+                        match changelog::changelog(current, &pin).await {
+                            Ok(changelog) => changelog.map(|changelog| changelog.total_commits()),
+                            Err(err) => {
+                                log::warn!("[{name}] Failed to compare the revisions: {err:#}");
+                                None
+                            },
+                        }
+                    };
+                    let outdated = output::Outdated {
+                        current: output::short_version(current),
+                        available: output::short_version(&pin),
+                        behind,
+                    };
+                    (name, outdated)
+                }
+            })
+            .buffer_unordered(o.max_concurrent_downloads)
+            .collect()
+            .await;
+
+        match self.output {
+            output::Format::Json => output::print_json(&outdated)?,
+            _ if outdated.is_empty() => {
+                if failures == 0 {
+                    log::info!("All pins are up to date.")
+                }
+            },
+            _ => print!("{}", output::Outdated::table(&outdated)),
+        }
+        anyhow::ensure!(failures == 0, "{failures} pin(s) could not be checked");
+        Ok(())
+    }
+
+    fn upgrade(&self) -> Result<()> {
+        if self.lock_file.is_none() {
+            anyhow::ensure!(
+                self.folder.exists(),
+                "Could not find npins folder at {}",
+                self.folder.display(),
+            );
+
+            let nix_path = self.folder.join("default.nix");
+            let nix_file = DEFAULT_NIX;
+            if std::fs::read_to_string(&nix_path)? == nix_file {
+                log::info!("default.nix is already up to date");
+            } else {
+                log::info!("Replacing default.nix with an up to date version");
+                std::fs::write(&nix_path, nix_file)
+                    .context("Failed to create npins default.nix")?;
+            }
+        }
+
+        log::info!("Upgrading lock file to the newest format version");
+        let sources_json = self.folder.join("sources.json");
+        let path = self.lock_file.as_ref().unwrap_or(&sources_json);
+        let fh = std::io::BufReader::new(std::fs::File::open(&path).with_context(move || {
+            format!(
+                "Failed to open {}. You must initialize npins first.",
+                path.display()
+            )
+        })?);
+
+        let pins_raw: serde_json::Map<String, serde_json::Value> = serde_json::from_reader(fh)
+            .context("lock file must be a valid JSON file with an object as top level")?;
+
+        let pins_raw_new = versions::upgrade(pins_raw.clone()).context("Upgrading failed")?;
+        let pins: NixPins = serde_json::from_value(pins_raw_new.clone())?;
+        if pins_raw_new != serde_json::Value::Object(pins_raw) {
+            log::info!(
+                "Done. It is recommended to at least run `npins update --partial` afterwards."
+            );
+        }
+        self.write_pins(&pins)
+    }
+
+    async fn sync(&self, o: &SyncOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        let registry = registry::read(&o.registry).await?;
+        let changes = registry::reconcile(&mut pins, &registry, &o.names)?;
+        if changes.is_empty() {
+            log::info!("No pins are managed by the registry, name some to adopt them");
+            return Ok(());
+        }
+
+        for (name, change) in &changes {
+            match change {
+                registry::Change::Synced(diff) => write_diff(&mut std::io::stdout(), name, diff),
+                registry::Change::Adopted => {
+                    println!("[{name}] Adopted from the registry");
+                    print!("{}", pins.pins[name]);
+                },
+                registry::Change::Missing => {
+                    log::warn!("[{name}] Not in the registry anymore, keeping it as it is")
+                },
+            }
+        }
+        if !o.dry_run {
+            self.write_pins(&pins)?;
+        }
+        Ok(())
+    }
+
+    fn export(&self, o: &ExportOpts) -> Result<()> {
+        let pins = self.read_pins()?;
+        let pin = pins
+            .pins
+            .get(&o.name)
+            .ok_or_else(|| anyhow::format_err!("Could not find the pin '{}'", o.name))?;
+        let snippet = snippet::export(&o.name, pin)?;
+        match &o.to {
+            Some(path) => std::fs::write(path, snippet)
+                .with_context(|| format!("Failed to write {}", path.display()))?,
+            None => print!("{snippet}"),
+        }
+        Ok(())
+    }
+
+    async fn import_pin(&self, o: &ImportPinOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        let (name, mut pin) = snippet::read(&o.source).await?;
+        let name = o.name.clone().unwrap_or(name);
+        anyhow::ensure!(
+            o.force || !pins.pins.contains_key(&name),
+            "A pin named '{}' already exists. Use --name to import it under a different name, or --force to replace it",
+            name
+        );
+        for finding in check::check_pin(&pin) {
+            log::warn!("[{name}] {finding}");
+        }
+
+        /* Snippets normally come with their version and hashes, but they may have been edited */
+        let strategy = if !pin.has_version() {
+            Some(UpdateStrategy::Full)
+        } else if !pin.has_hashes() {
+            Some(UpdateStrategy::HashesOnly)
+        } else {
+            None
+        };
+        if let Some(strategy) = strategy {
+            engine::update_one(&mut pin, strategy, false)
+                .await
+                .context("Failed to fully initialize the pin")?;
+        }
+        log::info!("Importing '{}' …", name);
+        pins.pins.insert(name, pin.clone());
+        self.write_pins(&pins)?;
+
+        println!("{}", pin);
+        Ok(())
+    }
+
+    fn remove(&self, r: &RemoveOpts) -> Result<()> {
+        let pins = self.read_pins()?;
+
+        let names = match (&r.name, &r.label) {
+            (Some(name), _) => {
+                let names = pins.with_variants(std::slice::from_ref(name));
+                if !pins.pins.contains_key(&names[0]) {
+                    return Err(anyhow::anyhow!("Could not find the pin '{}'", name));
+                }
+                names
+            },
+            (None, Some(label)) => {
+                let names = pins
+                    .pins
+                    .iter()
+                    .filter(|(_, pin)| pin.metadata().labels.contains(label))
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>();
+                anyhow::ensure!(!names.is_empty(), "No pins have the label '{}'", label);
+                names
+            },
+            (None, None) => unreachable!("clap requires a name or a label"),
+        };
+
+        let mut new_pins = pins.clone();
+        for name in &names {
+            new_pins.pins.remove(name);
+        }
+
+        self.write_pins(&new_pins)?;
+        for name in names {
+            log::info!("Successfully removed pin '{}'.", name);
+        }
+        Ok(())
+    }
+
+    fn rollback(&self, o: &RollbackOpts) -> Result<()> {
+        let history = self.history();
+        let pins = self.read_pins()?;
+
+        if o.list {
+            for entry in history.list()? {
+                let changed = entry
+                    .pins
+                    .pins
+                    .keys()
+                    .chain(pins.pins.keys())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .filter(|name| entry.pins.pins.get(*name) != pins.pins.get(*name))
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+                let changed = if changed.is_empty() {
+                    "same as now".to_owned()
+                } else {
+                    format!("differs in {}", changed.join(", "))
+                };
+                println!(
+                    "{}: replaced at {}, {}",
+                    entry.age, entry.timestamp, changed
+                );
+            }
+            return Ok(());
+        }
+
+        let entry = history.get(o.to)?;
+        let new_pins = match &o.name {
+            None => entry.pins,
+            Some(name) => {
+                let mut new_pins = pins.clone();
+                match entry.pins.pins.get(name) {
+                    Some(pin) => {
+                        new_pins.pins.insert(name.clone(), pin.clone());
+                    },
+                    None => {
+                        anyhow::ensure!(
+                            new_pins.pins.remove(name).is_some(),
+                            "Could not find the pin '{}', neither now nor {} change(s) ago",
+                            name,
+                            o.to
+                        );
+                        log::info!("'{}' did not exist {} change(s) ago", name, o.to);
+                    },
+                }
+                new_pins
+            },
+        };
+
+        if new_pins == pins {
+            log::info!("Nothing to roll back, the pins are the same as back then");
+            return Ok(());
+        }
+        let names = pins
+            .pins
+            .keys()
+            .chain(new_pins.pins.keys())
+            .collect::<BTreeSet<_>>();
+        for name in names {
+            match (pins.pins.get(name), new_pins.pins.get(name)) {
+                (Some(before), Some(after)) if before != after => write_diff(
+                    &mut std::io::stdout(),
+                    name,
+                    &diff::diff(
+                        &diff::Diff::properties(before),
+                        &diff::Diff::properties(after),
+                    ),
+                ),
+                (Some(_), None) => println!("[{name}] Removed"),
+                (None, Some(_)) => println!("[{name}] Restored"),
+                _ => {},
+            }
+        }
+        self.write_pins(&new_pins)?;
+        log::info!(
+            "Rolled back {} change(s), `npins rollback` again undoes this",
+            o.to
+        );
+        Ok(())
+    }
+
+    fn explain_nix(&self, o: &ExplainNixOpts) -> Result<()> {
+        let pins = self.read_pins()?;
+        let pin = pins
+            .pins
+            .get(&o.name)
+            .with_context(|| format!("Could not find the pin '{}'", o.name))?;
+        println!("{}", explain::nix_expression(&o.name, pin, o.pkgs)?);
+        Ok(())
+    }
+
+    async fn pin(&self, o: &PinOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        let pin = pins
+            .pins
+            .get_mut(&o.name)
+            .with_context(|| format!("Could not find the pin '{}'", o.name))?;
+        let before = pin.clone();
+        let Pin::Git { input, version, .. } = pin else {
+            anyhow::bail!(
+                "Only git pins tracking a branch can be set to a revision, not a {}",
+                pin.pin_type()
+            );
+        };
+        log::info!("Checking that {} is on {} …", o.revision, input.branch);
+        *version = Some(
+            input
+                .verify(git::GitRevision::new(o.revision.clone())?)
+                .await?,
+        );
+
+        engine::update_one(pin, UpdateStrategy::HashesOnly, false).await?;
+        pin.remember(before.clone());
+        let diff = diff::diff(
+            &diff::Diff::properties(&before),
+            &diff::Diff::properties(&*pin),
+        );
+        write_diff(&mut stderr(), &o.name, &diff);
+        self.write_pins(&pins)?;
+        Ok(())
+    }
+
+    async fn edit(&self, o: &EditOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        let pin = pins
+            .pins
+            .get_mut(&o.name)
+            .with_context(|| format!("Could not find the pin '{}'", o.name))?;
+
+        let before = pin.clone();
+        match o.configure(pin)? {
+            Revalidate::Nothing => {},
+            Revalidate::Refetch => {
+                log::info!("Fetching '{}' again …", o.name);
+                engine::update_one(pin, UpdateStrategy::HashesOnly, false).await?;
+            },
+            Revalidate::Update => {
+                log::info!(
+                    "The pinned version of '{}' no longer fits, updating it …",
+                    o.name
+                );
+                engine::update_one(pin, UpdateStrategy::Full, false).await?;
+                pin.remember(before.clone());
+            },
+        }
+        if *pin != before {
+            let diff = diff::diff(
+                &diff::Diff::properties(&before),
+                &diff::Diff::properties(&*pin),
+            );
+            write_diff(&mut stderr(), &o.name, &diff);
+        }
+
+        let labels = &mut pin.metadata_mut().labels;
+        for label in &o.removed_labels {
+            if !labels.remove(label) {
+                log::warn!("'{}' has no label '{}'", o.name, label);
+            }
+        }
+        labels.extend(o.labels.iter().cloned());
+
+        let aliases = &mut pin.metadata_mut().aliases;
+        for alias in &o.removed_aliases {
+            if !aliases.remove(alias) {
+                log::warn!("'{}' has no alias '{}'", o.name, alias);
+            }
+        }
+        for alias in &o.aliases {
+            pins.add_alias(&o.name, alias)?;
+        }
+
+        let other_names = &mut pins
+            .pins
+            .get_mut(&o.name)
+            .expect("checked above")
+            .metadata_mut()
+            .other_names;
+        for other in &o.removed_other_names {
+            if !other_names.remove(other) {
+                log::warn!("'{}' has no other name '{}'", o.name, other);
+            }
+        }
+        for other in &o.other_names {
+            pins.add_other_name(&o.name, other)?;
+        }
+        if let Some(keep) = o.keep {
+            let metadata = pins
+                .pins
+                .get_mut(&o.name)
+                .expect("checked above")
+                .metadata_mut();
+            metadata.keep = keep;
+            metadata.previous.truncate(keep);
+        }
+
+        self.write_pins(&pins)?;
+        log::info!("Updated pin '{}'.", o.name);
+        Ok(())
+    }
+
+    fn annotate(&self, o: &AnnotateOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        let metadata = pins
+            .pins
+            .get_mut(&o.name)
+            .with_context(|| format!("Could not find the pin '{}'", o.name))?
+            .metadata_mut();
+
+        if o.description.is_none() && o.set.is_empty() && o.unset.is_empty() {
+            if let Some(description) = &metadata.description {
+                println!("{description}");
+            }
+            for (key, value) in &metadata.metadata {
+                println!("{key}={value}");
+            }
+            return Ok(());
+        }
+
+        if let Some(description) = &o.description {
+            metadata.description = Some(description.clone()).filter(|d| !d.is_empty());
+        }
+        for key in &o.unset {
+            if metadata.metadata.remove(key).is_none() {
+                log::warn!("'{}' has no metadata '{}'", o.name, key);
+            }
+        }
+        metadata.metadata.extend(o.set.iter().cloned());
+
+        self.write_pins(&pins)?;
+        log::info!("Updated pin '{}'.", o.name);
+        Ok(())
+    }
+
+    fn diff(&self, o: &DiffOpts) -> Result<()> {
+        let read = |path: &PathBuf| -> Result<NixPins> {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            NixPins::from_json_versioned(serde_json::from_str(&contents)?)
+                .with_context(|| format!("Failed to deserialize {}", path.display()))
+        };
+        let old = match &o.old {
+            Some(path) => read(path)?,
+            None => self.read_pins_at(&o.rev)?,
+        };
+        let new = match &o.new {
+            Some(path) => read(path)?,
+            None => self.read_pins()?,
+        };
+
+        let diff = diff::diff_pins(&old, &new);
+        match (o.json, self.output) {
+            (true, _) | (false, output::Format::Json) => return output::print_json(&diff),
+            (false, output::Format::Table) => {
+                print!("{}", output::diff_table(&diff));
+                return Ok(());
+            },
+            (false, output::Format::Text) => {},
+        }
+        if diff.is_empty() {
+            println!("No changes");
+        }
+        for (name, pin) in &diff {
+            match pin.status {
+                diff::Status::Changed => println!("[{name}] Changes:"),
+                diff::Status::Added => println!("[{name}] Added:"),
+                diff::Status::Removed => println!("[{name}] Removed:"),
+            }
+            for entry in &pin.changes {
+                print!("{entry}");
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(&self, o: &RenameOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        pins.rename(&o.old, &o.new, o.keep_alias)?;
+        if o.keep_name {
+            pins.add_other_name(&o.new, &o.old)?;
+        }
+        self.write_pins(&pins)?;
+        if o.keep_name {
+            log::info!(
+                "Renamed '{}' to '{}', the old name keeps working as well.",
+                o.old,
+                o.new
+            );
+        } else if o.keep_alias {
+            log::info!(
+                "Renamed '{}' to '{}', the old name keeps working with a deprecation warning.",
+                o.old,
+                o.new
+            );
+        } else {
+            log::info!("Renamed '{}' to '{}'.", o.old, o.new);
+        }
+        Ok(())
+    }
+
+    async fn freeze(&self, o: &FreezeOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+
+        for name in o.names.iter() {
+            let pin = match pins.pins.get_mut(name) {
+                None => return Err(anyhow::anyhow!("Couldn't find the pin {} to freeze.", name)),
+                Some(pin) => pin,
+            };
+
+            if pin.is_frozen() {
+                log::warn!("The pin {} is frozen already", name);
+            }
+            pin.freeze();
+            log::info!("Froze pin {}", name);
+        }
+
+        self.write_pins(&pins)?;
+
+        Ok(())
+    }
+
+    async fn unfreeze(&self, o: &FreezeOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+
+        for name in o.names.iter() {
+            let pin = match pins.pins.get_mut(name) {
+                None => return Err(anyhow::anyhow!("Couldn't find the pin {} to thaw.", name)),
+                Some(pin) => pin,
+            };
+
+            if !pin.is_frozen() {
+                log::warn!("The pin {} is not frozen", name);
+            }
+            pin.unfreeze();
+
+            log::info!("Thawed pin {}", name);
+        }
+
+        self.write_pins(&pins)?;
+
+        Ok(())
+    }
 
-    /// Print debug messages.
-    #[arg(global = true, short = 'v', long = "verbose")]
-    pub verbose: bool,
+    fn require_approval(&self, o: &ApprovalOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
 
-    #[command(subcommand)]
-    command: Command,
-}
+        for name in &o.names {
+            let pin = pins
+                .pins
+                .get_mut(name)
+                .with_context(|| format!("Couldn't find the pin {}", name))?;
+            /* The current state is what the changes are approved against */
+            let approval = approval::Approval::new(pin, &o.by, None)?;
+            let metadata = pin.metadata_mut();
+            metadata.requires_approval = true;
+            metadata.approval = Some(approval);
+            log::info!("Changes to {} now require approval", name);
+        }
 
-fn write_diff(writer: &mut impl Write, name: &str, diff: &[diff::DiffEntry]) {
-    if diff.is_empty() {
-        writeln!(writer, "[{name}] No Changes").unwrap();
-    } else {
-        writeln!(writer, "[{name}] Changes:").unwrap();
-        for entry in diff {
-            write!(writer, "{entry}").unwrap();
+        self.write_pins(&pins)
+    }
+
+    fn approve(&self, o: &ApprovalOpts) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        let path = approval::proposals_path(&self.lock_file_path());
+        let mut proposals = approval::Proposals::read(&path)?;
+
+        for name in &o.names {
+            let pin = pins
+                .pins
+                .get_mut(name)
+                .with_context(|| format!("Couldn't find the pin {}", name))?;
+            let before = diff::Diff::properties(&*pin);
+            proposals.approve(name, pin, &o.by)?;
+            write_diff(
+                &mut stderr(),
+                name,
+                &diff::diff(&before, &diff::Diff::properties(&*pin)),
+            );
         }
+
+        self.write_pins(&pins)?;
+        proposals.write(&path)
     }
-}
 
-impl Opts {
-    fn read_pins(&self) -> Result<NixPins> {
-        let path = if let Some(lock_file) = self.lock_file.as_ref() {
-            lock_file.to_owned()
-        } else {
-            self.folder.join("sources.json")
-        };
-        let fh = std::io::BufReader::new(std::fs::File::open(&path).with_context(move || {
-            format!(
-                "Failed to open {}. You must initialize npins before you can show current pins.",
-                path.display()
-            )
-        })?);
-        NixPins::from_json_versioned(serde_json::from_reader(fh)?)
-            .context("Failed to deserialize sources.json")
+    /// Read what updates must pass, see [`UpdateGate`]
+    fn update_gate(
+        &self,
+        policy: Option<&std::path::Path>,
+        approved_by: Option<String>,
+        violations: Option<PathBuf>,
+        attest: Option<PathBuf>,
+        sign: bool,
+        signing_key: Option<&std::path::Path>,
+    ) -> Result<UpdateGate> {
+        let started_on = std::time::SystemTime::now();
+        Ok(UpdateGate {
+            policy: self.read_policy(policy)?,
+            context: policy::UpdateContext {
+                weekday: policy::Weekday::of(started_on),
+                approved_by,
+            },
+            violations,
+            signing_key: signing_key
+                .filter(|_| attest.is_some() || sign)
+                .map(read_signing_key)
+                .transpose()?,
+            attest,
+            sign,
+            started_on,
+        })
     }
 
-    fn write_pins(&self, pins: &NixPins) -> Result<()> {
-        let path = if let Some(lock_file) = &self.lock_file {
-            lock_file.to_owned()
-        } else {
-            if !self.folder.exists() {
-                std::fs::create_dir(&self.folder)?;
+    /// Check the updated pins against the policy, and write them unless it's a dry run
+    ///
+    /// Changes to pins that require approval are proposed instead of written. Both `npins
+    /// update` and `npins batch` land their updates through here.
+    fn land_update(
+        &self,
+        gate: &UpdateGate,
+        original: &NixPins,
+        pins: &mut NixPins,
+        dry_run: bool,
+    ) -> Result<()> {
+        if let Some(policy) = &gate.policy {
+            let violations = policy.evaluate_all(original, pins, &gate.context);
+            if let Some(path) = &gate.violations {
+                policy::write_violations(path, &violations)?;
             }
-            self.folder.join("sources.json")
-        };
-        let mut fh = std::fs::File::create(&path)
-            .with_context(move || format!("Failed to open {} for writing.", path.display()))?;
-        serde_json::to_writer_pretty(&mut fh, &pins.to_value_versioned())?;
-        fh.write_all(b"\n")?;
+            if !violations.is_empty() {
+                for violation in &violations {
+                    log::error!("{violation}");
+                }
+                anyhow::bail!(
+                    "{} policy violations, the lock file was not changed",
+                    violations.len()
+                );
+            }
+            if let Some(approved_by) = &gate.context.approved_by {
+                log::info!("Update approved by {approved_by}");
+            }
+        }
+        if !dry_run {
+            self.propose_changes(original, pins)?;
+            self.write_pins(pins)?;
+        }
         Ok(())
     }
 
-    async fn init(&self, o: &InitOpts) -> Result<()> {
-        log::info!("Welcome to npins!");
-
-        // Skip the entire default.nix and convenience creating folders bit in lockfile mode
-        if self.lock_file.is_none() {
-            let default_nix = DEFAULT_NIX;
-            if !self.folder.exists() {
-                log::info!("Creating `{}` directory", self.folder.display());
-                std::fs::create_dir(&self.folder).context("Failed to create npins folder")?;
-            }
-            log::info!("Writing default.nix");
-            let p = self.folder.join("default.nix");
-            let mut fh = std::fs::File::create(&p).context("Failed to create npins default.nix")?;
-            fh.write_all(default_nix.as_bytes())?;
+    /// Attest and sign the lock file written by [`land_update`](Self::land_update), if asked to
+    fn seal_update(&self, gate: &UpdateGate, pins: &NixPins) -> Result<()> {
+        if let (Some(path), Some(key)) = (&gate.attest, &gate.signing_key) {
+            self.attest(pins, path, key, gate.started_on)?;
         }
+        if let Some(key) = gate.signing_key.as_ref().filter(|_| gate.sign) {
+            self.sign_lock_file(key)?;
+        }
+        Ok(())
+    }
 
-        let sources_json = self.folder.join("sources.json");
-        let path = self.lock_file.as_ref().unwrap_or(&sources_json);
-        // Only create the pins if the file isn't there yet
-        if path.exists() {
-            log::info!(
-                "The file '{}' already exists; nothing to do.",
-                path.display()
+    /// Turn the changes to pins that require approval into proposals, leaving the pins as they were
+    fn propose_changes(&self, original: &NixPins, pins: &mut NixPins) -> Result<()> {
+        let path = approval::proposals_path(&self.lock_file_path());
+        let mut proposals = approval::Proposals::read(&path)?;
+        let mut proposed = false;
+        for (name, pin) in pins.pins.iter_mut() {
+            let Some(before) = original.pins.get(name) else {
+                continue;
+            };
+            if !before.requires_approval() || before == pin {
+                continue;
+            }
+            let after = std::mem::replace(pin, before.clone());
+            proposals
+                .proposals
+                .insert(name.clone(), approval::Proposal::new(before, after)?);
+            proposed = true;
+            log::warn!(
+                "[{name}] The change needs to be approved by somebody else, with `npins approve {name} --by <name>`"
             );
-            return Ok(());
         }
+        if proposed {
+            proposals.write(&path)?;
+        }
+        Ok(())
+    }
 
-        let initial_pins = if o.bare {
-            log::info!("Writing initial lock file (empty)");
-            NixPins::default()
-        } else {
-            log::info!(
-                "Writing initial lock file with nixpkgs entry (need to fetch latest commit first)"
-            );
-            let mut pin = NixPins::new_with_nixpkgs();
-            Self::update_one(pin.pins.get_mut("nixpkgs").unwrap(), UpdateStrategy::Full)
-                .await
-                .context("Failed to fetch initial nixpkgs entry")?;
-            pin
-        };
-        self.write_pins(&initial_pins)?;
+    fn niv_shim(&self, o: &NivShimOpts) -> Result<()> {
+        anyhow::ensure!(
+            self.lock_file.is_none(),
+            "The shim needs the default.nix of the npins directory, which doesn't exist in lockfile mode"
+        );
+        let shim = niv::shim(&o.path, &self.folder)?;
+        if let Some(parent) = o
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        if o.path.exists() {
+            log::info!("Replacing {}", o.path.display());
+        }
+        std::fs::write(&o.path, shim)
+            .with_context(|| format!("Failed to write {}", o.path.display()))?;
         log::info!(
-            "Successfully written initial files to '{}'.",
-            self.lock_file
-                .as_ref()
-                .unwrap_or(&self.folder.join("sources.json"))
-                .display()
+            "Written {}, which now provides the pins from {}",
+            o.path.display(),
+            self.folder.display()
         );
         Ok(())
     }
 
-    fn show(&self) -> Result<()> {
-        let pins = self.read_pins()?;
-        for (name, pin) in pins.pins.iter() {
-            println!("{}: ({})", name, pin.pin_type());
-            println!("{}", pin);
+    /// Import the entries of another tool's lock file, or only the entry `name`
+    async fn import_from(
+        &self,
+        importer: &dyn import::Importer,
+        name: Option<&str>,
+        upgrade: bool,
+    ) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        if upgrade {
+            log::info!("Note that all the imported entries will be updated so they won't necessarily point to the same commits as before!");
         }
 
-        Ok(())
-    }
+        async fn import(
+            name: &str,
+            importer: &dyn import::Importer,
+            npins: &mut NixPins,
+            upgrade: bool,
+        ) -> Result<()> {
+            anyhow::ensure!(
+                !npins.pins.contains_key(name),
+                "Pin '{}' exists in both files, this is a collision. Please delete the entry in one of the files.",
+                name
+            );
 
-    async fn add(&self, opts: &AddOpts) -> Result<()> {
-        let mut pins = self.read_pins()?;
-        let (name, mut pin) = opts.run()?;
-        if opts.frozen {
-            log::info!("Adding '{}' (frozen) …", name);
-        } else {
-            log::info!("Adding '{}' …", name);
+            let Some(mut pin) = importer
+                .import(name)
+                .await
+                .context("Could not convert pin to npins format")?
+            else {
+                return Ok(());
+            };
+            let strategy = if upgrade {
+                Some(UpdateStrategy::Full)
+            } else if !pin.has_version() {
+                log::warn!(
+                    "{} has no version for '{name}', it will be updated to the latest one",
+                    importer.source()
+                );
+                Some(UpdateStrategy::Full)
+            } else if !pin.has_hashes() {
+                /* E.g. Niv doesn't hash git checkouts, fetch the pinned version */
+                Some(UpdateStrategy::HashesOnly)
+            } else {
+                None
+            };
+            if let Some(strategy) = strategy {
+                engine::update_one(&mut pin, strategy, false)
+                    .await
+                    .context("Failed to update the pin")?;
+            }
+            npins.pins.insert(name.to_string(), pin);
+
+            Ok(())
         }
-        /* Fetch the latest version unless the user specified some */
-        let strategy = if pin.has_version() {
-            UpdateStrategy::HashesOnly
+
+        if let Some(name) = name {
+            anyhow::ensure!(
+                importer.names().iter().any(|entry| entry == name),
+                "Pin '{}' not found in {}",
+                name,
+                importer.source()
+            );
+            import(name, importer, &mut pins, upgrade).await?;
         } else {
-            UpdateStrategy::Full
-        };
-        Self::update_one(&mut pin, strategy)
-            .await
-            .context("Failed to fully initialize the pin")?;
-        pins.pins.insert(name.clone(), pin.clone());
-        if !opts.dry_run {
-            self.write_pins(&pins)?;
+            for name in importer.names() {
+                log::info!("Importing {}", name);
+                if let Err(err) = import(&name, importer, &mut pins, upgrade).await {
+                    log::error!("Failed to import pin '{}'", name);
+                    log::error!("{}", err);
+                    err.chain()
+                        .skip(1)
+                        .for_each(|cause| log::error!("\t{}", cause));
+                }
+            }
         }
 
-        println!("{}", pin);
+        self.write_pins(&pins)?;
+        log::info!("Done.");
         Ok(())
     }
 
-    async fn update_one(pin: &mut Pin, strategy: UpdateStrategy) -> Result<Vec<diff::DiffEntry>> {
-        /* Skip this for partial updates */
-        let diff1 = if strategy.should_update() {
-            pin.update().await?
-        } else {
-            vec![]
-        };
+    async fn import_niv(&self, o: &ImportOpts) -> Result<()> {
+        let niv = niv::NivSources::read(&o.path)?;
+        self.import_from(&niv, o.name.as_deref(), o.upgrade).await
+    }
 
-        /* We only need to fetch the hashes if the version changed, or if the flags indicate that we should */
-        let diff = if !diff1.is_empty() || strategy.must_fetch() {
-            let diff2 = pin.fetch().await?;
-            diff1.into_iter().chain(diff2.into_iter()).collect()
-        } else {
-            diff1
+    async fn import_flake(&self, o: &ImportFlakeOpts) -> Result<()> {
+        let flake = flake::FlakeLockFile::read(&o.path)?;
+        self.import_from(&flake, o.name.as_deref(), o.upgrade).await
+    }
+
+    async fn import_nvfetcher(&self, o: &ImportNvfetcherOpts) -> Result<()> {
+        let generated = match &o.generated {
+            Some(generated) => generated.clone(),
+            None => o
+                .path
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join("_sources/generated.json"),
         };
+        let nvfetcher = nvfetcher::Nvfetcher::read(&o.path, &generated)?;
+        self.import_from(&nvfetcher, o.name.as_deref(), o.upgrade)
+            .await
+    }
 
-        Ok(diff)
+    async fn import_gridlock(&self, o: &ImportGridlockOpts) -> Result<()> {
+        let gridlock = gridlock::Gridlock::read(&o.path)?;
+        self.import_from(&gridlock, o.name.as_deref(), o.upgrade)
+            .await
     }
 
-    async fn update(&self, opts: &UpdateOpts) -> Result<()> {
-        let mut pins = self.read_pins()?;
+    fn export_flake(&self, o: &ExportFlakeOpts) -> Result<()> {
+        let pins = self.read_pins()?;
+        if o.inputs {
+            print!("{}", flake::export_inputs(&pins)?);
+            return Ok(());
+        }
 
-        let mut selected_pins = BTreeSet::new();
-        for name in &opts.names {
-            if !selected_pins.insert(name) {
-                log::warn!("Ignoring duplicate pin: {name}")
+        let (lock, skipped) = flake::export_lock(&pins)?;
+        for name in skipped {
+            log::warn!("Skipping '{name}', it can't be a flake input");
+        }
+        if o.path.exists() {
+            log::info!("Replacing {}", o.path.display());
+        }
+        let mut contents = serde_json::to_vec_pretty(&lock)?;
+        contents.push(b'\n');
+        std::fs::write(&o.path, contents)
+            .with_context(|| format!("Failed to write {}", o.path.display()))?;
+        log::info!(
+            "Written {}, `npins export-flake --inputs` prints the matching inputs for the flake.nix",
+            o.path.display()
+        );
+        Ok(())
+    }
+
+    async fn check(&self, o: &CheckOpts) -> Result<()> {
+        let pins = self.read_pins()?;
+
+        for name in &o.names {
+            anyhow::ensure!(
+                pins.pins.contains_key(name),
+                "Could not find the pin '{}'",
+                name
+            );
+        }
+
+        let mut problems = 0;
+        if o.verify_signature {
+            let lock_file_path = self.lock_file_path();
+            let result = std::fs::read_to_string(signature::path_for(&lock_file_path))
+                .context("The lock file is not signed, run `npins sign`")
+                .and_then(|signatures| {
+                    signature::verify(
+                        &std::fs::read(&lock_file_path)?,
+                        &signatures,
+                        &o.public_keys,
+                    )
+                });
+            match result {
+                Ok(key) => log::info!("The lock file is signed by {key}"),
+                Err(err) => {
+                    log::error!("{err:#}");
+                    problems += 1;
+                },
             }
         }
-        selected_pins.retain(|&name| match pins.pins.get(name) {
-            Some(p) if !opts.update_frozen && p.is_frozen() => {
-                log::warn!("Ignoring frozen pin: {name}");
-                false
-            },
-            Some(_) => true,
-            None => {
-                log::warn!("Specified pin does not exist: {name}");
-                false
-            },
-        });
 
-        let length = if opts.names.is_empty() {
+        let mut credentials = false;
+        for (name, pin) in &pins.pins {
+            if !o.names.is_empty() && !o.names.contains(name) {
+                continue;
+            }
+            for finding in check::check_pin(pin) {
+                log::error!("[{name}] {finding}");
+                problems += 1;
+                credentials = true;
+            }
+            if let Err(err) = approval::check(pin) {
+                log::error!("[{name}] {err}");
+                problems += 1;
+            }
+        }
+
+        if credentials {
+            log::info!("{}", check::CREDENTIALS_ADVICE);
+        }
+
+        if o.eval {
+            /* Check the project's own `default.nix`, which may be outdated */
+            let default_nix = match &self.lock_file {
+                Some(_) => DEFAULT_NIX.to_owned(),
+                None => {
+                    let path = self.folder.join("default.nix");
+                    std::fs::read_to_string(&path)
+                        .with_context(|| format!("Failed to read {}", path.display()))?
+                },
+            };
+            log::info!("Evaluating the pins, this fetches them if necessary …");
+            match nix::nix_eval_pins(&default_nix, &self.lock_file_path()).await {
+                Ok(evaluated) => {
+                    for problem in nix::evaluation_problems(&pins, &evaluated) {
+                        log::error!("{problem}");
+                        problems += 1;
+                    }
+                    for (name, _) in evaluated.iter().filter(|(_, path)| path.is_none()) {
+                        log::warn!("[{name}] Not evaluated, it needs Nixpkgs but there is no <nixpkgs> in NIX_PATH");
+                    }
+                },
+                Err(err) => {
+                    log::error!("{err:#}");
+                    problems += 1;
+                },
+            }
+        }
+
+        if problems > 0 {
+            anyhow::bail!("Found {problems} problem(s)");
+        }
+        log::info!("No problems found.");
+        Ok(())
+    }
+
+    async fn verify(&self, o: &VerifyOpts) -> Result<()> {
+        let pins = self.read_pins()?;
+
+        for name in &o.names {
+            anyhow::ensure!(
+                pins.pins.contains_key(name),
+                "Could not find the pin '{}'",
+                name
+            );
+        }
+
+        let mut results: Vec<(&String, Result<Option<String>>)> = stream::iter(
             pins.pins
                 .iter()
-                .filter(|(_, pin)| (opts.update_frozen || !pin.is_frozen()))
-                .count()
+                .filter(|(name, _)| o.names.is_empty() || o.names.contains(name))
+                .map(|(name, pin)| async move {
+                    log::info!("Verifying {name} …");
+                    let result = if o.offline {
+                        Self::verify_offline(pin).await
+                    } else {
+                        Self::verify_one(pin, o.cross_check).await.map(|()| None)
+                    };
+                    (name, result)
+                }),
+        )
+        .buffer_unordered(o.max_concurrent_downloads)
+        .collect()
+        .await;
+        results.sort_by_key(|(name, _)| *name);
+
+        let mut failures = 0;
+        let mut skipped = 0;
+        let mut outcomes = BTreeMap::new();
+        for (name, result) in results {
+            let outcome = match result {
+                Ok(None) => {
+                    log::info!("[{name}] OK");
+                    output::Outcome::Ok { changes: vec![] }
+                },
+                Ok(Some(reason)) => {
+                    log::warn!("[{name}] Skipped, {reason}");
+                    skipped += 1;
+                    output::Outcome::Skipped { reason }
+                },
+                Err(err) => {
+                    log::error!("[{name}] {err:#}");
+                    failures += 1;
+                    output::Outcome::failed(&err)
+                },
+            };
+            outcomes.insert(name.clone(), outcome);
+        }
+        self.print_outcomes(&outcomes)?;
+        anyhow::ensure!(failures == 0, "{failures} pin(s) failed to verify");
+        if skipped > 0 {
+            log::info!("All other pins verified successfully, {skipped} pin(s) were skipped.");
         } else {
-            selected_pins.len()
-        };
-
-        let strategy = match (opts.partial, opts.full) {
-            (false, false) => UpdateStrategy::Normal,
-            (false, true) => UpdateStrategy::Full,
-            (true, false) => UpdateStrategy::HashesOnly,
-            (true, true) => panic!("partial and full are mutually exclusive"),
-        };
-
-        let animation = Animation::new(|stderr, finished| {
-            write!(stderr, "Updated {finished}/{length} pins").unwrap()
-        });
-        let animation = &animation;
+            log::info!("All pins verified successfully.");
+        }
+        Ok(())
+    }
 
-        let update_iter = pins
-            .pins
-            .iter_mut()
-            .filter(|(name, pin)| {
-                selected_pins.contains(name)
-                    || (opts.names.is_empty() && (opts.update_frozen || !pin.is_frozen()))
-            })
-            .map(|(name, pin)| async move {
-                animation.on_pin_start(&*name);
-                let diff = Self::update_one(pin, strategy).await?;
-                animation.on_pin_finish(&*name);
-                animation.write(|stderr| write_diff(stderr, name, &diff));
-                anyhow::Result::<_, anyhow::Error>::Ok((name, diff))
-            });
+    fn ensure_fetched(pin: &Pin) -> Result<()> {
+        anyhow::ensure!(
+            pin.has_hashes(),
+            "The pin has not been fetched yet, run `npins update` first"
+        );
+        anyhow::ensure!(
+            pin.metadata().state == PinState::Fetched,
+            "The pin has not been fetched since its version changed, run `npins fetch` first"
+        );
+        Ok(())
+    }
 
-        let mut has_diff = false;
-        stream::iter(update_iter)
-            .buffer_unordered(opts.max_concurrent_downloads)
-            .try_for_each(|(_name, diff)| {
-                has_diff |= !diff.is_empty();
-                future::ready(Ok(()))
-            })
-            .await
-            .inspect_err(|_| {
-                /* Flush the status line */
-                if length != 0 && stderr().is_terminal() {
-                    eprintln!();
-                }
-            })?;
+    /// Hash the pin's artifact in the local Nix store again, returns why it was skipped if it
+    /// couldn't be checked
+    async fn verify_offline(pin: &Pin) -> Result<Option<String>> {
+        Self::ensure_fetched(pin)?;
+        let Some(artifact) = explain::store_artifact(pin)? else {
+            return Ok(Some(format!(
+                "the store path of a {} depends on Nixpkgs",
+                pin.pin_type()
+            )));
+        };
+        let (path, present) = nix::store_path(&artifact).await?;
+        if !present {
+            return Ok(Some(format!("{} is not in the Nix store", path.display())));
+        }
+        let actual = nix::store_path_hash(&path, artifact.recursive).await?;
+        let expected = nix::hash_to_sri(&artifact.hash, "sha256")?;
+        anyhow::ensure!(
+            actual == expected,
+            "The contents of {} hash to {} instead of {}",
+            path.display(),
+            actual,
+            expected
+        );
+        Ok(None)
+    }
 
-        /* Flush the status line */
-        if length != 0 && stderr().is_terminal() {
-            eprintln!();
+    async fn verify_one(pin: &Pin, cross_check: bool) -> Result<()> {
+        Self::ensure_fetched(pin)?;
+
+        let diff = pin.clone().fetch().await?;
+        if !diff.is_empty() {
+            let changes: String = diff.iter().map(ToString::to_string).collect();
+            anyhow::bail!("Fetching again resulted in different hashes:\n{changes}");
         }
 
-        if !opts.dry_run {
-            if has_diff {
-                self.write_pins(&pins)?;
+        if cross_check {
+            match pin.cross_check().await? {
+                Some(check) => anyhow::ensure!(
+                    check.matches(),
+                    "Fetching via {} resulted in {} instead of {}",
+                    check.method,
+                    check.actual,
+                    check.expected
+                ),
+                None => log::warn!(
+                    "There is no independent way to fetch this {}, skipping the cross-check",
+                    pin.pin_type()
+                ),
             }
-            log::info!("Update successful.");
-        } else {
-            log::info!("Dry run successful.");
         }
 
+        if let Some(reason) = pin.yanked().await? {
+            anyhow::bail!("The pinned version was withdrawn upstream: {reason}");
+        }
+
+        if let Some(repository) = pin.repository() {
+            if let Some(renamed) = repository.renamed().await? {
+                log::warn!(
+                    "{} was renamed to {}. Run `npins update --follow-renames` to update the pin",
+                    repository.git_url()?,
+                    renamed.git_url()?
+                );
+            }
+        }
         Ok(())
     }
 
-    fn upgrade(&self) -> Result<()> {
-        if self.lock_file.is_none() {
-            anyhow::ensure!(
-                self.folder.exists(),
-                "Could not find npins folder at {}",
-                self.folder.display(),
-            );
-
-            let nix_path = self.folder.join("default.nix");
-            let nix_file = DEFAULT_NIX;
-            if std::fs::read_to_string(&nix_path)? == nix_file {
-                log::info!("default.nix is already up to date");
-            } else {
-                log::info!("Replacing default.nix with an up to date version");
-                std::fs::write(&nix_path, nix_file)
-                    .context("Failed to create npins default.nix")?;
+    fn maintenance(&self, o: &MaintenanceOpts) -> Result<()> {
+        let retention = maintenance::Retention {
+            keep: o.keep,
+            max_age: o
+                .max_age
+                .map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60)),
+            dry_run: o.dry_run,
+        };
+        let history = self.history();
+        let ranges = self.ranges();
+        let lock_file = self.lock_file_path();
+        let journal = journal::Journal::path_for(&lock_file);
+        let reports = [
+            (
+                "History",
+                history.dir(),
+                maintenance::history(&history, &retention)?,
+            ),
+            (
+                "Update journal",
+                journal.as_path(),
+                maintenance::journal(&lock_file, &retention)?,
+            ),
+            (
+                "Ranges",
+                ranges.as_path(),
+                maintenance::ranges(&ranges, &retention)?,
+            ),
+        ];
+
+        let mut problems = 0;
+        for (what, path, report) in reports {
+            for removed in &report.removed {
+                if o.dry_run {
+                    log::info!("Would remove {}", removed.display());
+                } else {
+                    log::info!("Removed {}", removed.display());
+                }
             }
+            for problem in &report.problems {
+                log::error!("{problem}");
+            }
+            problems += report.problems.len();
+            println!(
+                "{what} ({}): {} file(s), {:.1} KiB",
+                path.display(),
+                report.kept,
+                report.bytes as f64 / 1024.0
+            );
         }
+        anyhow::ensure!(problems == 0, "Found {problems} problem(s)");
+        Ok(())
+    }
 
-        log::info!("Upgrading lock file to the newest format version");
-        let sources_json = self.folder.join("sources.json");
-        let path = self.lock_file.as_ref().unwrap_or(&sources_json);
-        let fh = std::io::BufReader::new(std::fs::File::open(&path).with_context(move || {
-            format!(
-                "Failed to open {}. You must initialize npins first.",
-                path.display()
-            )
-        })?);
-
-        let pins_raw: serde_json::Map<String, serde_json::Value> = serde_json::from_reader(fh)
-            .context("lock file must be a valid JSON file with an object as top level")?;
-
-        let pins_raw_new = versions::upgrade(pins_raw.clone()).context("Upgrading failed")?;
-        let pins: NixPins = serde_json::from_value(pins_raw_new.clone())?;
-        if pins_raw_new != serde_json::Value::Object(pins_raw) {
-            log::info!(
-                "Done. It is recommended to at least run `npins update --partial` afterwards."
-            );
+    /// Where the lock files written by `npins range` go
+    fn ranges(&self) -> PathBuf {
+        match &self.lock_file {
+            Some(lock_file) => lock_file.with_extension("ranges"),
+            None => self.folder.join("ranges"),
         }
-        self.write_pins(&pins)
     }
 
-    fn remove(&self, r: &RemoveOpts) -> Result<()> {
+    async fn range(&self, o: &RangeOpts) -> Result<()> {
         let pins = self.read_pins()?;
+        let pin = pins
+            .pins
+            .get(&o.name)
+            .ok_or_else(|| anyhow::format_err!("Could not find the pin '{}'", o.name))?;
+        let repository = pin
+            .repository()
+            .context("Ranges are only supported for git pins")?;
+        let to = match &o.to {
+            Some(to) => to.clone(),
+            None => diff::Diff::properties(pin)
+                .into_iter()
+                .find(|(property, _)| property == "revision")
+                .map(|(_, revision)| revision)
+                .context("The pin has no revision yet, run `npins update` first or pass `--to`")?,
+        };
 
-        if !pins.pins.contains_key(&r.name) {
-            return Err(anyhow::anyhow!("Could not find the pin '{}'", r.name));
-        }
+        log::info!("Listing the revisions from {} to {} …", o.from, to);
+        let revisions = range::revisions(&repository.git_url()?, &o.from, &to).await?;
+        let sampled = range::sample(&revisions, o.sample);
+        log::info!(
+            "Pinning {} of the {} revisions …",
+            sampled.len(),
+            revisions.len()
+        );
 
-        let mut new_pins = pins.clone();
-        new_pins.pins.remove(&r.name);
+        let fetched: Vec<Result<Pin>> = stream::iter(sampled.iter().map(|revision| async move {
+            let mut pin = range::at_revision(pin, revision)?;
+            pin.fetch()
+                .await
+                .with_context(|| format!("Failed to fetch {revision}"))?;
+            Ok(pin)
+        }))
+        .buffered(o.max_concurrent_downloads)
+        .collect()
+        .await;
+        let mut range = NixPins::default();
+        for (step, pin) in range::step_names(fetched.len()).into_iter().zip(fetched) {
+            range.pins.insert(step, pin?);
+        }
 
-        self.write_pins(&new_pins)?;
-        log::info!("Successfully removed pin '{}'.", r.name);
+        let directory = self.ranges();
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+        let path = directory.join(format!("{}.json", o.name));
+        let mut contents = serde_json::to_vec_pretty(&range.to_value_versioned())?;
+        contents.push(b'\n');
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        log::info!(
+            "Wrote {}, pass it to `default.nix` as `input` to build any of the revisions",
+            path.display()
+        );
         Ok(())
     }
 
-    async fn freeze(&self, o: &FreezeOpts) -> Result<()> {
-        let mut pins = self.read_pins()?;
+    fn update_script(&self, o: &UpdateScriptOpts) -> Result<()> {
+        let pins = self.read_pins()?;
 
-        for name in o.names.iter() {
-            let pin = match pins.pins.get_mut(name) {
-                None => return Err(anyhow::anyhow!("Couldn't find the pin {} to freeze.", name)),
-                Some(pin) => pin,
-            };
+        for name in &o.names {
+            anyhow::ensure!(
+                pins.pins.contains_key(name),
+                "Could not find the pin '{}'",
+                name
+            );
+        }
+        let names = pins
+            .pins
+            .iter()
+            .filter(|(name, pin)| {
+                o.names.contains(name)
+                    || (o.names.is_empty() && !pin.is_frozen() && !pin.is_managed())
+            })
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
 
-            pin.freeze();
-            log::info!("Froze pin {}", name);
+        let npins_args = self.npins_args();
+        if o.shell {
+            print!("{}", update_script::shell_script(&npins_args, &names));
+        } else {
+            print!("{}", update_script::nix_snippets(&npins_args, &names));
         }
+        Ok(())
+    }
 
-        self.write_pins(&pins)?;
+    /// The global arguments that locate the lock file, for commands that run npins later on
+    fn npins_args(&self) -> Vec<String> {
+        match &self.lock_file {
+            Some(lock_file) => vec![
+                "--lock-file".to_owned(),
+                lock_file.to_string_lossy().into_owned(),
+            ],
+            None => vec![
+                "--directory".to_owned(),
+                self.folder.to_string_lossy().into_owned(),
+            ],
+        }
+    }
 
+    fn direnv_hook(&self, o: &DirenvHookOpts) -> Result<()> {
+        let lock_file = self.lock_file_path();
+        let config = self.config_path();
+        print!(
+            "{}",
+            env::direnv_hook(&self.npins_args(), &[&lock_file, &config], o.check)
+        );
         Ok(())
     }
 
-    async fn unfreeze(&self, o: &FreezeOpts) -> Result<()> {
-        let mut pins = self.read_pins()?;
+    fn env(&self, o: &EnvOpts) -> Result<()> {
+        let pins = self.read_pins()?;
 
-        for name in o.names.iter() {
-            let pin = match pins.pins.get_mut(name) {
-                None => return Err(anyhow::anyhow!("Couldn't find the pin {} to thaw.", name)),
-                Some(pin) => pin,
-            };
+        for name in &o.names {
+            anyhow::ensure!(
+                pins.pins.contains_key(name),
+                "Could not find the pin '{}'",
+                name
+            );
+        }
+        let selected = pins
+            .pins
+            .iter()
+            .filter(|(name, _)| o.names.is_empty() || o.names.contains(name));
+        print!("{}", env::render(selected, o.format)?);
+        Ok(())
+    }
 
-            pin.unfreeze();
+    async fn check_mirrors(&self, o: &MirrorsCheckOpts) -> Result<()> {
+        let pins = self.read_pins()?;
 
-            log::info!("Thawed pin {}", name);
+        for name in &o.names {
+            anyhow::ensure!(
+                pins.pins.contains_key(name),
+                "Could not find the pin '{}'",
+                name
+            );
         }
 
-        self.write_pins(&pins)?;
+        /* Pins may share URLs, e.g. several pins of the same repository */
+        let mut urls: BTreeMap<Url, Vec<&String>> = BTreeMap::new();
+        for (name, pin) in &pins.pins {
+            if !o.names.is_empty() && !o.names.contains(name) {
+                continue;
+            }
+            for url in mirrors::urls_to_check(pin, &o.mirrors) {
+                urls.entry(url).or_default().push(name);
+            }
+        }
+
+        let results: Vec<(&Url, mirrors::Status)> = stream::iter(urls.keys())
+            .map(|url| async move { (url, mirrors::check(url).await) })
+            .buffer_unordered(o.max_concurrent_requests)
+            .collect()
+            .await;
+        let results: BTreeMap<_, _> = results.into_iter().collect();
+
+        let mut problems = 0;
+        for (url, status) in results {
+            let names = urls[url]
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            match status {
+                mirrors::Status::Ok => log::debug!("[{names}] {url}: {status}"),
+                mirrors::Status::Redirect(_) => log::warn!("[{names}] {url} {status}"),
+                _ => {
+                    log::error!("[{names}] {url}: {status}");
+                    problems += 1;
+                },
+            }
+        }
 
+        anyhow::ensure!(problems == 0, "{problems} URL(s) are not reachable");
+        log::info!("Checked {} URL(s), all are reachable.", urls.len());
         Ok(())
     }
 
-    async fn import_niv(&self, o: &ImportOpts) -> Result<()> {
-        let mut pins = self.read_pins()?;
-
-        let niv: BTreeMap<String, serde_json::Value> =
-            serde_json::from_reader(std::fs::File::open(&o.path).context(anyhow::format_err!(
-                "Could not open sources.json at '{}'",
-                o.path.canonicalize().unwrap_or_else(|_| o.path.clone()).display()
-            ))?)
-            .context("Niv file is not a valid JSON dict")?;
-        log::info!("Note that all the imported entries will be updated so they won't necessarily point to the same commits as before!");
+    async fn audit(&self, o: &AuditOpts) -> Result<()> {
+        let pins = self.read_pins()?;
 
-        async fn import(
-            name: &str,
-            pin: Option<&serde_json::Value>,
-            npins: &mut NixPins,
-            niv: &BTreeMap<String, serde_json::Value>,
-        ) -> Result<()> {
-            let pin = pin
-                .or_else(|| niv.get(name))
-                .ok_or_else(|| anyhow::format_err!("Pin '{}' not found in sources.json", name))?;
+        for name in o
+            .names
+            .iter()
+            .chain(o.products.iter().map(|(name, _)| name))
+        {
             anyhow::ensure!(
-                !npins.pins.contains_key(name),
-                "Pin '{}' exists in both files, this is a collision. Please delete the entry in one of the files.",
+                pins.pins.contains_key(name),
+                "Could not find the pin '{}'",
                 name
             );
-
-            let pin: niv::NivPin = serde_json::from_value(pin.clone())
-                .context("Pin is either invalid, or we don't support it")?;
-            let mut pin: Pin = pin
-                .try_into()
-                .context("Could not convert pin to npins format")?;
-            pin.update().await.context("Failed to update the pin")?;
-            pin.fetch().await.context("Failed to update the pin")?;
-            npins.pins.insert(name.to_string(), pin);
-
-            Ok(())
         }
+        let products: BTreeMap<_, _> = o.products.iter().cloned().collect();
 
-        if let Some(name) = &o.name {
-            import(name, None, &mut pins, &niv).await?;
-        } else {
-            for (name, pin) in niv.iter() {
-                log::info!("Importing {}", name);
-                if let Err(err) = import(name, Some(pin), &mut pins, &niv).await {
-                    log::error!("Failed to import pin '{}'", name);
-                    log::error!("{}", err);
-                    err.chain()
-                        .skip(1)
-                        .for_each(|cause| log::error!("\t{}", cause));
-                }
+        let results: Vec<(&String, Result<Vec<audit::Finding>>)> = stream::iter(
+            pins.pins
+                .iter()
+                .filter(|(name, _)| o.names.is_empty() || o.names.contains(name)),
+        )
+        .map(|(name, pin)| {
+            let product = products.get(name).map(String::as_str);
+            async move { (name, audit::audit(name, pin, product).await) }
+        })
+        .buffer_unordered(o.max_concurrent_requests)
+        .collect()
+        .await;
+        let results: BTreeMap<_, _> = results.into_iter().collect();
+
+        let mut problems = 0;
+        for (name, result) in results {
+            match result {
+                Ok(findings) => {
+                    for finding in findings {
+                        if finding.is_error() {
+                            log::error!("[{name}] {finding}");
+                            problems += 1;
+                        } else {
+                            log::warn!("[{name}] {finding}");
+                        }
+                    }
+                },
+                Err(err) => {
+                    log::error!("[{name}] Failed to audit: {err:#}");
+                    problems += 1;
+                },
             }
         }
 
-        self.write_pins(&pins)?;
-        log::info!("Done.");
+        anyhow::ensure!(problems == 0, "Found {problems} problem(s)");
+        log::info!("No pins past their end of life found.");
         Ok(())
     }
 
-    async fn import_flake(&self, o: &ImportFlakeOpts) -> Result<()> {
-        let mut pins = self.read_pins()?;
+    fn provenance(&self, o: &ProvenanceOpts) -> Result<()> {
+        let pins = self.read_pins()?;
+        let pin = pins
+            .pins
+            .get(&o.name)
+            .ok_or_else(|| anyhow::format_err!("Could not find the pin '{}'", o.name))?;
 
-        let flake: serde_json::Value =
-            serde_json::from_reader(std::fs::File::open(&o.path).context(anyhow::format_err!(
-                "Could not open flake.lock at '{}'",
-                o.path.canonicalize().unwrap_or_else(|_| o.path.clone()).display()
-            ))?)
-            .context("Nix lock file is not a valid JSON object")?;
-        log::info!("Note that all the imported entries will be updated so they won't necessarily point to the same commits as before!");
-
-        let nodes: &serde_json::Map<String, serde_json::Value> = flake
-            .get("nodes")
-            .context("flake.lock missing key `nodes`")?
-            .as_object()
-            .context("`nodes` key does not contain an object")?;
-
-        let root_name = flake
-            .get("root")
-            .context("missing `root` key")?
-            .as_str()
-            .context("`root` key of flake lockfile is not a string")?;
-        let root = nodes
-            .get(root_name)
-            .context("flake.lock missing key `root`")?
-            .get("inputs")
-            .context("`root` key missing `inputs` key")?
-            .as_object()
-            .context("`root` key is not an object")?;
-
-        let inputs: BTreeMap<String, String> = root
-            .into_iter()
-            .map(|(key, value)| Some((key.to_string(), value.as_str()?.to_string())))
-            .collect::<Option<_>>()
-            .context(format!(
-                "root flake input `{root_name}` had unexpected format and could not be read"
-            ))?;
+        let provenance = provenance::check(pin)?;
+        println!("{}: ({})", o.name, pin.pin_type());
+        for (key, value) in diff::Diff::properties(provenance) {
+            println!("    {}: {}", key, value);
+        }
+        log::info!("The pin was not modified since.");
+        Ok(())
+    }
 
-        async fn import(
-            name: &str,
-            npins: &mut NixPins,
-            nodes: &serde_json::Map<String, serde_json::Value>,
-        ) -> Result<()> {
-            let pin = nodes
-                .get(name)
-                .ok_or_else(|| anyhow::format_err!("Pin '{}' not found in flake.lock", name))?;
-            anyhow::ensure!(
-                !npins.pins.contains_key(name),
-                "Pin '{}' exists in both files, this is a collision. Please delete the entry in one of the files.",
-                name
-            );
+    fn attest(
+        &self,
+        pins: &NixPins,
+        path: &std::path::Path,
+        key: &attestation::SigningKey,
+        started_on: std::time::SystemTime,
+    ) -> Result<()> {
+        let lock_file_path = self.lock_file_path();
+        let lock_file = std::fs::read(&lock_file_path)
+            .with_context(|| format!("Failed to read {}", lock_file_path.display()))?;
+        let name = lock_file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "sources.json".into());
+
+        let statement = attestation::statement(&name, &lock_file, pins, started_on)?;
+        let envelope = attestation::Envelope::sign(&statement, key)?;
+        let mut fh = std::fs::File::create(path)
+            .with_context(|| format!("Failed to open {} for writing.", path.display()))?;
+        serde_json::to_writer_pretty(&mut fh, &envelope)?;
+        fh.write_all(b"\n")?;
+        log::info!("Wrote the attestation to {}", path.display());
+        Ok(())
+    }
 
-            let pin: flake::FlakePin = serde_json::from_value(pin.clone())
-                .context("Pin is either invalid, or we don't support it")?;
+    /// Write the signature of the lock file, replacing any previous ones
+    fn sign_lock_file(&self, key: &attestation::SigningKey) -> Result<()> {
+        let lock_file_path = self.lock_file_path();
+        let lock_file = std::fs::read(&lock_file_path)
+            .with_context(|| format!("Failed to read {}", lock_file_path.display()))?;
+        let path = signature::path_for(&lock_file_path);
+        std::fs::write(&path, signature::sign(&lock_file, key)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        log::info!("Signed the lock file with {}", key.name);
+        Ok(())
+    }
 
-            if pin.is_indirect() {
-                log::info!("skipping indirect input {}", name);
-                return Ok(());
-            }
+    fn sign(&self, o: &SignOpts) -> Result<()> {
+        /* Make sure that it is a valid lock file, signing garbage helps no one */
+        self.read_pins()?;
+        self.sign_lock_file(&read_signing_key(&o.signing_key)?)
+    }
 
-            let mut pin: Pin = pin
-                .try_to_pin()
-                .await
-                .context("Could not convert pin to npins format")?;
+    fn verify_attestation(&self, o: &VerifyAttestationOpts) -> Result<()> {
+        let envelope: attestation::Envelope = serde_json::from_reader(std::io::BufReader::new(
+            std::fs::File::open(&o.path)
+                .with_context(|| format!("Failed to open {}", o.path.display()))?,
+        ))
+        .context("Failed to parse the attestation")?;
+        let statement = envelope.verify(&o.public_keys)?;
 
-            pin.update().await?;
-            pin.fetch().await.context("Failed to update the pin")?;
-            npins.pins.insert(name.to_string(), pin);
+        let lock_file_path = self.lock_file_path();
+        let lock_file = std::fs::read(&lock_file_path)
+            .with_context(|| format!("Failed to read {}", lock_file_path.display()))?;
+        attestation::check_subject(&statement, &lock_file)?;
 
-            Ok(())
+        log::info!(
+            "The attestation is valid and matches {}.",
+            lock_file_path.display()
+        );
+        Ok(())
+    }
+
+    async fn batch(&self, o: &BatchOpts) -> Result<()> {
+        /* Keep the connections open from one request to the next */
+        npins::share_client()?;
+        let mut stdout = std::io::stdout();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if std::io::stdin().read_line(&mut line)? == 0 {
+                break;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (id, command) = batch::parse(&line);
+            let result = match command {
+                Ok(command) => self.batch_request(o, command).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = &result {
+                log::debug!("Request {id} failed: {err:#}");
+            }
+            serde_json::to_writer(&mut stdout, &batch::Response::new(id, result))?;
+            writeln!(stdout)?;
+            stdout.flush()?;
         }
+        Ok(())
+    }
 
-        if let Some(name) = &o.name {
-            import(
-                inputs
-                    .get(name)
-                    .context(format!("flake input {name} not found"))?,
-                &mut pins,
-                nodes,
-            )
-            .await?;
-        } else {
-            for (name, input_name) in inputs.iter() {
-                log::info!("Importing {}", name);
-                if let Err(err) = import(input_name, &mut pins, nodes).await {
-                    log::error!("Failed to import pin '{}'", name);
-                    log::error!("{}", err);
-                    err.chain()
-                        .skip(1)
-                        .for_each(|cause| log::error!("\t{}", cause));
+    /// Handle a request of `npins batch`, the lock file is read anew for each one
+    async fn batch_request(
+        &self,
+        o: &BatchOpts,
+        command: batch::Command,
+    ) -> Result<serde_json::Value> {
+        /* The given pins along with their variants, all of which must exist */
+        let select = |pins: &NixPins, names: &[String]| -> Result<Vec<String>> {
+            if names.is_empty() {
+                return Ok(pins.pins.keys().cloned().collect());
+            }
+            let selected = pins.with_variants(names);
+            for name in &selected {
+                anyhow::ensure!(
+                    pins.pins.contains_key(name),
+                    "Could not find the pin '{}'",
+                    name
+                );
+            }
+            Ok(selected)
+        };
+
+        Ok(match command {
+            batch::Command::Get { names } => {
+                let pins = self.read_pins()?;
+                let selected = select(&pins, &names)?
+                    .into_iter()
+                    .map(|name| {
+                        let pin = pins.pins[&name].clone();
+                        (name, pin)
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                serde_json::to_value(selected)?
+            },
+            batch::Command::Add { args } => {
+                let opts = AddOpts::try_parse_from(std::iter::once("add".to_owned()).chain(args))?;
+                let (name, pin) = self.add_pin(&opts).await?;
+                serde_json::to_value(BTreeMap::from([(name, pin)]))?
+            },
+            batch::Command::Update {
+                names,
+                full,
+                approved_by,
+            } => {
+                let mut pins = self.read_pins()?;
+                let original = pins.clone();
+                let gate = self.update_gate(
+                    o.policy.as_deref(),
+                    approved_by,
+                    None,
+                    o.attest.clone(),
+                    o.sign,
+                    o.signing_key.as_deref(),
+                )?;
+                let strategy = if full {
+                    UpdateStrategy::Full
+                } else {
+                    UpdateStrategy::Normal
+                };
+                let selected = select(&pins, &names)?;
+
+                /* Pick up where an interrupted update left off, like `npins update` */
+                let (journal, done) = journal::Journal::open(&self.lock_file_path())?;
+                let done: BTreeMap<String, Pin> = done
+                    .into_iter()
+                    .filter(|(name, _)| pins.pins.contains_key(name))
+                    .collect();
+                let mut has_diff = false;
+                for (name, pin) in &done {
+                    has_diff |= pins.pins.insert(name.clone(), pin.clone()).as_ref() != Some(pin);
+                }
+                let jobs = selected
+                    .into_iter()
+                    .filter(|name| !done.contains_key(name))
+                    .map(|name| {
+                        let pin = pins.pins[&name].clone();
+                        engine::Job::new(name, pin, strategy)
+                    })
+                    .collect();
+
+                let mut outcomes = BTreeMap::new();
+                let mut events = std::pin::pin!(engine::update_all(jobs, Default::default()));
+                while let Some(event) = events.next().await {
+                    let (name, outcome) = match event {
+                        engine::PinEvent::Fetched {
+                            name, pin, diff, ..
+                        } => {
+                            journal.record(&name, &pin)?;
+                            has_diff |= !diff.is_empty();
+                            pins.pins.insert(name.clone(), pin);
+                            (name, output::Outcome::Ok { changes: diff })
+                        },
+                        engine::PinEvent::Failed { name, error, .. } => {
+                            (name, output::Outcome::failed(&error))
+                        },
+                        engine::PinEvent::Skipped { name, reason } => {
+                            let reason = match reason {
+                                engine::SkipReason::Frozen => "frozen",
+                                engine::SkipReason::Managed => "managed by the registry",
+                            };
+                            (
+                                name,
+                                output::Outcome::Skipped {
+                                    reason: reason.into(),
+                                },
+                            )
+                        },
+                        engine::PinEvent::Started { .. } | engine::PinEvent::Resolved { .. } => {
+                            continue
+                        },
+                    };
+                    outcomes.insert(name, outcome);
+                }
+                if has_diff {
+                    self.land_update(&gate, &original, &mut pins, false)?;
+                    self.seal_update(&gate, &pins)?;
                 }
+                journal.finish()?;
+                serde_json::to_value(outcomes)?
+            },
+            batch::Command::Remove { names } => {
+                anyhow::ensure!(!names.is_empty(), "No pins to remove given");
+                let mut pins = self.read_pins()?;
+                let selected = select(&pins, &names)?;
+                for name in &selected {
+                    pins.pins.remove(name);
+                }
+                self.write_pins(&pins)?;
+                serde_json::to_value(selected)?
+            },
+        })
+    }
+
+    fn clean_temp(&self, o: &CleanTempOpts) -> Result<()> {
+        let root = tempdir::root();
+        let stale = tempdir::stale(&root)?;
+        if stale.is_empty() {
+            log::info!("Nothing was left behind in {}.", root.display());
+        }
+        for directory in &stale {
+            if o.dry_run {
+                println!("{}", directory.display());
+                continue;
             }
+            std::fs::remove_dir_all(directory)
+                .with_context(|| format!("Failed to remove {}", directory.display()))?;
+            log::info!("Removed {}", directory.display());
         }
+        Ok(())
+    }
 
-        self.write_pins(&pins)?;
-        log::info!("Done.");
+    fn migrate_config(&self, o: &MigrateConfigOpts) -> Result<()> {
+        let path = self.config_path();
+        let file = config::Config::read(&path)?.unwrap_or_default();
+        let (config, legacy) = file.clone().with_legacy(config::environment);
+        if legacy.is_empty() {
+            log::info!("None of the deprecated environment variables are set, nothing to migrate.");
+            return Ok(());
+        }
+
+        let contents = config.to_toml()?;
+        if o.dry_run {
+            print!("{contents}");
+            return Ok(());
+        }
+        if config != file {
+            if self.lock_file.is_none() && !self.folder.exists() {
+                std::fs::create_dir(&self.folder)?;
+            }
+            std::fs::write(&path, contents)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        for legacy in &legacy {
+            if let Some(overridden) = legacy.overrides() {
+                log::warn!(
+                    "{} replaced the value {} in {}",
+                    legacy.variable,
+                    overridden,
+                    path.display()
+                );
+            }
+        }
+        let variables = legacy
+            .iter()
+            .map(|legacy| legacy.variable)
+            .collect::<Vec<_>>();
+        log::info!(
+            "Wrote the settings to {}, {} can be unset now.",
+            path.display(),
+            variables.join(" and ")
+        );
         Ok(())
     }
 
@@ -1010,17 +5064,21 @@ impl Opts {
         /* Although redundant, we still parse the lock file here for better error messages */
         self.read_pins()?;
 
-        let path = self
-            .lock_file
-            .to_owned()
-            .unwrap_or(self.folder.join("sources.json"));
+        let path = self.lock_file_path();
         let out_path = nix::nix_eval_pin(&path, &o.name)
             .await
             .context("Could not evaluate pin")?;
-        /* note(piegames): HMU if you ever find yourself using npins on Windows */
-        use std::os::unix::ffi::OsStrExt;
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            std::io::stdout()
+                .write_all(out_path.as_path().as_os_str().as_bytes())
+                .unwrap();
+        }
+        /* Store paths are plain ASCII, so nothing is lost here */
+        #[cfg(not(unix))]
         std::io::stdout()
-            .write_all(out_path.as_path().as_os_str().as_bytes())
+            .write_all(out_path.as_path().to_string_lossy().as_bytes())
             .unwrap();
         Ok(())
     }
@@ -1029,18 +5087,64 @@ impl Opts {
         if self.lock_file.is_some() && &*self.folder != std::path::Path::new("npins") {
             anyhow::bail!("If --lock-file is set, --directory will be ignored and thus should not be set to a non-default value (which is \"npins\")");
         }
+        if let Some(hasher) = self.hasher {
+            anyhow::ensure!(
+                hasher == nix::Hasher::Nix || self.prefetch_on.is_none(),
+                "Hashes can only be computed on another machine with Nix, not with --hasher {hasher}"
+            );
+            nix::set_hasher(hasher);
+        }
+        if let Some(builder) = &self.prefetch_on {
+            remote::set_builder(builder.clone());
+        }
+        if !matches!(self.command, Command::MigrateConfig(_)) {
+            config::set(config::Config::read(&self.config_path())?.unwrap_or_default());
+        }
         match &self.command {
             Command::Init(o) => self.init(o).await?,
-            Command::Show => self.show()?,
+            Command::Show(o) => self.show(o)?,
             Command::Add(a) => self.add(a).await?,
             Command::Update(o) => self.update(o).await?,
+            Command::Pin(o) => self.pin(o).await?,
+            Command::Fetch(o) => self.fetch(o).await?,
+            Command::Outdated(o) => self.outdated(o).await?,
             Command::Upgrade => self.upgrade()?,
             Command::Remove(r) => self.remove(r)?,
+            Command::Edit(o) => self.edit(o).await?,
+            Command::Annotate(o) => self.annotate(o)?,
+            Command::Rename(o) => self.rename(o)?,
+            Command::Diff(o) => self.diff(o)?,
+            Command::Rollback(o) => self.rollback(o)?,
+            Command::Maintenance(o) => self.maintenance(o)?,
             Command::ImportNiv(o) => self.import_niv(o).await?,
+            Command::NivShim(o) => self.niv_shim(o)?,
             Command::ImportFlake(o) => self.import_flake(o).await?,
+            Command::ImportNvfetcher(o) => self.import_nvfetcher(o).await?,
+            Command::ImportGridlock(o) => self.import_gridlock(o).await?,
+            Command::ExportFlake(o) => self.export_flake(o)?,
+            Command::Sync(o) => self.sync(o).await?,
+            Command::Export(o) => self.export(o)?,
+            Command::ImportPin(o) => self.import_pin(o).await?,
             Command::Freeze(o) => self.freeze(o).await?,
             Command::Unfreeze(o) => self.unfreeze(o).await?,
+            Command::RequireApproval(o) => self.require_approval(o)?,
+            Command::Approve(o) => self.approve(o)?,
             Command::GetPath(o) => self.get_path(o).await?,
+            Command::ExplainNix(o) => self.explain_nix(o)?,
+            Command::Check(o) => self.check(o).await?,
+            Command::Verify(o) => self.verify(o).await?,
+            Command::Range(o) => self.range(o).await?,
+            Command::Provenance(o) => self.provenance(o)?,
+            Command::VerifyAttestation(o) => self.verify_attestation(o)?,
+            Command::Sign(o) => self.sign(o)?,
+            Command::UpdateScript(o) => self.update_script(o)?,
+            Command::Env(o) => self.env(o)?,
+            Command::DirenvHook(o) => self.direnv_hook(o)?,
+            Command::Mirrors(MirrorsCommand::Check(o)) => self.check_mirrors(o).await?,
+            Command::Audit(o) => self.audit(o).await?,
+            Command::Batch(o) => self.batch(o).await?,
+            Command::MigrateConfig(o) => self.migrate_config(o)?,
+            Command::CleanTemp(o) => self.clean_temp(o)?,
         };
 
         Ok(())
@@ -1142,6 +5246,106 @@ async fn main() -> Result<()> {
         .format_target(false)
         .init();
 
-    opts.run().await?;
-    Ok(())
+    interrupt::listen();
+    let result = tokio::select! {
+        result = opts.run() => result,
+        () = interrupt::aborted() => Err(interrupt::Interrupted.into()),
+    };
+    tempdir::clean_up();
+    match result {
+        Err(err) if err.is::<interrupt::Interrupted>() => {
+            log::error!("{err}");
+            std::process::exit(130);
+        },
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let opts = Opts::try_parse_from(["npins", "export", "x", "-o", "f"]).unwrap();
+        assert_eq!(opts.output, output::Format::Text);
+        let Command::Export(export) = opts.command else {
+            panic!("expected export");
+        };
+        assert_eq!(export.to, Some(PathBuf::from("f")));
+
+        let opts = Opts::try_parse_from(["npins", "show", "--output", "json"]).unwrap();
+        assert_eq!(opts.output, output::Format::Json);
+    }
+
+    #[test]
+    fn test_parse_describe() {
+        let opts =
+            Opts::try_parse_from(["npins", "annotate", "x", "--describe", "Our fork"]).unwrap();
+        let Command::Annotate(annotate) = opts.command else {
+            panic!("expected annotate");
+        };
+        assert_eq!(annotate.description.as_deref(), Some("Our fork"));
+        assert!(Opts::try_parse_from(["npins", "annotate", "x", "Our fork"]).is_err());
+    }
+
+    /// Needs `git`
+    #[tokio::test]
+    async fn test_batch_update_proposes_changes() {
+        let root = std::env::temp_dir().join(format!("npins-test-batch-{}", std::process::id()));
+        let repo = root.join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args([
+                    "-c",
+                    "user.name=npins",
+                    "-c",
+                    "user.email=npins@example.org",
+                ])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q", "-b", "main"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "first"]);
+
+        nix::set_hasher(nix::Hasher::Builtin);
+        let lock_file = root.join("sources.json");
+        let opts =
+            Opts::try_parse_from(["npins", "--lock-file", lock_file.to_str().unwrap(), "batch"])
+                .unwrap();
+        let Command::Batch(batch) = &opts.command else {
+            panic!("expected batch");
+        };
+        opts.write_pins(&NixPins::default()).unwrap();
+        let url = format!("file://{}", repo.display());
+        let add = batch::Command::Add {
+            args: ["git", &url, "-b", "main", "--name", "x"]
+                .map(String::from)
+                .to_vec(),
+        };
+        opts.batch_request(batch, add).await.unwrap();
+        opts.require_approval(&ApprovalOpts {
+            names: vec!["x".into()],
+            by: "alice".into(),
+        })
+        .unwrap();
+        let before = opts.read_pins().unwrap();
+
+        git(&["commit", "-q", "--allow-empty", "-m", "second"]);
+        let update = batch::Command::Update {
+            names: vec![],
+            full: false,
+            approved_by: None,
+        };
+        opts.batch_request(batch, update).await.unwrap();
+        assert_eq!(opts.read_pins().unwrap(), before);
+        let proposals = approval::Proposals::read(&approval::proposals_path(&lock_file)).unwrap();
+        assert!(proposals.proposals.contains_key("x"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,228 @@
+//! Settings in `npins.toml`, next to the lock file
+//!
+//! So far, this is where a GitHub Enterprise instance is configured:
+//!
+//! ```toml
+//! [github]
+//! host = "https://github.example.org"
+//! api-host = "https://github.example.org/api/v3"
+//! ```
+//!
+//! And the checksums of npins' own files per subsystem, see [`crate::checksum`].
+//!
+//! These used to be the environment variables `NPINS_GITHUB_HOST` and `NPINS_GITHUB_API_HOST`.
+//! They still work, and take precedence over the file so that existing automation doesn't change
+//! its behavior, but they warn once. `npins migrate-config` moves them into the file.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, skip_serializing_if = "GitHubConfig::is_default")]
+    pub github: GitHubConfig,
+    #[serde(default, skip_serializing_if = "ChecksumConfig::is_default")]
+    pub journal: ChecksumConfig,
+    #[serde(default, skip_serializing_if = "ChecksumConfig::is_default")]
+    pub provenance: ChecksumConfig,
+    #[serde(default, skip_serializing_if = "ChecksumConfig::is_default")]
+    pub attestation: ChecksumConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct GitHubConfig {
+    /// Where the repositories and their web pages are, `https://github.com` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Where the REST API is, `https://api.github.com` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_host: Option<String>,
+}
+
+impl GitHubConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ChecksumConfig {
+    /// The algorithm of the checksums the subsystem writes
+    #[serde(default)]
+    pub checksum: crate::checksum::Algorithm,
+}
+
+impl ChecksumConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Where a setting is in the configuration
+type Setting = fn(&mut Config) -> &mut Option<String>;
+
+/// The environment variables that used to configure npins, and the settings they map onto
+const LEGACY_VARIABLES: [(&str, Setting); 2] = [
+    ("NPINS_GITHUB_HOST", |config| &mut config.github.host),
+    ("NPINS_GITHUB_API_HOST", |config| {
+        &mut config.github.api_host
+    }),
+];
+
+/// A legacy environment variable that is set, see [`Config::with_legacy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Legacy {
+    pub variable: &'static str,
+    pub value: String,
+    /// The value from the file, which the variable takes precedence over
+    pub file: Option<String>,
+}
+
+impl Legacy {
+    /// The value from the file, if the variable changes it
+    pub fn overrides(&self) -> Option<&str> {
+        self.file.as_deref().filter(|file| *file != self.value)
+    }
+}
+
+impl Config {
+    /// Read a configuration file, `None` if it doesn't exist
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            },
+        };
+        toml::from_str(&contents)
+            .with_context(|| format!("{} is not a valid configuration", path.display()))
+            .map(Some)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// Apply the legacy environment variables that `var` finds, returning those that are set
+    pub fn with_legacy(mut self, var: impl Fn(&str) -> Option<String>) -> (Self, Vec<Legacy>) {
+        let mut legacy = Vec::new();
+        for (variable, setting) in LEGACY_VARIABLES {
+            let Some(value) = var(variable) else {
+                continue;
+            };
+            let file = setting(&mut self).replace(value.clone());
+            legacy.push(Legacy {
+                variable,
+                value,
+                file,
+            });
+        }
+        (self, legacy)
+    }
+}
+
+/// The value of an environment variable, if it is set to something
+pub fn environment(variable: &str) -> Option<String> {
+    std::env::var(variable)
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The configuration from the environment, warning about legacy variables
+fn with_environment(file: Config) -> Config {
+    let (config, legacy) = file.with_legacy(environment);
+    for legacy in legacy {
+        let variable = legacy.variable;
+        match (&legacy.file, legacy.overrides()) {
+            (_, Some(overridden)) => log::warn!(
+                "{variable} is deprecated, and overrides the value {overridden} from npins.toml. Unset it once npins.toml is right"
+            ),
+            (Some(_), None) => {
+                log::warn!("{variable} is deprecated, and already set in npins.toml. Unset it")
+            },
+            (None, None) => log::warn!(
+                "{variable} is deprecated, `npins migrate-config` moves it into npins.toml"
+            ),
+        }
+    }
+    config
+}
+
+/// Use the configuration from `npins.toml` from now on, must be called before [`get`]
+pub fn set(file: Config) {
+    CONFIG
+        .set(with_environment(file))
+        .expect("The configuration is already set");
+}
+
+/// The configuration in effect, without a file if [`set`] wasn't called
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| with_environment(Config::default()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_legacy_variables() {
+        let file: Config = toml::from_str(
+            r#"
+            [github]
+            host = "https://github.example.org"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.github.api_host, None);
+        assert!(toml::from_str::<Config>("[gitlab]\nhost = \"x\"").is_err());
+        assert_eq!(
+            toml::from_str::<Config>("[journal]\nchecksum = \"blake3\"")
+                .unwrap()
+                .journal
+                .checksum,
+            crate::checksum::Algorithm::Blake3
+        );
+        let checksums: Config = toml::from_str(
+            "[provenance]\nchecksum = \"blake3\"\n[attestation]\nchecksum = \"sha256\"",
+        )
+        .unwrap();
+        assert_eq!(
+            checksums.provenance.checksum,
+            crate::checksum::Algorithm::Blake3
+        );
+        assert!(checksums.attestation.checksum.is_default());
+
+        /* Nothing set */
+        let (config, legacy) = file.clone().with_legacy(|_| None);
+        assert_eq!(config, file);
+        assert!(legacy.is_empty());
+
+        /* The variables take precedence, and say so when they change something */
+        let (config, legacy) = file.with_legacy(|variable| {
+            Some(match variable {
+                "NPINS_GITHUB_HOST" => "https://ghe.example.org".into(),
+                _ => "https://ghe.example.org/api/v3".into(),
+            })
+        });
+        assert_eq!(
+            config.github.host.as_deref(),
+            Some("https://ghe.example.org")
+        );
+        assert_eq!(legacy.len(), 2);
+        assert_eq!(legacy[0].variable, "NPINS_GITHUB_HOST");
+        assert_eq!(legacy[0].overrides(), Some("https://github.example.org"));
+        assert_eq!(legacy[1].overrides(), None);
+        assert_eq!(
+            config.to_toml().unwrap(),
+            "[github]\nhost = \"https://ghe.example.org\"\napi-host = \"https://ghe.example.org/api/v3\"\n"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Pin container images from OCI registries
+//!
+//! An image tag is resolved to the digest of its manifest (or of its image index, for multi-platform
+//! images) via the [registry API](https://github.com/opencontainers/distribution-spec/blob/main/spec.md).
+//! The hash is computed by `nix-prefetch-docker`, and is the one `dockerTools.pullImage` expects.
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ContainerPin {
+    /// The image as one would pass it to `docker pull`, e.g. `nginx` or `ghcr.io/owner/image`
+    pub image_name: String,
+    /// The tag to follow, e.g. `latest` or `1.27`
+    pub image_tag: String,
+    /// The platform to pull from multi-platform images, as in `dockerTools.pullImage`
+    pub os: String,
+    pub arch: String,
+}
+
+impl diff::Diff for ContainerPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![
+            ("image_name".into(), self.image_name.clone()),
+            ("image_tag".into(), self.image_tag.clone()),
+            ("os".into(), self.os.clone()),
+            ("arch".into(), self.arch.clone()),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ContainerVersion {
+    /// The digest the tag pointed to, e.g. `sha256:…`
+    pub image_digest: String,
+}
+
+impl diff::Diff for ContainerVersion {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![("image_digest".into(), self.image_digest.clone())]
+    }
+}
+
+/// An image name split into the registry to talk to and the repository on it
+#[derive(Debug, PartialEq, Eq)]
+struct Reference {
+    /// Host name (and port) of the registry API
+    registry: String,
+    /// The repository on the registry, e.g. `library/nginx`
+    repository: String,
+}
+
+impl Reference {
+    /// Parse an image name the way `docker pull` does
+    ///
+    /// The first component is a registry if it looks like a host name, otherwise the image is on
+    /// Docker Hub, where official images live under `library/`.
+    fn parse(image_name: &str) -> Result<Self> {
+        anyhow::ensure!(
+            !image_name.contains(['@', ' ']) && !image_name.is_empty(),
+            "Invalid image name '{image_name}', it must not contain a tag or digest"
+        );
+        let (registry, repository) = match image_name.split_once('/') {
+            Some((host, rest)) if host.contains(['.', ':']) || host == "localhost" => {
+                (host, rest.to_owned())
+            },
+            Some(_) => ("docker.io", image_name.to_owned()),
+            None => ("docker.io", format!("library/{image_name}")),
+        };
+        anyhow::ensure!(
+            !repository.contains(':'),
+            "Invalid image name '{image_name}', pass the tag separately"
+        );
+        let registry = match registry {
+            /* Docker Hub's API lives on a different host */
+            "docker.io" | "index.docker.io" => "registry-1.docker.io",
+            registry => registry,
+        };
+        Ok(Self {
+            registry: registry.to_owned(),
+            repository,
+        })
+    }
+
+    /// `https://<registry>/v2/<repository>/manifests/<tag>`
+    fn manifest_url(&self, tag: &str) -> Result<Url> {
+        let mut url: Url = format!("https://{}/v2/", self.registry)
+            .parse()
+            .with_context(|| format!("Invalid registry '{}'", self.registry))?;
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(self.repository.split('/'))
+            .push("manifests")
+            .push(tag);
+        Ok(url)
+    }
+}
+
+/// The media types we accept for manifests, most importantly including multi-platform indices
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+/// Parse the parameters of a `WWW-Authenticate: Bearer realm="…",service="…",scope="…"` challenge
+fn parse_bearer_challenge(header: &str) -> Option<Vec<(String, String)>> {
+    let mut rest = header.trim().strip_prefix("Bearer ")?.trim();
+    let mut params = Vec::new();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, remainder) = quoted.split_once('"')?;
+                (value, remainder)
+            },
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        params.push((key.trim().to_owned(), value.to_owned()));
+        rest = remainder.trim_start_matches([',', ' ']);
+    }
+    Some(params)
+}
+
+impl ContainerPin {
+    /// Get an anonymous pull token, as registries require even for public images
+    async fn token(&self, challenge: &str) -> Result<String> {
+        let params = parse_bearer_challenge(challenge)
+            .with_context(|| format!("Unsupported authentication challenge '{challenge}'"))?;
+        let realm = params
+            .iter()
+            .find(|(key, _)| key == "realm")
+            .context("The authentication challenge has no realm")?;
+        let mut url: Url = realm
+            .1
+            .parse()
+            .context("The authentication challenge has an invalid realm")?;
+        url.query_pairs_mut().extend_pairs(
+            params
+                .iter()
+                .filter(|(key, _)| key == "service" || key == "scope"),
+        );
+
+        #[derive(Debug, Deserialize)]
+        struct Token {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        let token: Token = get_and_deserialize(url)
+            .await
+            .context("Couldn't get a token for the registry")?;
+        token
+            .token
+            .or(token.access_token)
+            .context("The registry didn't hand out a token")
+    }
+
+    /// Resolve the tag to a digest
+    async fn resolve(&self, reference: &Reference) -> Result<String> {
+        let url = reference.manifest_url(&self.image_tag)?;
+        provenance::record_endpoint(&url);
+        /* HEAD requests don't count towards Docker Hub's rate limit */
+        let request = || -> Result<_> {
+            Ok(http_request(reqwest::Method::HEAD, url.clone())?
+                .header(reqwest::header::ACCEPT, MANIFEST_TYPES))
+        };
+        let mut response = request()?.send_throttled().await?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            let challenge = response
+                .headers()
+                .get(reqwest::header::WWW_AUTHENTICATE)
+                .and_then(|value| value.to_str().ok())
+                .context("The registry requires authentication, but didn't say how")?
+                .to_owned();
+            let token = self.token(&challenge).await?;
+            response = request()?.bearer_auth(token).send_throttled().await?;
+        }
+        let response = response
+            .error_for_status()
+            .with_context(|| format!("Couldn't find {}:{}", self.image_name, self.image_tag))?;
+        let digest = response
+            .headers()
+            .get("Docker-Content-Digest")
+            .and_then(|value| value.to_str().ok())
+            .context("The registry didn't report the digest of the manifest")?;
+        Ok(digest.to_owned())
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for ContainerPin {
+    type Version = ContainerVersion;
+    type Hashes = GenericHash;
+
+    async fn update(&self, _old: Option<&ContainerVersion>) -> Result<ContainerVersion> {
+        /* Digests have no order, so there's no monotonicity to check */
+        let reference = Reference::parse(&self.image_name)?;
+        Ok(ContainerVersion {
+            image_digest: self.resolve(&reference).await?,
+        })
+    }
+
+    async fn fetch(&self, version: &ContainerVersion) -> Result<GenericHash> {
+        let hash = nix::nix_prefetch_docker(
+            &self.image_name,
+            &version.image_digest,
+            &self.image_tag,
+            &self.os,
+            &self.arch,
+        )
+        .await?;
+        Ok(GenericHash {
+            hash,
+            archive: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reference() {
+        let parse = |name| Reference::parse(name).unwrap();
+        assert_eq!(
+            parse("nginx"),
+            Reference {
+                registry: "registry-1.docker.io".into(),
+                repository: "library/nginx".into(),
+            }
+        );
+        assert_eq!(
+            parse("grafana/grafana"),
+            Reference {
+                registry: "registry-1.docker.io".into(),
+                repository: "grafana/grafana".into(),
+            }
+        );
+        assert_eq!(
+            parse("ghcr.io/nixos/nix"),
+            Reference {
+                registry: "ghcr.io".into(),
+                repository: "nixos/nix".into(),
+            }
+        );
+        assert_eq!(
+            parse("localhost:5000/a/b/c")
+                .manifest_url("v1")
+                .unwrap()
+                .as_str(),
+            "https://localhost:5000/v2/a/b/c/manifests/v1"
+        );
+        assert!(Reference::parse("nginx:latest").is_err());
+        assert!(Reference::parse("nginx@sha256:abc").is_err());
+    }
+
+    #[test]
+    fn test_bearer_challenge() {
+        assert_eq!(
+            parse_bearer_challenge(
+                r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull""#
+            )
+            .unwrap(),
+            vec![
+                ("realm".into(), "https://auth.docker.io/token".into()),
+                ("service".into(), "registry.docker.io".into()),
+                ("scope".into(), "repository:library/nginx:pull".into()),
+            ]
+        );
+        assert_eq!(
+            parse_bearer_challenge("Bearer realm=https://ghcr.io/token, service=ghcr.io").unwrap(),
+            vec![
+                ("realm".into(), "https://ghcr.io/token".into()),
+                ("service".into(), "ghcr.io".into()),
+            ]
+        );
+        assert!(parse_bearer_challenge(r#"Basic realm="x""#).is_none());
+    }
+}
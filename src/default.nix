@@ -65,14 +65,20 @@ let
       fetchers =
         if pkgs == null then
           {
-            inherit (builtins) fetchTarball fetchurl;
+            inherit (builtins) fetchurl;
+            # The builtin recognizes archives by their contents, it doesn't need the extension
+            fetchTarball = args: builtins.fetchTarball (removeAttrs args [ "extension" ]);
             # For some fucking reason, fetchGit has a different signature than the other builtin fetchers …
             fetchGit = args: (builtins.fetchGit args).outPath;
+            # There is no builtin to fetch container images
+            pullImage = _: throw "Container image pins need Nixpkgs, pass it as `pkgs` argument";
+            fetchsvn = _: throw "Subversion pins need Nixpkgs, pass it as `pkgs` argument";
           }
         else
           {
             fetchTarball = pkgs.fetchzip;
-            inherit (pkgs) fetchurl;
+            inherit (pkgs) fetchurl fetchsvn;
+            inherit (pkgs.dockerTools) pullImage;
             fetchGit =
               {
                 url,
@@ -80,6 +86,8 @@ let
                 rev,
                 name,
                 narHash,
+                # `fetchgit` fetches the revision itself
+                ref ? null,
               }:
               pkgs.fetchgit {
                 inherit url rev name;
@@ -90,20 +98,70 @@ let
 
       # Dispatch to the correct code path based on the type
       path =
-        if spec.type == "Git" then
+        if (spec.state or "fetched") != "fetched" then
+          throw "The pin ${name} has not been fetched since its version changed, run `npins fetch` first"
+        else if spec.type == "Git" then
           mkGitSource fetchers spec
         else if spec.type == "GitRelease" then
           mkGitSource fetchers spec
+        else if spec.type == "ReleaseAsset" then
+          mkDownloadSource fetchers spec
+        else if spec.type == "GitLabPackage" then
+          mkDownloadSource fetchers spec
         else if spec.type == "PyPi" then
-          mkPyPiSource fetchers spec
+          mkFileSource fetchers spec
+        else if spec.type == "GoModule" then
+          mkFileSource fetchers spec
         else if spec.type == "Channel" then
           mkChannelSource fetchers spec
+        else if spec.type == "Hydra" then
+          mkChannelSource fetchers spec
+        else if spec.type == "Flake" then
+          mkFlakeSource fetchers spec
+        else if spec.type == "Svn" then
+          mkSvnSource fetchers spec
         else if spec.type == "Tarball" then
           mkTarballSource fetchers spec
+        else if spec.type == "File" then
+          mkFileSource fetchers spec
+        else if spec.type == "VsCodeExtension" then
+          mkFileSource fetchers spec
+        else if spec.type == "FirefoxAddon" then
+          mkFileSource fetchers spec
+        else if spec.type == "ChromeExtension" then
+          mkFileSource fetchers spec
+        else if spec.type == "Manifest" then
+          mkDownloadSource fetchers spec
+        else if spec.type == "Artifact" then
+          mkDownloadSource fetchers spec
+        else if spec.type == "Maven" then
+          mkFileSource fetchers spec
+        else if spec.type == "Bucket" then
+          mkDownloadSource fetchers spec
+        else if spec.type == "Ipfs" then
+          mkDownloadSource fetchers spec
+        else if spec.type == "GitHubArtifact" then
+          mkDownloadSource fetchers spec
+        else if spec.type == "GitLabArtifact" then
+          mkDownloadSource fetchers spec
+        else if spec.type == "BuildkiteArtifact" then
+          mkDownloadSource fetchers spec
+        else if spec.type == "Container" then
+          mkContainerSource fetchers spec
         else
           builtins.throw "Unknown source type ${spec.type}";
     in
-    spec // { outPath = mayOverride name path; };
+    spec
+    // {
+      outPath = mayOverride name path;
+    }
+    // (
+      # The versions kept with `--keep`, newest first
+      if spec ? previous then
+        { previous = map (previous: mkSource name previous { inherit pkgs; }) spec.previous; }
+      else
+        { }
+    );
 
   mkGitSource =
     { fetchTarball, fetchGit, ... }:
@@ -133,6 +191,17 @@ let
             "https://github.com/${repository.owner}/${repository.repo}.git"
           else if repository.type == "GitLab" then
             "${repository.server}/${repository.repo_path}.git"
+          else if repository.type == "SourceHut" then
+            "${repository.server}/~${repository.owner}/${repository.repo}"
+          else if repository.type == "Bitbucket" then
+            (
+              if repository.server or null == null then
+                "https://bitbucket.org/${repository.owner}/${repository.repo}.git"
+              else
+                "${repository.server}/scm/${repository.owner}/${repository.repo}.git"
+            )
+          else if repository.type == "Radicle" then
+            "${repository.seed}/${builtins.elemAt (builtins.match "(rad:)?(.*)" repository.rid) 1}.git"
           else
             throw "Unrecognized repository type ${repository.type}";
         urlToName =
@@ -147,14 +216,18 @@ let
           "${if matched == null then "source" else builtins.head matched}${appendShort}";
         name = urlToName url revision;
       in
-      fetchGit {
-        rev = revision;
-        narHash = hash;
+      fetchGit (
+        {
+          rev = revision;
+          narHash = hash;
 
-        inherit name submodules url;
-      };
+          inherit name submodules url;
+        }
+        # Commits of other refs than branches and tags, like `refs/pull/42/head`, are only fetched along with the ref
+        // (if branch != null && builtins.substring 0 5 branch == "refs/" then { ref = branch; } else { })
+      );
 
-  mkPyPiSource =
+  mkFileSource =
     { fetchurl, ... }:
     { url, hash, ... }:
     fetchurl {
@@ -170,18 +243,111 @@ let
       sha256 = hash;
     };
 
-  mkTarballSource =
-    { fetchTarball, ... }:
+  # `fetchzip` picks the unpacker by the URL's extension, which needs to be given explicitly if it doesn't have one
+  withExtension = archive: args: if archive == null then args else args // { extension = archive; };
+
+  mkDownloadSource =
+    { fetchTarball, fetchurl, ... }:
     {
       url,
-      locked_url ? url,
       hash,
+      unpack ? false,
+      archive ? null,
       ...
     }:
-    fetchTarball {
-      url = locked_url;
+    if unpack then
+      fetchTarball (
+        withExtension archive {
+          inherit url;
+          sha256 = hash;
+        }
+      )
+    else
+      fetchurl {
+        inherit url;
+        sha256 = hash;
+      };
+
+  # The locked URL contains the `narHash`, so this is pure. Nix needs to support flakes though.
+  mkFlakeSource =
+    _:
+    { locked_url, ... }:
+    if builtins ? fetchTree then
+      (builtins.fetchTree locked_url).outPath
+    else
+      throw "Flake pins need a Nix with `builtins.fetchTree`, i.e. with the `flakes` feature enabled";
+
+  mkSvnSource =
+    { fetchsvn, ... }:
+    {
+      url,
+      revision,
+      hash,
+      ...
+    }:
+    fetchsvn {
+      inherit url;
+      rev = revision;
       sha256 = hash;
     };
+
+  mkContainerSource =
+    { pullImage, ... }:
+    {
+      image_name,
+      image_tag,
+      image_digest,
+      os,
+      arch,
+      hash,
+      ...
+    }:
+    pullImage {
+      imageName = image_name;
+      imageDigest = image_digest;
+      # The tag is part of the image archive, and thus of its hash
+      finalImageTag = image_tag;
+      inherit os arch hash;
+    };
+
+  # Descend into the only entry of a directory `n` times, see `strip_components` of tarball pins.
+  # This needs to read the fetched directory, which is an import from derivation with the Nixpkgs fetchers.
+  stripComponents =
+    n: path:
+    let
+      entries = builtins.attrNames (builtins.readDir path);
+    in
+    if n == 0 then
+      path
+    else if builtins.length entries != 1 then
+      throw "Can't strip another component from ${path}, it has ${toString (builtins.length entries)} entries instead of one"
+    else
+      stripComponents (n - 1) "${path}/${builtins.head entries}";
+
+  mkTarballSource =
+    { fetchTarball, ... }:
+    {
+      url,
+      resolved_url ? url,
+      locked_url ? resolved_url,
+      hash,
+      archive ? null,
+      strip_components ? 1,
+      subpath ? null,
+      ...
+    }:
+    let
+      # The fetchers already strip the root directory
+      unpacked = stripComponents (strip_components - 1) (
+        fetchTarball (
+          withExtension archive {
+            url = locked_url;
+            sha256 = hash;
+          }
+        )
+      );
+    in
+    if subpath == null then unpacked else "${unpacked}/${subpath}";
 in
 mkFunctor (
   {
@@ -202,9 +368,48 @@ mkFunctor (
       else
         throw "Unsupported input type ${builtins.typeOf input}, must be a path or an attrset";
     version = data.version;
+    pins = builtins.mapAttrs (name: spec: mkFunctor (mkSource name spec)) data.pins;
+    # Variants like `postgres.v15` are nested below their pin, which need not exist
+    variantNames = builtins.filter (name: data.pins.${name}.variant or false) (
+      builtins.attrNames data.pins
+    );
+    variants = builtins.foldl' (
+      groups: name:
+      let
+        split = builtins.match "([^.]*)\\.(.*)" name;
+        group = builtins.elemAt split 0;
+      in
+      groups
+      // {
+        ${group} = groups.${group} or { } // {
+          ${builtins.elemAt split 1} = pins.${name};
+        };
+      }
+    ) { } variantNames;
+    plain = removeAttrs pins variantNames;
+    # Former names of pins, which warn when used
+    aliases = builtins.listToAttrs (
+      builtins.concatMap (
+        name:
+        map (alias: {
+          name = alias;
+          value = builtins.trace "The pin \"${alias}\" is now called \"${name}\"" pins.${name};
+        }) (data.pins.${name}.aliases or [ ])
+      ) (builtins.attrNames data.pins)
+    );
+    # Further names of pins, e.g. during a migration
+    otherNames = builtins.listToAttrs (
+      builtins.concatMap (
+        name:
+        map (other: {
+          name = other;
+          value = pins.${name};
+        }) (data.pins.${name}.other_names or [ ])
+      ) (builtins.attrNames data.pins)
+    );
   in
   if version == 6 then
-    builtins.mapAttrs (name: spec: mkFunctor (mkSource name spec)) data.pins
+    aliases // otherNames // plain // builtins.mapAttrs (group: members: plain.${group} or { } // members) variants
   else
     throw "Unsupported format version ${toString version} in sources.json. Try running `npins upgrade`"
 )
@@ -1,5 +1,7 @@
 //! Helper tools for generating differential output
 
+use std::collections::{BTreeMap, BTreeSet};
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Change {
     Added(String),
@@ -10,6 +12,57 @@ pub enum Change {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct DiffEntry(String, Change);
 
+impl DiffEntry {
+    pub fn property(&self) -> &str {
+        &self.0
+    }
+
+    /// The value before, `None` if the property was added
+    pub fn old_value(&self) -> Option<&str> {
+        match &self.1 {
+            Change::Removed(old) | Change::Changed(old, _) => Some(old),
+            Change::Added(_) => None,
+        }
+    }
+
+    /// The value after, `None` if the property was removed
+    pub fn new_value(&self) -> Option<&str> {
+        match &self.1 {
+            Change::Added(new) | Change::Changed(_, new) => Some(new),
+            Change::Removed(_) => None,
+        }
+    }
+
+    /// On a single line, like `version: 1.0 -> 1.1`
+    pub fn summary(&self) -> String {
+        format!(
+            "{}: {} -> {}",
+            self.0,
+            self.old_value().unwrap_or("(none)"),
+            self.new_value().unwrap_or("(none)")
+        )
+    }
+}
+
+/// As `{"property": …, "old": …, "new": …}`, leaving out the side that doesn't exist
+impl serde::Serialize for DiffEntry {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let DiffEntry(property, change) = self;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("property", property)?;
+        match change {
+            Change::Added(new) => map.serialize_entry("new", new)?,
+            Change::Removed(old) => map.serialize_entry("old", old)?,
+            Change::Changed(old, new) => {
+                map.serialize_entry("old", old)?;
+                map.serialize_entry("new", new)?;
+            },
+        }
+        map.end()
+    }
+}
+
 impl std::fmt::Display for DiffEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let DiffEntry(property, change) = self;
@@ -63,6 +116,58 @@ pub fn diff(old: &[(String, String)], new: &[(String, String)]) -> Vec<DiffEntry
     changes
 }
 
+/// How a pin differs between two sets of pins
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
+pub struct PinDiff {
+    pub status: Status,
+    /// For added and removed pins, all of their properties
+    pub changes: Vec<DiffEntry>,
+}
+
+/// Diff two sets of pins, leaving out the pins that are the same in both
+pub fn diff_pins(old: &crate::NixPins, new: &crate::NixPins) -> BTreeMap<String, PinDiff> {
+    let names = old
+        .pins
+        .keys()
+        .chain(new.pins.keys())
+        .collect::<BTreeSet<_>>();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (status, changes) = match (old.pins.get(name), new.pins.get(name)) {
+                (Some(old), Some(new)) if old == new => return None,
+                (Some(old), Some(new)) => {
+                    (Status::Changed, diff(&old.properties(), &new.properties()))
+                },
+                (Some(old), None) => (
+                    Status::Removed,
+                    old.properties()
+                        .into_iter()
+                        .map(|(key, value)| DiffEntry(key, Change::Removed(value)))
+                        .collect(),
+                ),
+                (None, Some(new)) => (
+                    Status::Added,
+                    new.properties()
+                        .into_iter()
+                        .map(|(key, value)| DiffEntry(key, Change::Added(value)))
+                        .collect(),
+                ),
+                (None, None) => unreachable!(),
+            };
+            Some((name.clone(), PinDiff { status, changes }))
+        })
+        .collect()
+}
+
 pub trait Diff {
     /// List the key-value properties for this struct. Order matters
     fn properties(&self) -> Vec<(String, String)>;
@@ -144,4 +249,46 @@ mod test {
             vec![DiffEntry(baz(), Change::Changed(foo(), bar())),],
         );
     }
+
+    #[test]
+    fn test_diff_pins() {
+        let mut old = crate::NixPins::new_with_nixpkgs();
+        old.pins.insert(
+            "tool".into(),
+            crate::test_pin("https://example.org/tool-1.tar.gz"),
+        );
+        let mut new = crate::NixPins::default();
+        new.pins.insert(
+            "tool".into(),
+            crate::test_pin("https://example.org/tool-2.tar.gz"),
+        );
+        new.pins.insert(
+            "other".into(),
+            crate::test_pin("https://example.org/other.tar.gz"),
+        );
+
+        let diff = diff_pins(&old, &new);
+        assert_eq!(
+            diff.iter()
+                .map(|(name, diff)| (name.as_str(), diff.status.clone()))
+                .collect::<Vec<_>>(),
+            [
+                ("nixpkgs", Status::Removed),
+                ("other", Status::Added),
+                ("tool", Status::Changed)
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&diff["tool"]).unwrap(),
+            serde_json::json!({
+                "status": "changed",
+                "changes": [{
+                    "property": "url",
+                    "old": "https://example.org/tool-1.tar.gz",
+                    "new": "https://example.org/tool-2.tar.gz",
+                }],
+            })
+        );
+        assert!(diff_pins(&new, &new).is_empty());
+    }
 }
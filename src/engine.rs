@@ -0,0 +1,408 @@
+//! The update engine: update pins concurrently, reporting progress as a stream of events
+//!
+//! [`update_all`] does the work of `npins update`, for embedding npins into other programs. Instead
+//! of logging, it yields a [`PinEvent`] whenever a pin makes progress, so that a front end can
+//! render that however it wants. The pins are taken by value and handed back in the events; the
+//! caller decides what to do with them, e.g. write them to the lock file or show a preview.
+//!
+//! ```no_run
+//! # async fn example(pins: npins::NixPins) {
+//! use futures::StreamExt;
+//! use npins::engine::*;
+//!
+//! let jobs = pins
+//!     .pins
+//!     .into_iter()
+//!     .map(|(name, pin)| Job::new(name, pin, UpdateStrategy::Normal))
+//!     .collect();
+//! let mut events = std::pin::pin!(update_all(jobs, Options::default()));
+//! while let Some(event) = events.next().await {
+//!     println!("{event:?}");
+//! }
+//! # }
+//! ```
+
+use crate::*;
+use anyhow::Result;
+use futures::stream::{self, Stream, StreamExt};
+
+/// How to handle updates
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum UpdateStrategy {
+    /// Fetch latest version, update hashes if necessary
+    Normal,
+    /// Update hashes of the currently pinned version
+    HashesOnly,
+    /// Fetch latest version, always update hashes
+    Full,
+    /// Fetch latest version, but leave the hashes to `npins fetch`
+    VersionOnly,
+}
+
+impl UpdateStrategy {
+    /// Whether the latest version should be fetched
+    pub fn should_update(&self) -> bool {
+        match self {
+            UpdateStrategy::Normal => true,
+            UpdateStrategy::HashesOnly => false,
+            UpdateStrategy::Full => true,
+            UpdateStrategy::VersionOnly => true,
+        }
+    }
+
+    /// Whether we want to force-update the hashes
+    pub fn must_fetch(&self) -> bool {
+        match self {
+            UpdateStrategy::Normal => false,
+            UpdateStrategy::HashesOnly => true,
+            UpdateStrategy::Full => true,
+            UpdateStrategy::VersionOnly => false,
+        }
+    }
+
+    /// Whether the hashes may be fetched at all
+    pub fn may_fetch(&self) -> bool {
+        !matches!(self, UpdateStrategy::VersionOnly)
+    }
+}
+
+/// Report a renamed repository, and switch the pin over to the new name if asked to
+async fn check_renamed(pin: &mut Pin, follow: bool) -> Result<Vec<diff::DiffEntry>> {
+    let Some(repository) = pin.repository() else {
+        return Ok(vec![]);
+    };
+    /* Only a courtesy, the redirect still works after all */
+    let renamed = match repository.renamed().await {
+        Ok(Some(renamed)) => renamed,
+        Ok(None) => return Ok(vec![]),
+        Err(err) => {
+            log::debug!("Failed to check whether the repository was renamed: {err:#}");
+            return Ok(vec![]);
+        },
+    };
+    let (old, new) = (repository.git_url()?, renamed.git_url()?);
+    if !follow {
+        log::warn!(
+            "{old} was renamed to {new}. Run `npins update --follow-renames` to update the pin"
+        );
+        return Ok(vec![]);
+    }
+    *pin.repository_mut().expect("checked above") = renamed;
+    Ok(diff::diff(
+        &[("repository".into(), old.to_string())],
+        &[("repository".into(), new.to_string())],
+    ))
+}
+
+/// The first half of an update: look for the latest version
+///
+/// Returns the changes, and what was recorded for the provenance along the way.
+async fn resolve(
+    pin: &mut Pin,
+    strategy: UpdateStrategy,
+    follow_renames: bool,
+) -> Result<(Vec<diff::DiffEntry>, provenance::Recorded)> {
+    let (diff, recorded) = provenance::record(async {
+        /* Skip this for partial updates */
+        if strategy.should_update() {
+            let renamed = check_renamed(pin, follow_renames).await?;
+            anyhow::Ok(renamed.into_iter().chain(pin.update().await?).collect())
+        } else {
+            anyhow::Ok(vec![])
+        }
+    })
+    .await;
+    Ok((diff?, recorded))
+}
+
+/// The second half of an update: fetch the hashes if needed, and record the provenance
+///
+/// Returns the changes, and what could not be looked up, see [`provenance::record_missing`].
+async fn complete(
+    pin: &mut Pin,
+    strategy: UpdateStrategy,
+    diff1: Vec<diff::DiffEntry>,
+    mut recorded: provenance::Recorded,
+) -> Result<(Vec<diff::DiffEntry>, Vec<String>)> {
+    /* We only need to fetch the hashes if the version changed, or if the flags indicate that we should */
+    let stale = pin.metadata().state != PinState::Fetched;
+    let diff = if !strategy.may_fetch() {
+        if !diff1.is_empty() {
+            pin.metadata_mut().state = PinState::NeedsFetch;
+        }
+        diff1
+    } else if !diff1.is_empty() || stale || strategy.must_fetch() {
+        let (diff2, more) = provenance::record(pin.fetch()).await;
+        recorded.extend(more);
+        pin.metadata_mut().state = PinState::Fetched;
+        diff1.into_iter().chain(diff2?).collect()
+    } else {
+        diff1
+    };
+
+    if !diff.is_empty() {
+        for finding in check::check_pin(pin) {
+            log::warn!("{finding}");
+            log::warn!("{}", check::CREDENTIALS_ADVICE);
+        }
+        pin.metadata_mut().provenance = Some(provenance::Provenance::new(pin, recorded.clone())?);
+    }
+
+    Ok((diff, recorded.normalized().missing))
+}
+
+/// Update a single pin, returning what changed
+pub async fn update_one(
+    pin: &mut Pin,
+    strategy: UpdateStrategy,
+    follow_renames: bool,
+) -> Result<Vec<diff::DiffEntry>> {
+    let before = pin.clone();
+    let (diff, recorded) = resolve(pin, strategy, follow_renames).await?;
+    let (diff, _) = complete(pin, strategy, diff, recorded).await?;
+    pin.remember(before);
+    Ok(diff)
+}
+
+/// A pin to update
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub name: String,
+    pub pin: Pin,
+    pub strategy: UpdateStrategy,
+}
+
+impl Job {
+    pub fn new(name: impl Into<String>, pin: Pin, strategy: UpdateStrategy) -> Self {
+        Job {
+            name: name.into(),
+            pin,
+            strategy,
+        }
+    }
+}
+
+/// How to run the jobs
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// How many pins to update at once
+    pub concurrency: usize,
+    /// Also update frozen pins
+    pub update_frozen: bool,
+    /// Switch pins of renamed repositories over to the new name
+    pub follow_renames: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            concurrency: 5,
+            update_frozen: false,
+            follow_renames: false,
+        }
+    }
+}
+
+/// Why a pin was not updated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    Frozen,
+    /// The pin is managed by a registry, and updated by `npins sync`
+    Managed,
+}
+
+/// Whether a pin is left alone
+pub fn skip_reason(pin: &Pin, options: &Options) -> Option<SkipReason> {
+    if pin.is_managed() {
+        Some(SkipReason::Managed)
+    } else if pin.is_frozen() && !options.update_frozen {
+        Some(SkipReason::Frozen)
+    } else {
+        None
+    }
+}
+
+/// Progress of a pin in [`update_all`]
+///
+/// Every pin is either skipped, or started and then either fetched or failed.
+#[derive(Debug)]
+pub enum PinEvent {
+    /// Looking for the latest version of the pin started
+    Started { name: String },
+    /// The latest version is known, `diff` is empty if it is the pinned one
+    Resolved {
+        name: String,
+        diff: Vec<diff::DiffEntry>,
+    },
+    /// The pin is done: it is at the latest version, and its hashes were fetched unless the
+    /// strategy leaves that for later
+    Fetched {
+        name: String,
+        pin: Pin,
+        diff: Vec<diff::DiffEntry>,
+        /// What could not be looked up, e.g. because the forge's API was down, see
+        /// [`provenance::record_missing`]
+        missing: Vec<String>,
+    },
+    /// Updating the pin failed. `pin` is as far as the update got, e.g. with the new version but
+    /// the old hashes
+    Failed {
+        name: String,
+        pin: Pin,
+        error: anyhow::Error,
+    },
+    /// The pin was not touched
+    Skipped { name: String, reason: SkipReason },
+}
+
+impl PinEvent {
+    /// The name of the pin the event is about
+    pub fn name(&self) -> &str {
+        match self {
+            PinEvent::Started { name }
+            | PinEvent::Resolved { name, .. }
+            | PinEvent::Fetched { name, .. }
+            | PinEvent::Failed { name, .. }
+            | PinEvent::Skipped { name, .. } => name,
+        }
+    }
+}
+
+/// The events of a single job
+fn job_events(job: Job, options: Options) -> impl Stream<Item = PinEvent> {
+    enum State {
+        Start(Job),
+        Resolve(Job),
+        /* Along with the pin before the update */
+        Fetch(Job, Box<Pin>, Vec<diff::DiffEntry>, provenance::Recorded),
+        Done,
+    }
+
+    stream::unfold(State::Start(job), move |state| {
+        let follow_renames = options.follow_renames;
+        let skip = match &state {
+            State::Start(job) => skip_reason(&job.pin, &options),
+            _ => None,
+        };
+        async move {
+            Some(match state {
+                State::Start(job) => match skip {
+                    Some(reason) => (
+                        PinEvent::Skipped {
+                            name: job.name,
+                            reason,
+                        },
+                        State::Done,
+                    ),
+                    None => (
+                        PinEvent::Started {
+                            name: job.name.clone(),
+                        },
+                        State::Resolve(job),
+                    ),
+                },
+                State::Resolve(mut job) => {
+                    let before = Box::new(job.pin.clone());
+                    match resolve(&mut job.pin, job.strategy, follow_renames).await {
+                        Ok((diff, recorded)) => (
+                            PinEvent::Resolved {
+                                name: job.name.clone(),
+                                diff: diff.clone(),
+                            },
+                            State::Fetch(job, before, diff, recorded),
+                        ),
+                        Err(error) => (
+                            PinEvent::Failed {
+                                name: job.name,
+                                pin: job.pin,
+                                error,
+                            },
+                            State::Done,
+                        ),
+                    }
+                },
+                State::Fetch(mut job, before, diff, recorded) => {
+                    let event = match complete(&mut job.pin, job.strategy, diff, recorded).await {
+                        Ok((diff, missing)) => {
+                            job.pin.remember(*before);
+                            PinEvent::Fetched {
+                                name: job.name,
+                                pin: job.pin,
+                                diff,
+                                missing,
+                            }
+                        },
+                        Err(error) => PinEvent::Failed {
+                            name: job.name,
+                            pin: job.pin,
+                            error,
+                        },
+                    };
+                    (event, State::Done)
+                },
+                State::Done => return None,
+            })
+        }
+    })
+}
+
+/// Update the pins of the jobs, `options.concurrency` at a time
+///
+/// The events of different pins are interleaved in the order they happen. Dropping the stream
+/// cancels the remaining jobs.
+pub fn update_all(jobs: Vec<Job>, options: Options) -> impl Stream<Item = PinEvent> {
+    let concurrency = options.concurrency.max(1);
+    stream::iter(jobs)
+        .map(move |job| job_events(job, options.clone()).boxed())
+        .flatten_unordered(concurrency)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_update_all() {
+        let pin = |frozen: bool| -> Pin {
+            let mut pin: Pin = serde_json::from_value(serde_json::json!({
+                "type": "File",
+                "url": "https://example.org/data.json",
+                "hash": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+            }))
+            .unwrap();
+            if frozen {
+                pin.freeze();
+            }
+            pin
+        };
+        let jobs = vec![
+            Job::new("a", pin(false), UpdateStrategy::Normal),
+            Job::new("b", pin(true), UpdateStrategy::Normal),
+        ];
+        let events = update_all(jobs, Options::default())
+            .collect::<Vec<_>>()
+            .await;
+
+        let events = events
+            .iter()
+            .map(|event| match event {
+                PinEvent::Started { name } => format!("started {name}"),
+                PinEvent::Resolved { name, diff } => format!("resolved {name} {}", diff.len()),
+                PinEvent::Fetched { name, diff, .. } => format!("fetched {name} {}", diff.len()),
+                PinEvent::Failed { name, error, .. } => format!("failed {name}: {error}"),
+                PinEvent::Skipped { name, reason } => format!("skipped {name} {reason:?}"),
+            })
+            .collect::<Vec<_>>();
+        /* The pins are updated concurrently, but the events of each pin come in order. Files
+         * never change, so there is nothing to fetch either */
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| event.ends_with(" a") || event.contains(" a "))
+                .collect::<Vec<_>>(),
+            ["started a", "resolved a 0", "fetched a 0"]
+        );
+        assert!(events.contains(&"skipped b Frozen".to_owned()));
+        assert_eq!(events.len(), 4);
+    }
+}
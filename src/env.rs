@@ -0,0 +1,205 @@
+//! The pins as environment variables, for builds that don't use Nix
+//!
+//! Shell scripts, Makefiles and Docker builds can then use the same versions as the Nix code. Each
+//! pin becomes a few variables named after it, `NPINS_<NAME>_VERSION`, `_REVISION`, `_URL`,
+//! `_HASH` and `_REPOSITORY`, of which only those are set that the pin has. The name is upper
+//! cased, with everything but letters and digits replaced by `_`: `home-manager` becomes
+//! `NPINS_HOME_MANAGER_REVISION`.
+//!
+//! With [direnv](https://direnv.net), the variables are set whenever one enters the project, see
+//! [`direnv_hook`].
+
+use crate::*;
+use anyhow::Result;
+use std::path::Path;
+
+/// How to print the variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// `export NPINS_FOO_HASH='…'` lines, for `eval` or `source`
+    #[default]
+    Shell,
+    /// `NPINS_FOO_HASH=…` lines, for `docker run --env-file` and the like, which take the values
+    /// as they are
+    Dotenv,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Format::Shell => "shell",
+            Format::Dotenv => "dotenv",
+        })
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "shell" => Ok(Format::Shell),
+            "dotenv" => Ok(Format::Dotenv),
+            _ => anyhow::bail!("expected `shell` or `dotenv`"),
+        }
+    }
+}
+
+/// The fields of the lock file that become variables
+const FIELDS: [&str; 4] = ["version", "revision", "url", "hash"];
+
+/// `NPINS_<NAME>`, the prefix of the variables of a pin
+pub fn prefix(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect::<String>();
+    format!("NPINS_{name}")
+}
+
+/// The variables of a pin, in a fixed order
+pub fn variables(name: &str, pin: &Pin) -> Result<Vec<(String, String)>> {
+    let prefix = prefix(name);
+    let value = serde_json::to_value(pin)?;
+    let mut variables = FIELDS
+        .iter()
+        .filter_map(|field| {
+            let value = value.get(field)?.as_str()?;
+            Some((
+                format!("{prefix}_{}", field.to_uppercase()),
+                value.to_owned(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    if let Some(url) = pin
+        .repository()
+        .and_then(|repository| repository.git_url().ok())
+    {
+        variables.push((format!("{prefix}_REPOSITORY"), url.to_string()));
+    }
+    Ok(variables)
+}
+
+/// The variables of all the given pins, refusing names that end up as the same variables
+pub fn render<'a>(
+    pins: impl IntoIterator<Item = (&'a String, &'a Pin)>,
+    format: Format,
+) -> Result<String> {
+    let mut prefixes = BTreeMap::new();
+    let mut output = String::new();
+    for (name, pin) in pins {
+        if let Some(other) = prefixes.insert(prefix(name), name) {
+            anyhow::bail!(
+                "The pins '{other}' and '{name}' would both be exported as {}_*, rename one of them",
+                prefix(name)
+            );
+        }
+        for (variable, value) in variables(name, pin)? {
+            match format {
+                Format::Shell => output.push_str(&format!(
+                    "export {variable}={}\n",
+                    update_script::shell_word(&value)
+                )),
+                Format::Dotenv => output.push_str(&format!("{variable}={value}\n")),
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// A snippet for direnv's `.envrc` that exports the pins, and reloads them when `watched` change
+///
+/// `npins_args` are the global arguments that locate the lock file, like `--directory npins`.
+/// With `check`, it also runs `npins check`, so that broken pins show up on entering the project.
+pub fn direnv_hook(npins_args: &[String], watched: &[&Path], check: bool) -> String {
+    let npins = std::iter::once("npins".to_owned())
+        .chain(npins_args.iter().cloned())
+        .map(|argument| update_script::shell_word(&argument))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let watched = watched
+        .iter()
+        .map(|path| update_script::shell_word(&path.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut hook = format!(
+        "# Exports the pins as environment variables, generated by `npins direnv-hook`\n\
+         watch_file {watched}\n\
+         if has npins; then\n  \
+           eval \"$({npins} env)\"\n"
+    );
+    if check {
+        hook.push_str(&format!(
+            "  {npins} check || log_error \"npins check found problems with the pins\"\n"
+        ));
+    }
+    hook.push_str("else\n  log_error \"npins is not installed, not exporting the pins\"\nfi\n");
+    hook
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut pins = NixPins::default();
+        pins.pins.insert(
+            "home-manager".into(),
+            (
+                git::GitPin::new(
+                    git::Repository::github("nix-community", "home-manager"),
+                    "master".into(),
+                    false,
+                ),
+                Some(
+                    git::GitRevision::new("0123456789abcdef0123456789abcdef01234567".into())
+                        .unwrap(),
+                ),
+            )
+                .into(),
+        );
+        let shell = render(&pins.pins, Format::Shell).unwrap();
+        assert_eq!(
+            shell,
+            "export NPINS_HOME_MANAGER_REVISION=0123456789abcdef0123456789abcdef01234567\n\
+             export NPINS_HOME_MANAGER_REPOSITORY=https://github.com/nix-community/home-manager.git\n"
+        );
+        assert!(render(&pins.pins, Format::Dotenv)
+            .unwrap()
+            .starts_with("NPINS_HOME_MANAGER_REVISION=0123"));
+
+        let pin = pins.pins["home-manager"].clone();
+        pins.pins.insert("home_manager".into(), pin);
+        assert!(render(&pins.pins, Format::Shell).is_err());
+    }
+
+    #[test]
+    fn test_direnv_hook() {
+        let args = ["--directory".to_owned(), "my pins".to_owned()];
+        let hook = direnv_hook(
+            &args,
+            &[
+                Path::new("my pins/sources.json"),
+                Path::new("my pins/npins.toml"),
+            ],
+            true,
+        );
+        assert_eq!(
+            hook,
+            "# Exports the pins as environment variables, generated by `npins direnv-hook`\n\
+             watch_file 'my pins/sources.json' 'my pins/npins.toml'\n\
+             if has npins; then\n  \
+               eval \"$(npins --directory 'my pins' env)\"\n  \
+               npins --directory 'my pins' check || log_error \"npins check found problems with the pins\"\n\
+             else\n  \
+               log_error \"npins is not installed, not exporting the pins\"\n\
+             fi\n"
+        );
+        assert!(!direnv_hook(&args, &[], false).contains(" check"));
+    }
+}
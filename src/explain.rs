@@ -0,0 +1,381 @@
+//! Show the Nix expression that fetches a pin
+//!
+//! This mirrors what the generated `default.nix` does with a pin, so that hash mismatches and
+//! restricted evaluation errors can be debugged by looking at (or evaluating) a single fetcher
+//! call instead of reading through the glue code. Like `default.nix`, it works on the pin as it
+//! is stored in `sources.json`. Keep both in sync.
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// Escape a string for use within double quotes in Nix
+fn escape(string: &str) -> String {
+    string
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+        .replace('\n', r"\n")
+}
+
+/// Quote a string for Nix
+fn nix_string(string: &str) -> String {
+    format!("\"{}\"", escape(string))
+}
+
+/// A function call with an attribute set as argument, one attribute per line
+fn call(function: &str, arguments: &[(&str, String)]) -> String {
+    let mut expression = format!("{function} {{\n");
+    for (name, value) in arguments {
+        expression.push_str(&format!("  {name} = {value};\n"));
+    }
+    expression.push('}');
+    expression
+}
+
+/// Which fetchers `default.nix` uses, the builtin ones or the ones from Nixpkgs
+struct Fetchers {
+    pkgs: bool,
+}
+
+impl Fetchers {
+    fn fetch_tarball(&self, url: &str, hash: &str, archive: Option<&str>) -> String {
+        let mut arguments = vec![("url", nix_string(url)), ("sha256", nix_string(hash))];
+        if self.pkgs {
+            /* Only `fetchzip` needs the extension, the builtin recognizes archives by their contents */
+            if let Some(archive) = archive {
+                arguments.push(("extension", nix_string(archive)));
+            }
+            call("pkgs.fetchzip", &arguments)
+        } else {
+            call("builtins.fetchTarball", &arguments)
+        }
+    }
+
+    fn fetch_url(&self, url: &str, hash: &str) -> String {
+        let function = if self.pkgs {
+            "pkgs.fetchurl"
+        } else {
+            "builtins.fetchurl"
+        };
+        call(
+            function,
+            &[("url", nix_string(url)), ("sha256", nix_string(hash))],
+        )
+    }
+
+    fn fetch_git(&self, url: &str, rev: &str, name: &str, submodules: bool, hash: &str) -> String {
+        if self.pkgs {
+            call(
+                "pkgs.fetchgit",
+                &[
+                    ("url", nix_string(url)),
+                    ("rev", nix_string(rev)),
+                    ("name", nix_string(name)),
+                    ("fetchSubmodules", submodules.to_string()),
+                    ("hash", nix_string(hash)),
+                ],
+            )
+        } else {
+            format!(
+                "({}).outPath",
+                call(
+                    "builtins.fetchGit",
+                    &[
+                        ("url", nix_string(url)),
+                        ("rev", nix_string(rev)),
+                        ("name", nix_string(name)),
+                        ("submodules", submodules.to_string()),
+                        ("narHash", nix_string(hash)),
+                    ],
+                )
+            )
+        }
+    }
+
+    fn needs_pkgs(&self, what: &str) -> Result<()> {
+        anyhow::ensure!(
+            self.pkgs,
+            "{what} pins need Nixpkgs, pass it as `pkgs` argument (or `--pkgs` here)"
+        );
+        Ok(())
+    }
+}
+
+/// The name `default.nix` gives git checkouts: the last component of the URL, plus the short
+/// revision if it is a commit hash
+fn git_checkout_name(url: &str, revision: &str) -> String {
+    let base = url.rsplit('/').next().unwrap_or("source");
+    if revision.chars().all(|c| c.is_ascii_hexdigit()) {
+        format!("{}-{}", base, &revision[..revision.len().min(7)])
+    } else {
+        base.to_owned()
+    }
+}
+
+fn str_field<'a>(spec: &'a Value, field: &str) -> Result<&'a str> {
+    spec[field]
+        .as_str()
+        .with_context(|| format!("The pin has no `{}`", field))
+}
+
+/// The Nix expression that `default.nix` evaluates to fetch `pin`
+///
+/// With `pkgs`, as when passing Nixpkgs to `default.nix`, the fetchers from Nixpkgs are used.
+pub fn nix_expression(name: &str, pin: &Pin, pkgs: bool) -> Result<String> {
+    let spec = serde_json::to_value(pin)?;
+    let fetchers = Fetchers { pkgs };
+    let field = |field| str_field(&spec, field);
+    let optional = |field: &str| spec[field].as_str();
+
+    anyhow::ensure!(
+        optional("state").unwrap_or("fetched") == "fetched",
+        "The pin {} has not been fetched since its version changed, run `npins fetch` first",
+        name
+    );
+
+    let expression = match field("type")? {
+        "Git" | "GitRelease" => {
+            let submodules = spec["submodules"].as_bool().unwrap_or(false);
+            match optional("url") {
+                Some(url) if !submodules => fetchers.fetch_tarball(url, field("hash")?, None),
+                _ => {
+                    let url = pin
+                        .repository()
+                        .context("Git pins have a repository")?
+                        .git_url()?
+                        .to_string();
+                    let revision = field("revision")?;
+                    fetchers.fetch_git(
+                        &url,
+                        revision,
+                        &git_checkout_name(&url, revision),
+                        submodules,
+                        field("hash")?,
+                    )
+                },
+            }
+        },
+        "Channel" | "Hydra" => fetchers.fetch_tarball(field("url")?, field("hash")?, None),
+        "PyPi" | "GoModule" | "File" | "VsCodeExtension" | "FirefoxAddon" | "ChromeExtension"
+        | "Maven" => fetchers.fetch_url(field("url")?, field("hash")?),
+        "ReleaseAsset" | "GitLabPackage" | "Manifest" | "Artifact" | "Bucket" | "Ipfs"
+        | "GitHubArtifact" | "GitLabArtifact" | "BuildkiteArtifact" => {
+            if spec["unpack"].as_bool().unwrap_or(false) {
+                fetchers.fetch_tarball(field("url")?, field("hash")?, optional("archive"))
+            } else {
+                fetchers.fetch_url(field("url")?, field("hash")?)
+            }
+        },
+        "Flake" => format!(
+            "(builtins.fetchTree {}).outPath",
+            nix_string(field("locked_url")?)
+        ),
+        "Svn" => {
+            fetchers.needs_pkgs("Subversion")?;
+            call(
+                "pkgs.fetchsvn",
+                &[
+                    ("url", nix_string(field("url")?)),
+                    ("rev", spec["revision"].to_string()),
+                    ("sha256", nix_string(field("hash")?)),
+                ],
+            )
+        },
+        "Container" => {
+            fetchers.needs_pkgs("Container image")?;
+            call(
+                "pkgs.dockerTools.pullImage",
+                &[
+                    ("imageName", nix_string(field("image_name")?)),
+                    ("imageDigest", nix_string(field("image_digest")?)),
+                    ("finalImageTag", nix_string(field("image_tag")?)),
+                    ("os", nix_string(field("os")?)),
+                    ("arch", nix_string(field("arch")?)),
+                    ("hash", nix_string(field("hash")?)),
+                ],
+            )
+        },
+        "Tarball" => {
+            let url = optional("locked_url")
+                .or(optional("resolved_url"))
+                .unwrap_or(field("url")?);
+            let fetch = fetchers.fetch_tarball(url, field("hash")?, optional("archive"));
+            let strip_components = spec["strip_components"].as_u64().unwrap_or(1);
+            let fetch = if strip_components > 1 {
+                format!(
+                    "# Afterwards, {} more directory level(s) are stripped, see `strip_components`\n{}",
+                    strip_components - 1,
+                    fetch
+                )
+            } else {
+                fetch
+            };
+            match optional("subpath") {
+                Some(subpath) => format!(
+                    "let\n  src = {};\nin\n\"${{src}}/{}\"",
+                    fetch.replace('\n', "\n  "),
+                    escape(subpath)
+                ),
+                None => fetch,
+            }
+        },
+        other => anyhow::bail!("Unknown source type {}", other),
+    };
+    Ok(expression)
+}
+
+/// What `default.nix` puts into the Nix store for a pin, with the builtin fetchers
+///
+/// The store path of a fixed-output artifact only depends on these, so it can be looked up
+/// without fetching anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreArtifact {
+    pub name: String,
+    pub hash: String,
+    /// Whether the hash is of the NAR serialization (for directories) or of a flat file
+    pub recursive: bool,
+}
+
+/// The store artifact of `pin`, if its store path is known in advance
+///
+/// Subversion and container pins are built by Nixpkgs, their store paths depend on it.
+pub fn store_artifact(pin: &Pin) -> Result<Option<StoreArtifact>> {
+    let spec = serde_json::to_value(pin)?;
+    let field = |field| str_field(&spec, field);
+    let optional = |field: &str| spec[field].as_str();
+    let directory = |name: String| {
+        Ok(Some(StoreArtifact {
+            name,
+            hash: field("hash")?.to_owned(),
+            recursive: true,
+        }))
+    };
+    let file = |url: &str| {
+        Ok(Some(StoreArtifact {
+            /* Like `baseNameOf` */
+            name: url
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or(url)
+                .to_owned(),
+            hash: field("hash")?.to_owned(),
+            recursive: false,
+        }))
+    };
+
+    match field("type")? {
+        "Git" | "GitRelease" => {
+            let submodules = spec["submodules"].as_bool().unwrap_or(false);
+            match optional("url") {
+                Some(_) if !submodules => directory("source".into()),
+                _ => {
+                    let url = pin
+                        .repository()
+                        .context("Git pins have a repository")?
+                        .git_url()?
+                        .to_string();
+                    directory(git_checkout_name(&url, field("revision")?))
+                },
+            }
+        },
+        "Channel" | "Hydra" | "Tarball" | "Flake" => directory("source".into()),
+        "PyPi" | "GoModule" | "File" | "VsCodeExtension" | "FirefoxAddon" | "ChromeExtension"
+        | "Maven" => file(field("url")?),
+        "ReleaseAsset" | "GitLabPackage" | "Manifest" | "Artifact" | "Bucket" | "Ipfs"
+        | "GitHubArtifact" | "GitLabArtifact" | "BuildkiteArtifact" => {
+            if spec["unpack"].as_bool().unwrap_or(false) {
+                directory("source".into())
+            } else {
+                file(field("url")?)
+            }
+        },
+        "Svn" | "Container" => Ok(None),
+        other => anyhow::bail!("Unknown source type {}", other),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nix_expression() {
+        let pin: Pin = serde_json::from_value(serde_json::json!({
+            "type": "Git",
+            "repository": {"type": "GitHub", "owner": "NixOS", "repo": "nixpkgs"},
+            "branch": "master",
+            "submodules": true,
+            "revision": "0123456789abcdef0123456789abcdef01234567",
+            "url": "https://github.com/NixOS/nixpkgs/archive/0123456789abcdef0123456789abcdef01234567.tar.gz",
+            "hash": "sha256-AAAA",
+        }))
+        .unwrap();
+        assert_eq!(
+            nix_expression("nixpkgs", &pin, false).unwrap(),
+            r#"(builtins.fetchGit {
+  url = "https://github.com/NixOS/nixpkgs.git";
+  rev = "0123456789abcdef0123456789abcdef01234567";
+  name = "nixpkgs.git-0123456";
+  submodules = true;
+  narHash = "sha256-AAAA";
+}).outPath"#
+        );
+
+        let mut pin = tarball::TarballPin::new("https://example.org/docs.tar.gz".parse().unwrap());
+        pin.subpath = Some("${x}".into());
+        let pin: Pin = pin.into();
+        assert!(nix_expression("docs", &pin, true).is_err());
+        let mut spec = serde_json::to_value(&pin).unwrap();
+        spec["hash"] = "sha256-BBBB".into();
+        let pin: Pin = serde_json::from_value(spec).unwrap();
+        assert_eq!(
+            nix_expression("docs", &pin, true).unwrap(),
+            r#"let
+  src = pkgs.fetchzip {
+    url = "https://example.org/docs.tar.gz";
+    sha256 = "sha256-BBBB";
+  };
+in
+"${src}/\${x}""#
+        );
+    }
+
+    #[test]
+    fn test_store_artifact() {
+        let pin: Pin = serde_json::from_value(serde_json::json!({
+            "type": "Git",
+            "repository": {"type": "Git", "url": "https://example.org/tool.git"},
+            "branch": "main",
+            "submodules": false,
+            "revision": "0123456789abcdef0123456789abcdef01234567",
+            "url": null,
+            "hash": "sha256-AAAA",
+        }))
+        .unwrap();
+        assert_eq!(
+            store_artifact(&pin).unwrap(),
+            Some(StoreArtifact {
+                name: "tool.git-0123456".into(),
+                hash: "sha256-AAAA".into(),
+                recursive: true,
+            })
+        );
+
+        let pin: Pin = serde_json::from_value(serde_json::json!({
+            "type": "File",
+            "url": "https://example.org/files/data.json",
+            "hash": "sha256-BBBB",
+        }))
+        .unwrap();
+        assert_eq!(
+            store_artifact(&pin).unwrap(),
+            Some(StoreArtifact {
+                name: "data.json".into(),
+                hash: "sha256-BBBB".into(),
+                recursive: false,
+            })
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Pin a single file by URL, and tell what kind of source an arbitrary URL points to
+//!
+//! The classification looks at the same things a human would: whether the server speaks the git
+//! [HTTP protocol](https://git-scm.com/docs/http-protocol), the `Content-Type` header, and the magic
+//! bytes at the start of the download.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::*;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct FilePin {
+    /// URL of a file that never changes its contents
+    pub url: Url,
+}
+
+impl diff::Diff for FilePin {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![("url".into(), self.url.to_string())]
+    }
+}
+
+/// Static URLs have no version, the hash is all there is
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct FileVersion {}
+
+impl diff::Diff for FileVersion {
+    fn properties(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for FilePin {
+    type Version = FileVersion;
+    type Hashes = GenericHash;
+
+    async fn update(&self, _old: Option<&FileVersion>) -> Result<FileVersion> {
+        Ok(FileVersion {})
+    }
+
+    async fn fetch(&self, _version: &FileVersion) -> Result<GenericHash> {
+        let hash = nix::nix_prefetch_url(&self.url).await?;
+        Ok(GenericHash {
+            hash,
+            archive: None,
+        })
+    }
+}
+
+/// What a URL points to, and thus which kind of pin to create for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Content {
+    /// An archive to unpack, i.e. a tarball or zip file
+    Tarball,
+    /// A single file to take as is
+    File,
+    /// A git repository
+    Git,
+}
+
+impl std::fmt::Display for Content {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Content::Tarball => "tarball",
+            Content::File => "file",
+            Content::Git => "git",
+        })
+    }
+}
+
+impl std::str::FromStr for Content {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tarball" => Ok(Content::Tarball),
+            "file" => Ok(Content::File),
+            "git" => Ok(Content::Git),
+            _ => anyhow::bail!("expected 'tarball', 'file' or 'git', got '{s}'"),
+        }
+    }
+}
+
+/// How many bytes to look at for the magic numbers, see [`nix::ArchiveFormat::sniff`]
+const SNIFF_LENGTH: usize = 512;
+
+/// Sort the content of a URL into the possible kinds of pins, most likely first
+///
+/// More than one result means that the content is ambiguous. For example, a gzip stream may
+/// either be a tarball or a single compressed file, and only the file name can tell them apart.
+pub fn classify(content_type: Option<&str>, head: &[u8], path: &str) -> Vec<Content> {
+    use nix::ArchiveFormat;
+
+    let name = path.rsplit('/').next().unwrap_or_default().to_lowercase();
+    let content_type = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|content_type| content_type.trim().to_lowercase());
+    let format = ArchiveFormat::sniff(head).or(match content_type.as_deref() {
+        Some("application/zip") => Some(ArchiveFormat::Zip),
+        Some("application/x-tar") => Some(ArchiveFormat::Tar),
+        Some("application/gzip" | "application/x-gzip") => Some(ArchiveFormat::TarGz),
+        Some("application/x-xz") => Some(ArchiveFormat::TarXz),
+        Some("application/x-bzip2") => Some(ArchiveFormat::TarBz2),
+        Some("application/zstd") => Some(ArchiveFormat::TarZst),
+        _ => None,
+    });
+
+    match format {
+        Some(ArchiveFormat::Zip | ArchiveFormat::Tar) => vec![Content::Tarball],
+        /* Compressed, but not necessarily a tarball */
+        Some(_) if ArchiveFormat::from_path(&name).is_some() => vec![Content::Tarball],
+        Some(_)
+            if [".gz", ".xz", ".bz2", ".zst"]
+                .iter()
+                .any(|suffix| name.ends_with(suffix)) =>
+        {
+            /* Something like `data.json.gz`, which won't unpack */
+            vec![Content::File]
+        },
+        Some(_) => vec![Content::Tarball, Content::File],
+        None if content_type.as_deref() == Some("text/html") => {
+            /* Most likely the web page of a project rather than something to download */
+            vec![Content::Git, Content::Tarball, Content::File]
+        },
+        None => vec![Content::File],
+    }
+}
+
+/// Check whether the URL is a git repository served via the smart HTTP protocol
+async fn is_git_repository(url: &Url) -> bool {
+    let mut probe = url.clone();
+    probe
+        .path_segments_mut()
+        .map(|mut segments| {
+            segments.pop_if_empty().extend(["info", "refs"]);
+        })
+        .ok();
+    probe
+        .query_pairs_mut()
+        .append_pair("service", "git-upload-pack");
+    let Ok(request) = http_get(probe) else {
+        return false;
+    };
+    match request.send_throttled().await {
+        Ok(response) => {
+            response.status().is_success()
+                && response
+                    .headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .is_some_and(|value| value == "application/x-git-upload-pack-advertisement")
+        },
+        Err(_) => false,
+    }
+}
+
+/// Find out what the URL points to, see [`classify`]
+pub async fn probe(url: &Url) -> Result<Vec<Content>> {
+    match url.scheme() {
+        "http" | "https" => {},
+        /* Everything else can only be cloned */
+        _ => return Ok(vec![Content::Git]),
+    }
+    if is_git_repository(url).await {
+        return Ok(vec![Content::Git]);
+    }
+
+    provenance::record_endpoint(url);
+    /* Only download the start, if the server allows it. Otherwise stop reading after that. */
+    let mut response = http_get(url.clone())?
+        .header(
+            reqwest::header::RANGE,
+            format!("bytes=0-{}", SNIFF_LENGTH - 1),
+        )
+        .send_throttled()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("Couldn't download {url}"))?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned);
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    while head.len() < SNIFF_LENGTH {
+        match response.chunk().await? {
+            Some(chunk) => head.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    head.truncate(SNIFF_LENGTH);
+
+    /* Redirects may lead to a more telling file name */
+    Ok(classify(
+        content_type.as_deref(),
+        &head,
+        response.url().path(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let gzip = [0x1f, 0x8b, 0x08, 0x00];
+        assert_eq!(
+            classify(Some("application/octet-stream"), &gzip, "/v1.0.tar.gz"),
+            vec![Content::Tarball]
+        );
+        assert_eq!(classify(None, &gzip, "/dump.json.gz"), vec![Content::File]);
+        assert_eq!(
+            classify(None, &gzip, "/download/v1.0"),
+            vec![Content::Tarball, Content::File]
+        );
+        assert_eq!(
+            classify(None, b"PK\x03\x04rest", "/download"),
+            vec![Content::Tarball]
+        );
+
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(classify(None, &tar, "/archive"), vec![Content::Tarball]);
+
+        assert_eq!(
+            classify(Some("application/json"), b"{}", "/data.json"),
+            vec![Content::File]
+        );
+        assert_eq!(
+            classify(
+                Some("text/html; charset=utf-8"),
+                b"<!DOCTYPE html>",
+                "/owner/repo"
+            ),
+            vec![Content::Git, Content::Tarball, Content::File]
+        );
+        assert_eq!("file".parse::<Content>().unwrap(), Content::File);
+    }
+}
@@ -1,4 +1,11 @@
-//! Convert+Import Nix flake lock files
+//! Pin flakes, and convert Nix flake lock files from and to pins
+//!
+//! Flake references like `github:owner/repo/branch` are locked by Nix itself, via
+//! `nix flake metadata`. This covers every kind of input Nix supports, at the cost of requiring a
+//! Nix with flakes support, both for npins and for evaluating the pins (`builtins.fetchTree`).
+//!
+//! Exporting goes the other way: the pins become the inputs of a `flake.lock`, locked to the
+//! same revisions and hashes, so that a flake can expose the same sources to downstream users.
 
 use crate::*;
 use anyhow::{Context, Result};
@@ -6,6 +13,100 @@ use git::fetch_default_branch;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct FlakeRefPin {
+    /// The flake reference, e.g. `github:owner/repo/branch` or `git+https://example.org/repo.git`
+    pub flake: String,
+}
+
+impl diff::Diff for FlakeRefPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![("flake".into(), self.flake.clone())]
+    }
+}
+
+impl FlakeRefPin {
+    /// A name for the pin, i.e. the repository's name for most kinds of references
+    pub fn guess_name(&self) -> Option<String> {
+        let (scheme, rest) = self.flake.split_once(':')?;
+        let path = rest.split(['?', '#']).next().unwrap_or_default();
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let name = match scheme {
+            /* `<owner>/<repo>[/<ref or revision>]` */
+            "github" | "gitlab" | "sourcehut" => segments.get(1)?,
+            /* Registry entries like `nixpkgs/nixos-24.05` */
+            "flake" => segments.first()?,
+            _ => segments.last()?,
+        };
+        Some(name.strip_suffix(".git").unwrap_or(name).to_owned())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FlakeLock {
+    /// The flake reference locked to a specific revision, including the `narHash`
+    pub locked_url: String,
+    /// Timestamp of the locked revision
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+}
+
+impl diff::Diff for FlakeLock {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("locked_url".into(), self.locked_url.clone())),
+            self.last_modified
+                .map(|last_modified| ("last_modified".into(), last_modified.to_string())),
+            self.revision
+                .as_ref()
+                .map(|revision| ("revision".into(), revision.clone())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for FlakeRefPin {
+    type Version = FlakeLock;
+    type Hashes = GenericHash;
+
+    async fn update(&self, old: Option<&FlakeLock>) -> Result<FlakeLock> {
+        let metadata = nix::nix_flake_metadata(&self.flake, true).await?;
+        if let (Some(old), Some(last_modified)) = (
+            old.and_then(|old| old.last_modified),
+            metadata.last_modified,
+        ) {
+            anyhow::ensure!(
+                last_modified >= old,
+                "Failed to ensure monotonicity, the latest revision was last modified at {} but the current one at {}",
+                last_modified,
+                old,
+            );
+        }
+        Ok(FlakeLock {
+            locked_url: metadata.url,
+            last_modified: metadata.last_modified,
+            revision: metadata.revision,
+        })
+    }
+
+    async fn fetch(&self, version: &FlakeLock) -> Result<GenericHash> {
+        /* The locked reference contains the hash already, this makes sure that it is actually right */
+        let metadata = nix::nix_flake_metadata(&version.locked_url, false).await?;
+        Ok(GenericHash {
+            hash: metadata.locked.nar_hash,
+            archive: None,
+        })
+    }
+}
+
 /// Pin entry from a nix flake's lock file
 ///
 /// Flake locks have a two-part structure: the input's specification, and the
@@ -40,8 +141,16 @@ pub struct FlakeLocked {
     /// git ref in all git input types
     #[serde(rename = "ref")]
     ref_: Option<String>,
-    /// the input's hash. not used, but kept here in case we want to implement
-    /// also importing the pins themselves
+    /// the locked revision of all git input types
+    rev: Option<String>,
+    /// whether a generic git input includes submodules
+    #[serde(default)]
+    submodules: bool,
+    /// the store or file system path of a path input
+    path: Option<String>,
+    #[serde(rename = "lastModified")]
+    last_modified: Option<u64>,
+    /// the input's hash, which is kept when importing
     #[serde(rename = "narHash")]
     nar_hash: String,
 }
@@ -64,6 +173,7 @@ impl FlakePin {
 }
 
 impl FlakePin {
+    /// The npins pin of the input, locked to the same revision with the same hash
     pub async fn try_to_pin(self: FlakePin) -> Result<Pin, anyhow::Error> {
         use FlakeType::*;
 
@@ -76,66 +186,121 @@ impl FlakePin {
                 // TODO: parsing the query string to retrieve servers other than
                 // gitlab.com is not supported for now, but could be added.
                 let branch = self.fetch_default_branch("https://gitlab.com").await?;
-                git::GitPin::new(
-                    git::Repository::gitlab(
-                        format!(
-                            "{}/{}",
-                            self.locked
-                                .owner
-                                .context("missing field owner in gitlab flake input")?,
-                            self.locked
-                                .repo
-                                .context("missing field repo in gitlab flake input")?
-                        ),
-                        None,
-                        None,
-                    ),
-                    branch,
-                    false,
-                )
-                .into()
-            },
-            Github => {
-                let branch = self.fetch_default_branch("https://github.com").await?;
-                git::GitPin::new(
-                    git::Repository::github(
+                let repository = git::Repository::gitlab(
+                    format!(
+                        "{}/{}",
                         self.locked
                             .owner
-                            .context("missing owner field in github flake input")?,
+                            .as_ref()
+                            .context("missing field owner in gitlab flake input")?,
                         self.locked
                             .repo
-                            .context("missing field repo in github flake input")?,
+                            .as_ref()
+                            .context("missing field repo in gitlab flake input")?
                     ),
-                    branch,
-                    false,
-                )
-                .into()
+                    None,
+                    None,
+                );
+                self.git_pin(git::GitPin::new(repository, branch, false))?
+            },
+            Github => {
+                let branch = self.fetch_default_branch("https://github.com").await?;
+                let repository = git::Repository::github(
+                    self.locked
+                        .owner
+                        .as_ref()
+                        .context("missing owner field in github flake input")?,
+                    self.locked
+                        .repo
+                        .as_ref()
+                        .context("missing field repo in github flake input")?,
+                );
+                self.git_pin(git::GitPin::new(repository, branch, false))?
             },
             Git => {
-                let mut ref_ = self.locked.ref_.context("missing ref on git flake input")?;
+                let mut ref_ = self
+                    .locked
+                    .ref_
+                    .clone()
+                    .context("missing ref on git flake input")?;
                 if let Some(shortened) = ref_.strip_prefix("refs/heads/") {
                     ref_ = shortened.to_string();
                 }
-                git::GitPin::new(
-                    git::Repository::git(
-                        self.locked.url.context("missing url on git flake input")?,
-                    ),
-                    ref_,
-                    false,
-                )
-                .into()
+                let repository = git::Repository::git(
+                    self.locked
+                        .url
+                        .clone()
+                        .context("missing url on git flake input")?,
+                );
+                self.git_pin(git::GitPin::new(repository, ref_, self.locked.submodules))?
             },
             Tarball => {
                 let url = self
                     .original
                     .url
                     .context("missing url on a tarball flake input")?;
-                tarball::TarballPin { url }.into()
+                /* The locked URL is the immutable one the original URL pointed to */
+                let locked_url = self.locked.url.filter(|locked| *locked != url);
+                Pin::Tarball {
+                    input: tarball::TarballPin::new(url),
+                    version: Some(tarball::LockedTarball {
+                        locked_url,
+                        resolved_url: None,
+                        etag: None,
+                        last_modified: None,
+                    }),
+                    hashes: Some(GenericHash {
+                        hash: self.locked.nar_hash,
+                        archive: None,
+                    }),
+                    metadata: Default::default(),
+                }
+            },
+            /* There is no pin type for paths, but `fetchTree` can fetch them just the same */
+            Path => {
+                let path = self
+                    .locked
+                    .path
+                    .context("missing path on a path flake input")?;
+                anyhow::ensure!(
+                    path.starts_with('/'),
+                    "The relative path input {} can only be used from within the flake",
+                    path
+                );
+                let locked_url = format!(
+                    "path:{path}?narHash={}",
+                    url::form_urlencoded::byte_serialize(self.locked.nar_hash.as_bytes())
+                        .collect::<String>()
+                );
+                Pin::Flake {
+                    input: FlakeRefPin {
+                        flake: format!("path:{path}"),
+                    },
+                    version: Some(FlakeLock {
+                        locked_url,
+                        last_modified: self.locked.last_modified,
+                        revision: None,
+                    }),
+                    hashes: Some(GenericHash {
+                        hash: self.locked.nar_hash,
+                        archive: None,
+                    }),
+                    metadata: Default::default(),
+                }
             },
-            Path => anyhow::bail!("Path inputs are currently not supported by npins."),
         })
     }
 
+    /// A git pin at the locked revision
+    fn git_pin(&self, input: git::GitPin) -> Result<Pin> {
+        let revision = self
+            .locked
+            .rev
+            .clone()
+            .context("missing rev on a locked git flake input")?;
+        import::locked_git_pin(input, revision, self.locked.nar_hash.clone())
+    }
+
     async fn fetch_default_branch(self: &FlakePin, prefix: &str) -> Result<String, anyhow::Error> {
         match &self.original.ref_ {
             Some(a) => Ok(a.to_owned()),
@@ -151,3 +316,367 @@ impl FlakePin {
         }
     }
 }
+
+/// The direct inputs of a `flake.lock`
+pub struct FlakeLockFile {
+    nodes: serde_json::Map<String, serde_json::Value>,
+    /// Input name to node name
+    inputs: BTreeMap<String, String>,
+}
+
+impl FlakeLockFile {
+    pub fn read(path: &std::path::Path) -> Result<Self> {
+        let flake: serde_json::Value = import::read_json(path, "flake.lock")?;
+        let nodes = flake
+            .get("nodes")
+            .context("flake.lock missing key `nodes`")?
+            .as_object()
+            .context("`nodes` key does not contain an object")?;
+
+        let root_name = flake
+            .get("root")
+            .context("missing `root` key")?
+            .as_str()
+            .context("`root` key of flake lockfile is not a string")?;
+        let root = nodes
+            .get(root_name)
+            .context("flake.lock missing key `root`")?
+            .get("inputs")
+            .context("`root` key missing `inputs` key")?
+            .as_object()
+            .context("`root` key is not an object")?;
+
+        let inputs = root
+            .into_iter()
+            .map(|(key, value)| Some((key.to_string(), value.as_str()?.to_string())))
+            .collect::<Option<_>>()
+            .context(format!(
+                "root flake input `{root_name}` had unexpected format and could not be read"
+            ))?;
+        Ok(FlakeLockFile {
+            nodes: nodes.clone(),
+            inputs,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl import::Importer for FlakeLockFile {
+    fn source(&self) -> &str {
+        "flake.lock"
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.inputs.keys().cloned().collect()
+    }
+
+    async fn import(&self, name: &str) -> Result<Option<Pin>> {
+        let node = &self.inputs[name];
+        let pin = self
+            .nodes
+            .get(node)
+            .ok_or_else(|| anyhow::format_err!("Node '{}' not found in flake.lock", node))?;
+        let pin: FlakePin = serde_json::from_value(pin.clone())
+            .context("Pin is either invalid, or we don't support it")?;
+
+        if pin.is_indirect() {
+            log::info!("skipping indirect input {}", name);
+            return Ok(None);
+        }
+        Ok(Some(pin.try_to_pin().await?))
+    }
+}
+
+/// A pin as flake input: what to track (`original`) and where it currently points (`locked`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlakeInput {
+    pub original: serde_json::Map<String, serde_json::Value>,
+    pub locked: serde_json::Map<String, serde_json::Value>,
+}
+
+impl FlakeInput {
+    /// The `original` as flake reference, for the `inputs` of a `flake.nix`
+    pub fn url(&self) -> String {
+        let field = |name: &str| self.original[name].as_str().unwrap_or_default();
+        let reference = self.original.get("ref").and_then(|ref_| ref_.as_str());
+        match field("type") {
+            "github" => match reference {
+                Some(reference) => {
+                    format!("github:{}/{}/{}", field("owner"), field("repo"), reference)
+                },
+                None => format!("github:{}/{}", field("owner"), field("repo")),
+            },
+            "git" => {
+                let mut url = format!("git+{}", field("url"));
+                let mut query = url::form_urlencoded::Serializer::new(String::new());
+                if let Some(reference) = reference {
+                    query.append_pair("ref", reference);
+                }
+                if self.original.get("submodules") == Some(&true.into()) {
+                    query.append_pair("submodules", "1");
+                }
+                let query = query.finish();
+                if !query.is_empty() {
+                    url.push('?');
+                    url.push_str(&query);
+                }
+                url
+            },
+            other => format!("{}+{}", other, field("url")),
+        }
+    }
+}
+
+/// The flake input equivalent to a pin, if there is one
+///
+/// Flakes hash everything as directory, so only pins of directories can be exported, and pins that
+/// are locked by Nix already have no attributes to export.
+pub fn flake_input(pin: &Pin) -> Result<Option<FlakeInput>> {
+    if let Some(finding) = check::check_pin(pin).first() {
+        anyhow::bail!(
+            "Refusing to export, as {finding}. {}",
+            check::CREDENTIALS_ADVICE
+        );
+    }
+    let spec = serde_json::to_value(pin)?;
+    let field = |field: &str| spec[field].as_str();
+    let Some(hash) = field("hash") else {
+        anyhow::bail!("The pin has not been fetched yet, run `npins update` first");
+    };
+    let object = |value: serde_json::Value| match value {
+        serde_json::Value::Object(map) => map,
+        _ => unreachable!(),
+    };
+    let tarball = |url: &str, locked_url: &str| FlakeInput {
+        original: object(serde_json::json!({"type": "tarball", "url": url})),
+        locked: object(serde_json::json!({"type": "tarball", "url": locked_url, "narHash": hash})),
+    };
+
+    Ok(Some(match field("type").unwrap_or_default() {
+        "Git" | "GitRelease" => {
+            let repository = pin.repository().context("Git pins have a repository")?;
+            let revision = field("revision").context("The pin has no revision")?;
+            /* Release pins track their tag, like `npins add git --at` does */
+            let reference = field("branch").or(field("version"));
+            let submodules = spec["submodules"].as_bool().unwrap_or(false);
+            let mut original = match repository {
+                git::Repository::GitHub { owner, repo, .. } if !submodules => {
+                    object(serde_json::json!({"type": "github", "owner": owner, "repo": repo}))
+                },
+                _ => {
+                    let mut original = object(serde_json::json!({
+                        "type": "git",
+                        "url": repository.git_url()?.to_string(),
+                    }));
+                    if submodules {
+                        original.insert("submodules".into(), true.into());
+                    }
+                    original
+                },
+            };
+            let mut locked = original.clone();
+            if let Some(reference) = reference {
+                original.insert("ref".into(), reference.into());
+                if locked["type"] == "git" {
+                    locked.insert("ref".into(), reference.into());
+                }
+            }
+            locked.insert("rev".into(), revision.into());
+            locked.insert("narHash".into(), hash.into());
+            FlakeInput { original, locked }
+        },
+        "Tarball" => {
+            let url = field("url").context("The pin has no URL")?;
+            let locked_url = field("locked_url").or(field("resolved_url")).unwrap_or(url);
+            tarball(url, locked_url)
+        },
+        "Channel" | "Hydra" => {
+            let url = field("url").context("The pin has no URL")?;
+            tarball(url, url)
+        },
+        "ReleaseAsset" | "GitLabPackage" | "Manifest" | "Artifact" | "Bucket" | "Ipfs"
+        | "GitHubArtifact" | "GitLabArtifact" | "BuildkiteArtifact"
+            if spec["unpack"].as_bool().unwrap_or(false) =>
+        {
+            let url = field("url").context("The pin has no URL")?;
+            tarball(url, url)
+        },
+        _ => return Ok(None),
+    }))
+}
+
+/// A `flake.lock` with the pins as inputs of the root flake, which are not flakes themselves
+///
+/// Returns the names of the pins that can't be exported along with it.
+pub fn export_lock(pins: &NixPins) -> Result<(serde_json::Value, Vec<String>)> {
+    let mut nodes = serde_json::Map::new();
+    let mut inputs = serde_json::Map::new();
+    let mut skipped = Vec::new();
+    for (name, pin) in &pins.pins {
+        let Some(input) = flake_input(pin).with_context(|| format!("Failed to export '{name}'"))?
+        else {
+            skipped.push(name.clone());
+            continue;
+        };
+        inputs.insert(name.clone(), name.clone().into());
+        nodes.insert(
+            name.clone(),
+            serde_json::json!({
+                "flake": false,
+                "locked": input.locked,
+                "original": input.original,
+            }),
+        );
+    }
+    nodes.insert("root".into(), serde_json::json!({ "inputs": inputs }));
+    Ok((
+        serde_json::json!({
+            "nodes": nodes,
+            "root": "root",
+            "version": 7,
+        }),
+        skipped,
+    ))
+}
+
+/// The `inputs` of a `flake.nix` that go along with [`export_lock`]
+pub fn export_inputs(pins: &NixPins) -> Result<String> {
+    let mut snippet = String::from("inputs = {\n");
+    for (name, pin) in &pins.pins {
+        if let Some(input) = flake_input(pin)? {
+            let name = if name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-'".contains(c))
+                && !name.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '\'')
+            {
+                name.clone()
+            } else {
+                format!("\"{name}\"")
+            };
+            snippet.push_str(&format!(
+                "  {name} = {{\n    url = \"{}\";\n    flake = false;\n  }};\n",
+                input.url()
+            ));
+        }
+    }
+    snippet.push_str("};\n");
+    Ok(snippet)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_try_to_pin() {
+        let pin = |node: serde_json::Value| async move {
+            let pin: FlakePin = serde_json::from_value(node).unwrap();
+            serde_json::to_value(pin.try_to_pin().await.unwrap()).unwrap()
+        };
+
+        let spec = pin(serde_json::json!({
+            "locked": {
+                "lastModified": 1700000000,
+                "narHash": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+                "owner": "NixOS",
+                "repo": "nixpkgs",
+                "rev": "0123456789abcdef0123456789abcdef01234567",
+                "type": "github"
+            },
+            "original": {"owner": "NixOS", "ref": "nixos-unstable", "repo": "nixpkgs", "type": "github"}
+        }))
+        .await;
+        assert_eq!(spec["branch"], "nixos-unstable");
+        assert_eq!(spec["revision"], "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(
+            spec["url"],
+            "https://github.com/NixOS/nixpkgs/archive/0123456789abcdef0123456789abcdef01234567.tar.gz"
+        );
+        assert_eq!(
+            spec["hash"],
+            "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+        );
+
+        let spec = pin(serde_json::json!({
+            "locked": {
+                "lastModified": 1700000000,
+                "narHash": "sha256-BBBB+/BB=",
+                "path": "/nix/store/abc-source",
+                "type": "path"
+            },
+            "original": {"path": "/nix/store/abc-source", "type": "path"}
+        }))
+        .await;
+        assert_eq!(spec["type"], "Flake");
+        assert_eq!(
+            spec["locked_url"],
+            "path:/nix/store/abc-source?narHash=sha256-BBBB%2B%2FBB%3D"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_lock() {
+        let github: Pin = serde_json::from_value(serde_json::json!({
+            "type": "Git",
+            "repository": {"type": "GitHub", "owner": "NixOS", "repo": "nixpkgs"},
+            "branch": "nixos-unstable",
+            "submodules": false,
+            "revision": "0123456789abcdef0123456789abcdef01234567",
+            "url": "https://github.com/NixOS/nixpkgs/archive/0123456789abcdef0123456789abcdef01234567.tar.gz",
+            "hash": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+        }))
+        .unwrap();
+        let file: Pin = serde_json::from_value(serde_json::json!({
+            "type": "File",
+            "url": "https://example.org/data.json",
+            "hash": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+        }))
+        .unwrap();
+        let mut pins = NixPins::default();
+        pins.pins.insert("nixpkgs".into(), github.clone());
+        pins.pins.insert("data".into(), file);
+
+        let (lock, skipped) = export_lock(&pins).unwrap();
+        assert_eq!(skipped, ["data"]);
+        assert_eq!(lock["nodes"]["root"]["inputs"]["nixpkgs"], "nixpkgs");
+
+        /* Importing it again results in the same pin */
+        let node: FlakePin = serde_json::from_value(lock["nodes"]["nixpkgs"].clone()).unwrap();
+        assert_eq!(node.try_to_pin().await.unwrap(), github);
+
+        assert_eq!(
+            export_inputs(&pins).unwrap(),
+            "inputs = {
+  nixpkgs = {
+    url = \"github:NixOS/nixpkgs/nixos-unstable\";
+    flake = false;
+  };
+};
+"
+        );
+    }
+
+    #[test]
+    fn test_guess_name() {
+        let guess = |flake: &str| {
+            FlakeRefPin {
+                flake: flake.into(),
+            }
+            .guess_name()
+        };
+        assert_eq!(
+            guess("github:nix-community/home-manager"),
+            Some("home-manager".into())
+        );
+        assert_eq!(
+            guess("github:NixOS/nixpkgs/nixos-24.05"),
+            Some("nixpkgs".into())
+        );
+        assert_eq!(
+            guess("git+https://git.example.org/tools/repo.git?ref=main"),
+            Some("repo".into())
+        );
+        assert_eq!(guess("flake:nixpkgs/nixos-24.05"), Some("nixpkgs".into()));
+        assert_eq!(guess("nixpkgs"), None);
+    }
+}
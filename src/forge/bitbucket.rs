@@ -0,0 +1,254 @@
+//! Bitbucket Cloud and Bitbucket Server/Data Center
+//!
+//! Both share little more than the name: Cloud has workspaces and the 2.0 API on a separate host,
+//! while self-hosted instances have projects and the 1.0 REST API. Neither has a concept of releases.
+
+use super::*;
+use crate::get_and_deserialize;
+use anyhow::Context;
+use serde::Deserialize;
+
+pub struct Bitbucket {
+    /// The Bitbucket Server/Data Center instance, or `None` for <https://bitbucket.org>
+    pub server: Option<Url>,
+    /// The workspace on Bitbucket Cloud, the project key on Bitbucket Server
+    pub owner: String,
+    pub repo: String,
+}
+
+impl Bitbucket {
+    /// Cloud: `https://api.bitbucket.org/2.0/repositories/<owner>/<repo>/<path…>`,
+    /// Server: `<server>/rest/api/1.0/projects/<owner>/repos/<repo>/<path…>`
+    fn api_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let url = match &self.server {
+            None => url_with_segments(
+                &"https://api.bitbucket.org/2.0/repositories/".parse()?,
+                [&self.owner, &self.repo],
+            )?,
+            Some(server) => url_with_segments(
+                server,
+                [
+                    "rest",
+                    "api",
+                    "1.0",
+                    "projects",
+                    &self.owner,
+                    "repos",
+                    &self.repo,
+                ],
+            )?,
+        };
+        url_with_segments(&url, path)
+    }
+}
+
+#[async_trait::async_trait]
+impl Forge for Bitbucket {
+    fn git_url(&self) -> Result<Url> {
+        let repo = format!("{}.git", self.repo);
+        match &self.server {
+            None => url_with_segments(&"https://bitbucket.org/".parse()?, [&self.owner, &repo]),
+            Some(server) => url_with_segments(server, ["scm", &self.owner, &repo]),
+        }
+    }
+
+    fn archive_url(&self, revision: &str) -> Result<Url> {
+        match &self.server {
+            None => url_with_segments(
+                &"https://bitbucket.org/".parse()?,
+                [self.owner.clone(), self.repo.clone(), "get".to_owned()]
+                    .into_iter()
+                    .chain(ref_segments(revision, ".tar.gz")),
+            ),
+            /* https://developer.atlassian.com/server/bitbucket/rest/v906/api-group-repository/#api-api-latest-projects-projectkey-repos-repositoryslug-archive-get */
+            Some(_) => {
+                let mut url = self.api_url(["archive"])?;
+                url.query_pairs_mut()
+                    .append_pair("at", revision)
+                    .append_pair("format", "tar.gz");
+                Ok(url)
+            },
+        }
+    }
+
+    async fn commit_timestamp(&self, revision: &str) -> Result<Option<String>> {
+        Ok(Some(match &self.server {
+            /* https://developer.atlassian.com/cloud/bitbucket/rest/api-group-commits/#api-repositories-workspace-repo-slug-commit-commit-get */
+            None => {
+                let commit: CloudCommit = get_and_deserialize(self.api_url(["commit", revision])?)
+                    .await
+                    .context("Couldn't fetch timestamp")?;
+                commit.date
+            },
+            Some(_) => {
+                let commit: ServerCommit =
+                    get_and_deserialize(self.api_url(["commits", revision])?)
+                        .await
+                        .context("Couldn't fetch timestamp")?;
+                crate::provenance::timestamp(
+                    std::time::UNIX_EPOCH
+                        + std::time::Duration::from_millis(commit.author_timestamp),
+                )
+            },
+        }))
+    }
+
+    async fn file_contents(&self, revision: &str, path: &str) -> Result<Option<String>> {
+        let url = match &self.server {
+            None => self.api_url(
+                ["src".to_owned(), revision.to_owned()]
+                    .into_iter()
+                    .chain(ref_segments(path, "")),
+            )?,
+            Some(_) => {
+                let mut url =
+                    self.api_url(["raw".to_owned()].into_iter().chain(ref_segments(path, "")))?;
+                url.query_pairs_mut().append_pair("at", revision);
+                url
+            },
+        };
+        get_text(url, &[]).await
+    }
+
+    async fn list_releases(&self) -> Result<Vec<Release>> {
+        Ok(Vec::new())
+    }
+
+    async fn compare(&self, from: &str, to: &str) -> Result<Comparison> {
+        /* Both list the commits newest first, and paginated. We only look at the first page. */
+        let commits = match &self.server {
+            /* https://developer.atlassian.com/cloud/bitbucket/rest/api-group-commits/#api-repositories-workspace-repo-slug-commits-revision-get */
+            None => {
+                let mut url = self.api_url(["commits", to])?;
+                url.query_pairs_mut().append_pair("exclude", from);
+                let page: Page<CloudCommit> = get_and_deserialize(url)
+                    .await
+                    .context("Couldn't compare the revisions")?;
+                page.values
+                    .into_iter()
+                    .map(|commit| CommitSummary {
+                        revision: commit.hash,
+                        summary: summary(&commit.message),
+                        author: commit.author.map(|author| author.name().to_owned()),
+                    })
+                    .collect::<Vec<_>>()
+            },
+            Some(_) => {
+                let mut url = self.api_url(["commits"])?;
+                url.query_pairs_mut()
+                    .append_pair("since", from)
+                    .append_pair("until", to);
+                let page: Page<ServerCommit> = get_and_deserialize(url)
+                    .await
+                    .context("Couldn't compare the revisions")?;
+                page.values
+                    .into_iter()
+                    .map(|commit| CommitSummary {
+                        revision: commit.id,
+                        summary: summary(&commit.message),
+                        author: commit.author.map(|author| author.name),
+                    })
+                    .collect::<Vec<_>>()
+            },
+        };
+
+        Ok(Comparison {
+            commits: commits.into_iter().rev().collect(),
+            total_commits: None,
+            web_url: None,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Page<T> {
+    values: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CloudCommit {
+    hash: String,
+    message: String,
+    date: String,
+    author: Option<CloudAuthor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CloudAuthor {
+    /// As in git, `Name <email>`
+    raw: String,
+}
+
+impl CloudAuthor {
+    fn name(&self) -> &str {
+        self.raw
+            .split_once(" <")
+            .map_or(self.raw.as_str(), |(name, _)| name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerCommit {
+    id: String,
+    message: String,
+    /// Milliseconds since the epoch
+    author_timestamp: u64,
+    author: Option<ServerAuthor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServerAuthor {
+    name: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cloud_urls() {
+        let forge = Bitbucket {
+            server: None,
+            owner: "tutorials".into(),
+            repo: "markdowndemo".into(),
+        };
+        assert_eq!(
+            forge.git_url().unwrap().as_str(),
+            "https://bitbucket.org/tutorials/markdowndemo.git"
+        );
+        assert_eq!(
+            forge.archive_url("v1.0").unwrap().as_str(),
+            "https://bitbucket.org/tutorials/markdowndemo/get/v1.0.tar.gz"
+        );
+        assert_eq!(
+            forge.api_url(["commit", "a#b"]).unwrap().as_str(),
+            "https://api.bitbucket.org/2.0/repositories/tutorials/markdowndemo/commit/a%23b"
+        );
+    }
+
+    #[test]
+    fn test_server_urls() {
+        let forge = Bitbucket {
+            server: Some("https://example.org/bitbucket/".parse().unwrap()),
+            owner: "PRJ".into(),
+            repo: "tool".into(),
+        };
+        assert_eq!(
+            forge.git_url().unwrap().as_str(),
+            "https://example.org/bitbucket/scm/PRJ/tool.git"
+        );
+        assert_eq!(
+            forge.archive_url("release/v1 β").unwrap().as_str(),
+            "https://example.org/bitbucket/rest/api/1.0/projects/PRJ/repos/tool/archive?at=release%2Fv1+%CE%B2&format=tar.gz"
+        );
+        assert_eq!(
+            forge.api_url(["commits", "abc"]).unwrap().as_str(),
+            "https://example.org/bitbucket/rest/api/1.0/projects/PRJ/repos/tool/commits/abc"
+        );
+    }
+}
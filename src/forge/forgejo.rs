@@ -0,0 +1,261 @@
+//! Forgejo (and Gitea, which shares the same API)
+
+use super::*;
+use crate::get_and_deserialize;
+use anyhow::Context;
+use serde::Deserialize;
+
+pub struct Forgejo {
+    pub server: Url,
+    pub owner: String,
+    pub repo: String,
+}
+
+impl Forgejo {
+    /// `<server>/<owner>/<repo>/<path…>`
+    fn web_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let url = url_with_segments(&self.server, [&self.owner, &self.repo])?;
+        url_with_segments(&url, path)
+    }
+
+    /// `<server>/api/v1/repos/<owner>/<repo>/<path…>`
+    fn api_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let url = url_with_segments(
+            &self.server,
+            ["api", "v1", "repos", &self.owner, &self.repo],
+        )?;
+        url_with_segments(&url, path)
+    }
+}
+
+#[async_trait::async_trait]
+impl Forge for Forgejo {
+    fn git_url(&self) -> Result<Url> {
+        url_with_segments(&self.server, [&self.owner, &format!("{}.git", self.repo)])
+    }
+
+    fn archive_url(&self, revision: &str) -> Result<Url> {
+        self.web_url(
+            ["archive".to_owned()]
+                .into_iter()
+                .chain(ref_segments(revision, ".tar.gz")),
+        )
+    }
+
+    fn release_archive_url(&self, tag: &str) -> Result<Url> {
+        self.api_url(
+            ["archive".to_owned()]
+                .into_iter()
+                .chain(ref_segments(tag, ".tar.gz")),
+        )
+    }
+
+    async fn file_contents(&self, revision: &str, path: &str) -> Result<Option<String>> {
+        /* https://codeberg.org/api/swagger#/repository/repoGetRawFile */
+        let mut url = self.api_url(["raw".to_owned()].into_iter().chain(ref_segments(path, "")))?;
+        url.query_pairs_mut().append_pair("ref", revision);
+        get_text(url, &[]).await
+    }
+
+    async fn api_ref(&self, ref_: &str) -> Result<Option<RemoteInfo>> {
+        /* https://codeberg.org/api/swagger#/repository/repoListGitRefs, which matches prefixes */
+        let path = ref_.strip_prefix("refs/").unwrap_or(ref_);
+        let refs: Vec<GitRef> = get_and_deserialize(
+            self.api_url(
+                ["git".to_owned(), "refs".to_owned()]
+                    .into_iter()
+                    .chain(ref_segments(path, "")),
+            )?,
+        )
+        .await
+        .with_context(|| format!("Couldn't fetch {ref_}"))?;
+        let found = refs
+            .into_iter()
+            .find(|found| found.ref_ == ref_)
+            .with_context(|| format!("{ref_} does not exist"))?;
+        Ok(Some(found.into()))
+    }
+
+    async fn api_tags(&self) -> Result<Option<Vec<RemoteInfo>>> {
+        /* https://codeberg.org/api/swagger#/repository/repoListGitRefs */
+        let refs: Vec<GitRef> = get_and_deserialize(self.api_url(["git", "refs", "tags"])?)
+            .await
+            .context("Couldn't list the tags")?;
+        Ok(Some(refs.into_iter().map(Into::into).collect()))
+    }
+
+    async fn list_releases(&self) -> Result<Vec<Release>> {
+        /* https://codeberg.org/api/swagger#/repository/repoListReleases */
+        let mut url = self.api_url(["releases"])?;
+        url.query_pairs_mut().append_pair("limit", "50");
+        let releases: Vec<ForgejoRelease> = get_and_deserialize(url)
+            .await
+            .context("Couldn't fetch the releases")?;
+
+        Ok(releases
+            .into_iter()
+            .map(|release| Release {
+                tag: release.tag_name,
+                name: release.name,
+                draft: release.draft,
+                prerelease: release.prerelease,
+                historical: false,
+                assets: release
+                    .assets
+                    .into_iter()
+                    .map(|asset| Asset {
+                        name: asset.name,
+                        url: asset.browser_download_url,
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+
+    async fn compare(&self, from: &str, to: &str) -> Result<Comparison> {
+        /* https://codeberg.org/api/swagger#/repository/repoCompareDiff */
+        let range = format!("{from}...{to}");
+        let comparison: ForgejoComparison = get_and_deserialize(self.api_url(["compare", &range])?)
+            .await
+            .context("Couldn't compare the revisions")?;
+
+        Ok(Comparison {
+            commits: comparison
+                .commits
+                .into_iter()
+                .map(|commit| CommitSummary {
+                    revision: commit.sha,
+                    summary: summary(&commit.commit.message),
+                    author: commit.commit.author.map(|author| author.name),
+                })
+                .collect(),
+            total_commits: Some(comparison.total_commits),
+            web_url: self.web_url(["compare", &range]).ok(),
+        })
+    }
+
+    async fn is_archived(&self) -> Result<Option<bool>> {
+        /* https://codeberg.org/api/swagger#/repository/repoGet */
+        let repository: ArchivedFlag = get_and_deserialize(self.api_url::<[&str; 0]>([])?)
+            .await
+            .context("Couldn't fetch the repository")?;
+        Ok(Some(repository.archived))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ForgejoRelease {
+    tag_name: String,
+    name: Option<String>,
+    draft: bool,
+    prerelease: bool,
+    #[serde(default)]
+    assets: Vec<ForgejoAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForgejoAsset {
+    name: String,
+    browser_download_url: Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForgejoComparison {
+    total_commits: usize,
+    commits: Vec<ForgejoCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForgejoCommit {
+    sha: String,
+    commit: ForgejoCommitDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForgejoCommitDetails {
+    message: String,
+    author: Option<ForgejoCommitAuthor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForgejoCommitAuthor {
+    name: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn forge(server: &str) -> Forgejo {
+        Forgejo {
+            server: server.parse().unwrap(),
+            owner: "lix-project".into(),
+            repo: "lix".into(),
+        }
+    }
+
+    #[test]
+    fn test_urls() {
+        let forge = forge("https://git.lix.systems");
+        assert_eq!(
+            forge.git_url().unwrap().as_str(),
+            "https://git.lix.systems/lix-project/lix.git"
+        );
+        assert_eq!(
+            forge
+                .archive_url("6b1a3b6d5ff2d4e3d6f3c3ec4f8d1a7e9c6e1c84")
+                .unwrap()
+                .as_str(),
+            "https://git.lix.systems/lix-project/lix/archive/6b1a3b6d5ff2d4e3d6f3c3ec4f8d1a7e9c6e1c84.tar.gz"
+        );
+        assert_eq!(
+            forge.release_archive_url("2.90.0").unwrap().as_str(),
+            "https://git.lix.systems/api/v1/repos/lix-project/lix/archive/2.90.0.tar.gz"
+        );
+    }
+
+    #[test]
+    fn test_release_assets() {
+        let release: ForgejoRelease = serde_json::from_str(
+            r#"{
+                "tag_name": "2.91.1",
+                "name": "Lix 2.91.1",
+                "draft": false,
+                "prerelease": false,
+                "assets": [{
+                    "id": 1,
+                    "name": "lix-2.91.1-x86_64-linux.tar.xz",
+                    "size": 1024,
+                    "browser_download_url": "https://git.lix.systems/lix-project/lix/releases/download/2.91.1/lix-2.91.1-x86_64-linux.tar.xz"
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(release.assets[0].name, "lix-2.91.1-x86_64-linux.tar.xz");
+    }
+
+    #[test]
+    fn test_encoding() {
+        let forge = forge("https://example.org/forgejo/");
+        assert_eq!(
+            forge.git_url().unwrap().as_str(),
+            "https://example.org/forgejo/lix-project/lix.git"
+        );
+        assert_eq!(
+            forge.release_archive_url("release/v1 β").unwrap().as_str(),
+            "https://example.org/forgejo/api/v1/repos/lix-project/lix/archive/release/v1%20%CE%B2.tar.gz"
+        );
+        assert_eq!(
+            forge.api_url(["compare", "a...b#c"]).unwrap().as_str(),
+            "https://example.org/forgejo/api/v1/repos/lix-project/lix/compare/a...b%23c"
+        );
+    }
+}
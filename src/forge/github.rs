@@ -0,0 +1,379 @@
+//! GitHub, and GitHub Enterprise as configured in `npins.toml`, see [`crate::config`]
+
+use super::*;
+use crate::{get_and_deserialize, http_get, ThrottledSend};
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::Value;
+
+pub(crate) fn get_github_url() -> String {
+    crate::config::get()
+        .github
+        .host
+        .clone()
+        .unwrap_or_else(|| String::from("https://github.com"))
+}
+
+pub(crate) fn get_github_api_url() -> String {
+    crate::config::get()
+        .github
+        .api_host
+        .clone()
+        .unwrap_or_else(|| String::from("https://api.github.com"))
+}
+
+pub struct GitHub {
+    pub owner: String,
+    pub repo: String,
+}
+
+impl GitHub {
+    /// `<github>/<owner>/<repo>/<path…>`
+    fn web_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let base: Url = get_github_url()
+            .parse()
+            .context("The GitHub host is not a valid URL")?;
+        let url = url_with_segments(&base, [&self.owner, &self.repo])?;
+        url_with_segments(&url, path)
+    }
+
+    /// `<github_api>/repos/<owner>/<repo>/<path…>`
+    fn api_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let base: Url = get_github_api_url()
+            .parse()
+            .context("The GitHub API host is not a valid URL")?;
+        let url = url_with_segments(&base, ["repos", &self.owner, &self.repo])?;
+        url_with_segments(&url, path)
+    }
+
+    /// The new `(owner, repo)` if GitHub redirects the repository's page to another one
+    ///
+    /// This uses the web page and not the API, as it does not count towards the rate limit.
+    pub async fn renamed(&self) -> Result<Option<(String, String)>> {
+        let base: Url = get_github_url()
+            .parse()
+            .context("The GitHub host is not a valid URL")?;
+        let url = self.web_url::<[&str; 0]>([])?;
+        let response = crate::http_request(reqwest::Method::HEAD, url)?
+            .send_throttled()
+            .await?;
+        /* Private repositories look like missing ones without a token, don't guess */
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+        Ok(renamed_to(&base, response.url()).filter(|(owner, repo)| {
+            !(owner.eq_ignore_ascii_case(&self.owner) && repo.eq_ignore_ascii_case(&self.repo))
+        }))
+    }
+}
+
+/// Extract `(owner, repo)` from the URL of a repository's page, if it is one
+fn renamed_to(base: &Url, url: &Url) -> Option<(String, String)> {
+    if url.host() != base.host() {
+        return None;
+    }
+    let path = url.path().strip_prefix(base.path().trim_end_matches('/'))?;
+    match path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()[..]
+    {
+        /* Owner and repository names are plain ASCII, nothing to decode */
+        [owner, repo] => Some((owner.to_owned(), repo.to_owned())),
+        _ => None,
+    }
+}
+
+#[async_trait::async_trait]
+impl Forge for GitHub {
+    fn git_url(&self) -> Result<Url> {
+        let base: Url = get_github_url()
+            .parse()
+            .context("The GitHub host is not a valid URL")?;
+        url_with_segments(&base, [&self.owner, &format!("{}.git", self.repo)])
+    }
+
+    fn archive_url(&self, revision: &str) -> Result<Url> {
+        self.web_url(
+            ["archive".to_owned()]
+                .into_iter()
+                .chain(ref_segments(revision, ".tar.gz")),
+        )
+    }
+
+    fn release_archive_url(&self, tag: &str) -> Result<Url> {
+        self.api_url(
+            ["tarball".to_owned(), "refs".to_owned(), "tags".to_owned()]
+                .into_iter()
+                .chain(ref_segments(tag, "")),
+        )
+    }
+
+    async fn commit_timestamp(&self, revision: &str) -> Result<Option<String>> {
+        let url = self.api_url(["commits", revision])?;
+        crate::provenance::record_endpoint(&url);
+
+        let body: Value = http_get(url)?
+            .send_throttled()
+            .await
+            .context("Couldn't fetch timestamp")?
+            .json()
+            .await
+            .context("Couldn't decode response as JSON")?;
+
+        Ok(Some(
+            body["commit"]["author"]["date"]
+                .as_str()
+                .context("Expected date in GitHub API response to be a string")?
+                .to_string(),
+        ))
+    }
+
+    async fn file_contents(&self, revision: &str, path: &str) -> Result<Option<String>> {
+        /* https://docs.github.com/en/rest/repos/contents#get-repository-content */
+        let mut url = self.api_url(
+            ["contents".to_owned()]
+                .into_iter()
+                .chain(ref_segments(path, "")),
+        )?;
+        url.query_pairs_mut().append_pair("ref", revision);
+        get_text(url, &[("Accept", "application/vnd.github.raw+json".into())]).await
+    }
+
+    async fn api_ref(&self, ref_: &str) -> Result<Option<RemoteInfo>> {
+        /* https://docs.github.com/en/rest/git/refs#get-a-reference */
+        let path = ref_.strip_prefix("refs/").unwrap_or(ref_);
+        let found: GitRef = get_and_deserialize(
+            self.api_url(
+                ["git".to_owned(), "ref".to_owned()]
+                    .into_iter()
+                    .chain(ref_segments(path, "")),
+            )?,
+        )
+        .await
+        .with_context(|| format!("Couldn't fetch {ref_}"))?;
+        Ok(Some(found.into()))
+    }
+
+    async fn api_tags(&self) -> Result<Option<Vec<RemoteInfo>>> {
+        /* https://docs.github.com/en/rest/git/refs#list-matching-references */
+        let refs: Vec<GitRef> =
+            get_and_deserialize(self.api_url(["git", "matching-refs", "tags"])?)
+                .await
+                .context("Couldn't list the tags")?;
+        Ok(Some(refs.into_iter().map(Into::into).collect()))
+    }
+
+    async fn list_releases(&self) -> Result<Vec<Release>> {
+        /* https://docs.github.com/en/rest/releases/releases#list-releases */
+        let mut url = self.api_url(["releases"])?;
+        url.query_pairs_mut().append_pair("per_page", "100");
+        let releases: Vec<GitHubRelease> = get_and_deserialize(url)
+            .await
+            .context("Couldn't fetch the releases")?;
+
+        Ok(releases.into_iter().map(Into::into).collect())
+    }
+
+    async fn latest_release(&self, pre_releases: bool) -> Result<Option<Release>> {
+        if pre_releases {
+            /* The "latest" marker never points to a pre-release, so take the newest published one */
+            return Ok(self
+                .list_releases()
+                .await?
+                .into_iter()
+                .find(|release| !release.draft));
+        }
+
+        /* https://docs.github.com/en/rest/releases/releases#get-the-latest-release */
+        let Some(body) = get_text(self.api_url(["releases", "latest"])?, &[]).await? else {
+            return Ok(None);
+        };
+        let release: GitHubRelease =
+            serde_json::from_str(&body).context("Couldn't parse the latest release")?;
+        Ok(Some(release.into()))
+    }
+
+    async fn compare(&self, from: &str, to: &str) -> Result<Comparison> {
+        /* https://docs.github.com/en/rest/commits/commits#compare-two-commits */
+        let comparison: GitHubComparison =
+            get_and_deserialize(self.api_url(["compare", &format!("{from}...{to}")])?)
+                .await
+                .context("Couldn't compare the revisions")?;
+
+        Ok(Comparison {
+            commits: comparison
+                .commits
+                .into_iter()
+                .map(|commit| CommitSummary {
+                    revision: commit.sha,
+                    summary: summary(&commit.commit.message),
+                    author: commit.commit.author.map(|author| author.name),
+                })
+                .collect(),
+            total_commits: Some(comparison.total_commits),
+            web_url: Some(comparison.html_url),
+        })
+    }
+
+    async fn is_archived(&self) -> Result<Option<bool>> {
+        /* https://docs.github.com/en/rest/repos/repos#get-a-repository */
+        let repository: ArchivedFlag = get_and_deserialize(self.api_url::<[&str; 0]>([])?)
+            .await
+            .context("Couldn't fetch the repository")?;
+        Ok(Some(repository.archived))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubRelease {
+    tag_name: String,
+    name: Option<String>,
+    draft: bool,
+    prerelease: bool,
+    #[serde(default)]
+    assets: Vec<GitHubAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubAsset {
+    name: String,
+    browser_download_url: Url,
+}
+
+impl From<GitHubRelease> for Release {
+    fn from(release: GitHubRelease) -> Self {
+        Release {
+            tag: release.tag_name,
+            name: release.name,
+            draft: release.draft,
+            prerelease: release.prerelease,
+            historical: false,
+            assets: release
+                .assets
+                .into_iter()
+                .map(|asset| Asset {
+                    name: asset.name,
+                    url: asset.browser_download_url,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubComparison {
+    html_url: Url,
+    total_commits: usize,
+    commits: Vec<GitHubCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubCommit {
+    sha: String,
+    commit: GitHubCommitDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubCommitDetails {
+    message: String,
+    author: Option<GitHubCommitAuthor>,
+}
+
+/// The git author, as opposed to the GitHub account
+#[derive(Debug, Deserialize)]
+struct GitHubCommitAuthor {
+    name: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn forge() -> GitHub {
+        GitHub {
+            owner: "andir".into(),
+            repo: "npins".into(),
+        }
+    }
+
+    #[test]
+    fn test_urls() {
+        let forge = forge();
+        assert_eq!(
+            forge.git_url().unwrap().as_str(),
+            "https://github.com/andir/npins.git"
+        );
+        assert_eq!(
+            forge
+                .archive_url("1edb0a9cebe046cc915a218c57dbf7f40739aeee")
+                .unwrap()
+                .as_str(),
+            "https://github.com/andir/npins/archive/1edb0a9cebe046cc915a218c57dbf7f40739aeee.tar.gz"
+        );
+        assert_eq!(
+            forge.release_archive_url("v0.3.1").unwrap().as_str(),
+            "https://api.github.com/repos/andir/npins/tarball/refs/tags/v0.3.1"
+        );
+    }
+
+    #[test]
+    fn test_tag_encoding() {
+        let forge = forge();
+        /* Slashes in tags are path separators */
+        assert_eq!(
+            forge.release_archive_url("release/2.0").unwrap().as_str(),
+            "https://api.github.com/repos/andir/npins/tarball/refs/tags/release/2.0"
+        );
+        assert_eq!(
+            forge.archive_url("release/2.0").unwrap().as_str(),
+            "https://github.com/andir/npins/archive/release/2.0.tar.gz"
+        );
+        /* Everything else gets encoded */
+        assert_eq!(
+            forge.release_archive_url("v1.0-β").unwrap().as_str(),
+            "https://api.github.com/repos/andir/npins/tarball/refs/tags/v1.0-%CE%B2"
+        );
+        assert_eq!(
+            forge.release_archive_url("v1.0?x=1#y").unwrap().as_str(),
+            "https://api.github.com/repos/andir/npins/tarball/refs/tags/v1.0%3Fx=1%23y"
+        );
+        assert_eq!(
+            forge.api_url(["compare", "a...b c"]).unwrap().as_str(),
+            "https://api.github.com/repos/andir/npins/compare/a...b%20c"
+        );
+    }
+
+    #[test]
+    fn test_renamed_to() {
+        let base: Url = "https://github.com".parse().unwrap();
+        let url = |url: &str| url.parse::<Url>().unwrap();
+        assert_eq!(
+            renamed_to(&base, &url("https://github.com/NixOS/npins")),
+            Some(("NixOS".into(), "npins".into()))
+        );
+        /* The login page of a private repository */
+        assert_eq!(
+            renamed_to(
+                &base,
+                &url("https://github.com/login?return_to=%2Fandir%2Fnpins")
+            ),
+            None
+        );
+        assert_eq!(renamed_to(&base, &url("https://example.org/a/b")), None);
+
+        let enterprise: Url = "https://example.org/github/".parse().unwrap();
+        assert_eq!(
+            renamed_to(&enterprise, &url("https://example.org/github/owner/repo")),
+            Some(("owner".into(), "repo".into()))
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Abstraction over git hosting platforms ("forges")
+//!
+//! Plain git only lets us list refs. Forges additionally offer archive downloads (which are a lot
+//! faster to fetch than a clone), commit metadata, releases and comparisons between revisions.
+//! Every supported forge implements the [`Forge`] trait, and pin types only ever talk to that trait
+//! (see [`Repository::forge`](crate::git::Repository::forge)). Adding a new forge thus means implementing
+//! [`Forge`] and adding a variant to [`Repository`](crate::git::Repository).
+//!
+//! The methods that have a sensible fallback via git itself (resolving branches and listing tags)
+//! come with a default implementation. In turn, forges can look up refs via their API, for when git
+//! can't reach them, e.g. because a proxy blocks it.
+
+use crate::git::{self, RemoteInfo};
+use crate::ThrottledSend;
+use anyhow::Result;
+use url::Url;
+
+pub mod bitbucket;
+pub mod forgejo;
+pub mod github;
+pub mod gitlab;
+pub mod radicle;
+pub mod sourcehut;
+
+/// A release as published on a forge
+///
+/// Note that these are not the same as git tags: not every tag has a release, and some forges
+/// allow releases without a tag (drafts).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    /// The git tag of the release
+    pub tag: String,
+    /// The human readable title, if any
+    pub name: Option<String>,
+    /// Unpublished draft release
+    pub draft: bool,
+    /// Marked as pre-release by the maintainers
+    pub prerelease: bool,
+    /// Created after the fact for an old version, so it is not the latest one regardless of its date
+    pub historical: bool,
+    /// Files attached to the release, if the forge supports them
+    pub assets: Vec<Asset>,
+}
+
+/// A file attached to a [`Release`], like a prebuilt binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Asset {
+    /// The file name
+    pub name: String,
+    /// Where to download it
+    pub url: Url,
+}
+
+/// A commit in a [`Comparison`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitSummary {
+    pub revision: String,
+    /// The first line of the commit message
+    pub summary: String,
+    /// The name of the author, if the forge tells
+    pub author: Option<String>,
+}
+
+/// The result of comparing two revisions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparison {
+    /// Commits reachable from the newer but not from the older revision, oldest first.
+    /// Forges may truncate this list, see `total_commits`.
+    pub commits: Vec<CommitSummary>,
+    /// Total number of commits, if known
+    pub total_commits: Option<usize>,
+    /// Link to a human readable view of the comparison
+    pub web_url: Option<Url>,
+}
+
+impl Comparison {
+    /// Whether there are no commits, i.e. the history of the older revision contains the newer
+    /// one. Comparing the head of a branch with a revision tells whether the revision is on it
+    pub fn is_empty(&self) -> bool {
+        self.total_commits.unwrap_or(self.commits.len()) == 0
+    }
+}
+
+/// A git hosting platform
+#[async_trait::async_trait]
+pub trait Forge: Send + Sync {
+    /// The URL to the git repository itself
+    fn git_url(&self) -> Result<Url>;
+
+    /// The URL to a tarball of a given revision
+    fn archive_url(&self, revision: &str) -> Result<Url>;
+
+    /// The URL to a tarball of a given tag
+    ///
+    /// Forges may have a dedicated endpoint for this, which avoids ambiguities between tag
+    /// and branch names.
+    fn release_archive_url(&self, tag: &str) -> Result<Url> {
+        self.archive_url(tag)
+    }
+
+    /// Whether the archive of a revision can actually be downloaded
+    ///
+    /// Most forges always serve archives, so there's no need to ask.
+    async fn serves_archives(&self, _revision: &str) -> bool {
+        true
+    }
+
+    /// HTTP headers to authenticate with, if credentials are configured
+    ///
+    /// Credentials must never be part of any URL returned by a forge. Instead, archives that
+    /// need them are downloaded in-process using these headers.
+    fn auth_headers(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    /// Get the current head of a branch
+    async fn resolve_branch(&self, branch: &str) -> Result<RemoteInfo> {
+        git::fetch_branch_head(&self.git_url()?, branch).await
+    }
+
+    /// List all tags of the repository
+    async fn list_tags(&self) -> Result<Vec<RemoteInfo>> {
+        git::fetch_tags(&self.git_url()?).await
+    }
+
+    /// Look up a ref like `refs/heads/main` via the API, for when git can't reach the forge
+    ///
+    /// Like `git ls-remote --refs`, annotated tags resolve to the tag object and not to the commit.
+    /// `Ok(None)` means that the forge does not support this.
+    async fn api_ref(&self, _ref_: &str) -> Result<Option<RemoteInfo>> {
+        Ok(None)
+    }
+
+    /// List all tags via the API, see [`api_ref`](Self::api_ref)
+    async fn api_tags(&self) -> Result<Option<Vec<RemoteInfo>>> {
+        Ok(None)
+    }
+
+    /// The commit timestamp of a revision, if the forge provides one
+    async fn commit_timestamp(&self, _revision: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// The contents of a file at a given revision, if the forge can provide it
+    ///
+    /// `Ok(None)` means that the file does not exist, or that the forge does not support this.
+    async fn file_contents(&self, _revision: &str, _path: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// List the releases published on the forge, newest first
+    async fn list_releases(&self) -> Result<Vec<Release>>;
+
+    /// The release the forge itself considers the latest one, if it has such a notion
+    ///
+    /// With `pre_releases`, the newest published release including pre-releases.
+    /// `Ok(None)` means that callers should fall back to ordering the tags by version.
+    async fn latest_release(&self, _pre_releases: bool) -> Result<Option<Release>> {
+        Ok(None)
+    }
+
+    /// Compare two revisions
+    async fn compare(&self, from: &str, to: &str) -> Result<Comparison>;
+
+    /// Whether the maintainers archived the repository, if the forge has such a notion
+    async fn is_archived(&self) -> Result<Option<bool>> {
+        Ok(None)
+    }
+}
+
+/// A ref as returned by the APIs of GitHub and Forgejo
+#[derive(Debug, serde::Deserialize)]
+struct GitRef {
+    #[serde(rename = "ref")]
+    ref_: String,
+    object: GitObject,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct GitObject {
+    sha: String,
+}
+
+impl From<GitRef> for RemoteInfo {
+    fn from(ref_: GitRef) -> Self {
+        RemoteInfo::new(ref_.object.sha, ref_.ref_)
+    }
+}
+
+/// The part of a forge's repository API response that tells whether it is archived
+#[derive(Debug, serde::Deserialize)]
+struct ArchivedFlag {
+    archived: bool,
+}
+
+/// Append path segments to a base URL, percent-encoding each of them
+///
+/// All forge URLs must be built this way instead of with string formatting. That way, user input
+/// can never accidentally produce `//`, inject a query string, or have a `/` interpreted as a
+/// path separator where it is not meant to be one.
+pub(crate) fn url_with_segments<I>(base: &Url, segments: I) -> Result<Url>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let mut url = base.clone();
+    url.path_segments_mut()
+        .map_err(|()| anyhow::format_err!("URL {base} cannot be used as a base"))?
+        .pop_if_empty()
+        .extend(segments);
+    Ok(url)
+}
+
+/// Split a git ref into path segments and append `suffix` to the last one
+///
+/// Refs may legitimately contain slashes (e.g. `release/2.0`), which forges expect to be
+/// path separators in their archive URLs.
+pub(crate) fn ref_segments(ref_: &str, suffix: &str) -> Vec<String> {
+    let mut segments: Vec<String> = ref_.split('/').map(Into::into).collect();
+    if let Some(last) = segments.last_mut() {
+        last.push_str(suffix);
+    }
+    segments
+}
+
+/// GET a plain text resource, returning `None` if it does not exist
+async fn get_text(url: Url, headers: &[(&'static str, String)]) -> Result<Option<String>> {
+    crate::provenance::record_endpoint(&url);
+    let mut request = crate::http_get(url)?;
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let response = request.send_throttled().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(response.error_for_status()?.text().await?))
+}
+
+/// Get the first line of a commit message
+fn summary(message: &str) -> String {
+    message.lines().next().unwrap_or_default().to_owned()
+}
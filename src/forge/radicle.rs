@@ -0,0 +1,232 @@
+//! Radicle, via the HTTP API of a seed node
+//!
+//! Repositories are identified by their RID (`rad:z…`) and may be replicated on any number of
+//! seeds, one of which we talk to. Seeds serve the canonical refs of a repository over git, along
+//! with a read-only API (`radicle-httpd`). Only some of them serve archives though, so those are
+//! probed before use. Radicle has no releases, only tags.
+
+use super::*;
+use crate::{get_and_deserialize, http_request};
+use anyhow::Context;
+use serde::Deserialize;
+
+pub struct Radicle {
+    /// Of the kind <https://seed.radicle.garden/>
+    pub seed: Url,
+    /// The repository ID, including the `rad:` prefix
+    pub rid: String,
+}
+
+impl Radicle {
+    /// The RID without the `rad:` prefix, as used in git URLs
+    fn id(&self) -> &str {
+        self.rid.strip_prefix("rad:").unwrap_or(&self.rid)
+    }
+
+    /// `<seed>/api/v1/repos/<rid>/<path…>`
+    fn api_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let url = url_with_segments(&self.seed, ["api", "v1", "repos", &self.rid])?;
+        url_with_segments(&url, path)
+    }
+
+    /// `<seed>/raw/<rid>/<path…>`
+    fn raw_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let url = url_with_segments(&self.seed, ["raw", &self.rid])?;
+        url_with_segments(&url, path)
+    }
+
+    /// The repository's project metadata
+    pub async fn project(&self) -> Result<Project> {
+        let repo: RadicleRepo = get_and_deserialize(self.api_url::<[&str; 0]>([])?)
+            .await
+            .with_context(|| format!("Couldn't find {} on {}", self.rid, self.seed))?;
+        Ok(repo.payloads.project)
+    }
+}
+
+#[async_trait::async_trait]
+impl Forge for Radicle {
+    fn git_url(&self) -> Result<Url> {
+        url_with_segments(&self.seed, [&format!("{}.git", self.id())])
+    }
+
+    fn archive_url(&self, revision: &str) -> Result<Url> {
+        self.raw_url(["archive", revision])
+    }
+
+    async fn serves_archives(&self, revision: &str) -> bool {
+        let request = self
+            .archive_url(revision)
+            .and_then(|url| http_request(reqwest::Method::HEAD, url));
+        let response = match request {
+            Ok(request) => request.send_throttled().await,
+            Err(_) => return false,
+        };
+        match response {
+            Ok(response) => response.status().is_success(),
+            Err(err) => {
+                log::debug!(
+                    "Could not check whether {} serves archives: {err}",
+                    self.seed
+                );
+                false
+            },
+        }
+    }
+
+    /// The API only knows the canonical head of the default branch, other branches need git
+    async fn resolve_branch(&self, branch: &str) -> Result<RemoteInfo> {
+        let project = self.project().await?;
+        if project.data.default_branch == branch {
+            Ok(RemoteInfo::new(
+                project.meta.head,
+                format!("refs/heads/{branch}"),
+            ))
+        } else {
+            git::fetch_branch_head(&self.git_url()?, branch).await
+        }
+    }
+
+    async fn commit_timestamp(&self, revision: &str) -> Result<Option<String>> {
+        let commit: RadicleCommit = get_and_deserialize(self.api_url(["commits", revision])?)
+            .await
+            .context("Couldn't fetch timestamp")?;
+        Ok(Some(crate::provenance::timestamp(
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(commit.commit.committer.time),
+        )))
+    }
+
+    async fn file_contents(&self, revision: &str, path: &str) -> Result<Option<String>> {
+        let url = self.raw_url(
+            [revision.to_owned()]
+                .into_iter()
+                .chain(ref_segments(path, "")),
+        )?;
+        get_text(url, &[]).await
+    }
+
+    async fn list_releases(&self) -> Result<Vec<Release>> {
+        Ok(Vec::new())
+    }
+
+    async fn compare(&self, _from: &str, _to: &str) -> Result<Comparison> {
+        anyhow::bail!("Radicle seeds do not support comparing revisions")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RadicleRepo {
+    payloads: Payloads,
+}
+
+#[derive(Debug, Deserialize)]
+struct Payloads {
+    #[serde(rename = "xyz.radicle.project")]
+    project: Project,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Project {
+    pub data: ProjectData,
+    meta: ProjectMeta,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectData {
+    pub name: String,
+    default_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectMeta {
+    /// The canonical head of the default branch
+    head: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RadicleCommit {
+    commit: CommitInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitInfo {
+    committer: Committer,
+}
+
+#[derive(Debug, Deserialize)]
+struct Committer {
+    /// Seconds since the UNIX epoch
+    time: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_urls() {
+        let forge = Radicle {
+            seed: "https://seed.radicle.garden".parse().unwrap(),
+            rid: "rad:z3gqcJUoA1n9HaHKufZs5FCSGazv5".into(),
+        };
+        assert_eq!(
+            forge.git_url().unwrap().as_str(),
+            "https://seed.radicle.garden/z3gqcJUoA1n9HaHKufZs5FCSGazv5.git"
+        );
+        assert_eq!(
+            forge
+                .archive_url("5c4f8ba7c4ed3f0a4eb3c4e66a2b7d8c9f0e1a2b")
+                .unwrap()
+                .as_str(),
+            "https://seed.radicle.garden/raw/rad:z3gqcJUoA1n9HaHKufZs5FCSGazv5/archive/5c4f8ba7c4ed3f0a4eb3c4e66a2b7d8c9f0e1a2b"
+        );
+        assert_eq!(
+            forge.api_url(["commits", "HEAD"]).unwrap().as_str(),
+            "https://seed.radicle.garden/api/v1/repos/rad:z3gqcJUoA1n9HaHKufZs5FCSGazv5/commits/HEAD"
+        );
+    }
+
+    #[test]
+    fn test_project() {
+        let repo: RadicleRepo = serde_json::from_str(
+            r#"{
+                "rid": "rad:z3gqcJUoA1n9HaHKufZs5FCSGazv5",
+                "payloads": {
+                    "xyz.radicle.project": {
+                        "data": {
+                            "defaultBranch": "master",
+                            "description": "Radicle Heartwood Protocol & Stack",
+                            "name": "heartwood"
+                        },
+                        "meta": {
+                            "head": "5c4f8ba7c4ed3f0a4eb3c4e66a2b7d8c9f0e1a2b",
+                            "issues": { "open": 1, "closed": 2 },
+                            "patches": { "open": 3, "draft": 0, "archived": 0, "merged": 4 }
+                        }
+                    }
+                },
+                "delegates": [],
+                "threshold": 1,
+                "visibility": { "type": "public" },
+                "seeding": 42
+            }"#,
+        )
+        .unwrap();
+        let project = repo.payloads.project;
+        assert_eq!(project.data.name, "heartwood");
+        assert_eq!(project.data.default_branch, "master");
+        assert_eq!(
+            project.meta.head,
+            "5c4f8ba7c4ed3f0a4eb3c4e66a2b7d8c9f0e1a2b"
+        );
+    }
+}
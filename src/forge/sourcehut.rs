@@ -0,0 +1,106 @@
+//! SourceHut (git.sr.ht and self-hosted instances)
+//!
+//! SourceHut offers archive downloads and raw files, but its only API for everything else
+//! requires authentication. Releases are plain tags there anyways.
+
+use super::*;
+
+pub struct SourceHut {
+    /// Of the kind <https://git.sr.ht/>
+    pub server: Url,
+    /// Without the leading `~`
+    pub owner: String,
+    pub repo: String,
+}
+
+impl SourceHut {
+    /// `<server>/~<owner>/<repo>/<path…>`
+    fn web_url<I>(&self, path: I) -> Result<Url>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let url = url_with_segments(&self.server, [&format!("~{}", self.owner), &self.repo])?;
+        url_with_segments(&url, path)
+    }
+}
+
+#[async_trait::async_trait]
+impl Forge for SourceHut {
+    fn git_url(&self) -> Result<Url> {
+        self.web_url(std::iter::empty::<&str>())
+    }
+
+    fn archive_url(&self, revision: &str) -> Result<Url> {
+        self.web_url(
+            ["archive".to_owned()]
+                .into_iter()
+                .chain(ref_segments(revision, ".tar.gz")),
+        )
+    }
+
+    async fn file_contents(&self, revision: &str, path: &str) -> Result<Option<String>> {
+        /* `blob` serves the raw file, unlike `tree` */
+        let url = self.web_url(
+            ["blob".to_owned(), revision.to_owned()]
+                .into_iter()
+                .chain(ref_segments(path, "")),
+        )?;
+        get_text(url, &[]).await
+    }
+
+    async fn list_releases(&self) -> Result<Vec<Release>> {
+        /* Releases are annotated tags with optional attachments, so there's nothing beyond the tags */
+        Ok(Vec::new())
+    }
+
+    async fn compare(&self, _from: &str, _to: &str) -> Result<Comparison> {
+        anyhow::bail!("SourceHut does not support comparing revisions without authentication")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn forge(server: &str) -> SourceHut {
+        SourceHut {
+            server: server.parse().unwrap(),
+            owner: "sircmpwn".into(),
+            repo: "scdoc".into(),
+        }
+    }
+
+    #[test]
+    fn test_urls() {
+        let forge = forge("https://git.sr.ht");
+        assert_eq!(
+            forge.git_url().unwrap().as_str(),
+            "https://git.sr.ht/~sircmpwn/scdoc"
+        );
+        assert_eq!(
+            forge
+                .archive_url("0528bcb993cac6c412acb3ae2e09f6ecf0b6d9d6")
+                .unwrap()
+                .as_str(),
+            "https://git.sr.ht/~sircmpwn/scdoc/archive/0528bcb993cac6c412acb3ae2e09f6ecf0b6d9d6.tar.gz"
+        );
+        assert_eq!(
+            forge.release_archive_url("1.11.3").unwrap().as_str(),
+            "https://git.sr.ht/~sircmpwn/scdoc/archive/1.11.3.tar.gz"
+        );
+    }
+
+    #[test]
+    fn test_encoding() {
+        let forge = forge("https://example.org/srht/");
+        assert_eq!(
+            forge.git_url().unwrap().as_str(),
+            "https://example.org/srht/~sircmpwn/scdoc"
+        );
+        assert_eq!(
+            forge.archive_url("release/v1 β").unwrap().as_str(),
+            "https://example.org/srht/~sircmpwn/scdoc/archive/release/v1%20%CE%B2.tar.gz"
+        );
+    }
+}
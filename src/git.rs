@@ -7,23 +7,12 @@
 //! instance. This should be preferred over the generic Git API if possible. See [`Repository`]
 //! for more on this.
 
-use crate::*;
+use crate::{forge::Forge, *};
 use anyhow::{Context, Result};
-use lenient_version::Version;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use tokio::process::Command;
 use url::Url;
 
-fn get_github_url() -> String {
-    std::env::var("NPINS_GITHUB_HOST").unwrap_or_else(|_| String::from("https://github.com"))
-}
-
-fn get_github_api_url() -> String {
-    std::env::var("NPINS_GITHUB_API_HOST")
-        .unwrap_or_else(|_| String::from("https://api.github.com"))
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 /// A git revision, with an optional timestamp.
 ///
@@ -31,6 +20,10 @@ fn get_github_api_url() -> String {
 pub struct GitRevision {
     revision: String,
     timestamp: Option<String>,
+    /// The branch the revision was checked to be on when it was pinned explicitly, see
+    /// [`GitPin::verify`]. Revisions that npins found itself are the head of the branch anyway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    verified_on: Option<String>,
 }
 
 impl GitRevision {
@@ -41,6 +34,7 @@ impl GitRevision {
         Ok(Self {
             revision,
             timestamp: None,
+            verified_on: None,
         })
     }
 }
@@ -54,6 +48,54 @@ impl diff::Diff for GitRevision {
                 self.timestamp.clone().unwrap_or_else(|| "N/A".into()),
             ),
         ]
+        .into_iter()
+        .chain(
+            self.verified_on
+                .as_ref()
+                .map(|branch| ("verified on".into(), branch.clone())),
+        )
+        .collect()
+    }
+}
+
+/// The tag a release pin is at
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ReleaseVersion {
+    pub version: String,
+    /// The [`epoch`](GitReleasePin::epoch) of the pin when the tag was pinned
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub epoch: u64,
+    /// The commit a [moving tag](GitReleasePin::moving_tag) pointed to
+    ///
+    /// Not named `revision`, as that is already taken by [`ReleasePinHashes::revision`] in the lock file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_commit: Option<String>,
+}
+
+fn is_zero(epoch: &u64) -> bool {
+    *epoch == 0
+}
+
+impl ReleaseVersion {
+    pub fn new(version: impl Into<String>) -> Self {
+        ReleaseVersion {
+            version: version.into(),
+            epoch: 0,
+            tag_commit: None,
+        }
+    }
+}
+
+impl diff::Diff for ReleaseVersion {
+    fn properties(&self) -> Vec<(String, String)> {
+        std::iter::once(("version".into(), self.version.clone()))
+            .chain((self.epoch != 0).then(|| ("epoch".into(), self.epoch.to_string())))
+            .chain(
+                self.tag_commit
+                    .as_ref()
+                    .map(|commit| ("tag_commit".into(), commit.clone())),
+            )
+            .collect()
     }
 }
 
@@ -64,6 +106,9 @@ impl diff::Diff for GitRevision {
 pub struct OptionalUrlHashes {
     pub url: Option<url::Url>,
     pub hash: String,
+    /// The url can only be fetched with credentials, which Nix needs to be configured with
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub requires_auth: bool,
 }
 
 impl diff::Diff for OptionalUrlHashes {
@@ -84,6 +129,9 @@ pub struct ReleasePinHashes {
     // This is the URL for the tarball to fetch, if absent use fetchgit instead
     pub url: Option<Url>,
     pub hash: String,
+    /// The url can only be fetched with credentials, which Nix needs to be configured with
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub requires_auth: bool,
 }
 
 impl diff::Diff for ReleasePinHashes {
@@ -97,7 +145,7 @@ impl diff::Diff for ReleasePinHashes {
 
 /// Abstraction over different git repository hosters
 ///
-/// Currently, GitHub and GitLab are supported. Plain git repositories
+/// Currently, GitHub, GitLab, Forgejo, SourceHut, Bitbucket and Radicle are supported. Plain git repositories
 /// have limited support: they cannot provide tarball urls for downloading
 /// versions.
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
@@ -116,6 +164,9 @@ pub enum Repository {
         /// "owner/repo"
         owner: String,
         repo: String,
+        /// Former "owner/repo" names, from before the repository was renamed or transferred
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        aliases: Vec<String>,
     },
     GitLab {
         /// usually "owner/repo" or "group/owner/repo" (without leading or trailing slashes)
@@ -129,6 +180,27 @@ pub enum Repository {
         #[serde(default)]
         private_token: Option<String>,
     },
+    SourceHut {
+        /// Of the kind <https://git.sr.ht/>
+        server: Url,
+        /// Without the leading `~`
+        owner: String,
+        repo: String,
+    },
+    Bitbucket {
+        /// A Bitbucket Server/Data Center instance, or none for Bitbucket Cloud
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        server: Option<Url>,
+        /// The workspace on Bitbucket Cloud, the project key on Bitbucket Server
+        owner: String,
+        repo: String,
+    },
+    Radicle {
+        /// The seed node to talk to, of the kind <https://seed.radicle.garden/>
+        seed: Url,
+        /// The repository ID, like `rad:z3gqcJUoA1n9HaHKufZs5FCSGazv5`
+        rid: String,
+    },
 }
 
 impl Repository {
@@ -140,6 +212,7 @@ impl Repository {
         Repository::GitHub {
             owner: owner.into(),
             repo: repo.into(),
+            aliases: Vec::new(),
         }
     }
 
@@ -160,159 +233,414 @@ impl Repository {
         }
     }
 
-    /// Get the URL to the represented Git repository
-    fn git_url(&self) -> Result<Url> {
-        Ok(match self {
-            Repository::Git { url } => url.clone(),
-            Repository::GitHub { owner, repo } => {
-                format!("{}/{}/{}.git", get_github_url(), owner, repo).parse()?
-            },
+    pub fn sourcehut(server: Option<Url>, owner: &str, repo: impl Into<String>) -> Self {
+        Repository::SourceHut {
+            server: server.unwrap_or_else(|| "https://git.sr.ht/".parse().unwrap()),
+            owner: owner.strip_prefix('~').unwrap_or(owner).into(),
+            repo: repo.into(),
+        }
+    }
+
+    pub fn bitbucket(
+        server: Option<Url>,
+        owner: impl Into<String>,
+        repo: impl Into<String>,
+    ) -> Self {
+        Repository::Bitbucket {
+            server,
+            owner: owner.into(),
+            repo: repo.into(),
+        }
+    }
+
+    /// The RID may be given with or without the `rad:` prefix
+    pub fn radicle(seed: Option<Url>, rid: &str) -> Self {
+        Repository::Radicle {
+            seed: seed.unwrap_or_else(|| "https://seed.radicle.garden/".parse().unwrap()),
+            rid: format!("rad:{}", rid.strip_prefix("rad:").unwrap_or(rid)),
+        }
+    }
+
+    /// Get the forge hosting this repository, if any
+    ///
+    /// Plain git repositories have no forge, and support only what git itself offers.
+    pub fn forge(&self) -> Option<Box<dyn Forge>> {
+        Some(match self {
+            Repository::Git { .. } => return None,
+            Repository::GitHub { owner, repo, .. } => Box::new(forge::github::GitHub {
+                owner: owner.clone(),
+                repo: repo.clone(),
+            }),
             Repository::Forgejo {
                 server,
                 owner,
                 repo,
-            } => format!("{}/{}/{}.git", server, owner, repo).parse()?,
+            } => Box::new(forge::forgejo::Forgejo {
+                server: server.clone(),
+                owner: owner.clone(),
+                repo: repo.clone(),
+            }),
             Repository::GitLab {
                 repo_path,
                 server,
                 private_token,
-            } => {
-                let mut server = server.clone();
-                if let Some(token) = private_token {
-                    server.set_username("oauth2").ok();
-                    server.set_password(Some(token)).ok();
-                } else if let Ok(token) = std::env::var("GITLAB_TOKEN") {
-                    server.set_username("oauth2").ok();
-                    server.set_password(Some(&token)).ok();
-                }
-                server.join(&format!("{}.git", repo_path))?
-            },
-        })
-    }
-
-    /// Get the url to a tarball of the requested revision
-    fn url(&self, revision: &str) -> Result<Option<Url>> {
-        Ok(match self {
-            Repository::Git { .. } => None,
-            Repository::GitHub { owner, repo } => Some(
-                format!(
-                    "{github}/{owner}/{repo}/archive/{revision}.tar.gz",
-                    github = get_github_url(),
-                    owner = owner,
-                    repo = repo,
-                    revision = revision,
-                )
-                .parse()?,
-            ),
-            Repository::Forgejo {
+            } => Box::new(forge::gitlab::GitLab {
+                repo_path: repo_path.clone(),
+                server: server.clone(),
+                private_token: private_token.clone(),
+            }),
+            Repository::SourceHut {
                 server,
                 owner,
                 repo,
-            } => Some(format!("{server}{owner}/{repo}/archive/{revision}.tar.gz",).parse()?),
-            Repository::GitLab {
-                repo_path,
+            } => Box::new(forge::sourcehut::SourceHut {
+                server: server.clone(),
+                owner: owner.clone(),
+                repo: repo.clone(),
+            }),
+            Repository::Bitbucket {
                 server,
-                private_token,
-            } => {
-                let mut url = server.clone();
-                url.path_segments_mut()
-                    .map_err(|()| anyhow::format_err!("GitLab server URL must be a base"))?
-                    .extend(
-                        [
-                            "api",
-                            "v4",
-                            "projects",
-                            repo_path,
-                            "repository",
-                            "archive.tar.gz",
-                        ]
-                        .iter(),
-                    );
-                url.set_query(Some(&format!("sha={}", revision)));
-                if let Some(token) = private_token {
-                    url.set_query(Some(&format!("private_token={}", token)));
-                }
-                Some(url)
-            },
+                owner,
+                repo,
+            } => Box::new(forge::bitbucket::Bitbucket {
+                server: server.clone(),
+                owner: owner.clone(),
+                repo: repo.clone(),
+            }),
+            Repository::Radicle { seed, rid } => Box::new(forge::radicle::Radicle {
+                seed: seed.clone(),
+                rid: rid.clone(),
+            }),
         })
     }
 
+    /// Check whether the repository was renamed or transferred to another owner
+    ///
+    /// Returns the repository under its new name, with the current one added to the aliases. Only
+    /// GitHub keeps redirecting from the old name, and only until someone takes it over.
+    pub async fn renamed(&self) -> Result<Option<Repository>> {
+        let Repository::GitHub {
+            owner,
+            repo,
+            aliases,
+        } = self
+        else {
+            return Ok(None);
+        };
+        let forge = forge::github::GitHub {
+            owner: owner.clone(),
+            repo: repo.clone(),
+        };
+        Ok(forge.renamed().await?.map(|(new_owner, new_repo)| {
+            let mut aliases = aliases.clone();
+            aliases.push(format!("{owner}/{repo}"));
+            Repository::GitHub {
+                owner: new_owner,
+                repo: new_repo,
+                aliases,
+            }
+        }))
+    }
+
+    /// Get the URL to the represented Git repository
+    pub fn git_url(&self) -> Result<Url> {
+        match (self, self.forge()) {
+            (Repository::Git { url }, _) => Ok(url.clone()),
+            (_, forge) => forge
+                .expect("Only plain git repositories have no forge")
+                .git_url(),
+        }
+    }
+
+    /// Get the url to a tarball of the requested revision
+    fn url(&self, revision: &str) -> Result<Option<Url>> {
+        self.forge()
+            .map(|forge| forge.archive_url(revision))
+            .transpose()
+    }
+
     /// Get the url to a tarball of the requested release
     fn release_url(&self, tag: &str) -> Result<Option<Url>> {
-        Ok(match self {
-            Repository::Git { .. } => None,
-            Repository::GitHub { owner, repo } => Some(
-                format!(
-                    "{github_api}/repos/{owner}/{repo}/tarball/refs/tags/{tag}",
-                    github_api = get_github_api_url(),
-                    owner = owner,
-                    repo = repo,
-                    tag = tag,
-                )
-                .parse()?,
+        self.forge()
+            .map(|forge| forge.release_archive_url(tag))
+            .transpose()
+    }
+
+    /// Prefetch a tarball of this repository
+    ///
+    /// Returns the hash, and whether credentials were needed for it.
+    async fn prefetch_archive(&self, url: &Url) -> Result<(String, bool)> {
+        let headers = self
+            .forge()
+            .map(|forge| forge.auth_headers())
+            .unwrap_or_default();
+        if headers.is_empty() {
+            Ok((nix::nix_prefetch_tarball(url).await?, false))
+        } else {
+            Ok((
+                nix::nix_prefetch_tarball_authenticated(url, &headers).await?,
+                true,
+            ))
+        }
+    }
+
+    /// Whether tarballs of a revision may change between downloads
+    ///
+    /// This is the case when the top-level `.gitattributes` uses `export-subst`: the substituted
+    /// placeholders (e.g. abbreviated hashes or ref names) depend on the current state of the
+    /// repository and on the git version of the forge, so the hash will eventually break.
+    /// `export-ignore` only makes tarballs differ from a checkout, but is stable and thus fine.
+    ///
+    /// This is a best-effort check, any errors are ignored.
+    async fn has_unstable_archives(&self, revision: &str) -> bool {
+        let Some(forge) = self.forge() else {
+            return false;
+        };
+        let attributes = match forge.file_contents(revision, ".gitattributes").await {
+            Ok(Some(attributes)) => attributes,
+            Ok(None) => return false,
+            Err(err) => {
+                log::debug!("Could not check the .gitattributes: {err:#}");
+                return false;
+            },
+        };
+        if gitattributes_set(&attributes, "export-ignore") {
+            log::info!("Tarballs of this repository omit some files because of `export-ignore` in its .gitattributes, use `prefer = \"git\"` if you need them");
+        }
+        gitattributes_set(&attributes, "export-subst")
+    }
+
+    /// Fetch a revision via git if `recorded_url` is a tarball, and vice versa
+    ///
+    /// See [`Updatable::cross_check`]
+    async fn cross_check(
+        &self,
+        revision: &str,
+        recorded_url: Option<&Url>,
+        archive_url: Option<Url>,
+        expected: &str,
+    ) -> Result<Option<CrossCheck>> {
+        let (method, actual) = match (recorded_url, archive_url) {
+            (Some(_), _) => (
+                "git",
+                nix::nix_prefetch_git(&self.git_url()?, revision, false).await?,
             ),
-            Repository::Forgejo {
-                server,
-                owner,
-                repo,
-            } => {
-                Some(format!("{server}api/v1/repos/{owner}/{repo}/archive/{tag}.tar.gz",).parse()?)
+            (None, Some(url)) if self.serves_archives(revision).await => {
+                ("tarball", self.prefetch_archive(&url).await?.0)
             },
-            Repository::GitLab {
-                repo_path,
-                server,
-                private_token,
-            } => {
-                let mut url = server.clone();
-                url.path_segments_mut()
-                    .map_err(|()| anyhow::format_err!("GitLab server URL must be a base"))?
-                    .extend(
-                        [
-                            "api",
-                            "v4",
-                            "projects",
-                            repo_path,
-                            "repository",
-                            "archive.tar.gz",
-                        ]
-                        .iter(),
-                    );
-                url.set_query(Some(&format!("sha={}", tag)));
-                if let Some(token) = private_token {
-                    url.set_query(Some(&format!("private_token={}", token)));
-                }
-                Some(url)
+            /* Plain git repositories only have the one way */
+            (None, _) => return Ok(None),
+        };
+        Ok(Some(CrossCheck {
+            method,
+            expected: expected.to_owned(),
+            actual,
+        }))
+    }
+
+    /// Fall back to git for unstable tarballs, unless the user explicitly asked for tarballs
+    async fn stable_archive(
+        &self,
+        prefer: Option<Prefer>,
+        url: Option<Url>,
+        revision: &str,
+    ) -> Option<Url> {
+        match url {
+            Some(_) if prefer.is_none() && self.has_unstable_archives(revision).await => {
+                log::warn!("The repository uses `export-subst`, which makes its tarballs unstable. Fetching via git instead.");
+                None
             },
-        })
+            Some(_) if prefer.is_none() && !self.serves_archives(revision).await => {
+                log::info!(
+                    "The repository's host does not serve tarballs. Fetching via git instead."
+                );
+                None
+            },
+            url => url,
+        }
+    }
+
+    async fn serves_archives(&self, revision: &str) -> bool {
+        match self.forge() {
+            Some(forge) => forge.serves_archives(revision).await,
+            None => false,
+        }
     }
 
     async fn get_timestamp(&self, commit: &str) -> Result<Option<String>> {
-        Ok(match self {
-            Repository::GitHub { owner, repo } => {
-                let url: Url = format!(
-                    "{github_api}/repos/{owner}/{repo}/commits/{commit}",
-                    github_api = get_github_api_url(),
-                )
-                .parse()?;
-
-                let body: Value = build_client()?
-                    .get(url)
-                    .send()
-                    .await
-                    .context("Couldn't fetch timestamp")?
-                    .json()
-                    .await
-                    .context("Couldn't decode response as JSON")?;
-
-                Some(
-                    body["commit"]["author"]["date"]
-                        .as_str()
-                        .context("Expected date in GitHub API response to be a string")?
-                        .to_string(),
-                )
+        match self.forge() {
+            Some(forge) => forge.commit_timestamp(commit).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Get the current head of a branch
+    async fn resolve_branch(&self, branch: &str) -> Result<RemoteInfo> {
+        match self.forge() {
+            Some(forge) => {
+                let ref_ = format!("refs/heads/{branch}");
+                api_fallback(forge.resolve_branch(branch).await, || forge.api_ref(&ref_)).await
             },
-            _ => None,
+            None => fetch_branch_head(&self.git_url()?, branch).await,
+        }
+    }
+
+    /// Get the commit of a ref, or the tag object of an annotated tag
+    async fn resolve_ref(&self, ref_: &str) -> Result<RemoteInfo> {
+        let git = fetch_ref(&self.git_url()?, ref_).await;
+        match self.forge() {
+            Some(forge) => api_fallback(git, || forge.api_ref(ref_)).await,
+            None => git,
+        }
+    }
+
+    /// Whether `revision` is in the history of `ref_`, whose head is `head`
+    async fn is_reachable(&self, revision: &str, ref_: &str, head: &str) -> Result<bool> {
+        if revision == head {
+            return Ok(true);
+        }
+        if let Some(forge) = self.forge() {
+            match forge.compare(head, revision).await {
+                Ok(comparison) => return Ok(comparison.is_empty()),
+                Err(err) => log::debug!("Asking git instead: {err:#}"),
+            }
+        }
+        range::is_ancestor(&self.git_url()?, revision, ref_).await
+    }
+
+    /// The first of `tags` that is in the history of `branch`
+    ///
+    /// The forge is asked about one tag after the other, which usually ends with the first one.
+    /// If it can't tell, or many of the tags aren't on the branch, it's cheaper to clone the
+    /// repository once.
+    async fn newest_reachable<T>(
+        &self,
+        tags: Vec<T>,
+        tag: impl Fn(&T) -> &str,
+        branch: &str,
+    ) -> Result<Option<T>> {
+        const MAX_COMPARISONS: usize = 10;
+
+        if let Some(forge) = self.forge() {
+            let head = self.resolve_branch(branch).await?.revision;
+            let mut compared = 0;
+            for candidate in tags.iter().take(MAX_COMPARISONS) {
+                match forge.compare(&head, tag(candidate)).await {
+                    Ok(comparison) if comparison.is_empty() => {
+                        return Ok(tags.into_iter().nth(compared))
+                    },
+                    Ok(_) => log::debug!("{} is not on {branch}", tag(candidate)),
+                    Err(err) => {
+                        log::debug!("Asking git instead: {err:#}");
+                        break;
+                    },
+                }
+                compared += 1;
+            }
+            if compared == tags.len() {
+                return Ok(None);
+            }
+        }
+        let merged = range::merged_tags(&self.git_url()?, branch).await?;
+        Ok(tags
+            .into_iter()
+            .find(|candidate| merged.contains(tag(candidate))))
+    }
+
+    /// List all tags of the repository
+    async fn list_tags(&self) -> Result<Vec<RemoteInfo>> {
+        match self.forge() {
+            Some(forge) => api_fallback(forge.list_tags().await, || forge.api_tags()).await,
+            None => fetch_tags(&self.git_url()?).await,
+        }
+    }
+}
+
+/// Ask the forge's API instead when git can't reach the repository, e.g. because of a proxy
+///
+/// `git` is the result of asking git. If the API doesn't help either, its error is returned.
+async fn api_fallback<T, F>(git: Result<T>, api: impl FnOnce() -> F) -> Result<T>
+where
+    F: std::future::Future<Output = Result<Option<T>>>,
+{
+    let err = match git {
+        Ok(result) => return Ok(result),
+        Err(err) => err,
+    };
+    match api().await {
+        Ok(Some(result)) => {
+            log::warn!("{err:#}");
+            log::warn!("Couldn't reach the repository with git, used the forge's API instead");
+            Ok(result)
+        },
+        Ok(None) => Err(err),
+        Err(api_err) => {
+            log::debug!("The forge's API didn't help either: {api_err:#}");
+            Err(err)
+        },
+    }
+}
+
+/// How to fetch the contents of a git pin
+///
+/// Tarballs are a lot faster to download than a clone, but their contents may subtly differ
+/// from a git checkout (see `export-subst` and `export-ignore` in gitattributes(5)).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Prefer {
+    /// Always use `fetchGit`, even if the forge provides tarballs
+    Git,
+    /// Always use a tarball, fail if the forge doesn't provide any
+    Tarball,
+}
+
+impl std::fmt::Display for Prefer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Prefer::Git => "git",
+            Prefer::Tarball => "tarball",
+        })
+    }
+}
+
+impl std::str::FromStr for Prefer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "git" => Ok(Prefer::Git),
+            "tarball" => Ok(Prefer::Tarball),
+            _ => anyhow::bail!("expected 'git' or 'tarball', got '{s}'"),
+        }
+    }
+}
+
+/// Check whether a `.gitattributes` file sets an attribute on any path
+fn gitattributes_set(content: &str, attribute: &str) -> bool {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        /* The first word is the pattern, the rest are attributes (`-attr` and `!attr` unset them) */
+        .flat_map(|line| line.split_whitespace().skip(1))
+        .any(|attr| {
+            attr == attribute
+                || attr
+                    .strip_prefix(attribute)
+                    .is_some_and(|value| value.starts_with('='))
         })
+}
+
+/// Get the URL of the tarball to fetch, honoring the pin's preference
+///
+/// `None` means that git must be used instead.
+fn preferred_archive(prefer: Option<Prefer>, url: Option<Url>) -> Result<Option<Url>> {
+    match prefer {
+        Some(Prefer::Git) => Ok(None),
+        Some(Prefer::Tarball) => Ok(Some(url.context(
+            "This repository does not provide tarballs, please use `prefer = \"git\"` instead",
+        )?)),
+        None => Ok(url),
     }
 }
 
@@ -320,10 +648,16 @@ impl Repository {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct GitPin {
     pub repository: Repository,
+    /// The name of the branch, or any other ref given in full, like `refs/pull/42/head` or
+    /// Gerrit's `refs/changes/…`, see [`GitPin::tracked_ref`]
     pub branch: String,
     /// Also fetch submodules
     #[serde(default)]
     pub submodules: bool,
+    /// Whether to fetch via git or as tarball. By default, tarballs are used when available
+    /// and stable (see [`Repository::has_unstable_archives`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefer: Option<Prefer>,
 }
 
 impl diff::Diff for GitPin {
@@ -336,6 +670,12 @@ impl diff::Diff for GitPin {
             ("branch".into(), self.branch.clone()),
             ("submodules".into(), self.submodules.to_string()),
         ]
+        .into_iter()
+        .chain(
+            self.prefer
+                .map(|prefer| ("prefer".into(), prefer.to_string())),
+        )
+        .collect()
     }
 }
 
@@ -345,7 +685,61 @@ impl GitPin {
             repository,
             branch,
             submodules,
+            prefer: None,
+        }
+    }
+
+    /// The full name of the tracked ref, like `refs/heads/main`
+    pub fn tracked_ref(&self) -> String {
+        if self.branch.starts_with("refs/") {
+            self.branch.clone()
+        } else {
+            format!("refs/heads/{}", self.branch)
+        }
+    }
+
+    /// The commit the tracked ref points to
+    async fn head(&self) -> Result<String> {
+        let ref_ = self.tracked_ref();
+        /* An annotated tag would resolve to the tag object and not to the commit */
+        anyhow::ensure!(
+            !ref_.starts_with("refs/tags/"),
+            "Tags are tracked by release pins, see `--moving-tag`"
+        );
+        Ok(match ref_.strip_prefix("refs/heads/") {
+            Some(branch) => self.repository.resolve_branch(branch).await,
+            None => self.repository.resolve_ref(&ref_).await,
         }
+        .context("Couldn't fetch the latest commit")?
+        .revision)
+    }
+
+    /// Check that a revision given by the user is on the tracked branch, and record that it is
+    ///
+    /// Otherwise, a typo or a commit from a fork would only show up once the pin is used.
+    pub async fn verify(&self, version: GitRevision) -> Result<GitRevision> {
+        let head = self.head().await?;
+        let revision = version.revision;
+        anyhow::ensure!(
+            self.repository
+                .is_reachable(&revision, &self.tracked_ref(), &head)
+                .await
+                .with_context(|| format!("Couldn't check that {revision} is on {}", self.branch))?,
+            "{revision} is not on {}, whose head is {head}",
+            self.branch
+        );
+        let timestamp = match self.repository.get_timestamp(&revision).await {
+            Ok(timestamp) => timestamp,
+            Err(err) => {
+                log::warn!("Couldn't fetch the timestamp of {revision}, leaving it out: {err:#}");
+                None
+            },
+        };
+        Ok(GitRevision {
+            revision,
+            timestamp,
+            verified_on: Some(self.branch.clone()),
+        })
     }
 }
 
@@ -354,39 +748,79 @@ impl Updatable for GitPin {
     type Version = GitRevision;
     type Hashes = OptionalUrlHashes;
 
-    async fn update(&self, _old: Option<&GitRevision>) -> Result<GitRevision> {
-        let repo_url = self.repository.git_url()?;
-        let latest = fetch_branch_head(&repo_url, &self.branch)
-            .await
-            .context("Couldn't fetch the latest commit")?
-            .revision;
-
+    async fn update(&self, old: Option<&GitRevision>) -> Result<GitRevision> {
+        let latest = self.head().await?;
+
+        /* Only nice to have, so an outage of the forge's API shouldn't stop the update */
+        let timestamp = match self.repository.get_timestamp(&latest).await {
+            Ok(timestamp) => timestamp,
+            Err(err) => {
+                log::warn!("Couldn't fetch the timestamp of {latest}, leaving it out: {err:#}");
+                provenance::record_missing("timestamp");
+                old.filter(|old| old.revision == latest)
+                    .and_then(|old| old.timestamp.clone())
+            },
+        };
         Ok(GitRevision {
-            timestamp: self.repository.get_timestamp(&latest).await?,
+            timestamp,
             revision: latest,
+            verified_on: None,
         })
     }
 
     async fn fetch(&self, version: &GitRevision) -> Result<OptionalUrlHashes> {
         if self.submodules {
+            anyhow::ensure!(
+                self.prefer != Some(Prefer::Tarball),
+                "Tarballs don't contain submodules, please use `prefer = \"git\"` instead"
+            );
             Ok(OptionalUrlHashes {
                 url: None,
                 hash: nix::nix_prefetch_git(&self.repository.git_url()?, &version.revision, true)
                     .await?,
+                requires_auth: false,
             })
         } else {
             // Try to find an URL for fetchtarball first, as it is faster than fetchgit
-            let url = self.repository.url(&version.revision)?;
-            let hash = match url.as_ref() {
-                Some(url) => nix::nix_prefetch_tarball(url).await?,
-                None => {
+            let url = preferred_archive(self.prefer, self.repository.url(&version.revision)?)?;
+            let url = self
+                .repository
+                .stable_archive(self.prefer, url, &version.revision)
+                .await;
+            let (hash, requires_auth) = match url.as_ref() {
+                Some(url) => self.repository.prefetch_archive(url).await?,
+                None => (
                     nix::nix_prefetch_git(&self.repository.git_url()?, &version.revision, false)
-                        .await?
-                },
+                        .await?,
+                    false,
+                ),
             };
 
-            Ok(OptionalUrlHashes { url, hash })
+            Ok(OptionalUrlHashes {
+                url,
+                hash,
+                requires_auth,
+            })
+        }
+    }
+
+    async fn cross_check(
+        &self,
+        version: &GitRevision,
+        hashes: &OptionalUrlHashes,
+    ) -> Result<Option<CrossCheck>> {
+        /* Tarballs don't contain submodules */
+        if self.submodules {
+            return Ok(None);
         }
+        self.repository
+            .cross_check(
+                &version.revision,
+                hashes.url.as_ref(),
+                self.repository.url(&version.revision)?,
+                &hashes.hash,
+            )
+            .await
     }
 }
 
@@ -405,11 +839,21 @@ pub struct GitReleasePin {
     ///
     /// If present, only versions < version_upper_bound will be pinned. This is a restricted
     /// syntax compared to the "version requirement grammar" with multiple different operators
-    /// that are common in dependency resolution tools. The reason is, that we aren't interested
-    /// in providing lower bounds for releases, so all we need is a "less than" operator.
+    /// that are common in dependency resolution tools, see [`version_requirement`] for that.
     ///
     /// Versions will be parsed the in the same rather lenient way as the tags themselves.
+    ///
+    /// [`version_requirement`]: Self::version_requirement
     pub version_upper_bound: Option<String>,
+    /// Optionally restrict the releases to those matching a requirement like `>=1.2, <2.0` or
+    /// `~1.4`, in the syntax of Cargo
+    ///
+    /// Applies on top of the [upper bound](Self::version_upper_bound). The tags are parsed
+    /// leniently like for the upper bound, so `v1.4` is 1.4.0. Pre-releases, if tracked at all,
+    /// count as the version they lead up to: `~1.4` admits `1.4.0-rc1`, but `<2.0` does not
+    /// admit `2.0.0-rc1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_requirement: Option<String>,
     /// Optionally filter the considered release names / tags by a prefix
     ///
     /// Some projects have a more elaborate tag structure that
@@ -421,6 +865,46 @@ pub struct GitReleasePin {
     /// Also fetch submodules
     #[serde(default)]
     pub submodules: bool,
+    /// Whether to fetch via git or as tarball. By default, tarballs are used when available
+    /// and stable (see [`Repository::has_unstable_archives`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefer: Option<Prefer>,
+    /// Trust the release the forge marks as latest instead of ordering the tags by version.
+    ///
+    /// Only supported on GitHub and GitLab, other forges fall back to ordering the tags.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forge_latest: bool,
+    /// How to compare the tags, by default leniently as they are
+    #[serde(default, skip_serializing_if = "latest::VersionScheme::is_default")]
+    pub version_scheme: latest::VersionScheme,
+    /// Bump this when upstream restarts its versioning, e.g. going from 3.9 to 1.0
+    ///
+    /// Like Debian's epochs: versions must only increase within an epoch, so updating to a lower
+    /// version fails unless the epoch was bumped since the current version was pinned. Usually
+    /// the old tags have to be excluded too, e.g. with a release prefix or by normalizing them.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub epoch: u64,
+    /// Track this tag instead of the latest release, for upstreams that move tags like `latest`
+    /// or `stable` to new commits
+    ///
+    /// The pin is locked to the commit the tag points to, and a moved tag is a normal update.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moving_tag: Option<String>,
+    /// Only consider the tags in the history of this branch
+    ///
+    /// Keeps the pin on the mainline when upstream also cuts releases from maintenance branches,
+    /// which may have higher versions than the latest release of the branch, e.g. a 1.x
+    /// backport that is tagged after 2.0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reachable_from: Option<String>,
+    /// Only consider the releases matching one of these glob patterns, see [`latest::listed`]
+    ///
+    /// The patterns match the tags without the [release prefix](Self::release_prefix).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only_versions: Vec<String>,
+    /// Skip the releases matching any of these glob patterns, like a broken `3.2.1` or all `*.0`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_versions: Vec<String>,
 }
 
 impl diff::Diff for GitReleasePin {
@@ -436,13 +920,49 @@ impl diff::Diff for GitReleasePin {
                 .map(|version_upper_bound| {
                     ("version_upper_bound".into(), version_upper_bound.clone())
                 }),
+            self.version_requirement
+                .as_ref()
+                .map(|requirement| ("version_requirement".into(), requirement.clone())),
             self.release_prefix
                 .as_ref()
                 .map(|release_prefix| ("release_prefix".into(), release_prefix.clone())),
             Some(("submodules".into(), self.submodules.to_string())),
+            self.prefer
+                .map(|prefer| ("prefer".into(), prefer.to_string())),
+            self.forge_latest
+                .then(|| ("forge_latest".into(), "true".into())),
+            (self.epoch != 0).then(|| ("epoch".into(), self.epoch.to_string())),
+            self.moving_tag
+                .as_ref()
+                .map(|tag| ("moving_tag".into(), tag.clone())),
+            self.reachable_from
+                .as_ref()
+                .map(|branch| ("reachable_from".into(), branch.clone())),
+            (!self.version_scheme.is_default()).then(|| {
+                (
+                    "version_scheme".into(),
+                    self.version_scheme.comparator.to_string(),
+                )
+            }),
         ]
         .into_iter()
         .flat_map(Option::into_iter)
+        .chain(self.version_scheme.normalize.iter().map(|rewrite| {
+            (
+                "normalize".into(),
+                format!("{}={}", rewrite.pattern, rewrite.replacement),
+            )
+        }))
+        .chain(
+            self.only_versions
+                .iter()
+                .map(|pattern| ("only_versions".into(), pattern.clone())),
+        )
+        .chain(
+            self.exclude_versions
+                .iter()
+                .map(|pattern| ("exclude_versions".into(), pattern.clone())),
+        )
         .collect()
     }
 }
@@ -459,110 +979,384 @@ impl GitReleasePin {
             repository,
             pre_releases,
             version_upper_bound,
+            version_requirement: None,
             release_prefix,
             submodules,
+            prefer: None,
+            forge_latest: false,
+            version_scheme: Default::default(),
+            epoch: 0,
+            moving_tag: None,
+            reachable_from: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         }
     }
-}
 
-#[async_trait::async_trait]
-impl Updatable for GitReleasePin {
-    type Version = GenericVersion;
-    type Hashes = ReleasePinHashes;
+    /// Whether the release passes [`only_versions`](Self::only_versions) and
+    /// [`exclude_versions`](Self::exclude_versions), by its tag without the release prefix
+    fn listed(&self, name: &str) -> bool {
+        latest::listed(name, &self.only_versions, &self.exclude_versions)
+    }
 
-    async fn update(&self, old: Option<&GenericVersion>) -> Result<GenericVersion> {
-        let repo_url = self.repository.git_url()?;
+    /// The [upper bound](Self::version_upper_bound), parsed
+    fn parsed_upper_bound(&self) -> Result<Option<latest::Parsed<'_>>> {
+        self.version_upper_bound
+            .as_deref()
+            .map(|bound| self.version_scheme.parse_bound(bound))
+            .transpose()
+            .context("Field `version_upper_bound` is invalid")
+    }
 
-        let version_upper_bound: Option<Version<'_>> = self
-            .version_upper_bound
+    /// The [requirement](Self::version_requirement), parsed
+    fn parsed_requirement(&self) -> Result<Option<semver::VersionReq>> {
+        anyhow::ensure!(
+            self.version_requirement.is_none()
+                || self.version_scheme.comparator != latest::Comparator::Lexicographic,
+            "Version requirements can't be used when comparing the tags lexicographically"
+        );
+        self.version_requirement
             .as_deref()
-            .map(lenient_semver_parser::parse::<Version>)
+            .map(semver::VersionReq::parse)
             .transpose()
-            .map_err(|err| err.owned())
-            .context("Field `version_upper_bound` is invalid")?;
+            .context("Field `version_requirement` is invalid")
+    }
 
-        let latest = latest_release(
-            fetch_tags(&repo_url)
-                .await
-                .context("Couldn't fetch the release tags")?
-                .iter()
-                /* Strip the common prefix, filter those that don't have it (that should actually never happen) */
-                .filter_map(|tag| tag.ref_.strip_prefix("refs/tags/")),
+    /// Whether the pin may be at `version`, e.g. after its configuration was changed
+    ///
+    /// This only checks the tag against the filters, and not whether it still exists.
+    pub fn admits(&self, version: &ReleaseVersion) -> Result<bool> {
+        if let Some(tag) = &self.moving_tag {
+            return Ok(version.version == *tag && version.tag_commit.is_some());
+        }
+        if version.tag_commit.is_some() {
+            return Ok(false);
+        }
+        Ok(self
+            .latest_tag(std::iter::once(version.version.as_str()))?
+            .is_some())
+    }
+
+    /// The latest release among `tags` (without `refs/tags/`) that passes the filters of the pin
+    pub fn latest_tag<'a>(&self, tags: impl Iterator<Item = &'a str>) -> Result<Option<String>> {
+        let prefix = self.release_prefix.as_deref().unwrap_or_default();
+        Ok(latest_release(
+            &self.version_scheme,
+            tags.filter(|tag| self.listed(tag.strip_prefix(prefix).unwrap_or(tag))),
             self.pre_releases,
-            version_upper_bound.as_ref(),
+            self.parsed_upper_bound()?.as_ref(),
             self.release_prefix.as_deref(),
-        )
-            .ok_or_else(|| anyhow::format_err!("Repository has no matching release tags"))?;
+            self.parsed_requirement()?.as_ref(),
+        )?
+        .map(|latest| latest.tag))
+    }
+
+    /// Look up the commit of the [moving tag](Self::moving_tag)
+    async fn resolve_moving_tag(
+        &self,
+        tag: &str,
+        old: Option<&ReleaseVersion>,
+    ) -> Result<ReleaseVersion> {
+        let revision = self
+            .repository
+            .resolve_ref(&format!("refs/tags/{tag}"))
+            .await?
+            .revision;
+        match old.and_then(|old| old.tag_commit.as_deref()) {
+            Some(old) if old != revision => {
+                log::info!("The tag {tag} moved from {old} to {revision}")
+            },
+            _ => {},
+        }
+        Ok(ReleaseVersion {
+            version: tag.to_owned(),
+            epoch: self.epoch,
+            tag_commit: Some(revision),
+        })
+    }
+
+    /// The tarball of the version, if the forge has any
+    ///
+    /// A moving tag may have moved since, so its commit is fetched instead of the tag.
+    fn archive_url(&self, version: &ReleaseVersion) -> Result<Option<Url>> {
+        match &version.tag_commit {
+            Some(commit) => self.repository.url(commit),
+            None => self.repository.release_url(&version.version),
+        }
+    }
+
+    /// Make sure that updating from `old` to the `latest` tag (without the release prefix) doesn't
+    /// go back in versions, unless the epoch was bumped since
+    fn ensure_monotonic(&self, old: &ReleaseVersion, latest: &str) -> Result<()> {
+        anyhow::ensure!(
+            old.epoch <= self.epoch,
+            "The current version {} was pinned in epoch {}, but the pin is at epoch {}",
+            old.version,
+            old.epoch,
+            self.epoch,
+        );
+        if old.epoch < self.epoch {
+            log::info!(
+                "The epoch was bumped from {} to {}, not ensuring version monotonicity",
+                old.epoch,
+                self.epoch
+            );
+            return Ok(());
+        }
 
         // If we have a release prefix strip it from the previous version for semver comparison.
         // If the old version didn't have a prefix we keep it as is.
-        let old = match (old, &self.release_prefix) {
-            (Some(version), None) => Some(version.clone()),
-            (Some(old), Some(prefix)) => {
-                let version = match old.version.strip_prefix(prefix) {
-                    None => old.version.clone(),
-                    Some(v) => v.into(),
-                };
-                Some(GenericVersion { version })
+        let old = match &self.release_prefix {
+            Some(prefix) => old.version.strip_prefix(prefix).unwrap_or(&old.version),
+            None => &old.version,
+        };
+        let rewrites = self.version_scheme.rewrites()?;
+        let old_normalized = rewrites.apply(old);
+        let old_version = self.version_scheme.parse(&old_normalized).ok_or(());
+        /* Tags are filtered by this exact requirement, but the forge's latest release is not */
+        let latest_normalized = rewrites.apply(latest);
+        let latest_version = self.version_scheme.parse(&latest_normalized).ok_or(());
+        match (old_version, latest_version) {
+            (Ok(old_version), Ok(latest)) => {
+                anyhow::ensure!(
+                    latest >= old_version,
+                    "Failed to ensure version monotonicity, latest found version is {} but current is {}",
+                    latest,
+                    old_version,
+                );
+            },
+            (Err(_), _) => {
+                log::warn!(
+                    "Old version ({}) failed to parse as SemVer, cannot ensure monotonicity",
+                    old
+                );
+            },
+            (_, Err(_)) => {
+                log::warn!(
+                    "Latest version ({}) failed to parse as SemVer, cannot ensure monotonicity",
+                    latest
+                );
             },
-            (None, _) => None,
+        }
+        Ok(())
+    }
+
+    /// Ask the forge for its latest release, see [`forge_latest`](Self::forge_latest)
+    ///
+    /// Returns `None` if the forge has no opinion, or if its answer does not match our filters.
+    async fn forge_latest_release(
+        &self,
+        version_upper_bound: Option<&latest::Parsed<'_>>,
+        version_requirement: Option<&semver::VersionReq>,
+    ) -> Result<Option<LatestRelease>> {
+        let Some(forge) = self.repository.forge() else {
+            return Ok(None);
+        };
+        let Some(release) = forge
+            .latest_release(self.pre_releases)
+            .await
+            .context("Couldn't fetch the latest release")?
+        else {
+            return Ok(None);
         };
 
-        if let Some(old) = old {
-            let old_version = lenient_semver_parser::parse::<Version>(&old.version);
-            let latest = lenient_semver_parser::parse::<Version>(&latest.name)
-                /* The first thing we do is filter tags with this exact requirement. */
-                .expect("Latest version must parse as SemVer");
-            match old_version {
-                Ok(old_version) => {
-                    anyhow::ensure!(
-                       latest >= old_version,
-                       "Failed to ensure version monotonicity, latest found version is {} but current is {}",
-                       latest,
-                       old_version,
-                   );
-                },
-                Err(_) => {
+        let name = match &self.release_prefix {
+            Some(prefix) => match release.tag.strip_prefix(prefix) {
+                Some(name) => name.to_owned(),
+                None => {
                     log::warn!(
-                        "Old version ({}) failed to parse as SemVer, cannot ensure monotonicity",
-                        old.version
+                        "The latest release {} does not have the prefix {}, ignoring it",
+                        release.tag,
+                        prefix
+                    );
+                    return Ok(None);
+                },
+            },
+            None => release.tag.clone(),
+        };
+        if !self.listed(&name) {
+            log::info!(
+                "The latest release {} is excluded, falling back to the tags",
+                release.tag
+            );
+            return Ok(None);
+        }
+        let normalized = self.version_scheme.normalize(&name)?;
+        if let Some(upper_bound) = version_upper_bound {
+            if self.version_scheme.parse(&normalized).is_some()
+                && !self.version_scheme.below(&normalized, upper_bound)
+            {
+                log::info!(
+                    "The latest release {} is out of bounds, falling back to the tags",
+                    release.tag
+                );
+                return Ok(None);
+            }
+        }
+        if let (Some(requirement), Some(version)) =
+            (version_requirement, self.version_scheme.semver(&normalized))
+        {
+            if !meets(requirement, version) {
+                log::info!(
+                    "The latest release {} does not match {requirement}, falling back to the tags",
+                    release.tag
+                );
+                return Ok(None);
+            }
+        }
+        Ok(Some(LatestRelease {
+            tag: release.tag,
+            name,
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for GitReleasePin {
+    type Version = ReleaseVersion;
+    type Hashes = ReleasePinHashes;
+
+    async fn update(&self, old: Option<&ReleaseVersion>) -> Result<ReleaseVersion> {
+        if let Some(tag) = &self.moving_tag {
+            return self.resolve_moving_tag(tag, old).await;
+        }
+
+        let version_upper_bound = self.parsed_upper_bound()?;
+        let version_requirement = self.parsed_requirement()?;
+
+        let forge_latest = if self.forge_latest {
+            match self
+                .forge_latest_release(version_upper_bound.as_ref(), version_requirement.as_ref())
+                .await
+            {
+                Ok(Some(latest)) => Some(latest),
+                Ok(None) => {
+                    log::info!(
+                        "The forge does not mark any release as latest, falling back to the tags"
                     );
+                    None
+                },
+                Err(err) => {
+                    log::warn!("{err:#}, falling back to the tags");
+                    provenance::record_missing("latest release");
+                    None
                 },
             }
+        } else {
+            None
+        };
+        let latest = match forge_latest {
+            Some(latest) => latest,
+            None => {
+                let releases = releases(
+                    &self.version_scheme,
+                    self.repository
+                        .list_tags()
+                        .await
+                        .context("Couldn't fetch the release tags")?
+                        .iter()
+                        /* Strip the common prefix, filter those that don't have it (that should actually never happen) */
+                        .filter_map(|tag| tag.ref_.strip_prefix("refs/tags/")),
+                    self.pre_releases,
+                    version_upper_bound.as_ref(),
+                    self.release_prefix.as_deref(),
+                    version_requirement.as_ref(),
+                )?
+                .into_iter()
+                .filter(|release| self.listed(&release.name))
+                .collect::<Vec<_>>();
+                match &self.reachable_from {
+                    Some(branch) => self
+                        .repository
+                        .newest_reachable(releases, |release| &release.tag, branch)
+                        .await
+                        .with_context(|| format!("Couldn't find the tags on {branch}"))?
+                        .ok_or_else(|| {
+                            anyhow::format_err!("No matching release tag is on {branch}")
+                        })?,
+                    None => releases.into_iter().next().ok_or_else(|| {
+                        anyhow::format_err!("Repository has no matching release tags")
+                    })?,
+                }
+            },
+        };
+
+        if let Some(old) = old {
+            self.ensure_monotonic(old, &latest.name)?;
         }
 
-        Ok(GenericVersion {
+        Ok(ReleaseVersion {
             version: latest.tag,
+            epoch: self.epoch,
+            tag_commit: None,
         })
     }
 
-    async fn fetch(&self, version: &GenericVersion) -> Result<ReleasePinHashes> {
+    async fn fetch(&self, version: &ReleaseVersion) -> Result<ReleasePinHashes> {
         let repo_url = self.repository.git_url()?;
 
-        let revision = fetch_ref(&repo_url, format!("refs/tags/{}", version.version))
-            .await?
-            .revision;
+        let revision = match &version.tag_commit {
+            Some(commit) => commit.clone(),
+            None => {
+                self.repository
+                    .resolve_ref(&format!("refs/tags/{}", version.version))
+                    .await?
+                    .revision
+            },
+        };
 
         if self.submodules {
+            anyhow::ensure!(
+                self.prefer != Some(Prefer::Tarball),
+                "Tarballs don't contain submodules, please use `prefer = \"git\"` instead"
+            );
             Ok(ReleasePinHashes {
                 url: None,
                 hash: nix::nix_prefetch_git(&repo_url, &revision, true).await?,
                 revision,
+                requires_auth: false,
             })
         } else {
             // Try to find an URL for fetchtarball first, as it is faster than fetchgit
-            let url = self.repository.release_url(&version.version)?;
-            let hash = match url.as_ref() {
-                Some(url) => nix::nix_prefetch_tarball(url).await?,
-                None => nix::nix_prefetch_git(&repo_url, &revision, false).await?,
+            let url = preferred_archive(self.prefer, self.archive_url(version)?)?;
+            let url = self
+                .repository
+                .stable_archive(self.prefer, url, &revision)
+                .await;
+            let (hash, requires_auth) = match url.as_ref() {
+                Some(url) => self.repository.prefetch_archive(url).await?,
+                None => (
+                    nix::nix_prefetch_git(&repo_url, &revision, false).await?,
+                    false,
+                ),
             };
             Ok(ReleasePinHashes {
                 url,
                 hash,
                 revision,
+                requires_auth,
             })
         }
     }
+
+    async fn cross_check(
+        &self,
+        version: &ReleaseVersion,
+        hashes: &ReleasePinHashes,
+    ) -> Result<Option<CrossCheck>> {
+        /* Tarballs don't contain submodules */
+        if self.submodules {
+            return Ok(None);
+        }
+        self.repository
+            .cross_check(
+                &hashes.revision,
+                hashes.url.as_ref(),
+                self.archive_url(version)?,
+                &hashes.hash,
+            )
+            .await
+    }
 }
 
 /// Output of `git ls-remote`
@@ -581,14 +1375,44 @@ impl RemoteInfo {
     }
 }
 
+/// A git command that fails instead of asking for credentials or about unknown hosts
+///
+/// How git runs ssh is left alone if the user configured it, e.g. to use PuTTY on Windows, where
+/// there may not even be an `ssh` for `GIT_SSH_COMMAND`.
+pub fn command() -> Command {
+    let mut command = Command::new("git");
+    command
+        .kill_on_drop(true)
+        // Disable any interactive login attempts, failing gracefully instead
+        .env("GIT_TERMINAL_PROMPT", "0")
+        // The Git Credential Manager, which would open a login window
+        .env("GCM_INTERACTIVE", "never");
+    if !configures_ssh() {
+        command.env("GIT_SSH_COMMAND", "ssh -o StrictHostKeyChecking=yes");
+    }
+    command
+}
+
+/// Whether the user told git how to run ssh, see [`command`]
+fn configures_ssh() -> bool {
+    static CONFIGURED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *CONFIGURED.get_or_init(|| {
+        ["GIT_SSH_COMMAND", "GIT_SSH"]
+            .iter()
+            .any(|variable| std::env::var_os(variable).is_some())
+            || std::process::Command::new("git")
+                .args(["config", "--get", "core.sshCommand"])
+                .output()
+                .is_ok_and(|output| output.status.success())
+    })
+}
+
 /// Convenience wrapper around calling `git ls-remote`
 async fn fetch_remote(url: &str, args: &[&str]) -> Result<Vec<RemoteInfo>> {
+    provenance::record_endpoint(url);
     let result = async {
         log::debug!("Executing `git ls-remote {}`", args.join(" "));
-        let process = Command::new("git")
-            // Disable any interactive login attempts, failing gracefully instead
-            .env("GIT_TERMINAL_PROMPT", "0")
-            .env("GIT_SSH_COMMAND", "ssh -o StrictHostKeyChecking=yes")
+        let process = command()
             .arg("ls-remote")
             .args(args)
             .output()
@@ -707,41 +1531,89 @@ impl LatestRelease {
 
 /// Take an iterator of tags and spit out the latest release
 fn latest_release<'a>(
+    scheme: &latest::VersionScheme,
     tags: impl Iterator<Item = &'a str>,
     pre_releases: bool,
-    version_upper_bound: Option<&Version>,
+    version_upper_bound: Option<&latest::Parsed>,
     prefix: Option<&str>,
-) -> Option<LatestRelease> {
+    version_requirement: Option<&semver::VersionReq>,
+) -> Result<Option<LatestRelease>> {
+    Ok(releases(
+        scheme,
+        tags,
+        pre_releases,
+        version_upper_bound,
+        prefix,
+        version_requirement,
+    )?
+    .into_iter()
+    .next())
+}
+
+/// Whether `version` meets the [requirement](GitReleasePin::version_requirement)
+///
+/// Unlike Cargo, which only admits pre-releases of the versions named in the requirement.
+fn meets(requirement: &semver::VersionReq, mut version: semver::Version) -> bool {
+    version.pre = semver::Prerelease::EMPTY;
+    version.build = semver::BuildMetadata::EMPTY;
+    requirement.matches(&version)
+}
+
+/// Take an iterator of tags and spit out the releases among them, latest first
+fn releases<'a>(
+    scheme: &latest::VersionScheme,
+    tags: impl Iterator<Item = &'a str>,
+    pre_releases: bool,
+    version_upper_bound: Option<&latest::Parsed>,
+    prefix: Option<&str>,
+    version_requirement: Option<&semver::VersionReq>,
+) -> Result<Vec<LatestRelease>> {
     // Optionally filter all tags by a prefix
     let tags: Box<dyn Iterator<Item = &'a str>> = match prefix {
         None => Box::new(tags),
         Some(prefix) => Box::new(tags.filter_map(move |tag| tag.strip_prefix(prefix))),
     };
+    let rewrites = scheme.rewrites()?;
+    let tags = tags
+        .map(|tag| (tag, rewrites.apply(tag)))
+        .collect::<Vec<_>>();
 
-    let tag = tags
+    let mut releases = tags
+        .iter()
         /* Try to parse as version, ignore those that are invalid (not every tag will be a release) */
-        .filter_map(|tag| lenient_semver_parser::parse::<Version>(tag)
-            .ok()
-            .map(|version| (tag, version))
+        .filter_map(|(tag, normalized)| scheme.parse(normalized)
+            .map(|version| (*tag, normalized, version))
         )
         /* Optionally filter out pre-releases */
-        .filter(|(_, version)| pre_releases || !version.is_pre_release())
+        .filter(|(_, _, version)| pre_releases || !version.is_pre_release())
         /* Filter against our upper bound */
-        .filter(|(_, version)| match &version_upper_bound {
-            Some(version_upper_bound) => version < version_upper_bound,
+        .filter(|(_, normalized, _)| match version_upper_bound {
+            Some(version_upper_bound) => scheme.below(normalized, version_upper_bound),
             None => true,
         })
-        /* Get the latest version */
-        .max_by(|(_, version_a), (_, version_b)| version_a.cmp(version_b))
-        .map(|(tag, _)| tag.to_owned());
-
-    tag.map(|tag| LatestRelease {
-        tag: match prefix {
-            Some(p) => format!("{p}{tag}"),
-            None => tag.clone(),
-        },
-        name: tag,
-    })
+        /* And against the requirement */
+        .filter(|(_, normalized, _)| match &version_requirement {
+            Some(requirement) => scheme
+                .semver(normalized)
+                .is_some_and(|version| meets(requirement, version)),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    /* Latest version first */
+    releases.sort_by(|(tag_a, _, version_a), (tag_b, _, version_b)| {
+        latest::cmp((version_b, tag_b), (version_a, tag_a))
+    });
+
+    Ok(releases
+        .into_iter()
+        .map(|(tag, _, _)| LatestRelease {
+            tag: match prefix {
+                Some(p) => format!("{p}{tag}"),
+                None => tag.to_owned(),
+            },
+            name: tag.to_owned(),
+        })
+        .collect())
 }
 
 /* All repositories used for tests are dead, super dead, or
@@ -753,43 +1625,75 @@ fn latest_release<'a>(
 #[cfg(test)]
 mod test {
     use super::*;
+    use lenient_version::Version;
 
     #[tokio::test]
     async fn test_latest_release() {
-        let v2 = lenient_semver_parser::parse::<Version>("2").unwrap();
+        let v2 = latest::Parsed::Lenient(lenient_semver_parser::parse::<Version>("2").unwrap());
         assert_eq!(
-            latest_release(["foo"].iter().copied(), false, None, None),
+            latest_release(
+                &Default::default(),
+                ["foo"].iter().copied(),
+                false,
+                None,
+                None,
+                None
+            )
+            .unwrap(),
             None
         );
         assert_eq!(
-            latest_release(["1.0", "foo"].iter().copied(), false, None, None),
+            latest_release(
+                &Default::default(),
+                ["1.0", "foo"].iter().copied(),
+                false,
+                None,
+                None,
+                None
+            )
+            .unwrap(),
             Some(LatestRelease::tag("1.0"))
         );
         assert_eq!(
-            latest_release(["1.0", "2.0"].iter().copied(), false, Some(&v2), None),
+            latest_release(
+                &Default::default(),
+                ["1.0", "2.0"].iter().copied(),
+                false,
+                Some(&v2),
+                None,
+                None
+            )
+            .unwrap(),
             Some(LatestRelease::tag("1.0"))
         );
         assert_eq!(
             latest_release(
+                &Default::default(),
                 ["1.0", "2.0", "2.0-pre"].iter().copied(),
                 false,
                 Some(&v2),
+                None,
                 None
-            ),
+            )
+            .unwrap(),
             Some(LatestRelease::tag("1.0"))
         );
         assert_eq!(
             latest_release(
+                &Default::default(),
                 ["1.0", "2.0", "2.0-pre"].iter().copied(),
                 true,
                 Some(&v2),
+                None,
                 None
-            ),
+            )
+            .unwrap(),
             Some(LatestRelease::tag("2.0-pre"))
         );
 
         assert_eq!(
             latest_release(
+                &Default::default(),
                 [
                     "foo/1.0",
                     "bar/2.0",
@@ -802,13 +1706,235 @@ mod test {
                 .copied(),
                 false,
                 None,
-                Some("zes/")
-            ),
+                Some("zes/"),
+                None
+            )
+            .unwrap(),
             Some(LatestRelease {
                 tag: "zes/2.0".into(),
                 name: "2.0".into()
             })
         );
+
+        /* Equal versions are picked regardless of the order they are listed in */
+        for tags in [["1.0", "v1.0", "1.0.0"], ["1.0.0", "v1.0", "1.0"]] {
+            assert_eq!(
+                latest_release(
+                    &Default::default(),
+                    tags.iter().copied(),
+                    false,
+                    None,
+                    None,
+                    None
+                )
+                .unwrap(),
+                Some(LatestRelease::tag("v1.0"))
+            );
+        }
+
+        /* Candidates for pins that only consider the tags reachable from a branch */
+        assert_eq!(
+            releases(
+                &Default::default(),
+                ["1.9.1", "2.0.0", "docs", "1.10.0", "2.1.0-rc1"]
+                    .iter()
+                    .copied(),
+                false,
+                None,
+                None,
+                None
+            )
+            .unwrap(),
+            ["2.0.0", "1.10.0", "1.9.1"].map(LatestRelease::tag)
+        );
+
+        /* Tracking a series with a requirement */
+        let series = semver::VersionReq::parse(">=1.2, <2.0").unwrap();
+        let tags = ["1.1.5", "v1.2", "1.4.0-rc1", "1.4.0", "2.0.0-rc1", "2.0.0"];
+        assert_eq!(
+            releases(
+                &Default::default(),
+                tags.iter().copied(),
+                true,
+                None,
+                None,
+                Some(&series)
+            )
+            .unwrap(),
+            ["1.4.0", "1.4.0-rc1", "v1.2"].map(LatestRelease::tag)
+        );
+        let tilde = semver::VersionReq::parse("~1.2").unwrap();
+        assert_eq!(
+            latest_release(
+                &Default::default(),
+                tags.iter().copied(),
+                false,
+                None,
+                None,
+                Some(&tilde)
+            )
+            .unwrap(),
+            Some(LatestRelease::tag("v1.2"))
+        );
+    }
+
+    #[test]
+    fn test_epoch() {
+        let mut pin = GitReleasePin::new(
+            Repository::github("owner", "repo"),
+            false,
+            None,
+            Some("release/".into()),
+            false,
+        );
+        let old = ReleaseVersion::new("release/3.9");
+        pin.ensure_monotonic(&old, "3.10").unwrap();
+        /* Upstream restarted at 1.0 */
+        pin.ensure_monotonic(&old, "1.0").unwrap_err();
+        pin.epoch = 1;
+        pin.ensure_monotonic(&old, "1.0").unwrap();
+        /* Within the new epoch, versions are checked again */
+        let old = ReleaseVersion {
+            version: "release/1.1".into(),
+            epoch: 1,
+            tag_commit: None,
+        };
+        pin.ensure_monotonic(&old, "1.0").unwrap_err();
+        /* Epochs never go back */
+        pin.epoch = 0;
+        pin.ensure_monotonic(&old, "1.2").unwrap_err();
+
+        assert_eq!(
+            serde_json::to_value(ReleaseVersion::new("1.0")).unwrap(),
+            serde_json::json!({ "version": "1.0" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_api_fallback() {
+        let blocked = || -> Result<u32> { Err(anyhow::format_err!("blocked by the proxy")) };
+        assert_eq!(
+            api_fallback(Ok(1), || async { unreachable!() })
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            api_fallback(blocked(), || async { Ok(Some(2)) })
+                .await
+                .unwrap(),
+            2
+        );
+        /* The forge has no such API, or it failed too */
+        let err = api_fallback(blocked(), || async { Ok(None) })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "blocked by the proxy");
+        let err = api_fallback(blocked(), || async { anyhow::bail!("rate limited") })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "blocked by the proxy");
+    }
+
+    #[test]
+    fn test_admits() {
+        let mut pin = GitReleasePin::new(
+            Repository::github("owner", "repo"),
+            false,
+            None,
+            None,
+            false,
+        );
+        let v1 = ReleaseVersion::new("v1.4.2");
+        assert!(pin.admits(&v1).unwrap());
+        assert!(!pin.admits(&ReleaseVersion::new("v2.0-rc1")).unwrap());
+        pin.pre_releases = true;
+        assert!(pin.admits(&ReleaseVersion::new("v2.0-rc1")).unwrap());
+
+        pin.version_upper_bound = Some("1.4".into());
+        assert!(!pin.admits(&v1).unwrap());
+        pin.version_upper_bound = Some("2".into());
+        assert!(pin.admits(&v1).unwrap());
+        pin.version_requirement = Some(">=1.5".into());
+        assert!(!pin.admits(&v1).unwrap());
+        pin.version_requirement = Some("^1".into());
+        assert!(pin.admits(&v1).unwrap());
+        pin.version_requirement = Some("1.x.y".into());
+        assert!(pin.admits(&v1).is_err());
+        pin.version_requirement = None;
+        pin.release_prefix = Some("release/".into());
+        assert!(!pin.admits(&v1).unwrap());
+        assert!(pin.admits(&ReleaseVersion::new("release/1.0")).unwrap());
+
+        pin.moving_tag = Some("stable".into());
+        assert!(!pin.admits(&v1).unwrap());
+        let stable = ReleaseVersion {
+            tag_commit: Some("0123456789abcdef0123456789abcdef01234567".into()),
+            ..ReleaseVersion::new("stable")
+        };
+        assert!(pin.admits(&stable).unwrap());
+
+        pin.moving_tag = None;
+        pin.exclude_versions = vec!["1.0".into()];
+        assert!(!pin.admits(&ReleaseVersion::new("release/1.0")).unwrap());
+        assert!(pin.admits(&ReleaseVersion::new("release/1.1")).unwrap());
+        pin.only_versions = vec!["2.*".into()];
+        assert!(!pin.admits(&ReleaseVersion::new("release/1.1")).unwrap());
+        pin.only_versions.clear();
+        pin.exclude_versions.clear();
+
+        /* Bounds are calendar versions too */
+        pin.release_prefix = None;
+        pin.version_scheme.comparator = latest::Comparator::Calver;
+        pin.version_upper_bound = Some("2025".into());
+        assert!(pin.admits(&ReleaseVersion::new("r20240915")).unwrap());
+        assert!(!pin.admits(&ReleaseVersion::new("r20250101")).unwrap());
+        pin.version_requirement = Some("~2024.9".into());
+        assert!(pin.admits(&ReleaseVersion::new("2024.09.2")).unwrap());
+        assert!(!pin.admits(&ReleaseVersion::new("2024.10.1")).unwrap());
+        pin.version_scheme.comparator = latest::Comparator::Lexicographic;
+        assert!(pin.admits(&ReleaseVersion::new("2024-09-15")).is_err());
+    }
+
+    #[test]
+    fn test_moving_tag() {
+        let mut pin = GitReleasePin::new(
+            Repository::github("owner", "repo"),
+            false,
+            None,
+            None,
+            false,
+        );
+        pin.moving_tag = Some("stable".into());
+        let version = |revision: &str| ReleaseVersion {
+            version: "stable".into(),
+            epoch: 0,
+            tag_commit: Some(revision.into()),
+        };
+        let old = version("0123456789abcdef0123456789abcdef01234567");
+        let new = version("89abcdef0123456789abcdef0123456789abcdef");
+        /* A moved tag is a change, and the commit is fetched instead of the tag */
+        assert_eq!(diff::diff(&old.properties(), &new.properties()).len(), 1);
+        /* Both the version and the hashes have a commit, which must not clash in the lock file */
+        let locked: Pin = (pin.clone(), new.clone()).into();
+        let mut locked = serde_json::to_value(locked).unwrap();
+        locked["revision"] = new.tag_commit.clone().into();
+        locked["url"] = serde_json::Value::Null;
+        locked["hash"] = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".into();
+        let locked: Pin = serde_json::from_value(locked).unwrap();
+        assert!(locked.has_hashes());
+        assert_eq!(locked.version_properties(), new.properties());
+        assert_eq!(
+            pin.archive_url(&new).unwrap().unwrap().as_str(),
+            "https://github.com/owner/repo/archive/89abcdef0123456789abcdef0123456789abcdef.tar.gz"
+        );
+        assert_eq!(
+            pin.archive_url(&ReleaseVersion::new("v1.0"))
+                .unwrap()
+                .unwrap()
+                .as_str(),
+            "https://api.github.com/repos/owner/repo/tarball/refs/tags/v1.0"
+        );
     }
 
     #[tokio::test]
@@ -895,6 +2021,81 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_preferred_archive() {
+        let url: Url = "https://github.com/andir/npins/archive/v0.3.1.tar.gz"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            preferred_archive(None, Some(url.clone())).unwrap(),
+            Some(url.clone())
+        );
+        assert_eq!(preferred_archive(None, None).unwrap(), None);
+        assert_eq!(
+            preferred_archive(Some(Prefer::Git), Some(url.clone())).unwrap(),
+            None
+        );
+        assert_eq!(
+            preferred_archive(Some(Prefer::Tarball), Some(url.clone())).unwrap(),
+            Some(url)
+        );
+        assert!(preferred_archive(Some(Prefer::Tarball), None).is_err());
+    }
+
+    #[test]
+    fn test_parse_gitattributes() {
+        let attributes = "# Normalize line endings\n* text=auto\n\n/tests export-ignore\n/src/version.c ident export-subst\n";
+        assert!(gitattributes_set(attributes, "export-subst"));
+        assert!(gitattributes_set(attributes, "export-ignore"));
+        assert!(gitattributes_set(attributes, "text"));
+        assert!(!gitattributes_set(attributes, "binary"));
+
+        assert!(!gitattributes_set("# export-subst\n", "export-subst"));
+        assert!(!gitattributes_set(
+            "version.txt -export-subst\n",
+            "export-subst"
+        ));
+        assert!(!gitattributes_set(
+            "version.txt !export-subst\n",
+            "export-subst"
+        ));
+        assert!(!gitattributes_set("export-subst binary\n", "export-subst"));
+    }
+
+    #[tokio::test]
+    async fn test_tracked_ref() {
+        let mut pin = GitPin::new(Repository::github("owner", "repo"), "main".into(), false);
+        assert_eq!(pin.tracked_ref(), "refs/heads/main");
+        pin.branch = "refs/pull/42/head".into();
+        assert_eq!(pin.tracked_ref(), "refs/pull/42/head");
+        pin.branch = "refs/tags/stable".into();
+        assert!(pin.update(None).await.is_err());
+    }
+
+    #[test]
+    fn test_verified_on() {
+        let mut version =
+            GitRevision::new("1edb0a9cebe046cc915a218c57dbf7f40739aeee".into()).unwrap();
+        let json = serde_json::to_value(&version).unwrap();
+        assert_eq!(json.get("verified_on"), None);
+        assert_eq!(
+            serde_json::from_value::<GitRevision>(json).unwrap(),
+            version
+        );
+
+        version.verified_on = Some("main".into());
+        let json = serde_json::to_value(&version).unwrap();
+        assert_eq!(json["verified_on"], "main");
+        assert_eq!(
+            serde_json::from_value::<GitRevision>(json).unwrap(),
+            version
+        );
+        assert_eq!(
+            diff::Diff::properties(&version).last().unwrap(),
+            &("verified on".to_owned(), "main".to_owned())
+        );
+    }
+
     #[tokio::test]
     async fn test_git_update() -> Result<()> {
         let pin = GitPin {
@@ -905,6 +2106,7 @@ mod test {
             },
             branch: "master".into(),
             submodules: false,
+            prefer: None,
         };
         let version = pin.update(None).await?;
         assert_eq!(
@@ -912,6 +2114,7 @@ mod test {
             GitRevision {
                 revision: "1edb0a9cebe046cc915a218c57dbf7f40739aeee".into(),
                 timestamp: None,
+                verified_on: None,
             }
         );
         assert_eq!(
@@ -919,6 +2122,7 @@ mod test {
             OptionalUrlHashes {
                 url: None,
                 hash: "sha256-zUM/evAqAwwjGXg67IVzqZvvwp2NjFG1HAUSdLv98Z0=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -932,22 +2136,27 @@ mod test {
             },
             pre_releases: false,
             version_upper_bound: None,
+            version_requirement: None,
             release_prefix: None,
             submodules: false,
+            prefer: None,
+            forge_latest: false,
+            version_scheme: Default::default(),
+            epoch: 0,
+            moving_tag: None,
+            reachable_from: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         };
         let version = pin.update(None).await?;
-        assert_eq!(
-            version,
-            GenericVersion {
-                version: "v1.1".into(),
-            }
-        );
+        assert_eq!(version, ReleaseVersion::new("v1.1"));
         assert_eq!(
             pin.fetch(&version).await?,
             ReleasePinHashes {
                 url: None,
                 hash: "sha256-BjxJ5aG8NyfDLcBNZrDVV2CAK4tdHNCBdiuJYKB8BmA=".into(),
                 revision: "35be5b2b2c3431de1100996487d53134f658b866".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -959,9 +2168,11 @@ mod test {
             repository: Repository::GitHub {
                 owner: "oliverwatkins".into(),
                 repo: "swing_library".into(),
+                aliases: Vec::new(),
             },
             branch: "master".into(),
             submodules: false,
+            prefer: None,
         };
         let version = pin.update(None).await?;
         assert_eq!(
@@ -969,6 +2180,7 @@ mod test {
             GitRevision {
                 revision: "1edb0a9cebe046cc915a218c57dbf7f40739aeee".into(),
                 timestamp: Some("2018-12-17T09:26:57Z".into()),
+                verified_on: None,
             }
         );
         assert_eq!(
@@ -976,6 +2188,7 @@ mod test {
             OptionalUrlHashes {
                 url: Some("https://github.com/oliverwatkins/swing_library/archive/1edb0a9cebe046cc915a218c57dbf7f40739aeee.tar.gz".parse().unwrap()),
                 hash: "sha256-zUM/evAqAwwjGXg67IVzqZvvwp2NjFG1HAUSdLv98Z0=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -987,19 +2200,24 @@ mod test {
             repository: Repository::GitHub {
                 owner: "jstutters".into(),
                 repo: "MidiOSC".into(),
+                aliases: Vec::new(),
             },
             pre_releases: false,
             version_upper_bound: None,
+            version_requirement: None,
             release_prefix: None,
             submodules: false,
+            prefer: None,
+            forge_latest: false,
+            version_scheme: Default::default(),
+            epoch: 0,
+            moving_tag: None,
+            reachable_from: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         };
         let version = pin.update(None).await?;
-        assert_eq!(
-            version,
-            GenericVersion {
-                version: "v1.1".into(),
-            }
-        );
+        assert_eq!(version, ReleaseVersion::new("v1.1"));
         assert_eq!(
             pin.fetch(&version).await?,
             ReleasePinHashes {
@@ -1010,6 +2228,7 @@ mod test {
                         .unwrap()
                 ),
                 hash: "sha256-BjxJ5aG8NyfDLcBNZrDVV2CAK4tdHNCBdiuJYKB8BmA=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -1028,12 +2247,19 @@ mod test {
             repository: Repository::github("alexfedosov", "AFHorizontalDayPicker"),
             pre_releases: false,
             version_upper_bound: None,
+            version_requirement: None,
             release_prefix: None,
             submodules: false,
+            prefer: None,
+            forge_latest: false,
+            version_scheme: Default::default(),
+            epoch: 0,
+            moving_tag: None,
+            reachable_from: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         };
-        let version = GenericVersion {
-            version: "0.2.1".into(),
-        };
+        let version = ReleaseVersion::new("0.2.1");
         assert_eq!(
             pin.fetch(&version).await?,
             ReleasePinHashes {
@@ -1044,6 +2270,7 @@ mod test {
                         .unwrap()
                 ),
                 hash: "sha256-++ywpuReqTb6tn8DddmLpOK4yOOTgX7M8X5YkJS8OCs=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -1059,6 +2286,7 @@ mod test {
             },
             branch: "release-2.90".into(),
             submodules: false,
+            prefer: None,
         };
         let version = pin.update(None).await?;
         assert_eq!(
@@ -1066,6 +2294,7 @@ mod test {
             GitRevision {
                 revision: "4bbdb2f5564b9b42bcaf0e1eec28325300f31c72".into(),
                 timestamp: None,
+                verified_on: None,
             }
         );
         assert_eq!(
@@ -1073,6 +2302,7 @@ mod test {
             OptionalUrlHashes {
                 url: Some("https://git.lix.systems/lix-project/lix/archive/4bbdb2f5564b9b42bcaf0e1eec28325300f31c72.tar.gz".parse().unwrap()),
                 hash: "sha256-w8JAk9Z3Fmkyway0VCjy/PtoBC6bGQVhNfTzFA98Pg8=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -1088,16 +2318,20 @@ mod test {
             },
             pre_releases: false,
             version_upper_bound: Some("2.90.1".to_string()),
+            version_requirement: None,
             release_prefix: None,
             submodules: false,
+            prefer: None,
+            forge_latest: false,
+            version_scheme: Default::default(),
+            epoch: 0,
+            moving_tag: None,
+            reachable_from: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         };
         let version = pin.update(None).await?;
-        assert_eq!(
-            version,
-            GenericVersion {
-                version: "2.90.0".into(),
-            }
-        );
+        assert_eq!(version, ReleaseVersion::new("2.90.0"));
         assert_eq!(
             pin.fetch(&version).await?,
             ReleasePinHashes {
@@ -1108,6 +2342,7 @@ mod test {
                         .unwrap()
                 ),
                 hash: "sha256-f8k+BezKdJfmE+k7zgBJiohtS3VkkriycdXYsKOm3sc=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -1123,6 +2358,7 @@ mod test {
             },
             branch: "master".into(),
             submodules: false,
+            prefer: None,
         };
         let version = pin.update(None).await?;
         assert_eq!(
@@ -1130,6 +2366,7 @@ mod test {
             git::GitRevision {
                 revision: "e7145078163692697b843915a665d4f41139a65c".into(),
                 timestamp: None,
+                verified_on: None,
             }
         );
         assert_eq!(
@@ -1137,6 +2374,7 @@ mod test {
             OptionalUrlHashes {
                 url: Some("https://gitlab.com/api/v4/projects/maxigaz%2Fgitlab-dark/repository/archive.tar.gz?sha=e7145078163692697b843915a665d4f41139a65c".parse().unwrap()),
                 hash: "sha256-WzPqIwEe6HzISyeg1XBSHNO2fd9+Pc1T90RXBh7IrFo=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -1152,16 +2390,20 @@ mod test {
             },
             pre_releases: false,
             version_upper_bound: None,
+            version_requirement: None,
             release_prefix: None,
             submodules: false,
+            prefer: None,
+            forge_latest: false,
+            version_scheme: Default::default(),
+            epoch: 0,
+            moving_tag: None,
+            reachable_from: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         };
         let version = pin.update(None).await?;
-        assert_eq!(
-            version,
-            GenericVersion {
-                version: "v1.16.0".into(),
-            }
-        );
+        assert_eq!(version, ReleaseVersion::new("v1.16.0"));
         assert_eq!(
             pin.fetch(&version).await?,
             ReleasePinHashes {
@@ -1170,6 +2412,7 @@ mod test {
                     .parse()
                     .unwrap()),
                 hash: "sha256-jcOkr5tJdEw1RL3jB8ItE8PLOVNzQtOyzDv8x/ySiiA=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -1188,12 +2431,19 @@ mod test {
             },
             pre_releases: false,
             version_upper_bound: None,
+            version_requirement: None,
             release_prefix: None,
             submodules: false,
+            prefer: None,
+            forge_latest: false,
+            version_scheme: Default::default(),
+            epoch: 0,
+            moving_tag: None,
+            reachable_from: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         };
-        let version = GenericVersion {
-            version: "40.0".into(),
-        };
+        let version = ReleaseVersion::new("40.0");
 
         assert_eq!(
             pin.fetch(&version).await?,
@@ -1203,6 +2453,7 @@ mod test {
                     .parse()
                     .unwrap()),
                 hash: "sha256-dxgbhEQt9FIjsleC6ob6FJv5XdxmKMb+NWbxEtAJYtA=".into(),
+                requires_auth: false,
             }
         );
 
@@ -1219,6 +2470,7 @@ mod test {
             },
             branch: "master".into(),
             submodules: false,
+            prefer: None,
         };
         let version = pin.update(None).await?;
         assert_eq!(
@@ -1226,6 +2478,7 @@ mod test {
             git::GitRevision {
                 revision: "bca2071b6923d45d9aabac27b3ea1e40f5fa3006".into(),
                 timestamp: None,
+                verified_on: None,
             }
         );
         assert_eq!(
@@ -1233,6 +2486,7 @@ mod test {
             OptionalUrlHashes {
                 url: Some("https://gitlab.gnome.org/api/v4/projects/Archive%2Fgnome-games/repository/archive.tar.gz?sha=bca2071b6923d45d9aabac27b3ea1e40f5fa3006".parse().unwrap()),
                 hash: "sha256-r84Y5/hI0rM/UWK569+nWo+BHuovmlQh3Zs6U2Srx14=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -1248,22 +2502,27 @@ mod test {
             },
             pre_releases: false,
             version_upper_bound: None,
+            version_requirement: None,
             release_prefix: None,
             submodules: false,
+            prefer: None,
+            forge_latest: false,
+            version_scheme: Default::default(),
+            epoch: 0,
+            moving_tag: None,
+            reachable_from: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         };
         let version = pin.update(None).await?;
-        assert_eq!(
-            version,
-            GenericVersion {
-                version: "40.0".into(),
-            }
-        );
+        assert_eq!(version, ReleaseVersion::new("40.0"));
         assert_eq!(
             pin.fetch(&version).await?,
             ReleasePinHashes {
                 revision: "2c89145d52d072a4ca5da900c2676d890bfab1ff".into(),
                 url: Some("https://gitlab.gnome.org/api/v4/projects/Archive%2Fgnome-games/repository/archive.tar.gz?sha=40.0".parse().unwrap()),
                 hash: "sha256-6+XMyOJOm2DTqnr4iCFupjW+Z7td4J+GJwSv1Am/5e8=".into(),
+                requires_auth: false,
             }
         );
         Ok(())
@@ -0,0 +1,226 @@
+//! Pin a Go module from a module proxy
+//!
+//! Modules are resolved against <https://proxy.golang.org>, or the first proxy in `GOPROXY`, using
+//! the [GOPROXY protocol](https://go.dev/ref/mod#goproxy-protocol). Both the module's `.zip` and
+//! its `go.mod` are pinned, since Go needs the latter to resolve dependencies.
+
+use crate::*;
+use anyhow::{Context, Result};
+use lenient_version::Version;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct GoModPin {
+    /// The module path, e.g. `golang.org/x/tools`
+    pub module: String,
+    /// Only pin versions below this one (exclusive), see [`pypi::Pin::version_upper_bound`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_upper_bound: Option<String>,
+}
+
+impl diff::Diff for GoModPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("module".into(), self.module.clone())),
+            self.version_upper_bound
+                .as_ref()
+                .map(|version_upper_bound| {
+                    ("version_upper_bound".into(), version_upper_bound.clone())
+                }),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct GoModHashes {
+    /// The module's `.zip`
+    pub url: Url,
+    pub hash: String,
+    /// Hash of the module's `go.mod`, which is next to the `.zip`
+    pub go_mod_hash: String,
+}
+
+impl diff::Diff for GoModHashes {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![
+            ("url".into(), self.url.to_string()),
+            ("hash".into(), self.hash.clone()),
+            ("go_mod_hash".into(), self.go_mod_hash.clone()),
+        ]
+    }
+}
+
+/// The proxy to use, given the value of `GOPROXY`
+///
+/// `GOPROXY` is a list separated by `,` or `|`, which may contain `direct` and `off`. Those have no
+/// proxy to talk to, so the first actual URL wins.
+fn proxy_from(goproxy: Option<&str>) -> Result<Url> {
+    let proxy = goproxy
+        .into_iter()
+        .flat_map(|goproxy| goproxy.split([',', '|']))
+        .map(str::trim)
+        .find(|proxy| !proxy.is_empty() && !matches!(*proxy, "direct" | "off"))
+        .unwrap_or("https://proxy.golang.org");
+    proxy
+        .parse()
+        .with_context(|| format!("GOPROXY contains the invalid URL '{proxy}'"))
+}
+
+/// Escape a module path or version for use in a proxy URL
+///
+/// Proxies may run on case-insensitive file systems, so upper-case letters are replaced by `!`
+/// followed by the lower-case letter.
+fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c.is_ascii_uppercase() {
+            escaped.push('!');
+            escaped.push(c.to_ascii_lowercase());
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, Deserialize)]
+struct Info {
+    #[serde(rename = "Version")]
+    version: String,
+}
+
+fn proxy() -> Result<Url> {
+    proxy_from(std::env::var("GOPROXY").ok().as_deref())
+}
+
+impl GoModPin {
+    /// `<proxy>/<escaped module>/<path…>`
+    fn proxy_url(&self, proxy: &Url, path: &[&str]) -> Result<Url> {
+        /* The module path consists of several segments itself */
+        let module = escape(&self.module);
+        let url = forge::url_with_segments(proxy, module.split('/'))?;
+        forge::url_with_segments(&url, path)
+    }
+
+    /// `<proxy>/<escaped module>/@v/<escaped version>.<extension>`
+    fn file_url(&self, proxy: &Url, version: &str, extension: &str) -> Result<Url> {
+        self.proxy_url(proxy, &["@v", &format!("{}.{extension}", escape(version))])
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for GoModPin {
+    type Version = GenericVersion;
+    type Hashes = GoModHashes;
+
+    async fn update(&self, old: Option<&GenericVersion>) -> Result<GenericVersion> {
+        let proxy = proxy()?;
+        let version = match &self.version_upper_bound {
+            /* Usually the latest tag, otherwise a pseudo-version of the latest commit */
+            None => {
+                let info: Info = get_and_deserialize(self.proxy_url(&proxy, &["@latest"])?)
+                    .await
+                    .with_context(|| format!("Couldn't find the module {}", self.module))?;
+                info.version
+            },
+            Some(version_upper_bound) => {
+                let version_upper_bound =
+                    lenient_semver_parser::parse::<Version>(version_upper_bound)
+                        .map_err(|err| err.owned())
+                        .context("Field `version_upper_bound` is invalid")?;
+                let url = self.proxy_url(&proxy, &["@v", "list"])?;
+                provenance::record_endpoint(&url);
+                let list = http_get(url)?
+                    .send_throttled()
+                    .await?
+                    .error_for_status()
+                    .with_context(|| format!("Couldn't find the module {}", self.module))?
+                    .text()
+                    .await?;
+                list.lines()
+                    .filter_map(|tag| {
+                        lenient_semver_parser::parse::<Version>(tag)
+                            .ok()
+                            .map(|version| (version, tag))
+                    })
+                    .filter(|(version, _)| {
+                        !version.is_pre_release() && *version < version_upper_bound
+                    })
+                    .max_by(|(a, a_tag), (b, b_tag)| latest::cmp((a, a_tag), (b, b_tag)))
+                    .map(|(_, tag)| tag.to_owned())
+                    .context("No matching versions found")?
+            },
+        };
+
+        if let Some(old) = old {
+            if let (Ok(old_version), Ok(new_version)) = (
+                lenient_semver_parser::parse::<Version>(&old.version),
+                lenient_semver_parser::parse::<Version>(&version),
+            ) {
+                anyhow::ensure!(
+                    new_version >= old_version,
+                    "Failed to ensure version monotonicity, latest found version is {} but current is {}",
+                    version,
+                    old.version,
+                );
+            }
+        }
+
+        Ok(GenericVersion { version })
+    }
+
+    async fn fetch(&self, version: &GenericVersion) -> Result<GoModHashes> {
+        let proxy = proxy()?;
+        let url = self.file_url(&proxy, &version.version, "zip")?;
+        let hash = nix::nix_prefetch_url(&url).await?;
+        let go_mod_url = self.file_url(&proxy, &version.version, "mod")?;
+        let go_mod_hash = nix::nix_prefetch_url(&go_mod_url).await?;
+        Ok(GoModHashes {
+            url,
+            hash,
+            go_mod_hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proxy_urls() {
+        assert_eq!(
+            proxy_from(None).unwrap().as_str(),
+            "https://proxy.golang.org/"
+        );
+        assert_eq!(
+            proxy_from(Some("direct,https://goproxy.example.org|off"))
+                .unwrap()
+                .as_str(),
+            "https://goproxy.example.org/"
+        );
+        assert_eq!(
+            proxy_from(Some("off")).unwrap().as_str(),
+            "https://proxy.golang.org/"
+        );
+
+        assert_eq!(
+            escape("github.com/BurntSushi/toml"),
+            "github.com/!burnt!sushi/toml"
+        );
+        let pin = GoModPin {
+            module: "github.com/BurntSushi/toml".into(),
+            version_upper_bound: None,
+        };
+        assert_eq!(
+            pin.file_url(&proxy_from(None).unwrap(), "v1.4.0", "zip")
+                .unwrap()
+                .as_str(),
+            "https://proxy.golang.org/github.com/!burnt!sushi/toml/@v/v1.4.0.zip"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Import gridlock's lock file
+//!
+//! [gridlock](https://github.com/lf-/gridlock) only tracks branches of GitHub repositories, which
+//! it fetches as tarballs just like npins does, so its entries keep their revisions and hashes.
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+
+#[derive(Debug, Clone, Deserialize)]
+struct LockFile {
+    version: u64,
+    packages: BTreeMap<String, Package>,
+}
+
+/// An entry of the lock file
+#[derive(Debug, Clone, Deserialize)]
+pub struct Package {
+    /// Always `github` so far
+    kind: String,
+    owner: String,
+    repo: String,
+    branch: String,
+    rev: String,
+    /// In Nix's base32 format, of the unpacked tarball
+    sha256: String,
+}
+
+impl TryFrom<Package> for Pin {
+    type Error = anyhow::Error;
+
+    fn try_from(package: Package) -> Result<Self> {
+        anyhow::ensure!(
+            package.kind == "github",
+            "Unsupported kind of package {}",
+            package.kind
+        );
+        import::locked_git_pin(
+            git::GitPin::new(
+                git::Repository::github(package.owner, package.repo),
+                package.branch,
+                false,
+            ),
+            package.rev,
+            import::sri_hash(&package.sha256)?,
+        )
+    }
+}
+
+/// gridlock's lock file
+pub struct Gridlock {
+    packages: BTreeMap<String, Package>,
+}
+
+impl Gridlock {
+    pub fn read(path: &Path) -> Result<Self> {
+        let lock: LockFile = import::read_json(path, "gridlock.json")?;
+        anyhow::ensure!(
+            lock.version == 0,
+            "Unsupported version {} of the gridlock lock file",
+            lock.version
+        );
+        Ok(Gridlock {
+            packages: lock.packages,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl import::Importer for Gridlock {
+    fn source(&self) -> &str {
+        "gridlock.json"
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.packages.keys().cloned().collect()
+    }
+
+    async fn import(&self, name: &str) -> Result<Option<Pin>> {
+        let pin = self.packages[name]
+            .clone()
+            .try_into()
+            .context("Could not convert the package")?;
+        Ok(Some(pin))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_import() {
+        let lock: LockFile = serde_json::from_value(serde_json::json!({
+            "version": 0,
+            "packages": {
+                "nixpkgs": {
+                    "kind": "github",
+                    "last_updated": 1700000000,
+                    "owner": "nixos",
+                    "repo": "nixpkgs",
+                    "branch": "nixos-unstable",
+                    "rev": "0123456789abcdef0123456789abcdef01234567",
+                    "sha256": "0".repeat(52),
+                },
+            },
+        }))
+        .unwrap();
+        let pin: Pin = lock.packages["nixpkgs"].clone().try_into().unwrap();
+        let spec = serde_json::to_value(&pin).unwrap();
+        assert_eq!(spec["branch"], "nixos-unstable");
+        assert_eq!(spec["revision"], "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(
+            spec["hash"],
+            "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+        );
+    }
+}
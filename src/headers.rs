@@ -0,0 +1,177 @@
+//! Extra HTTP headers for specific endpoints
+//!
+//! Some endpoints need custom headers, e.g. an API key in `X-JFrog-Art-Api` or a specific `Accept`
+//! header. These are configured outside of the lock file, in a file pointed to by `NPINS_HEADERS_FILE`.
+//! Each line has the form `<host>[/<path prefix>] <Header-Name>: <value>`; empty lines and lines
+//! starting with `#` are ignored:
+//!
+//! ```text
+//! # Internal Artifactory
+//! artifactory.example.com X-JFrog-Art-Api: AKCp…
+//! artifactory.example.com/artifactory/api Accept: application/json
+//! ```
+//!
+//! The headers are sent with all requests to matching URLs, be it API calls or downloads. Downloads
+//! that need headers happen in-process, so they never end up in the lock file (see
+//! [`nix::nix_prefetch_tarball`](crate::nix::nix_prefetch_tarball)).
+
+use anyhow::{Context, Result};
+use std::sync::OnceLock;
+use url::Url;
+
+/// One line of the headers file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderRule {
+    /// Host name, with the port if it is not the default one
+    pub host: String,
+    /// Only match URLs whose path starts with this, e.g. `/artifactory/api`
+    pub path_prefix: String,
+    pub name: String,
+    pub value: String,
+}
+
+impl HeaderRule {
+    fn matches(&self, url: &Url) -> bool {
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => return false,
+        };
+        host.eq_ignore_ascii_case(&self.host)
+            && url
+                .path()
+                .strip_prefix(&self.path_prefix)
+                .is_some_and(|rest| {
+                    rest.is_empty() || rest.starts_with('/') || self.path_prefix.ends_with('/')
+                })
+    }
+}
+
+/// Parse the contents of a headers file
+pub fn parse(contents: &str) -> Result<Vec<HeaderRule>> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let (target, header) = line
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("Line {number}: expected `<host> <Header>: <value>`"))?;
+            let (name, value) = header
+                .split_once(':')
+                .with_context(|| format!("Line {number}: expected `<Header>: <value>`"))?;
+            let (host, path_prefix) = match target.find('/') {
+                Some(index) => target.split_at(index),
+                None => (target, ""),
+            };
+            let (name, value) = (name.trim(), value.trim());
+            anyhow::ensure!(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_ok(),
+                "Line {number}: invalid header name '{name}'"
+            );
+            Ok(HeaderRule {
+                host: host.to_owned(),
+                path_prefix: path_prefix.to_owned(),
+                name: name.to_owned(),
+                value: value.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// The rules from `NPINS_HEADERS_FILE`, loaded once
+fn rules() -> &'static [HeaderRule] {
+    static RULES: OnceLock<Vec<HeaderRule>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        let Some(path) = std::env::var_os("NPINS_HEADERS_FILE") else {
+            return Vec::new();
+        };
+        let rules = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| parse(&contents));
+        match rules {
+            Ok(rules) => rules,
+            Err(err) => {
+                log::error!(
+                    "Ignoring NPINS_HEADERS_FILE {}: {:#}",
+                    path.to_string_lossy(),
+                    err
+                );
+                Vec::new()
+            },
+        }
+    })
+}
+
+/// The extra headers configured for an URL
+pub fn for_url(url: &Url) -> Vec<(String, String)> {
+    matching(rules(), url)
+}
+
+fn matching(rules: &[HeaderRule], url: &Url) -> Vec<(String, String)> {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(url))
+        .map(|rule| (rule.name.clone(), rule.value.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let rules = parse(
+            "# comment\n\
+             \n\
+             artifactory.example.com X-JFrog-Art-Api: secret:with:colons\n\
+             localhost:8080/api/v1   Accept:application/json\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                HeaderRule {
+                    host: "artifactory.example.com".into(),
+                    path_prefix: "".into(),
+                    name: "X-JFrog-Art-Api".into(),
+                    value: "secret:with:colons".into(),
+                },
+                HeaderRule {
+                    host: "localhost:8080".into(),
+                    path_prefix: "/api/v1".into(),
+                    name: "Accept".into(),
+                    value: "application/json".into(),
+                },
+            ]
+        );
+
+        assert!(parse("example.com").is_err());
+        assert!(parse("example.com no-colon").is_err());
+        assert!(parse("example.com Bad Header: x").is_err());
+    }
+
+    #[test]
+    fn test_matching() {
+        let rules = parse(
+            "artifactory.example.com X-JFrog-Art-Api: secret\n\
+             localhost:8080/api/v1 Accept: application/json\n",
+        )
+        .unwrap();
+        let headers = |url: &str| matching(&rules, &url.parse().unwrap());
+
+        assert_eq!(
+            headers("https://Artifactory.example.com/artifactory/x.tar.gz"),
+            vec![("X-JFrog-Art-Api".to_owned(), "secret".to_owned())]
+        );
+        assert_eq!(
+            headers("http://localhost:8080/api/v1/packages"),
+            vec![("Accept".to_owned(), "application/json".to_owned())]
+        );
+        assert!(headers("http://localhost:8080/api/v10").is_empty());
+        assert!(headers("http://localhost/api/v1").is_empty());
+        assert!(headers("https://example.com/").is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+//! Keep previous states of the lock file, so that a botched change can be rolled back
+//!
+//! Whenever the lock file changes, its previous contents are saved to the history directory as
+//! `<n>.json`, with `n` counting up. Only the last [`KEEP`] states are kept. The history is local
+//! to the checkout, it comes with a `.gitignore` so that it doesn't get committed by accident.
+
+use crate::*;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// How many previous states to keep
+pub const KEEP: usize = 10;
+
+/// A previous state of the lock file
+pub struct Entry {
+    /// How many changes ago this was, starting at 1 for the state before the last change
+    pub age: usize,
+    /// When it was replaced, in UTC
+    pub timestamp: String,
+    pub pins: NixPins,
+}
+
+pub struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    pub fn new(dir: PathBuf) -> Self {
+        History { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The saved states, from newest to oldest
+    pub(crate) fn files(&self) -> Result<Vec<(u64, PathBuf)>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", self.dir.display()))
+            },
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let number: Option<u64> = path
+                .file_name()
+                .and_then(|name| name.to_str()?.strip_suffix(".json")?.parse().ok());
+            if let Some(number) = number {
+                files.push((number, path));
+            }
+        }
+        files.sort_by_key(|(number, _)| std::cmp::Reverse(*number));
+        Ok(files)
+    }
+
+    /// Save the previous contents of the lock file, dropping the oldest states beyond [`KEEP`]
+    pub fn record(&self, contents: &[u8]) -> Result<()> {
+        if !self.dir.exists() {
+            std::fs::create_dir_all(&self.dir)
+                .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+            std::fs::write(self.dir.join(".gitignore"), "*\n")?;
+        }
+        let files = self.files()?;
+        let next = files.first().map(|(number, _)| number + 1).unwrap_or(1);
+        let path = self.dir.join(format!("{next}.json"));
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        for (_, path) in files.iter().skip(KEEP - 1) {
+            std::fs::remove_file(path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read(age: usize, path: &Path) -> Result<Entry> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let pins = NixPins::from_json_versioned(serde_json::from_str(&contents)?)
+            .with_context(|| format!("{} is not a valid lock file", path.display()))?;
+        let timestamp = provenance::timestamp(std::fs::metadata(path)?.modified()?);
+        Ok(Entry {
+            age,
+            timestamp,
+            pins,
+        })
+    }
+
+    /// The state `age` changes ago
+    pub fn get(&self, age: usize) -> Result<Entry> {
+        let files = self.files()?;
+        anyhow::ensure!(!files.is_empty(), "There is no history yet");
+        let (_, path) = age
+            .checked_sub(1)
+            .and_then(|index| files.get(index))
+            .with_context(|| {
+                format!(
+                    "The history only goes back {} change(s), not {}",
+                    files.len(),
+                    age
+                )
+            })?;
+        Self::read(age, path)
+    }
+
+    /// All saved states, from newest to oldest
+    pub fn list(&self) -> Result<Vec<Entry>> {
+        self.files()?
+            .iter()
+            .enumerate()
+            .map(|(index, (_, path))| Self::read(index + 1, path))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let dir = std::env::temp_dir().join(format!("npins-history-test-{}", std::process::id()));
+        let history = History::new(dir.clone());
+        assert!(history.get(1).is_err());
+
+        for i in 0..KEEP + 2 {
+            let mut pins = NixPins::default();
+            pins.pins
+                .insert(format!("pin-{i}"), test_pin("https://example.org/a.tar.gz"));
+            let contents = serde_json::to_vec(&pins.to_value_versioned()).unwrap();
+            history.record(&contents).unwrap();
+        }
+
+        let list = history.list().unwrap();
+        assert_eq!(list.len(), KEEP);
+        assert!(list[0].pins.pins.contains_key(&format!("pin-{}", KEEP + 1)));
+        assert!(history.get(KEEP).unwrap().pins.pins.contains_key("pin-2"));
+        assert!(history.get(KEEP + 1).is_err());
+        assert!(history.get(0).is_err());
+        assert!(dir.join(".gitignore").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+//! Pin the input of a Hydra evaluation
+//!
+//! Channels only tell which revision they point to, not which evaluation they come from. Following a
+//! job like `nixos/release-24.05/tested` directly gives the same revisions as the channel, but bumps
+//! can be traced back to the evaluation (and thus the builds) that let them through.
+//!
+//! Reference: <https://github.com/NixOS/hydra/blob/master/hydra-api.yaml>
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct HydraPin {
+    /// The Hydra instance, usually <https://hydra.nixos.org>
+    pub server: Url,
+    pub project: String,
+    pub jobset: String,
+    /// The job which has to succeed, e.g. the `tested` aggregate of NixOS
+    pub job: String,
+    /// The name of the jobset input to pin, e.g. `nixpkgs`
+    pub input: String,
+}
+
+impl diff::Diff for HydraPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![
+            ("server".into(), self.server.to_string()),
+            ("project".into(), self.project.clone()),
+            ("jobset".into(), self.jobset.clone()),
+            ("job".into(), self.job.clone()),
+            ("input".into(), self.input.clone()),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HydraEvaluation {
+    /// The ID of the evaluation, which increases with every evaluation
+    pub evaluation: u64,
+    /// The revision of the input that was evaluated
+    pub revision: String,
+    /// Tarball of the input at that revision
+    pub url: Url,
+}
+
+impl diff::Diff for HydraEvaluation {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![
+            ("evaluation".into(), self.evaluation.to_string()),
+            ("revision".into(), self.revision.clone()),
+            ("url".into(), self.url.to_string()),
+        ]
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Build {
+    id: u64,
+    /// All evaluations that contain the build. Builds are shared when nothing changed.
+    jobsetevals: Vec<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Evaluation {
+    jobsetevalinputs: std::collections::BTreeMap<String, EvaluationInput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EvaluationInput {
+    uri: Option<String>,
+    revision: Option<String>,
+}
+
+/// Guess the tarball URL for a revision of a git input
+fn archive_url(uri: &str, revision: &str) -> Result<Url> {
+    /* Git inputs may be specified as `<url> <branch>` */
+    let url: Url = uri
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .parse()
+        .with_context(|| format!("The input has an invalid URL '{uri}'"))?;
+    let segments = url
+        .path_segments()
+        .map(|segments| segments.collect::<Vec<_>>())
+        .unwrap_or_default();
+    match (url.host_str(), &segments[..]) {
+        (Some("github.com"), [owner, repo]) => {
+            git::Repository::github(*owner, repo.strip_suffix(".git").unwrap_or(repo))
+                .forge()
+                .expect("GitHub is a forge")
+                .archive_url(revision)
+        },
+        _ => anyhow::bail!("Only inputs from GitHub repositories are supported, not '{uri}'"),
+    }
+}
+
+impl HydraPin {
+    /// GET some JSON from the Hydra instance, which otherwise serves HTML
+    async fn get<T>(&self, path: &[&str]) -> Result<T>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        let url = forge::url_with_segments(&self.server, path)?;
+        provenance::record_endpoint(&url);
+        Ok(http_get(url)?
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send_throttled()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for HydraPin {
+    type Version = HydraEvaluation;
+    type Hashes = GenericHash;
+
+    async fn update(&self, old: Option<&HydraEvaluation>) -> Result<HydraEvaluation> {
+        /* The latest successful build of the job */
+        let build: Build = self
+            .get(&["job", &self.project, &self.jobset, &self.job, "latest"])
+            .await
+            .with_context(|| {
+                format!(
+                    "Couldn't find a successful build of {}/{}/{}",
+                    self.project, self.jobset, self.job
+                )
+            })?;
+        let evaluation = build
+            .jobsetevals
+            .iter()
+            .copied()
+            .max()
+            .with_context(|| format!("Build {} belongs to no evaluation", build.id))?;
+        if let Some(old) = old {
+            anyhow::ensure!(
+                evaluation >= old.evaluation,
+                "Failed to ensure monotonicity, latest evaluation is {} but current is {}",
+                evaluation,
+                old.evaluation,
+            );
+        }
+
+        let eval: Evaluation = self
+            .get(&["eval", &evaluation.to_string()])
+            .await
+            .with_context(|| format!("Couldn't fetch evaluation {evaluation}"))?;
+        let input = eval.jobsetevalinputs.get(&self.input).with_context(|| {
+            format!(
+                "Evaluation {evaluation} has no input '{}', it has: {}",
+                self.input,
+                eval.jobsetevalinputs
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+        let (Some(uri), Some(revision)) = (&input.uri, &input.revision) else {
+            anyhow::bail!("The input '{}' is not a git repository", self.input);
+        };
+
+        Ok(HydraEvaluation {
+            evaluation,
+            revision: revision.clone(),
+            url: archive_url(uri, revision)?,
+        })
+    }
+
+    async fn fetch(&self, version: &HydraEvaluation) -> Result<GenericHash> {
+        let hash = nix::nix_prefetch_tarball(&version.url).await?;
+        Ok(GenericHash {
+            hash,
+            archive: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_archive_url() {
+        assert_eq!(
+            archive_url("https://github.com/NixOS/nixpkgs.git", "abc123")
+                .unwrap()
+                .as_str(),
+            "https://github.com/NixOS/nixpkgs/archive/abc123.tar.gz"
+        );
+        assert_eq!(
+            archive_url("https://github.com/NixOS/nixpkgs release-24.05", "abc123")
+                .unwrap()
+                .as_str(),
+            "https://github.com/NixOS/nixpkgs/archive/abc123.tar.gz"
+        );
+        assert!(archive_url("https://git.example.org/nixpkgs.git", "abc123").is_err());
+    }
+}
@@ -0,0 +1,104 @@
+//! Import pins from the lock files of other tools
+//!
+//! Every supported format (Niv, flakes, nvfetcher, gridlock) implements [`Importer`], which only
+//! converts single entries. Everything around it is shared by all formats: picking the entries,
+//! refusing collisions with existing pins, and fetching whatever the other tool didn't lock.
+
+use crate::*;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// A lock file of another tool
+#[async_trait::async_trait]
+pub trait Importer: Send + Sync {
+    /// The file that is imported from, e.g. `flake.lock`, for messages
+    fn source(&self) -> &str;
+
+    /// The names of all entries, in the order they should be imported
+    fn names(&self) -> Vec<String>;
+
+    /// Convert the entry `name`, keeping its version and hashes where possible
+    ///
+    /// Returns `None` for entries that are skipped on purpose.
+    async fn import(&self, name: &str) -> Result<Option<Pin>>;
+}
+
+/// Read a JSON file, with a helpful message if it doesn't exist
+pub fn read_json<T: serde::de::DeserializeOwned>(path: &Path, what: &str) -> Result<T> {
+    let file = std::fs::File::open(path).with_context(|| {
+        format!(
+            "Could not open {what} at '{}'",
+            path.canonicalize()
+                .unwrap_or_else(|_| path.to_owned())
+                .display()
+        )
+    })?;
+    serde_json::from_reader(file).with_context(|| format!("{what} is not in the expected format"))
+}
+
+/// A SHA-256 hash in either SRI or Nix's base32 format, as SRI
+pub fn sri_hash(hash: &str) -> Result<String> {
+    if hash.starts_with("sha256-") {
+        Ok(hash.to_owned())
+    } else {
+        nix::hash_to_sri(hash, "sha256").with_context(|| format!("Invalid hash {hash}"))
+    }
+}
+
+/// A git pin at `revision`, with the hash of its contents
+///
+/// Forges serve the revision as tarball, which has the same hash as the checkout, so the pin gets
+/// the tarball URL like when updating it.
+pub fn locked_git_pin(input: git::GitPin, revision: String, hash: String) -> Result<Pin> {
+    let url = match input.repository.forge() {
+        Some(forge) if !input.submodules => Some(forge.archive_url(&revision)?),
+        _ => None,
+    };
+    Ok(Pin::Git {
+        version: Some(git::GitRevision::new(revision)?),
+        hashes: Some(git::OptionalUrlHashes {
+            url,
+            hash,
+            requires_auth: false,
+        }),
+        input,
+        metadata: Default::default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_locked_git_pin() {
+        let zero_hash = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+        assert_eq!(sri_hash(&"0".repeat(52)).unwrap(), zero_hash);
+        assert_eq!(sri_hash(zero_hash).unwrap(), zero_hash);
+
+        let revision = "0123456789abcdef0123456789abcdef01234567";
+        let input = git::GitPin::new(
+            git::Repository::github("owner", "repo"),
+            "main".into(),
+            false,
+        );
+        let spec =
+            serde_json::to_value(locked_git_pin(input, revision.into(), zero_hash.into()).unwrap())
+                .unwrap();
+        assert_eq!(
+            spec["url"],
+            format!("https://github.com/owner/repo/archive/{revision}.tar.gz")
+        );
+
+        let input = git::GitPin::new(
+            git::Repository::github("owner", "repo"),
+            "main".into(),
+            true,
+        );
+        let spec =
+            serde_json::to_value(locked_git_pin(input, revision.into(), zero_hash.into()).unwrap())
+                .unwrap();
+        assert_eq!(spec["url"], serde_json::Value::Null);
+        assert_eq!(spec["hash"], zero_hash);
+    }
+}
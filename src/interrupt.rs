@@ -0,0 +1,145 @@
+//! Ctrl-C and SIGTERM, or closing the console on Windows
+//!
+//! Interrupting npins normally aborts whatever it is doing: the command is dropped, which kills
+//! the processes it started (they are all spawned with `kill_on_drop`), and the temporary files
+//! are removed. Some commands can do better, see [`graceful`]: `npins update` for example stops
+//! updating pins and writes the ones that are done. Interrupting it a second time aborts it as
+//! well.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How many times npins was interrupted so far
+fn signals() -> &'static watch::Sender<usize> {
+    static SIGNALS: OnceLock<watch::Sender<usize>> = OnceLock::new();
+    SIGNALS.get_or_init(|| watch::channel(0).0)
+}
+
+/// How many [`Graceful`]s there are right now
+static GRACEFUL: AtomicUsize = AtomicUsize::new(0);
+
+/// How many signals it takes to abort: two if the first one is handled gracefully
+fn abort_after() -> usize {
+    match GRACEFUL.load(Ordering::SeqCst) {
+        0 => 1,
+        _ => 2,
+    }
+}
+
+/// The error of a command that stopped early because it was interrupted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+            },
+            Err(_) => tokio::signal::ctrl_c().await.unwrap_or_default(),
+        }
+    }
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_break, ctrl_close, ctrl_shutdown};
+        match (ctrl_break(), ctrl_close(), ctrl_shutdown()) {
+            (Ok(mut ctrl_break), Ok(mut close), Ok(mut shutdown)) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = ctrl_break.recv() => {},
+                    _ = close.recv() => {},
+                    _ = shutdown.recv() => {},
+                }
+            },
+            _ => tokio::signal::ctrl_c().await.unwrap_or_default(),
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    tokio::signal::ctrl_c().await.unwrap_or_default();
+}
+
+/// Start counting the signals, see [`aborted`]
+///
+/// A command that is blocked and can't be dropped in time, e.g. while it waits for input, is
+/// not waited for: npins exits shortly after being aborted in any case.
+pub fn listen() {
+    tokio::spawn(async {
+        loop {
+            signal().await;
+            signals().send_modify(|count| *count += 1);
+            if *signals().borrow() >= abort_after() {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                crate::tempdir::clean_up();
+                std::process::exit(130);
+            }
+        }
+    });
+}
+
+/// Completes once npins is interrupted for the first time
+pub async fn interrupted() {
+    signals()
+        .subscribe()
+        .wait_for(|&count| count >= 1)
+        .await
+        .ok();
+}
+
+/// Completes once the command is to be aborted, i.e. it didn't or can't stop gracefully
+pub async fn aborted() {
+    signals()
+        .subscribe()
+        .wait_for(|&count| count >= abort_after())
+        .await
+        .ok();
+}
+
+/// See [`graceful`]
+pub struct Graceful(());
+
+/// Leave the first interruption to the command instead of aborting it, until this is dropped
+///
+/// The command should stop soon after [`interrupted`] completes, and then fail with
+/// [`Interrupted`].
+pub fn graceful() -> Graceful {
+    GRACEFUL.fetch_add(1, Ordering::SeqCst);
+    Graceful(())
+}
+
+impl Drop for Graceful {
+    fn drop(&mut self) {
+        GRACEFUL.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_graceful() {
+        assert_eq!(abort_after(), 1);
+        let first = graceful();
+        let second = graceful();
+        assert_eq!(abort_after(), 2);
+        drop(first);
+        assert_eq!(abort_after(), 2);
+        drop(second);
+        assert_eq!(abort_after(), 1);
+    }
+}
@@ -0,0 +1,193 @@
+//! Pin content published over IPFS under an IPNS name
+//!
+//! The name (either a key like `k51q…` or a DNSLink domain) is resolved to the CID it currently
+//! points to by asking an HTTP gateway, which tells the CIDs along the path in its `X-Ipfs-Roots`
+//! header. The content itself is immutable once the CID is known, so the pin records a gateway URL
+//! for it that `fetchurl` can download.
+
+use crate::*;
+use anyhow::{Context, Result};
+use forge::url_with_segments;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct IpfsPin {
+    /// The IPNS name, without the `/ipns/` prefix
+    pub ipns: String,
+    /// A path within the content the name points to, for directories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// The HTTP gateway to resolve and download with, e.g. `https://ipfs.io`
+    pub gateway: Url,
+    /// Whether the content is an archive that should be unpacked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unpack: bool,
+}
+
+impl diff::Diff for IpfsPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("ipns".into(), self.ipns.clone())),
+            self.path.as_ref().map(|path| ("path".into(), path.clone())),
+            Some(("gateway".into(), self.gateway.to_string())),
+            self.unpack.then(|| ("unpack".into(), "true".into())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct IpfsVersion {
+    /// The CID the name pointed to
+    pub cid: String,
+}
+
+impl diff::Diff for IpfsVersion {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![("cid".into(), self.cid.clone())]
+    }
+}
+
+impl IpfsPin {
+    /// Accepts `/ipns/<name>/<path>` and `ipns://<name>/<path>` as well as plain names
+    pub fn new(name: &str, gateway: Url, unpack: bool) -> Result<Self> {
+        let name = name
+            .strip_prefix("/ipns/")
+            .or_else(|| name.strip_prefix("ipns://"))
+            .unwrap_or(name);
+        let (ipns, path) = match name.split_once('/') {
+            Some((ipns, path)) => (ipns, Some(path.trim_matches('/'))),
+            None => (name, None),
+        };
+        anyhow::ensure!(!ipns.is_empty(), "The IPNS name must not be empty");
+        Ok(IpfsPin {
+            ipns: ipns.to_owned(),
+            path: path.filter(|path| !path.is_empty()).map(ToOwned::to_owned),
+            gateway,
+            unpack,
+        })
+    }
+
+    /// The last path component, or the name itself for DNSLink domains
+    pub fn guess_name(&self) -> Option<String> {
+        match &self.path {
+            Some(path) => path.rsplit('/').next().map(ToOwned::to_owned),
+            None => self.ipns.contains('.').then(|| self.ipns.clone()),
+        }
+    }
+
+    fn path_segments(&self) -> impl Iterator<Item = &str> {
+        self.path
+            .iter()
+            .flat_map(|path| path.split('/'))
+            .filter(|segment| !segment.is_empty())
+    }
+
+    /// `<gateway>/ipfs/<cid>/<path>`
+    pub fn url(&self, cid: &str) -> Result<Url> {
+        url_with_segments(
+            &self.gateway,
+            ["ipfs", cid].into_iter().chain(self.path_segments()),
+        )
+    }
+
+    async fn resolve(&self) -> Result<String> {
+        let url = url_with_segments(&self.gateway, ["ipns", &self.ipns])?;
+        crate::provenance::record_endpoint(&url);
+        let response = http_request(reqwest::Method::HEAD, url.clone())?
+            .send_throttled()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("Failed to resolve /ipns/{} via {}", self.ipns, url))?;
+        let roots = response
+            .headers()
+            .get("x-ipfs-roots")
+            .and_then(|roots| roots.to_str().ok())
+            .with_context(|| {
+                format!(
+                    "{} did not tell which CID /ipns/{} resolves to, is it an IPFS gateway?",
+                    self.gateway, self.ipns
+                )
+            })?;
+        root_cid(roots)
+    }
+}
+
+/// The CID of the root, i.e. of what the name points to, from an `X-Ipfs-Roots` header
+fn root_cid(roots: &str) -> Result<String> {
+    let cid = roots.split(',').next().unwrap_or_default().trim();
+    anyhow::ensure!(
+        !cid.is_empty() && cid.chars().all(|c| c.is_ascii_alphanumeric()),
+        "'{}' is not a valid CID",
+        cid
+    );
+    Ok(cid.to_owned())
+}
+
+#[async_trait::async_trait]
+impl Updatable for IpfsPin {
+    type Version = IpfsVersion;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(&self, _old: Option<&IpfsVersion>) -> Result<IpfsVersion> {
+        /* CIDs have no order, so there's no monotonicity to check */
+        Ok(IpfsVersion {
+            cid: self.resolve().await?,
+        })
+    }
+
+    async fn fetch(&self, version: &IpfsVersion) -> Result<GenericUrlHashes> {
+        let url = self.url(&version.cid)?;
+        let (hash, archive) = if self.unpack {
+            (
+                nix::nix_prefetch_tarball(&url).await?,
+                nix::archive_format(&url, &[]).await?,
+            )
+        } else {
+            (nix::nix_prefetch_url(&url).await?, None)
+        };
+        Ok(GenericUrlHashes { url, hash, archive })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pin() {
+        let gateway: Url = "https://ipfs.io".parse().unwrap();
+        let pin = IpfsPin::new(
+            "/ipns/k51qzi5uqu5dlvj2baxnqndepeb86cbk3ng7n3i46uzyxzyqj2xjonzllnv0v8/releases/tool.tar.gz",
+            gateway.clone(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            pin.ipns,
+            "k51qzi5uqu5dlvj2baxnqndepeb86cbk3ng7n3i46uzyxzyqj2xjonzllnv0v8"
+        );
+        assert_eq!(pin.guess_name().as_deref(), Some("tool.tar.gz"));
+        assert_eq!(
+            pin.url("bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi")
+                .unwrap()
+                .as_str(),
+            "https://ipfs.io/ipfs/bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi/releases/tool.tar.gz"
+        );
+
+        let pin = IpfsPin::new("ipns://docs.ipfs.tech", gateway, false).unwrap();
+        assert_eq!(pin.ipns, "docs.ipfs.tech");
+        assert_eq!(pin.path, None);
+        assert_eq!(pin.guess_name().as_deref(), Some("docs.ipfs.tech"));
+
+        assert_eq!(
+            root_cid("bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi,bafkreib4ac")
+                .unwrap(),
+            "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"
+        );
+        assert!(root_cid("").is_err());
+    }
+}
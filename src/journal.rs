@@ -0,0 +1,247 @@
+//! Keep the progress of long updates, so that they survive a crash or Ctrl-C
+//!
+//! `npins update` only writes the lock file once all pins are done. Each finished pin is thus also
+//! appended to a journal next to it (`sources.json.journal`), one JSON object per line. The next
+//! update picks up the pins from the journal instead of updating them again, and removes it once
+//! the lock file is written.
+//!
+//! The journal starts with a checksum of the lock file it belongs to. If the lock file changed in
+//! the meantime, e.g. because the update got interrupted and the user edited the pins, the journal
+//! is stale and gets discarded. The algorithm of the checksum is set in `npins.toml`:
+//!
+//! ```toml
+//! [journal]
+//! checksum = "blake3"
+//! ```
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    /// The checksum of the lock file that was being updated
+    lock_file: String,
+    #[serde(default, skip_serializing_if = "checksum::Algorithm::is_default")]
+    checksum: checksum::Algorithm,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    name: String,
+    pin: Pin,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<std::fs::File>,
+}
+
+fn read_lock_file(lock_file: &Path) -> Result<Vec<u8>> {
+    std::fs::read(lock_file).with_context(|| format!("Failed to read {}", lock_file.display()))
+}
+
+/// The pins recorded in a journal, if it belongs to the lock file with these contents
+///
+/// A truncated last line is ignored, since that's what a crash while writing it leaves behind.
+fn read(journal: impl BufRead, lock_file: &[u8]) -> Result<Option<BTreeMap<String, Pin>>> {
+    let mut lines = journal.lines();
+    let Some(header) = lines.next().transpose()? else {
+        return Ok(None);
+    };
+    let header: Header = serde_json::from_str(&header).context("Invalid journal header")?;
+    if header.lock_file != header.checksum.hex(lock_file) {
+        return Ok(None);
+    }
+    let mut pins = BTreeMap::new();
+    let lines = lines.collect::<std::io::Result<Vec<_>>>()?;
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => {
+                pins.insert(entry.name, entry.pin);
+            },
+            Err(_) if i + 1 == lines.len() => break,
+            Err(err) => return Err(err).context("Invalid journal entry"),
+        }
+    }
+    Ok(Some(pins))
+}
+
+impl Journal {
+    /// `<lock file>.journal`
+    pub fn path_for(lock_file: &Path) -> PathBuf {
+        let mut path = lock_file.as_os_str().to_owned();
+        path.push(".journal");
+        path.into()
+    }
+
+    /// Open the journal of a lock file, along with the pins that are already done
+    ///
+    /// A stale journal is replaced by a fresh one.
+    pub fn open(lock_file: &Path) -> Result<(Self, BTreeMap<String, Pin>)> {
+        Self::open_with(lock_file, config::get().journal.checksum)
+    }
+
+    /// [`open`](Self::open) with the checksum algorithm for the new journal
+    fn open_with(
+        lock_file: &Path,
+        checksum: checksum::Algorithm,
+    ) -> Result<(Self, BTreeMap<String, Pin>)> {
+        let path = Self::path_for(lock_file);
+        let contents = read_lock_file(lock_file)?;
+
+        let done = match std::fs::File::open(&path) {
+            Ok(file) => match read(std::io::BufReader::new(file), &contents) {
+                Ok(Some(done)) => Some(done),
+                Ok(None) => {
+                    log::warn!(
+                        "Discarding {}, as {} changed since it was written",
+                        path.display(),
+                        lock_file.display()
+                    );
+                    None
+                },
+                Err(err) => {
+                    log::warn!("Discarding {}: {err:#}", path.display());
+                    None
+                },
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to open {}", path.display()))
+            },
+        };
+
+        /* Start over even when resuming, so that a truncated line doesn't end up in the middle,
+         * and with the configured algorithm */
+        let file = std::fs::File::create(&path)
+            .with_context(|| format!("Failed to open {} for writing", path.display()))?;
+        let journal = Journal {
+            path,
+            file: Mutex::new(file),
+        };
+        journal.append(&Header {
+            lock_file: checksum.hex(&contents),
+            checksum,
+        })?;
+        let done = done.unwrap_or_default();
+        for (name, pin) in &done {
+            journal.record(name, pin)?;
+        }
+        Ok((journal, done))
+    }
+
+    /// Whether the journal of a lock file is left over from an earlier version of it, or can't
+    /// be read at all. `None` if there is no journal
+    pub fn is_stale(lock_file: &Path) -> Result<Option<bool>> {
+        let path = Self::path_for(lock_file);
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to open {}", path.display()))
+            },
+        };
+        let current = read(std::io::BufReader::new(file), &read_lock_file(lock_file)?);
+        Ok(Some(!matches!(current, Ok(Some(_)))))
+    }
+
+    fn append(&self, value: &impl Serialize) -> Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)
+            .and_then(|()| file.sync_data())
+            .with_context(|| format!("Failed to write to {}", self.path.display()))
+    }
+
+    /// Record a pin that is done
+    pub fn record(&self, name: &str, pin: &Pin) -> Result<()> {
+        self.append(&Entry {
+            name: name.to_owned(),
+            pin: pin.clone(),
+        })
+    }
+
+    /// Remove the journal, once the lock file contains everything in it
+    pub fn finish(self) -> Result<()> {
+        drop(self.file);
+        std::fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_journal() {
+        let root = std::env::temp_dir().join(format!("npins-test-journal-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let lock_file = root.join("sources.json");
+        std::fs::write(&lock_file, "{}").unwrap();
+        let Pin::Tarball { input, .. } = test_pin("https://example.org/tool.tar.gz") else {
+            unreachable!()
+        };
+        let pin = Pin::Tarball {
+            input,
+            version: Some(tarball::LockedTarball {
+                locked_url: None,
+                resolved_url: None,
+                etag: Some("\"1\"".into()),
+                last_modified: None,
+            }),
+            hashes: Some(GenericHash {
+                hash: "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".into(),
+                archive: None,
+            }),
+            metadata: Default::default(),
+        };
+
+        let (journal, done) = Journal::open(&lock_file).unwrap();
+        assert!(done.is_empty());
+        journal.record("tool", &pin).unwrap();
+        drop(journal);
+
+        /* Resume, with a line that was only written halfway */
+        let path = Journal::path_for(&lock_file);
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(br#"{"name":"other","pin":{"ty"#).unwrap();
+        drop(file);
+        let (journal, done) = Journal::open(&lock_file).unwrap();
+        assert_eq!(done.keys().collect::<Vec<_>>(), vec!["tool"]);
+        assert_eq!(done["tool"], pin);
+        drop(journal);
+
+        /* Switching the algorithm keeps the pins, and the journal is rewritten with it */
+        let (journal, done) = Journal::open_with(&lock_file, checksum::Algorithm::Blake3).unwrap();
+        assert_eq!(done["tool"], pin);
+        drop(journal);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with(&format!(
+            r#"{{"lock_file":"{}","checksum":"blake3"}}"#,
+            checksum::Algorithm::Blake3.hex(b"{}")
+        )));
+        let (journal, done) = Journal::open(&lock_file).unwrap();
+        assert_eq!(done["tool"], pin);
+        drop(journal);
+
+        /* Stale once the lock file changed */
+        std::fs::write(&lock_file, "{\"pins\": {}}").unwrap();
+        let (journal, done) = Journal::open(&lock_file).unwrap();
+        assert!(done.is_empty());
+        journal.finish().unwrap();
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
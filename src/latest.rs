@@ -0,0 +1,640 @@
+//! Pick the latest of several versions, independently of the order they are listed in
+//!
+//! Different strings can be the same version: `1.0`, `1.0.0` and `v1.0` all compare equal, and so
+//! do versions that only differ in their build metadata (`1.0+a` and `1.0+b`). Which one of them is
+//! the latest must not depend on the order a remote happens to list them in, or two people
+//! updating against the same remote could end up with different lock files. Ties are thus broken
+//! by comparing the strings byte by byte, the greater one wins: `v1.0` over `1.0`, `1.0.0` over
+//! `1.0`.
+//!
+//! Release pins can also choose how their tags are compared (see [`VersionScheme`]): they can be
+//! rewritten before parsing, e.g. to turn `OpenSSL_1_1_1w` into `1.1.1w`, and compared as strict
+//! SemVer, calendar versions, plain numbers or strings instead of leniently.
+
+use anyhow::{Context, Result};
+use lenient_version::Version;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Whether a version passes the `only_versions` and `exclude_versions` of a pin
+///
+/// These are glob patterns with `*` and `?`, like `3.*` or `*.0`. A leading `v` of the version
+/// is optional, so `3.2.1` also matches `v3.2.1`.
+pub fn listed(version: &str, only_versions: &[String], exclude_versions: &[String]) -> bool {
+    let matches = |pattern: &String| {
+        crate::asset::glob_matches(pattern, version)
+            || version
+                .strip_prefix('v')
+                .is_some_and(|version| crate::asset::glob_matches(pattern, version))
+    };
+    (only_versions.is_empty() || only_versions.iter().any(matches))
+        && !exclude_versions.iter().any(matches)
+}
+
+/// Compare two versions along with the strings they were parsed from
+pub fn cmp<V: Ord>(a: (&V, &str), b: (&V, &str)) -> Ordering {
+    a.0.cmp(b.0).then_with(|| a.1.cmp(b.1))
+}
+
+/// How to compare versions
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Comparator {
+    /// Anything that looks like a version is one: `v1.2`, `1.2.3.4`, `1.2-rc1`. Leading zeros
+    /// are dropped, so `1.02` is the same as `1.2`, and build metadata is ignored.
+    #[default]
+    Lenient,
+    /// Only SemVer 2.0 versions (with an optional leading `v`), ordered by the `semver` crate.
+    /// Versions with leading zeros are not versions at all, and build metadata breaks ties.
+    Semver,
+    /// Calendar versions like `2024.05.1`, `24.04` or `r20240915`: a year, optionally after a
+    /// prefix of letters, followed by more numbers. Two-digit years are in this century, and
+    /// compact dates like `20240915` are the same as `2024.09.15`. A suffix of letters, like in
+    /// `2024.05-rc1`, makes a pre-release.
+    Calver,
+    /// Only the numbers in the tags count, compared one by one: `build-123`, `1_2_3`
+    Numeric,
+    /// Every tag is a version, compared as a string, e.g. for ISO dates like `2024-09-15`
+    Lexicographic,
+}
+
+impl std::fmt::Display for Comparator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Comparator::Lenient => "lenient",
+            Comparator::Semver => "semver",
+            Comparator::Calver => "calver",
+            Comparator::Numeric => "numeric",
+            Comparator::Lexicographic => "lexicographic",
+        })
+    }
+}
+
+impl std::str::FromStr for Comparator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lenient" => Ok(Comparator::Lenient),
+            "semver" => Ok(Comparator::Semver),
+            "calver" => Ok(Comparator::Calver),
+            "numeric" => Ok(Comparator::Numeric),
+            "lexicographic" => Ok(Comparator::Lexicographic),
+            _ => anyhow::bail!(
+                "expected `lenient`, `semver`, `calver`, `numeric` or `lexicographic`"
+            ),
+        }
+    }
+}
+
+/// A regular expression replacement applied to tags before parsing them, see [`regex::Regex::replace_all`]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Rewrite {
+    pub pattern: String,
+    /// May refer to groups of the pattern, like `$1`
+    pub replacement: String,
+}
+
+impl std::str::FromStr for Rewrite {
+    type Err = anyhow::Error;
+
+    /// `<pattern>=<replacement>`
+    fn from_str(s: &str) -> Result<Self> {
+        let (pattern, replacement) = s
+            .split_once('=')
+            .context("expected `<pattern>=<replacement>`")?;
+        regex::Regex::new(pattern)?;
+        Ok(Rewrite {
+            pattern: pattern.to_owned(),
+            replacement: replacement.to_owned(),
+        })
+    }
+}
+
+/// The compiled [`VersionScheme::normalize`] rewrites
+pub struct Rewrites<'a>(Vec<(regex::Regex, &'a str)>);
+
+impl Rewrites<'_> {
+    pub fn apply(&self, tag: &str) -> String {
+        let mut tag = tag.to_owned();
+        for (regex, replacement) in &self.0 {
+            tag = regex.replace_all(&tag, *replacement).into_owned();
+        }
+        tag
+    }
+}
+
+/// How the tags of a pin are turned into versions and compared
+///
+/// In the lock file, this is just the comparator, like `"calver"`, unless there are rewrites.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(from = "SchemeRepr", into = "SchemeRepr")]
+pub struct VersionScheme {
+    pub comparator: Comparator,
+    /// Applied in order before parsing, e.g. `^OpenSSL_=` and `_=.` for OpenSSL's tags
+    pub normalize: Vec<Rewrite>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "a comparator like \"semver\", or an object with a comparator and rewrites"
+)]
+enum SchemeRepr {
+    Comparator(Comparator),
+    Full {
+        #[serde(default, skip_serializing_if = "is_default")]
+        comparator: Comparator,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        normalize: Vec<Rewrite>,
+    },
+}
+
+impl From<SchemeRepr> for VersionScheme {
+    fn from(repr: SchemeRepr) -> Self {
+        match repr {
+            SchemeRepr::Comparator(comparator) => VersionScheme {
+                comparator,
+                normalize: Vec::new(),
+            },
+            SchemeRepr::Full {
+                comparator,
+                normalize,
+            } => VersionScheme {
+                comparator,
+                normalize,
+            },
+        }
+    }
+}
+
+impl From<VersionScheme> for SchemeRepr {
+    fn from(scheme: VersionScheme) -> Self {
+        match scheme.normalize.is_empty() {
+            true => SchemeRepr::Comparator(scheme.comparator),
+            false => SchemeRepr::Full {
+                comparator: scheme.comparator,
+                normalize: scheme.normalize,
+            },
+        }
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// A calendar version, see [`Comparator::Calver`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Calver<'a> {
+    /// Starting with the full year
+    pub components: Vec<u64>,
+    pub pre: Option<&'a str>,
+}
+
+impl<'a> Calver<'a> {
+    pub fn parse(tag: &'a str) -> Option<Self> {
+        let mut rest =
+            tag.trim_start_matches(|c: char| c.is_ascii_alphabetic() || c == '-' || c == '_');
+        let mut components = Vec::new();
+        loop {
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 {
+                return None;
+            }
+            let (number, tail) = rest.split_at(digits);
+            if components.is_empty() {
+                let year = |digits: &str| digits.parse::<u64>().ok();
+                match number.len() {
+                    2 => components.push(2000 + year(number)?),
+                    4 => components.push(year(number)?),
+                    /* Compact dates */
+                    6 | 8 => {
+                        components.push(year(&number[..4])?);
+                        for part in number.as_bytes()[4..].chunks(2) {
+                            components.push(year(std::str::from_utf8(part).ok()?)?);
+                        }
+                    },
+                    _ => return None,
+                }
+            } else {
+                components.push(number.parse().ok()?);
+            }
+            let separated = tail.strip_prefix(['.', '-', '_']);
+            rest = separated.unwrap_or(tail);
+            match rest.chars().next() {
+                None => {
+                    return Some(Calver {
+                        components,
+                        pre: None,
+                    })
+                },
+                Some(next) if next.is_ascii_alphabetic() => {
+                    return Some(Calver {
+                        components,
+                        pre: Some(rest),
+                    })
+                },
+                /* The next number */
+                Some(_) => {
+                    separated?;
+                },
+            }
+        }
+    }
+}
+
+impl PartialOrd for Calver<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Calver<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.components
+            .cmp(&other.components)
+            /* Pre-releases come before the release */
+            .then_with(|| match (self.pre, other.pre) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => a.cmp(b),
+            })
+    }
+}
+
+impl std::fmt::Display for Calver<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, component) in self.components.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{component}")?;
+        }
+        match self.pre {
+            Some(pre) => write!(f, "-{pre}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The numbers in a tag, see [`Comparator::Numeric`]
+fn numbers(tag: &str) -> Option<Vec<u64>> {
+    let numbers = tag
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|number| !number.is_empty())
+        .map(|number| number.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    (!numbers.is_empty()).then_some(numbers)
+}
+
+/// A version, parsed according to a [`VersionScheme`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parsed<'a> {
+    Lenient(Version<'a>),
+    Semver(semver::Version),
+    Calver(Calver<'a>),
+    Numeric(Vec<u64>),
+    Lexicographic(&'a str),
+}
+
+impl Parsed<'_> {
+    pub fn is_pre_release(&self) -> bool {
+        match self {
+            Parsed::Lenient(version) => version.is_pre_release(),
+            Parsed::Semver(version) => !version.pre.is_empty(),
+            Parsed::Calver(version) => version.pre.is_some(),
+            Parsed::Numeric(_) | Parsed::Lexicographic(_) => false,
+        }
+    }
+
+    /// The first three components, for matching against SemVer requirements
+    fn semver(&self) -> Option<semver::Version> {
+        let numbers = match self {
+            Parsed::Lenient(_) | Parsed::Semver(_) | Parsed::Lexicographic(_) => return None,
+            Parsed::Calver(version) => &version.components,
+            Parsed::Numeric(numbers) => numbers,
+        };
+        let component = |i: usize| numbers.get(i).copied().unwrap_or(0);
+        Some(semver::Version::new(
+            component(0),
+            component(1),
+            component(2),
+        ))
+    }
+
+    /// Only versions of the same kind are compared, see [`Ord`]
+    fn kind(&self) -> u8 {
+        match self {
+            Parsed::Lenient(_) => 0,
+            Parsed::Semver(_) => 1,
+            Parsed::Calver(_) => 2,
+            Parsed::Numeric(_) => 3,
+            Parsed::Lexicographic(_) => 4,
+        }
+    }
+}
+
+impl PartialOrd for Parsed<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Parsed<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Parsed::Lenient(a), Parsed::Lenient(b)) => a.cmp(b),
+            (Parsed::Semver(a), Parsed::Semver(b)) => a.cmp(b),
+            (Parsed::Calver(a), Parsed::Calver(b)) => a.cmp(b),
+            (Parsed::Numeric(a), Parsed::Numeric(b)) => a.cmp(b),
+            (Parsed::Lexicographic(a), Parsed::Lexicographic(b)) => a.cmp(b),
+            /* Never happens, all versions of a pin are parsed the same way */
+            (a, b) => a.kind().cmp(&b.kind()),
+        }
+    }
+}
+
+impl std::fmt::Display for Parsed<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Parsed::Lenient(version) => version.fmt(f),
+            Parsed::Semver(version) => version.fmt(f),
+            Parsed::Calver(version) => version.fmt(f),
+            Parsed::Numeric(numbers) => {
+                for (i, number) in numbers.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(".")?;
+                    }
+                    write!(f, "{number}")?;
+                }
+                Ok(())
+            },
+            Parsed::Lexicographic(version) => f.write_str(version),
+        }
+    }
+}
+
+impl VersionScheme {
+    pub fn is_default(&self) -> bool {
+        is_default(self)
+    }
+
+    /// Compile the rewrites, to apply them to many tags
+    pub fn rewrites(&self) -> Result<Rewrites<'_>> {
+        self.normalize
+            .iter()
+            .map(|rewrite| {
+                let regex = regex::Regex::new(&rewrite.pattern)
+                    .with_context(|| format!("Invalid pattern {}", rewrite.pattern))?;
+                Ok((regex, rewrite.replacement.as_str()))
+            })
+            .collect::<Result<_>>()
+            .map(Rewrites)
+    }
+
+    /// Rewrite a single tag as configured, see [`rewrites`](Self::rewrites) for many
+    pub fn normalize(&self, tag: &str) -> Result<String> {
+        Ok(self.rewrites()?.apply(tag))
+    }
+
+    /// Parse a normalized tag, or `None` if it isn't a version
+    pub fn parse<'a>(&self, normalized: &'a str) -> Option<Parsed<'a>> {
+        match self.comparator {
+            Comparator::Lenient => lenient_semver_parser::parse::<Version>(normalized)
+                .ok()
+                .map(Parsed::Lenient),
+            Comparator::Semver => {
+                semver::Version::parse(normalized.strip_prefix('v').unwrap_or(normalized))
+                    .ok()
+                    .map(Parsed::Semver)
+            },
+            Comparator::Calver => Calver::parse(normalized).map(Parsed::Calver),
+            Comparator::Numeric => numbers(normalized).map(Parsed::Numeric),
+            Comparator::Lexicographic => Some(Parsed::Lexicographic(normalized)),
+        }
+    }
+
+    /// Parse an upper bound for the tags
+    ///
+    /// The bounds are parsed like the tags (but not normalized), except for the lenient and
+    /// SemVer comparators, whose bounds are always lenient.
+    pub fn parse_bound<'a>(&self, bound: &'a str) -> Result<Parsed<'a>> {
+        match self.comparator {
+            Comparator::Lenient | Comparator::Semver => {
+                lenient_semver_parser::parse::<Version>(bound)
+                    .map(Parsed::Lenient)
+                    .map_err(|err| anyhow::format_err!("{}", err.owned()))
+            },
+            _ => self
+                .parse(bound)
+                .with_context(|| format!("{bound} is not a {} version", self.comparator)),
+        }
+    }
+
+    /// Whether a normalized tag is below an upper bound from [`parse_bound`](Self::parse_bound)
+    pub fn below(&self, normalized: &str, bound: &Parsed) -> bool {
+        match self.comparator {
+            Comparator::Lenient | Comparator::Semver => {
+                lenient_semver_parser::parse::<Version>(normalized)
+                    .is_ok_and(|version| Parsed::Lenient(version) < *bound)
+            },
+            _ => self
+                .parse(normalized)
+                .is_some_and(|version| version < *bound),
+        }
+    }
+
+    /// A normalized tag as SemVer version, for matching it against requirements
+    ///
+    /// Lenient and SemVer tags are parsed leniently, calendar versions and numbers are cut to
+    /// three components, and strings are never versions.
+    pub fn semver(&self, normalized: &str) -> Option<semver::Version> {
+        match self.comparator {
+            Comparator::Lenient | Comparator::Semver => {
+                lenient_semver_parser::parse::<semver::Version>(normalized).ok()
+            },
+            _ => self.parse(normalized)?.semver(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The latest of the given versions, skipping those that aren't versions
+    fn latest<'a>(versions: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+        versions
+            .into_iter()
+            .filter_map(|version| {
+                Some((
+                    lenient_semver_parser::parse::<Version>(version).ok()?,
+                    version,
+                ))
+            })
+            .max_by(|(a, a_str), (b, b_str)| cmp((a, a_str), (b, b_str)))
+            .map(|(_, version)| version)
+    }
+
+    /// The latest of the given tags according to a scheme
+    fn latest_by<'a>(scheme: &VersionScheme, tags: &[&'a str]) -> Option<&'a str> {
+        let rewrites = scheme.rewrites().unwrap();
+        let normalized = tags
+            .iter()
+            .map(|tag| rewrites.apply(tag))
+            .collect::<Vec<_>>();
+        tags.iter()
+            .zip(&normalized)
+            .filter_map(|(tag, normalized)| Some((scheme.parse(normalized)?, *tag)))
+            .max_by(|(a, a_tag), (b, b_tag)| cmp((a, a_tag), (b, b_tag)))
+            .map(|(_, tag)| tag)
+    }
+
+    #[test]
+    fn test_version_scheme() {
+        let openssl = VersionScheme {
+            comparator: Comparator::Lenient,
+            normalize: vec!["^OpenSSL_=".parse().unwrap(), "_=.".parse().unwrap()],
+        };
+        assert_eq!(openssl.normalize("OpenSSL_1_1_1w").unwrap(), "1.1.1w");
+        let invalid = VersionScheme {
+            comparator: Comparator::Lenient,
+            normalize: vec![Rewrite {
+                pattern: "(".into(),
+                replacement: String::new(),
+            }],
+        };
+        assert!(invalid.rewrites().is_err());
+        assert_eq!(
+            latest_by(
+                &openssl,
+                &["OpenSSL_1_0_2u", "OpenSSL_1_1_1", "OpenSSL_1_1_0l"]
+            ),
+            Some("OpenSSL_1_1_1")
+        );
+
+        let semver = VersionScheme {
+            comparator: Comparator::Semver,
+            normalize: Vec::new(),
+        };
+        /* Leading zeros, and fewer than three components, are not SemVer */
+        assert_eq!(
+            latest_by(&semver, &["1.02.0", "1.2", "1.1.0"]),
+            Some("1.1.0")
+        );
+        /* Build metadata breaks ties */
+        assert_eq!(
+            latest_by(&semver, &["1.0.0+20240101", "1.0.0+20240301", "v1.0.0-rc1"]),
+            Some("1.0.0+20240301")
+        );
+        /* ffmpeg-style tags, normalized into SemVer */
+        let ffmpeg = VersionScheme {
+            comparator: Comparator::Semver,
+            normalize: vec![
+                "^n=".parse().unwrap(),
+                r"^(\d+)\.(\d+)$=$1.$2.0".parse().unwrap(),
+            ],
+        };
+        assert_eq!(
+            latest_by(&ffmpeg, &["n6.1", "n6.1.1", "n7.0", "n7.0-dev"]),
+            Some("n7.0")
+        );
+
+        let calver = VersionScheme {
+            comparator: Comparator::Calver,
+            normalize: Vec::new(),
+        };
+        assert_eq!(
+            latest_by(&calver, &["2024.05.1", "2024.10", "2024.9.30", "v1.2"]),
+            Some("2024.10")
+        );
+        /* Compact dates, two-digit years and pre-releases */
+        assert_eq!(
+            latest_by(&calver, &["r20240915", "2024.09.14.1", "24.09.15-rc1"]),
+            Some("r20240915")
+        );
+        assert_eq!(
+            calver.parse("2024.05-rc1").unwrap().to_string(),
+            "2024.5-rc1"
+        );
+        assert!(calver.parse("2024.05-rc1").unwrap().is_pre_release());
+        assert!(calver.parse("2024+1").is_none());
+        assert!(calver.parse("1.2.3").is_none());
+
+        let numeric = VersionScheme {
+            comparator: Comparator::Numeric,
+            normalize: Vec::new(),
+        };
+        assert_eq!(
+            latest_by(&numeric, &["build-99", "build-123", "nightly"]),
+            Some("build-123")
+        );
+        let lexicographic = VersionScheme {
+            comparator: Comparator::Lexicographic,
+            normalize: Vec::new(),
+        };
+        assert_eq!(
+            latest_by(&lexicographic, &["2024-09-15", "2024-10-01", "2024-09-30"]),
+            Some("2024-10-01")
+        );
+        assert!(lexicographic.below("2024-09-15", &lexicographic.parse_bound("2024-10").unwrap()));
+
+        let spec = serde_json::to_value(&ffmpeg).unwrap();
+        assert_eq!(spec["comparator"], "semver");
+        assert!(VersionScheme::default().is_default());
+
+        let calver: VersionScheme = serde_json::from_str(r#""calver""#).unwrap();
+        assert_eq!(calver.comparator, Comparator::Calver);
+        assert_eq!(serde_json::to_string(&calver).unwrap(), r#""calver""#);
+        let rewritten = VersionScheme {
+            comparator: Comparator::Lenient,
+            normalize: vec!["^OpenSSL_=".parse().unwrap()],
+        };
+        let json = serde_json::to_string(&rewritten).unwrap();
+        assert_eq!(
+            json,
+            r#"{"normalize":[{"pattern":"^OpenSSL_","replacement":""}]}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<VersionScheme>(&json).unwrap(),
+            rewritten
+        );
+        assert!(serde_json::from_str::<VersionScheme>(r#""newest""#).is_err());
+    }
+
+    #[test]
+    fn test_listed() {
+        let none = Vec::<String>::new();
+        let patterns = |patterns: &[&str]| {
+            patterns
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(listed("3.2.1", &none, &none));
+        assert!(!listed("v3.2.1", &none, &patterns(&["3.2.1"])));
+        assert!(!listed("3.0", &none, &patterns(&["*.0"])));
+        assert!(listed("3.1", &none, &patterns(&["*.0"])));
+        assert!(listed(
+            "v3.1",
+            &patterns(&["3.*", "4.*"]),
+            &patterns(&["3.2.*"])
+        ));
+        assert!(!listed("3.2.1", &patterns(&["3.*"]), &patterns(&["3.2.*"])));
+        assert!(!listed("5.0", &patterns(&["3.*", "4.*"]), &none));
+    }
+
+    #[test]
+    fn test_latest() {
+        assert_eq!(latest(["1.0", "2.0", "foo", "1.5"]), Some("2.0"));
+        assert_eq!(latest(["foo"]), None);
+        /* Equal versions, in either order */
+        assert_eq!(latest(["1.0", "v1.0"]), Some("v1.0"));
+        assert_eq!(latest(["v1.0", "1.0"]), Some("v1.0"));
+        assert_eq!(latest(["1.0.0", "1.0"]), Some("1.0.0"));
+        assert_eq!(latest(["1.0", "1.0.0"]), Some("1.0.0"));
+        assert_eq!(latest(["1.0+b", "1.0+a"]), Some("1.0+b"));
+    }
+}
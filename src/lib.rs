@@ -7,17 +7,70 @@ use anyhow::Result;
 use diff::{Diff, OptionExt};
 use reqwest::IntoUrl;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+pub use throttle::ThrottledSend;
 
+pub mod approval;
+pub mod artifact;
+pub mod asset;
+pub mod attestation;
+pub mod audit;
+pub mod batch;
+pub mod browser;
+pub mod bucket;
+pub mod changelog;
 pub mod channel;
+pub mod check;
+pub mod checksum;
+pub mod ci;
+pub mod config;
+pub mod container;
 pub mod diff;
+pub mod engine;
+pub mod env;
+pub mod explain;
+pub mod file;
 pub mod flake;
+pub mod forge;
 pub mod git;
+pub mod gomod;
+pub mod gridlock;
+pub mod headers;
+pub mod history;
+pub mod hydra;
+pub mod import;
+pub mod interrupt;
+pub mod ipfs;
+pub mod journal;
+pub mod latest;
+pub mod maintenance;
+pub mod manifest;
+pub mod maven;
+pub mod mirrors;
+pub mod nar;
 pub mod niv;
 pub mod nix;
+pub mod nvfetcher;
+pub mod output;
+pub mod policy;
+pub mod prompt;
+pub mod provenance;
 pub mod pypi;
+pub mod range;
+pub mod registry;
+pub mod remote;
+pub mod report;
+pub mod selection;
+pub mod signature;
+pub mod snippet;
+pub mod svn;
 pub mod tarball;
+pub mod tempdir;
+pub mod throttle;
+pub mod update_script;
 pub mod versions;
+pub mod vscode;
 
 /// Helper method to build you a client.
 // TODO make injectable via a configuration mechanism
@@ -31,15 +84,47 @@ pub fn build_client() -> Result<reqwest::Client, reqwest::Error> {
         .build()
 }
 
+static SHARED_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// Use the same client for all further requests, so that connections are kept open between them.
+/// Connections belong to the Tokio runtime that opened them, so only call this in processes with a
+/// single runtime, like the CLI
+pub fn share_client() -> Result<()> {
+    let _ = SHARED_CLIENT.set(build_client()?);
+    Ok(())
+}
+
+/// Start a request, with the extra headers configured for the URL (see [`headers`])
+pub(crate) fn http_request(
+    method: reqwest::Method,
+    url: impl IntoUrl,
+) -> Result<reqwest::RequestBuilder> {
+    let url = url.into_url()?;
+    let client = match SHARED_CLIENT.get() {
+        Some(client) => client.clone(),
+        None => build_client()?,
+    };
+    let mut request = client.request(method, url.clone());
+    for (name, value) in headers::for_url(&url) {
+        request = request.header(name, value);
+    }
+    Ok(request)
+}
+
+pub(crate) fn http_get(url: impl IntoUrl) -> Result<reqwest::RequestBuilder> {
+    http_request(reqwest::Method::GET, url)
+}
+
 /// Helper method for doing various API calls
 async fn get_and_deserialize<T, U>(url: U) -> anyhow::Result<T>
 where
     T: for<'a> Deserialize<'a> + 'static,
     U: IntoUrl,
 {
-    let response = build_client()?
-        .get(url)
-        .send()
+    let url = url.into_url()?;
+    provenance::record_endpoint(&url);
+    let response = http_get(url)?
+        .send_throttled()
         .await?
         .error_for_status()?
         .text()
@@ -63,7 +148,7 @@ async fn check_url<T>(result: anyhow::Result<T>, url: &str) -> anyhow::Result<T>
          * Some return 405 Method Not Allowed which would be fine, however GitLab for example simply returns
          * 403 Forbidden on HEAD for an URL that is 200 on GET.
          */
-        build_client()?.get(url).send().await?.error_for_status()?;
+        http_get(url)?.send_throttled().await?.error_for_status()?;
     }
     result
 }
@@ -129,6 +214,42 @@ pub trait Updatable:
 
     /// Fetch hashes for a given version
     async fn fetch(&self, version: &Self::Version) -> Result<Self::Hashes>;
+
+    /// Fetch a version again via a path independent from [`fetch`](Self::fetch), e.g. via git
+    /// instead of a tarball, to detect tampered or unstable downloads.
+    ///
+    /// Returns `None` if the pin has no such second path.
+    async fn cross_check(
+        &self,
+        _version: &Self::Version,
+        _hashes: &Self::Hashes,
+    ) -> Result<Option<CrossCheck>> {
+        Ok(None)
+    }
+
+    /// Check whether upstream withdrew a version (e.g. yanked it on PyPi)
+    ///
+    /// Returns the reason given by upstream, if any.
+    async fn yanked(&self, _version: &Self::Version) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// The result of [`Updatable::cross_check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrossCheck {
+    /// How the pin was fetched the second time, e.g. "git"
+    pub method: &'static str,
+    /// The hash recorded in the pin
+    pub expected: String,
+    /// The hash obtained via `method`
+    pub actual: String,
+}
+
+impl CrossCheck {
+    pub fn matches(&self) -> bool {
+        self.expected == self.actual
+    }
 }
 
 /// Create the `Pin` type
@@ -159,10 +280,8 @@ macro_rules! mkPin {
                     version: Option<<$input_name as Updatable>::Version>,
                     #[serde(flatten)]
                     hashes: Option<<$input_name as Updatable>::Hashes>,
-                    // TODO(piegames): This is the only field which is independent of the pin type and equal for all pins,
-                    // eventually it should be factored out (especially once a second field of that kind is added)
-                    #[serde(default, skip_serializing_if="Frozen::is_default")]
-                    frozen: Frozen,
+                    #[serde(flatten)]
+                    metadata: PinMetadata,
                 }
             ),*
         }
@@ -170,7 +289,7 @@ macro_rules! mkPin {
         impl Pin {
             /* Constructors */
             $(fn $lower_name(input: $input_name, version: Option<<$input_name as Updatable>::Version>) -> Self {
-                Self::$name { input, version, hashes: None, frozen: Frozen::default() }
+                Self::$name { input, version, hashes: None, metadata: PinMetadata::default() }
             })*
 
             /* If an error is returned, `self` remains unchanged */
@@ -199,6 +318,30 @@ macro_rules! mkPin {
                 })
             }
 
+            /// Fetch the pin again via an independent path, see [`Updatable::cross_check`]
+            pub async fn cross_check(&self) -> Result<Option<CrossCheck>> {
+                match self {
+                    $(Self::$name { input, version, hashes, .. } => {
+                        let version = version.as_ref()
+                            .ok_or_else(|| anyhow::format_err!("No version information available, call `update` first or manually set one"))?;
+                        let hashes = hashes.as_ref()
+                            .ok_or_else(|| anyhow::format_err!("No hashes available, call `fetch` first"))?;
+                        <$input_name as Updatable>::cross_check(input, version, hashes).await
+                    }),*
+                }
+            }
+
+            /// Check whether upstream withdrew the pinned version, see [`Updatable::yanked`]
+            pub async fn yanked(&self) -> Result<Option<String>> {
+                match self {
+                    $(Self::$name { input, version, .. } => {
+                        let version = version.as_ref()
+                            .ok_or_else(|| anyhow::format_err!("No version information available, call `update` first or manually set one"))?;
+                        <$input_name as Updatable>::yanked(input, version).await
+                    }),*
+                }
+            }
+
             pub fn has_version(&self) -> bool {
                 match self {
                     $(Self::$name { version, ..} => version.is_some() ),*
@@ -211,6 +354,13 @@ macro_rules! mkPin {
                 }
             }
 
+            /// The properties of the pinned version, empty if there is none yet
+            pub fn version_properties(&self) -> Vec<(String, String)> {
+                match self {
+                    $(Self::$name { version, .. } => version.properties()),*
+                }
+            }
+
             /// Human readable name of the pin type
             pub fn pin_type(&self) -> &'static str {
                 match self {
@@ -218,46 +368,74 @@ macro_rules! mkPin {
                 }
             }
 
-            /// Unfreeze a pin
-            pub fn unfreeze(&mut self) {
+            /// The metadata common to all pin types
+            pub fn metadata(&self) -> &PinMetadata {
                 match self {
-                    $(Self::$name { ref mut frozen, .. } => frozen.unfreeze()),*
+                    $(Self::$name { metadata, .. } => metadata),*
                 }
             }
 
-            /// Freeze a pin
-            pub fn freeze(&mut self) {
+            pub fn metadata_mut(&mut self) -> &mut PinMetadata {
                 match self {
-                    $(Self::$name { ref mut frozen, .. } => frozen.freeze()),*
+                    $(Self::$name { metadata, .. } => metadata),*
                 }
             }
 
+            /// Unfreeze a pin
+            pub fn unfreeze(&mut self) {
+                self.metadata_mut().frozen.unfreeze()
+            }
+
+            /// Freeze a pin
+            pub fn freeze(&mut self) {
+                self.metadata_mut().frozen.freeze()
+            }
+
             /// Is frozen
             pub fn is_frozen(&self) -> bool {
-                match self {
-                    $(Self::$name { frozen, .. } => frozen.is_frozen()),*
-                }
+                self.metadata().frozen.is_frozen()
+            }
+
+            /// Is kept in line with somewhere else, e.g. a registry
+            pub fn is_managed(&self) -> bool {
+                self.metadata().managed.is_some()
+            }
+
+            /// Changes need to be approved, see [`approval`]
+            pub fn requires_approval(&self) -> bool {
+                self.metadata().requires_approval
+            }
+
+            /// Carries any of the labels
+            pub fn has_any_label(&self, labels: &[String]) -> bool {
+                labels.iter().any(|label| self.metadata().labels.contains(label))
             }
         }
 
-        impl std::fmt::Display for Pin {
-            fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        impl diff::Diff for Pin {
+            fn properties(&self) -> Vec<(String, String)> {
                 match self {
-                    $(Self::$name { input, version, hashes, frozen } => {
-                        /* Concat all properties and then print them */
-                        let properties = input.properties().into_iter()
+                    $(Self::$name { input, version, hashes, metadata } => {
+                        input.properties().into_iter()
                             .chain(version.iter().flat_map(Diff::properties))
                             .chain(hashes.iter().flat_map(Diff::properties))
-                            .chain(frozen.properties());
-                        for (key, value) in properties {
-                            writeln!(fmt, "    {}: {}", key, value)?;
-                        }
-                        Ok(())
+                            .chain(metadata.properties())
+                            .collect()
                     }),*
                 }
             }
         }
 
+        impl std::fmt::Display for Pin {
+            fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+                /* Concat all properties and then print them */
+                for (key, value) in self.properties() {
+                    writeln!(fmt, "    {}: {}", key, value)?;
+                }
+                Ok(())
+            }
+        }
+
         // Each variant holds exactly one distinct type, so we can easily create convenient type wrappers that simply call the constructor
         $(
             impl From<$input_name> for Pin {
@@ -284,9 +462,67 @@ macro_rules! mkPin {
 mkPin! {
     (Git, git, "git repository", git::GitPin),
     (GitRelease, git_release, "git release tag", git::GitReleasePin),
+    (ReleaseAsset, release_asset, "release asset", asset::ReleaseAssetPin),
+    (GitLabPackage, gitlab_package, "GitLab package", asset::GitLabPackagePin),
     (PyPi, pypi, "pypi package", pypi::Pin),
+    (GoModule, go_module, "Go module", gomod::GoModPin),
     (Channel, channel, "Nix channel", channel::Pin),
+    (Hydra, hydra, "Hydra evaluation", hydra::HydraPin),
+    (Flake, flake, "flake", flake::FlakeRefPin),
+    (Svn, svn, "Subversion repository", svn::SvnPin),
     (Tarball, tarball, "tarball", tarball::TarballPin),
+    (File, file, "file", file::FilePin),
+    (VsCodeExtension, vscode_extension, "VS Code extension", vscode::ExtensionPin),
+    (FirefoxAddon, firefox_addon, "Firefox add-on", browser::FirefoxAddonPin),
+    (ChromeExtension, chrome_extension, "Chrome extension", browser::ChromeExtensionPin),
+    (Manifest, manifest, "release manifest", manifest::ManifestPin),
+    (Artifact, artifact, "repository manager artifact", artifact::ArtifactPin),
+    (Maven, maven, "Maven artifact", maven::MavenPin),
+    (Container, container, "container image", container::ContainerPin),
+    (Bucket, bucket, "bucket object", bucket::BucketPin),
+    (Ipfs, ipfs, "IPFS content", ipfs::IpfsPin),
+    (GitHubArtifact, github_artifact, "GitHub Actions artifact", ci::GitHubArtifactPin),
+    (GitLabArtifact, gitlab_artifact, "GitLab CI artifact", ci::GitLabArtifactPin),
+    (BuildkiteArtifact, buildkite_artifact, "Buildkite artifact", ci::BuildkiteArtifactPin),
+}
+
+impl Pin {
+    /// The repository, for pin types that track one
+    pub fn repository(&self) -> Option<&git::Repository> {
+        match self {
+            Pin::Git { input, .. } => Some(&input.repository),
+            Pin::GitRelease { input, .. } => Some(&input.repository),
+            Pin::ReleaseAsset { input, .. } => Some(&input.repository),
+            _ => None,
+        }
+    }
+
+    pub fn repository_mut(&mut self) -> Option<&mut git::Repository> {
+        match self {
+            Pin::Git { input, .. } => Some(&mut input.repository),
+            Pin::GitRelease { input, .. } => Some(&mut input.repository),
+            Pin::ReleaseAsset { input, .. } => Some(&mut input.repository),
+            _ => None,
+        }
+    }
+
+    /// Keep the pin as it was `before` an update among its previous versions, if its version
+    /// changed and it is supposed to [`keep`](PinMetadata::keep) any
+    pub fn remember(&mut self, mut before: Pin) {
+        let keep = self.metadata().keep;
+        if keep == 0
+            || !before.has_hashes()
+            || before.metadata().state != PinState::Fetched
+            || before.version_properties() == self.version_properties()
+        {
+            return;
+        }
+        /* Only the version and hashes are of interest */
+        *before.metadata_mut() = PinMetadata::default();
+        let previous = &mut self.metadata_mut().previous;
+        previous.insert(0, before);
+        previous.truncate(keep);
+    }
 }
 
 /// The main struct the CLI operates on
@@ -317,6 +553,106 @@ impl NixPins {
     pub fn to_value_versioned(&self) -> serde_json::Value {
         versions::to_value_versioned(self)
     }
+
+    /// The pin a variant like `postgres.v15` belongs to, and its name within it
+    pub fn split_variant<'a>(name: &'a str, pin: &Pin) -> Option<(&'a str, &'a str)> {
+        name.split_once('.').filter(|_| pin.metadata().variant)
+    }
+
+    /// The variants of a pin, which `default.nix` provides as attributes of it
+    ///
+    /// This way, a pin can lock several versions, e.g. `postgres.v15` and `postgres.v16`. They are
+    /// pins of their own in the lock file, and there doesn't need to be a pin `postgres` itself.
+    pub fn variants(&self, name: &str) -> Vec<&String> {
+        self.pins
+            .iter()
+            .filter(|(variant, pin)| {
+                Self::split_variant(variant, pin).is_some_and(|(group, _)| group == name)
+            })
+            .map(|(variant, _)| variant)
+            .collect()
+    }
+
+    /// The given names, each followed by the names of its variants
+    ///
+    /// Names that are neither a pin nor have variants are kept, so that the caller can complain.
+    pub fn with_variants(&self, names: &[String]) -> Vec<String> {
+        let mut selected = Vec::new();
+        for name in names {
+            let variants = self.variants(name);
+            if variants.is_empty() || self.pins.contains_key(name) {
+                selected.push(name.clone());
+            }
+            selected.extend(variants.into_iter().cloned());
+        }
+        selected
+    }
+
+    /// The pin that also provides `name`, as one of its former or other names
+    pub fn aliased(&self, name: &str) -> Option<&str> {
+        self.pins
+            .iter()
+            .find(|(_, pin)| {
+                pin.metadata().aliases.contains(name) || pin.metadata().other_names.contains(name)
+            })
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Give a pin an alias, which must not be taken by any other pin
+    pub fn add_alias(&mut self, name: &str, alias: &str) -> Result<()> {
+        let metadata = self.name_for(name, alias)?;
+        metadata.other_names.remove(alias);
+        metadata.aliases.insert(alias.to_owned());
+        Ok(())
+    }
+
+    /// Give a pin another name, like [`Self::add_alias`] but without a deprecation warning
+    pub fn add_other_name(&mut self, name: &str, other: &str) -> Result<()> {
+        let metadata = self.name_for(name, other)?;
+        metadata.aliases.remove(other);
+        metadata.other_names.insert(other.to_owned());
+        Ok(())
+    }
+
+    /// The metadata of the pin `name`, after checking that no other pin uses `alias`
+    fn name_for(&mut self, name: &str, alias: &str) -> Result<&mut PinMetadata> {
+        anyhow::ensure!(
+            !self.pins.contains_key(alias),
+            "There already is a pin '{}'",
+            alias
+        );
+        if let Some(other) = self.aliased(alias).filter(|other| *other != name) {
+            anyhow::bail!("'{}' is already an alias of '{}'", alias, other);
+        }
+        let pin = self
+            .pins
+            .get_mut(name)
+            .ok_or_else(|| anyhow::format_err!("Could not find the pin '{}'", name))?;
+        Ok(pin.metadata_mut())
+    }
+
+    /// Rename a pin, optionally keeping the old name as alias
+    pub fn rename(&mut self, old: &str, new: &str, keep_alias: bool) -> Result<()> {
+        anyhow::ensure!(
+            !self.pins.contains_key(new),
+            "There already is a pin '{}'",
+            new
+        );
+        if let Some(other) = self.aliased(new).filter(|other| *other != old) {
+            anyhow::bail!("'{}' is an alias of '{}'", new, other);
+        }
+        let mut pin = self
+            .pins
+            .remove(old)
+            .ok_or_else(|| anyhow::format_err!("Could not find the pin '{}'", old))?;
+        pin.metadata_mut().aliases.remove(new);
+        pin.metadata_mut().other_names.remove(new);
+        self.pins.insert(new.to_owned(), pin);
+        if keep_alias {
+            self.add_alias(new, old)?;
+        }
+        Ok(())
+    }
 }
 
 /// Just a version string
@@ -336,11 +672,166 @@ impl diff::Diff for GenericVersion {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct GenericHash {
     pub hash: String,
+    /// For archives whose URL doesn't tell their format, see [`nix::archive_format`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<nix::ArchiveFormat>,
 }
 
 impl diff::Diff for GenericHash {
     fn properties(&self) -> Vec<(String, String)> {
-        vec![("hash".into(), self.hash.clone())]
+        std::iter::once(("hash".into(), self.hash.clone()))
+            .chain(
+                self.archive
+                    .map(|archive| ("archive".into(), archive.extension().into())),
+            )
+            .collect()
+    }
+}
+
+/// Metadata shared by all pin types
+///
+/// This is about the pin itself, as opposed to what it points to.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct PinMetadata {
+    #[serde(default, skip_serializing_if = "Frozen::is_default")]
+    pub frozen: Frozen,
+    /// How the pin was last changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<provenance::Provenance>,
+    #[serde(default, skip_serializing_if = "PinState::is_default")]
+    pub state: PinState,
+    /// Whether the pin is kept in line with somewhere else instead of being updated locally
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed: Option<Manager>,
+    /// Whether changes need to be approved by somebody else, see [`approval`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub requires_approval: bool,
+    /// Who approved the current contents of the pin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<approval::Approval>,
+    /// Free-form labels to select groups of pins by, e.g. `security`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub labels: BTreeSet<String>,
+    /// Former names of the pin, which `default.nix` still provides with a deprecation warning
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub aliases: BTreeSet<String>,
+    /// Further names of the pin, which `default.nix` provides as well, without any warning. Meant
+    /// for migrations, e.g. `nixpkgs-stable` next to `nixpkgs`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub other_names: BTreeSet<String>,
+    /// Whether this is one of several versions of a pin, named like `postgres.v15`, see
+    /// [`NixPins::variants`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub variant: bool,
+    /// How many of the previously pinned versions to keep, e.g. to fall back to an older nightly
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub keep: usize,
+    /// The previously pinned versions, newest first, see [`Pin::remember`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous: Vec<Pin>,
+    /// What the pin is for, in a sentence or two
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Free-form data about the pin, which `default.nix` passes through as is, e.g. an owner
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+fn is_zero(keep: &usize) -> bool {
+    *keep == 0
+}
+
+impl diff::Diff for PinMetadata {
+    fn properties(&self) -> Vec<(String, String)> {
+        /* The provenance is too verbose for this, see `npins provenance` */
+        let mut properties = self.frozen.properties();
+        if let Some(description) = &self.description {
+            properties.push(("description".into(), description.clone()));
+        }
+        if !self.state.is_default() {
+            properties.push(("state".into(), self.state.to_string()));
+        }
+        if let Some(managed) = self.managed {
+            properties.push(("managed".into(), managed.to_string()));
+        }
+        if self.requires_approval {
+            properties.push(("requires approval".into(), "true".into()));
+        }
+        if let Some(approval) = &self.approval {
+            properties.push(("approved by".into(), approval.by.clone()));
+        }
+        if !self.labels.is_empty() {
+            let labels = self.labels.iter().cloned().collect::<Vec<_>>();
+            properties.push(("labels".into(), labels.join(", ")));
+        }
+        if !self.aliases.is_empty() {
+            let aliases = self.aliases.iter().cloned().collect::<Vec<_>>();
+            properties.push(("aliases".into(), aliases.join(", ")));
+        }
+        if !self.other_names.is_empty() {
+            let other_names = self.other_names.iter().cloned().collect::<Vec<_>>();
+            properties.push(("other names".into(), other_names.join(", ")));
+        }
+        if self.variant {
+            properties.push(("variant".into(), "true".into()));
+        }
+        if self.keep > 0 {
+            properties.push(("keep".into(), self.keep.to_string()));
+        }
+        if !self.previous.is_empty() {
+            properties.push(("previous versions".into(), self.previous.len().to_string()));
+        }
+        for (key, value) in &self.metadata {
+            properties.push((format!("metadata.{key}"), value.clone()));
+        }
+        properties
+    }
+}
+
+/// Whether the hashes of a pin belong to its version
+///
+/// `npins update --no-fetch` only looks for new versions, which leaves the hashes of the old one
+/// behind until `npins fetch` is run.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PinState {
+    #[default]
+    Fetched,
+    /// The version changed, but the hashes weren't fetched yet
+    NeedsFetch,
+    /// Fetching the hashes of the version failed
+    Error,
+}
+
+impl PinState {
+    fn is_default(&self) -> bool {
+        self == &PinState::default()
+    }
+}
+
+impl std::fmt::Display for PinState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PinState::Fetched => "fetched",
+            PinState::NeedsFetch => "needs-fetch",
+            PinState::Error => "error",
+        })
+    }
+}
+
+/// Where a managed pin comes from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Manager {
+    /// A pin registry, see `npins sync`
+    Registry,
+}
+
+impl std::fmt::Display for Manager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Manager::Registry => "registry",
+        })
     }
 }
 
@@ -383,17 +874,32 @@ impl std::default::Default for Frozen {
 pub struct GenericUrlHashes {
     pub url: url::Url,
     pub hash: String,
+    /// For archives whose URL doesn't tell their format, see [`nix::archive_format`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<nix::ArchiveFormat>,
 }
 
 impl diff::Diff for GenericUrlHashes {
     fn properties(&self) -> Vec<(String, String)> {
-        vec![
+        [
             ("url".into(), self.url.to_string()),
             ("hash".into(), self.hash.clone()),
         ]
+        .into_iter()
+        .chain(
+            self.archive
+                .map(|archive| ("archive".into(), archive.extension().into())),
+        )
+        .collect()
     }
 }
 
+/// A tarball pin of `url`, for the tests that need some pin
+#[cfg(test)]
+pub(crate) fn test_pin(url: &str) -> Pin {
+    tarball::TarballPin::new(url.parse().unwrap()).into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -427,4 +933,133 @@ mod tests {
         }
         .is_frozen());
     }
+
+    #[test]
+    fn test_state() {
+        let mut pin = test_pin("https://example.org/tool.tar.gz");
+        assert!(serde_json::to_value(&pin).unwrap().get("state").is_none());
+        assert!(!diff::Diff::properties(pin.metadata())
+            .iter()
+            .any(|(key, _)| key == "state"));
+        pin.metadata_mut().state = PinState::NeedsFetch;
+        assert!(diff::Diff::properties(pin.metadata())
+            .contains(&("state".into(), "needs-fetch".into())));
+        let json = serde_json::to_value(&pin).unwrap();
+        assert_eq!(json["state"], "needs-fetch");
+        assert_eq!(
+            serde_json::from_value::<Pin>(json)
+                .unwrap()
+                .metadata()
+                .state,
+            PinState::NeedsFetch
+        );
+    }
+
+    #[test]
+    fn test_remember() {
+        let pin = |version: &str| -> Pin {
+            serde_json::from_value(serde_json::json!({
+                "type": "GitRelease",
+                "repository": { "type": "GitHub", "owner": "owner", "repo": "tool" },
+                "pre_releases": false,
+                "version_upper_bound": null,
+                "release_prefix": null,
+                "version": version,
+                "revision": "0123456789abcdef0123456789abcdef01234567",
+                "url": null,
+                "hash": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+                "keep": 2,
+            }))
+            .unwrap()
+        };
+        let mut pin = pin("nightly-3");
+        pin.remember(pin.clone());
+        assert!(pin.metadata().previous.is_empty());
+        for version in ["nightly-2", "nightly-1", "nightly-0"] {
+            let mut before = pin.clone();
+            if let Pin::GitRelease { version: v, .. } = &mut before {
+                *v = Some(git::ReleaseVersion::new(version));
+            }
+            pin.remember(before);
+        }
+        let json = serde_json::to_value(&pin).unwrap();
+        assert_eq!(json["previous"][0]["version"], "nightly-0");
+        assert_eq!(json["previous"][1]["version"], "nightly-1");
+        assert_eq!(json["previous"].as_array().unwrap().len(), 2);
+        assert!(json["previous"][0].get("keep").is_none());
+        assert_eq!(serde_json::from_value::<Pin>(json).unwrap(), pin);
+    }
+
+    #[test]
+    fn test_annotations() {
+        let json = serde_json::json!({
+            "type": "Channel",
+            "name": "nixos-24.05",
+            "url": "https://releases.nixos.org/nixos/24.05/nixos-24.05.1234/nixexprs.tar.xz",
+            "hash": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+            "description": "The packages of the servers",
+            "metadata": { "owner": "ops", "review": "quarterly" },
+        });
+        let pin: Pin = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(
+            pin.metadata().description.as_deref(),
+            Some("The packages of the servers")
+        );
+        assert_eq!(pin.metadata().metadata["owner"], "ops");
+        assert!(pin
+            .properties()
+            .contains(&("metadata.review".into(), "quarterly".into())));
+        assert_eq!(serde_json::to_value(&pin).unwrap(), json);
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut pins = NixPins::new_with_nixpkgs();
+        pins.pins
+            .insert("tool".into(), test_pin("https://example.org/tool.tar.gz"));
+        pins.rename("nixpkgs", "pkgs", true).unwrap();
+        assert_eq!(pins.aliased("nixpkgs"), Some("pkgs"));
+        assert!(pins.rename("pkgs", "tool", false).is_err());
+        assert!(pins.add_alias("tool", "nixpkgs").is_err());
+
+        /* Renaming back to an alias drops it */
+        pins.rename("pkgs", "nixpkgs", true).unwrap();
+        assert_eq!(pins.aliased("pkgs"), Some("nixpkgs"));
+        assert_eq!(pins.aliased("nixpkgs"), None);
+
+        /* Other names are exclusive just like aliases, and turning one into the other moves it */
+        pins.add_other_name("nixpkgs", "nixpkgs-stable").unwrap();
+        assert_eq!(pins.aliased("nixpkgs-stable"), Some("nixpkgs"));
+        assert!(pins.add_other_name("tool", "pkgs").is_err());
+        pins.add_alias("nixpkgs", "nixpkgs-stable").unwrap();
+        let metadata = pins.pins["nixpkgs"].metadata();
+        assert!(metadata.other_names.is_empty());
+        assert!(metadata.aliases.contains("nixpkgs-stable"));
+    }
+
+    #[test]
+    fn test_variants() {
+        let mut pins = NixPins::default();
+        for name in [
+            "postgres.v15",
+            "postgres.v16",
+            "postgres",
+            "postgresql.v1",
+            "a.b",
+        ] {
+            let mut pin = test_pin("https://example.org/pg.tar.gz");
+            pin.metadata_mut().variant = name.contains('.') && name != "a.b";
+            pins.pins.insert(name.into(), pin);
+        }
+        assert_eq!(pins.variants("postgres"), ["postgres.v15", "postgres.v16"]);
+        assert!(pins.variants("a").is_empty());
+        assert_eq!(
+            pins.with_variants(&["postgresql".into(), "a.b".into(), "missing".into()]),
+            ["postgresql.v1", "a.b", "missing"]
+        );
+        assert_eq!(
+            pins.with_variants(&["postgres".into()]),
+            ["postgres", "postgres.v15", "postgres.v16"]
+        );
+    }
 }
@@ -0,0 +1,186 @@
+//! Keep the auxiliary files next to the lock file from growing without bounds
+//!
+//! Besides the lock file, npins keeps its previous states (see [`history`]), the journal of an
+//! interrupted update (see [`journal`]) and the lock files written by `npins range` (see
+//! [`range`]). This reports how much space they take, checks that they can still be read, and
+//! removes the files that a retention policy doesn't keep.
+
+use crate::*;
+use anyhow::{Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Which files to keep
+#[derive(Debug, Clone)]
+pub struct Retention {
+    /// How many previous states of the lock file to keep
+    pub keep: usize,
+    /// Remove history entries and ranges older than this
+    pub max_age: Option<Duration>,
+    /// Only report what would be removed
+    pub dry_run: bool,
+}
+
+/// What was found for one kind of auxiliary file
+#[derive(Debug, Default)]
+pub struct Report {
+    /// How many files are kept
+    pub kept: usize,
+    /// Their total size in bytes
+    pub bytes: u64,
+    /// The files that were removed, or would be on a dry run
+    pub removed: Vec<PathBuf>,
+    /// Files that are kept but can't be read
+    pub problems: Vec<String>,
+}
+
+impl Report {
+    fn keep(&mut self, path: &Path) -> Result<()> {
+        self.kept += 1;
+        self.bytes += std::fs::metadata(path)?.len();
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &Path, retention: &Retention) -> Result<()> {
+        if !retention.dry_run {
+            std::fs::remove_file(path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        self.removed.push(path.to_owned());
+        Ok(())
+    }
+}
+
+fn too_old(path: &Path, retention: &Retention) -> Result<bool> {
+    let Some(max_age) = retention.max_age else {
+        return Ok(false);
+    };
+    let modified = std::fs::metadata(path)?.modified()?;
+    Ok(SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|age| age > max_age))
+}
+
+/// Prune the previous states of the lock file
+pub fn history(history: &history::History, retention: &Retention) -> Result<Report> {
+    let mut report = Report::default();
+    for (index, (_, path)) in history.files()?.iter().enumerate() {
+        if index >= retention.keep || too_old(path, retention)? {
+            report.remove(path, retention)?;
+            continue;
+        }
+        report.keep(path)?;
+        if let Err(err) = history::History::read(index + 1, path) {
+            report.problems.push(format!("{err:#}"));
+        }
+    }
+    Ok(report)
+}
+
+/// Remove the journal of an update if it is stale, it would be discarded by the next update anyway
+pub fn journal(lock_file: &Path, retention: &Retention) -> Result<Report> {
+    let mut report = Report::default();
+    let path = journal::Journal::path_for(lock_file);
+    match journal::Journal::is_stale(lock_file)? {
+        None => {},
+        Some(true) => report.remove(&path, retention)?,
+        /* The next update resumes from it */
+        Some(false) => report.keep(&path)?,
+    }
+    Ok(report)
+}
+
+/// Prune the lock files written by `npins range`
+pub fn ranges(dir: &Path, retention: &Retention) -> Result<Report> {
+    let mut report = Report::default();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(report),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut paths = entries
+        .map(|entry| Ok(entry?.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "json")
+    });
+    paths.sort();
+    for path in paths {
+        if too_old(&path, retention)? {
+            report.remove(&path, retention)?;
+            continue;
+        }
+        report.keep(&path)?;
+        let valid = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| NixPins::from_json_versioned(serde_json::from_str(&contents)?));
+        if let Err(err) = valid {
+            report.problems.push(format!(
+                "{} is not a valid lock file: {err:#}",
+                path.display()
+            ));
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_maintenance() {
+        let root =
+            std::env::temp_dir().join(format!("npins-test-maintenance-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let history = history::History::new(root.join("history"));
+        let contents = serde_json::to_vec(&NixPins::default().to_value_versioned()).unwrap();
+        for _ in 0..5 {
+            history.record(&contents).unwrap();
+        }
+        std::fs::write(root.join("history/1.json"), "{").unwrap();
+
+        let retention = Retention {
+            keep: 3,
+            max_age: None,
+            dry_run: true,
+        };
+        let report = super::history(&history, &retention).unwrap();
+        assert_eq!((report.kept, report.removed.len()), (3, 2));
+        assert!(report.problems.is_empty());
+        assert_eq!(history.files().unwrap().len(), 5);
+
+        let retention = Retention {
+            keep: 5,
+            dry_run: false,
+            ..retention
+        };
+        let report = super::history(&history, &retention).unwrap();
+        assert_eq!((report.kept, report.removed.len()), (5, 0));
+        assert_eq!(report.problems.len(), 1);
+
+        let retention = Retention {
+            keep: 2,
+            ..retention
+        };
+        super::history(&history, &retention).unwrap();
+        assert_eq!(history.list().unwrap().len(), 2);
+
+        let lock_file = root.join("sources.json");
+        std::fs::write(&lock_file, &contents).unwrap();
+        assert_eq!(super::journal(&lock_file, &retention).unwrap().kept, 0);
+        std::fs::write(
+            journal::Journal::path_for(&lock_file),
+            "{\"lock_file\":\"\"}\n",
+        )
+        .unwrap();
+        let report = super::journal(&lock_file, &retention).unwrap();
+        assert_eq!(report.removed.len(), 1);
+        assert!(!journal::Journal::path_for(&lock_file).exists());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
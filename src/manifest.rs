@@ -0,0 +1,203 @@
+//! Pin whatever an upstream release manifest currently points to
+//!
+//! Some projects publish their releases as JSON document, e.g. a `latest-stable.json` or an update
+//! manifest with one entry per channel (stable, beta, nightly, …). Instead of ordering tags, we
+//! follow that document: two [JSON pointers](https://www.rfc-editor.org/rfc/rfc6901) select the
+//! version and the download URL in it. If a channel is given, it replaces `{channel}` in both pointers.
+
+use crate::*;
+use anyhow::{Context, Result};
+use lenient_version::Version;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ManifestPin {
+    /// URL of the JSON manifest
+    pub manifest: Url,
+    /// JSON pointer to the version, e.g. `/channels/{channel}/version`
+    pub version_pointer: String,
+    /// JSON pointer to the download URL, e.g. `/channels/{channel}/url`.
+    ///
+    /// Relative URLs are resolved against the manifest URL.
+    pub url_pointer: String,
+    /// Substituted for `{channel}` in the pointers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// Whether the download is an archive that should be unpacked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unpack: bool,
+}
+
+impl diff::Diff for ManifestPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("manifest".into(), self.manifest.to_string())),
+            Some(("version_pointer".into(), self.version_pointer.clone())),
+            Some(("url_pointer".into(), self.url_pointer.clone())),
+            self.channel
+                .as_ref()
+                .map(|channel| ("channel".into(), channel.clone())),
+            self.unpack.then(|| ("unpack".into(), "true".into())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// The version as announced by the manifest, together with its URL
+///
+/// Unlike for other pins, the URL cannot be derived from the version and thus is part of it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ManifestVersion {
+    pub version: String,
+    pub url: Url,
+}
+
+impl diff::Diff for ManifestVersion {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![
+            ("version".into(), self.version.clone()),
+            ("url".into(), self.url.to_string()),
+        ]
+    }
+}
+
+impl ManifestPin {
+    fn pointer(&self, pointer: &str) -> Result<String> {
+        match &self.channel {
+            Some(channel) => {
+                /* Escape the channel name as a single reference token, see RFC 6901 section 3 */
+                let token = channel.replace('~', "~0").replace('/', "~1");
+                Ok(pointer.replace("{channel}", &token))
+            },
+            None => {
+                anyhow::ensure!(
+                    !pointer.contains("{channel}"),
+                    "The pointer '{pointer}' refers to a channel, but none is set"
+                );
+                Ok(pointer.to_owned())
+            },
+        }
+    }
+
+    /// Extract the version and URL from a manifest
+    fn select(&self, manifest: &serde_json::Value) -> Result<ManifestVersion> {
+        let lookup = |pointer: &str| -> Result<String> {
+            let pointer = self.pointer(pointer)?;
+            match manifest.pointer(&pointer) {
+                Some(serde_json::Value::String(value)) => Ok(value.clone()),
+                /* Some manifests have numeric versions */
+                Some(serde_json::Value::Number(value)) => Ok(value.to_string()),
+                Some(_) => {
+                    anyhow::bail!("The value at '{pointer}' in the manifest is not a string")
+                },
+                None => anyhow::bail!("The manifest has no value at '{pointer}'"),
+            }
+        };
+
+        let version = lookup(&self.version_pointer)?;
+        let url = lookup(&self.url_pointer)?;
+        let url = self
+            .manifest
+            .join(&url)
+            .with_context(|| format!("The manifest contains an invalid URL '{url}'"))?;
+        Ok(ManifestVersion { version, url })
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for ManifestPin {
+    type Version = ManifestVersion;
+    type Hashes = GenericHash;
+
+    async fn update(&self, old: Option<&ManifestVersion>) -> Result<ManifestVersion> {
+        let manifest: serde_json::Value = get_and_deserialize(self.manifest.clone())
+            .await
+            .context("Couldn't fetch the manifest")?;
+        let version = self.select(&manifest)?;
+
+        /* Channels may legitimately go back, e.g. when upstream pulls a broken release, so only warn */
+        if let Some(old) = old {
+            let old_version = lenient_semver_parser::parse::<Version>(&old.version);
+            let new_version = lenient_semver_parser::parse::<Version>(&version.version);
+            if let (Ok(old_version), Ok(new_version)) = (old_version, new_version) {
+                if new_version < old_version {
+                    log::warn!(
+                        "The manifest went back from version {} to {}",
+                        old_version,
+                        new_version
+                    );
+                }
+            }
+        }
+
+        Ok(version)
+    }
+
+    async fn fetch(&self, version: &ManifestVersion) -> Result<GenericHash> {
+        let (hash, archive) = if self.unpack {
+            (
+                nix::nix_prefetch_tarball(&version.url).await?,
+                nix::archive_format(&version.url, &[]).await?,
+            )
+        } else {
+            (nix::nix_prefetch_url(&version.url).await?, None)
+        };
+        Ok(GenericHash { hash, archive })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pin(channel: Option<&str>) -> ManifestPin {
+        ManifestPin {
+            manifest: "https://example.org/releases/manifest.json"
+                .parse()
+                .unwrap(),
+            version_pointer: "/channels/{channel}/version".into(),
+            url_pointer: "/channels/{channel}/downloads/0/url".into(),
+            channel: channel.map(Into::into),
+            unpack: false,
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let manifest = serde_json::json!({
+            "channels": {
+                "stable": {
+                    "version": "1.4.2",
+                    "downloads": [{"url": "https://cdn.example.org/tool-1.4.2.tar.gz"}],
+                },
+                "beta/next": {
+                    "version": 15,
+                    "downloads": [{"url": "tool-1.5.0-beta.tar.gz"}],
+                },
+            },
+        });
+
+        assert_eq!(
+            pin(Some("stable")).select(&manifest).unwrap(),
+            ManifestVersion {
+                version: "1.4.2".into(),
+                url: "https://cdn.example.org/tool-1.4.2.tar.gz".parse().unwrap(),
+            }
+        );
+        /* Relative URLs, numeric versions, and escaping */
+        assert_eq!(
+            pin(Some("beta/next")).select(&manifest).unwrap(),
+            ManifestVersion {
+                version: "15".into(),
+                url: "https://example.org/releases/tool-1.5.0-beta.tar.gz"
+                    .parse()
+                    .unwrap(),
+            }
+        );
+        assert!(pin(Some("nightly")).select(&manifest).is_err());
+        assert!(pin(None).select(&manifest).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Pin artifacts from Maven repositories
+//!
+//! Maven Central as well as Nexus and Artifactory serve the standard repository layout, where each
+//! artifact has a `maven-metadata.xml` listing its versions. The latest release is the one it names
+//! in `<release>`, which is also what Maven resolves `RELEASE` to. Repositories may publish a
+//! `.sha256` file next to each file, otherwise the file is downloaded to compute the hash.
+//!
+//! Credentials for internal repositories are taken from `NPINS_HEADERS_FILE`, see
+//! [`crate::headers`].
+
+use crate::*;
+use anyhow::{Context, Result};
+use bucket::{xml_element, xml_unescape};
+use lenient_version::Version;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+pub const MAVEN_CENTRAL: &str = "https://repo1.maven.org/maven2/";
+
+fn default_packaging() -> String {
+    "jar".into()
+}
+
+fn is_default_packaging(packaging: &str) -> bool {
+    packaging == "jar"
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct MavenPin {
+    /// Base URL of the repository, e.g. <https://repo1.maven.org/maven2/>
+    pub repository: Url,
+    pub group_id: String,
+    pub artifact_id: String,
+    /// The file extension, e.g. `jar`, `aar` or `pom`
+    #[serde(
+        default = "default_packaging",
+        skip_serializing_if = "is_default_packaging"
+    )]
+    pub packaging: String,
+    /// E.g. `sources` or `linux-x86_64`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub classifier: Option<String>,
+}
+
+impl diff::Diff for MavenPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("repository".into(), self.repository.to_string())),
+            Some(("group_id".into(), self.group_id.clone())),
+            Some(("artifact_id".into(), self.artifact_id.clone())),
+            Some(("packaging".into(), self.packaging.clone())),
+            self.classifier
+                .as_ref()
+                .map(|classifier| ("classifier".into(), classifier.clone())),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl MavenPin {
+    /// `<repository>/<group as path>/<artifact>/<path…>`
+    fn url<'a>(&'a self, path: impl IntoIterator<Item = &'a str>) -> Result<Url> {
+        let mut url = self.repository.clone();
+        url.path_segments_mut()
+            .map_err(|()| anyhow::format_err!("Invalid repository URL {}", self.repository))?
+            .pop_if_empty()
+            .extend(self.group_id.split('.'))
+            .push(&self.artifact_id)
+            .extend(path);
+        Ok(url)
+    }
+
+    /// E.g. `tool-1.2.3-sources.jar`
+    fn file_name(&self, version: &str) -> String {
+        match &self.classifier {
+            Some(classifier) => format!(
+                "{}-{}-{}.{}",
+                self.artifact_id, version, classifier, self.packaging
+            ),
+            None => format!("{}-{}.{}", self.artifact_id, version, self.packaging),
+        }
+    }
+
+    fn file_url(&self, version: &str) -> Result<Url> {
+        self.url([version, &self.file_name(version)])
+    }
+
+    async fn get(&self, url: Url) -> Result<Option<String>> {
+        provenance::record_endpoint(&url);
+        let response = http_get(url.clone())?.send_throttled().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let text = response
+            .error_for_status()
+            .with_context(|| format!("Failed to download {}", url))?
+            .text()
+            .await?;
+        Ok(Some(text))
+    }
+}
+
+/// The latest release named in a `maven-metadata.xml`
+fn latest_release(metadata: &str) -> Result<String> {
+    /* Older metadata may lack `<release>`, in which case `<latest>` is all there is */
+    let version = xml_element(metadata, "release")
+        .or_else(|| xml_element(metadata, "latest"))
+        .map(|version| xml_unescape(version.trim()))
+        .filter(|version| !version.is_empty())
+        .context("The metadata names no release")?;
+    anyhow::ensure!(
+        !version.ends_with("-SNAPSHOT"),
+        "The latest version {} is a snapshot, which can't be pinned",
+        version
+    );
+    Ok(version)
+}
+
+#[async_trait::async_trait]
+impl Updatable for MavenPin {
+    type Version = GenericVersion;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(&self, old: Option<&GenericVersion>) -> Result<GenericVersion> {
+        let url = self.url(["maven-metadata.xml"])?;
+        let metadata = self.get(url).await?.with_context(|| {
+            format!(
+                "Couldn't find {}:{} in {}",
+                self.group_id, self.artifact_id, self.repository
+            )
+        })?;
+        let version = latest_release(&metadata)?;
+
+        if let Some(old) = old {
+            if let (Ok(old_version), Ok(new_version)) = (
+                lenient_semver_parser::parse::<Version>(&old.version),
+                lenient_semver_parser::parse::<Version>(&version),
+            ) {
+                anyhow::ensure!(
+                    new_version >= old_version,
+                    "Failed to ensure version monotonicity, latest found version is {} but current is {}",
+                    new_version,
+                    old_version,
+                );
+            }
+        }
+
+        Ok(GenericVersion { version })
+    }
+
+    async fn fetch(&self, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        let url = self.file_url(&version.version)?;
+        let mut checksum_url = url.clone();
+        checksum_url.set_path(&format!("{}.sha256", url.path()));
+        /* The checksum file may be followed by the file name, like the output of `sha256sum` */
+        let sha256 = self
+            .get(checksum_url)
+            .await?
+            .and_then(|checksum| checksum.split_whitespace().next().map(ToOwned::to_owned));
+        let hash = match sha256 {
+            Some(sha256) => nix::hash_to_sri(&sha256, "sha256")?,
+            None => nix::nix_prefetch_url(&url).await?,
+        };
+        Ok(GenericUrlHashes {
+            url,
+            hash,
+            archive: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_urls() {
+        let mut pin = MavenPin {
+            repository: MAVEN_CENTRAL.parse().unwrap(),
+            group_id: "com.google.guava".into(),
+            artifact_id: "guava".into(),
+            packaging: default_packaging(),
+            classifier: None,
+        };
+        assert_eq!(
+            pin.url(["maven-metadata.xml"]).unwrap().as_str(),
+            "https://repo1.maven.org/maven2/com/google/guava/guava/maven-metadata.xml"
+        );
+        assert_eq!(
+            pin.file_url("33.4.0-jre").unwrap().as_str(),
+            "https://repo1.maven.org/maven2/com/google/guava/guava/33.4.0-jre/guava-33.4.0-jre.jar"
+        );
+        pin.classifier = Some("sources".into());
+        assert_eq!(
+            pin.file_url("33.4.0-jre").unwrap().as_str(),
+            "https://repo1.maven.org/maven2/com/google/guava/guava/33.4.0-jre/guava-33.4.0-jre-sources.jar"
+        );
+    }
+
+    #[test]
+    fn test_latest_release() {
+        let metadata = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata>
+  <groupId>com.google.guava</groupId>
+  <artifactId>guava</artifactId>
+  <versioning>
+    <latest>33.4.0-jre</latest>
+    <release>33.4.0-jre</release>
+    <versions>
+      <version>33.3.1-jre</version>
+      <version>33.4.0-android</version>
+      <version>33.4.0-jre</version>
+    </versions>
+    <lastUpdated>20241218150254</lastUpdated>
+  </versioning>
+</metadata>"#;
+        assert_eq!(latest_release(metadata).unwrap(), "33.4.0-jre");
+        assert!(latest_release(
+            "<metadata><versioning><latest>1.0-SNAPSHOT</latest></versioning></metadata>"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Check that the URLs in the lock file are still reachable
+//!
+//! Pins only get fetched when they are updated, or when the Nix store does not have them yet. A URL
+//! that went away thus goes unnoticed until some cold cache needs it. This sends a `HEAD` request to
+//! every URL recorded in a pin, and to the same URL on configured mirrors.
+//!
+//! Mirrors are given as a prefix to replace, like `https://github.com/=https://mirror.example.org/github/`.
+
+use crate::*;
+use anyhow::{Context, Result};
+use url::Url;
+
+/// Replaces the start of URLs to point to a mirror
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mirror {
+    pub original: String,
+    pub mirror: String,
+}
+
+impl std::str::FromStr for Mirror {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (original, mirror) = s
+            .split_once('=')
+            .context("expected `<original prefix>=<mirror prefix>`")?;
+        mirror
+            .parse::<Url>()
+            .with_context(|| format!("'{mirror}' is not a valid URL"))?;
+        Ok(Mirror {
+            original: original.to_owned(),
+            mirror: mirror.to_owned(),
+        })
+    }
+}
+
+impl Mirror {
+    /// The URL on the mirror, if it is mirrored at all
+    pub fn apply(&self, url: &Url) -> Option<Url> {
+        let rest = url.as_str().strip_prefix(&self.original)?;
+        format!("{}{rest}", self.mirror).parse().ok()
+    }
+}
+
+/// What happened when requesting a URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Still works, but the URL should probably be updated
+    Redirect(Url),
+    /// The server answered with an error
+    Dead(reqwest::StatusCode),
+    /// The TLS certificate is invalid, e.g. expired or for a different host
+    Certificate(String),
+    /// No answer at all, e.g. the host does not resolve anymore
+    Unreachable(String),
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Ok => write!(f, "OK"),
+            Status::Redirect(target) => write!(f, "redirects to {target}"),
+            Status::Dead(status) => write!(f, "dead link ({status})"),
+            Status::Certificate(message) => write!(f, "certificate problem: {message}"),
+            Status::Unreachable(message) => write!(f, "unreachable: {message}"),
+        }
+    }
+}
+
+/// The HTTP(S) URLs of a pin, and their counterparts on the mirrors
+///
+/// Other schemes (like `ssh://`) and strings that merely look like URLs are skipped.
+pub fn urls_to_check(pin: &Pin, mirrors: &[Mirror]) -> Vec<Url> {
+    let mut urls = Vec::new();
+    for url in check::recorded_urls(pin)
+        .into_iter()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .flat_map(|url| {
+            let mirrored = mirrors
+                .iter()
+                .filter_map(|mirror| mirror.apply(&url))
+                .collect::<Vec<_>>();
+            std::iter::once(url).chain(mirrored)
+        })
+    {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+fn describe(err: &reqwest::Error) -> Status {
+    /* reqwest does not expose TLS errors as such, only their message */
+    let messages = std::iter::successors(Some(err as &(dyn std::error::Error + 'static)), |err| {
+        err.source()
+    })
+    .map(ToString::to_string)
+    .collect::<Vec<_>>();
+    let message = messages.last().cloned().unwrap_or_default();
+    if messages
+        .iter()
+        .any(|message| message.to_lowercase().contains("certificate"))
+    {
+        Status::Certificate(message)
+    } else {
+        Status::Unreachable(message)
+    }
+}
+
+/// Request a single URL, without downloading it
+pub async fn check(url: &Url) -> Status {
+    let response = match http_request(reqwest::Method::HEAD, url.clone()) {
+        Ok(request) => request.send_throttled().await,
+        Err(err) => return Status::Unreachable(err.to_string()),
+    };
+    let response = match response {
+        /* Not everybody implements HEAD, so retry with the first byte only */
+        Ok(response)
+            if matches!(
+                response.status(),
+                reqwest::StatusCode::METHOD_NOT_ALLOWED | reqwest::StatusCode::NOT_IMPLEMENTED
+            ) =>
+        {
+            match http_get(url.clone()) {
+                Ok(request) => {
+                    request
+                        .header(reqwest::header::RANGE, "bytes=0-0")
+                        .send_throttled()
+                        .await
+                },
+                Err(err) => return Status::Unreachable(err.to_string()),
+            }
+        },
+        response => response,
+    };
+    match response {
+        Ok(response) if !response.status().is_success() => Status::Dead(response.status()),
+        Ok(response) if response.url() != url => Status::Redirect(response.url().clone()),
+        Ok(_) => Status::Ok,
+        Err(err) => describe(&err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mirror_apply() {
+        let mirror: Mirror = "https://github.com/=https://mirror.example.org/github/"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            mirror
+                .apply(
+                    &"https://github.com/andir/npins/archive/v0.3.1.tar.gz"
+                        .parse()
+                        .unwrap()
+                )
+                .unwrap()
+                .as_str(),
+            "https://mirror.example.org/github/andir/npins/archive/v0.3.1.tar.gz"
+        );
+        assert_eq!(
+            mirror.apply(&"https://gitlab.com/a/b.git".parse().unwrap()),
+            None
+        );
+        assert!("https://github.com/".parse::<Mirror>().is_err());
+        assert!("https://github.com/=not a url".parse::<Mirror>().is_err());
+    }
+
+    #[test]
+    fn test_urls_to_check() {
+        let pin = test_pin("https://github.com/andir/npins/archive/v0.3.1.tar.gz");
+        let mirrors = ["https://github.com/=https://mirror.example.org/github/"
+            .parse()
+            .unwrap()];
+        assert_eq!(
+            urls_to_check(&pin, &mirrors)
+                .iter()
+                .map(Url::as_str)
+                .collect::<Vec<_>>(),
+            vec![
+                "https://github.com/andir/npins/archive/v0.3.1.tar.gz",
+                "https://mirror.example.org/github/andir/npins/archive/v0.3.1.tar.gz"
+            ]
+        );
+    }
+}
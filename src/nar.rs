@@ -0,0 +1,827 @@
+//! Hashing sources in-process, for machines without Nix like Windows ones
+//!
+//! Nix hashes unpacked sources as their [NAR] serialization, which npins can compute on its own
+//! for the most common kinds of pins: git repositories, archives and single files. It goes by
+//! what `git` and `bsdtar` (which is the `tar` of Windows) tell about the files instead of
+//! checking them out or unpacking them, so that the file system doesn't get a say: Windows ones
+//! don't know executable bits, need privileges for symlinks, and don't tell `README` and
+//! `readme` apart.
+//!
+//! [NAR]: https://nix.dev/manual/nix/latest/protocols/nix-archive/
+
+use crate::*;
+use anyhow::{Context, Result};
+use data_encoding::BASE64;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+/// A tree of files, with their contents kept elsewhere
+#[derive(Debug, PartialEq, Eq)]
+enum Node<C> {
+    File {
+        executable: bool,
+        size: u64,
+        contents: C,
+    },
+    Symlink {
+        target: Vec<u8>,
+    },
+    Directory(BTreeMap<Vec<u8>, Node<C>>),
+}
+
+/// The components of a `/` separated path, without the empty and `.` ones
+fn components(path: &[u8]) -> Result<Vec<&[u8]>> {
+    let components = path
+        .split(|&c| c == b'/')
+        .filter(|component| !component.is_empty() && *component != b".")
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        !components.contains(&&b".."[..]),
+        "The path {} points outside of the source",
+        String::from_utf8_lossy(path)
+    );
+    Ok(components)
+}
+
+impl<C> Node<C> {
+    fn directory() -> Self {
+        Node::Directory(BTreeMap::new())
+    }
+
+    /// Put `node` at `path`, creating the directories on the way
+    ///
+    /// A directory that is already there gets the entries of `node` added, if that is one too.
+    fn insert(&mut self, path: &[u8], node: Node<C>) -> Result<()> {
+        let components = components(path)?;
+        let mut directory = self;
+        for component in &components {
+            let Node::Directory(entries) = directory else {
+                anyhow::bail!(
+                    "{} is in something that is not a directory",
+                    String::from_utf8_lossy(path)
+                );
+            };
+            directory = entries
+                .entry(component.to_vec())
+                .or_insert_with(Node::directory);
+        }
+        match (directory, node) {
+            (Node::Directory(existing), Node::Directory(mut entries)) => {
+                existing.append(&mut entries)
+            },
+            /* The directory was just created, or something else is replaced */
+            (directory, node) => *directory = node,
+        }
+        Ok(())
+    }
+}
+
+/// Part of a NAR, see [`serialize`]
+enum Piece<'a, C> {
+    Bytes(Vec<u8>),
+    /// The contents of a file, which are streamed in
+    Contents(&'a C, u64),
+}
+
+fn padding(length: u64) -> &'static [u8] {
+    &[0; 8][..((8 - length % 8) % 8) as usize]
+}
+
+fn string(buffer: &mut Vec<u8>, string: &[u8]) {
+    buffer.extend((string.len() as u64).to_le_bytes());
+    buffer.extend(string);
+    buffer.extend(padding(string.len() as u64));
+}
+
+/// The NAR of `root`, leaving out the contents of the files
+fn serialize<C>(root: &Node<C>) -> Vec<Piece<'_, C>> {
+    fn node<'a, C>(pieces: &mut Vec<Piece<'a, C>>, buffer: &mut Vec<u8>, root: &'a Node<C>) {
+        string(buffer, b"(");
+        string(buffer, b"type");
+        match root {
+            Node::File {
+                executable,
+                size,
+                contents,
+            } => {
+                string(buffer, b"regular");
+                if *executable {
+                    string(buffer, b"executable");
+                    string(buffer, b"");
+                }
+                string(buffer, b"contents");
+                buffer.extend(size.to_le_bytes());
+                pieces.push(Piece::Bytes(std::mem::take(buffer)));
+                pieces.push(Piece::Contents(contents, *size));
+                buffer.extend(padding(*size));
+            },
+            Node::Symlink { target } => {
+                string(buffer, b"symlink");
+                string(buffer, b"target");
+                string(buffer, target);
+            },
+            Node::Directory(entries) => {
+                string(buffer, b"directory");
+                /* In the order of the bytes of the names, like the map has them */
+                for (name, entry) in entries {
+                    string(buffer, b"entry");
+                    string(buffer, b"(");
+                    string(buffer, b"name");
+                    string(buffer, name);
+                    string(buffer, b"node");
+                    node(pieces, buffer, entry);
+                    string(buffer, b")");
+                }
+            },
+        }
+        string(buffer, b")");
+    }
+
+    let mut pieces = Vec::new();
+    let mut buffer = Vec::new();
+    string(&mut buffer, b"nix-archive-1");
+    node(&mut pieces, &mut buffer, root);
+    pieces.push(Piece::Bytes(buffer));
+    pieces
+}
+
+fn sri(hasher: Sha256) -> String {
+    format!("sha256-{}", BASE64.encode(&hasher.finalize()))
+}
+
+/// The hash of a single file, like `nix-prefetch-url` without `--unpack` computes it
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(sri(hasher))
+}
+
+/// The number in a field of a tar header, in octal or, if it doesn't fit, base-256
+fn tar_number(field: &[u8]) -> Result<u64> {
+    if field.first().is_some_and(|byte| byte & 0x80 != 0) {
+        return Ok(field[1..]
+            .iter()
+            .fold(0, |number, &byte| (number << 8) | u64::from(byte)));
+    }
+    let digits = String::from_utf8_lossy(tar_string(field));
+    let digits = digits.trim_matches(' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).with_context(|| format!("Invalid number '{digits}' in tarball"))
+}
+
+/// A string in a field of a tar header, up to the first NUL byte
+fn tar_string(field: &[u8]) -> &[u8] {
+    field.split(|&byte| byte == 0).next().unwrap_or_default()
+}
+
+/// The entries of an uncompressed tarball, with the offsets of the contents of the files
+///
+/// Supports what `bsdtar --format=pax` writes, as well as GNU tar's long names.
+fn read_tar(tarball: &mut (impl Read + Seek)) -> Result<Node<u64>> {
+    let mut root = Node::directory();
+    /* What extended headers say about the next entry */
+    let mut next_path = None;
+    let mut next_link = None;
+    let mut next_size = None;
+    /* For hard links, which can only point to files that came before */
+    let mut files = HashMap::new();
+    let mut offset = 0;
+    loop {
+        let mut header = [0; 512];
+        match tarball.read_exact(&mut header) {
+            Ok(()) => {},
+            /* Some tarballs end without the two blocks of zeros */
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err).context("Failed to read the tarball"),
+        }
+        offset += 512;
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+
+        let size = match next_size.take() {
+            Some(size) => size,
+            None => tar_number(&header[124..136])?,
+        };
+        let path = next_path.take().unwrap_or_else(|| {
+            let name = tar_string(&header[0..100]);
+            let prefix = tar_string(&header[345..500]);
+            if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                [prefix, b"/", name].concat()
+            } else {
+                name.to_vec()
+            }
+        });
+        let link = next_link
+            .take()
+            .unwrap_or_else(|| tar_string(&header[157..257]).to_vec());
+        let executable = tar_number(&header[100..108])? & 0o100 != 0;
+        let mut contents = || -> Result<Vec<u8>> {
+            let mut contents = Vec::new();
+            tarball.by_ref().take(size).read_to_end(&mut contents)?;
+            Ok(contents)
+        };
+
+        match header[156] {
+            /* pax extended header, as lines of `<length> <key>=<value>` */
+            b'x' => {
+                let records = contents()?;
+                let mut records = &records[..];
+                while let Some(space) = records.iter().position(|&byte| byte == b' ') {
+                    let length = std::str::from_utf8(&records[..space])
+                        .ok()
+                        .and_then(|length| length.parse::<usize>().ok())
+                        .filter(|&length| length > space && length <= records.len())
+                        .context("Invalid pax header in tarball")?;
+                    let record = &records[space + 1..length];
+                    let record = record.strip_suffix(b"\n").unwrap_or(record);
+                    if let Some(equals) = record.iter().position(|&byte| byte == b'=') {
+                        let (key, value) = (&record[..equals], &record[equals + 1..]);
+                        match key {
+                            b"path" => next_path = Some(value.to_vec()),
+                            b"linkpath" => next_link = Some(value.to_vec()),
+                            b"size" => {
+                                next_size = Some(
+                                    String::from_utf8_lossy(value)
+                                        .parse()
+                                        .context("Invalid size in pax header")?,
+                                )
+                            },
+                            _ => {},
+                        }
+                    }
+                    records = &records[length..];
+                }
+            },
+            /* pax global header, nothing of interest to Nix */
+            b'g' => {},
+            /* GNU long names */
+            b'L' => next_path = Some(tar_string(&contents()?).to_vec()),
+            b'K' => next_link = Some(tar_string(&contents()?).to_vec()),
+            b'0' | b'\0' | b'7' => {
+                files.insert(components(&path)?.join(&b'/'), (executable, size, offset));
+                root.insert(
+                    &path,
+                    Node::File {
+                        executable,
+                        size,
+                        contents: offset,
+                    },
+                )?;
+            },
+            b'1' => {
+                let &(executable, size, contents) = files
+                    .get(&components(&link)?.join(&b'/'))
+                    .with_context(|| {
+                        format!(
+                            "The hard link {} points to {}, which is not a file before it",
+                            String::from_utf8_lossy(&path),
+                            String::from_utf8_lossy(&link)
+                        )
+                    })?;
+                root.insert(
+                    &path,
+                    Node::File {
+                        executable,
+                        size,
+                        contents,
+                    },
+                )?;
+            },
+            b'2' => root.insert(&path, Node::Symlink { target: link })?,
+            b'5' => root.insert(&path, Node::directory())?,
+            _ => anyhow::bail!(
+                "{} is a special file, which Nix doesn't unpack",
+                String::from_utf8_lossy(&path)
+            ),
+        }
+
+        offset += size.div_ceil(512) * 512;
+        tarball.seek(SeekFrom::Start(offset))?;
+    }
+    Ok(root)
+}
+
+/// The program that can convert between archive formats
+const BSDTAR: &str = if cfg!(windows) { "tar" } else { "bsdtar" };
+
+/// The hash of an archive's contents, like `nix-prefetch-url --unpack` computes it
+///
+/// Like Nix, this expects a single directory (or file) at the top of the archive, and hashes what
+/// is in it.
+pub async fn hash_archive(archive: &Path) -> Result<String> {
+    let tarball = tempdir::TempFile::new("unpacked.tar")?;
+    let mut source = std::ffi::OsString::from("@");
+    source.push(archive);
+    log::debug!(
+        "Executing `{BSDTAR} --create --format=pax --file {} {}`",
+        tarball.path().display(),
+        source.to_string_lossy()
+    );
+    let output = tokio::process::Command::new(BSDTAR)
+        .kill_on_drop(true)
+        .args(["--create", "--format=pax", "--file"])
+        .arg(tarball.path())
+        .arg(&source)
+        .output()
+        .await
+        .with_context(|| {
+            format!("Failed to spawn `{BSDTAR}`, which is needed to hash archives without Nix")
+        })?;
+    anyhow::ensure!(
+        output.status.success(),
+        "Failed to read the archive {}\n{}",
+        archive.display(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut file = std::fs::File::open(tarball.path())?;
+    let Node::Directory(entries) = read_tar(&mut std::io::BufReader::new(&mut file))? else {
+        unreachable!("The root of an archive is a directory");
+    };
+    let mut entries = entries.into_values();
+    let (Some(top), None) = (entries.next(), entries.next()) else {
+        anyhow::bail!(
+            "The archive {} has more or less than one entry at the top",
+            archive.display()
+        );
+    };
+
+    let mut hasher = Sha256::new();
+    for piece in serialize(&top) {
+        match piece {
+            Piece::Bytes(bytes) => hasher.update(bytes),
+            Piece::Contents(&offset, size) => {
+                file.seek(SeekFrom::Start(offset))?;
+                let copied = std::io::copy(&mut (&mut file).take(size), &mut hasher)?;
+                anyhow::ensure!(copied == size, "The tarball ends early");
+            },
+        }
+    }
+    Ok(sri(hasher))
+}
+
+/// Where the contents of a file in a git repository are, see [`hash_git`]
+#[derive(Debug, PartialEq, Eq)]
+struct Blob {
+    /// Which of the repositories, as there are several with submodules
+    repository: usize,
+    id: String,
+}
+
+/// `git cat-file --batch`, which reads one object after another
+struct CatFile {
+    stdin: tokio::process::ChildStdin,
+    stdout: tokio::io::BufReader<tokio::process::ChildStdout>,
+    _child: tokio::process::Child,
+}
+
+impl CatFile {
+    fn start(directory: &Path) -> Result<Self> {
+        let mut child = git::command()
+            .arg("-C")
+            .arg(directory)
+            .args(["cat-file", "--batch"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .context("Failed to spawn git cat-file")?;
+        Ok(CatFile {
+            stdin: child.stdin.take().unwrap(),
+            stdout: tokio::io::BufReader::new(child.stdout.take().unwrap()),
+            _child: child,
+        })
+    }
+
+    /// Feed the contents of the blob `id` to `sink`, piece by piece
+    async fn read(&mut self, id: &str, mut sink: impl FnMut(&[u8])) -> Result<u64> {
+        self.stdin.write_all(format!("{id}\n").as_bytes()).await?;
+        self.stdin.flush().await?;
+        let mut header = String::new();
+        self.stdout.read_line(&mut header).await?;
+        let size = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse::<u64>()?,
+            _ => anyhow::bail!("git cat-file can't read {id}: {}", header.trim_end()),
+        };
+        let mut contents = (&mut self.stdout).take(size);
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = contents.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            sink(&buffer[..read]);
+        }
+        anyhow::ensure!(contents.limit() == 0, "git cat-file stopped early");
+        /* Every object is followed by a newline */
+        self.stdout.read_exact(&mut [0]).await?;
+        Ok(size)
+    }
+}
+
+/// Fetch `revision` of `url` into a new repository
+async fn fetch_git(url: &str, revision: &str) -> Result<tempdir::TempDir> {
+    let directory = tempdir::TempDir::new("hash-git")?;
+    range::git(directory.path(), &["init", "--bare", "--quiet"]).await?;
+    let shallow = range::git(
+        directory.path(),
+        &["fetch", "--quiet", "--depth", "1", "--", url, revision],
+    )
+    .await;
+    if let Err(err) = shallow {
+        /* Not all servers hand out commits by their hash */
+        log::debug!("{err:#}");
+        range::git(
+            directory.path(),
+            &[
+                "fetch",
+                "--quiet",
+                "--",
+                url,
+                "+refs/heads/*:refs/heads/*",
+                "+refs/tags/*:refs/tags/*",
+            ],
+        )
+        .await?;
+    }
+    range::git(
+        directory.path(),
+        &["cat-file", "-e", &format!("{revision}^{{commit}}")],
+    )
+    .await
+    .with_context(|| format!("{url} has no commit {revision}"))?;
+    Ok(directory)
+}
+
+/// The files of `revision`, and the submodules in it as their paths and commits
+async fn git_tree(
+    directory: &Path,
+    repository: usize,
+    revision: &str,
+    cat_file: &mut CatFile,
+) -> Result<(Node<Blob>, Vec<(Vec<u8>, String)>)> {
+    let listing = range::git_output(
+        directory,
+        &["ls-tree", "-r", "-z", "--long", "--full-tree", revision],
+    )
+    .await?;
+    let mut root = Node::directory();
+    let mut submodules = Vec::new();
+    for entry in listing
+        .split(|&byte| byte == 0)
+        .filter(|entry| !entry.is_empty())
+    {
+        let tab = entry
+            .iter()
+            .position(|&byte| byte == b'\t')
+            .context("Invalid output of git ls-tree")?;
+        let (path, info) = (&entry[tab + 1..], std::str::from_utf8(&entry[..tab])?);
+        let [mode, _, id, size] = info.split_whitespace().collect::<Vec<_>>()[..] else {
+            anyhow::bail!("Invalid output of git ls-tree: {info}");
+        };
+        let node = match mode {
+            "100644" | "100755" => Node::File {
+                executable: mode == "100755",
+                size: size.parse()?,
+                contents: Blob {
+                    repository,
+                    id: id.to_owned(),
+                },
+            },
+            "120000" => {
+                let mut target = Vec::new();
+                cat_file
+                    .read(id, |piece| target.extend_from_slice(piece))
+                    .await?;
+                Node::Symlink { target }
+            },
+            /* Like a checkout without submodules, which has empty directories for them */
+            "160000" => {
+                submodules.push((path.to_vec(), id.to_owned()));
+                Node::directory()
+            },
+            _ => anyhow::bail!(
+                "Unexpected mode {mode} of {}",
+                String::from_utf8_lossy(path)
+            ),
+        };
+        root.insert(path, node)?;
+    }
+    Ok((root, submodules))
+}
+
+/// The URLs of the submodules in `revision` by their paths, as `.gitmodules` has them
+async fn submodule_urls(directory: &Path, revision: &str) -> Result<HashMap<Vec<u8>, String>> {
+    let config = range::git_output(
+        directory,
+        &[
+            "config",
+            "--blob",
+            &format!("{revision}:.gitmodules"),
+            "-z",
+            "--get-regexp",
+            r"^submodule\..*\.(path|url)$",
+        ],
+    )
+    .await
+    .context("Failed to read .gitmodules")?;
+    let mut paths = HashMap::new();
+    let mut urls = HashMap::new();
+    for entry in config
+        .split(|&byte| byte == 0)
+        .filter(|entry| !entry.is_empty())
+    {
+        let entry = String::from_utf8_lossy(entry);
+        let Some((key, value)) = entry.split_once('\n') else {
+            continue;
+        };
+        if let Some(name) = key.strip_suffix(".path") {
+            paths.insert(name.to_owned(), value.as_bytes().to_vec());
+        } else if let Some(name) = key.strip_suffix(".url") {
+            urls.insert(name.to_owned(), value.to_owned());
+        }
+    }
+    Ok(paths
+        .into_iter()
+        .filter_map(|(name, path)| Some((components(&path).ok()?.join(&b'/'), urls.remove(&name)?)))
+        .collect())
+}
+
+/// The URL of a submodule, which may be relative to the one of the repository it is in
+fn submodule_url(base: &str, url: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_owned();
+    }
+    let mut base = base.trim_end_matches('/');
+    let mut url = url;
+    loop {
+        if let Some(rest) = url.strip_prefix("./") {
+            url = rest;
+        } else if let Some(rest) = url.strip_prefix("../") {
+            url = rest;
+            base = base.rsplit_once('/').map_or(base, |(parent, _)| parent);
+        } else {
+            break;
+        }
+    }
+    format!("{base}/{url}")
+}
+
+/// The hash of a git revision, like `nix-prefetch-git` computes it
+pub async fn hash_git(url: &str, revision: &str, submodules: bool) -> Result<String> {
+    let mut root = Node::directory();
+    /* The repositories need to stay around until the files are hashed */
+    let mut repositories = Vec::new();
+    let mut pending = vec![(url.to_owned(), revision.to_owned(), Vec::new())];
+    while let Some((url, revision, prefix)) = pending.pop() {
+        let directory = fetch_git(&url, &revision).await?;
+        let mut cat_file = CatFile::start(directory.path())?;
+        let (tree, gitlinks) = git_tree(
+            directory.path(),
+            repositories.len(),
+            &revision,
+            &mut cat_file,
+        )
+        .await?;
+        if submodules && !gitlinks.is_empty() {
+            let urls = submodule_urls(directory.path(), &revision).await?;
+            for (path, commit) in gitlinks {
+                let submodule = urls.get(&components(&path)?.join(&b'/')).with_context(|| {
+                    format!(
+                        "The submodule {} of {url} is missing from .gitmodules",
+                        String::from_utf8_lossy(&path)
+                    )
+                })?;
+                pending.push((
+                    submodule_url(&url, submodule),
+                    commit,
+                    [&prefix[..], b"/", &path[..]].concat(),
+                ));
+            }
+        }
+        root.insert(&prefix, tree)?;
+        repositories.push((directory, cat_file));
+    }
+
+    let mut hasher = Sha256::new();
+    for piece in serialize(&root) {
+        match piece {
+            Piece::Bytes(bytes) => hasher.update(bytes),
+            Piece::Contents(blob, size) => {
+                let read = repositories[blob.repository]
+                    .1
+                    .read(&blob.id, |piece| hasher.update(piece))
+                    .await?;
+                anyhow::ensure!(read == size, "{} changed its size", blob.id);
+            },
+        }
+    }
+    Ok(sri(hasher))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The NAR of `root`, with the contents of the files being their names
+    fn nar(root: &Node<&'static str>) -> Vec<u8> {
+        serialize(root)
+            .into_iter()
+            .flat_map(|piece| match piece {
+                Piece::Bytes(bytes) => bytes,
+                Piece::Contents(contents, _) => contents.as_bytes().to_vec(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_serialize() {
+        let file = |contents: &'static str| Node::File {
+            executable: false,
+            size: contents.len() as u64,
+            contents,
+        };
+        let mut root = Node::directory();
+        root.insert(b"./foo.txt", file("hello")).unwrap();
+        root.insert(b"foo/bar", file("")).unwrap();
+        root.insert(b"foo/", Node::directory()).unwrap();
+        assert!(root.insert(b"foo/../../etc", file("")).is_err());
+
+        let mut expected = Vec::new();
+        for token in [
+            "nix-archive-1",
+            "(",
+            "type",
+            "directory",
+            /* `foo` comes before `foo.txt`, unlike in git */
+            "entry",
+            "(",
+            "name",
+            "foo",
+            "node",
+            "(",
+            "type",
+            "directory",
+            "entry",
+            "(",
+            "name",
+            "bar",
+            "node",
+            "(",
+            "type",
+            "regular",
+            "contents",
+            "",
+            ")",
+            ")",
+            ")",
+            ")",
+            "entry",
+            "(",
+            "name",
+            "foo.txt",
+            "node",
+            "(",
+            "type",
+            "regular",
+            "contents",
+            "hello",
+            ")",
+            ")",
+            ")",
+        ] {
+            string(&mut expected, token.as_bytes());
+        }
+        assert_eq!(nar(&root), expected);
+    }
+
+    /// A ustar header
+    fn header(name: &str, kind: u8, mode: &str, size: usize, link: &str) -> Vec<u8> {
+        let mut header = [0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..100 + mode.len()].copy_from_slice(mode.as_bytes());
+        header[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+        header[156] = kind;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..263].copy_from_slice(b"ustar\0");
+        /* Summed with the checksum itself being spaces */
+        header[148..156].fill(b' ');
+        let checksum = header.iter().map(|&byte| u32::from(byte)).sum::<u32>();
+        header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+        header.to_vec()
+    }
+
+    /// The contents of a file in a tarball, padded to whole blocks
+    fn block(contents: &[u8]) -> Vec<u8> {
+        let mut block = contents.to_vec();
+        block.resize(contents.len().div_ceil(512) * 512, 0);
+        block
+    }
+
+    #[test]
+    fn test_read_tar() {
+        let long_name = format!("source/{}", "x".repeat(120));
+        let pax = format!("{} path={long_name}\n", long_name.len() + 10);
+        let tarball = [
+            header("source/", b'5', "0000755", 0, ""),
+            header("source/run.sh", b'0', "0000755", 3, ""),
+            block(b"hi\n"),
+            header("source/link", b'2', "0000777", 0, "run.sh"),
+            header("source/copy", b'1', "0000644", 0, "source/run.sh"),
+            header("pax", b'x', "0000644", pax.len(), ""),
+            block(pax.as_bytes()),
+            header("ignored", b'0', "0000644", 0, ""),
+            vec![0; 1024],
+        ]
+        .concat();
+
+        let root = read_tar(&mut std::io::Cursor::new(tarball)).unwrap();
+        let script = Node::File {
+            executable: true,
+            size: 3,
+            contents: 1024,
+        };
+        let source = BTreeMap::from([
+            (b"run.sh".to_vec(), script),
+            (
+                b"link".to_vec(),
+                Node::Symlink {
+                    target: b"run.sh".to_vec(),
+                },
+            ),
+            (
+                b"copy".to_vec(),
+                Node::File {
+                    executable: true,
+                    size: 3,
+                    contents: 1024,
+                },
+            ),
+            (
+                "x".repeat(120).into_bytes(),
+                Node::File {
+                    executable: false,
+                    size: 0,
+                    contents: 4096,
+                },
+            ),
+        ]);
+        assert_eq!(
+            root,
+            Node::Directory(BTreeMap::from([(
+                b"source".to_vec(),
+                Node::Directory(source)
+            )]))
+        );
+    }
+
+    /// Needs `bsdtar`
+    #[tokio::test]
+    async fn test_hash_archive() {
+        let tarball = [
+            header("source/", b'5', "0000755", 0, ""),
+            header("source/README", b'0', "0000644", 6, ""),
+            block(b"hello\n"),
+            header("source/bin/", b'5', "0000755", 0, ""),
+            header("source/bin/run.sh", b'0', "0000755", 18, ""),
+            block(b"#!/bin/sh\necho hi\n"),
+            header("source/bin/run", b'2', "0000777", 0, "run.sh"),
+            header("source/share/doc/", b'5', "0000755", 0, ""),
+            header("source/share/doc/empty", b'0', "0000644", 0, ""),
+            vec![0; 1024],
+        ]
+        .concat();
+        let archive = tempdir::TempFile::new("source.tar").unwrap();
+        std::fs::write(archive.path(), tarball).unwrap();
+
+        /* What `nix-prefetch-url --unpack file://$PWD/source.tar` prints */
+        assert_eq!(
+            hash_archive(archive.path()).await.unwrap(),
+            "sha256-WM6qF6QovgJz5NL0a+KitYF5nKpWJ2uPzzSdfhnydzY="
+        );
+    }
+
+    #[test]
+    fn test_submodule_url() {
+        assert_eq!(
+            submodule_url("https://example.org/owner/repo.git", "../lib.git"),
+            "https://example.org/owner/lib.git"
+        );
+        assert_eq!(
+            submodule_url("https://example.org/owner/repo/", "./lib"),
+            "https://example.org/owner/repo/lib"
+        );
+        assert_eq!(
+            submodule_url("https://example.org/owner/repo", "https://example.com/lib"),
+            "https://example.com/lib"
+        );
+    }
+}
@@ -0,0 +1,46 @@
+# Generated by npins. Do not modify; will be overwritten regularly
+#
+# This stands in for the `sources.nix` of niv, so that Nix code written against niv keeps working
+# while migrating to npins. The pins come from @npins@, with the attributes that niv provides
+# (`rev`, `sha256`, `owner`, `repo`, `branch` and niv's `type`) added to them.
+let
+  pins = import @npins@;
+
+  # How niv would have fetched the source
+  nivType =
+    spec:
+    if spec.type == "Git" || spec.type == "GitRelease" then
+      (if spec.url or null != null && !spec.submodules then "tarball" else "git")
+    else if spec.type == "Channel" || spec.type == "Hydra" || spec.type == "Tarball" then
+      "tarball"
+    else if spec.unpack or false then
+      "tarball"
+    else
+      "file";
+
+  mkSource =
+    spec:
+    spec
+    // {
+      type = nivType spec;
+      rev = spec.revision or null;
+      sha256 = spec.hash or null;
+      url = spec.url or null;
+      owner = spec.repository.owner or null;
+      repo = spec.repository.repo or null;
+      branch = spec.branch or null;
+    };
+
+  # Like niv, this takes `pkgs` to fetch with, and ignores the other settings
+  mkSources =
+    {
+      pkgs ? null,
+      ...
+    }:
+    builtins.mapAttrs (
+      _: pin:
+      # Variants of pins without a pin of their own are not sources, niv has nothing like them
+      if pin ? type then mkSource (if pkgs == null then pin else pin { inherit pkgs; }) else pin
+    ) pins;
+in
+mkSources { } // { __functor = _: mkSources; }
@@ -1,37 +1,253 @@
-//! Convert+Import Niv files
+//! Convert+Import Niv files, and stand in for niv's `sources.nix` during migrations
 
 use crate::*;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::convert::TryFrom;
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    path::{Path, PathBuf},
+};
+use url::Url;
 
 /// Pin entry from Niv's sources.json
 ///
-/// We only take the minimum information required to get things working. This does not include
-/// the actual hashes, so an update must be performed afterwards.
+/// GitHub repositories, git repositories, tarballs and files are supported. The pinned revision
+/// and hash are taken over where Niv has them, so that importing doesn't change what is pinned.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NivPin {
-    branch: String,
+    /// How Niv fetches the entry: `tarball`, `file` or `git`
+    #[serde(rename = "type", default)]
+    niv_type: Option<String>,
+    branch: Option<String>,
     /// The GitHub repository owner. If not present, then `repo` contains the full git URL.
     owner: Option<String>,
     /// Might be a git URL. In that case, `owner` won't be present.
-    repo: String,
+    repo: Option<String>,
+    rev: Option<String>,
+    url: Option<Url>,
+    /// The hash in Nix's base32 format, of the unpacked contents for tarballs
+    sha256: Option<String>,
+}
+
+impl NivPin {
+    fn hash(&self) -> Result<Option<String>> {
+        self.sha256
+            .as_deref()
+            .map(|sha256| nix::hash_to_sri(sha256, "sha256"))
+            .transpose()
+            .context("Niv's hash is invalid")
+    }
 }
 
 impl TryFrom<NivPin> for Pin {
     type Error = anyhow::Error;
 
     fn try_from(niv: NivPin) -> Result<Self> {
-        Ok(match niv.owner {
-            None => {
-                git::GitPin::new(git::Repository::git(niv.repo.parse()?), niv.branch, false).into()
-            },
-            Some(owner) => git::GitPin::new(
-                git::Repository::github(&owner, &niv.repo),
-                niv.branch,
-                false,
-            )
-            .into(),
+        let hash = niv.hash()?;
+        let Some(repo) = niv.repo.clone() else {
+            /* Neither GitHub nor git, so a plain URL */
+            let url = niv
+                .url
+                .context("The entry has neither a repository nor a URL")?;
+            let hashes = hash.map(|hash| GenericHash {
+                hash,
+                archive: None,
+            });
+            return Ok(if niv.niv_type.as_deref() == Some("file") {
+                Pin::File {
+                    input: file::FilePin { url },
+                    version: Some(file::FileVersion {}),
+                    hashes,
+                    metadata: Default::default(),
+                }
+            } else {
+                Pin::Tarball {
+                    input: tarball::TarballPin::new(url),
+                    version: Some(tarball::LockedTarball {
+                        locked_url: None,
+                        resolved_url: None,
+                        etag: None,
+                        last_modified: None,
+                    }),
+                    hashes,
+                    metadata: Default::default(),
+                }
+            });
+        };
+
+        let branch = niv.branch.context("The entry has no branch")?;
+        let (repository, hashes) = match &niv.owner {
+            None => (git::Repository::git(repo.parse()?), None),
+            /* Niv fetches GitHub repositories as tarball, like we do */
+            Some(owner) => (
+                git::Repository::github(owner, &repo),
+                hash.zip(niv.url.clone())
+                    .filter(|_| niv.niv_type.as_deref() != Some("git"))
+                    .map(|(hash, url)| git::OptionalUrlHashes {
+                        url: Some(url),
+                        hash,
+                        requires_auth: false,
+                    }),
+            ),
+        };
+        let version = niv.rev.map(git::GitRevision::new).transpose()?;
+        Ok(Pin::Git {
+            input: git::GitPin::new(repository, branch, false),
+            hashes: hashes.filter(|_| version.is_some()),
+            version,
+            metadata: Default::default(),
+        })
+    }
+}
+
+/// Niv's `sources.json`
+pub struct NivSources {
+    entries: BTreeMap<String, serde_json::Value>,
+}
+
+impl NivSources {
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(NivSources {
+            entries: import::read_json(path, "sources.json")?,
         })
     }
 }
+
+#[async_trait::async_trait]
+impl import::Importer for NivSources {
+    fn source(&self) -> &str {
+        "sources.json"
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+
+    async fn import(&self, name: &str) -> Result<Option<Pin>> {
+        let pin: NivPin = serde_json::from_value(self.entries[name].clone())
+            .context("Pin is either invalid, or we don't support it")?;
+        Ok(Some(pin.try_into()?))
+    }
+}
+
+const SHIM: &str = include_str!("niv-sources.nix");
+
+/// `to` relative to the directory `from`, both given relative to the same directory or absolute
+fn relative_path(from: &Path, to: &Path) -> Result<PathBuf> {
+    let from = std::path::absolute(from)?;
+    let to = std::path::absolute(to)?;
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut path = PathBuf::new();
+    for _ in from.components().skip(common) {
+        path.push("..");
+    }
+    path.extend(to.components().skip(common));
+    Ok(path)
+}
+
+/// A replacement for niv's `sources.nix` at `shim`, which adapts the pins in `npins` to it
+pub fn shim(shim: &Path, npins: &Path) -> Result<String> {
+    let directory = shim
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let relative = relative_path(directory, npins)?;
+    /* Nix paths are separated by `/` on Windows too */
+    let relative = relative
+        .components()
+        .map(|component| match component {
+            std::path::Component::Normal(name) => name.to_str(),
+            std::path::Component::ParentDir => Some(".."),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .with_context(|| {
+            format!(
+                "The path from {} to the npins directory can't be used in Nix",
+                directory.display()
+            )
+        })?
+        .join("/");
+    /* Relative path literals in Nix need to start with `./` or `../` */
+    let path = if relative.starts_with("..") {
+        relative
+    } else {
+        format!("./{}", relative)
+    };
+    anyhow::ensure!(
+        !path.contains(|c: char| c.is_whitespace() || "\"'${}#;".contains(c)),
+        "The path {} can't be used as a Nix path literal",
+        path
+    );
+    Ok(SHIM.replace("@npins@", &path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_import() {
+        let import = |niv: serde_json::Value| -> Pin {
+            serde_json::from_value::<NivPin>(niv)
+                .unwrap()
+                .try_into()
+                .unwrap()
+        };
+        let zero_hash = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+        let pin = import(serde_json::json!({
+            "branch": "nixos-unstable",
+            "owner": "NixOS",
+            "repo": "nixpkgs",
+            "rev": "0123456789abcdef0123456789abcdef01234567",
+            "sha256": "0".repeat(52),
+            "type": "tarball",
+            "url": "https://github.com/NixOS/nixpkgs/archive/0123456789abcdef0123456789abcdef01234567.tar.gz",
+            "url_template": "https://github.com/<owner>/<repo>/archive/<rev>.tar.gz",
+        }));
+        let spec = serde_json::to_value(&pin).unwrap();
+        assert_eq!(spec["type"], "Git");
+        assert_eq!(spec["revision"], "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(spec["hash"], zero_hash);
+
+        /* Niv doesn't hash git checkouts */
+        let pin = import(serde_json::json!({
+            "branch": "main",
+            "repo": "https://example.org/tool.git",
+            "rev": "0123456789abcdef0123456789abcdef01234567",
+            "type": "git",
+        }));
+        assert!(pin.has_version() && !pin.has_hashes());
+
+        let pin = import(serde_json::json!({
+            "sha256": "0".repeat(52),
+            "type": "file",
+            "url": "https://example.org/data.json",
+        }));
+        let spec = serde_json::to_value(&pin).unwrap();
+        assert_eq!(spec["type"], "File");
+        assert_eq!(spec["hash"], zero_hash);
+    }
+
+    #[test]
+    fn test_shim() {
+        assert_eq!(
+            relative_path(Path::new("nix"), Path::new("npins")).unwrap(),
+            Path::new("../npins")
+        );
+        assert_eq!(
+            relative_path(Path::new("a/b"), Path::new("a/npins")).unwrap(),
+            Path::new("../npins")
+        );
+        let shim = shim(Path::new("sources.nix"), Path::new("npins")).unwrap();
+        assert!(shim.contains("pins = import ./npins;"));
+        let shim = super::shim(Path::new("nix/niv/sources.nix"), Path::new("npins")).unwrap();
+        assert!(shim.contains("pins = import ../../npins;"));
+    }
+}
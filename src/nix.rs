@@ -1,12 +1,84 @@
-use crate::{check_git_url, check_url};
+use crate::{check_git_url, check_url, http_get, nar, remote, tempdir, ThrottledSend};
 use anyhow::{Context, Result};
 use data_encoding::BASE64;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use url::Url;
+
+/// How the hashes of pins are computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hasher {
+    /// With the `nix-prefetch-*` tools, locally or [on another machine](crate::remote)
+    Nix,
+    /// In-process, see [`crate::nar`]. Supports git repositories, archives and single files
+    Builtin,
+}
+
+impl std::fmt::Display for Hasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Hasher::Nix => "nix",
+            Hasher::Builtin => "builtin",
+        })
+    }
+}
+
+impl std::str::FromStr for Hasher {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "nix" => Ok(Hasher::Nix),
+            "builtin" => Ok(Hasher::Builtin),
+            _ => anyhow::bail!("Unknown hasher '{s}', expected nix or builtin"),
+        }
+    }
+}
+
+static HASHER: OnceLock<Hasher> = OnceLock::new();
+
+/// Compute the hashes with `hasher` from now on, must be called before any prefetching
+pub fn set_hasher(hasher: Hasher) {
+    HASHER.set(hasher).expect("The hasher is already set");
+}
+
+/// How the hashes are computed: with Nix if it is there, or the prefetches happen elsewhere
+pub fn hasher() -> Hasher {
+    *HASHER.get_or_init(|| {
+        if remote::is_set() || on_path("nix-prefetch-url") {
+            Hasher::Nix
+        } else {
+            log::debug!("Nix is not installed, computing the hashes in-process");
+            Hasher::Builtin
+        }
+    })
+}
+
+/// Whether `program` can be found in `PATH`
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|directory| {
+            let path = directory.join(program);
+            path.is_file() || (cfg!(windows) && path.with_extension("exe").is_file())
+        })
+    })
+}
+
+/// Fail unless the hashes are computed with Nix, for the kinds of pins only it can hash
+fn require_nix(what: &str) -> Result<()> {
+    anyhow::ensure!(
+        hasher() == Hasher::Nix,
+        "{what} can only be hashed with Nix, which is not installed. Use --prefetch-on to hash them on another machine"
+    );
+    Ok(())
+}
 
-#[allow(unused)]
 pub struct PrefetchInfo {
-    store_path: String,
-    hash: String,
+    /// Unless the prefetch happened on another machine, see [`crate::remote`]
+    pub store_path: Option<PathBuf>,
+    pub hash: String,
 }
 
 pub fn hash_to_sri(s: &str, algo: &str) -> Result<String> {
@@ -19,38 +91,310 @@ pub fn hash_to_sri(s: &str, algo: &str) -> Result<String> {
     ))
 }
 
+/// The kinds of archives that can be unpacked, serialized as their file extension
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArchiveFormat {
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.xz")]
+    TarXz,
+    #[serde(rename = "tar.bz2")]
+    TarBz2,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The file extension, which is what `fetchzip` picks the unpacker by
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarXz => "tar.xz",
+            ArchiveFormat::TarBz2 => "tar.bz2",
+            ArchiveFormat::TarZst => "tar.zst",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+
+    /// Recognize an archive by the file name at the end of an URL path
+    pub fn from_path(path: &str) -> Option<Self> {
+        let name = path.rsplit('/').next()?.to_lowercase();
+        [
+            (".tar", ArchiveFormat::Tar),
+            (".tar.gz", ArchiveFormat::TarGz),
+            (".tgz", ArchiveFormat::TarGz),
+            (".tar.xz", ArchiveFormat::TarXz),
+            (".txz", ArchiveFormat::TarXz),
+            (".tar.bz2", ArchiveFormat::TarBz2),
+            (".tbz", ArchiveFormat::TarBz2),
+            (".tbz2", ArchiveFormat::TarBz2),
+            (".tar.zst", ArchiveFormat::TarZst),
+            (".tzst", ArchiveFormat::TarZst),
+            (".zip", ArchiveFormat::Zip),
+        ]
+        .into_iter()
+        .find_map(|(suffix, format)| name.ends_with(suffix).then_some(format))
+    }
+
+    /// Recognize an archive by the magic bytes at its start
+    ///
+    /// Compressed streams are assumed to contain a tarball. Tar headers have their magic at
+    /// offset 257, so this needs the first 512 bytes to recognize uncompressed ones.
+    pub fn sniff(head: &[u8]) -> Option<Self> {
+        if head.starts_with(b"PK\x03\x04") {
+            Some(ArchiveFormat::Zip)
+        } else if head.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if head.starts_with(b"\xfd7zXZ\x00") {
+            Some(ArchiveFormat::TarXz)
+        } else if head.starts_with(b"BZh") {
+            Some(ArchiveFormat::TarBz2)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(ArchiveFormat::TarZst)
+        } else if head.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Find out the format of an archive, unless its URL already tells
+///
+/// `pkgs.fetchzip` picks the unpacker by the file extension of the URL, which download APIs often
+/// don't have. Pins record the format for those, so that the right unpacker can be passed in. Only
+/// the start of the file is downloaded, with the same headers as the actual download would use.
+pub async fn archive_format(
+    url: &Url,
+    headers: &[(&'static str, String)],
+) -> Result<Option<ArchiveFormat>> {
+    if ArchiveFormat::from_path(url.path()).is_some() {
+        return Ok(None);
+    }
+
+    crate::provenance::record_endpoint(url);
+    let mut request = http_get(url.clone())?.header(reqwest::header::RANGE, "bytes=0-511");
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let mut response = request
+        .send_throttled()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("Failed to download {}", url))?;
+    /* Servers may ignore the range, so stop reading on our own */
+    let mut head = Vec::new();
+    while head.len() < 512 {
+        match response.chunk().await? {
+            Some(chunk) => head.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    match ArchiveFormat::sniff(&head) {
+        Some(format) => Ok(Some(format)),
+        None => anyhow::bail!("{} doesn't look like an archive that can be unpacked", url),
+    }
+}
+
+/// Whether downloading `url` needs extra headers from `NPINS_HEADERS_FILE`, see [`crate::headers`]
+fn needs_headers(url: &str) -> Option<Url> {
+    let url: Url = url.parse().ok()?;
+    (!crate::headers::for_url(&url).is_empty()).then_some(url)
+}
+
 pub async fn nix_prefetch_tarball(url: impl AsRef<str>) -> Result<String> {
+    Ok(nix_prefetch_tarball_info(url).await?.hash)
+}
+
+/// Like [`nix_prefetch_tarball`], but also tell where the unpacked contents are in the store
+pub async fn nix_prefetch_tarball_info(url: impl AsRef<str>) -> Result<PrefetchInfo> {
     let url = url.as_ref();
-    let result = async {
-        log::debug!(
-            "Executing `nix-prefetch-url --unpack --name source --type sha256 {}`",
-            url
-        );
-        let output = tokio::process::Command::new("nix-prefetch-url")
-            .arg("--unpack") // force calculation of the unpacked NAR hash
-            .arg("--name")
-            .arg("source") // use the same symbolic store path name as `builtins.fetchTarball` to avoid downloading the source twice
-            .arg("--type")
-            .arg("sha256")
-            .arg(url)
-            .output()
-            .await
-            .with_context(|| format!("Failed to spawn nix-prefetch-url for {}", url))?;
+    if let Some(url) = needs_headers(url) {
+        return prefetch_downloaded_tarball(&url, &[]).await;
+    }
+    if hasher() == Hasher::Builtin {
+        return prefetch_downloaded_tarball(&url.parse()?, &[]).await;
+    }
+    crate::provenance::record_endpoint(url);
+    check_url(prefetch_tarball(url).await, url).await
+}
 
-        // FIXME: handle errors and pipe stderr through
-        if !output.status.success() {
-            return Err(anyhow::anyhow!(format!(
-                "failed to prefetch url: {}\n{}",
-                url,
-                String::from_utf8_lossy(&output.stderr)
-            )));
-        }
+/// Prefetch a tarball that can only be downloaded with credentials
+///
+/// The download happens in-process with the given headers (and those configured in
+/// `NPINS_HEADERS_FILE`), and Nix then only gets to see a local copy of it. This way the
+/// credentials never have to be part of an URL, where they would end up in the lock file and in
+/// world-readable store paths. The resulting hash is the same as the one [`nix_prefetch_tarball`]
+/// would give for `url`.
+pub async fn nix_prefetch_tarball_authenticated(
+    url: &Url,
+    headers: &[(&'static str, String)],
+) -> Result<String> {
+    Ok(prefetch_downloaded_tarball(url, headers).await?.hash)
+}
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        log::debug!("Got hash: {}", stdout);
-        hash_to_sri(&stdout.trim(), "sha256")
-    };
-    check_url(result.await, url).await
+async fn prefetch_downloaded_tarball(
+    url: &Url,
+    headers: &[(&'static str, String)],
+) -> Result<PrefetchInfo> {
+    let file = download(url, headers).await?;
+    if hasher() == Hasher::Builtin {
+        return Ok(PrefetchInfo {
+            store_path: None,
+            hash: nar::hash_archive(file.path()).await?,
+        });
+    }
+    let local_url = Url::from_file_path(file.path())
+        .map_err(|()| anyhow::format_err!("Invalid path {}", file.path().display()))?;
+    prefetch_tarball(local_url.as_str()).await
+}
+
+/// Download an URL in-process to a temporary file
+async fn download(url: &Url, headers: &[(&'static str, String)]) -> Result<tempdir::TempFile> {
+    log::debug!("Downloading {}", url);
+    crate::provenance::record_endpoint(url);
+    let mut request = http_get(url.clone())?;
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let response = request
+        .send_throttled()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("Failed to download {}", url))?;
+    if let Some(size) = response.content_length() {
+        /* Once for the download, and about as much again when Nix unpacks it */
+        tempdir::ensure_space(size.saturating_mul(2), format_args!("Downloading {url}"))?;
+    }
+    let contents = response.bytes().await?;
+
+    let file = tempdir::TempFile::new(&format!(
+        "download.{}",
+        ArchiveFormat::sniff(&contents).map_or("download", ArchiveFormat::extension),
+    ))?;
+    std::fs::write(file.path(), &contents)
+        .with_context(|| format!("Failed to write {}", file.path().display()))?;
+    Ok(file)
+}
+
+async fn prefetch_tarball(url: &str) -> Result<PrefetchInfo> {
+    log::debug!(
+        "Executing `nix-prefetch-url --unpack --print-path --name source --type sha256 {}`",
+        url
+    );
+    let mut command = remote::Command::new("nix-prefetch-url");
+    if url.starts_with("file:") {
+        command.local();
+    }
+    let output = command
+        .arg("--unpack") // force calculation of the unpacked NAR hash
+        .arg("--print-path")
+        .arg("--name")
+        .arg("source") // use the same symbolic store path name as `builtins.fetchTarball` to avoid downloading the source twice
+        .arg("--type")
+        .arg("sha256")
+        .arg(url)
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn nix-prefetch-url for {}", url))?;
+
+    // FIXME: handle errors and pipe stderr through
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(format!(
+            "failed to prefetch url: {}\n{}",
+            url,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    log::debug!("Got hash and path: {}", stdout);
+    let (hash, store_path) = stdout
+        .trim()
+        .split_once('\n')
+        .context("nix-prefetch-url didn't print the store path")?;
+    Ok(PrefetchInfo {
+        store_path: (!command.is_remote()).then(|| store_path.into()),
+        hash: hash_to_sri(hash, "sha256")?,
+    })
+}
+
+/// Prefetch a single file, as opposed to [`nix_prefetch_tarball`] which unpacks it first.
+///
+/// The resulting hash matches what `builtins.fetchurl` and `pkgs.fetchurl` expect.
+pub async fn nix_prefetch_url(url: impl AsRef<str>) -> Result<String> {
+    let url = url.as_ref();
+    if let Some(url) = needs_headers(url) {
+        return nix_prefetch_url_authenticated(&url, &[]).await;
+    }
+    if hasher() == Hasher::Builtin {
+        return nix_prefetch_url_authenticated(&url.parse()?, &[]).await;
+    }
+    crate::provenance::record_endpoint(url);
+    check_url(prefetch_file(url, None).await, url).await
+}
+
+/// Prefetch a single file that can only be downloaded with credentials
+///
+/// Like [`nix_prefetch_tarball_authenticated`], the download happens in-process.
+pub async fn nix_prefetch_url_authenticated(
+    url: &Url,
+    headers: &[(&'static str, String)],
+) -> Result<String> {
+    let file = download(url, headers).await?;
+    if hasher() == Hasher::Builtin {
+        return nar::hash_file(file.path());
+    }
+    let local_url = Url::from_file_path(file.path())
+        .map_err(|()| anyhow::format_err!("Invalid path {}", file.path().display()))?;
+    /* Use the name `builtins.fetchurl` would give it, so that it finds the store path */
+    prefetch_file(local_url.as_str(), store_name(url.as_str())).await
+}
+
+/// The store path name Nix derives from an URL (`baseNameOf`), if it is a valid one
+fn store_name(url: &str) -> Option<&str> {
+    let name = url.rsplit('/').next()?;
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-._?=".contains(c));
+    valid.then_some(name)
+}
+
+async fn prefetch_file(url: &str, name: Option<&str>) -> Result<String> {
+    log::debug!("Executing `nix-prefetch-url --type sha256 {}`", url);
+    let mut command = remote::Command::new("nix-prefetch-url");
+    if url.starts_with("file:") {
+        command.local();
+    }
+    if let Some(name) = name {
+        command.arg("--name").arg(name);
+    }
+    let output = command
+        .arg("--type")
+        .arg("sha256")
+        .arg(url)
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn nix-prefetch-url for {}", url))?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(format!(
+            "failed to prefetch url: {}\n{}",
+            url,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    log::debug!("Got hash: {}", stdout);
+    hash_to_sri(stdout.trim(), "sha256")
 }
 
 pub async fn nix_prefetch_git(
@@ -59,8 +403,12 @@ pub async fn nix_prefetch_git(
     submodules: bool,
 ) -> Result<String> {
     let url = url.as_ref();
+    crate::provenance::record_endpoint(url);
 
     let result = async {
+        if hasher() == Hasher::Builtin {
+            return nar::hash_git(url, git_ref.as_ref(), submodules).await;
+        }
         log::debug!(
             "Executing: `nix-prefetch-git {}{} {}`",
             if submodules {
@@ -71,7 +419,7 @@ pub async fn nix_prefetch_git(
             url,
             git_ref.as_ref()
         );
-        let mut output = tokio::process::Command::new("nix-prefetch-git");
+        let mut output = remote::Command::new("nix-prefetch-git");
         if submodules {
             output.arg("--fetch-submodules");
         }
@@ -127,6 +475,151 @@ pub async fn nix_prefetch_git(
     check_git_url(result.await, url).await
 }
 
+/// Prefetch a container image like `dockerTools.pullImage` would
+///
+/// The tag is baked into the resulting archive, so it must match `finalImageTag`.
+pub async fn nix_prefetch_docker(
+    image_name: &str,
+    image_digest: &str,
+    final_image_tag: &str,
+    os: &str,
+    arch: &str,
+) -> Result<String> {
+    require_nix("Container images")?;
+    log::debug!(
+        "Executing `nix-prefetch-docker --json --quiet --os {} --arch {} --image-name {} --image-digest {} --final-image-tag {}`",
+        os,
+        arch,
+        image_name,
+        image_digest,
+        final_image_tag
+    );
+    let output = remote::Command::new("nix-prefetch-docker")
+        .arg("--json")
+        .arg("--quiet")
+        .args(["--os", os, "--arch", arch])
+        .args(["--image-name", image_name])
+        .args(["--image-digest", image_digest])
+        .args(["--final-image-tag", final_image_tag])
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn nix-prefetch-docker for {}", image_name))?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(format!(
+            "failed to prefetch image: {}@{}\n{}",
+            image_name,
+            image_digest,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    /* Older versions only output `sha256` (in base32), newer ones `hash` (in SRI format) */
+    #[derive(Debug, serde::Deserialize)]
+    struct NixPrefetchDockerResponse {
+        hash: Option<String>,
+        sha256: Option<String>,
+    }
+
+    log::debug!(
+        "nix-prefetch-docker output: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    let info: NixPrefetchDockerResponse = serde_json::from_slice(&output.stdout)
+        .context("Failed to deserialize nix-prefetch-docker JSON response.")?;
+    match (info.hash, info.sha256) {
+        (Some(hash), _) => Ok(hash),
+        (None, Some(sha256)) => hash_to_sri(&sha256, "sha256"),
+        (None, None) => anyhow::bail!("nix-prefetch-docker did not output a hash"),
+    }
+}
+
+/// Prefetch a Subversion repository at some revision like `fetchsvn` would
+pub async fn nix_prefetch_svn(url: &Url, revision: u64) -> Result<String> {
+    require_nix("Subversion checkouts")?;
+    crate::provenance::record_endpoint(url.as_str());
+    log::debug!("Executing `nix-prefetch-svn {} {}`", url, revision);
+    let output = remote::Command::new("nix-prefetch-svn")
+        .arg(url.as_str())
+        .arg(revision.to_string())
+        .env("QUIET", "1")
+        .output()
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to spawn nix-prefetch-svn for {} @ r{}",
+                url, revision
+            )
+        })?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(format!(
+            "failed to prefetch url: {} @ r{}\n{}",
+            url,
+            revision,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    /* The hash comes first, followed by the store path if `PRINT_PATH` is set */
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    log::debug!("nix-prefetch-svn output: {}", stdout);
+    let hash = stdout
+        .lines()
+        .next()
+        .context("nix-prefetch-svn did not output a hash")?;
+    hash_to_sri(hash.trim(), "sha256")
+}
+
+/// What `nix flake metadata --json` says about a flake, as far as we are interested in it
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct FlakeMetadata {
+    /// The locked flake reference, including the `narHash`
+    pub url: String,
+    #[serde(rename = "lastModified")]
+    pub last_modified: Option<u64>,
+    /// Only for inputs that come from a git repository
+    pub revision: Option<String>,
+    pub locked: FlakeMetadataLocked,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct FlakeMetadataLocked {
+    #[serde(rename = "narHash")]
+    pub nar_hash: String,
+}
+
+/// Resolve and lock a flake reference, e.g. `github:owner/repo/branch`
+///
+/// With `refresh`, cached flake references are looked up again.
+pub async fn nix_flake_metadata(flake_ref: &str, refresh: bool) -> Result<FlakeMetadata> {
+    log::debug!("Executing `nix flake metadata --json {}`", flake_ref);
+    let mut command = tokio::process::Command::new("nix");
+    command
+        .kill_on_drop(true)
+        .args(["--extra-experimental-features", "nix-command flakes"])
+        .args(["flake", "metadata", "--json", "--no-write-lock-file"]);
+    if refresh {
+        command.arg("--refresh");
+    }
+    let output = command
+        .arg(flake_ref)
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn nix flake metadata for {}", flake_ref))?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(format!(
+            "failed to lock flake: {}\n{}",
+            flake_ref,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    serde_json::from_slice(&output.stdout)
+        .context("Failed to deserialize nix flake metadata JSON response.")
+}
+
 pub async fn nix_eval_pin(lockfile_path: &Path, pin: &str) -> Result<std::path::PathBuf> {
     const DEFAULT_NIX: &'static str = include_str!("default.nix");
 
@@ -153,6 +646,7 @@ pub async fn nix_eval_pin(lockfile_path: &Path, pin: &str) -> Result<std::path::
         "Executing: `nix-instantiate --eval --json --expr '{{pin}}: (import default.nix).${{pin}}.outPath' --argstr pin '{pin}' --argstr path '{{«snip»}}'`",
     );
     let output = tokio::process::Command::new("nix-instantiate")
+        .kill_on_drop(true)
         .arg("--show-trace")
         .arg("--eval")
         .arg("--json")
@@ -182,3 +676,212 @@ pub async fn nix_eval_pin(lockfile_path: &Path, pin: &str) -> Result<std::path::
     serde_json::from_slice::<std::path::PathBuf>(&output.stdout)
         .context("Failed to deserialize nix-instantiate JSON response.")
 }
+
+/// Evaluate the `outPath` of all pins with the given `default.nix`, like a project using them would
+///
+/// This fetches the pins, unless they are in the store already. Pins that need Nixpkgs are
+/// evaluated with `<nixpkgs>` if it is in `NIX_PATH`, or else skipped and returned as `None`.
+/// Aliases are included, and pins missing from the evaluation are left out.
+pub async fn nix_eval_pins(
+    default_nix: &str,
+    lockfile_path: &Path,
+) -> Result<BTreeMap<String, Option<PathBuf>>> {
+    let lockfile_path = lockfile_path.canonicalize()?;
+    let lockfile_path = lockfile_path
+        .to_str()
+        .context("Lockfile path must be UTF-8")?;
+
+    /* See `nix_eval_pin` for why the code is inlined and the path passed as string */
+    let nix_eval_code = format!(
+        r#"{{path}}:
+let
+  data = builtins.fromJSON (builtins.readFile (/. + path));
+  pins = ({default_nix}) {{ input = /. + path; }};
+  hasNixpkgs = builtins.any (entry: entry.prefix == "nixpkgs") builtins.nixPath;
+  pkgs = if hasNixpkgs then import <nixpkgs> {{ }} else null;
+  needsPkgs = pin: builtins.elem pin.type [ "Svn" "Container" ];
+  outPath =
+    pin:
+    if !needsPkgs pin then
+      pin.outPath
+    else if pkgs != null then
+      (pin {{ inherit pkgs; }}).outPath
+    else
+      null;
+  # Variants like `postgres.v15` are nested, and missing attributes are left out
+  attrPath =
+    name: if data.pins.${{name}}.variant or false then builtins.match "([^.]*)\.(.*)" name else [ name ];
+  lookup = builtins.foldl' (set: attr: if set != null && set ? ${{attr}} then set.${{attr}} else null);
+  aliases = builtins.concatMap (
+    name: data.pins.${{name}}.aliases or [ ] ++ data.pins.${{name}}.other_names or [ ]
+  ) (builtins.attrNames data.pins);
+in
+builtins.listToAttrs (
+  builtins.concatMap (
+    name:
+    let
+      pin = lookup pins (if data.pins ? ${{name}} then attrPath name else [ name ]);
+    in
+    if pin == null then [ ] else [ {{ inherit name; value = outPath pin; }} ]
+  ) (builtins.attrNames data.pins ++ aliases)
+)"#
+    );
+
+    log::debug!(
+        "Executing: `nix-instantiate --eval --strict --json --expr '{{path}}: «outPath of all pins»' --argstr path '{lockfile_path}'`",
+    );
+    let output = tokio::process::Command::new("nix-instantiate")
+        .kill_on_drop(true)
+        .arg("--eval")
+        .arg("--strict")
+        .arg("--json")
+        .arg("--expr")
+        .arg(nix_eval_code)
+        .arg("--argstr")
+        .arg("path")
+        .arg(lockfile_path)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .context("Failed to spawn `nix-instantiate`")?
+        .wait_with_output()
+        .await
+        .context("Failed to spawn `nix-instantiate`")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to evaluate the pins\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    serde_json::from_slice(&output.stdout)
+        .context("Failed to deserialize nix-instantiate JSON response.")
+}
+
+/// Problems with the attributes of an evaluation by [`nix_eval_pins`]: pins and aliases that are
+/// missing, or that collide
+pub fn evaluation_problems(
+    pins: &crate::NixPins,
+    evaluated: &BTreeMap<String, Option<PathBuf>>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (name, pin) in &pins.pins {
+        if !evaluated.contains_key(name) {
+            problems.push(format!(
+                "The pin '{name}' is missing from the evaluated pins"
+            ));
+        }
+        let metadata = pin.metadata();
+        for alias in metadata.aliases.iter().chain(&metadata.other_names) {
+            if pins.pins.contains_key(alias) {
+                problems.push(format!(
+                    "The alias '{alias}' of '{name}' is shadowed by the pin of the same name"
+                ));
+            } else if let Some(other) = pins.aliased(alias).filter(|other| other != name) {
+                problems.push(format!(
+                    "The alias '{alias}' of '{name}' is also an alias of '{other}'"
+                ));
+            } else if !evaluated.contains_key(alias) {
+                problems.push(format!(
+                    "The alias '{alias}' of '{name}' is missing from the evaluated pins, `npins upgrade` updates `default.nix`"
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// Run a Nix command that prints a single line, e.g. a path or a hash
+async fn nix_output_line(program: &str, args: &[&str]) -> Result<String> {
+    log::debug!("Executing `{} {}`", program, args.join(" "));
+    let output = tokio::process::Command::new(program)
+        .kill_on_drop(true)
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn `{program}`"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`{} {}` failed\n{}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+}
+
+/// The store path that the artifact has once fetched, and whether it is in the store already
+pub async fn store_path(artifact: &crate::explain::StoreArtifact) -> Result<(PathBuf, bool)> {
+    let hash = hash_to_sri(&artifact.hash, "sha256")?;
+    let mut args = vec!["--print-fixed-path"];
+    if artifact.recursive {
+        args.push("--recursive");
+    }
+    args.extend(["sha256", &hash, &artifact.name]);
+    let path = PathBuf::from(nix_output_line("nix-store", &args).await?);
+    let present = path.exists();
+    Ok((path, present))
+}
+
+/// Hash the contents of a store path again, as SRI hash
+pub async fn store_path_hash(path: &Path, recursive: bool) -> Result<String> {
+    let path = path.to_str().context("Store paths are UTF-8")?;
+    let mut args = vec!["--type", "sha256", "--sri"];
+    if !recursive {
+        args.push("--flat");
+    }
+    args.push(path);
+    nix_output_line("nix-hash", &args).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_archive_format() {
+        assert_eq!(
+            ArchiveFormat::from_path("/releases/tool-1.0.TGZ"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::from_path("/a.zip/download"), None);
+        assert_eq!(
+            ArchiveFormat::sniff(b"\xfd7zXZ\x00\x00"),
+            Some(ArchiveFormat::TarXz)
+        );
+        assert_eq!(ArchiveFormat::sniff(b"{}"), None);
+        assert_eq!(
+            serde_json::to_value(ArchiveFormat::TarZst).unwrap(),
+            serde_json::json!("tar.zst")
+        );
+    }
+
+    #[test]
+    fn test_evaluation_problems() {
+        let mut pins = crate::NixPins::new_with_nixpkgs();
+        pins.rename("nixpkgs", "pkgs", true).unwrap();
+        let evaluated = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| (name.to_string(), None))
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert!(evaluation_problems(&pins, &evaluated(&["pkgs", "nixpkgs"])).is_empty());
+        assert_eq!(
+            evaluation_problems(&pins, &evaluated(&["pkgs"])).len(),
+            1,
+            "an outdated default.nix doesn't know aliases"
+        );
+
+        pins.pins.insert(
+            "nixpkgs".into(),
+            crate::channel::Pin::new("nixos-unstable").into(),
+        );
+        assert_eq!(
+            evaluation_problems(&pins, &evaluated(&["pkgs", "nixpkgs"])),
+            ["The alias 'nixpkgs' of 'pkgs' is shadowed by the pin of the same name"]
+        );
+    }
+}
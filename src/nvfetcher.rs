@@ -0,0 +1,304 @@
+//! Import nvfetcher's `nvfetcher.toml` and the `_sources/generated.json` it writes
+//!
+//! The configuration says what to track (`src.*`) and how to fetch it (`fetch.*`), the generated
+//! file holds what nvfetcher last found: the version and the hash of the source. Only the
+//! combinations that have a pin type doing the same are supported:
+//!
+//! - `src.git` fetched from git or GitHub becomes a git pin following the branch
+//! - `src.github` and `src.github_tag` fetched from git or GitHub become a release pin
+//! - `src.pypi` fetched from PyPI becomes a PyPI pin
+//! - anything fetched with `fetch.url` or `fetch.tarball` becomes a file or tarball pin for the
+//!   URL nvfetcher last fetched, as npins can't substitute the version into URLs
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+use url::Url;
+
+/// An entry of `nvfetcher.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Entry {
+    /// How to find the latest version, e.g. `github = "owner/repo"`
+    #[serde(default)]
+    src: BTreeMap<String, toml::Value>,
+    /// How to fetch a version, e.g. `url = "https://example.org/tool-$ver.tar.gz"`
+    #[serde(default)]
+    fetch: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    git: GitOptions,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct GitOptions {
+    #[serde(rename = "fetchSubmodules", default)]
+    fetch_submodules: bool,
+}
+
+/// An entry of `generated.json`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Generated {
+    version: String,
+    src: GeneratedSource,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GeneratedSource {
+    url: Option<Url>,
+    rev: Option<String>,
+    /// In SRI format in newer versions of nvfetcher, in Nix's base32 format in older ones
+    sha256: String,
+}
+
+impl Entry {
+    fn option(options: &BTreeMap<String, toml::Value>, key: &str) -> Result<Option<String>> {
+        options
+            .get(key)
+            .map(|value| {
+                value
+                    .as_str()
+                    .map(str::to_owned)
+                    .with_context(|| format!("`{key}` is not a string"))
+            })
+            .transpose()
+    }
+
+    fn src(&self, key: &str) -> Result<Option<String>> {
+        Self::option(&self.src, key)
+    }
+
+    fn fetch(&self, key: &str) -> Result<Option<String>> {
+        Self::option(&self.fetch, key)
+    }
+
+    /// The repository of the git fetchers
+    fn repository(&self) -> Result<Option<git::Repository>> {
+        if let Some(github) = self.fetch("github")? {
+            let (owner, repo) = github
+                .split_once('/')
+                .with_context(|| format!("`fetch.github = \"{github}\"` is not owner/repo"))?;
+            return Ok(Some(git::Repository::github(owner, repo)));
+        }
+        self.fetch("git")?
+            .map(|url| Ok(git::Repository::git(url.parse()?)))
+            .transpose()
+    }
+
+    /// The pin for the entry, locked to the `generated` version if there is one
+    pub async fn to_pin(&self, generated: Option<&Generated>) -> Result<Pin> {
+        let hash = generated
+            .map(|generated| import::sri_hash(&generated.src.sha256))
+            .transpose()?;
+
+        if let Some(repository) = self.repository()? {
+            let submodules = self.git.fetch_submodules;
+            if let Some(url) = self.src("git")? {
+                let branch = match self.src("branch")? {
+                    Some(branch) => branch,
+                    None => git::fetch_default_branch(&url.parse()?).await?,
+                };
+                let input = git::GitPin::new(repository, branch, submodules);
+                let revision = generated.and_then(|generated| generated.src.rev.clone());
+                return match revision.zip(hash) {
+                    Some((revision, hash)) => import::locked_git_pin(input, revision, hash),
+                    None => Ok(Pin::Git {
+                        input,
+                        version: None,
+                        hashes: None,
+                        metadata: Default::default(),
+                    }),
+                };
+            }
+            anyhow::ensure!(
+                self.src.contains_key("github") || self.src.contains_key("github_tag"),
+                "Only `src.git`, `src.github` and `src.github_tag` can be imported for git fetchers"
+            );
+            /* The generated revision is the tag, the commit's hash is fetched later */
+            return Ok(Pin::GitRelease {
+                input: git::GitReleasePin::new(repository, false, None, None, submodules),
+                version: generated
+                    .map(|generated| git::ReleaseVersion::new(generated.version.clone())),
+                hashes: None,
+                metadata: Default::default(),
+            });
+        }
+
+        if let Some(name) = self.fetch("pypi")? {
+            anyhow::ensure!(
+                self.src.contains_key("pypi"),
+                "PyPI packages can only be imported if their version comes from PyPI"
+            );
+            return Ok(Pin::PyPi {
+                input: pypi::Pin {
+                    name,
+                    version_upper_bound: None,
+                    wheel: None,
+                    only_versions: Vec::new(),
+                    exclude_versions: Vec::new(),
+                },
+                version: generated.map(|generated| GenericVersion {
+                    version: generated.version.clone(),
+                }),
+                hashes: None,
+                metadata: Default::default(),
+            });
+        }
+
+        let tarball = self.fetch.contains_key("tarball");
+        anyhow::ensure!(
+            tarball || self.fetch.contains_key("url"),
+            "Unsupported fetcher {}",
+            self.fetch.keys().next().map_or("(none)", String::as_str)
+        );
+        let url = generated
+            .and_then(|generated| generated.src.url.clone())
+            .context("The URL has not been generated yet, run nvfetcher first")?;
+        let hashes = hash.map(|hash| GenericHash {
+            hash,
+            archive: None,
+        });
+        Ok(if tarball {
+            Pin::Tarball {
+                input: tarball::TarballPin::new(url),
+                version: Some(tarball::LockedTarball {
+                    locked_url: None,
+                    resolved_url: None,
+                    etag: None,
+                    last_modified: None,
+                }),
+                hashes,
+                metadata: Default::default(),
+            }
+        } else {
+            Pin::File {
+                input: file::FilePin { url },
+                version: Some(file::FileVersion {}),
+                hashes,
+                metadata: Default::default(),
+            }
+        })
+    }
+}
+
+/// An nvfetcher configuration together with its generated sources
+pub struct Nvfetcher {
+    entries: BTreeMap<String, Entry>,
+    generated: BTreeMap<String, Generated>,
+}
+
+impl Nvfetcher {
+    /// Read the configuration, and the generated sources if nvfetcher has run already
+    pub fn read(config: &Path, generated: &Path) -> Result<Self> {
+        let entries = std::fs::read_to_string(config)
+            .with_context(|| format!("Could not open nvfetcher.toml at '{}'", config.display()))?;
+        let entries = toml::from_str(&entries).context("nvfetcher.toml is not valid")?;
+        let generated = if generated.exists() {
+            import::read_json(generated, "generated.json")?
+        } else {
+            log::warn!(
+                "{} doesn't exist, all pins will be updated to the latest versions",
+                generated.display()
+            );
+            BTreeMap::new()
+        };
+        Ok(Nvfetcher { entries, generated })
+    }
+}
+
+#[async_trait::async_trait]
+impl import::Importer for Nvfetcher {
+    fn source(&self) -> &str {
+        "nvfetcher.toml"
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+
+    async fn import(&self, name: &str) -> Result<Option<Pin>> {
+        Ok(Some(
+            self.entries[name].to_pin(self.generated.get(name)).await?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_to_pin() {
+        let entries: BTreeMap<String, Entry> = toml::from_str(
+            r#"
+            [tool]
+            src.git = "https://github.com/owner/tool"
+            src.branch = "main"
+            fetch.github = "owner/tool"
+
+            [lib]
+            src.github_tag = "owner/lib"
+            fetch.git = "https://example.org/lib.git"
+            git.fetchSubmodules = true
+
+            [data]
+            src.manual = "1.0"
+            fetch.url = "https://example.org/data-$ver.json"
+            "#,
+        )
+        .unwrap();
+        let generated: BTreeMap<String, Generated> = serde_json::from_value(serde_json::json!({
+            "tool": {
+                "version": "0123456789abcdef0123456789abcdef01234567",
+                "src": {
+                    "type": "github",
+                    "owner": "owner",
+                    "repo": "tool",
+                    "rev": "0123456789abcdef0123456789abcdef01234567",
+                    "sha256": "0".repeat(52),
+                },
+            },
+            "lib": {
+                "version": "v2.1",
+                "src": {
+                    "type": "git",
+                    "url": "https://example.org/lib.git",
+                    "rev": "v2.1",
+                    "fetchSubmodules": true,
+                    "sha256": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+                },
+            },
+            "data": {
+                "version": "1.0",
+                "src": {
+                    "type": "url",
+                    "url": "https://example.org/data-1.0.json",
+                    "sha256": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+                },
+            },
+        }))
+        .unwrap();
+        let import = |name: &str| entries[name].to_pin(generated.get(name));
+
+        let spec = serde_json::to_value(import("tool").await.unwrap()).unwrap();
+        assert_eq!(spec["type"], "Git");
+        assert_eq!(spec["branch"], "main");
+        assert_eq!(spec["revision"], "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(
+            spec["hash"],
+            "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+        );
+
+        let pin = import("lib").await.unwrap();
+        let spec = serde_json::to_value(&pin).unwrap();
+        assert_eq!(spec["type"], "GitRelease");
+        assert_eq!(spec["version"], "v2.1");
+        assert_eq!(spec["submodules"], true);
+        assert!(!pin.has_hashes());
+
+        let pin = import("data").await.unwrap();
+        let spec = serde_json::to_value(&pin).unwrap();
+        assert_eq!(spec["type"], "File");
+        assert_eq!(spec["url"], "https://example.org/data-1.0.json");
+        assert!(pin.has_hashes());
+    }
+}
@@ -0,0 +1,261 @@
+//! Machine-readable output of the commands, see `npins --output`
+//!
+//! By default, commands print for humans. With `--output json`, `show`, `update`, `outdated`,
+//! `diff` and `verify` print a single JSON document on stdout instead, and `--output table`
+//! prints aligned columns. Logging still goes to stderr in all cases, so stdout can be piped into `jq`.
+
+use crate::*;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How to print the results of a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Table,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Table => "table",
+        })
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "table" => Ok(Format::Table),
+            _ => anyhow::bail!("expected `text`, `json` or `table`"),
+        }
+    }
+}
+
+/// Print a value as pretty JSON on stdout
+pub fn print_json(value: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Rows of text, printed with aligned columns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(header: &[&str]) -> Self {
+        Table {
+            header: header.iter().map(|&column| column.to_uppercase()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Add a row, with as many columns as the header
+    pub fn push(&mut self, row: Vec<String>) {
+        assert_eq!(row.len(), self.header.len(), "wrong number of columns");
+        self.rows.push(row);
+    }
+}
+
+impl std::fmt::Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut widths = vec![0; self.header.len()];
+        for row in std::iter::once(&self.header).chain(&self.rows) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        for row in std::iter::once(&self.header).chain(&self.rows) {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// What happened to a pin in `npins update` or `npins verify`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Outcome {
+    /// Updated or verified, `changes` is empty if the pin stayed the same
+    Ok {
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        changes: Vec<diff::DiffEntry>,
+    },
+    Skipped {
+        reason: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+impl Outcome {
+    pub fn failed(error: &anyhow::Error) -> Self {
+        Outcome::Failed {
+            error: format!("{error:#}"),
+        }
+    }
+
+    /// One row per pin, summarizing the changes
+    pub fn table(outcomes: &BTreeMap<String, Outcome>) -> Table {
+        let mut table = Table::new(&["name", "status", "details"]);
+        for (name, outcome) in outcomes {
+            let (status, details) = match outcome {
+                Outcome::Ok { changes } if changes.is_empty() => ("ok", String::new()),
+                Outcome::Ok { changes } => (
+                    "changed",
+                    changes
+                        .iter()
+                        .map(diff::DiffEntry::summary)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+                Outcome::Skipped { reason } => ("skipped", reason.clone()),
+                Outcome::Failed { error } => ("failed", error.replace('\n', " ")),
+            };
+            table.push(vec![name.clone(), status.into(), details]);
+        }
+        table
+    }
+}
+
+/// The version of a pin in a few characters, like `v1.2` or a short git revision
+pub fn short_version(pin: &Pin) -> String {
+    match pin.version_properties().into_iter().next() {
+        Some((_, revision))
+            if revision.len() == 40 && revision.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            revision[..7].to_owned()
+        },
+        Some((_, version)) => version,
+        None => "-".into(),
+    }
+}
+
+/// The pins of `npins show`, by type, version, state and description
+pub fn pins_table<'a>(pins: impl IntoIterator<Item = (&'a String, &'a Pin)>) -> Table {
+    let mut table = Table::new(&["name", "type", "version", "state", "description"]);
+    for (name, pin) in pins {
+        table.push(vec![
+            name.clone(),
+            pin.pin_type().into(),
+            short_version(pin),
+            pin.metadata().state.to_string(),
+            pin.metadata().description.clone().unwrap_or_default(),
+        ]);
+    }
+    table
+}
+
+/// A pin that has a newer version, see `npins outdated`
+#[derive(Debug, Clone, Serialize)]
+pub struct Outdated {
+    pub current: String,
+    pub available: String,
+    /// How many commits the pinned revision is behind, if the forge can tell
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub behind: Option<usize>,
+}
+
+impl Outdated {
+    pub fn table(outdated: &BTreeMap<String, Outdated>) -> Table {
+        let mut table = Table::new(&["name", "current", "available", "behind"]);
+        for (name, pin) in outdated {
+            table.push(vec![
+                name.clone(),
+                pin.current.clone(),
+                pin.available.clone(),
+                pin.behind
+                    .map(|behind| format!("{behind} commits"))
+                    .unwrap_or_default(),
+            ]);
+        }
+        table
+    }
+}
+
+/// The differences of `npins diff`, one row per changed property
+pub fn diff_table(diff: &BTreeMap<String, diff::PinDiff>) -> Table {
+    let mut table = Table::new(&["name", "status", "property", "old", "new"]);
+    for (name, pin) in diff {
+        let status = serde_json::to_value(&pin.status).expect("a string");
+        for entry in &pin.changes {
+            table.push(vec![
+                name.clone(),
+                status.as_str().unwrap_or_default().into(),
+                entry.property().into(),
+                entry.old_value().unwrap_or("-").into(),
+                entry.new_value().unwrap_or("-").into(),
+            ]);
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(&["name", "status", "details"]);
+        table.push(vec!["nixpkgs".into(), "ok".into(), "".into()]);
+        table.push(vec!["a".into(), "failed".into(), "Not found".into()]);
+        assert_eq!(
+            table.to_string(),
+            "NAME     STATUS  DETAILS\n\
+             nixpkgs  ok\n\
+             a        failed  Not found\n"
+        );
+
+        let outcomes = BTreeMap::from([
+            (
+                "a".to_owned(),
+                Outcome::Skipped {
+                    reason: "frozen".into(),
+                },
+            ),
+            ("b".to_owned(), Outcome::Ok { changes: vec![] }),
+        ]);
+        assert_eq!(
+            serde_json::to_value(&outcomes).unwrap(),
+            serde_json::json!({
+                "a": { "status": "skipped", "reason": "frozen" },
+                "b": { "status": "ok" },
+            })
+        );
+        assert_eq!(Outcome::table(&outcomes).rows.len(), 2);
+
+        let outdated = BTreeMap::from([(
+            "nixpkgs".to_owned(),
+            Outdated {
+                current: "0123456".into(),
+                available: "89abcde".into(),
+                behind: Some(42),
+            },
+        )]);
+        assert_eq!(
+            Outdated::table(&outdated).to_string(),
+            "NAME     CURRENT  AVAILABLE  BEHIND\n\
+             nixpkgs  0123456  89abcde    42 commits\n"
+        );
+    }
+}
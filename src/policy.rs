@@ -0,0 +1,307 @@
+//! Gate updates on rules of a policy file
+//!
+//! A policy (`npins/policy.json` by default) restricts which changes `npins update` may write to
+//! the lock file. All rules are optional:
+//!
+//! ```json
+//! {
+//!   "no_major_bumps_on": ["friday", "saturday", "sunday"],
+//!   "require_approval": ["nixpkgs"],
+//!   "allowed_hosts": ["github.com", "*.example.org"]
+//! }
+//! ```
+//!
+//! The rules are checked for every pin that changed, before anything is written. Violations are
+//! reported all at once, and can also be written as JSON for CI to pick up.
+
+use crate::*;
+use anyhow::{Context, Result};
+use lenient_version::Version;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, path::Path, time::SystemTime};
+use url::Url;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    /// The day of the week in UTC
+    pub fn of(time: SystemTime) -> Self {
+        let days = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / (24 * 60 * 60);
+        /* 1970-01-01 was a Thursday */
+        Self::ALL[((days + 3) % 7) as usize]
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Days (in UTC) on which versions must not move to a new major version
+    #[serde(default)]
+    pub no_major_bumps_on: Vec<Weekday>,
+    /// Pins that may only be changed with `--approved-by`
+    #[serde(default)]
+    pub require_approval: Vec<String>,
+    /// Hosts that pins may point to, `*.example.org` allows all subdomains of `example.org`.
+    /// Any host is allowed if this is absent
+    #[serde(default)]
+    pub allowed_hosts: Option<Vec<String>>,
+}
+
+/// The circumstances of an update
+#[derive(Debug)]
+pub struct UpdateContext {
+    pub weekday: Weekday,
+    /// Who approved the update
+    pub approved_by: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    NoMajorBumpsOn,
+    RequireApproval,
+    AllowedHosts,
+}
+
+/// A pin change that the policy forbids
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub pin: String,
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.pin, self.message)
+    }
+}
+
+/// The version of a pin, for pins that have one
+fn version(pin: &Pin) -> Option<String> {
+    pin.properties()
+        .into_iter()
+        .find(|(key, _)| key == "version")
+        .map(|(_, version)| version)
+}
+
+fn is_major_bump(old: &str, new: &str) -> bool {
+    match (
+        lenient_semver_parser::parse::<Version>(old),
+        lenient_semver_parser::parse::<Version>(new),
+    ) {
+        (Ok(old), Ok(new)) => new.major > old.major,
+        _ => false,
+    }
+}
+
+fn host_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    allowed_hosts
+        .iter()
+        .any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|subdomain| subdomain.ends_with('.')),
+            None => host == allowed,
+        })
+}
+
+/// All URLs a pin points to, including the repository it is fetched from
+fn urls(pin: &Pin) -> Vec<Url> {
+    let mut urls = check::recorded_urls(pin);
+    urls.extend(
+        pin.repository()
+            .and_then(|repository| repository.git_url().ok()),
+    );
+    urls
+}
+
+impl Policy {
+    /// Read a policy file, `None` if it doesn't exist
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            },
+        };
+        serde_json::from_str(&contents)
+            .with_context(|| format!("{} is not a valid policy", path.display()))
+            .map(Some)
+    }
+
+    /// Check the change of a pin against the rules, `before` is `None` for new pins
+    pub fn evaluate(
+        &self,
+        name: &str,
+        before: Option<&Pin>,
+        after: &Pin,
+        context: &UpdateContext,
+    ) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let mut violation = |rule, message| {
+            violations.push(Violation {
+                pin: name.to_owned(),
+                rule,
+                message,
+            })
+        };
+
+        if self.no_major_bumps_on.contains(&context.weekday) {
+            if let (Some(old), Some(new)) = (before.and_then(version), version(after)) {
+                if is_major_bump(&old, &new) {
+                    violation(
+                        Rule::NoMajorBumpsOn,
+                        format!(
+                            "Major version bump from {} to {} is not allowed on {:?}s",
+                            old, new, context.weekday
+                        ),
+                    );
+                }
+            }
+        }
+
+        if self.require_approval.iter().any(|pin| pin == name) && context.approved_by.is_none() {
+            violation(
+                Rule::RequireApproval,
+                "Changes to this pin need to be approved, pass `--approved-by`".into(),
+            );
+        }
+
+        if let Some(allowed_hosts) = &self.allowed_hosts {
+            let forbidden = urls(after)
+                .iter()
+                .filter_map(Url::host_str)
+                .filter(|host| !host_allowed(host, allowed_hosts))
+                .map(ToOwned::to_owned)
+                .collect::<BTreeSet<_>>();
+            for host in forbidden {
+                violation(
+                    Rule::AllowedHosts,
+                    format!("{} is not an allowed host", host),
+                );
+            }
+        }
+
+        violations
+    }
+
+    /// Check all pins that changed between two versions of the lock file
+    pub fn evaluate_all(
+        &self,
+        before: &NixPins,
+        after: &NixPins,
+        context: &UpdateContext,
+    ) -> Vec<Violation> {
+        after
+            .pins
+            .iter()
+            .filter(|(name, pin)| before.pins.get(*name) != Some(*pin))
+            .flat_map(|(name, pin)| self.evaluate(name, before.pins.get(name), pin, context))
+            .collect()
+    }
+}
+
+/// Write violations as a JSON array
+pub fn write_violations(path: &Path, violations: &[Violation]) -> Result<()> {
+    let mut json = serde_json::to_string_pretty(violations)?;
+    json.push('\n');
+    std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_weekday() {
+        let day = |days: u64| {
+            Weekday::of(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(days * 86400))
+        };
+        assert_eq!(day(0), Weekday::Thursday);
+        assert_eq!(day(1), Weekday::Friday);
+        assert_eq!(day(4), Weekday::Monday);
+        /* 2026-10-16 */
+        assert_eq!(day(20742), Weekday::Friday);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let policy: Policy = serde_json::from_str(
+            r#"{
+                "no_major_bumps_on": ["friday"],
+                "require_approval": ["critical"],
+                "allowed_hosts": ["*.example.org"]
+            }"#,
+        )
+        .unwrap();
+        let friday = UpdateContext {
+            weekday: Weekday::Friday,
+            approved_by: None,
+        };
+
+        assert_eq!(
+            policy.evaluate(
+                "tool",
+                None,
+                &test_pin("https://dl.example.org/tool.tar.gz"),
+                &friday
+            ),
+            vec![]
+        );
+        assert_eq!(
+            policy
+                .evaluate(
+                    "critical",
+                    None,
+                    &test_pin("https://example.org/x.tar.gz"),
+                    &friday
+                )
+                .iter()
+                .map(|violation| violation.rule)
+                .collect::<Vec<_>>(),
+            vec![Rule::RequireApproval, Rule::AllowedHosts]
+        );
+        let approved = UpdateContext {
+            weekday: Weekday::Friday,
+            approved_by: Some("alice".into()),
+        };
+        assert!(policy
+            .evaluate(
+                "critical",
+                None,
+                &test_pin("https://dl.example.org/x.tar.gz"),
+                &approved
+            )
+            .is_empty());
+
+        assert!(is_major_bump("1.9.2", "2.0.0"));
+        assert!(!is_major_bump("1.9.2", "1.10.0"));
+        assert!(!is_major_bump("nightly", "2.0.0"));
+    }
+}
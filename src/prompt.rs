@@ -0,0 +1,136 @@
+//! Questions on the terminal, for the interactive parts of the CLI
+//!
+//! Used by `npins init --guide`, `npins update --interactive`, and wherever `npins add` can't
+//! decide on its own. Questions and explanations go to stderr, so that they don't mix with the
+//! output of the command.
+
+use anyhow::Result;
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Asks questions on `output` and reads the answers from `input`
+pub struct Prompt<R, W> {
+    input: R,
+    output: W,
+}
+
+impl Prompt<std::io::StdinLock<'static>, std::io::Stderr> {
+    /// Ask on the terminal, fails if there is none
+    pub fn terminal() -> Result<Self> {
+        anyhow::ensure!(
+            std::io::stdin().is_terminal() && std::io::stderr().is_terminal(),
+            "This needs an interactive terminal"
+        );
+        Ok(Prompt::new(std::io::stdin().lock(), std::io::stderr()))
+    }
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Prompt { input, output }
+    }
+
+    /// Print an explanation
+    pub fn say(&mut self, text: impl std::fmt::Display) -> Result<()> {
+        writeln!(self.output, "{text}")?;
+        Ok(())
+    }
+
+    /// Ask for a line of text, `default` is taken for an empty answer
+    pub fn ask(&mut self, question: &str, default: Option<&str>) -> Result<String> {
+        match default {
+            Some(default) => write!(self.output, "{question} [{default}] ")?,
+            None => write!(self.output, "{question} ")?,
+        }
+        self.output.flush()?;
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            anyhow::bail!("No answer given");
+        }
+        let answer = answer.trim();
+        Ok(match default {
+            Some(default) if answer.is_empty() => default.to_owned(),
+            _ => answer.to_owned(),
+        })
+    }
+
+    /// Ask until the answer parses
+    pub fn ask_parsed<T>(&mut self, question: &str, default: Option<&str>) -> Result<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        loop {
+            match self.ask(question, default)?.parse() {
+                Ok(answer) => return Ok(answer),
+                Err(err) => self.say(err)?,
+            }
+        }
+    }
+
+    /// Ask a yes/no question
+    pub fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+        let hint = if default { "Y/n" } else { "y/N" };
+        loop {
+            match self.ask(question, Some(hint))?.to_lowercase().as_str() {
+                "y/n" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => self.say("Please answer yes or no")?,
+            }
+        }
+    }
+
+    /// Ask to pick one of `choices`, by name
+    pub fn choose<'a>(
+        &mut self,
+        question: &str,
+        choices: &[&'a str],
+        default: &'a str,
+    ) -> Result<&'a str> {
+        let question = format!("{question} ({})", choices.join("/"));
+        loop {
+            let answer = self.ask(&question, Some(default))?;
+            match choices.iter().find(|choice| **choice == answer) {
+                Some(choice) => return Ok(choice),
+                None => self.say(format!("Please answer one of {}", choices.join(", ")))?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prompt() {
+        let input = "\nno\nmaybe\ny\nbranch\nbranches\n\n\ntwelve\n12\n";
+        let mut output = Vec::new();
+        let mut prompt = Prompt::new(input.as_bytes(), &mut output);
+        assert!(prompt.confirm("Pin nixpkgs?", true).unwrap());
+        assert!(!prompt.confirm("Pin nixpkgs?", true).unwrap());
+        /* Asks again until the answer makes sense */
+        assert!(prompt.confirm("Pin nixpkgs?", false).unwrap());
+        assert_eq!(
+            prompt
+                .choose("Track", &["releases", "branch"], "releases")
+                .unwrap(),
+            "branch"
+        );
+        assert_eq!(
+            prompt
+                .choose("Track", &["releases", "branch"], "releases")
+                .unwrap(),
+            "releases"
+        );
+        assert_eq!(prompt.ask("Branch?", Some("master")).unwrap(), "master");
+        assert_eq!(prompt.ask_parsed::<u32>("Number?", None).unwrap(), 12);
+        assert!(prompt.ask("More?", None).is_err());
+        drop(prompt);
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Pin nixpkgs? [Y/n] "));
+        assert!(output.contains("Please answer yes or no"));
+        assert!(output.contains("Please answer one of releases, branch"));
+    }
+}
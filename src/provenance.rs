@@ -0,0 +1,358 @@
+//! Provenance of pins: which npins, invoked how, when and where, changed them
+//!
+//! Every time a pin changes, a [`Provenance`] record is stored alongside it. It contains a digest
+//! over the pin's contents, so that edits made without going through npins can be detected
+//! later on.
+
+use crate::Pin;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::future::Future;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// The version of npins that made the change
+    pub npins_version: String,
+    /// The command line npins was invoked with, with secrets redacted
+    pub invocation: Vec<String>,
+    /// When the change was made, in UTC
+    pub timestamp: String,
+    /// The remote endpoints that were queried for the change
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<String>,
+    /// What could not be looked up for the change, see [`record_missing`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<String>,
+    /// The CI job that made the change, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ci_job_url: Option<String>,
+    /// A checksum over the contents of the pin at the time of the change, see [`digest`]
+    pub digest: String,
+}
+
+impl Provenance {
+    /// Create a record for the current process making `pin` what it is now
+    pub fn new(pin: &Pin, recorded: Recorded) -> Result<Self> {
+        let Recorded { endpoints, missing } = recorded.normalized();
+        Ok(Provenance {
+            npins_version: env!("CARGO_PKG_VERSION").into(),
+            invocation: redact_arguments(std::env::args()),
+            timestamp: timestamp(std::time::SystemTime::now()),
+            endpoints,
+            missing,
+            ci_job_url: ci_job_url(),
+            digest: digest(pin)?,
+        })
+    }
+
+    /// Check that the pin was not modified since this record was made
+    pub fn verify(&self, pin: &Pin) -> Result<()> {
+        anyhow::ensure!(
+            matches(&self.digest, pin)?,
+            "The pin was modified without npins: its digest is {} instead of {}",
+            digest(pin)?,
+            self.digest
+        );
+        Ok(())
+    }
+}
+
+impl crate::diff::Diff for Provenance {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("npins version".into(), self.npins_version.clone())),
+            Some(("invocation".into(), self.invocation.join(" "))),
+            Some(("timestamp".into(), self.timestamp.clone())),
+            self.ci_job_url
+                .as_ref()
+                .map(|url| ("CI job".into(), url.clone())),
+            Some(("digest".into(), self.digest.clone())),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(
+            self.endpoints
+                .iter()
+                .map(|endpoint| ("endpoint".into(), endpoint.clone())),
+        )
+        .chain(
+            self.missing
+                .iter()
+                .map(|what| ("missing".into(), what.clone())),
+        )
+        .collect()
+    }
+}
+
+/// Serialize JSON canonically: no whitespace, object keys sorted
+///
+/// Good enough for hashing our own data, though not a full implementation of RFC 8785
+/// (we have no floats anyways).
+pub fn canonical_json(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+            let entries: Vec<String> = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{}:{}",
+                        serde_json::Value::String(key.clone()),
+                        canonical_json(value)
+                    )
+                })
+                .collect();
+            format!("{{{}}}", entries.join(","))
+        },
+        serde_json::Value::Array(values) => {
+            let values: Vec<String> = values.iter().map(canonical_json).collect();
+            format!("[{}]", values.join(","))
+        },
+        value => value.to_string(),
+    }
+}
+
+/// The canonical JSON of a pin, excluding its metadata
+fn contents(pin: &Pin) -> Result<String> {
+    let mut value = serde_json::to_value(pin)?;
+    let metadata = serde_json::to_value(pin.metadata())?;
+    if let (Some(value), Some(metadata)) = (value.as_object_mut(), metadata.as_object()) {
+        for key in metadata.keys() {
+            value.remove(key);
+        }
+    }
+    Ok(canonical_json(&value))
+}
+
+/// A checksum over the contents of a pin, like `sha256:<hex>`
+///
+/// The algorithm is `checksum` under `[provenance]` in `npins.toml`, see [`crate::checksum`].
+pub fn digest(pin: &Pin) -> Result<String> {
+    let algorithm = crate::config::get().provenance.checksum;
+    Ok(algorithm.prefixed(contents(pin)?.as_bytes()))
+}
+
+/// Whether a [`digest`] is the one of the pin, whichever algorithm it was made with
+pub fn matches(digest: &str, pin: &Pin) -> Result<bool> {
+    crate::checksum::matches_prefixed(digest, contents(pin)?.as_bytes())
+}
+
+/// Redact the values of options that carry secrets
+pub(crate) fn redact_arguments(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    const SECRET_OPTIONS: &[&str] = &["--token"];
+
+    let mut redact_next = false;
+    args.into_iter()
+        .map(|arg| {
+            if std::mem::take(&mut redact_next) {
+                return "***".into();
+            }
+            for option in SECRET_OPTIONS {
+                if arg == *option {
+                    redact_next = true;
+                } else if arg.starts_with(&format!("{option}=")) {
+                    return format!("{option}=***");
+                }
+            }
+            arg
+        })
+        .collect()
+}
+
+/// The URL of the current CI job, as told by the usual environment variables
+pub(crate) fn ci_job_url() -> Option<String> {
+    let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+    /* GitHub Actions */
+    if let (Some(server), Some(repository), Some(run)) = (
+        var("GITHUB_SERVER_URL"),
+        var("GITHUB_REPOSITORY"),
+        var("GITHUB_RUN_ID"),
+    ) {
+        return Some(format!("{server}/{repository}/actions/runs/{run}"));
+    }
+    /* GitLab CI, Buildkite, Jenkins */
+    var("CI_JOB_URL")
+        .or_else(|| var("BUILDKITE_BUILD_URL"))
+        .or_else(|| var("BUILD_URL"))
+}
+
+/// Format a point in time as RFC 3339 timestamp in UTC
+pub(crate) fn timestamp(time: std::time::SystemTime) -> String {
+    let seconds = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    /* Convert days since the epoch to a date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days */
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// What an update reported along the way, see [`record`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recorded {
+    /// Reported via [`record_endpoint`]
+    pub endpoints: Vec<String>,
+    /// Reported via [`record_missing`]
+    pub missing: Vec<String>,
+}
+
+impl Recorded {
+    pub fn extend(&mut self, other: Recorded) {
+        self.endpoints.extend(other.endpoints);
+        self.missing.extend(other.missing);
+    }
+
+    /// Sorted, without duplicates
+    pub fn normalized(mut self) -> Self {
+        for list in [&mut self.endpoints, &mut self.missing] {
+            list.sort();
+            list.dedup();
+        }
+        self
+    }
+}
+
+tokio::task_local! {
+    static RECORDED: RefCell<Recorded>;
+}
+
+/// Run a future, collecting everything it reports via [`record_endpoint`] and [`record_missing`]
+pub async fn record<T>(future: impl Future<Output = T>) -> (T, Recorded) {
+    RECORDED
+        .scope(RefCell::new(Recorded::default()), async {
+            let result = future.await;
+            (result, RECORDED.with(|recorded| recorded.take()))
+        })
+        .await
+}
+
+/// Report a remote endpoint that is being queried
+///
+/// Does nothing when not called from within [`record`]. Credentials in the URL are redacted.
+pub fn record_endpoint(endpoint: impl AsRef<str>) {
+    let endpoint = endpoint.as_ref();
+    /* Better safe than sorry */
+    let endpoint = match endpoint.parse() {
+        Ok(url) => crate::check::check_url(&url)
+            .map(|finding| finding.redacted_url)
+            .unwrap_or(url)
+            .to_string(),
+        Err(_) => endpoint.to_owned(),
+    };
+    RECORDED
+        .try_with(|recorded| recorded.borrow_mut().endpoints.push(endpoint))
+        .ok();
+}
+
+/// Report data that could not be looked up, and was left out or kept as it was
+///
+/// This is for extras like commit timestamps, without which a pin still works: when a forge's
+/// API is down or rate limited, the update carries on without them. Does nothing when not called
+/// from within [`record`].
+pub fn record_missing(what: impl Into<String>) {
+    let what = what.into();
+    RECORDED
+        .try_with(|recorded| recorded.borrow_mut().missing.push(what))
+        .ok();
+}
+
+/// Show the provenance of a pin, and check that it is still accurate
+pub fn check(pin: &Pin) -> Result<&Provenance> {
+    let provenance = pin
+        .metadata()
+        .provenance
+        .as_ref()
+        .context("The pin has no provenance record. It was last changed by an older npins version, or by hand.")?;
+    provenance.verify(pin)?;
+    Ok(provenance)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timestamp() {
+        let time = |seconds| std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+        assert_eq!(timestamp(time(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(time(951782400)), "2000-02-29T00:00:00Z");
+        assert_eq!(timestamp(time(1792152245)), "2026-10-16T12:04:05Z");
+    }
+
+    #[test]
+    fn test_redact_arguments() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            redact_arguments(args(&[
+                "npins", "add", "gitlab", "a", "b", "--token", "secret"
+            ])),
+            args(&["npins", "add", "gitlab", "a", "b", "--token", "***"])
+        );
+        assert_eq!(
+            redact_arguments(args(&[
+                "npins",
+                "add",
+                "gitlab",
+                "--token=secret",
+                "a",
+                "b"
+            ])),
+            args(&["npins", "add", "gitlab", "--token=***", "a", "b"])
+        );
+    }
+
+    #[test]
+    fn test_canonical_json() {
+        let value = serde_json::json!({"b": [1, {"d": null, "c": "x\"y"}], "a": true});
+        assert_eq!(
+            canonical_json(&value),
+            r#"{"a":true,"b":[1,{"c":"x\"y","d":null}]}"#
+        );
+    }
+
+    #[test]
+    fn test_digest_ignores_metadata() {
+        let mut pin: Pin = crate::channel::Pin::new("nixos-unstable").into();
+        let before = digest(&pin).unwrap();
+        pin.freeze();
+        assert_eq!(digest(&pin).unwrap(), before);
+
+        let provenance = Provenance::new(&pin, Recorded::default()).unwrap();
+        pin.metadata_mut().provenance = Some(provenance.clone());
+        assert!(provenance.verify(&pin).is_ok());
+
+        let mut other: Pin = crate::channel::Pin::new("nixos-24.05").into();
+        other.metadata_mut().provenance = Some(provenance.clone());
+        assert!(provenance.verify(&other).is_err());
+
+        /* Digests made with another algorithm than the configured one */
+        let blake3 =
+            crate::checksum::Algorithm::Blake3.prefixed(contents(&pin).unwrap().as_bytes());
+        assert!(matches(&blake3, &pin).unwrap());
+        assert!(!matches(&blake3, &other).unwrap());
+    }
+}
@@ -1,4 +1,9 @@
 //! Pin a PyPi package
+//!
+//! By default, the source distribution (sdist) is pinned. Packages that only ship wheels, or that
+//! are expensive to build from source, can pin a wheel instead. It is selected by its
+//! [compatibility tags](https://packaging.python.org/en/latest/specifications/platform-compatibility-tags/),
+//! like `cp311-manylinux_x86_64` or `py3-none-any`.
 
 use crate::{nix::hash_to_sri, *};
 use anyhow::{Context, Result};
@@ -22,6 +27,16 @@ pub struct Pin {
     ///
     /// Versions will be parsed the in the same rather lenient way as the tags themselves.
     pub version_upper_bound: Option<String>,
+    /// Pin the wheel with these compatibility tags instead of the source distribution, see
+    /// [`wheel_matches`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wheel: Option<String>,
+    /// Only consider the releases matching one of these glob patterns, see [`latest::listed`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only_versions: Vec<String>,
+    /// Skip the releases matching any of these glob patterns, like a broken `3.2.1` or all `*.0`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_versions: Vec<String>,
 }
 
 impl diff::Diff for Pin {
@@ -33,13 +48,108 @@ impl diff::Diff for Pin {
                 .map(|version_upper_bound| {
                     ("version_upper_bound".into(), version_upper_bound.clone())
                 }),
+            self.wheel
+                .as_ref()
+                .map(|wheel| ("wheel".into(), wheel.clone())),
         ]
         .into_iter()
         .flat_map(Option::into_iter)
+        .chain(
+            self.only_versions
+                .iter()
+                .map(|pattern| ("only_versions".into(), pattern.clone())),
+        )
+        .chain(
+            self.exclude_versions
+                .iter()
+                .map(|pattern| ("exclude_versions".into(), pattern.clone())),
+        )
         .collect()
     }
 }
 
+/// Check whether a wheel has all the given compatibility tags
+///
+/// The tags are separated by `-`, in any order and any subset of the Python, ABI and platform
+/// tags. Platform tags may leave out the version of the libc or OS, so `manylinux_x86_64` matches
+/// `manylinux_2_17_x86_64` and `manylinux2014_x86_64`, and `macosx_arm64` matches `macosx_11_0_arm64`.
+pub fn wheel_matches(tags: &str, filename: &str) -> bool {
+    /* `{distribution}-{version}(-{build tag})?-{python tag}-{abi tag}-{platform tag}.whl` */
+    let Some(stem) = filename.strip_suffix(".whl") else {
+        return false;
+    };
+    let parts = stem.split('-').collect::<Vec<_>>();
+    let Some(wheel_tags) = parts.len().checked_sub(3).map(|start| &parts[start..]) else {
+        return false;
+    };
+    /* Each tag may be a compressed set like `py2.py3` */
+    let wheel_tags = wheel_tags
+        .iter()
+        .flat_map(|tag| tag.split('.'))
+        .collect::<Vec<_>>();
+
+    tags.split('-').all(|wanted| {
+        wheel_tags.iter().any(|tag| {
+            *tag == wanted
+                || ["manylinux", "musllinux", "macosx"].iter().any(|family| {
+                    wanted
+                        .strip_prefix(family)
+                        .and_then(|arch| arch.strip_prefix('_'))
+                        .is_some_and(|arch| {
+                            tag.starts_with(family) && tag.ends_with(&format!("_{arch}"))
+                        })
+                })
+        })
+    })
+}
+
+impl Pin {
+    /// Whether the pin may be at `version`, by [`only_versions`](Self::only_versions) and
+    /// [`exclude_versions`](Self::exclude_versions)
+    pub fn admits(&self, version: &str) -> bool {
+        latest::listed(version, &self.only_versions, &self.exclude_versions)
+    }
+
+    /// The file of a release to pin, i.e. the source distribution or the requested wheel
+    fn select<'a>(&self, files: &'a [PyPiUrlMetadata]) -> Result<&'a PyPiUrlMetadata> {
+        let Some(tags) = &self.wheel else {
+            return files
+                .iter()
+                /* Of all files for the release, we only care about source tarballs */
+                .find(|file_meta| file_meta.python_version == "source")
+                .ok_or_else(|| {
+                    anyhow::format_err!(
+                        "Unsupported package: must contain some \"source\" download. Use a wheel instead?",
+                    )
+                });
+        };
+        let matching = files
+            .iter()
+            .filter(|file| wheel_matches(tags, &file.filename))
+            .collect::<Vec<_>>();
+        match matching[..] {
+            [file] => Ok(file),
+            [] => anyhow::bail!(
+                "No wheel matches '{tags}'. There are: {}",
+                files
+                    .iter()
+                    .map(|file| file.filename.as_str())
+                    .filter(|filename| filename.ends_with(".whl"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            _ => anyhow::bail!(
+                "Several wheels match '{tags}', please be more specific: {}",
+                matching
+                    .iter()
+                    .map(|file| file.filename.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl Updatable for Pin {
     type Version = GenericVersion;
@@ -62,22 +172,36 @@ impl Updatable for Pin {
             get_and_deserialize(format!("https://pypi.org/pypi/{}/json", &self.name))
                 .await
                 .context("Could not fetch Pypi metadata")?;
+        let usable = |version: &str| {
+            self.admits(version)
+                && metadata.yanked(version).is_none()
+                && metadata
+                    .releases
+                    .get(version)
+                    .is_some_and(|files| self.select(files).is_ok())
+        };
         let version = match version_upper_bound {
-            Some(version_upper_bound) => {
+            /* Simply take latest, unless it got yanked or lacks the file we want */
+            None if usable(&metadata.info.version) => metadata.info.version.clone(),
+            version_upper_bound => {
                 metadata.releases.keys()
+                    /* Skip yanked releases, and those without the file we want */
+                    .filter(|version| usable(version))
                     /* Try to parse as version, ignore those that are invalid (not every tag will be a release) */
-                    .filter_map(|version| lenient_semver_parser::parse::<Version>(version).ok())
+                    .filter_map(|version| Some((lenient_semver_parser::parse::<Version>(version).ok()?, version)))
                     /* Pre-releases are not supported at the moment */
-                    .filter(|version| !version.is_pre_release())
+                    .filter(|(version, _)| !version.is_pre_release())
                     /* Filter against our upper bound */
-                    .filter(|version| version < &version_upper_bound)
-                    /* Get the latest version */
-                    .max()
+                    .filter(|(version, _)| match &version_upper_bound {
+                        Some(version_upper_bound) => version < version_upper_bound,
+                        None => true,
+                    })
+                    /* Get the latest version, the releases come in no particular order */
+                    .max_by(|(a, a_str), (b, b_str)| latest::cmp((a, a_str), (b, b_str)))
                     .ok_or_else(|| anyhow::format_err!("No matching versions found"))?
+                    .0
                     .to_string()
             },
-            /* Simply take latest */
-            None => metadata.info.version,
         };
 
         if let Some(old) = old {
@@ -112,34 +236,35 @@ impl Updatable for Pin {
                 .await
                 .context("Could not fetch Pypi metadata")?;
 
-        let mut latest_source: PyPiUrlMetadata = metadata
-            .releases
-            .remove(&version.version)
-            .ok_or_else(|| {
-                anyhow::format_err!("Could not find requested version {}", version.version)
-            })?
-            .into_iter()
-            /* Of all files for the latest release, we only care about source tarballs */
-            .find(|file_meta| file_meta.python_version == "source")
-            .ok_or_else(|| {
-                anyhow::format_err!("Unsupported package: must contain some \"source\" download",)
-            })?;
+        let files = metadata.releases.remove(&version.version).ok_or_else(|| {
+            anyhow::format_err!("Could not find requested version {}", version.version)
+        })?;
+        let file = self.select(&files)?;
 
-        let hash = latest_source
+        let hash = file
             .digests
-            .remove("sha256")
+            .get("sha256")
             .ok_or_else(|| {
                 anyhow::format_err!(
                     "JSON metadata is invalid: must contain a `sha256` entry within `digests`",
                 )
             })
-            .and_then(|s| hash_to_sri(&s, "sha256"))?;
+            .and_then(|s| hash_to_sri(s, "sha256"))?;
 
         Ok(GenericUrlHashes {
             hash,
-            url: latest_source.url.parse()?,
+            url: file.url.parse()?,
+            archive: None,
         })
     }
+
+    async fn yanked(&self, version: &GenericVersion) -> Result<Option<String>> {
+        let metadata: PyPiMetadata =
+            get_and_deserialize(format!("https://pypi.org/pypi/{}/json", &self.name))
+                .await
+                .context("Could not fetch Pypi metadata")?;
+        Ok(metadata.yanked(&version.version))
+    }
 }
 
 /// The actual JSON file is rather large, we only deserialize what we are interested in,
@@ -156,6 +281,25 @@ struct PyPiMetadata {
     pub urls: Vec<PyPiUrlMetadata>,
 }
 
+impl PyPiMetadata {
+    /// Whether a release was yanked, and why
+    ///
+    /// Files are yanked individually, a release counts as yanked if all of its files are
+    /// (see [PEP 592](https://peps.python.org/pep-0592/)).
+    fn yanked(&self, version: &str) -> Option<String> {
+        let files = self.releases.get(version)?;
+        if files.is_empty() || !files.iter().all(|file| file.yanked) {
+            return None;
+        }
+        Some(
+            files
+                .iter()
+                .find_map(|file| file.yanked_reason.clone())
+                .unwrap_or_else(|| "no reason given".into()),
+        )
+    }
+}
+
 // Again, this is not complete
 #[allow(unused)]
 #[derive(Debug, Deserialize)]
@@ -164,6 +308,10 @@ struct PyPiUrlMetadata {
     filename: String,
     python_version: String,
     url: String,
+    #[serde(default)]
+    yanked: bool,
+    #[serde(default)]
+    yanked_reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -175,6 +323,99 @@ struct PyPiInfoMetadata {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_yanked() {
+        let file = |yanked: bool, reason: Option<&str>| {
+            serde_json::json!({
+                "digests": {},
+                "filename": "example-1.0.tar.gz",
+                "python_version": "source",
+                "url": "https://files.pythonhosted.org/example-1.0.tar.gz",
+                "yanked": yanked,
+                "yanked_reason": reason,
+            })
+        };
+        let metadata: PyPiMetadata = serde_json::from_value(serde_json::json!({
+            "info": { "version": "1.2" },
+            "releases": {
+                "1.0": [file(false, None)],
+                "1.1": [file(true, None), file(false, None)],
+                "1.2": [file(true, Some("Broken build")), file(true, None)],
+                "1.3": [],
+            },
+            "urls": [],
+        }))
+        .unwrap();
+
+        assert_eq!(metadata.yanked("1.0"), None);
+        assert_eq!(metadata.yanked("1.1"), None);
+        assert_eq!(metadata.yanked("1.2"), Some("Broken build".into()));
+        assert_eq!(metadata.yanked("1.3"), None);
+        assert_eq!(metadata.yanked("2.0"), None);
+    }
+
+    #[test]
+    fn test_wheel_matches() {
+        let wheel = "numpy-2.1.0-cp311-cp311-manylinux_2_17_x86_64.manylinux2014_x86_64.whl";
+        assert!(wheel_matches("cp311-manylinux_x86_64", wheel));
+        assert!(wheel_matches("cp311-manylinux2014_x86_64", wheel));
+        assert!(wheel_matches("manylinux_x86_64-cp311", wheel));
+        assert!(!wheel_matches("cp312-manylinux_x86_64", wheel));
+        assert!(!wheel_matches("cp311-manylinux_aarch64", wheel));
+        assert!(!wheel_matches("cp311-musllinux_x86_64", wheel));
+
+        assert!(wheel_matches(
+            "py3-none-any",
+            "six-1.16.0-py2.py3-none-any.whl"
+        ));
+        assert!(wheel_matches("py3", "six-1.16.0-py2.py3-none-any.whl"));
+        /* With a build tag */
+        assert!(wheel_matches(
+            "macosx_arm64",
+            "tool-1.0-1-cp311-cp311-macosx_11_0_arm64.whl"
+        ));
+        assert!(!wheel_matches("py3", "six-1.16.0.tar.gz"));
+    }
+
+    #[test]
+    fn test_select() {
+        let file = |filename: &str, python_version: &str| PyPiUrlMetadata {
+            digests: HashMap::new(),
+            filename: filename.into(),
+            python_version: python_version.into(),
+            url: format!("https://files.pythonhosted.org/{filename}"),
+            yanked: false,
+            yanked_reason: None,
+        };
+        let files = [
+            file("tool-1.0.tar.gz", "source"),
+            file("tool-1.0-cp311-cp311-manylinux_2_17_x86_64.whl", "cp311"),
+            file("tool-1.0-cp311-cp311-manylinux_2_28_x86_64.whl", "cp311"),
+            file("tool-1.0-cp311-cp311-musllinux_1_1_x86_64.whl", "cp311"),
+        ];
+        let pin = |wheel: Option<&str>| Pin {
+            name: "tool".into(),
+            version_upper_bound: None,
+            wheel: wheel.map(Into::into),
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
+        };
+        assert_eq!(
+            pin(None).select(&files).unwrap().filename,
+            "tool-1.0.tar.gz"
+        );
+        assert_eq!(
+            pin(Some("cp311-musllinux_x86_64"))
+                .select(&files)
+                .unwrap()
+                .filename,
+            "tool-1.0-cp311-cp311-musllinux_1_1_x86_64.whl"
+        );
+        assert!(pin(Some("cp311-manylinux_x86_64")).select(&files).is_err());
+        assert!(pin(Some("cp312")).select(&files).is_err());
+        assert!(pin(None).select(&files[1..]).is_err());
+    }
+
     #[tokio::test]
     async fn test_pypi_update() -> Result<()> {
         /* Last release has been in 2016, there are separate packages for major releases.
@@ -183,6 +424,9 @@ mod test {
         let pin = Pin {
             name: "gaiatest".into(),
             version_upper_bound: None,
+            wheel: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         };
         let version = pin.update(None).await?;
         assert_eq!(
@@ -196,6 +440,7 @@ mod test {
             GenericUrlHashes {
                 hash: "sha256-OVOxWLe2kGQtaM1r6x1Z9uEFJvLuEKb7RjapE8yV5xg=".into(),
                 url: "https://files.pythonhosted.org/packages/d1/d5/0c270c22d61ff6b883d0f24956f13e904b131b5ac2829e0af1cda99d70b1/gaiatest-0.34.tar.gz".parse().unwrap(),
+                archive: None,
             }
         );
         Ok(())
@@ -209,6 +454,9 @@ mod test {
         let pin = Pin {
             name: "streamlit".into(),
             version_upper_bound: Some("1.0.0".into()),
+            wheel: None,
+            only_versions: Vec::new(),
+            exclude_versions: Vec::new(),
         };
         let version = pin.update(None).await?;
         assert_eq!(
@@ -222,6 +470,7 @@ mod test {
             GenericUrlHashes {
                 hash: "sha256-OdCcZiclX885yTiTeZVmW2N3eZxPoUH2tIG8teamiKw=".into(),
                 url: "https://files.pythonhosted.org/packages/fd/75/6e72889c3b154a179040b94963a50901966ff30b68600271df374b2ded7a/streamlit-0.89.0.tar.gz".parse().unwrap(),
+                archive: None,
             }
         );
         Ok(())
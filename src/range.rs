@@ -0,0 +1,191 @@
+//! Pin evenly spaced revisions between two commits of a git pin, for bisecting
+//!
+//! The revisions are written to a lock file of their own, with the same format as `sources.json`,
+//! so that Nix code can build any of them with `import ./npins { input = …; }` instead of
+//! prefetching them ad hoc. The pins in it are named by their position in the range, padded with
+//! zeros, so that `builtins.attrNames` lists them in order.
+//!
+//! The same kind of clone, without any files, also answers questions about the history that
+//! forges can't, like which tags are on a branch.
+
+use crate::*;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+/// Run git in `directory`, returning its output
+pub(crate) async fn git(directory: &Path, args: &[&str]) -> Result<String> {
+    Ok(String::from_utf8(git_output(directory, args).await?)?)
+}
+
+/// Like [`git`], for output that isn't necessarily UTF-8
+pub(crate) async fn git_output(directory: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    log::debug!("Executing `git {}`", args.join(" "));
+    let output = git::command()
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .output()
+        .await
+        .context("Failed to spawn git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`git {}` failed\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Clone the repository without any files into a fresh temporary directory, and run `f` in it
+async fn in_clone<T, F>(url: &url::Url, purpose: &str, f: impl FnOnce(PathBuf) -> F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    provenance::record_endpoint(url);
+    let directory = tempdir::TempDir::new(purpose)?;
+    git(
+        directory.path(),
+        &[
+            "clone",
+            "--bare",
+            "--filter=tree:0",
+            "--quiet",
+            "--",
+            url.as_str(),
+            ".",
+        ],
+    )
+    .await
+    .with_context(|| format!("Failed to clone {url}"))?;
+    f(directory.path().to_owned()).await
+}
+
+/// Resolve a revision in a clone to the full hash of a commit
+async fn commit(directory: &Path, revision: &str) -> Result<String> {
+    let commit = git(
+        directory,
+        &["rev-parse", "--verify", &format!("{revision}^{{commit}}")],
+    )
+    .await
+    .with_context(|| format!("There is no revision {revision}"))?;
+    Ok(commit.trim().to_owned())
+}
+
+/// The first-parent history from `from` to `to`, both included, oldest first
+///
+/// This needs the commit graph, so the repository is cloned, but without any files.
+pub async fn revisions(url: &url::Url, from: &str, to: &str) -> Result<Vec<String>> {
+    in_clone(url, "range", |directory| async move {
+        let from = commit(&directory, from).await?;
+        let to = commit(&directory, to).await?;
+        git(&directory, &["merge-base", "--is-ancestor", &from, &to])
+            .await
+            .with_context(|| format!("{from} is not an ancestor of {to}"))?;
+        let range = git(
+            &directory,
+            &[
+                "rev-list",
+                "--first-parent",
+                "--reverse",
+                &format!("{from}..{to}"),
+            ],
+        )
+        .await?;
+        Ok(std::iter::once(from)
+            .chain(range.lines().map(str::to_owned))
+            .collect())
+    })
+    .await
+}
+
+/// Whether `revision` is in the history of `ref_`, for when the forge can't tell
+///
+/// Refs outside of `refs/heads/`, like `refs/pull/42/head`, are fetched on top of the clone.
+pub async fn is_ancestor(url: &url::Url, revision: &str, ref_: &str) -> Result<bool> {
+    in_clone(url, "ancestor", |directory| async move {
+        git(&directory, &["fetch", "--quiet", "origin", ref_])
+            .await
+            .with_context(|| format!("There is no {ref_}"))?;
+        let revision = commit(&directory, revision).await?;
+        /* Fails if it isn't one, the revision itself is known to exist by now */
+        Ok(git(
+            &directory,
+            &["merge-base", "--is-ancestor", &revision, "FETCH_HEAD"],
+        )
+        .await
+        .is_ok())
+    })
+    .await
+}
+
+/// The tags in the history of `branch`, for when the forge can't tell
+pub async fn merged_tags(url: &url::Url, branch: &str) -> Result<HashSet<String>> {
+    in_clone(url, "merged", |directory| async move {
+        let merged = git(
+            &directory,
+            &["tag", "--merged", &format!("refs/heads/{branch}")],
+        )
+        .await
+        .with_context(|| format!("There is no branch {branch}"))?;
+        Ok(merged.lines().map(str::to_owned).collect())
+    })
+    .await
+}
+
+/// Pick `count` evenly spaced entries, always including the first and the last one
+pub fn sample<T: Clone>(entries: &[T], count: usize) -> Vec<T> {
+    if entries.len() <= count {
+        return entries.to_vec();
+    }
+    match count {
+        0 => Vec::new(),
+        1 => entries[entries.len() - 1..].to_vec(),
+        _ => (0..count)
+            .map(|i| entries[i * (entries.len() - 1) / (count - 1)].clone())
+            .collect(),
+    }
+}
+
+/// The pin at another revision, without hashes
+pub fn at_revision(pin: &Pin, revision: &str) -> Result<Pin> {
+    let Pin::Git { input, .. } = pin else {
+        anyhow::bail!(
+            "Ranges are only supported for git pins, not for a {}",
+            pin.pin_type()
+        );
+    };
+    Ok(Pin::Git {
+        input: input.clone(),
+        version: Some(git::GitRevision::new(revision.to_owned())?),
+        hashes: None,
+        metadata: Default::default(),
+    })
+}
+
+/// The names of the pins in a range lock file, by position
+pub fn step_names(count: usize) -> Vec<String> {
+    let width = count.saturating_sub(1).to_string().len();
+    (0..count).map(|i| format!("{i:0width$}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let entries = (0..=100).collect::<Vec<_>>();
+        assert_eq!(sample(&entries, 5), [0, 25, 50, 75, 100]);
+        assert_eq!(sample(&entries, 1), [100]);
+        assert_eq!(sample(&entries[..3], 10), [0, 1, 2]);
+        assert_eq!(sample(&entries[..10], 4), [0, 3, 6, 9]);
+        assert_eq!(
+            step_names(10),
+            ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"]
+        );
+        assert_eq!(step_names(11)[..2], ["00", "01"]);
+    }
+}
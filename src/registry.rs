@@ -0,0 +1,202 @@
+//! Keep pins in line with an organization-wide registry
+//!
+//! A registry is a curated set of pins, e.g. the blessed Nixpkgs and toolchains, in a lock file that
+//! lives in a git repository (either at the top or in `npins/`), or is served directly. Projects
+//! adopt pins from it with `npins sync --registry <registry> <names…>`, which marks them as
+//! managed by the registry. Later syncs replace all managed pins with the registry's version of
+//! them, while other pins are left alone.
+
+use crate::*;
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+/// What happened to a pin during a sync
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    /// The pin was replaced with the registry's, possibly without any difference
+    Synced(Vec<diff::DiffEntry>),
+    /// The pin was adopted from the registry, possibly replacing a local one
+    Adopted,
+    /// A managed pin that is no longer in the registry, it is left as it is
+    Missing,
+}
+
+/// Replace the managed pins and the ones to adopt with those of the registry
+pub fn reconcile(
+    pins: &mut NixPins,
+    registry: &NixPins,
+    adopt: &[String],
+) -> Result<BTreeMap<String, Change>> {
+    for name in adopt {
+        anyhow::ensure!(
+            registry.pins.contains_key(name),
+            "The registry has no pin named '{}'",
+            name
+        );
+    }
+
+    let names = pins
+        .pins
+        .iter()
+        .filter(|(_, pin)| pin.is_managed())
+        .map(|(name, _)| name.clone())
+        .chain(adopt.iter().cloned())
+        .collect::<BTreeSet<_>>();
+    let mut changes = BTreeMap::new();
+    for name in names {
+        let Some(pin) = registry.pins.get(&name) else {
+            changes.insert(name, Change::Missing);
+            continue;
+        };
+        let mut pin = pin.clone();
+        pin.metadata_mut().managed = Some(Manager::Registry);
+        let change = match pins.pins.insert(name.clone(), pin.clone()) {
+            Some(old) if old.is_managed() => {
+                Change::Synced(diff::diff(&old.properties(), &pin.properties()))
+            },
+            _ => Change::Adopted,
+        };
+        changes.insert(name, change);
+    }
+    Ok(changes)
+}
+
+/// The lock file in a checkout or other directory
+fn lock_file_in(directory: &Path) -> Result<PathBuf> {
+    ["sources.json", "npins/sources.json"]
+        .into_iter()
+        .map(|path| directory.join(path))
+        .find(|path| path.is_file())
+        .with_context(|| {
+            format!(
+                "Neither sources.json nor npins/sources.json found in {}",
+                directory.display()
+            )
+        })
+}
+
+/// Shallowly clone a git repository into a temporary directory
+async fn clone(url: &str) -> Result<tempdir::TempDir> {
+    provenance::record_endpoint(url);
+    let directory = tempdir::TempDir::new("registry")?;
+    log::debug!("Executing `git clone --depth 1 {}`", url);
+    let output = git::command()
+        .args(["clone", "--depth", "1", "--quiet", "--"])
+        .arg(url)
+        .arg(directory.path())
+        .output()
+        .await
+        .context("Failed to spawn git clone")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to clone {}\n{}",
+            url,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(directory)
+}
+
+/// Read the pins of a registry
+///
+/// `registry` is a local path to a lock file or a directory, an http(s) URL of a `.json` file, or
+/// else the URL of a git repository.
+pub async fn read(registry: &str) -> Result<NixPins> {
+    let contents = match registry.parse::<Url>() {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.path().ends_with(".json") => {
+            provenance::record_endpoint(&url);
+            http_get(url.clone())?
+                .send_throttled()
+                .await?
+                .error_for_status()
+                .with_context(|| format!("Failed to download {}", url))?
+                .text()
+                .await?
+        },
+        _ if Path::new(registry).is_file() => std::fs::read_to_string(registry)
+            .with_context(|| format!("Failed to read {}", registry))?,
+        _ if Path::new(registry).is_dir() => {
+            let path = lock_file_in(Path::new(registry))?;
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+        },
+        _ => {
+            let checkout = clone(registry).await?;
+            let path = lock_file_in(checkout.path())?;
+            std::fs::read_to_string(path)?
+        },
+    };
+    let raw: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(&contents).context("The registry's lock file is not a JSON object")?;
+    versions::from_value_upgraded(raw)
+        .with_context(|| format!("{} is not a valid registry", registry))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reconcile() {
+        let registry = NixPins {
+            pins: BTreeMap::from([
+                (
+                    "nixpkgs".into(),
+                    test_pin("https://example.org/nixpkgs-2.tar.gz"),
+                ),
+                ("rust".into(), test_pin("https://example.org/rust-2.tar.gz")),
+                ("go".into(), test_pin("https://example.org/go-2.tar.gz")),
+            ]),
+        };
+        let managed = |mut pin: Pin| {
+            pin.metadata_mut().managed = Some(Manager::Registry);
+            pin
+        };
+        let mut pins = NixPins {
+            pins: BTreeMap::from([
+                (
+                    "nixpkgs".into(),
+                    managed(test_pin("https://example.org/nixpkgs-1.tar.gz")),
+                ),
+                (
+                    "gone".into(),
+                    managed(test_pin("https://example.org/gone.tar.gz")),
+                ),
+                ("go".into(), test_pin("https://example.org/go-local.tar.gz")),
+                ("local".into(), test_pin("https://example.org/local.tar.gz")),
+            ]),
+        };
+
+        let changes = reconcile(&mut pins, &registry, &["rust".into()]).unwrap();
+        assert_eq!(
+            changes,
+            BTreeMap::from([
+                (
+                    "nixpkgs".into(),
+                    Change::Synced(diff::diff(
+                        &[("url".into(), "https://example.org/nixpkgs-1.tar.gz".into())],
+                        &[("url".into(), "https://example.org/nixpkgs-2.tar.gz".into())],
+                    ))
+                ),
+                ("gone".into(), Change::Missing),
+                ("rust".into(), Change::Adopted),
+            ])
+        );
+        assert_eq!(
+            pins.pins["rust"],
+            managed(test_pin("https://example.org/rust-2.tar.gz"))
+        );
+        /* Local pins stay local, even if the registry has one with the same name */
+        assert_eq!(
+            pins.pins["go"],
+            test_pin("https://example.org/go-local.tar.gz")
+        );
+        assert_eq!(pins.pins.len(), 5);
+
+        assert!(reconcile(&mut pins, &registry, &["nope".into()]).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Run prefetches on another machine
+//!
+//! With `--prefetch-on ssh://builder`, the `nix-prefetch-*` commands that compute the hashes run on
+//! that machine via SSH, since they download the whole source. Everything else, i.e. finding out the
+//! latest versions, still happens locally. Prefetches of local files, like downloads that needed
+//! credentials, also stay local.
+
+use anyhow::{Context, Result};
+use std::{process::Output, str::FromStr, sync::OnceLock};
+use url::Url;
+
+/// A machine to prefetch on, as `ssh://[user@]host[:port]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Builder {
+    url: Url,
+}
+
+impl FromStr for Builder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let url: Url = s
+            .parse()
+            .with_context(|| format!("'{}' is not of the form ssh://[user@]host[:port]", s))?;
+        anyhow::ensure!(
+            url.scheme() == "ssh",
+            "Only ssh:// builders are supported, not '{}'",
+            s
+        );
+        anyhow::ensure!(url.has_host(), "'{}' is missing the host", s);
+        anyhow::ensure!(
+            matches!(url.path(), "" | "/") && url.query().is_none() && url.password().is_none(),
+            "'{}' must not have a path, query or password",
+            s
+        );
+        Ok(Builder { url })
+    }
+}
+
+impl Builder {
+    /// The destination as `ssh` takes it, which understands `ssh://` URIs itself
+    fn destination(&self) -> String {
+        let mut url = self.url.clone();
+        url.set_path("");
+        url.to_string()
+    }
+}
+
+static BUILDER: OnceLock<Builder> = OnceLock::new();
+
+/// Prefetch on `builder` from now on, must be called before any prefetching
+pub fn set_builder(builder: Builder) {
+    BUILDER
+        .set(builder)
+        .expect("The builder to prefetch on is already set");
+}
+
+/// Whether prefetches happen on another machine
+pub fn is_set() -> bool {
+    BUILDER.get().is_some()
+}
+
+/// Quote an argument for the remote shell, which is what `ssh` passes the command line to
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@+,%".contains(c))
+    {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// A prefetch command, which runs on the builder if there is one
+pub struct Command {
+    program: String,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    builder: Option<&'static Builder>,
+}
+
+impl Command {
+    pub fn new(program: impl Into<String>) -> Self {
+        Command {
+            program: program.into(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            builder: BUILDER.get(),
+        }
+    }
+
+    /// Run this locally in any case, e.g. because it needs local files
+    pub fn local(&mut self) -> &mut Self {
+        self.builder = None;
+        self
+    }
+
+    /// Whether this runs on another machine, so that any paths it outputs aren't local ones
+    pub fn is_remote(&self) -> bool {
+        self.builder.is_some()
+    }
+
+    pub fn arg(&mut self, arg: impl Into<String>) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// The command line to run on the builder
+    fn remote_command(&self) -> String {
+        let envs = self
+            .envs
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value));
+        let command = std::iter::once(self.program.clone()).chain(self.args.iter().cloned());
+        let words = if self.envs.is_empty() {
+            command.collect::<Vec<_>>()
+        } else {
+            std::iter::once("env".to_owned())
+                .chain(envs)
+                .chain(command)
+                .collect()
+        };
+        words
+            .iter()
+            .map(|word| shell_quote(word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub async fn output(&self) -> std::io::Result<Output> {
+        match self.builder {
+            None => {
+                let mut command = tokio::process::Command::new(&self.program);
+                /* Keep the temporary files of nix-prefetch-git and others with ours */
+                if let Ok(directory) = crate::tempdir::session() {
+                    command.env("TMPDIR", directory);
+                }
+                command
+                    .kill_on_drop(true)
+                    .args(&self.args)
+                    .envs(self.envs.iter().map(|(key, value)| (key, value)))
+                    .output()
+                    .await
+            },
+            Some(builder) => {
+                let command = self.remote_command();
+                log::debug!("Running `{}` on {}", command, builder.destination());
+                tokio::process::Command::new("ssh")
+                    .kill_on_drop(true)
+                    /* Fail instead of asking for passwords or host key confirmations */
+                    .args(["-o", "BatchMode=yes", "--"])
+                    .arg(builder.destination())
+                    .arg(command)
+                    .output()
+                    .await
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let builder: Builder = "ssh://nix@builder.example.org:2222".parse().unwrap();
+        assert_eq!(builder.destination(), "ssh://nix@builder.example.org:2222");
+        assert!("builder.example.org".parse::<Builder>().is_err());
+        assert!("https://builder.example.org".parse::<Builder>().is_err());
+        assert!("ssh://builder.example.org/nix/store"
+            .parse::<Builder>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_remote_command() {
+        let mut command = Command::new("nix-prefetch-git");
+        command
+            .env("GIT_SSH_COMMAND", "ssh -o StrictHostKeyChecking=yes")
+            .arg("https://example.org/it's.git")
+            .arg("refs/heads/main");
+        assert_eq!(
+            command.remote_command(),
+            r"env 'GIT_SSH_COMMAND=ssh -o StrictHostKeyChecking=yes' nix-prefetch-git 'https://example.org/it'\''s.git' refs/heads/main"
+        );
+    }
+}
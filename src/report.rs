@@ -0,0 +1,139 @@
+//! Reports of update runs, so that a failed run can be retried without redoing everything
+//!
+//! `npins update --report` keeps going when some pins fail, and records for each pin whether it
+//! succeeded. For failed pins, it also records the version they were being updated to, if they got
+//! that far. `npins update --resume` then only retries the failed pins, and fetches those at
+//! the recorded version instead of looking for a new one.
+//!
+//! Pins that were updated without some extras, like commit timestamps when the forge's API was
+//! down, succeed as well. The report lists what is missing for them.
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Outcome {
+    Succeeded {
+        /// What could not be looked up, see [`provenance::record_missing`]
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        missing: Vec<String>,
+    },
+    Failed {
+        error: String,
+        /// The pin with the version it was being updated to, if that was found before failing
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resolved: Option<Box<Pin>>,
+    },
+}
+
+impl Outcome {
+    /// `before` and `after` are the pin before and after the failed update
+    pub fn failed(err: &anyhow::Error, before: &Pin, after: &Pin) -> Self {
+        Outcome::Failed {
+            error: format!("{err:#}"),
+            /* A failed update leaves the pin unchanged, so it was the fetching that failed */
+            resolved: (after != before).then(|| Box::new(after.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub pins: BTreeMap<String, Outcome>,
+}
+
+impl Report {
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("{} is not a valid report", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The pins that failed, along with the version to retry them with if there is one
+    pub fn failed(&self) -> impl Iterator<Item = (&str, Option<&Pin>)> {
+        self.pins
+            .iter()
+            .filter_map(|(name, outcome)| match outcome {
+                Outcome::Succeeded { .. } => None,
+                Outcome::Failed { resolved, .. } => Some((name.as_str(), resolved.as_deref())),
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let before = test_pin("https://example.org/tool.tar.gz");
+        let Pin::Tarball { input, .. } = before.clone() else {
+            unreachable!()
+        };
+        let after = Pin::Tarball {
+            input,
+            version: Some(tarball::LockedTarball {
+                locked_url: None,
+                resolved_url: None,
+                etag: Some("\"2\"".into()),
+                last_modified: None,
+            }),
+            hashes: None,
+            metadata: Default::default(),
+        };
+        let err = anyhow::format_err!("connection reset");
+
+        let mut report = Report::default();
+        report
+            .pins
+            .insert("a".into(), Outcome::Succeeded { missing: vec![] });
+        report
+            .pins
+            .insert("b".into(), Outcome::failed(&err, &before, &before));
+        report
+            .pins
+            .insert("c".into(), Outcome::failed(&err, &before, &after));
+        report.pins.insert(
+            "d".into(),
+            Outcome::Succeeded {
+                missing: vec!["timestamp".into()],
+            },
+        );
+        assert_eq!(
+            report.failed().collect::<Vec<_>>(),
+            vec![("b", None), ("c", Some(&after))]
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "pins": {
+                    "a": { "status": "succeeded" },
+                    "b": { "status": "failed", "error": "connection reset" },
+                    "c": {
+                        "status": "failed",
+                        "error": "connection reset",
+                        "resolved": {
+                            "type": "Tarball",
+                            "url": "https://example.org/tool.tar.gz",
+                            "etag": "\"2\"",
+                        },
+                    },
+                    "d": { "status": "succeeded", "missing": ["timestamp"] },
+                }
+            })
+        );
+        assert_eq!(serde_json::from_value::<Report>(json).unwrap(), report);
+    }
+}
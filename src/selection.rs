@@ -0,0 +1,98 @@
+//! Select pins by glob patterns and regular expressions instead of exact names
+
+use crate::*;
+use anyhow::{Context, Result};
+
+/// Whether a name is a glob pattern rather than the name of a single pin
+fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Expand glob patterns (`*` and `?`) and regular expressions into the names of the pins
+/// matching them
+///
+/// Plain names are passed through as they are, even if there is no such pin or they are given
+/// twice, so that the caller can complain about them. Pins matched by patterns are only added
+/// once. Regular expressions match anywhere in the name unless anchored.
+pub fn expand<'a>(
+    pins: impl IntoIterator<Item = &'a String> + Clone,
+    names: &[String],
+    regexes: &[String],
+) -> Result<Vec<String>> {
+    let mut selected: Vec<String> = Vec::new();
+
+    for name in names {
+        if !is_pattern(name) {
+            selected.push(name.clone());
+            continue;
+        }
+        let matching = pins
+            .clone()
+            .into_iter()
+            .filter(|pin| asset::glob_matches(name, pin))
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            log::warn!("No pins match '{name}'");
+        }
+        for name in matching {
+            if !selected.contains(name) {
+                selected.push(name.clone());
+            }
+        }
+    }
+
+    for regex in regexes {
+        let compiled = regex::Regex::new(regex)
+            .with_context(|| format!("'{}' is not a valid regular expression", regex))?;
+        let matching = pins
+            .clone()
+            .into_iter()
+            .filter(|pin| compiled.is_match(pin))
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            log::warn!("No pins match the regular expression '{regex}'");
+        }
+        for name in matching {
+            if !selected.contains(name) {
+                selected.push(name.clone());
+            }
+        }
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let pins = ["nixpkgs", "nixpkgs-stable", "lib-a", "lib-b", "tool"]
+            .map(String::from)
+            .to_vec();
+        let expand = |names: &[&str], regexes: &[&str]| {
+            expand(
+                &pins,
+                &names
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect::<Vec<_>>(),
+                &regexes
+                    .iter()
+                    .map(|regex| regex.to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(
+            expand(&["nixpkgs*", "tool"], &["^lib-"]).unwrap(),
+            ["nixpkgs", "nixpkgs-stable", "tool", "lib-a", "lib-b"]
+        );
+        assert_eq!(
+            expand(&["missing", "tool", "tool"], &["pkgs$"]).unwrap(),
+            ["missing", "tool", "tool", "nixpkgs"]
+        );
+        assert!(expand(&["x*"], &[]).unwrap().is_empty());
+        assert!(expand(&[], &["("]).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! Detached signatures of lock files, to refuse unauthorized edits
+//!
+//! The signature file sits next to the lock file (`sources.json.sig`) and has one line per
+//! signature, `<key name>:<base64 of the Ed25519 signature>`, like the signatures of Nix store
+//! paths. The keys are the same as for attestations (see [`attestation`]).
+//!
+//! What gets signed is a canonical form of the lock file: its JSON with sorted keys and without
+//! whitespace, so that merely reformatting the lock file keeps the signature valid.
+
+use crate::attestation::{PublicKey, SigningKey};
+use anyhow::{Context, Result};
+use data_encoding::BASE64;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Separates the signed message from other uses of the same key
+const DOMAIN: &[u8] = b"npins-lock-file-v1\n";
+
+/// `<lock file>.sig`
+pub fn path_for(lock_file: &Path) -> PathBuf {
+    let mut path = lock_file.as_os_str().to_owned();
+    path.push(".sig");
+    path.into()
+}
+
+fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by_key(|(key, _)| *key);
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.clone(), sorted(value)))
+                    .collect(),
+            )
+        },
+        Value::Array(values) => Value::Array(values.iter().map(sorted).collect()),
+        other => other.clone(),
+    }
+}
+
+/// The message that is signed for a lock file
+fn message(lock_file: &[u8]) -> Result<Vec<u8>> {
+    let value: Value = serde_json::from_slice(lock_file).context("The lock file is not JSON")?;
+    let mut message = DOMAIN.to_vec();
+    message.extend(serde_json::to_vec(&sorted(&value))?);
+    Ok(message)
+}
+
+/// A signature line for the lock file
+pub fn sign(lock_file: &[u8], key: &SigningKey) -> Result<String> {
+    let signature = key.sign_message(&message(lock_file)?);
+    Ok(format!("{}:{}", key.name, BASE64.encode(&signature)))
+}
+
+/// Check that one of the signatures is by one of the trusted keys, returning the key's name
+pub fn verify(lock_file: &[u8], signatures: &str, keys: &[PublicKey]) -> Result<String> {
+    let message = message(lock_file)?;
+    for line in signatures.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((name, signature)) = line.trim().split_once(':') else {
+            continue;
+        };
+        let Ok(signature) = BASE64.decode(signature.as_bytes()) else {
+            continue;
+        };
+        if keys
+            .iter()
+            .any(|key| key.name == name && key.verifies(&message, &signature))
+        {
+            return Ok(name.to_owned());
+        }
+    }
+    anyhow::bail!("The lock file is not signed by any of the trusted keys, or it was changed since")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /* The keys of the attestation tests */
+    const SECRET_KEY: &str = "test-1:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8DoQe/884Qvh1w3RjnS8CZZ+TWMJulDV8d3IZkElUxuA==";
+    const PUBLIC_KEY: &str = "test-1:A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=";
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key: SigningKey = SECRET_KEY.parse().unwrap();
+        let keys = [PUBLIC_KEY.parse().unwrap()];
+        let lock_file =
+            br#"{"pins": {"a": {"type": "File", "hash": "sha256-AAAA"}}, "version": 6}"#;
+        let signatures = format!("other:AAAA\n{}\n", sign(lock_file, &key).unwrap());
+
+        assert_eq!(verify(lock_file, &signatures, &keys).unwrap(), "test-1");
+        /* Formatting doesn't matter */
+        let reformatted =
+            b"{\"version\":6,\n\"pins\":{\"a\":{\"hash\":\"sha256-AAAA\",\"type\":\"File\"}}}";
+        assert!(verify(reformatted, &signatures, &keys).is_ok());
+
+        let changed = br#"{"pins": {"a": {"type": "File", "hash": "sha256-BBBB"}}, "version": 6}"#;
+        assert!(verify(changed, &signatures, &keys).is_err());
+        assert!(verify(lock_file, "", &keys).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Share single pins between repositories
+//!
+//! A snippet is simply a lock file with only one pin in it, including its version and hashes. This
+//! way it carries its format version, so that snippets from older versions of npins can be
+//! upgraded on import like any lock file.
+
+use crate::*;
+use anyhow::{Context, Result};
+use url::Url;
+
+/// Serialize a pin as a snippet
+///
+/// Credentials would be leaked to whoever the snippet is shared with, so private tokens are
+/// removed, and URLs with credentials in them are refused.
+pub fn export(name: &str, pin: &Pin) -> Result<String> {
+    let mut pin = pin.clone();
+    if let Some(git::Repository::GitLab { private_token, .. }) = pin.repository_mut() {
+        if private_token.take().is_some() {
+            log::warn!("Leaving out the private token of '{name}', it needs to be set again after importing");
+        }
+    }
+    if let Some(finding) = check::check_pin(&pin).first() {
+        anyhow::bail!(
+            "Refusing to export '{name}', as {finding}. {}",
+            check::CREDENTIALS_ADVICE
+        );
+    }
+    let snippet = NixPins {
+        pins: BTreeMap::from([(name.to_owned(), pin)]),
+    };
+    let mut json = serde_json::to_string_pretty(&snippet.to_value_versioned())?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Parse a snippet, upgrading it to the current format version if necessary
+pub fn parse(snippet: &str) -> Result<(String, Pin)> {
+    let raw: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(snippet).context("A snippet must be a JSON object")?;
+    let pins = versions::from_value_upgraded(raw).context("Invalid pin in the snippet")?;
+    let mut pins = pins.pins.into_iter();
+    match (pins.next(), pins.next()) {
+        (Some(pin), None) => Ok(pin),
+        (None, _) => anyhow::bail!("The snippet contains no pin"),
+        (Some(_), Some(_)) => {
+            anyhow::bail!("The snippet contains more than one pin, it looks like a whole lock file")
+        },
+    }
+}
+
+/// Read a snippet from a file, an `http(s)://` URL or stdin (`-`)
+pub async fn read(source: &str) -> Result<(String, Pin)> {
+    let snippet = match source.parse::<Url>() {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {
+            http_get(url.clone())?
+                .send_throttled()
+                .await?
+                .error_for_status()
+                .with_context(|| format!("Failed to download {}", url))?
+                .text()
+                .await?
+        },
+        _ if source == "-" => std::io::read_to_string(std::io::stdin())
+            .context("Failed to read the snippet from stdin")?,
+        _ => {
+            std::fs::read_to_string(source).with_context(|| format!("Failed to read {}", source))?
+        },
+    };
+    parse(&snippet).with_context(|| format!("{} is not a valid pin snippet", source))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snippet() {
+        let pin: Pin = git::GitPin::new(
+            git::Repository::gitlab("owner/repo".into(), None, Some("glpat-secret".into())),
+            "main".into(),
+            false,
+        )
+        .into();
+        let snippet = export("repo", &pin).unwrap();
+        assert!(!snippet.contains("glpat-secret"));
+        let (name, imported) = parse(&snippet).unwrap();
+        assert_eq!(name, "repo");
+        assert_eq!(
+            imported.repository(),
+            Some(&git::Repository::gitlab("owner/repo".into(), None, None))
+        );
+
+        let leaky = test_pin("https://example.org/tool.tar.gz?token=hunter2");
+        assert!(export("leaky", &leaky).is_err());
+
+        assert!(parse(r#"{"pins": {}, "version": 6}"#).is_err());
+        assert!(parse(r#"{"pins": {"a": {"type": "Nope"}}, "version": 6}"#).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Pin Subversion repositories
+//!
+//! A pin tracks a URL within a repository, usually its trunk, a branch or a tag. It is locked to
+//! the revision that last changed anything below that URL, as reported by `svn info`, so that
+//! commits elsewhere in the repository don't cause spurious updates. The hash is computed by
+//! `nix-prefetch-svn`, and is the one `fetchsvn` from Nixpkgs expects.
+
+use crate::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct SvnPin {
+    /// E.g. `https://svn.example.org/repos/tool/trunk`
+    pub url: Url,
+}
+
+impl diff::Diff for SvnPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![("url".into(), self.url.to_string())]
+    }
+}
+
+impl SvnPin {
+    /// The project's name, i.e. the path segment before the usual `trunk`, `branches/…` or `tags/…`
+    pub fn guess_name(&self) -> Option<String> {
+        let segments = self
+            .url
+            .path_segments()?
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let project = match segments.as_slice() {
+            [.., project, "trunk"] => project,
+            [.., project, "branches" | "tags", _] => project,
+            [.., last] => last,
+            [] => return None,
+        };
+        Some(project.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SvnRevision {
+    /// The revision that last changed the tracked URL
+    pub revision: u64,
+}
+
+impl diff::Diff for SvnRevision {
+    fn properties(&self) -> Vec<(String, String)> {
+        vec![("revision".into(), self.revision.to_string())]
+    }
+}
+
+/// The revision that last changed something below `url`
+async fn last_changed_revision(url: &Url) -> Result<u64> {
+    crate::provenance::record_endpoint(url.as_str());
+    log::debug!(
+        "Executing `svn info --non-interactive --show-item last-changed-revision {}`",
+        url
+    );
+    let output = tokio::process::Command::new("svn")
+        .kill_on_drop(true)
+        .args([
+            "info",
+            "--non-interactive",
+            "--show-item",
+            "last-changed-revision",
+        ])
+        .arg(url.as_str())
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn svn for {}", url))?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "svn info failed for {}\n{}",
+            url,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    parse_revision(&String::from_utf8_lossy(&output.stdout))
+        .with_context(|| format!("Unexpected output of svn info for {}", url))
+}
+
+fn parse_revision(output: &str) -> Result<u64> {
+    let revision = output.trim();
+    revision
+        .parse()
+        .with_context(|| format!("'{}' is not a revision number", revision))
+}
+
+#[async_trait::async_trait]
+impl Updatable for SvnPin {
+    type Version = SvnRevision;
+    type Hashes = GenericHash;
+
+    async fn update(&self, old: Option<&SvnRevision>) -> Result<SvnRevision> {
+        let revision = last_changed_revision(&self.url).await?;
+        if let Some(old) = old {
+            anyhow::ensure!(
+                revision >= old.revision,
+                "Failed to ensure monotonicity, the latest revision is r{} but the current one is r{}",
+                revision,
+                old.revision,
+            );
+        }
+        Ok(SvnRevision { revision })
+    }
+
+    async fn fetch(&self, version: &SvnRevision) -> Result<GenericHash> {
+        Ok(GenericHash {
+            hash: nix::nix_prefetch_svn(&self.url, version.revision).await?,
+            archive: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_guess_name() {
+        let name = |url: &str| {
+            SvnPin {
+                url: url.parse().unwrap(),
+            }
+            .guess_name()
+        };
+        assert_eq!(
+            name("https://svn.example.org/repos/tool/trunk").as_deref(),
+            Some("tool")
+        );
+        assert_eq!(
+            name("https://svn.example.org/repos/tool/branches/1.x/").as_deref(),
+            Some("tool")
+        );
+        assert_eq!(
+            name("svn://svn.example.org/tool/tags/v2.0").as_deref(),
+            Some("tool")
+        );
+        assert_eq!(
+            name("https://svn.example.org/repos/tool").as_deref(),
+            Some("tool")
+        );
+    }
+
+    #[test]
+    fn test_parse_revision() {
+        assert_eq!(parse_revision("1234\n").unwrap(), 1234);
+        assert!(parse_revision("\n").is_err());
+    }
+}
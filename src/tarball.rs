@@ -2,6 +2,10 @@
 //!
 //! Optionally (if the host supports it) can use the "Lockable HTTP Tarball Protocol" from flakes.
 //! Reference: <https://github.com/nixos/nix/blob/56763ff918eb308db23080e560ed2ea3e00c80a7/doc/manual/src/protocols/tarball-fetcher.md>
+//!
+//! Otherwise, redirects are followed and the URL they end up at is pinned, together with the
+//! `ETag` and `Last-Modified` headers. This makes "latest" URLs like `…/foo-latest.tar.gz` usable,
+//! and catches upstreams which replace the contents of a release without changing its URL.
 
 use anyhow::{Context, Result};
 use reqwest::header::HeaderName;
@@ -14,11 +18,65 @@ use crate::*;
 pub struct TarballPin {
     /// URL provided as user input
     pub url: Url,
+    /// Like `tar --strip-components`. The fetchers always strip the archive's root directory, so
+    /// this must be at least 1, the default. Each further component must be the only directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip_components: Option<u32>,
+    /// Only use this path within the (stripped) archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subpath: Option<String>,
 }
 
 impl diff::Diff for TarballPin {
     fn properties(&self) -> Vec<(String, String)> {
-        vec![("url".into(), self.url.to_string())]
+        [
+            ("url", Some(self.url.to_string())),
+            (
+                "strip_components",
+                self.strip_components.map(|n| n.to_string()),
+            ),
+            ("subpath", self.subpath.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.into(), value?)))
+        .collect()
+    }
+}
+
+impl TarballPin {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            strip_components: None,
+            subpath: None,
+        }
+    }
+
+    /// Find the part of the unpacked archive the pin stands for, the same way the Nix side does
+    fn locate(&self, root: &std::path::Path) -> Result<std::path::PathBuf> {
+        let mut path = root.to_owned();
+        for _ in 1..self.strip_components.unwrap_or(1) {
+            let entries = std::fs::read_dir(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .collect::<std::io::Result<Vec<_>>>()?;
+            let [entry] = &entries[..] else {
+                anyhow::bail!(
+                    "Can't strip another component, as the archive has {} entries instead of one at {}",
+                    entries.len(),
+                    path.strip_prefix(root).unwrap_or(&path).display(),
+                );
+            };
+            path = entry.path();
+        }
+        if let Some(subpath) = &self.subpath {
+            path.push(subpath.trim_start_matches('/'));
+            anyhow::ensure!(
+                path.exists(),
+                "The archive has no {}",
+                path.strip_prefix(root).unwrap_or(&path).display()
+            );
+        }
+        Ok(path)
     }
 }
 
@@ -28,14 +86,67 @@ pub struct LockedTarball {
     /// flakeref here
     #[serde(skip_serializing_if = "Option::is_none")]
     pub locked_url: Option<Url>,
+    /// Where the URL redirected to, if anywhere. Not set when there is a `locked_url`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_url: Option<Url>,
+    /// The `ETag` header of the (resolved) URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// The `Last-Modified` header of the (resolved) URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl LockedTarball {
+    /// The URL to actually download
+    pub fn url<'a>(&'a self, pin: &'a TarballPin) -> &'a Url {
+        self.locked_url
+            .as_ref()
+            .or(self.resolved_url.as_ref())
+            .unwrap_or(&pin.url)
+    }
+
+    /// Make sure that the contents of an URL only changed together with the URL
+    ///
+    /// If the pinned URL redirects, the URL it redirects to is assumed to stand for one release. A
+    /// different `ETag` or `Last-Modified` date for it means that the release was replaced.
+    fn check_unchanged(&self, old: &LockedTarball) -> Result<()> {
+        let Some(resolved_url) = self.resolved_url.as_ref() else {
+            /* The URL is all there is, so any change might be a new release */
+            return Ok(());
+        };
+        if old.resolved_url.as_ref() != Some(resolved_url) {
+            return Ok(());
+        }
+        let changed = |new: &Option<String>, old: &Option<String>| matches!((new, old), (Some(new), Some(old)) if new != old);
+        anyhow::ensure!(
+            !changed(&self.etag, &old.etag) && !changed(&self.last_modified, &old.last_modified),
+            "The contents of {} changed without its URL changing (ETag {} → {}, Last-Modified {} → {}). \
+             If this is expected, remove the `etag` and `last_modified` fields from the pin",
+            resolved_url,
+            old.etag.as_deref().unwrap_or("none"),
+            self.etag.as_deref().unwrap_or("none"),
+            old.last_modified.as_deref().unwrap_or("none"),
+            self.last_modified.as_deref().unwrap_or("none"),
+        );
+        Ok(())
+    }
 }
 
 impl diff::Diff for LockedTarball {
     fn properties(&self) -> Vec<(String, String)> {
-        self.locked_url
-            .iter()
-            .map(|locked_url| ("locked_url".into(), locked_url.to_string()))
-            .collect()
+        [
+            ("locked_url", self.locked_url.as_ref().map(Url::to_string)),
+            (
+                "resolved_url",
+                self.resolved_url.as_ref().map(Url::to_string),
+            ),
+            ("etag", self.etag.clone()),
+            ("last_modified", self.last_modified.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.into(), value?)))
+        .collect()
     }
 }
 
@@ -50,12 +161,11 @@ impl Updatable for TarballPin {
         // Attempt to use the Lockable HTTP Tarball Protocol, if that fails (the
         // expected Link header is missing) we fail back to using whatever was
         // the input.
-        let headers = build_client()?
-            .head(self.url.clone())
-            .send()
-            .await?
-            .headers()
-            .clone();
+        provenance::record_endpoint(&self.url);
+        let response = http_request(reqwest::Method::HEAD, self.url.clone())?
+            .send_throttled()
+            .await?;
+        let headers = response.headers();
         let flakerefs = headers
             .get_all(LINK)
             .into_iter()
@@ -86,12 +196,108 @@ impl Updatable for TarballPin {
                 None
             }
         };
-        Ok(LockedTarball { locked_url })
+        if locked_url.is_some() {
+            return Ok(LockedTarball {
+                locked_url,
+                resolved_url: None,
+                etag: None,
+                last_modified: None,
+            });
+        }
+
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned)
+        };
+        let resolved_url = Some(response.url()).filter(|&url| *url != self.url);
+        let version = LockedTarball {
+            locked_url: None,
+            /* Download links with a query are most likely signed and will expire */
+            resolved_url: resolved_url.filter(|url| url.query().is_none()).cloned(),
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        };
+        if let Some(resolved_url) = resolved_url.filter(|_| version.resolved_url.is_none()) {
+            log::debug!("Not pinning {resolved_url}, as it looks like a temporary URL");
+        }
+        if let Some(old) = old {
+            version.check_unchanged(old)?;
+        }
+        Ok(version)
     }
 
     async fn fetch(&self, version: &LockedTarball) -> Result<GenericHash> {
-        let url = version.locked_url.as_ref().unwrap_or(&self.url);
-        let hash = nix::nix_prefetch_tarball(&url).await?;
-        Ok(GenericHash { hash })
+        anyhow::ensure!(
+            self.strip_components != Some(0),
+            "The archive's root directory is always stripped, strip_components can't be 0"
+        );
+        let url = version.url(self);
+        let nix::PrefetchInfo { hash, store_path } = nix::nix_prefetch_tarball_info(&url).await?;
+        /* The hash is of the whole archive, but make sure that the pin points to something */
+        match store_path {
+            Some(store_path) => {
+                self.locate(&store_path)?;
+            },
+            None => log::debug!(
+                "Not checking the contents of {}, it was prefetched remotely",
+                url
+            ),
+        }
+        let archive = nix::archive_format(url, &[]).await?;
+        Ok(GenericHash { hash, archive })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        let root = std::env::temp_dir().join(format!("npins-test-locate-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("dist/tool-1.0/bin")).unwrap();
+        std::fs::create_dir_all(root.join("dist/tool-1.0/share")).unwrap();
+
+        let mut pin = TarballPin::new("https://example.org/tool.tar.gz".parse().unwrap());
+        assert_eq!(pin.locate(&root).unwrap(), root);
+        pin.strip_components = Some(3);
+        assert_eq!(pin.locate(&root).unwrap(), root.join("dist/tool-1.0"));
+        pin.subpath = Some("bin".into());
+        assert_eq!(pin.locate(&root).unwrap(), root.join("dist/tool-1.0/bin"));
+        pin.subpath = Some("lib".into());
+        assert!(pin.locate(&root).is_err());
+        /* `bin` and `share` */
+        pin.strip_components = Some(4);
+        assert!(pin.locate(&root).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_check_unchanged() {
+        let version = |resolved_url: Option<&str>, etag: &str| LockedTarball {
+            locked_url: None,
+            resolved_url: resolved_url.map(|url| url.parse().unwrap()),
+            etag: Some(etag.into()),
+            last_modified: None,
+        };
+        let release = Some("https://example.org/foo-1.0.tar.gz");
+
+        /* A new release behind the same "latest" URL */
+        assert!(version(None, "b")
+            .check_unchanged(&version(None, "a"))
+            .is_ok());
+        assert!(version(Some("https://example.org/foo-1.1.tar.gz"), "b")
+            .check_unchanged(&version(release, "a"))
+            .is_ok());
+        /* The same release, but different contents */
+        assert!(version(release, "a")
+            .check_unchanged(&version(release, "a"))
+            .is_ok());
+        assert!(version(release, "b")
+            .check_unchanged(&version(release, "a"))
+            .is_err());
     }
 }
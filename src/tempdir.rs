@@ -0,0 +1,299 @@
+//! Temporary files and directories, kept in one place so that nothing is left behind
+//!
+//! Everything npins needs for a short while goes into a directory of its own per process,
+//! `npins-<pid>` in `$NPINS_TMPDIR` or else the system's temporary directory: clones,
+//! downloads, and the temporary files of the processes npins runs, like the clones of
+//! `nix-prefetch-git`. [`TempDir`] and [`TempFile`] remove themselves when dropped, and
+//! [`clean_up`] removes the whole directory when npins exits or is interrupted.
+//!
+//! A crashed or killed npins can't clean up after itself, though. Every such directory holds a
+//! lock for as long as its process runs, so [`stale`] can tell which ones are left over for
+//! `npins clean-temp` to remove.
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+const LOCK: &str = ".lock";
+
+/// Where the directories of all npins processes are
+pub fn root() -> PathBuf {
+    crate::config::environment("NPINS_TMPDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+struct Session {
+    directory: PathBuf,
+    /// Locked until the process exits, or the directory is removed
+    lock: Mutex<Option<File>>,
+}
+
+static SESSION: OnceLock<Result<Session, String>> = OnceLock::new();
+
+/// Create and lock the directory of process `pid` in `root`
+///
+/// That is `npins-<pid>`, unless it is still in use by another process with the same id, like
+/// one in another PID namespace or on another machine sharing `root`. Then it is
+/// `npins-<pid>-<n>`, with the first `n` that is free.
+fn claim(root: &Path, pid: u32) -> Result<(PathBuf, File)> {
+    std::fs::create_dir_all(root)?;
+    for n in 0.. {
+        let directory = match n {
+            0 => root.join(format!("npins-{pid}")),
+            n => root.join(format!("npins-{pid}-{n}")),
+        };
+        if directory.exists() {
+            /* Left over from an earlier process with the same id, unless somebody holds the lock */
+            let Ok(lock) = File::open(directory.join(LOCK)) else {
+                continue;
+            };
+            if lock.try_lock().is_err() {
+                continue;
+            }
+            drop(lock);
+            std::fs::remove_dir_all(&directory)?;
+        }
+        match std::fs::create_dir(&directory) {
+            Ok(()) => {},
+            /* Claimed by another process in the meantime */
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+        let lock = File::create(directory.join(LOCK))?;
+        lock.try_lock()?;
+        return Ok((directory, lock));
+    }
+    unreachable!()
+}
+
+fn start() -> Result<Session> {
+    let (directory, lock) = claim(&root(), std::process::id())?;
+    Ok(Session {
+        directory,
+        lock: Mutex::new(Some(lock)),
+    })
+}
+
+/// The directory of this process, created on first use
+pub fn session() -> Result<&'static Path> {
+    match SESSION.get_or_init(|| {
+        start()
+            .with_context(|| {
+                format!(
+                    "Failed to create a temporary directory in {}, set NPINS_TMPDIR to use another one",
+                    root().display()
+                )
+            })
+            .map_err(|err| format!("{err:#}"))
+    }) {
+        Ok(session) => Ok(&session.directory),
+        Err(err) => Err(anyhow::format_err!("{err}")),
+    }
+}
+
+/// Remove the directory of this process, if it was ever created
+pub fn clean_up() {
+    if let Some(Ok(session)) = SESSION.get() {
+        /* Windows doesn't remove open files */
+        drop(session.lock.lock().unwrap().take());
+        if let Err(err) = std::fs::remove_dir_all(&session.directory) {
+            log::debug!("Failed to remove {}: {err}", session.directory.display());
+        }
+    }
+}
+
+/// A unique path in the directory of this process, `name` goes into it
+fn unique(name: &str) -> Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    Ok(session()?.join(format!(
+        "{}-{name}",
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )))
+}
+
+/// A temporary directory, removed with everything in it when dropped
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Result<Self> {
+        let path = unique(name)?;
+        std::fs::create_dir(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(TempDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.path).ok();
+    }
+}
+
+/// A path for a temporary file, which is removed when dropped if it was created
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(name: &str) -> Result<Self> {
+        Ok(TempFile {
+            path: unique(name)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// The directories of npins processes in `root` that are no longer running
+pub fn stale(root: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", root.display())),
+    };
+    let mut stale = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+        /* `npins-<pid>` or `npins-<pid>-<n>`, see `claim` */
+        let is_session = path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_prefix("npins-"))
+            .is_some_and(|suffix| {
+                let (pid, n) = suffix.split_once('-').unwrap_or((suffix, "0"));
+                is_number(pid) && is_number(n)
+            });
+        if !is_session || !path.is_dir() {
+            continue;
+        }
+        /* Another user's, or otherwise none of our business */
+        let Ok(lock) = File::open(path.join(LOCK)) else {
+            continue;
+        };
+        if lock.try_lock().is_ok() {
+            stale.push(path);
+        }
+    }
+    stale.sort();
+    Ok(stale)
+}
+
+/// How many bytes are free on the file system of `path`, if that can be told
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    /* SAFETY: `path` is a valid C string, and `stat` is only read if it was filled in */
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast, clippy::useless_conversion)]
+    Some(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn mebibytes(bytes: u64) -> String {
+    format!("{} MiB", bytes.div_ceil(1024 * 1024))
+}
+
+/// Make sure that there is room for `size` bytes of `what` in the temporary directory
+///
+/// Checked before large downloads, so that they fail right away instead of once the disk is
+/// full. Keeps some space to spare, for unpacking and for everybody else.
+pub fn ensure_space(size: u64, what: impl std::fmt::Display) -> Result<()> {
+    const SPARE: u64 = 256 * 1024 * 1024;
+
+    let directory = session()?;
+    let Some(available) = available_space(directory) else {
+        return Ok(());
+    };
+    anyhow::ensure!(
+        available >= size.saturating_add(SPARE),
+        "{what} is {}, but only {} are free in {}. Set NPINS_TMPDIR to a directory with more space",
+        mebibytes(size),
+        mebibytes(available),
+        root().display(),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stale() {
+        let root = std::env::temp_dir().join(format!("npins-test-stale-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        for name in ["npins-1", "npins-2", "npins-3", "npins-range-4"] {
+            std::fs::create_dir_all(root.join(name)).unwrap();
+            File::create(root.join(name).join(LOCK)).unwrap();
+        }
+        /* Still running */
+        let running = File::open(root.join("npins-2").join(LOCK)).unwrap();
+        running.try_lock().unwrap();
+        /* Not one of ours */
+        std::fs::remove_file(root.join("npins-3").join(LOCK)).unwrap();
+
+        assert_eq!(stale(&root).unwrap(), [root.join("npins-1")]);
+        drop(running);
+        assert_eq!(
+            stale(&root).unwrap(),
+            [root.join("npins-1"), root.join("npins-2")]
+        );
+        assert!(stale(&root.join("missing")).unwrap().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_claim() {
+        let root = std::env::temp_dir().join(format!("npins-test-claim-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        /* Left over, and thus taken over */
+        std::fs::create_dir_all(root.join("npins-1/0-clone")).unwrap();
+        File::create(root.join("npins-1").join(LOCK)).unwrap();
+        let (directory, first) = claim(&root, 1).unwrap();
+        assert_eq!(directory, root.join("npins-1"));
+        assert!(!directory.join("0-clone").exists());
+
+        /* Still in use by another process with the same id */
+        let (directory, second) = claim(&root, 1).unwrap();
+        assert_eq!(directory, root.join("npins-1-1"));
+        assert!(root.join("npins-1").join(LOCK).exists());
+        assert!(stale(&root).unwrap().is_empty());
+
+        drop(second);
+        assert_eq!(stale(&root).unwrap(), [root.join("npins-1-1")]);
+        drop(first);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+//! Throttle requests to forges, so that large updates don't get us blocked
+//!
+//! Forges limit how many requests a client may make, and anonymous clients get far less than
+//! authenticated ones. Every request to a forge is thus subject to a [`Profile`], which limits the
+//! number of parallel requests, the rate at which they start, and how often they are retried when
+//! the forge asks to slow down. The profile is picked per request:
+//!
+//! - `enterprise` for self-hosted instances, which usually have more generous limits
+//! - `authenticated` if the request carries a token
+//! - `anonymous` otherwise
+//!
+//! `NPINS_THROTTLE_PROFILE` overrides this for all forges. Other hosts are not throttled.
+
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use url::Url;
+
+/// How gently to treat a forge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Profile {
+    Anonymous,
+    Authenticated,
+    Enterprise,
+}
+
+impl std::str::FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "anonymous" => Ok(Profile::Anonymous),
+            "authenticated" => Ok(Profile::Authenticated),
+            "enterprise" => Ok(Profile::Enterprise),
+            _ => anyhow::bail!("expected 'anonymous', 'authenticated' or 'enterprise', got '{s}'"),
+        }
+    }
+}
+
+impl Profile {
+    /// Requests in flight at the same time, per host
+    pub fn max_parallel(self) -> usize {
+        match self {
+            Profile::Anonymous => 2,
+            Profile::Authenticated => 8,
+            Profile::Enterprise => 16,
+        }
+    }
+
+    /// Time between the start of two requests, per host
+    pub fn interval(self) -> Duration {
+        match self {
+            Profile::Anonymous => Duration::from_millis(500),
+            Profile::Authenticated => Duration::from_millis(50),
+            Profile::Enterprise => Duration::ZERO,
+        }
+    }
+
+    /// How often to retry a request that was rejected because of rate limiting
+    pub fn retries(self) -> u32 {
+        match self {
+            Profile::Anonymous => 1,
+            Profile::Authenticated => 3,
+            Profile::Enterprise => 5,
+        }
+    }
+
+    /// Don't wait longer than this for a retry. GitHub may ask to wait for up to an hour.
+    pub fn max_wait(self) -> Duration {
+        match self {
+            Profile::Anonymous => Duration::from_secs(10),
+            Profile::Authenticated | Profile::Enterprise => Duration::from_secs(60),
+        }
+    }
+}
+
+/// The forges we know, by the host names of their public instance
+const PUBLIC_FORGES: &[&str] = &[
+    "github.com",
+    "api.github.com",
+    "codeload.github.com",
+    "gitlab.com",
+    "codeberg.org",
+    "git.sr.ht",
+    "bitbucket.org",
+    "api.bitbucket.org",
+];
+
+/// Whether the URL belongs to a forge, and whether that is self-hosted
+fn forge_kind(url: &Url) -> Option<bool> {
+    let host = url.host_str()?;
+    if PUBLIC_FORGES.contains(&host) {
+        return Some(false);
+    }
+    let github = &crate::config::get().github;
+    let configured = [&github.host, &github.api_host]
+        .into_iter()
+        .flatten()
+        .filter_map(|value| value.parse::<Url>().ok());
+    for configured in configured {
+        if configured.host_str() == Some(host) {
+            return Some(true);
+        }
+    }
+    /* The APIs of self-hosted GitLab, Forgejo and Gitea instances */
+    if url.path().starts_with("/api/v4/projects/") || url.path().starts_with("/api/v1/repos/") {
+        return Some(true);
+    }
+    None
+}
+
+/// Pick the profile for a request, if it goes to a forge at all
+pub fn profile_for(url: &Url, authenticated: bool, configured: Option<Profile>) -> Option<Profile> {
+    let self_hosted = forge_kind(url)?;
+    Some(match (configured, self_hosted, authenticated) {
+        (Some(profile), _, _) => profile,
+        (None, true, _) => Profile::Enterprise,
+        (None, false, true) => Profile::Authenticated,
+        (None, false, false) => Profile::Anonymous,
+    })
+}
+
+fn configured_profile() -> Option<Profile> {
+    static PROFILE: OnceLock<Option<Profile>> = OnceLock::new();
+    *PROFILE.get_or_init(|| {
+        let value = std::env::var("NPINS_THROTTLE_PROFILE").ok()?;
+        value
+            .parse()
+            .inspect_err(|err| log::warn!("Ignoring NPINS_THROTTLE_PROFILE: {err}"))
+            .ok()
+    })
+}
+
+/// The throttling state of one host
+struct Throttle {
+    permits: tokio::sync::Semaphore,
+    /// When the next request may start
+    next: tokio::sync::Mutex<tokio::time::Instant>,
+    interval: Duration,
+}
+
+/// Per host, since a profile's limits apply to each instance of a forge separately
+type Throttles = Mutex<HashMap<(String, Profile), Arc<Throttle>>>;
+
+fn throttle(host: &str, profile: Profile) -> Arc<Throttle> {
+    static THROTTLES: OnceLock<Throttles> = OnceLock::new();
+    THROTTLES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry((host.to_owned(), profile))
+        .or_insert_with(|| {
+            Arc::new(Throttle {
+                permits: tokio::sync::Semaphore::new(profile.max_parallel()),
+                next: tokio::sync::Mutex::new(tokio::time::Instant::now()),
+                interval: profile.interval(),
+            })
+        })
+        .clone()
+}
+
+/// How long the forge wants us to wait before retrying, if it rejected the request for its rate
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let headers = response.headers();
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    let limited = match response.status() {
+        reqwest::StatusCode::TOO_MANY_REQUESTS => true,
+        /* GitHub uses 403 for rate limits, with the remaining requests at zero */
+        reqwest::StatusCode::FORBIDDEN => header("x-ratelimit-remaining") == Some(0),
+        _ => false,
+    };
+    if !limited {
+        return None;
+    }
+    if let Some(seconds) = header("retry-after") {
+        return Some(Duration::from_secs(seconds));
+    }
+    /* Otherwise GitHub says when the limit resets, as a UNIX timestamp */
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some(match header("x-ratelimit-reset") {
+        Some(reset) => Duration::from_secs(reset.saturating_sub(now)),
+        None => Duration::from_secs(1),
+    })
+}
+
+async fn send(request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let authenticated = request
+        .headers()
+        .contains_key(reqwest::header::AUTHORIZATION)
+        || request.headers().contains_key("PRIVATE-TOKEN");
+    let (Some(profile), Some(host)) = (
+        profile_for(request.url(), authenticated, configured_profile()),
+        request.url().host_str(),
+    ) else {
+        return client.execute(request).await;
+    };
+    let throttle = throttle(host, profile);
+
+    let mut retry = Some(request);
+    let mut attempt = 0;
+    while let Some(request) = retry.take() {
+        /* Streaming bodies can't be copied, such requests are sent once without retrying */
+        retry = request.try_clone();
+        let response = {
+            let _permit = throttle.permits.acquire().await.expect("never closed");
+            {
+                let mut next = throttle.next.lock().await;
+                tokio::time::sleep_until(*next).await;
+                *next = tokio::time::Instant::now() + throttle.interval;
+            }
+            client.execute(request).await?
+        };
+        match retry_after(&response) {
+            Some(wait)
+                if retry.is_some() && attempt < profile.retries() && wait <= profile.max_wait() =>
+            {
+                attempt += 1;
+                log::debug!(
+                    "{} is rate limited, retrying in {}s ({attempt}/{})",
+                    response.url(),
+                    wait.as_secs(),
+                    profile.retries()
+                );
+                tokio::time::sleep(wait).await;
+            },
+            _ => return Ok(response),
+        }
+    }
+    unreachable!("only retried with a copy of the request")
+}
+
+/// Send requests subject to throttling, see the [module documentation](self)
+pub trait ThrottledSend {
+    fn send_throttled(
+        self,
+    ) -> impl std::future::Future<Output = reqwest::Result<reqwest::Response>> + Send;
+}
+
+impl ThrottledSend for reqwest::RequestBuilder {
+    fn send_throttled(
+        self,
+    ) -> impl std::future::Future<Output = reqwest::Result<reqwest::Response>> + Send {
+        send(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profile_for() {
+        let url = |url: &str| url.parse::<Url>().unwrap();
+        assert_eq!(
+            profile_for(&url("https://api.github.com/repos/a/b"), false, None),
+            Some(Profile::Anonymous)
+        );
+        assert_eq!(
+            profile_for(&url("https://gitlab.com/api/v4/projects/a%2Fb"), true, None),
+            Some(Profile::Authenticated)
+        );
+        assert_eq!(
+            profile_for(
+                &url("https://git.example.org/api/v4/projects/a%2Fb/releases"),
+                false,
+                None
+            ),
+            Some(Profile::Enterprise)
+        );
+        assert_eq!(
+            profile_for(
+                &url("https://github.com/a/b"),
+                true,
+                Some(Profile::Anonymous)
+            ),
+            Some(Profile::Anonymous)
+        );
+        assert_eq!(
+            profile_for(&url("https://files.pythonhosted.org/a.tar.gz"), false, None),
+            None
+        );
+        assert!("aggressive".parse::<Profile>().is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Update scripts for automation that knows nothing about npins
+//!
+//! Nixpkgs calls the `passthru.updateScript` of a package to update it, both in
+//! `maintainers/scripts/update.nix` and in bots like [nixpkgs-update](https://github.com/nix-community/nixpkgs-update).
+//! It is a command given as a list of arguments, so for packages built from pins, it is simply the
+//! right `npins update` invocation.
+
+/// Quote a string for Nix
+pub fn nix_string(string: &str) -> String {
+    let escaped = string
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+/// Quote a word for a POSIX shell, if needed
+pub fn shell_word(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:@+".contains(c))
+    {
+        word.to_owned()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// The command to update some pins
+///
+/// `npins_args` are the global arguments that locate the lock file, like `--directory npins`.
+pub fn command(npins_args: &[String], names: &[&str]) -> Vec<String> {
+    std::iter::once("npins".to_owned())
+        .chain(npins_args.iter().cloned())
+        .chain(std::iter::once("update".to_owned()))
+        .chain(names.iter().map(|name| name.to_string()))
+        .collect()
+}
+
+/// An attribute set with the `updateScript` of every pin
+pub fn nix_snippets(npins_args: &[String], names: &[&str]) -> String {
+    let mut nix = String::from("{\n");
+    for name in names {
+        let command = command(npins_args, &[name])
+            .iter()
+            .map(|argument| nix_string(argument))
+            .collect::<Vec<_>>()
+            .join(" ");
+        nix.push_str(&format!("  {} = [ {command} ];\n", nix_string(name)));
+    }
+    nix.push_str("}\n");
+    nix
+}
+
+/// A shell script that updates all the pins at once
+pub fn shell_script(npins_args: &[String], names: &[&str]) -> String {
+    let command = command(npins_args, names)
+        .iter()
+        .map(|argument| shell_word(argument))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("#!/usr/bin/env sh\nset -eu\nexec {command}\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(nix_string("nixpkgs"), r#""nixpkgs""#);
+        assert_eq!(nix_string(r#"a "${b}" \c"#), r#""a \"\${b}\" \\c""#);
+        assert_eq!(shell_word("npins/sources.json"), "npins/sources.json");
+        assert_eq!(shell_word("my pins"), "'my pins'");
+        assert_eq!(shell_word("it's"), r"'it'\''s'");
+        assert_eq!(shell_word(""), "''");
+    }
+
+    #[test]
+    fn test_snippets() {
+        let args = ["--directory".to_owned(), "pkgs/tool/npins".to_owned()];
+        assert_eq!(
+            nix_snippets(&args, &["tool", "tool-data"]),
+            r#"{
+  "tool" = [ "npins" "--directory" "pkgs/tool/npins" "update" "tool" ];
+  "tool-data" = [ "npins" "--directory" "pkgs/tool/npins" "update" "tool-data" ];
+}
+"#
+        );
+        assert_eq!(
+            shell_script(&args, &["tool", "tool-data"]),
+            "#!/usr/bin/env sh\nset -eu\nexec npins --directory pkgs/tool/npins update tool tool-data\n"
+        );
+    }
+}
@@ -31,6 +31,16 @@ pub fn from_value_versioned(value: Value) -> Result<NixPins> {
     Ok(serde_json::from_value(value)?)
 }
 
+/// Read pins of any format version, e.g. from other repositories, upgrading them if necessary
+pub fn from_value_upgraded(value: Map<String, Value>) -> Result<NixPins> {
+    let value = if value.get("version").and_then(Value::as_u64) == Some(LATEST) {
+        Value::Object(value)
+    } else {
+        upgrade(value).context("Failed to upgrade the pins")?
+    };
+    Ok(serde_json::from_value(value)?)
+}
+
 /// Custom manual serialize wrapper that adds a version field
 pub fn to_value_versioned(pins: &NixPins) -> serde_json::Value {
     let mut raw = serde_json::to_value(pins).expect("Serialization should not fail");
@@ -323,26 +333,26 @@ mod test {
                     "nixos-mailserver".into() => Pin::Git {
                         input: git::GitPin::new(git::Repository::git("https://gitlab.com/simple-nixos-mailserver/nixos-mailserver.git".parse().unwrap()), "nixos-21.11".into(), false),
                         version: Some(git::GitRevision::new("6e3a7b2ea6f0d68b82027b988aa25d3423787303".into()).unwrap()),
-                        hashes: Some(git::OptionalUrlHashes { url: None, hash: "sha256-hNhzLOp+dApEY15vwLAQZu+sjEQbJcOXCaSfAT6lpsQ=".into() } ),
-                        frozen: Frozen::default(),
+                        hashes: Some(git::OptionalUrlHashes { url: None, hash: "sha256-hNhzLOp+dApEY15vwLAQZu+sjEQbJcOXCaSfAT6lpsQ=".into(), requires_auth: false } ),
+                        metadata: PinMetadata::default(),
                     },
                     "nixpkgs".into() => Pin::Git {
                         input: git::GitPin::new(git::Repository::github("nixos", "nixpkgs"), "nixpkgs-unstable".into(), false),
                         version: Some(git::GitRevision::new("5c37ad87222cfc1ec36d6cd1364514a9efc2f7f2".into()).unwrap()),
-                        hashes: Some(git::OptionalUrlHashes { url: Some("https://github.com/nixos/nixpkgs/archive/5c37ad87222cfc1ec36d6cd1364514a9efc2f7f2.tar.gz".parse().unwrap()), hash: "sha256-a8GGtxn2iL3WAkY5H+4E0s3Q7XJt6bTOvos9qqxT5OQ=".into() }),
-                        frozen: Frozen::default(),
+                        hashes: Some(git::OptionalUrlHashes { url: Some("https://github.com/nixos/nixpkgs/archive/5c37ad87222cfc1ec36d6cd1364514a9efc2f7f2.tar.gz".parse().unwrap()), hash: "sha256-a8GGtxn2iL3WAkY5H+4E0s3Q7XJt6bTOvos9qqxT5OQ=".into(), requires_auth: false }),
+                        metadata: PinMetadata::default(),
                     },
                     "streamlit".into() => Pin::PyPi {
-                        input: pypi::Pin { name: "streamlit".into(), version_upper_bound: None },
+                        input: pypi::Pin { name: "streamlit".into(), version_upper_bound: None, wheel: None, only_versions: Vec::new(), exclude_versions: Vec::new() },
                         version: Some(GenericVersion { version: "1.3.1".into() }),
-                        hashes: Some(GenericUrlHashes { url: "https://files.pythonhosted.org/packages/c3/9d/ac871992617220442832af12c3808716f4349ab05ff939d695fe8b542f00/streamlit-1.3.1.tar.gz".parse().unwrap(), hash: "sha256-rex5NcnPd0uRFbJFbPL0jE9JufZxWal9sP4ig1fBr98=".into() } ),
-                        frozen: Frozen::default(),
+                        hashes: Some(GenericUrlHashes { url: "https://files.pythonhosted.org/packages/c3/9d/ac871992617220442832af12c3808716f4349ab05ff939d695fe8b542f00/streamlit-1.3.1.tar.gz".parse().unwrap(), hash: "sha256-rex5NcnPd0uRFbJFbPL0jE9JufZxWal9sP4ig1fBr98=".into(), archive: None } ),
+                        metadata: PinMetadata::default(),
                     },
                     "youtube-dl".into() => Pin::GitRelease {
                         input: git::GitReleasePin::new(git::Repository::github("ytdl-org", "youtube-dl"), false, None, None, false),
-                        version: Some(GenericVersion { version: "youtube-dl 2021.12.17".into() }),
+                        version: Some(git::ReleaseVersion::new("youtube-dl 2021.12.17")),
                         hashes: None,
-                        frozen: Frozen::default(),
+                        metadata: PinMetadata::default(),
                     }
                 ],
             }
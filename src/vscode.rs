@@ -0,0 +1,333 @@
+//! Pin a VS Code extension
+//!
+//! Extensions are looked up by their `publisher.name` identifier, either on
+//! [Open VSX](https://open-vsx.org) (the default) or on the Visual Studio Marketplace.
+//! The pinned artifact is the `.vsix` file of the resolved version.
+
+use crate::*;
+use anyhow::{Context, Result};
+use lenient_version::Version;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// The registry from which the extension is fetched
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Registry {
+    /// <https://open-vsx.org>
+    #[default]
+    OpenVsx,
+    /// <https://marketplace.visualstudio.com>
+    Marketplace,
+}
+
+impl std::fmt::Display for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Registry::OpenVsx => "open-vsx",
+            Registry::Marketplace => "marketplace",
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ExtensionPin {
+    /// The publisher (Open VSX calls it "namespace"), e.g. `rust-lang`
+    pub publisher: String,
+    /// The extension name, e.g. `rust-analyzer`
+    pub name: String,
+    #[serde(default)]
+    pub registry: Registry,
+    /// Platform-specific extensions ship a different `.vsix` per platform, e.g. `linux-x64`.
+    ///
+    /// If absent, the universal package is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_platform: Option<String>,
+}
+
+impl diff::Diff for ExtensionPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("publisher".into(), self.publisher.clone())),
+            Some(("name".into(), self.name.clone())),
+            Some(("registry".into(), self.registry.to_string())),
+            self.target_platform
+                .as_ref()
+                .map(|target_platform| ("target_platform".into(), target_platform.clone())),
+        ]
+        .into_iter()
+        .flat_map(Option::into_iter)
+        .collect()
+    }
+}
+
+impl ExtensionPin {
+    /// Parse an extension identifier of the form `publisher.name`
+    pub fn new(id: &str, registry: Registry, target_platform: Option<String>) -> Result<Self> {
+        let (publisher, name) = id.split_once('.').ok_or_else(|| {
+            anyhow::format_err!("Extension ID '{id}' must be of the form `publisher.name`")
+        })?;
+        anyhow::ensure!(
+            !publisher.is_empty() && !name.is_empty(),
+            "Extension ID '{id}' must be of the form `publisher.name`"
+        );
+        Ok(Self {
+            publisher: publisher.into(),
+            name: name.into(),
+            registry,
+            target_platform,
+        })
+    }
+
+    /// Get the download URL of the `.vsix` for a given version
+    fn download_url(&self, version: &str) -> Result<Url> {
+        Ok(match self.registry {
+            /* The Open VSX API also tells us the URL, but it follows a stable pattern
+             * https://open-vsx.org/api/{namespace}/{extension}[/{targetPlatform}]/{version}/file/{namespace}.{extension}-{version}[@{targetPlatform}].vsix
+             */
+            Registry::OpenVsx => {
+                let mut url: Url = "https://open-vsx.org/api/".parse().unwrap();
+                let file_name = match &self.target_platform {
+                    Some(target_platform) => format!(
+                        "{}.{}-{}@{}.vsix",
+                        self.publisher, self.name, version, target_platform
+                    ),
+                    None => format!("{}.{}-{}.vsix", self.publisher, self.name, version),
+                };
+                url.path_segments_mut()
+                    .unwrap()
+                    .pop_if_empty()
+                    .push(&self.publisher)
+                    .push(&self.name)
+                    .extend(self.target_platform.as_deref())
+                    .push(version)
+                    .push("file")
+                    .push(&file_name);
+                url
+            },
+            /* https://marketplace.visualstudio.com/_apis/public/gallery/publishers/{publisher}/vsextensions/{name}/{version}/vspackage[?targetPlatform=…] */
+            Registry::Marketplace => {
+                let mut url: Url = "https://marketplace.visualstudio.com/_apis/public/gallery/"
+                    .parse()
+                    .unwrap();
+                url.path_segments_mut()
+                    .unwrap()
+                    .pop_if_empty()
+                    .extend([
+                        "publishers",
+                        self.publisher.as_str(),
+                        "vsextensions",
+                        self.name.as_str(),
+                    ])
+                    .push(version)
+                    .push("vspackage");
+                if let Some(target_platform) = &self.target_platform {
+                    url.query_pairs_mut()
+                        .append_pair("targetPlatform", target_platform);
+                }
+                url
+            },
+        })
+    }
+
+    /// Query Open VSX for the latest version
+    async fn latest_open_vsx(&self) -> Result<String> {
+        /* Url template: `https://open-vsx.org/api/{namespace}/{extension}[/{targetPlatform}]`
+         * API reference: https://open-vsx.org/swagger-ui/index.html
+         */
+        let mut url: Url = "https://open-vsx.org/api/".parse().unwrap();
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .push(&self.publisher)
+            .push(&self.name)
+            .extend(self.target_platform.as_deref());
+
+        let metadata: OpenVsxMetadata = get_and_deserialize(url)
+            .await
+            .context("Could not fetch Open VSX metadata")?;
+        if let Some(error) = metadata.error {
+            anyhow::bail!("Open VSX returned an error: {error}");
+        }
+        metadata
+            .version
+            .ok_or_else(|| anyhow::format_err!("Open VSX metadata contains no version"))
+    }
+
+    /// Query the Visual Studio Marketplace for the latest (non pre-release) version
+    async fn latest_marketplace(&self) -> Result<String> {
+        /* This API is not officially documented, but is what VS Code itself uses.
+         * filterType 7 is "ExtensionName", flags 0x1 | 0x10 are "IncludeVersions" and "IncludeVersionProperties".
+         */
+        let query = serde_json::json!({
+            "filters": [{
+                "criteria": [{
+                    "filterType": 7,
+                    "value": format!("{}.{}", self.publisher, self.name),
+                }],
+            }],
+            "flags": 0x1 | 0x10,
+        });
+        const MARKETPLACE_API: &str =
+            "https://marketplace.visualstudio.com/_apis/public/gallery/extensionquery";
+        provenance::record_endpoint(MARKETPLACE_API);
+        let response: MarketplaceResponse = http_request(reqwest::Method::POST, MARKETPLACE_API)?
+            .header("Accept", "application/json;api-version=3.0-preview.1")
+            .json(&query)
+            .send_throttled()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Could not fetch Marketplace metadata")?;
+
+        let extension = response
+            .results
+            .into_iter()
+            .flat_map(|result| result.extensions)
+            .next()
+            .ok_or_else(|| {
+                anyhow::format_err!(
+                    "Extension {}.{} not found on the Marketplace",
+                    self.publisher,
+                    self.name
+                )
+            })?;
+
+        /* Versions are sorted newest first */
+        extension
+            .versions
+            .into_iter()
+            .filter(|version| version.target_platform == self.target_platform)
+            .find(|version| !version.is_pre_release())
+            .map(|version| version.version)
+            .ok_or_else(|| anyhow::format_err!("No matching versions found"))
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for ExtensionPin {
+    type Version = GenericVersion;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(&self, old: Option<&GenericVersion>) -> Result<GenericVersion> {
+        let version = match self.registry {
+            Registry::OpenVsx => self.latest_open_vsx().await?,
+            Registry::Marketplace => self.latest_marketplace().await?,
+        };
+
+        if let Some(old) = old {
+            let old_version = lenient_semver_parser::parse::<Version>(&old.version);
+            let new_version = lenient_semver_parser::parse::<Version>(&version);
+            match (old_version, new_version) {
+                (Ok(old_version), Ok(new_version)) => {
+                    anyhow::ensure!(
+                        new_version >= old_version,
+                        "Failed to ensure version monotonicity, latest found version is {} but current is {}",
+                        new_version,
+                        old_version,
+                    );
+                },
+                _ => {
+                    log::warn!("This extension does not appear to be following SemVer, so no guarantees on monotonicity can be made.");
+                },
+            }
+        }
+
+        Ok(GenericVersion { version })
+    }
+
+    async fn fetch(&self, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        let url = self.download_url(&version.version)?;
+        let hash = nix::nix_prefetch_url(&url).await?;
+        Ok(GenericUrlHashes {
+            url,
+            hash,
+            archive: None,
+        })
+    }
+}
+
+/// We only deserialize what we are interested in
+#[derive(Debug, Deserialize)]
+struct OpenVsxMetadata {
+    version: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MarketplaceResponse {
+    results: Vec<MarketplaceResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MarketplaceResult {
+    extensions: Vec<MarketplaceExtension>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MarketplaceExtension {
+    versions: Vec<MarketplaceVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MarketplaceVersion {
+    version: String,
+    target_platform: Option<String>,
+    #[serde(default)]
+    properties: Vec<MarketplaceProperty>,
+}
+
+impl MarketplaceVersion {
+    fn is_pre_release(&self) -> bool {
+        self.properties.iter().any(|property| {
+            property.key == "Microsoft.VisualStudio.Code.PreRelease" && property.value == "true"
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MarketplaceProperty {
+    key: String,
+    value: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_download_url() {
+        let pin = ExtensionPin::new("rust-lang.rust-analyzer", Registry::OpenVsx, None).unwrap();
+        assert_eq!(
+            pin.download_url("0.3.2").unwrap().as_str(),
+            "https://open-vsx.org/api/rust-lang/rust-analyzer/0.3.2/file/rust-lang.rust-analyzer-0.3.2.vsix"
+        );
+
+        let pin = ExtensionPin::new(
+            "rust-lang.rust-analyzer",
+            Registry::OpenVsx,
+            Some("linux-x64".into()),
+        )
+        .unwrap();
+        assert_eq!(
+            pin.download_url("0.3.2").unwrap().as_str(),
+            "https://open-vsx.org/api/rust-lang/rust-analyzer/linux-x64/0.3.2/file/rust-lang.rust-analyzer-0.3.2@linux-x64.vsix"
+        );
+
+        let pin = ExtensionPin::new(
+            "ms-python.python",
+            Registry::Marketplace,
+            Some("linux-x64".into()),
+        )
+        .unwrap();
+        assert_eq!(
+            pin.download_url("2024.0.1").unwrap().as_str(),
+            "https://marketplace.visualstudio.com/_apis/public/gallery/publishers/ms-python/vsextensions/python/2024.0.1/vspackage?targetPlatform=linux-x64"
+        );
+
+        assert!(ExtensionPin::new("no-publisher", Registry::OpenVsx, None).is_err());
+        assert!(ExtensionPin::new(".name", Registry::OpenVsx, None).is_err());
+    }
+}
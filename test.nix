@@ -483,6 +483,302 @@ in
     '';
   };
 
+  gitMovingTag = mkGitTest rec {
+    name = "git-moving-tag";
+    repositories."foo" = mkGitRepo { tags = [ "stable" ]; };
+    repositories."moved" = mkGitRepo {
+      tags = [ "stable" ];
+      extraCommands = ''
+        echo moved > test.txt
+        git commit -am "moved"
+        git tag -f stable
+      '';
+    };
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo --moving-tag stable
+      eq "$(jq -r .pins.foo.version npins/sources.json)" "stable"
+      eq "$(jq -r .pins.foo.revision npins/sources.json)" "$(resolveGitCommit ${repositories."foo"} stable)"
+
+      # Upstream moves the tag, which is a normal update
+      rm foo && ln -s ${repositories."moved"} foo
+      npins update
+      eq "$(jq -r .pins.foo.version npins/sources.json)" "stable"
+      eq "$(jq -r .pins.foo.revision npins/sources.json)" "$(resolveGitCommit ${repositories."moved"} stable)"
+      nix-instantiate --eval npins -A foo.outPath
+    '';
+  };
+
+  gitKeepPrevious = mkGitTest rec {
+    name = "git-keep-previous";
+    repositories."foo" = mkGitRepo { tags = [ "nightly" ]; };
+    repositories."moved" = mkGitRepo {
+      tags = [ "nightly" ];
+      extraCommands = ''
+        echo moved > test.txt
+        git commit -am "moved"
+        git tag -f nightly
+      '';
+    };
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo --moving-tag nightly --keep 1
+
+      rm foo && ln -s ${repositories."moved"} foo
+      npins update
+      eq "$(jq -r .pins.foo.revision npins/sources.json)" "$(resolveGitCommit ${repositories."moved"} nightly)"
+      eq "$(jq -r .pins.foo.previous[0].revision npins/sources.json)" "$(resolveGitCommit ${repositories."foo"} nightly)"
+      eq "$(nix-instantiate --eval npins -A foo.previous --apply builtins.length)" "1"
+      nix-instantiate --eval --expr '(builtins.elemAt (import ./npins).foo.previous 0).outPath'
+    '';
+  };
+
+  gitFrozen = mkGitTest rec {
+    name = "git-frozen";
+    repositories."foo" = mkGitRepo { tags = [ "v0.1" ]; };
+    repositories."newer" = mkGitRepo {
+      tags = [
+        "v0.1"
+        "v0.2"
+      ];
+    };
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo
+      npins freeze foo
+
+      rm foo && ln -s ${repositories."newer"} foo
+      npins update 2>&1 | grep "Not updating 1 frozen pin(s): foo"
+      eq "$(jq -r .pins.foo.version npins/sources.json)" "v0.1"
+
+      npins unfreeze foo
+      npins update
+      eq "$(jq -r .pins.foo.version npins/sources.json)" "v0.2"
+    '';
+  };
+
+  gitAnnotate = mkGitTest {
+    name = "git-annotate";
+    repositories."foo" = mkGitRepo { tags = [ "v0.1" ]; };
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo --describe "Our fork of foo"
+      npins annotate foo --set owner=ops
+      npins show | grep "description: Our fork of foo"
+      eq "$(nix-instantiate --eval npins -A foo.description)" '"Our fork of foo"'
+      eq "$(nix-instantiate --eval npins -A foo.metadata.owner)" '"ops"'
+
+      npins annotate foo --describe "" --unset owner
+      eq "$(jq -r '.pins.foo | has("description") or has("metadata")' npins/sources.json)" "false"
+    '';
+  };
+
+  gitEditRelease = mkGitTest {
+    name = "git-edit-release";
+    repositories."foo" = mkGitRepo {
+      tags = [
+        "v0.1"
+        "v0.2"
+        "v1.0"
+      ];
+    };
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo
+      eq "$(jq -r .pins.foo.version npins/sources.json)" "v1.0"
+
+      # Still fits, so it is kept
+      npins edit foo --upper-bound 2
+      eq "$(jq -r .pins.foo.version npins/sources.json)" "v1.0"
+      eq "$(jq -r .pins.foo.version_upper_bound npins/sources.json)" "2"
+
+      # Doesn't fit anymore, so it goes back
+      npins edit foo --upper-bound 1
+      eq "$(jq -r .pins.foo.version npins/sources.json)" "v0.2"
+
+      npins edit foo --upper-bound ""
+      eq "$(jq -r .pins.foo.version_upper_bound npins/sources.json)" "null"
+      eq "$(jq -r .pins.foo.version npins/sources.json)" "v0.2"
+
+      # Lower bounds need a requirement
+      npins edit foo --version-requirement '>=0.1, <0.2'
+      eq "$(jq -r .pins.foo.version npins/sources.json)" "v0.1"
+      npins add git http://localhost:8000/foo --name lts --version-requirement '~0.2'
+      eq "$(jq -r .pins.lts.version npins/sources.json)" "v0.2"
+
+      npins edit foo --branch stable && exit 1 || echo "expected failure"
+    '';
+  };
+
+  gitEditBranch = mkGitTest rec {
+    name = "git-edit-branch";
+    repositories."foo" = mkGitRepo {
+      extraCommands = ''
+        git checkout -b other
+        echo other > test.txt
+        git commit -am other
+        git checkout main
+      '';
+    };
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo -b main
+      eq "$(jq -r .pins.foo.revision npins/sources.json)" "$(resolveGitCommit ${repositories."foo"} main)"
+
+      npins edit foo --branch other
+      eq "$(jq -r .pins.foo.branch npins/sources.json)" "other"
+      eq "$(jq -r .pins.foo.revision npins/sources.json)" "$(resolveGitCommit ${repositories."foo"} other)"
+
+      npins edit foo --upper-bound 2 && exit 1 || echo "expected failure"
+    '';
+  };
+
+  gitRef = mkGitTest rec {
+    name = "git-ref";
+    # A pull request, whose commit is on no branch
+    repositories."foo" = mkGitRepo {
+      extraCommands = ''
+        git checkout -b pr
+        echo pr > test.txt
+        git commit -am pr
+        git update-ref refs/pull/1/head HEAD
+        git checkout main
+        git branch -D pr
+      '';
+    };
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo --ref refs/pull/1/head
+      eq "$(jq -r .pins.foo.branch npins/sources.json)" "refs/pull/1/head"
+      eq "$(jq -r .pins.foo.revision npins/sources.json)" "$(resolveGitCommit ${repositories."foo"} refs/pull/1/head)"
+      eq "$(cat "$(nix-instantiate --eval npins -A foo.outPath --json | jq -r .)/test.txt")" "pr"
+
+      npins add git http://localhost:8000/foo --ref refs/tags/v0.2 --name tag && exit 1 || echo "expected failure"
+    '';
+  };
+
+  gitPinRevision = mkGitTest rec {
+    name = "git-pin-revision";
+    repositories."foo" = mkGitRepo {
+      extraCommands = ''
+        echo newer > test.txt
+        git commit -am newer
+        git checkout -b fork HEAD~1
+        echo fork > test.txt
+        git commit -am fork
+        git checkout main
+      '';
+    };
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo -b main --at "$(resolveGitCommit ${repositories."foo"} main~1)"
+      eq "$(jq -r .pins.foo.revision npins/sources.json)" "$(resolveGitCommit ${repositories."foo"} main~1)"
+      eq "$(jq -r .pins.foo.verified_on npins/sources.json)" "main"
+
+      npins pin foo "$(resolveGitCommit ${repositories."foo"} fork)" && exit 1 || echo "expected failure"
+      npins pin foo "$(resolveGitCommit ${repositories."foo"} main)"
+      eq "$(jq -r .pins.foo.revision npins/sources.json)" "$(resolveGitCommit ${repositories."foo"} main)"
+      eq "$(cat "$(nix-instantiate --eval npins -A foo.outPath --json | jq -r .)/test.txt")" "newer"
+
+      # Found by npins itself, so there's nothing to verify
+      npins update foo --full
+      eq "$(jq -r .pins.foo.verified_on npins/sources.json)" "null"
+    '';
+  };
+
+  gitReachableFrom = mkGitTest rec {
+    name = "git-reachable-from";
+    # A backport tagged with a higher version than the latest release on main
+    repositories."foo" = mkGitRepo {
+      tags = [ "v1.0" ];
+      extraCommands = ''
+        git checkout -b maintenance
+        echo backport > test.txt
+        git commit -am backport
+        git tag v1.5
+        git checkout main
+        echo feature > test.txt
+        git commit -am feature
+        git tag v1.2
+      '';
+    };
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo --name all
+      eq "$(jq -r .pins.all.version npins/sources.json)" "v1.5"
+
+      npins add git http://localhost:8000/foo --name main --reachable-from main
+      eq "$(jq -r .pins.main.version npins/sources.json)" "v1.2"
+      eq "$(jq -r .pins.main.reachable_from npins/sources.json)" "main"
+      eq "$(cat "$(nix-instantiate --eval npins -A main.outPath --json | jq -r .)/test.txt")" "feature"
+
+      npins edit main --reachable-from ""
+      eq "$(jq -r .pins.main.version npins/sources.json)" "v1.5"
+    '';
+  };
+
+  tempFiles = mkGitTest {
+    name = "temp-files";
+    repositories."foo" = gitRepo;
+    commands = ''
+      export NPINS_TMPDIR=$(mktemp -d)
+      npins init --bare
+      npins add git http://localhost:8000/foo -b test-branch --submodules
+      eq "$(ls -A "$NPINS_TMPDIR")" ""
+
+      # Left behind by a killed npins
+      mkdir "$NPINS_TMPDIR/npins-1"
+      touch "$NPINS_TMPDIR/npins-1/.lock"
+      npins clean-temp
+      eq "$(ls -A "$NPINS_TMPDIR")" ""
+    '';
+  };
+
+  interruptedUpdate = mkGitTest {
+    name = "interrupted-update";
+    repositories."foo" = gitRepo;
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo -b test-branch
+      git clone --quiet --bare http://localhost:8000/foo bar.git
+      npins add git "file://$PWD/bar.git" -b test-branch --name bar
+
+      # Updating bar hangs
+      mkdir bin
+      printf '#!/bin/sh\ncase "$*" in *bar.git*) exec sleep 60;; esac\nexec %s "$@"\n' "$(command -v git)" > bin/git
+      chmod +x bin/git
+      PATH=$PWD/bin:$PATH npins update --report report.json &
+      sleep 5
+      kill -INT $!
+      status=0
+      wait $! || status=$?
+      eq "$status" "130"
+      eq "$(jq -r '.pins.foo.status' report.json)" "succeeded"
+      eq "$(jq -r '.pins.bar.error' report.json)" "Interrupted"
+      jq -e '.pins.foo' npins/sources.json
+    '';
+  };
+
+  batchMode = mkGitTest rec {
+    name = "batch-mode";
+    repositories."foo" = gitRepo;
+    commands = ''
+      npins init --bare
+      printf '%s\n' \
+        '{"id": 1, "command": "add", "args": ["git", "http://localhost:8000/foo", "-b", "test-branch"]}' \
+        '{"id": 2, "command": "update", "names": ["foo"]}' \
+        '{"id": 3, "command": "get", "names": ["bar"]}' \
+        '{"id": 4, "command": "get"}' \
+        | npins batch > results.json
+      eq "$(jq -s -r 'map(.ok | tostring) | join(",")' results.json)" "true,true,false,true"
+      eq "$(jq -s -r '.[0].result.foo.revision' results.json)" "$(resolveGitCommit ${repositories."foo"} test-branch)"
+      eq "$(jq -s -r '.[1].result.foo.status' results.json)" "ok"
+
+      echo '{"command": "remove", "names": ["foo"]}' | npins batch
+      eq "$(jq -r '.pins | length' npins/sources.json)" "0"
+    '';
+  };
+
   # maybe test using forgejo? https://github.com/NixOS/nixpkgs/blob/master/nixos/tests/forgejo.nix
   forgejoRelease = mkForgejoTest {
     name = "forgejo-release";
@@ -614,6 +910,22 @@ in
     '';
   };
 
+  githubMigrateConfig = mkGithubTest {
+    name = "github-migrate-config";
+    repositories."foo/bar" = gitRepo;
+    apiTarballs = [ "v0.2" ];
+    commands = ''
+      npins init --bare
+      npins migrate-config
+      eq "$(grep -c localhost:8000 npins/npins.toml)" "2"
+
+      # The settings come from npins.toml now
+      unset NPINS_GITHUB_HOST NPINS_GITHUB_API_HOST
+      npins add github foo bar
+      eq "$(jq -r .pins.bar.url npins/sources.json)" "http://localhost:8000/api/repos/foo/bar/tarball/refs/tags/v0.2"
+    '';
+  };
+
   githubBranch = mkGithubTest {
     name = "github-branch";
     repositories."foo/bar" = gitRepo;
@@ -667,6 +979,38 @@ in
     '';
   };
 
+  builtinHasher = mkGitTest rec {
+    name = "builtin-hasher";
+    repositories."bar" = gitRepo;
+    repositories."foo" = mkGitRepo {
+      name = "repo-with-submodules";
+      extraCommands = ''
+        git submodule init
+
+        cd ..
+        ${gitServe}/bin/git-serve &
+        timeout 30 sh -c 'set -e; until ${pkgs.netcat}/bin/nc -z 127.0.0.1 8000; do sleep 1; done' || exit 1
+        ln -s ${repositories.bar} "bar"
+        cd tmp
+
+        git submodule add "http://localhost:8000/bar"
+      '';
+    };
+
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo --branch main --submodules
+      npins --hasher builtin add --name builtin git http://localhost:8000/foo --branch main --submodules
+
+      # npins computes the same hash as Nix
+      eq "$(jq -r .pins.builtin.hash npins/sources.json)" "$(jq -r .pins.foo.hash npins/sources.json)"
+
+      npins add --name bar git http://localhost:8000/bar --branch test-branch
+      NPINS_HASHER=builtin npins add --name bar2 git http://localhost:8000/bar --branch test-branch
+      eq "$(jq -r .pins.bar2.hash npins/sources.json)" "$(jq -r .pins.bar.hash npins/sources.json)"
+    '';
+  };
+
   githubSubmoduleFromRelease = mkGithubTest rec {
     name = "github-submodule-from-release";
     apiTarballs = [ "v0.5" ];
@@ -773,9 +1117,44 @@ in
 
         V=$(jq -r .pins.foo.branch npins/sources.json)
         [[ "$V" = "test-branch" ]]
+
+        # The locked revision and hash are kept
+        eq "$(jq -r .pins.foo.revision npins/sources.json)" "$(jq -r .nodes.foo.locked.rev flake.lock)"
+        eq "$(jq -r .pins.foo.hash npins/sources.json)" "$(jq -r .nodes.foo.locked.narHash flake.lock)"
       '';
     };
 
+  importGitFromNvfetcher = mkGitTest {
+    name = "from-nvfetcher-import-git";
+    repositories."foo" = gitRepo;
+    commands = ''
+      cat > nvfetcher.toml <<EOF
+      [foo]
+      src.git = "http://localhost:8000/foo"
+      src.branch = "test-branch"
+      fetch.git = "http://localhost:8000/foo"
+      EOF
+
+      npins init --bare
+      # nvfetcher hasn't run, so the pin is updated
+      npins import-nvfetcher
+      eq "$(jq -r .pins.foo.branch npins/sources.json)" "test-branch"
+      REV=$(jq -r .pins.foo.revision npins/sources.json)
+      HASH=$(jq -r .pins.foo.hash npins/sources.json)
+      nix-instantiate --eval npins -A foo.outPath
+
+      # With generated sources, their revision and hash are kept
+      mkdir _sources
+      cat > _sources/generated.json <<EOF
+      {"foo": {"version": "$REV", "src": {"type": "git", "url": "http://localhost:8000/foo", "rev": "$REV", "sha256": "$HASH"}}}
+      EOF
+      npins remove foo
+      npins import-nvfetcher -n foo
+      eq "$(jq -r .pins.foo.revision npins/sources.json)" "$REV"
+      eq "$(jq -r .pins.foo.hash npins/sources.json)" "$HASH"
+    '';
+  };
+
   gitDependencyOverride = mkGitTest rec {
     name = "git-dependency-override";
     repositories."foo" = gitRepo;
@@ -792,6 +1171,100 @@ in
     '';
   };
 
+  pinAlias = mkGitTest rec {
+    name = "pin-alias";
+    repositories."foo" = gitRepo;
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo -b test-branch
+      npins rename foo bar --keep-alias
+      eq "$(jq -r '.pins.bar.aliases[0]' npins/sources.json)" "foo"
+
+      eq "$(nix-instantiate --eval npins -A foo.outPath 2>/dev/null)" "$(nix-instantiate --eval npins -A bar.outPath)"
+      nix-instantiate --eval npins -A foo.outPath 2>&1 | grep 'is now called'
+    '';
+  };
+
+  pinOtherName = mkGitTest rec {
+    name = "pin-other-name";
+    repositories."foo" = gitRepo;
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo -b test-branch
+      npins rename foo bar --keep-name
+      npins edit bar --other-name baz
+      eq "$(jq -r '.pins.bar.other_names | join(",")' npins/sources.json)" "baz,foo"
+
+      eq "$(nix-instantiate --eval npins -A foo.outPath)" "$(nix-instantiate --eval npins -A bar.outPath)"
+      ! nix-instantiate --eval npins -A baz.outPath 2>&1 | grep 'is now called'
+      npins check --eval
+
+      npins edit bar --remove-other-name foo
+      ! nix-instantiate --eval npins -A foo.outPath
+    '';
+  };
+
+  pinVariants = mkGitTest rec {
+    name = "pin-variants";
+    repositories."foo" = gitRepo;
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo -b test-branch --name foo --variant branch
+      npins add git http://localhost:8000/foo --at v0.2 --name foo --variant tag
+      eq "$(jq -r '.pins."foo.tag".variant' npins/sources.json)" "true"
+
+      nix-instantiate --eval npins -A foo.branch.outPath
+      nix-instantiate --eval npins -A foo.tag.outPath
+      npins update foo
+      npins remove foo
+      eq "$(jq -r '.pins | length' npins/sources.json)" "0"
+    '';
+  };
+
+  checkEval = mkGitTest rec {
+    name = "check-eval";
+    repositories."foo" = gitRepo;
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo -b test-branch
+      npins rename foo bar --keep-alias
+      npins check --eval
+
+      # An outdated default.nix doesn't provide the alias
+      sed -i 's/aliases \/\/ otherNames \/\/ plain/plain/' npins/default.nix
+      ! npins check --eval
+    '';
+  };
+
+  pinRange = mkGitTest rec {
+    name = "pin-range";
+    repositories."foo" = gitRepo;
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo -b test-branch
+      npins range foo --from release --sample 2
+      eq "$(jq -r '.pins | length' npins/ranges/foo.json)" "2"
+      eq "$(jq -r '.pins."1".revision' npins/ranges/foo.json)" "$(jq -r .pins.foo.revision npins/sources.json)"
+
+      nix-instantiate --eval -E '(import ./npins { input = ./npins/ranges/foo.json; })."0".outPath'
+    '';
+  };
+
+  exportFlake = mkGitTest rec {
+    name = "export-flake";
+    repositories."foo" = gitRepo;
+    commands = ''
+      npins init --bare
+      npins add git http://localhost:8000/foo -b test-branch
+      npins export-flake
+      echo "{ $(npins export-flake --inputs) outputs = _: { }; }" > flake.nix
+      eq "$(jq -r .nodes.foo.locked.rev flake.lock)" "$(jq -r .pins.foo.revision npins/sources.json)"
+
+      # Nix accepts the lock file as it is
+      nix --extra-experimental-features "nix-command flakes" flake metadata --no-update-lock-file .
+    '';
+  };
+
   # https://github.com/andir/npins/issues/75
   regression_issue75 = mkGitTest rec {
     name = "regression-issue-75";